@@ -8,6 +8,11 @@ macro_rules! set_rustc_env_var {
 
 #[allow(clippy::unusual_byte_groupings)]
 fn main() {
+	println!("cargo:rustc-check-cfg=cfg(ed25519)");
+	println!("cargo:rustc-check-cfg=cfg(ed448)");
+	println!("cargo:rustc-check-cfg=cfg(ml_dsa)");
+	let ml_dsa = env::var("CARGO_FEATURE_ML_DSA").is_ok();
+	let mut has_ml_dsa = false;
 	if let Ok(v) = env::var("DEP_OPENSSL_VERSION_NUMBER") {
 		let version = u64::from_str_radix(&v, 16).unwrap();
 		// OpenSSL 1.1.1
@@ -15,9 +20,18 @@ fn main() {
 			println!("cargo:rustc-cfg=ed25519");
 			println!("cargo:rustc-cfg=ed448");
 		}
+		// OpenSSL 3.5.0
+		has_ml_dsa = version >= 0x3_05_00_00_0;
 		set_rustc_env_var!("ACMED_TLS_LIB_NAME", "OpenSSL");
 	}
 	if env::var("DEP_OPENSSL_LIBRESSL_VERSION_NUMBER").is_ok() {
+		has_ml_dsa = false;
 		set_rustc_env_var!("ACMED_TLS_LIB_NAME", "LibreSSL");
 	}
+	if ml_dsa {
+		if !has_ml_dsa {
+			panic!("the ml_dsa feature requires OpenSSL 3.5 or later");
+		}
+		println!("cargo:rustc-cfg=ml_dsa");
+	}
 }
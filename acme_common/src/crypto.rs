@@ -3,6 +3,7 @@ use std::fmt;
 use std::str::FromStr;
 
 mod jws_signature_algorithm;
+mod key_format;
 mod key_type;
 #[cfg(feature = "crypto_openssl")]
 mod openssl_certificate;
@@ -10,6 +11,10 @@ mod openssl_certificate;
 mod openssl_hash;
 #[cfg(feature = "crypto_openssl")]
 mod openssl_keys;
+#[cfg(ml_dsa)]
+mod openssl_ml_dsa;
+#[cfg(feature = "crypto_openssl")]
+mod openssl_ocsp;
 #[cfg(feature = "crypto_openssl")]
 mod openssl_subject_attribute;
 #[cfg(feature = "crypto_openssl")]
@@ -80,13 +85,16 @@ impl fmt::Display for BaseHashFunction {
 }
 
 pub use jws_signature_algorithm::JwsSignatureAlgorithm;
+pub use key_format::{KeyEncoding, PrivateKeyFormat};
 pub use key_type::KeyType;
 #[cfg(feature = "crypto_openssl")]
-pub use openssl_certificate::{Csr, X509Certificate};
+pub use openssl_certificate::{convert_certificate, Csr, X509Certificate};
 #[cfg(feature = "crypto_openssl")]
 pub use openssl_hash::HashFunction;
 #[cfg(feature = "crypto_openssl")]
-pub use openssl_keys::{gen_keypair, KeyPair};
+pub use openssl_keys::{convert_private_key, gen_keypair, KeyPair};
+#[cfg(feature = "crypto_openssl")]
+pub use openssl_ocsp::OcspValidity;
 #[cfg(feature = "crypto_openssl")]
 pub use openssl_subject_attribute::SubjectAttribute;
 #[cfg(feature = "crypto_openssl")]
@@ -0,0 +1,125 @@
+use crate::crypto::KeyType;
+use crate::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Container format of a private key.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PrivateKeyFormat {
+	/// RSA keys only.
+	Pkcs1,
+	#[default]
+	Pkcs8,
+	/// Elliptic curve keys only.
+	Sec1,
+}
+
+impl PrivateKeyFormat {
+	pub fn check_key_type_compatibility(&self, key_type: KeyType) -> Result<(), Error> {
+		let ok = match self {
+			PrivateKeyFormat::Pkcs1 => matches!(key_type, KeyType::Rsa2048 | KeyType::Rsa4096),
+			PrivateKeyFormat::Pkcs8 => true,
+			PrivateKeyFormat::Sec1 => matches!(
+				key_type,
+				KeyType::EcdsaP256 | KeyType::EcdsaP384 | KeyType::EcdsaP521
+			),
+		};
+		if ok {
+			Ok(())
+		} else {
+			let msg = format!("a {key_type} key cannot be encoded using the {self} format");
+			Err(msg.into())
+		}
+	}
+}
+
+impl FromStr for PrivateKeyFormat {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s.to_lowercase().as_str() {
+			"pkcs1" => Ok(PrivateKeyFormat::Pkcs1),
+			"pkcs8" => Ok(PrivateKeyFormat::Pkcs8),
+			"sec1" => Ok(PrivateKeyFormat::Sec1),
+			_ => Err(format!("{s}: unknown private key format").into()),
+		}
+	}
+}
+
+impl fmt::Display for PrivateKeyFormat {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match self {
+			PrivateKeyFormat::Pkcs1 => "pkcs1",
+			PrivateKeyFormat::Pkcs8 => "pkcs8",
+			PrivateKeyFormat::Sec1 => "sec1",
+		};
+		write!(f, "{s}")
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum KeyEncoding {
+	#[default]
+	Pem,
+	Der,
+}
+
+impl FromStr for KeyEncoding {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s.to_lowercase().as_str() {
+			"pem" => Ok(KeyEncoding::Pem),
+			"der" => Ok(KeyEncoding::Der),
+			_ => Err(format!("{s}: unknown encoding").into()),
+		}
+	}
+}
+
+impl fmt::Display for KeyEncoding {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match self {
+			KeyEncoding::Pem => "pem",
+			KeyEncoding::Der => "der",
+		};
+		write!(f, "{s}")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{KeyEncoding, PrivateKeyFormat};
+	use crate::crypto::KeyType;
+	use std::str::FromStr;
+
+	#[test]
+	fn test_format_from_str() {
+		assert_eq!(
+			PrivateKeyFormat::from_str("PKCS1").unwrap(),
+			PrivateKeyFormat::Pkcs1
+		);
+		assert_eq!(
+			PrivateKeyFormat::from_str("sec1").unwrap(),
+			PrivateKeyFormat::Sec1
+		);
+		assert!(PrivateKeyFormat::from_str("pkcs12").is_err());
+		assert_eq!(KeyEncoding::from_str("der").unwrap(), KeyEncoding::Der);
+	}
+
+	#[test]
+	fn test_format_compatibility() {
+		let pkcs1 = PrivateKeyFormat::Pkcs1;
+		let sec1 = PrivateKeyFormat::Sec1;
+		assert!(pkcs1.check_key_type_compatibility(KeyType::Rsa2048).is_ok());
+		assert!(pkcs1
+			.check_key_type_compatibility(KeyType::EcdsaP256)
+			.is_err());
+		assert!(sec1
+			.check_key_type_compatibility(KeyType::EcdsaP384)
+			.is_ok());
+		assert!(sec1.check_key_type_compatibility(KeyType::Rsa4096).is_err());
+		assert!(PrivateKeyFormat::Pkcs8
+			.check_key_type_compatibility(KeyType::EcdsaP521)
+			.is_ok());
+	}
+}
@@ -14,11 +14,19 @@ pub enum KeyType {
 	Ed25519,
 	#[cfg(ed448)]
 	Ed448,
+	#[cfg(ml_dsa)]
+	MlDsa44,
+	#[cfg(ml_dsa)]
+	MlDsa65,
+	#[cfg(ml_dsa)]
+	MlDsa87,
 }
 
 impl KeyType {
-	pub fn get_default_signature_alg(&self) -> JwsSignatureAlgorithm {
-		match self {
+	/// Return the JWS signature algorithm used by default with this key type. ML-DSA keys do not
+	/// have any, hence they cannot be used as account keys.
+	pub fn get_default_signature_alg(&self) -> Result<JwsSignatureAlgorithm, Error> {
+		let alg = match self {
 			KeyType::Rsa2048 | KeyType::Rsa4096 => JwsSignatureAlgorithm::Rs256,
 			KeyType::EcdsaP256 => JwsSignatureAlgorithm::Es256,
 			KeyType::EcdsaP384 => JwsSignatureAlgorithm::Es384,
@@ -27,19 +35,28 @@ impl KeyType {
 			KeyType::Ed25519 => JwsSignatureAlgorithm::Ed25519,
 			#[cfg(ed448)]
 			KeyType::Ed448 => JwsSignatureAlgorithm::Ed448,
-		}
+			#[cfg(ml_dsa)]
+			KeyType::MlDsa44 | KeyType::MlDsa65 | KeyType::MlDsa87 => {
+				let err_msg =
+					format!("{self}: no signature algorithm is available for this key type");
+				return Err(err_msg.into());
+			}
+		};
+		Ok(alg)
 	}
 
 	pub fn check_alg_compatibility(&self, alg: &JwsSignatureAlgorithm) -> Result<(), Error> {
 		let ok = match self {
 			KeyType::Rsa2048 | KeyType::Rsa4096 => *alg == JwsSignatureAlgorithm::Rs256,
 			KeyType::EcdsaP256 | KeyType::EcdsaP384 | KeyType::EcdsaP521 => {
-				*alg == self.get_default_signature_alg()
+				*alg == self.get_default_signature_alg()?
 			}
 			#[cfg(ed25519)]
-			KeyType::Ed25519 => *alg == self.get_default_signature_alg(),
+			KeyType::Ed25519 => *alg == self.get_default_signature_alg()?,
 			#[cfg(ed448)]
-			KeyType::Ed448 => *alg == self.get_default_signature_alg(),
+			KeyType::Ed448 => *alg == self.get_default_signature_alg()?,
+			#[cfg(ml_dsa)]
+			KeyType::MlDsa44 | KeyType::MlDsa65 | KeyType::MlDsa87 => false,
 		};
 		if ok {
 			Ok(())
@@ -62,6 +79,12 @@ impl KeyType {
 			"ed25519",
 			#[cfg(ed448)]
 			"ed448",
+			#[cfg(ml_dsa)]
+			"ml-dsa-44",
+			#[cfg(ml_dsa)]
+			"ml-dsa-65",
+			#[cfg(ml_dsa)]
+			"ml-dsa-87",
 		]
 	}
 }
@@ -80,6 +103,12 @@ impl FromStr for KeyType {
 			"ed25519" => Ok(KeyType::Ed25519),
 			#[cfg(ed448)]
 			"ed448" => Ok(KeyType::Ed448),
+			#[cfg(ml_dsa)]
+			"ml_dsa_44" => Ok(KeyType::MlDsa44),
+			#[cfg(ml_dsa)]
+			"ml_dsa_65" => Ok(KeyType::MlDsa65),
+			#[cfg(ml_dsa)]
+			"ml_dsa_87" => Ok(KeyType::MlDsa87),
 			_ => Err(format!("{s}: unknown algorithm").into()),
 		}
 	}
@@ -97,6 +126,12 @@ impl fmt::Display for KeyType {
 			KeyType::Ed25519 => "ed25519",
 			#[cfg(ed448)]
 			KeyType::Ed448 => "ed448",
+			#[cfg(ml_dsa)]
+			KeyType::MlDsa44 => "ml-dsa-44",
+			#[cfg(ml_dsa)]
+			KeyType::MlDsa65 => "ml-dsa-65",
+			#[cfg(ml_dsa)]
+			KeyType::MlDsa87 => "ml-dsa-87",
 		};
 		write!(f, "{s}")
 	}
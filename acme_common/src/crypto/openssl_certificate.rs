@@ -1,17 +1,21 @@
-use super::{gen_keypair, KeyPair, KeyType, SubjectAttribute};
+use super::{gen_keypair, KeyEncoding, KeyPair, KeyType, SubjectAttribute};
 use crate::b64_encode;
 use crate::crypto::HashFunction;
 use crate::error::Error;
-use openssl::asn1::Asn1Time;
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
 use openssl::bn::{BigNum, MsbOption};
 use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
 use openssl::stack::Stack;
 use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
 use openssl::x509::{X509Builder, X509Extension, X509NameBuilder, X509Req, X509ReqBuilder, X509};
 use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::net::IpAddr;
 use std::time::Duration;
 
+const PEM_CRT_END: &[u8] = b"-----END CERTIFICATE-----";
+
 fn get_digest(digest: HashFunction, key_pair: &KeyPair) -> MessageDigest {
 	#[cfg(not(any(ed25519, ed448)))]
 	let digest = digest.native_digest();
@@ -22,6 +26,8 @@ fn get_digest(digest: HashFunction, key_pair: &KeyPair) -> MessageDigest {
 		KeyType::Ed25519 => MessageDigest::null(),
 		#[cfg(ed448)]
 		KeyType::Ed448 => MessageDigest::null(),
+		#[cfg(ml_dsa)]
+		KeyType::MlDsa44 | KeyType::MlDsa65 | KeyType::MlDsa87 => MessageDigest::null(),
 		_ => digest.native_digest(),
 	};
 	digest
@@ -91,6 +97,34 @@ impl X509Certificate {
 		})
 	}
 
+	pub fn from_der(der_data: &[u8]) -> Result<Self, Error> {
+		Ok(X509Certificate {
+			inner_cert: X509::from_der(der_data)?,
+		})
+	}
+
+	pub fn from_pem_chain(pem_data: &[u8]) -> Result<Vec<Self>, Error> {
+		Ok(X509::stack_from_pem(pem_data)?
+			.into_iter()
+			.map(|inner_cert| X509Certificate { inner_cert })
+			.collect())
+	}
+
+	pub fn issuer_common_name(&self) -> Option<String> {
+		self.inner_cert
+			.issuer_name()
+			.entries_by_nid(Nid::COMMONNAME)
+			.next()
+			.and_then(|e| e.data().as_utf8().ok())
+			.map(|e| e.to_string())
+	}
+
+	pub fn to_der_base64(&self) -> Result<String, Error> {
+		let crt = self.inner_cert.to_der()?;
+		let crt = b64_encode(&crt);
+		Ok(crt)
+	}
+
 	pub fn from_pem_native(pem_data: &[u8]) -> Result<native_tls::Certificate, Error> {
 		Ok(native_tls::Certificate::from_pem(pem_data)?)
 	}
@@ -109,14 +143,60 @@ impl X509Certificate {
 	}
 
 	pub fn expires_in(&self) -> Result<Duration, Error> {
-		let now = Asn1Time::days_from_now(0)?;
-		let not_after = self.inner_cert.not_after();
-		let diff = now.diff(not_after)?;
+		let nb_secs = self.not_after()?.saturating_sub(crate::now());
+		Ok(Duration::from_secs(nb_secs))
+	}
+
+	/// Return the expiration date as a Unix timestamp.
+	pub fn not_after(&self) -> Result<u64, Error> {
+		to_timestamp(self.inner_cert.not_after())
+	}
+
+	/// Return the start of the validity period as a Unix timestamp.
+	pub fn not_before(&self) -> Result<u64, Error> {
+		to_timestamp(self.inner_cert.not_before())
+	}
+
+	/// Return the serial number as a lowercase hexadecimal string.
+	pub fn serial_number(&self) -> Result<String, Error> {
+		let serial = self.inner_cert.serial_number().to_bn()?.to_hex_str()?;
+		Ok(serial.to_lowercase())
+	}
+
+	/// Return the lowercase hexadecimal hash of the DER-encoded certificate.
+	pub fn fingerprint(&self, hash: HashFunction) -> Result<String, Error> {
+		let der = self.inner_cert.to_der()?;
+		Ok(hash.hash(&der).iter().map(|b| format!("{b:02x}")).collect())
+	}
+
+	/// Return the type of the certificate's public key.
+	pub fn key_type(&self) -> Result<KeyType, Error> {
+		let public_key = self.inner_cert.public_key()?;
+		super::openssl_keys::get_key_type(&public_key)
+	}
+
+	pub fn lifetime(&self) -> Result<Duration, Error> {
+		let diff = self
+			.inner_cert
+			.not_before()
+			.diff(self.inner_cert.not_after())?;
 		let nb_secs = diff.days * 24 * 60 * 60 + diff.secs;
 		let nb_secs = if nb_secs > 0 { nb_secs as u64 } else { 0 };
 		Ok(Duration::from_secs(nb_secs))
 	}
 
+	/// Return the data of a TLSA record using the given certificate usage, the whole public key
+	/// as selector and the SHA-256 matching type, e.g. `3 1 1 <hash>`.
+	pub fn tlsa_rdata(&self, usage: u8) -> Result<String, Error> {
+		let spki = self.inner_cert.public_key()?.public_key_to_der()?;
+		let hash = HashFunction::Sha256
+			.hash(&spki)
+			.iter()
+			.map(|b| format!("{b:02x}"))
+			.collect::<String>();
+		Ok(format!("{usage} 1 1 {hash}"))
+	}
+
 	pub fn subject_alt_names(&self) -> HashSet<String> {
 		match self.inner_cert.subject_alt_names() {
 			Some(s) => s
@@ -148,6 +228,60 @@ impl X509Certificate {
 	}
 }
 
+/// Translate a certificate chain, whatever its current encoding, into the specified one. Since a
+/// DER file contains a single certificate, only the first certificate of the chain is kept. The
+/// certificates are converted and written one at a time, hence large chains are never held
+/// entirely in memory in both encodings.
+pub fn convert_certificate<W: Write>(
+	data: &[u8],
+	encoding: KeyEncoding,
+	out: &mut W,
+) -> Result<(), Error> {
+	if !data.starts_with(b"-----BEGIN ") {
+		let crt = X509::from_der(data)?;
+		return write_certificate(&crt, encoding, out);
+	}
+	let mut rest = data;
+	let mut nb_crt = 0;
+	while let Some(pos) = rest
+		.windows(PEM_CRT_END.len())
+		.position(|w| w == PEM_CRT_END)
+	{
+		let (block, tail) = rest.split_at(pos + PEM_CRT_END.len());
+		let crt = X509::from_pem(block)?;
+		write_certificate(&crt, encoding, out)?;
+		nb_crt += 1;
+		if encoding == KeyEncoding::Der {
+			break;
+		}
+		rest = tail;
+	}
+	if nb_crt == 0 {
+		return Err("no certificate found".into());
+	}
+	Ok(())
+}
+
+fn write_certificate<W: Write>(
+	crt: &X509,
+	encoding: KeyEncoding,
+	out: &mut W,
+) -> Result<(), Error> {
+	let data = match encoding {
+		KeyEncoding::Pem => crt.to_pem()?,
+		KeyEncoding::Der => crt.to_der()?,
+	};
+	out.write_all(&data)?;
+	Ok(())
+}
+
+pub(super) fn to_timestamp(time: &Asn1TimeRef) -> Result<u64, Error> {
+	let epoch = Asn1Time::from_unix(0)?;
+	let diff = epoch.diff(time)?;
+	let ts = i64::from(diff.days) * 24 * 60 * 60 + i64::from(diff.secs);
+	Ok(if ts > 0 { ts as u64 } else { 0 })
+}
+
 fn gen_certificate(
 	domain: &str,
 	key_pair: &KeyPair,
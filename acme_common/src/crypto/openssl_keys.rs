@@ -1,14 +1,17 @@
 use crate::b64_encode;
-use crate::crypto::{HashFunction, JwsSignatureAlgorithm, KeyType};
+#[cfg(ml_dsa)]
+use crate::crypto::openssl_ml_dsa;
+use crate::crypto::{HashFunction, JwsSignatureAlgorithm, KeyEncoding, KeyType, PrivateKeyFormat};
 use crate::error::Error;
 use openssl::bn::{BigNum, BigNumContext};
 use openssl::ec::{Asn1Flag, EcGroup, EcKey};
 use openssl::ecdsa::EcdsaSig;
 use openssl::hash::MessageDigest;
 use openssl::nid::Nid;
-use openssl::pkey::{Id, PKey, Private};
+use openssl::pkey::{HasParams, HasPublic, Id, PKey, PKeyRef, Private};
 use openssl::rsa::Rsa;
 use openssl::sign::Signer;
+use openssl::symm::Cipher;
 use serde_json::json;
 use serde_json::value::Value;
 
@@ -37,6 +40,12 @@ macro_rules! get_key_type {
 			Id::ED25519 => KeyType::Ed25519,
 			#[cfg(ed448)]
 			Id::ED448 => KeyType::Ed448,
+			#[cfg(ml_dsa)]
+			_ if openssl_ml_dsa::is_a(&$key, KeyType::MlDsa44) => KeyType::MlDsa44,
+			#[cfg(ml_dsa)]
+			_ if openssl_ml_dsa::is_a(&$key, KeyType::MlDsa65) => KeyType::MlDsa65,
+			#[cfg(ml_dsa)]
+			_ if openssl_ml_dsa::is_a(&$key, KeyType::MlDsa87) => KeyType::MlDsa87,
 			_ => {
 				return Err("unsupported key type".into());
 			}
@@ -44,6 +53,11 @@ macro_rules! get_key_type {
 	};
 }
 
+/// Return the type of a key, which may be a private or a public one.
+pub(crate) fn get_key_type<T: HasParams + HasPublic>(key: &PKeyRef<T>) -> Result<KeyType, Error> {
+	Ok(get_key_type!(key))
+}
+
 macro_rules! get_ecdsa_sig_part {
 	($part: expr, $size: ident) => {{
 		let mut p = $part.to_vec();
@@ -84,6 +98,15 @@ impl KeyPair {
 		})
 	}
 
+	pub fn from_encrypted_pem(pem_data: &[u8], passphrase: &[u8]) -> Result<Self, Error> {
+		let inner_key = PKey::private_key_from_pem_passphrase(pem_data, passphrase)?;
+		let key_type = get_key_type!(inner_key);
+		Ok(KeyPair {
+			key_type,
+			inner_key,
+		})
+	}
+
 	pub fn private_key_to_der(&self) -> Result<Vec<u8>, Error> {
 		self.inner_key.private_key_to_der().map_err(Error::from)
 	}
@@ -94,6 +117,60 @@ impl KeyPair {
 			.map_err(Error::from)
 	}
 
+	/// Export the private key, encrypted using AES-256, in the specified container format. A
+	/// PKCS#8 structure is encrypted as a whole, while PKCS#1 and SEC1 ones rely on the PEM
+	/// encryption headers.
+	pub fn private_key_to_encrypted_pem(
+		&self,
+		format: PrivateKeyFormat,
+		passphrase: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		format.check_key_type_compatibility(self.key_type)?;
+		let cipher = Cipher::aes_256_cbc();
+		let res = match format {
+			PrivateKeyFormat::Pkcs1 => self
+				.inner_key
+				.rsa()?
+				.private_key_to_pem_passphrase(cipher, passphrase),
+			PrivateKeyFormat::Pkcs8 => self
+				.inner_key
+				.private_key_to_pem_pkcs8_passphrase(cipher, passphrase),
+			PrivateKeyFormat::Sec1 => self
+				.inner_key
+				.ec_key()?
+				.private_key_to_pem_passphrase(cipher, passphrase),
+		};
+		res.map_err(Error::from)
+	}
+
+	/// Export the private key using the specified container format and encoding.
+	pub fn private_key_to_format(
+		&self,
+		format: PrivateKeyFormat,
+		encoding: KeyEncoding,
+	) -> Result<Vec<u8>, Error> {
+		format.check_key_type_compatibility(self.key_type)?;
+		let res = match (format, encoding) {
+			(PrivateKeyFormat::Pkcs1, KeyEncoding::Pem) => {
+				self.inner_key.rsa()?.private_key_to_pem()
+			}
+			(PrivateKeyFormat::Pkcs1, KeyEncoding::Der) => {
+				self.inner_key.rsa()?.private_key_to_der()
+			}
+			(PrivateKeyFormat::Pkcs8, KeyEncoding::Pem) => {
+				self.inner_key.private_key_to_pem_pkcs8()
+			}
+			(PrivateKeyFormat::Pkcs8, KeyEncoding::Der) => self.inner_key.private_key_to_pkcs8(),
+			(PrivateKeyFormat::Sec1, KeyEncoding::Pem) => {
+				self.inner_key.ec_key()?.private_key_to_pem()
+			}
+			(PrivateKeyFormat::Sec1, KeyEncoding::Der) => {
+				self.inner_key.ec_key()?.private_key_to_der()
+			}
+		};
+		res.map_err(Error::from)
+	}
+
 	pub fn public_key_to_pem(&self) -> Result<Vec<u8>, Error> {
 		self.inner_key.public_key_to_pem().map_err(Error::from)
 	}
@@ -159,6 +236,14 @@ impl KeyPair {
 		self.get_jwk_public_key(true)
 	}
 
+	/// Return the base64url-encoded SHA-256 JWK thumbprint (RFC 7638) of the public key, as
+	/// used in the ACME key authorizations.
+	pub fn jwk_thumbprint(&self) -> Result<String, Error> {
+		let thumbprint = self.jwk_public_key_thumbprint()?;
+		let thumbprint = HashFunction::Sha256.hash(thumbprint.to_string().as_bytes());
+		Ok(b64_encode(&thumbprint))
+	}
+
 	fn get_jwk_public_key(&self, thumbprint: bool) -> Result<Value, Error> {
 		match self.key_type {
 			KeyType::Rsa2048 | KeyType::Rsa4096 => self.get_rsa_jwk(thumbprint),
@@ -169,6 +254,10 @@ impl KeyPair {
 			KeyType::Ed25519 => self.get_eddsa_jwk(thumbprint),
 			#[cfg(ed448)]
 			KeyType::Ed448 => self.get_eddsa_jwk(thumbprint),
+			#[cfg(ml_dsa)]
+			KeyType::MlDsa44 | KeyType::MlDsa65 | KeyType::MlDsa87 => {
+				Err(format!("{}: no JWK representation for this key type", self.key_type).into())
+			}
 		}
 	}
 
@@ -322,6 +411,21 @@ fn gen_ed448_pair() -> Result<PKey<Private>, Error> {
 	Ok(pk)
 }
 
+/// Translate an unencrypted private key, whatever its current format and encoding, into the
+/// specified ones.
+pub fn convert_private_key(
+	data: &[u8],
+	format: PrivateKeyFormat,
+	encoding: KeyEncoding,
+) -> Result<Vec<u8>, Error> {
+	let key_pair = if data.starts_with(b"-----BEGIN ") {
+		KeyPair::from_pem(data)?
+	} else {
+		KeyPair::from_der(data)?
+	};
+	key_pair.private_key_to_format(format, encoding)
+}
+
 pub fn gen_keypair(key_type: KeyType) -> Result<KeyPair, Error> {
 	let priv_key = match key_type {
 		KeyType::Rsa2048 => gen_rsa_pair(2048),
@@ -333,6 +437,8 @@ pub fn gen_keypair(key_type: KeyType) -> Result<KeyPair, Error> {
 		KeyType::Ed25519 => gen_ed25519_pair(),
 		#[cfg(ed448)]
 		KeyType::Ed448 => gen_ed448_pair(),
+		#[cfg(ml_dsa)]
+		KeyType::MlDsa44 | KeyType::MlDsa65 | KeyType::MlDsa87 => openssl_ml_dsa::gen_pair(key_type),
 	}
 	.map_err(|_| Error::from(format!("unable to generate a {key_type} key pair")))?;
 	let key_pair = KeyPair {
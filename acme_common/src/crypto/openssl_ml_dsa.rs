@@ -0,0 +1,60 @@
+// ML-DSA keys (FIPS 204) are only provided by OpenSSL 3.5 and later. They do not have any
+// legacy key identifier and the openssl crate does not expose them, so they are generated
+// and identified by name.
+
+use crate::crypto::KeyType;
+use crate::error::Error;
+use foreign_types::{ForeignType, ForeignTypeRef};
+use openssl::error::ErrorStack;
+use openssl::pkey::{PKey, PKeyRef, Private};
+use openssl_sys::{EVP_PKEY_CTX_free, EVP_PKEY_keygen_init, EVP_PKEY, EVP_PKEY_CTX, OSSL_LIB_CTX};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+extern "C" {
+	fn EVP_PKEY_CTX_new_from_name(
+		libctx: *mut OSSL_LIB_CTX,
+		name: *const c_char,
+		propquery: *const c_char,
+	) -> *mut EVP_PKEY_CTX;
+	fn EVP_PKEY_generate(ctx: *mut EVP_PKEY_CTX, ppkey: *mut *mut EVP_PKEY) -> c_int;
+	fn EVP_PKEY_is_a(pkey: *const EVP_PKEY, name: *const c_char) -> c_int;
+}
+
+fn get_algorithm_name(key_type: KeyType) -> Result<&'static [u8], Error> {
+	match key_type {
+		KeyType::MlDsa44 => Ok(b"ML-DSA-44\0"),
+		KeyType::MlDsa65 => Ok(b"ML-DSA-65\0"),
+		KeyType::MlDsa87 => Ok(b"ML-DSA-87\0"),
+		_ => Err(format!("{key_type}: not an ML-DSA key type").into()),
+	}
+}
+
+/// Check whether a key, which may be a private or a public one, is of the given ML-DSA type.
+pub(crate) fn is_a<T>(key: &PKeyRef<T>, key_type: KeyType) -> bool {
+	match get_algorithm_name(key_type) {
+		Ok(name) => unsafe { EVP_PKEY_is_a(key.as_ptr(), name.as_ptr().cast()) == 1 },
+		Err(_) => false,
+	}
+}
+
+pub(crate) fn gen_pair(key_type: KeyType) -> Result<PKey<Private>, Error> {
+	let name = get_algorithm_name(key_type)?;
+	unsafe {
+		let ctx = EVP_PKEY_CTX_new_from_name(ptr::null_mut(), name.as_ptr().cast(), ptr::null());
+		if ctx.is_null() {
+			return Err(ErrorStack::get().into());
+		}
+		let mut key = ptr::null_mut();
+		let ret = if EVP_PKEY_keygen_init(ctx) == 1 {
+			EVP_PKEY_generate(ctx, &mut key)
+		} else {
+			0
+		};
+		EVP_PKEY_CTX_free(ctx);
+		if ret != 1 || key.is_null() {
+			return Err(ErrorStack::get().into());
+		}
+		Ok(PKey::from_ptr(key))
+	}
+}
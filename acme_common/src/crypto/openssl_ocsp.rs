@@ -0,0 +1,115 @@
+use crate::crypto::X509Certificate;
+use crate::error::Error;
+use foreign_types::ForeignTypeRef;
+use openssl::asn1::{Asn1GeneralizedTimeRef, Asn1TimeRef};
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{
+	OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus,
+};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
+
+/// Tolerated clock skew, in seconds, when checking the validity period of an OCSP response.
+const OCSP_CLOCK_SKEW_SEC: u32 = 5 * 60;
+
+/// Validity period of an OCSP response, as Unix timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OcspValidity {
+	pub this_update: u64,
+	pub next_update: u64,
+}
+
+impl X509Certificate {
+	/// Return the URL of the certificate's OCSP responder, if any.
+	pub fn ocsp_responder(&self) -> Option<String> {
+		// OpenSSL fails when the certificate has no authority information access extension.
+		let responders = self.inner_cert.ocsp_responders().ok()?;
+		let url = responders.iter().next()?;
+		Some(url.to_string())
+	}
+
+	/// Build the DER-encoded OCSP request asking for the status of this certificate.
+	pub fn ocsp_request(&self, issuer: &X509Certificate) -> Result<Vec<u8>, Error> {
+		let id =
+			OcspCertId::from_cert(MessageDigest::sha1(), &self.inner_cert, &issuer.inner_cert)?;
+		let mut req = OcspRequest::new()?;
+		req.add_id(id)?;
+		Ok(req.to_der()?)
+	}
+
+	/// Check that a DER-encoded OCSP response has been signed by the issuer, or by a responder it
+	/// designated, and states this certificate is good.
+	pub fn check_ocsp_response(
+		&self,
+		issuer: &X509Certificate,
+		response: &[u8],
+	) -> Result<OcspValidity, Error> {
+		let response = OcspResponse::from_der(response)?;
+		if response.status() != OcspResponseStatus::SUCCESSFUL {
+			let msg = format!(
+				"OCSP responder returned the error status {}",
+				response.status().as_raw()
+			);
+			return Err(msg.into());
+		}
+		let basic = response.basic()?;
+		let mut certs = Stack::new()?;
+		certs.push(issuer.inner_cert.clone())?;
+		// The issuer is usually an intermediate certificate, hence the chain stops there.
+		let mut store = X509StoreBuilder::new()?;
+		store.add_cert(issuer.inner_cert.clone())?;
+		store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+		let store = store.build();
+		basic.verify(&certs, &store, OcspFlag::empty())?;
+		let id =
+			OcspCertId::from_cert(MessageDigest::sha1(), &self.inner_cert, &issuer.inner_cert)?;
+		let status = basic
+			.find_status(&id)
+			.ok_or_else(|| Error::from("the OCSP response does not cover the certificate"))?;
+		if status.status == OcspCertStatus::REVOKED {
+			return Err("the certificate has been revoked".into());
+		}
+		if status.status != OcspCertStatus::GOOD {
+			return Err("the certificate status is unknown to the OCSP responder".into());
+		}
+		status.check_validity(OCSP_CLOCK_SKEW_SEC, None)?;
+		Ok(OcspValidity {
+			this_update: to_timestamp(status.this_update)?,
+			next_update: to_timestamp(status.next_update)?,
+		})
+	}
+}
+
+/// Convert an ASN.1 GeneralizedTime into a Unix timestamp. The time functions of OpenSSL accept
+/// both the UTCTime and the GeneralizedTime types, hence the time is converted as any other
+/// ASN.1 time.
+fn to_timestamp(time: &Asn1GeneralizedTimeRef) -> Result<u64, Error> {
+	// SAFETY: ASN1_GENERALIZEDTIME and ASN1_TIME are both ASN1_STRING, the pointer remains valid
+	// for the lifetime of the borrowed time.
+	let time = unsafe { Asn1TimeRef::from_ptr(time.as_ptr() as *mut openssl_sys::ASN1_TIME) };
+	super::openssl_certificate::to_timestamp(time)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::to_timestamp;
+	use foreign_types::{ForeignType, ForeignTypeRef};
+	use openssl::asn1::{Asn1GeneralizedTimeRef, Asn1Time};
+
+	fn check_time(time: &str, timestamp: u64) {
+		// A time set using the four-digit year format is a GeneralizedTime.
+		let time = Asn1Time::from_str(time).unwrap();
+		let time = unsafe {
+			Asn1GeneralizedTimeRef::from_ptr(time.as_ptr() as *mut openssl_sys::ASN1_GENERALIZEDTIME)
+		};
+		assert_eq!(to_timestamp(time).unwrap(), timestamp);
+	}
+
+	#[test]
+	fn test_to_timestamp() {
+		check_time("20240102030405Z", 1704164645);
+		check_time("19991231235959Z", 946684799);
+		check_time("19600101000000Z", 0);
+	}
+}
@@ -1,14 +1,197 @@
+use crate::logs::LogContext;
 use std::fmt;
+use std::sync::Arc;
+
+const ACME_PROBLEM_PREFIX: &str = "urn:ietf:params:acme:error:";
+
+/// ACME problem types which are caused by a temporary condition, hence the request may succeed
+/// later without any intervention.
+const TRANSIENT_PROBLEMS: &[&str] = &[
+	"badNonce",
+	"connection",
+	"dns",
+	"incorrectResponse",
+	"orderNotReady",
+	"rateLimited",
+	"serverInternal",
+	"tls",
+];
+
+/// IO errors which are caused by a temporary condition, mostly on network sockets.
+const TRANSIENT_IO_ERRORS: &[std::io::ErrorKind] = &[
+	std::io::ErrorKind::ConnectionRefused,
+	std::io::ErrorKind::ConnectionReset,
+	std::io::ErrorKind::Interrupted,
+	std::io::ErrorKind::TimedOut,
+	std::io::ErrorKind::WouldBlock,
+];
+
+/// Category of the underlying cause of an error, which is kept when context is added to the
+/// error's message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorKind {
+	/// The CA returned an ACME error.
+	Acme,
+	Config,
+	Crypto,
+	/// A hook failed.
+	Hook,
+	/// A network error occurred, e.g. while sending an HTTP request or a DNS query.
+	Http,
+	/// A file could not be read or written.
+	Io,
+	Template,
+	#[default]
+	Other,
+}
+
+impl fmt::Display for ErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match self {
+			ErrorKind::Acme => "acme",
+			ErrorKind::Config => "config",
+			ErrorKind::Crypto => "crypto",
+			ErrorKind::Hook => "hook",
+			ErrorKind::Http => "http",
+			ErrorKind::Io => "io",
+			ErrorKind::Template => "template",
+			ErrorKind::Other => "other",
+		};
+		write!(f, "{s}")
+	}
+}
+
+/// Element of the context in which an error occurred.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorContext {
+	/// Identifier of the certificate, e.g. `example.org_ecdsa_p256`.
+	Certificate(String),
+	/// Name of the endpoint.
+	Endpoint(String),
+	/// Name of the operation which failed, e.g. `renewal`.
+	Operation(String),
+}
 
 #[derive(Clone, Debug)]
 pub struct Error {
 	pub message: String,
+	pub kind: ErrorKind,
+	/// Type of the problem returned by the CA, e.g. `urn:ietf:params:acme:error:dns`.
+	pub problem_type: Option<String>,
+	/// Context in which the error occurred, with at most one element of each type.
+	pub context: Vec<ErrorContext>,
+	source: Option<Arc<dyn std::error::Error + Send + Sync>>,
 }
 
 impl Error {
+	pub fn new(kind: ErrorKind, message: &str) -> Self {
+		Error {
+			message: message.to_string(),
+			kind,
+			problem_type: None,
+			context: Vec::new(),
+			source: None,
+		}
+	}
+
 	pub fn prefix(&self, prefix: &str) -> Self {
 		Error {
 			message: format!("{prefix}: {}", &self.message),
+			..self.clone()
+		}
+	}
+
+	/// Set the kind of an error whose cause has not been categorized yet.
+	pub fn or_kind(self, kind: ErrorKind) -> Self {
+		match self.kind {
+			ErrorKind::Other => Error { kind, ..self },
+			_ => self,
+		}
+	}
+
+	pub fn with_kind(self, kind: ErrorKind) -> Self {
+		Error { kind, ..self }
+	}
+
+	pub fn with_problem_type(self, problem_type: &str) -> Self {
+		Error {
+			problem_type: Some(problem_type.to_string()),
+			..self
+		}
+	}
+
+	/// Add an element to the context of the error. Since the innermost context is the most
+	/// accurate one, it is not replaced by an outer context of the same type.
+	pub fn with_context(mut self, context: ErrorContext) -> Self {
+		let d = std::mem::discriminant(&context);
+		if !self.context.iter().any(|c| std::mem::discriminant(c) == d) {
+			self.context.push(context);
+		}
+		self
+	}
+
+	pub fn certificate(&self) -> Option<&str> {
+		self.context.iter().find_map(|c| match c {
+			ErrorContext::Certificate(v) => Some(v.as_str()),
+			_ => None,
+		})
+	}
+
+	pub fn endpoint(&self) -> Option<&str> {
+		self.context.iter().find_map(|c| match c {
+			ErrorContext::Endpoint(v) => Some(v.as_str()),
+			_ => None,
+		})
+	}
+
+	pub fn operation(&self) -> Option<&str> {
+		self.context.iter().find_map(|c| match c {
+			ErrorContext::Operation(v) => Some(v.as_str()),
+			_ => None,
+		})
+	}
+
+	/// Return the context to attach to the log records about this error.
+	pub fn get_log_context(&self) -> LogContext {
+		LogContext {
+			certificate: self.certificate().map(|e| e.to_string()),
+			endpoint: self.endpoint().map(|e| e.to_string()),
+			error_kind: self.operation().map(|e| e.to_string()),
+			error_source: Some(self.kind.to_string()),
+			..Default::default()
+		}
+	}
+
+	pub fn with_source<E>(self, source: E) -> Self
+	where
+		E: std::error::Error + Send + Sync + 'static,
+	{
+		Error {
+			source: Some(Arc::new(source)),
+			..self
+		}
+	}
+
+	/// Return whether or not the cause of the error is temporary, in which case the operation
+	/// may be retried shortly. Otherwise, it is unlikely to succeed until the configuration, the
+	/// environment or the CA's policy has been changed.
+	pub fn is_transient(&self) -> bool {
+		match self.kind {
+			ErrorKind::Acme => match &self.problem_type {
+				Some(t) => t
+					.strip_prefix(ACME_PROBLEM_PREFIX)
+					.map(|t| TRANSIENT_PROBLEMS.contains(&t))
+					.unwrap_or(false),
+				None => true,
+			},
+			ErrorKind::Io => self
+				.source
+				.as_deref()
+				.and_then(|e| e.downcast_ref::<std::io::Error>())
+				.map(|e| TRANSIENT_IO_ERRORS.contains(&e.kind()))
+				.unwrap_or(false),
+			ErrorKind::Hook | ErrorKind::Http | ErrorKind::Other => true,
+			ErrorKind::Config | ErrorKind::Crypto | ErrorKind::Template => false,
 		}
 	}
 }
@@ -19,11 +202,17 @@ impl fmt::Display for Error {
 	}
 }
 
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		self.source
+			.as_deref()
+			.map(|e| e as &(dyn std::error::Error + 'static))
+	}
+}
+
 impl From<&str> for Error {
 	fn from(error: &str) -> Self {
-		Error {
-			message: error.to_string(),
-		}
+		Error::new(ErrorKind::Other, error)
 	}
 }
 
@@ -41,25 +230,25 @@ impl From<&String> for Error {
 
 impl From<std::io::Error> for Error {
 	fn from(error: std::io::Error) -> Self {
-		format!("IO error: {error}").into()
+		Error::new(ErrorKind::Io, &format!("IO error: {error}")).with_source(error)
 	}
 }
 
 impl From<std::net::AddrParseError> for Error {
 	fn from(error: std::net::AddrParseError) -> Self {
-		format!("{error}").into()
+		Error::from(format!("{error}")).with_source(error)
 	}
 }
 
 impl From<std::string::FromUtf8Error> for Error {
 	fn from(error: std::string::FromUtf8Error) -> Self {
-		format!("UTF-8 error: {error}").into()
+		Error::from(format!("UTF-8 error: {error}")).with_source(error)
 	}
 }
 
 impl From<std::sync::mpsc::RecvError> for Error {
 	fn from(error: std::sync::mpsc::RecvError) -> Self {
-		format!("MSPC receiver error: {error}").into()
+		Error::from(format!("MSPC receiver error: {error}")).with_source(error)
 	}
 }
 
@@ -71,7 +260,7 @@ impl From<std::time::SystemTimeError> for Error {
 
 impl From<base64::DecodeError> for Error {
 	fn from(error: base64::DecodeError) -> Self {
-		format!("base 64 decode error: {error}").into()
+		Error::from(format!("base 64 decode error: {error}")).with_source(error)
 	}
 }
 
@@ -83,51 +272,119 @@ impl From<syslog::Error> for Error {
 
 impl From<toml::de::Error> for Error {
 	fn from(error: toml::de::Error) -> Self {
-		format!("IO error: {error}").into()
+		Error::new(
+			ErrorKind::Config,
+			&format!("invalid configuration: {error}"),
+		)
+		.with_source(error)
 	}
 }
 
 impl From<serde_json::error::Error> for Error {
 	fn from(error: serde_json::error::Error) -> Self {
-		format!("IO error: {error}").into()
+		Error::from(format!("JSON error: {error}")).with_source(error)
 	}
 }
 
 impl From<reqwest::Error> for Error {
 	fn from(error: reqwest::Error) -> Self {
-		format!("HTTP error: {error}").into()
+		Error::new(ErrorKind::Http, &format!("HTTP error: {error}")).with_source(error)
 	}
 }
 
 impl From<glob::PatternError> for Error {
 	fn from(error: glob::PatternError) -> Self {
-		format!("pattern error: {error}").into()
+		Error::from(format!("pattern error: {error}")).with_source(error)
 	}
 }
 
 impl From<minijinja::Error> for Error {
 	fn from(error: minijinja::Error) -> Self {
-		format!("template error: {error}").into()
+		Error::new(ErrorKind::Template, &format!("template error: {error}")).with_source(error)
 	}
 }
 
 #[cfg(feature = "crypto_openssl")]
 impl From<native_tls::Error> for Error {
 	fn from(error: native_tls::Error) -> Self {
-		format!("{error}").into()
+		Error::new(ErrorKind::Crypto, &format!("{error}")).with_source(error)
 	}
 }
 
 #[cfg(feature = "crypto_openssl")]
 impl From<openssl::error::ErrorStack> for Error {
 	fn from(error: openssl::error::ErrorStack) -> Self {
-		format!("{error}").into()
+		Error::new(ErrorKind::Crypto, &format!("{error}")).with_source(error)
 	}
 }
 
 #[cfg(unix)]
 impl From<nix::Error> for Error {
 	fn from(error: nix::Error) -> Self {
-		format!("{error}").into()
+		Error::new(ErrorKind::Io, &format!("{error}")).with_source(error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Error, ErrorContext, ErrorKind};
+
+	#[test]
+	fn test_error_kind() {
+		let e = Error::from("oops");
+		assert_eq!(e.kind, ErrorKind::Other);
+		let e = e.or_kind(ErrorKind::Config).prefix("context");
+		assert_eq!(e.kind, ErrorKind::Config);
+		assert_eq!(e.message, "context: oops");
+		let e = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+		assert_eq!(e.kind, ErrorKind::Io);
+		assert_eq!(e.or_kind(ErrorKind::Config).kind, ErrorKind::Io);
+	}
+
+	#[test]
+	fn test_error_context() {
+		let e = Error::from("oops")
+			.with_context(ErrorContext::Operation("ocsp".to_string()))
+			.prefix("context")
+			.with_context(ErrorContext::Certificate("example.org_rsa2048".to_string()))
+			.with_context(ErrorContext::Operation("renewal".to_string()));
+		assert_eq!(e.message, "context: oops");
+		assert_eq!(e.certificate(), Some("example.org_rsa2048"));
+		assert_eq!(e.endpoint(), None);
+		assert_eq!(e.operation(), Some("ocsp"));
+		let ctx = e.get_log_context();
+		assert_eq!(ctx.certificate.as_deref(), Some("example.org_rsa2048"));
+		assert_eq!(ctx.error_kind.as_deref(), Some("ocsp"));
+		assert_eq!(ctx.error_source.as_deref(), Some("other"));
+	}
+
+	#[test]
+	fn test_error_source() {
+		let e = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound)).prefix("context");
+		let source = std::error::Error::source(&e).unwrap();
+		assert_eq!(source.to_string(), "entity not found");
+		assert!(std::error::Error::source(&Error::from("oops")).is_none());
+	}
+
+	#[test]
+	fn test_is_transient() {
+		assert!(Error::new(ErrorKind::Http, "timeout").is_transient());
+		assert!(Error::new(ErrorKind::Hook, "failed").is_transient());
+		assert!(!Error::new(ErrorKind::Config, "invalid").is_transient());
+		assert!(!Error::new(ErrorKind::Io, "permission denied").is_transient());
+		let e = Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+		assert!(!e.is_transient());
+		let e = Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+		assert!(e.prefix("context").is_transient());
+		let e = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+		assert!(e.is_transient());
+		let e = Error::new(ErrorKind::Acme, "too many orders");
+		assert!(e.is_transient());
+		let e = e.with_problem_type("urn:ietf:params:acme:error:rateLimited");
+		assert!(e.prefix("context").is_transient());
+		let e = e.with_problem_type("urn:ietf:params:acme:error:caa");
+		assert!(!e.is_transient());
+		let e = e.with_problem_type("about:blank");
+		assert!(!e.is_transient());
 	}
 }
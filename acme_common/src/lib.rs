@@ -1,7 +1,11 @@
 use base64::Engine;
+#[cfg(unix)]
 use daemonize::Daemonize;
 use std::fs::File;
 use std::io::prelude::*;
+#[cfg(feature = "time_override")]
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, process};
 
 pub mod crypto;
@@ -22,6 +26,51 @@ macro_rules! exit_match {
 	};
 }
 
+#[cfg(feature = "time_override")]
+static TIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Set the offset, in seconds, applied to the system's clock. This is only meant to test the
+/// behavior around the expiration dates, hence it requires the `time_override` feature.
+#[cfg(feature = "time_override")]
+pub fn set_time_offset(offset: i64) -> Result<(), error::Error> {
+	TIME_OFFSET.store(offset, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Without the `time_override` feature, the system's clock cannot be shifted.
+#[cfg(not(feature = "time_override"))]
+pub fn set_time_offset(offset: i64) -> Result<(), error::Error> {
+	if offset != 0 {
+		return Err("overriding the time requires the time_override feature".into());
+	}
+	Ok(())
+}
+
+/// Return the current Unix timestamp, shifted by the time offset if any.
+pub fn now() -> u64 {
+	let now = system_now();
+	#[cfg(feature = "time_override")]
+	let now = {
+		let offset = TIME_OFFSET.load(Ordering::Relaxed);
+		if offset >= 0 {
+			now.saturating_add(offset.unsigned_abs())
+		} else {
+			now.saturating_sub(offset.unsigned_abs())
+		}
+	};
+	now
+}
+
+/// Return the current Unix timestamp of the system's clock, which is never shifted by the time
+/// offset. It must be used whenever the time is checked by a third party, such as in the
+/// signature of a request, since the shifted time would be rejected.
+pub fn system_now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
 pub fn to_idna(domain_name: &str) -> Result<String, error::Error> {
 	let mut idna_parts = vec![];
 	let parts: Vec<&str> = domain_name.split('.').collect();
@@ -49,13 +98,20 @@ pub fn b64_decode<T: ?Sized + AsRef<[u8]>>(input: &T) -> Result<Vec<u8>, error::
 }
 
 pub fn init_server(foreground: bool, pid_file: Option<&str>) {
+	#[cfg(unix)]
 	if !foreground {
 		let mut daemonize = Daemonize::new();
 		if let Some(f) = pid_file {
 			daemonize = daemonize.pid_file(f);
 		}
 		exit_match!(daemonize.start());
-	} else if let Some(f) = pid_file {
+		return;
+	}
+	#[cfg(not(unix))]
+	if !foreground {
+		log::warn!("running in the background is not supported on this platform");
+	}
+	if let Some(f) = pid_file {
 		exit_match!(write_pid_file(f).map_err(|e| e.prefix(f)));
 	}
 }
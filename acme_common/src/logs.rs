@@ -1,15 +1,213 @@
 use crate::error::Error;
 use env_logger::Builder;
-use log::LevelFilter;
-use syslog::Facility;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use syslog::{BasicLogger, Facility, Formatter3164, LoggerBackend};
 
 pub const DEFAULT_LOG_SYSTEM: LogSystem = LogSystem::SysLog;
 pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Warn;
+pub const DEFAULT_LOG_FORMAT: LogFormat = LogFormat::Text;
+pub const DEFAULT_SYSLOG_FACILITY: Facility = Facility::LOG_DAEMON;
+
+static LOG_FORMAT_JSON: AtomicBool = AtomicBool::new(false);
+static SYSLOG_LOGGER: RwLock<Option<BasicLogger>> = RwLock::new(None);
+
+thread_local! {
+	static LOG_CONTEXT: RefCell<Option<LogContext>> = const { RefCell::new(None) };
+	static LOG_EVENT_ID: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum LogSystem {
 	SysLog,
 	StdErr,
+	SysLogAndStdErr,
+}
+
+struct Logger {
+	stderr: Option<env_logger::Logger>,
+	syslog_level: LevelFilter,
+}
+
+impl Logger {
+	/// The stderr output may be more verbose than the log level, using the `ACMED_LOG_LEVEL`
+	/// environment variable, which must not apply to the syslog output.
+	fn is_syslog_enabled(&self, level: Level) -> bool {
+		level <= self.syslog_level
+	}
+}
+
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		metadata.level() <= log::max_level()
+	}
+
+	fn log(&self, record: &Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+		if let Some(l) = &self.stderr {
+			if l.matches(record) {
+				l.log(record);
+			}
+		}
+		if !self.is_syslog_enabled(record.level()) {
+			return;
+		}
+		if let Ok(l) = SYSLOG_LOGGER.read() {
+			if let Some(l) = &*l {
+				let suffix = get_text_suffix();
+				if suffix.is_empty() {
+					l.log(record);
+				} else {
+					l.log(
+						&Record::builder()
+							.args(format_args!("{}{suffix}", record.args()))
+							.metadata(record.metadata().clone())
+							.module_path(record.module_path())
+							.file(record.file())
+							.line(record.line())
+							.build(),
+					);
+				}
+			}
+		}
+	}
+
+	fn flush(&self) {
+		if let Some(l) = &self.stderr {
+			l.flush();
+		}
+		if let Ok(l) = SYSLOG_LOGGER.read() {
+			if let Some(l) = &*l {
+				l.flush();
+			}
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+	Text,
+	Json,
+}
+
+impl FromStr for LogFormat {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"text" => Ok(LogFormat::Text),
+			"json" => Ok(LogFormat::Json),
+			_ => Err(format!("{s}: invalid log format").into()),
+		}
+	}
+}
+
+/// Structured fields attached to the log events emitted while the context is active. Those
+/// fields are only displayed when the JSON log format is used, except for the labels which are
+/// also appended to the text logs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogContext {
+	pub certificate: Option<String>,
+	pub endpoint: Option<String>,
+	pub error_kind: Option<String>,
+	pub error_source: Option<String>,
+	pub labels: BTreeMap<String, String>,
+}
+
+pub fn set_log_format(format: LogFormat) {
+	LOG_FORMAT_JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn get_log_format() -> LogFormat {
+	if LOG_FORMAT_JSON.load(Ordering::Relaxed) {
+		LogFormat::Json
+	} else {
+		LogFormat::Text
+	}
+}
+
+pub fn with_log_context<F: FnOnce()>(ctx: LogContext, f: F) {
+	let previous = LOG_CONTEXT.with(|c| c.replace(Some(ctx)));
+	f();
+	LOG_CONTEXT.with(|c| c.replace(previous));
+}
+
+/// Attach a stable identifier to the log events emitted by the given function, so they can be
+/// matched regardless of the wording of their message.
+pub fn with_event_id<F: FnOnce()>(event_id: &'static str, f: F) {
+	let previous = LOG_EVENT_ID.with(|c| c.replace(Some(event_id)));
+	f();
+	LOG_EVENT_ID.with(|c| c.set(previous));
+}
+
+fn get_event_id() -> Option<&'static str> {
+	LOG_EVENT_ID.with(|c| c.get())
+}
+
+/// Return the event identifier and the labels appended to the message in the text logs.
+fn get_text_suffix() -> String {
+	let mut suffix = get_event_id()
+		.map(|id| format!(" [event_id={id}]"))
+		.unwrap_or_default();
+	LOG_CONTEXT.with(|c| {
+		if let Some(ctx) = &*c.borrow() {
+			if !ctx.labels.is_empty() {
+				let labels: Vec<String> =
+					ctx.labels.iter().map(|(k, v)| format!("{k}={v}")).collect();
+				suffix += &format!(" [labels: {}]", labels.join(", "));
+			}
+		}
+	});
+	suffix
+}
+
+fn format_json(timestamp: &str, record: &Record) -> String {
+	let mut fields = vec![
+		("timestamp", Value::from(timestamp)),
+		("level", Value::from(record.level().as_str().to_lowercase())),
+		("target", Value::from(record.target())),
+	];
+	if let Some(id) = get_event_id() {
+		fields.push(("event_id", Value::from(id)));
+	}
+	LOG_CONTEXT.with(|c| {
+		if let Some(ctx) = &*c.borrow() {
+			let ctx_fields = [
+				("certificate", &ctx.certificate),
+				("endpoint", &ctx.endpoint),
+				("error_kind", &ctx.error_kind),
+				("error_source", &ctx.error_source),
+			];
+			for (name, value) in ctx_fields {
+				if let Some(v) = value {
+					fields.push((name, Value::from(v.as_str())));
+				}
+			}
+			if !ctx.labels.is_empty() {
+				let labels = ctx
+					.labels
+					.iter()
+					.map(|(k, v)| (k.to_owned(), Value::from(v.as_str())))
+					.collect();
+				fields.push(("labels", Value::Object(labels)));
+			}
+		}
+	});
+	fields.push(("message", Value::from(record.args().to_string())));
+	let fields: Vec<String> = fields
+		.iter()
+		.map(|(k, v)| format!("{}:{v}", Value::from(*k)))
+		.collect();
+	format!("{{{}}}", fields.join(","))
 }
 
 fn get_loglevel(log_level: Option<&str>) -> Result<LevelFilter, Error> {
@@ -29,20 +227,77 @@ fn get_loglevel(log_level: Option<&str>) -> Result<LevelFilter, Error> {
 	Ok(level)
 }
 
-fn set_log_syslog(log_level: LevelFilter) -> Result<(), Error> {
-	syslog::init(
-		Facility::LOG_DAEMON,
-		log_level,
-		Some(env!("CARGO_PKG_NAME")),
-	)?;
+fn get_default_syslog_tag() -> String {
+	std::env::current_exe()
+		.ok()
+		.and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+		.unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+}
+
+fn get_syslog_logger(facility: Facility, tag: &str) -> Result<BasicLogger, Error> {
+	let formatter = Formatter3164 {
+		facility,
+		hostname: None,
+		process: tag.to_string(),
+		pid: std::process::id(),
+	};
+	let backend = syslog::unix(formatter.clone())
+		.map(|logger| logger.backend)
+		.or_else(|_| {
+			TcpStream::connect(("127.0.0.1", 601))
+				.map(|s| LoggerBackend::Tcp(std::io::BufWriter::new(s)))
+		})
+		.or_else(|_| {
+			let udp_addr = "127.0.0.1:514".parse().unwrap();
+			UdpSocket::bind(("127.0.0.1", 0)).map(|s| LoggerBackend::Udp(s, udp_addr))
+		})
+		.map_err(|e| Error::from(e).prefix("syslog"))?;
+	Ok(BasicLogger::new(syslog::Logger { formatter, backend }))
+}
+
+fn set_syslog_logger(logger: Option<BasicLogger>) -> Result<(), Error> {
+	let mut l = SYSLOG_LOGGER
+		.write()
+		.map_err(|_| Error::from("syslog: unable to update the logger"))?;
+	*l = logger;
 	Ok(())
 }
 
-fn set_log_stderr(log_level: LevelFilter) -> Result<(), Error> {
+fn get_stderr_logger(log_level: LevelFilter) -> env_logger::Logger {
 	let mut builder = Builder::from_env("ACMED_LOG_LEVEL");
 	builder.filter_level(log_level);
-	builder.init();
-	Ok(())
+	builder.format(|buf, record| match get_log_format() {
+		LogFormat::Text => writeln!(
+			buf,
+			"[{} {:<5} {}] {}{}",
+			buf.timestamp(),
+			buf.default_styled_level(record.level()),
+			record.target(),
+			record.args(),
+			get_text_suffix()
+		),
+		LogFormat::Json => writeln!(buf, "{}", format_json(&buf.timestamp().to_string(), record)),
+	});
+	builder.build()
+}
+
+/// Enable or reconfigure the syslog output once the logging system has been set. If `enable` is
+/// false, the syslog output is only reconfigured if it is already active.
+pub fn set_syslog(enable: bool, facility: Option<&str>, tag: Option<&str>) -> Result<(), Error> {
+	let is_active = SYSLOG_LOGGER.read().map(|l| l.is_some()).unwrap_or(false);
+	if !enable && !is_active {
+		return Ok(());
+	}
+	let facility = match facility {
+		Some(f) => Facility::from_str(f)
+			.map_err(|_| Error::from(format!("{f}: invalid syslog facility")))?,
+		None => DEFAULT_SYSLOG_FACILITY,
+	};
+	let tag = match tag {
+		Some(t) => t.to_string(),
+		None => get_default_syslog_tag(),
+	};
+	set_syslog_logger(Some(get_syslog_logger(facility, &tag)?))
 }
 
 pub fn set_log_system(
@@ -51,23 +306,41 @@ pub fn set_log_system(
 	has_stderr: bool,
 ) -> Result<(LogSystem, LevelFilter), Error> {
 	let log_level = get_loglevel(log_level)?;
-	let logtype = if has_syslog {
-		LogSystem::SysLog
-	} else if has_stderr {
-		LogSystem::StdErr
-	} else {
-		DEFAULT_LOG_SYSTEM
+	let logtype = match (has_syslog, has_stderr) {
+		(true, true) => LogSystem::SysLogAndStdErr,
+		(true, false) => LogSystem::SysLog,
+		(false, true) => LogSystem::StdErr,
+		(false, false) => DEFAULT_LOG_SYSTEM,
 	};
-	match logtype {
-		LogSystem::SysLog => set_log_syslog(log_level)?,
-		LogSystem::StdErr => set_log_stderr(log_level)?,
+	let stderr = match logtype {
+		LogSystem::StdErr | LogSystem::SysLogAndStdErr => Some(get_stderr_logger(log_level)),
+		LogSystem::SysLog => None,
+	};
+	if logtype != LogSystem::StdErr {
+		let logger = get_syslog_logger(DEFAULT_SYSLOG_FACILITY, &get_default_syslog_tag())?;
+		set_syslog_logger(Some(logger))?;
+	}
+	let max_level = match &stderr {
+		Some(l) => l.filter().max(log_level),
+		None => log_level,
 	};
+	log::set_boxed_logger(Box::new(Logger {
+		stderr,
+		syslog_level: log_level,
+	}))
+	.map_err(|e| Error::from(format!("unable to set the logger: {e}")))?;
+	log::set_max_level(max_level);
 	Ok((logtype, log_level))
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{set_log_system, DEFAULT_LOG_LEVEL, DEFAULT_LOG_SYSTEM};
+	use super::{
+		format_json, get_event_id, get_text_suffix, set_log_system, set_syslog, with_event_id,
+		with_log_context, LogContext, LogFormat, Logger, DEFAULT_LOG_LEVEL, DEFAULT_LOG_SYSTEM,
+	};
+	use log::{Level, LevelFilter, Record};
+	use std::str::FromStr;
 
 	#[test]
 	fn test_invalid_level() {
@@ -83,4 +356,109 @@ mod tests {
 		assert_eq!(logtype, DEFAULT_LOG_SYSTEM);
 		assert_eq!(log_level, DEFAULT_LOG_LEVEL);
 	}
+
+	#[test]
+	fn test_syslog_level() {
+		let logger = Logger {
+			stderr: None,
+			syslog_level: LevelFilter::Warn,
+		};
+		assert!(logger.is_syslog_enabled(Level::Error));
+		assert!(logger.is_syslog_enabled(Level::Warn));
+		assert!(!logger.is_syslog_enabled(Level::Info));
+		assert!(!logger.is_syslog_enabled(Level::Trace));
+	}
+
+	#[test]
+	fn test_invalid_syslog_facility() {
+		let ret = set_syslog(true, Some("invalid"), None);
+		assert!(ret.is_err());
+	}
+
+	#[test]
+	fn test_log_format_from_str() {
+		assert_eq!(LogFormat::from_str("text").unwrap(), LogFormat::Text);
+		assert_eq!(LogFormat::from_str("JSON").unwrap(), LogFormat::Json);
+		assert!(LogFormat::from_str("xml").is_err());
+	}
+
+	#[test]
+	fn test_format_json() {
+		let ctx = LogContext {
+			certificate: Some("example.org_rsa2048".to_string()),
+			endpoint: Some("Let's Encrypt".to_string()),
+			error_kind: Some("renewal".to_string()),
+			error_source: Some("http".to_string()),
+			labels: [("team", "payments"), ("env", "prod")]
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect(),
+		};
+		let mut out = String::new();
+		with_log_context(ctx, || {
+			let record = Record::builder()
+				.args(format_args!("some \"message\""))
+				.level(Level::Warn)
+				.target("acmed")
+				.build();
+			out = format_json("2023-01-01T00:00:00Z", &record);
+		});
+		assert_eq!(
+			out,
+			r#"{"timestamp":"2023-01-01T00:00:00Z","level":"warn","target":"acmed","certificate":"example.org_rsa2048","endpoint":"Let's Encrypt","error_kind":"renewal","error_source":"http","labels":{"env":"prod","team":"payments"},"message":"some \"message\""}"#
+		);
+		let record = Record::builder()
+			.args(format_args!("no context"))
+			.level(Level::Info)
+			.target("acmed")
+			.build();
+		let out = format_json("2023-01-01T00:00:00Z", &record);
+		assert_eq!(
+			out,
+			r#"{"timestamp":"2023-01-01T00:00:00Z","level":"info","target":"acmed","message":"no context"}"#
+		);
+	}
+
+	#[test]
+	fn test_event_id() {
+		let mut out = String::new();
+		with_event_id("certificate_renewed", || {
+			with_event_id("hook_failed", || {
+				assert_eq!(get_event_id(), Some("hook_failed"));
+			});
+			let record = Record::builder()
+				.args(format_args!("renewed"))
+				.level(Level::Info)
+				.target("acmed")
+				.build();
+			out = format_json("2023-01-01T00:00:00Z", &record);
+		});
+		assert_eq!(
+			out,
+			r#"{"timestamp":"2023-01-01T00:00:00Z","level":"info","target":"acmed","event_id":"certificate_renewed","message":"renewed"}"#
+		);
+		assert_eq!(get_event_id(), None);
+	}
+
+	#[test]
+	fn test_text_suffix() {
+		assert_eq!(get_text_suffix(), "");
+		let ctx = LogContext {
+			labels: [("team", "payments"), ("env", "prod")]
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect(),
+			..Default::default()
+		};
+		let mut out = String::new();
+		with_event_id("certificate_renewed", || {
+			with_log_context(ctx, || out = get_text_suffix());
+		});
+		assert_eq!(
+			out,
+			" [event_id=certificate_renewed] [labels: env=prod, team=payments]"
+		);
+		with_log_context(LogContext::default(), || out = get_text_suffix());
+		assert_eq!(out, "");
+	}
 }
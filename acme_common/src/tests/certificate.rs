@@ -1,6 +1,14 @@
-use crate::crypto::{HashFunction, KeyType, X509Certificate, CRT_NB_DAYS_VALIDITY};
+use crate::b64_decode;
+use crate::crypto::{
+	convert_certificate, HashFunction, KeyEncoding, KeyType, X509Certificate, CRT_NB_DAYS_VALIDITY,
+};
+#[cfg(ml_dsa)]
+use crate::crypto::{gen_keypair, Csr};
+#[cfg(ml_dsa)]
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::iter::FromIterator;
+use std::time::Duration;
 
 const CERTIFICATE_P256_DOMAINS_PEM: &str = r#"-----BEGIN CERTIFICATE-----
 MIICtDCCAZygAwIBAgIIf5BEPlNrrYkwDQYJKoZIhvcNAQELBQAwKDEmMCQGA1UE
@@ -81,9 +89,34 @@ bQg4DAU6E9VT+pvyGsc1NNyREKxOlDkam3CqfYc0oAowjn11MmDac2aKP8Pyt4pk
 ehm+yKg=
 -----END CERTIFICATE-----"#;
 
+const OCSP_LEAF_PEM: &str = r#"-----BEGIN CERTIFICATE-----
+MIIBuDCCAV2gAwIBAgICEJIwCgYIKoZIzj0EAwIwEjEQMA4GA1UEAwwHVGVzdCBD
+QTAgFw0yNjEwMTQxNTQwMjdaGA8yMTI2MDkyMDE1NDAyN1owFjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAR/G5FfSOfs0AWy
+tImhLUlillj6JkkOGpVJM4Au9/nFCuPVVLaT/vr+PABQztCbS13ghilzwRn1urX5
+LRjp4TPRo4GcMIGZMAwGA1UdEwEB/wQCMAAwMQYIKwYBBQUHAQEEJTAjMCEGCCsG
+AQUFBzABhhVodHRwOi8vMTI3LjAuMC4xOjg4ODgwFgYDVR0RBA8wDYILZXhhbXBs
+ZS5vcmcwHQYDVR0OBBYEFAQGtNaTsf1QyJbjYqwJTyrCX9VKMB8GA1UdIwQYMBaA
+FDcjpwH1daJ8YcnUNkTLq3dUktd2MAoGCCqGSM49BAMCA0kAMEYCIQDaWURrrW51
+zPgi8vYa8gQMv3hJ3fgIgHMmCBK9Xzv0nAIhAJkZ30zhl84aFMvl+zbUEUIU+zDp
+irpiBsRDQVcyDQAh
+-----END CERTIFICATE-----"#;
+const OCSP_ISSUER_PEM: &str = r#"-----BEGIN CERTIFICATE-----
+MIIBajCCARCgAwIBAgIUFUpG23wCXXESjH4G+lhh0Cwc03gwCgYIKoZIzj0EAwIw
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTQxNTQwMjdaGA8yMTI2MDkyMDE1
+NDAyN1owEjEQMA4GA1UEAwwHVGVzdCBDQTBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABKhYHdZp2mXjStLoTvzbkZjB9+YHPJDBoNm/wZXFv1Ye8fE4edbjW5LCCaXx
+mIUB6vpEbbgd6vBKvYEoBH60FYCjQjBAMA8GA1UdEwEB/wQFMAMBAf8wDgYDVR0P
+AQH/BAQDAgEGMB0GA1UdDgQWBBQ3I6cB9XWifGHJ1DZEy6t3VJLXdjAKBggqhkjO
+PQQDAgNIADBFAiEAt0HbpKvq0dwZs4jI0x87t0S3pbScbb3kSISfN7cqHLkCIHt3
+GQuUnMzAd1ZE4UXds+FpBW5mX4/HpDFhf9/IhzaI
+-----END CERTIFICATE-----"#;
+const OCSP_RESPONSE_GOOD: &str = "MIICfAoBAKCCAnUwggJxBgkrBgEFBQcwAQEEggJiMIICXjCBjqEUMBIxEDAOBgNVBAMMB1Rlc3QgQ0EYDzIwMjYxMDE0MTU0MDI3WjBlMGMwOzAJBgUrDgMCGgUABBS_cFLIucD3YMiRI-CZgV6ywDlCJgQUNyOnAfV1onxhydQ2RMurd1SS13YCAhCSgAAYDzIwMjYxMDE0MTU0MDI3WqARGA8yMTI2MDkyMDE1NDAyN1owCgYIKoZIzj0EAwIDSQAwRgIhAPAsyvj_Ncju-6wkQEgleyQQx8UoxM1prNoND1v7DLHXAiEApcKNkcmoBErz_gPKdSfdw879voMgqbIw7ykT0WAJtQegggFyMIIBbjCCAWowggEQoAMCAQICFBVKRtt8Al1xEox-BvpYYdAsHNN4MAoGCCqGSM49BAMCMBIxEDAOBgNVBAMMB1Rlc3QgQ0EwIBcNMjYxMDE0MTU0MDI3WhgPMjEyNjA5MjAxNTQwMjdaMBIxEDAOBgNVBAMMB1Rlc3QgQ0EwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAASoWB3Wadpl40rS6E7825GYwffmBzyQwaDZv8GVxb9WHvHxOHnW41uSwgml8ZiFAer6RG24HerwSr2BKAR-tBWAo0IwQDAPBgNVHRMBAf8EBTADAQH_MA4GA1UdDwEB_wQEAwIBBjAdBgNVHQ4EFgQUNyOnAfV1onxhydQ2RMurd1SS13YwCgYIKoZIzj0EAwIDSAAwRQIhALdB26Sr6tHcGbOIyNMfO7dEt6W0nG295EiEnze3Khy5AiB7dxkLlJzMwHdWROFF3bPhaQVuZl-Px6QxYX_fyIc2iA";
+const OCSP_RESPONSE_REVOKED: &str = "MIICjAoBAKCCAoUwggKBBgkrBgEFBQcwAQEEggJyMIICbjCBn6EUMBIxEDAOBgNVBAMMB1Rlc3QgQ0EYDzIwMjYxMDE0MTU0MDM2WjB2MHQwOzAJBgUrDgMCGgUABBS_cFLIucD3YMiRI-CZgV6ywDlCJgQUNyOnAfV1onxhydQ2RMurd1SS13YCAhCSoREYDzIwMjQwMTAxMDAwMDAwWhgPMjAyNjEwMTQxNTQwMzZaoBEYDzIxMjYwOTIwMTU0MDM2WjAKBggqhkjOPQQDAgNIADBFAiBHCeL_69AZMfawyFv99XY7iGL-JMd4abUmyWTVTUXriAIhAJ2upZXM-3wvT3zAd0qJQFU9GQFsoqVaLH5OneA4EVIgoIIBcjCCAW4wggFqMIIBEKADAgECAhQVSkbbfAJdcRKMfgb6WGHQLBzTeDAKBggqhkjOPQQDAjASMRAwDgYDVQQDDAdUZXN0IENBMCAXDTI2MTAxNDE1NDAyN1oYDzIxMjYwOTIwMTU0MDI3WjASMRAwDgYDVQQDDAdUZXN0IENBMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEqFgd1mnaZeNK0uhO_NuRmMH35gc8kMGg2b_BlcW_Vh7x8Th51uNbksIJpfGYhQHq-kRtuB3q8Eq9gSgEfrQVgKNCMEAwDwYDVR0TAQH_BAUwAwEB_zAOBgNVHQ8BAf8EBAMCAQYwHQYDVR0OBBYEFDcjpwH1daJ8YcnUNkTLq3dUktd2MAoGCCqGSM49BAMCA0gAMEUCIQC3Qdukq-rR3BmziMjTHzu3RLeltJxtveRIhJ83tyocuQIge3cZC5SczMB3VkThRd2z4WkFbmZfj8ekMWF_38iHNog";
+
 #[test]
 fn test_san_domains() {
-	let san = vec!["local.what.tf", "1.local.what.tf", "2.local.what.tf"];
+	let san = ["local.what.tf", "1.local.what.tf", "2.local.what.tf"];
 	let san = HashSet::from_iter(san.iter().map(|v| v.to_string()));
 	let crt = X509Certificate::from_pem(CERTIFICATE_P256_DOMAINS_PEM.as_bytes()).unwrap();
 	assert_eq!(crt.subject_alt_names(), san);
@@ -91,7 +124,7 @@ fn test_san_domains() {
 
 #[test]
 fn test_san_ip() {
-	let san = vec!["127.0.0.1", "::1"];
+	let san = ["127.0.0.1", "::1"];
 	let san = HashSet::from_iter(san.iter().map(|v| v.to_string()));
 	let crt = X509Certificate::from_pem(CERTIFICATE_P256_IP_PEM.as_bytes()).unwrap();
 	assert_eq!(crt.subject_alt_names(), san);
@@ -99,7 +132,7 @@ fn test_san_ip() {
 
 #[test]
 fn test_san_domains_and_ip() {
-	let san = vec![
+	let san = [
 		"127.0.0.1",
 		"::1",
 		"local.what.tf",
@@ -111,6 +144,73 @@ fn test_san_domains_and_ip() {
 	assert_eq!(crt.subject_alt_names(), san);
 }
 
+#[test]
+fn test_issuer_common_name() {
+	let crt = X509Certificate::from_pem(CERTIFICATE_P256_DOMAINS_PEM.as_bytes()).unwrap();
+	assert_eq!(
+		crt.issuer_common_name(),
+		Some("Pebble Intermediate CA 2d1688".to_string())
+	);
+	let crt = X509Certificate::from_pem(CERTIFICATE_EXPIRED_PEM.as_bytes()).unwrap();
+	assert_eq!(
+		crt.issuer_common_name(),
+		Some("Let's Encrypt Authority X3".to_string())
+	);
+}
+
+#[test]
+fn test_pem_chain() {
+	let chain = format!("{CERTIFICATE_P256_DOMAINS_PEM}\n{CERTIFICATE_EXPIRED_PEM}\n");
+	let chain = X509Certificate::from_pem_chain(chain.as_bytes()).unwrap();
+	assert_eq!(chain.len(), 2);
+	assert_eq!(
+		chain[1].issuer_common_name(),
+		Some("Let's Encrypt Authority X3".to_string())
+	);
+}
+
+#[test]
+fn test_lifetime() {
+	let crt = X509Certificate::from_pem(CERTIFICATE_EXPIRED_PEM.as_bytes()).unwrap();
+	assert_eq!(
+		crt.lifetime().unwrap(),
+		Duration::from_secs(90 * 24 * 60 * 60)
+	);
+}
+
+#[test]
+fn test_details() {
+	let crt = X509Certificate::from_pem(CERTIFICATE_EXPIRED_PEM.as_bytes()).unwrap();
+	assert_eq!(
+		crt.serial_number().unwrap(),
+		"040a4c22661f94f757ec160b8e29d0f84ad4"
+	);
+	assert_eq!(crt.not_before().unwrap(), 1575139316);
+	assert_eq!(crt.not_after().unwrap(), 1582915316);
+	assert_eq!(crt.key_type().unwrap(), KeyType::EcdsaP384);
+	assert_eq!(
+		crt.fingerprint(HashFunction::Sha256).unwrap(),
+		"a6695741acdf9412a149ec87d4b0aacb69eb5779c98136b91c4e453e8e1e87f0"
+	);
+}
+
+#[test]
+fn test_convert_certificate() {
+	let convert = |data: &[u8], encoding| {
+		let mut out = vec![];
+		convert_certificate(data, encoding, &mut out).map(|_| out)
+	};
+	let pem = format!("{CERTIFICATE_EXPIRED_PEM}\n{CERTIFICATE_P256_DOMAINS_PEM}\n");
+	let der = convert(pem.as_bytes(), KeyEncoding::Der).unwrap();
+	let crt = X509Certificate::from_pem(&convert(&der, KeyEncoding::Pem).unwrap()).unwrap();
+	assert_eq!(crt.not_after().unwrap(), 1582915316);
+	let chain = convert(pem.as_bytes(), KeyEncoding::Pem).unwrap();
+	assert_eq!(X509Certificate::from_pem_chain(&chain).unwrap().len(), 2);
+	assert!(convert(b"-----BEGIN nothing", KeyEncoding::Der).is_err());
+	assert!(convert(b"-----BEGIN nothing", KeyEncoding::Pem).is_err());
+	assert!(convert(b"garbage", KeyEncoding::Pem).is_err());
+}
+
 #[test]
 fn generate_rsa2048_certificate() {
 	let (kp, _) =
@@ -161,6 +261,36 @@ fn generate_ed448_certificate() {
 	assert_eq!(kp.key_type, KeyType::Ed448);
 }
 
+#[cfg(ml_dsa)]
+#[test]
+fn generate_ml_dsa_certificate() {
+	for key_type in [KeyType::MlDsa44, KeyType::MlDsa65, KeyType::MlDsa87] {
+		let (kp, crt) =
+			X509Certificate::from_acme_ext("example.org", "", key_type, HashFunction::Sha256)
+				.unwrap();
+		assert_eq!(kp.key_type, key_type);
+		assert_eq!(crt.key_type().unwrap(), key_type);
+	}
+}
+
+#[cfg(ml_dsa)]
+#[test]
+fn generate_ml_dsa_csr() {
+	let kp = gen_keypair(KeyType::MlDsa65).unwrap();
+	let csr = Csr::new(
+		&kp,
+		HashFunction::Sha256,
+		&["example.org".to_string()],
+		&[],
+		&HashMap::new(),
+	)
+	.unwrap();
+	assert!(csr
+		.to_pem()
+		.unwrap()
+		.starts_with("-----BEGIN CERTIFICATE REQUEST-----"));
+}
+
 #[test]
 fn cert_expiration_date_future() {
 	let (_, crt) =
@@ -179,3 +309,37 @@ fn cert_expiration_date_past() {
 	let duration = crt.expires_in().unwrap().as_secs();
 	assert_eq!(duration, 0);
 }
+
+#[test]
+fn test_ocsp_response() {
+	let leaf = X509Certificate::from_pem(OCSP_LEAF_PEM.as_bytes()).unwrap();
+	let issuer = X509Certificate::from_pem(OCSP_ISSUER_PEM.as_bytes()).unwrap();
+	assert_eq!(
+		leaf.ocsp_responder(),
+		Some("http://127.0.0.1:8888".to_string())
+	);
+	assert!(!leaf.ocsp_request(&issuer).unwrap().is_empty());
+	let good = b64_decode(OCSP_RESPONSE_GOOD).unwrap();
+	let validity = leaf.check_ocsp_response(&issuer, &good).unwrap();
+	assert!(validity.this_update < validity.next_update);
+	let revoked = b64_decode(OCSP_RESPONSE_REVOKED).unwrap();
+	assert!(leaf.check_ocsp_response(&issuer, &revoked).is_err());
+	assert!(issuer.check_ocsp_response(&issuer, &good).is_err());
+	let other = X509Certificate::from_pem(CERTIFICATE_P256_DOMAINS_PEM.as_bytes()).unwrap();
+	assert!(leaf.check_ocsp_response(&other, &good).is_err());
+	assert_eq!(other.ocsp_responder(), None);
+}
+
+#[test]
+fn test_tlsa_rdata() {
+	let leaf = X509Certificate::from_pem(OCSP_LEAF_PEM.as_bytes()).unwrap();
+	let issuer = X509Certificate::from_pem(OCSP_ISSUER_PEM.as_bytes()).unwrap();
+	assert_eq!(
+		leaf.tlsa_rdata(3).unwrap(),
+		"3 1 1 9f3d37628fce0dbfb5244c2f76e8c40808d78476ccfa2e964f799c4c68ac4289"
+	);
+	assert_eq!(
+		issuer.tlsa_rdata(2).unwrap(),
+		"2 1 1 a71ed8bc17ff8d62b8df12d32779fd796d17fbfb9213bf5e3562dd9f63473399"
+	);
+}
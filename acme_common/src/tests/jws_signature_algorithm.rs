@@ -1,6 +1,6 @@
 use crate::crypto::{gen_keypair, JwsSignatureAlgorithm, KeyType};
 
-const TEST_DATA: &'static [u8] = &[72, 101, 108, 108, 111, 32, 119, 111, 114, 108, 100, 33];
+const TEST_DATA: &[u8] = &[72, 101, 108, 108, 111, 32, 119, 111, 114, 108, 100, 33];
 
 #[test]
 fn test_rs256_sign_rsa2048() {
@@ -60,3 +60,11 @@ fn test_ed448_sign() {
 	let k = gen_keypair(KeyType::Ed448).unwrap();
 	let _ = k.sign(&JwsSignatureAlgorithm::Ed448, TEST_DATA).unwrap();
 }
+
+#[cfg(ml_dsa)]
+#[test]
+fn test_ml_dsa_sign() {
+	let k = gen_keypair(KeyType::MlDsa65).unwrap();
+	let res = k.sign(&JwsSignatureAlgorithm::Es256, TEST_DATA);
+	assert!(res.is_err());
+}
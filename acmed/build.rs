@@ -115,6 +115,7 @@ fn set_target() {
 fn set_default_values() {
 	set_data_path_if_absent!("ACMED_DEFAULT_ACCOUNTS_DIR", "accounts");
 	set_data_path_if_absent!("ACMED_DEFAULT_CERT_DIR", "certs");
+	set_data_path_if_absent!("ACMED_DEFAULT_STATE_DIR", "state");
 	set_env_var_if_absent!(
 		"ACMED_DEFAULT_CERT_FORMAT",
 		"{{ name }}_{{ key_type }}.{{ file_type }}.{{ ext }}"
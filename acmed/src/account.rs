@@ -1,4 +1,6 @@
-use crate::acme_proto::account::{register_account, update_account_contacts, update_account_key};
+use crate::acme_proto::account::{
+	agree_to_terms_of_service, register_account, update_account_contacts, update_account_key,
+};
 use crate::endpoint::Endpoint;
 use crate::logs::HasLogger;
 use crate::storage::FileManager;
@@ -19,6 +21,7 @@ pub struct ExternalAccount {
 	pub signature_algorithm: JwsSignatureAlgorithm,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub enum AccountContactType {
 	Mailfrom,
@@ -84,6 +87,14 @@ impl AccountEndpoint {
 	}
 }
 
+/// Identity of an account on an endpoint, as exposed to the hooks and the events.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountIdentity {
+	pub name: String,
+	pub url: String,
+	pub contacts: Vec<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Account {
 	pub name: String,
@@ -93,6 +104,7 @@ pub struct Account {
 	pub past_keys: Vec<AccountKey>,
 	pub file_manager: FileManager,
 	pub external_account: Option<ExternalAccount>,
+	pub auto_register: bool,
 }
 
 impl HasLogger for Account {
@@ -151,6 +163,9 @@ impl Account {
 		Err("key not found".into())
 	}
 
+	/// Load the account, or initialize a new one. If the key type or the signature algorithm has
+	/// been changed in the configuration, a new key is created and saved unless `update_keys` is
+	/// unset. The `auto_register` field is left to the caller.
 	pub async fn load(
 		file_manager: &FileManager,
 		name: &str,
@@ -158,6 +173,7 @@ impl Account {
 		key_type: &Option<String>,
 		signature_algorithm: &Option<String>,
 		external_account: &Option<ExternalAccount>,
+		update_keys: bool,
 	) -> Result<Self, Error> {
 		let contacts = contacts
 			.iter()
@@ -169,12 +185,14 @@ impl Account {
 		};
 		let signature_algorithm = match signature_algorithm {
 			Some(sa) => sa.parse()?,
-			None => key_type.get_default_signature_alg(),
+			None => key_type.get_default_signature_alg()?,
 		};
 		key_type.check_alg_compatibility(&signature_algorithm)?;
 		let account = match storage::fetch(file_manager, name).await? {
 			Some(mut a) => {
-				a.update_keys(key_type, signature_algorithm).await?;
+				if update_keys {
+					a.update_keys(key_type, signature_algorithm).await?;
+				}
 				a.contacts = contacts;
 				a.external_account = external_account.to_owned();
 				a
@@ -188,6 +206,7 @@ impl Account {
 					past_keys: Vec::new(),
 					file_manager: file_manager.clone(),
 					external_account: external_account.to_owned(),
+					auto_register: false,
 				};
 				account.debug("initializing a new account");
 				account
@@ -213,7 +232,7 @@ impl Account {
 						&endpoint.name
 					);
 					self.info(&msg);
-					register_account(endpoint, self).await?;
+					self.auto_register(endpoint).await?;
 					return Ok(());
 				}
 			}
@@ -221,14 +240,19 @@ impl Account {
 			let key_hash = hash_key(&self.current_key)?;
 			let contacts_changed = ct_hash != acc_ep.contacts_hash;
 			let key_changed = key_hash != acc_ep.key_hash;
+			// The contacts are compared to the last ones sent to the endpoint, which may
+			// normalize them. Failing to update them is not an error since the account is
+			// still usable: the update is attempted again on the next synchronization.
 			if contacts_changed {
-				update_account_contacts(endpoint, self).await?;
+				if let Err(e) = update_account_contacts(endpoint, self).await {
+					self.warn(&e.prefix("unable to update the contacts").message);
+				}
 			}
 			if key_changed {
 				update_account_key(endpoint, self).await?;
 			}
 		} else {
-			register_account(endpoint, self).await?;
+			self.auto_register(endpoint).await?;
 		}
 		Ok(())
 	}
@@ -237,32 +261,66 @@ impl Account {
 		register_account(endpoint, self).await
 	}
 
+	pub async fn agree_to_terms_of_service(
+		&mut self,
+		endpoint: &mut Endpoint,
+	) -> Result<(), Error> {
+		agree_to_terms_of_service(endpoint, self).await
+	}
+
+	/// Register the account without any user interaction, unless this has been disabled using
+	/// the `auto_register` option.
+	pub async fn auto_register(&mut self, endpoint: &mut Endpoint) -> Result<(), Error> {
+		if !self.auto_register {
+			let msg = format!(
+				"account not registered on endpoint \"{0}\" and automatic registration disabled: run `acmed account register {0}`",
+				endpoint.name
+			);
+			return Err(msg.into());
+		}
+		self.register(endpoint).await
+	}
+
+	/// Return the account's identity on the endpoint. The URL, which is also the key ID of the
+	/// signed requests, is empty if the account has not been registered yet.
+	pub fn get_identity(&self, endpoint_name: &str) -> AccountIdentity {
+		AccountIdentity {
+			name: self.name.to_owned(),
+			url: self
+				.endpoints
+				.get(endpoint_name)
+				.map(|e| e.account_url.to_owned())
+				.unwrap_or_default(),
+			contacts: self.contacts.iter().map(|c| c.to_string()).collect(),
+		}
+	}
+
 	pub async fn save(&self) -> Result<(), Error> {
 		storage::save(&self.file_manager, self).await
 	}
 
 	pub fn set_account_url(&mut self, endpoint_name: &str, account_url: &str) -> Result<(), Error> {
-		let mut ep = self.get_endpoint_mut(endpoint_name)?;
+		let ep = self.get_endpoint_mut(endpoint_name)?;
 		ep.account_url = account_url.to_string();
 		Ok(())
 	}
 
 	pub fn set_orders_url(&mut self, endpoint_name: &str, orders_url: &str) -> Result<(), Error> {
-		let mut ep = self.get_endpoint_mut(endpoint_name)?;
+		let ep = self.get_endpoint_mut(endpoint_name)?;
 		ep.orders_url = orders_url.to_string();
 		Ok(())
 	}
 
 	pub fn update_key_hash(&mut self, endpoint_name: &str) -> Result<(), Error> {
 		let key = self.current_key.clone();
-		let mut ep = self.get_endpoint_mut(endpoint_name)?;
+		let ep = self.get_endpoint_mut(endpoint_name)?;
 		ep.key_hash = hash_key(&key)?;
 		Ok(())
 	}
 
 	pub fn update_contacts_hash(&mut self, endpoint_name: &str) -> Result<(), Error> {
 		let ct = self.contacts.clone();
-		let mut ep = self.get_endpoint_mut(endpoint_name)?;
+		let ep = self.get_endpoint_mut(endpoint_name)?;
 		ep.contacts_hash = hash_contacts(&ct);
 		Ok(())
 	}
@@ -270,7 +328,7 @@ impl Account {
 	pub fn update_external_account_hash(&mut self, endpoint_name: &str) -> Result<(), Error> {
 		if let Some(ec) = &self.external_account {
 			let ec = ec.clone();
-			let mut ep = self.get_endpoint_mut(endpoint_name)?;
+			let ep = self.get_endpoint_mut(endpoint_name)?;
 			ep.external_account_hash = hash_external_account(&ec);
 		}
 		Ok(())
@@ -9,23 +9,24 @@ fn clean_mailto(value: &str) -> Result<String, Error> {
 	Ok(value.to_string())
 }
 
-// TODO: implement other URI shemes
+// Besides mailto, the URI schemes are not interpreted, hence their support depends on the CA.
 // https://www.iana.org/assignments/uri-schemes/uri-schemes.xhtml
-// https://en.wikipedia.org/wiki/List_of_URI_schemes
-// Exemples:
-//   - P1: tel, sms
-//   - P2: geo, maps
-//   - P3: irc, irc6, ircs, xmpp
-//   - P4: sip, sips
 #[derive(Clone, Debug, PartialEq)]
 pub enum ContactType {
 	Mailto,
+	Other(String),
 }
 
 impl ContactType {
 	pub fn clean_value(&self, value: &str) -> Result<String, Error> {
 		match self {
 			ContactType::Mailto => clean_mailto(value),
+			ContactType::Other(scheme) => {
+				if value.is_empty() {
+					return Err(format!("{scheme}: empty contact URI").into());
+				}
+				Ok(value.to_string())
+			}
 		}
 	}
 }
@@ -34,8 +35,15 @@ impl FromStr for ContactType {
 	type Err = Error;
 
 	fn from_str(s: &str) -> Result<Self, Error> {
-		match s.to_lowercase().as_str() {
+		let scheme = s.to_lowercase();
+		// RFC 3986, section 3.1
+		let is_valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+			&& scheme
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+		match scheme.as_str() {
 			"mailto" => Ok(ContactType::Mailto),
+			_ if is_valid => Ok(ContactType::Other(scheme)),
 			_ => Err(format!("{s}: unknown contact type.").into()),
 		}
 	}
@@ -45,6 +53,7 @@ impl fmt::Display for ContactType {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let s = match self {
 			ContactType::Mailto => "mailto",
+			ContactType::Other(scheme) => scheme,
 		};
 		write!(f, "{s}")
 	}
@@ -88,9 +97,21 @@ mod tests {
 		assert_ne!(c2, c3);
 	}
 
+	#[test]
+	fn test_account_contact_uri() {
+		let c = AccountContact::new("TEL", "+33-1-23-45-67-89").unwrap();
+		assert_eq!(c.contact_type, ContactType::Other("tel".to_string()));
+		assert_eq!(c.to_string(), "tel:+33-1-23-45-67-89");
+		let c = AccountContact::new("mailto", "derp@example.com").unwrap();
+		assert_eq!(c.contact_type, ContactType::Mailto);
+		assert!(AccountContact::new("tel", "").is_err());
+		assert!(AccountContact::new("1tel", "+33").is_err());
+		assert!(AccountContact::new("te l", "+33").is_err());
+	}
+
 	#[test]
 	fn test_account_contact_in_vec() {
-		let contacts = vec![
+		let contacts = [
 			AccountContact::new("mailto", "derp.derpson@example.com").unwrap(),
 			AccountContact::new("mailto", "derp@example.com").unwrap(),
 		];
@@ -100,7 +121,7 @@ mod tests {
 
 	#[test]
 	fn test_account_contact_not_in_vec() {
-		let contacts = vec![
+		let contacts = [
 			AccountContact::new("mailto", "derp.derpson@example.com").unwrap(),
 			AccountContact::new("mailto", "derp@example.com").unwrap(),
 		];
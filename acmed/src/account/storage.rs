@@ -134,6 +134,7 @@ async fn do_fetch(file_manager: &FileManager, name: &str) -> Result<Option<Accou
 			past_keys,
 			file_manager: file_manager.clone(),
 			external_account,
+			auto_register: crate::DEFAULT_AUTO_REGISTER,
 		}))
 	} else {
 		Ok(None)
@@ -0,0 +1,205 @@
+use crate::certificate::Certificate;
+use crate::dns;
+use crate::dns_propagation::resolve_cname;
+use crate::dns_provider::{get_record_name, send_expect, with_json};
+use crate::endpoint::get_state_file;
+use crate::http;
+use crate::logs::HasLogger;
+use crate::platform::{Current, Platform};
+use acme_common::error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+const CREDENTIALS_FILE_MODE: u32 = 0o600;
+
+/// Prevent a domain from being registered twice when several certificates include it.
+static REGISTRATION: Mutex<()> = Mutex::const_new(());
+
+/// Credentials returned by the acme-dns server when a domain is registered.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct Credentials {
+	username: String,
+	password: String,
+	fulldomain: String,
+	subdomain: String,
+}
+
+/// Publish the dns-01 proofs on an acme-dns server, to which the `_acme-challenge` records are
+/// delegated using CNAME records. Each domain is registered the first time it is validated and
+/// its credentials are kept in the state directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AcmeDns {
+	pub url: String,
+	pub allow_from: Vec<String>,
+}
+
+impl AcmeDns {
+	pub fn new(url: &str, allow_from: &[String]) -> Result<Self, Error> {
+		let parsed = reqwest::Url::parse(url).map_err(|e| Error::from(format!("{url}: {e}")))?;
+		if !["http", "https"].contains(&parsed.scheme()) {
+			return Err(format!("{url}: the acme-dns server URL must use HTTP or HTTPS").into());
+		}
+		Ok(AcmeDns {
+			url: url.trim_end_matches('/').to_string(),
+			allow_from: allow_from.to_vec(),
+		})
+	}
+
+	/// Publish the proof. The acme-dns server keeps the two latest values of each record, hence
+	/// a domain and its wildcard can be validated at the same time.
+	pub async fn add_record(
+		&self,
+		cert: &Certificate,
+		identifier: &str,
+		proof: &str,
+	) -> Result<(), Error> {
+		let name = get_record_name(identifier);
+		let creds = self.get_credentials(cert, &name).await?;
+		check_delegation(cert, &name, &creds.fulldomain).await?;
+		cert.debug(&format!(
+			"{name}: updating the TXT record of {} on the acme-dns server",
+			creds.fulldomain
+		));
+		let client = http::get_api_client()?;
+		let request = client
+			.post(format!("{}/update", self.url))
+			.header("X-Api-User", &creds.username)
+			.header("X-Api-Key", &creds.password);
+		let body = json!({
+			"subdomain": creds.subdomain,
+			"txt": proof,
+		});
+		send_expect(with_json(request, &body))
+			.await
+			.map_err(|e| e.prefix(&format!("{name}: acme-dns")))?;
+		Ok(())
+	}
+
+	/// The acme-dns API does not allow to remove a value, which is replaced by the next ones.
+	pub fn remove_record(&self, cert: &Certificate, identifier: &str) {
+		cert.trace(&format!(
+			"{}: the acme-dns records are not removed",
+			get_record_name(identifier)
+		));
+	}
+
+	/// Return the credentials of the domain, which is registered if it has not been yet.
+	async fn get_credentials(&self, cert: &Certificate, name: &str) -> Result<Credentials, Error> {
+		let _lock = REGISTRATION.lock().await;
+		let state_file =
+			get_state_file(&cert.state_dir, &format!("{}#{name}", self.url), "acme-dns");
+		let path = state_file.display().to_string();
+		match fs::read(&state_file).await {
+			Ok(data) => {
+				return serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path));
+			}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(Error::from(e).prefix(&path)),
+		}
+		cert.info(&format!(
+			"{name}: registering on the acme-dns server {}",
+			self.url
+		));
+		let client = http::get_api_client()?;
+		let request = client.post(format!("{}/register", self.url));
+		let request = if self.allow_from.is_empty() {
+			request
+		} else {
+			with_json(request, &json!({ "allowfrom": self.allow_from }))
+		};
+		let body = send_expect(request)
+			.await
+			.map_err(|e| e.prefix(&format!("{name}: acme-dns registration")))?;
+		let creds: Credentials = serde_json::from_str(&body)?;
+		// The registration cannot be done again with the same credentials, hence the file is
+		// replaced at once and synced, so it is never read partially written.
+		let tmp_path = state_file.with_extension("json.tmp");
+		let tmp_path_s = tmp_path.display().to_string();
+		let mut options = OpenOptions::from(Current::file_options(CREDENTIALS_FILE_MODE));
+		let mut file = options
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.open(&tmp_path)
+			.await
+			.map_err(|e| Error::from(e).prefix(&tmp_path_s))?;
+		file.write_all(&serde_json::to_vec(&creds)?)
+			.await
+			.map_err(|e| Error::from(e).prefix(&tmp_path_s))?;
+		file.sync_all()
+			.await
+			.map_err(|e| Error::from(e).prefix(&tmp_path_s))?;
+		fs::rename(&tmp_path, &state_file)
+			.await
+			.map_err(|e| Error::from(e).prefix(&path))?;
+		cert.info(&format!(
+			"{name}: registered on the acme-dns server, the record has to be delegated using: {}",
+			get_delegation_record(name, &creds.fulldomain)
+		));
+		Ok(creds)
+	}
+}
+
+fn get_delegation_record(name: &str, fulldomain: &str) -> String {
+	let fulldomain = fulldomain.trim_end_matches('.').to_lowercase();
+	format!("{name}. CNAME {fulldomain}.")
+}
+
+/// Check the record is delegated to the acme-dns server, so a missing CNAME record does not cost
+/// a failed validation. Since the system's resolver may not be able to answer, the check is only
+/// skipped if the lookup fails.
+async fn check_delegation(cert: &Certificate, name: &str, fulldomain: &str) -> Result<(), Error> {
+	let target = match dns::get_system_nameservers() {
+		Ok(resolvers) => resolve_cname(&resolvers, name).await,
+		Err(e) => Err(e),
+	};
+	match target {
+		Ok(t) if t == fulldomain.trim_end_matches('.').to_lowercase() => Ok(()),
+		Ok(_) => {
+			let msg = format!(
+				"{name}: the record is not delegated to the acme-dns server, the following record has to be created: {}",
+				get_delegation_record(name, fulldomain)
+			);
+			Err(msg.into())
+		}
+		Err(e) => {
+			cert.debug(&e.prefix("unable to check the acme-dns delegation").message);
+			Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_delegation_record, AcmeDns, Credentials};
+
+	#[test]
+	fn test_new() {
+		let acme_dns = AcmeDns::new("https://auth.example.org/", &[]).unwrap();
+		assert_eq!(acme_dns.url, "https://auth.example.org");
+		assert!(AcmeDns::new("auth.example.org", &[]).is_err());
+		assert!(AcmeDns::new("ftp://auth.example.org", &[]).is_err());
+	}
+
+	#[test]
+	fn test_credentials() {
+		let body = r#"{
+			"allowfrom": ["192.0.2.0/24"],
+			"fulldomain": "8e5700ea-a4bf-41c7-8a77-e990661dcc6a.auth.example.org",
+			"password": "htB9mR9DYgcu9bX_afHF62erXaH2TS7bg9KW3F7Z",
+			"subdomain": "8e5700ea-a4bf-41c7-8a77-e990661dcc6a",
+			"username": "c36f50e8-4632-44f0-83fe-e070fef28a10"
+		}"#;
+		let creds: Credentials = serde_json::from_str(body).unwrap();
+		assert_eq!(creds.subdomain, "8e5700ea-a4bf-41c7-8a77-e990661dcc6a");
+		assert_eq!(creds.username, "c36f50e8-4632-44f0-83fe-e070fef28a10");
+		assert_eq!(
+			get_delegation_record("_acme-challenge.example.org", &creds.fulldomain),
+			"_acme-challenge.example.org. CNAME 8e5700ea-a4bf-41c7-8a77-e990661dcc6a.auth.example.org."
+		);
+	}
+}
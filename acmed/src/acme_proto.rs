@@ -1,17 +1,30 @@
 use crate::acme_proto::structs::{
-	AcmeError, ApiError, Authorization, AuthorizationStatus, NewOrder, Order, OrderStatus,
+	AcmeError, ApiError, Authorization, AuthorizationDeactivation, AuthorizationStatus,
+	AutoRenewal, NewOrder, Order, OrderStatus,
 };
-use crate::certificate::Certificate;
+use crate::caa;
+use crate::certificate::{Certificate, PendingOrder, Star, StarOrder};
+use crate::config::CaaCheck;
+use crate::dns_propagation;
+use crate::endpoint::{format_timestamp, Endpoint, TosChange};
+use crate::events;
+use crate::hooks::ChallengeHookData;
 use crate::http::HttpError;
 use crate::identifier::IdentifierType;
 use crate::jws::encode_kid;
-use crate::logs::HasLogger;
+use crate::kubernetes;
+use crate::logs::{HasLogger, LogEvent};
+use crate::ocsp;
+use crate::solver::Solver;
 use crate::storage;
 use crate::{AccountSync, EndpointSync};
-use acme_common::crypto::Csr;
-use acme_common::error::Error;
+use acme_common::crypto::{Csr, HashFunction, KeyPair, X509Certificate};
+use acme_common::error::{Error, ErrorKind};
+use acme_common::now;
+use futures::future;
 use serde_json::json;
 use std::fmt;
+use std::str::FromStr;
 
 pub mod account;
 mod certificate;
@@ -58,6 +71,43 @@ impl PartialEq<structs::Challenge> for Challenge {
 	}
 }
 
+/// Revocation reason codes, as defined in RFC 5280 section 5.3.1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RevocationReason {
+	Unspecified,
+	KeyCompromise,
+	AffiliationChanged,
+	Superseded,
+	CessationOfOperation,
+}
+
+impl FromStr for RevocationReason {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Error> {
+		match s.to_lowercase().as_str() {
+			"unspecified" => Ok(RevocationReason::Unspecified),
+			"key-compromise" => Ok(RevocationReason::KeyCompromise),
+			"affiliation-changed" => Ok(RevocationReason::AffiliationChanged),
+			"superseded" => Ok(RevocationReason::Superseded),
+			"cessation-of-operation" => Ok(RevocationReason::CessationOfOperation),
+			_ => Err(format!("{s}: unknown revocation reason.").into()),
+		}
+	}
+}
+
+impl RevocationReason {
+	pub fn code(&self) -> u8 {
+		match self {
+			RevocationReason::Unspecified => 0,
+			RevocationReason::KeyCompromise => 1,
+			RevocationReason::AffiliationChanged => 3,
+			RevocationReason::Superseded => 4,
+			RevocationReason::CessationOfOperation => 5,
+		}
+	}
+}
+
 #[macro_export]
 macro_rules! set_data_builder_sync {
 	($account: ident, $endpoint_name: ident, $data: expr) => {{
@@ -85,19 +135,300 @@ macro_rules! set_data_builder {
 	};
 }
 
+/// Final state of an order once its authorizations have been solved and it has been finalized.
+enum OrderOutcome {
+	/// The certificate has been issued and stored.
+	Valid,
+	/// The order, whose URL is given, did not leave the processing state in time.
+	Stuck(String),
+}
+
+/// Challenges handled by the same solver.
+type SolverGroup = (Vec<ChallengeHookData>, Box<dyn Solver>);
+
+enum ChallengeSolver {
+	/// Indexes of the already prepared challenge's group and of the challenge in this group.
+	Batched((usize, usize)),
+	Sequential(Box<ChallengeHookData>, Box<dyn Solver>),
+}
+
+/// Solve the challenges of the pending authorizations.
+///
+/// The dns-01 challenges are all prepared before the first one is submitted, this way the DNS
+/// provider receives every change at once and the propagation delays overlap. The challenges
+/// sharing a named solver are grouped, so each solver prepares and cleans up its proofs in a
+/// single batch. They are cleaned up by the caller once the order has reached its final state,
+/// since some CAs check the records again before issuing the certificate. The other challenges
+/// often rely on resources which cannot be shared between identifiers, such as the port used by
+/// tacd, hence they are solved and cleaned up one after the other. Since the batched challenges
+/// do not depend on each other, their authorizations are polled concurrently.
+///
+/// Every challenge which has been prepared but not yet cleaned up is listed in `prepared`,
+/// grouped by solver.
+async fn solve_authorizations(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	pending: &[(&String, Authorization)],
+	prepared: &mut Vec<SolverGroup>,
+) -> Result<(), Error> {
+	let mut challenges = vec![];
+	let mut groups: Vec<SolverGroup> = vec![];
+	let mut group_names: Vec<Option<String>> = vec![];
+	for (auth_url, auth) in pending.iter() {
+		let current_identifier = cert.get_identifier_from_str(&auth.identifier.value)?;
+		let current_challenge = current_identifier.challenge;
+		let mut to_solve = None;
+		for challenge in auth.challenges.iter() {
+			if current_challenge == *challenge {
+				let proof = challenge.get_proof(&account_s.read().await.current_key.key)?;
+				let thumbprint = account_s.read().await.current_key.key.jwk_thumbprint()?;
+				let account = account_s.read().await.get_identity(endpoint_name);
+				let file_name = challenge.get_file_name();
+				let identifier = auth.identifier.value.to_owned();
+				let (data, solver) = cert
+					.get_challenge_data(&file_name, &proof, &identifier, &account, &thumbprint)
+					.await?;
+				let solver = if current_challenge == Challenge::Dns01 {
+					// The challenges of unnamed solvers are never grouped.
+					let name = current_identifier
+						.solver
+						.as_ref()
+						.map(|s| s.name.to_owned());
+					let group_idx = match group_names
+						.iter()
+						.position(|n| name.is_some() && *n == name)
+					{
+						Some(idx) => {
+							groups[idx].0.push(data);
+							idx
+						}
+						None => {
+							group_names.push(name);
+							groups.push((vec![data], solver));
+							groups.len() - 1
+						}
+					};
+					let item_idx = groups[group_idx].0.len() - 1;
+					ChallengeSolver::Batched((group_idx, item_idx))
+				} else {
+					ChallengeSolver::Sequential(Box::new(data), solver)
+				};
+				to_solve = Some((challenge.get_url(), solver));
+			}
+		}
+		challenges.push((*auth_url, to_solve));
+	}
+	for (data, solver) in groups.into_iter() {
+		// Ask the solver to make the proofs available
+		let res = solver.prepare_all(cert, &data).await;
+		prepared.push((data, solver));
+		res?;
+	}
+	let nb_prepared: usize = prepared.iter().map(|(data, _)| data.len()).sum();
+	if nb_prepared > 1 {
+		cert.debug(&format!(
+			"{nb_prepared} dns-01 challenges prepared at once using {} solver(s)",
+			prepared.len()
+		));
+	}
+
+	let mut polls = vec![];
+	for (auth_url, to_solve) in challenges.into_iter() {
+		match to_solve {
+			Some((chall_url, ChallengeSolver::Sequential(data, solver))) => {
+				// Ask the solver to make the proof available
+				let res = solver.prepare(cert, &data).await;
+				prepared.push((vec![*data], solver));
+				res?;
+				let (data, solver) = &prepared[prepared.len() - 1];
+				submit_challenge(
+					cert,
+					account_s,
+					endpoint_s,
+					endpoint_name,
+					&chall_url,
+					&data[0],
+					solver.as_ref(),
+				)
+				.await?;
+				poll_authorization(cert, account_s, endpoint_s, endpoint_name, auth_url).await?;
+				if let Some((data, solver)) = prepared.pop() {
+					solver.cleanup_all(cert, &data).await?;
+				}
+			}
+			Some((chall_url, ChallengeSolver::Batched((group_idx, item_idx)))) => {
+				let (data, solver) = &prepared[group_idx];
+				submit_challenge(
+					cert,
+					account_s,
+					endpoint_s,
+					endpoint_name,
+					&chall_url,
+					&data[item_idx],
+					solver.as_ref(),
+				)
+				.await?;
+				polls.push(poll_authorization(
+					cert,
+					account_s,
+					endpoint_s,
+					endpoint_name,
+					auth_url,
+				));
+			}
+			None => polls.push(poll_authorization(
+				cert,
+				account_s,
+				endpoint_s,
+				endpoint_name,
+				auth_url,
+			)),
+		}
+	}
+	future::try_join_all(polls).await?;
+	Ok(())
+}
+
+/// Wait until the proof is available, then tell the server the challenge has been completed.
+async fn submit_challenge(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	chall_url: &str,
+	data: &ChallengeHookData,
+	solver: &dyn Solver,
+) -> Result<(), Error> {
+	solver.wait(cert, data).await?;
+	if cert.dns_propagation_check && data.challenge == Challenge::Dns01.to_string() {
+		dns_propagation::wait_for_propagation(cert, &data.identifier, &data.proof).await?;
+	}
+	if cert.http_self_check && data.challenge == Challenge::Http01.to_string() {
+		crate::http::check_http_01_proof(
+			&data.identifier,
+			&data.file_name,
+			&data.proof,
+			cert.http_self_check_address,
+		)
+		.await
+		.map_err(|e| e.prefix(&format!("{}: http-01 self-check", data.identifier)))?;
+		cert.debug(&format!("{}: http-01 self-check passed", data.identifier));
+	}
+
+	let data_builder = set_data_builder!(account_s, endpoint_name, b"{}").await;
+	http::post_jose_no_response(
+		&mut *(endpoint_s.write().await),
+		&data_builder,
+		chall_url,
+		None,
+	)
+	.await
+	.map_err(HttpError::in_err)
+}
+
+/// Pool the authorization until it is valid.
+async fn poll_authorization(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	auth_url: &str,
+) -> Result<(), Error> {
+	let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
+	let break_fn = |a: &Authorization| {
+		a.status == AuthorizationStatus::Valid || a.status == AuthorizationStatus::Invalid
+	};
+	let timeout = endpoint_s.read().await.authorization_timeout;
+	let auth = http::pool_authorization(endpoint_s, &data_builder, &break_fn, auth_url, timeout)
+		.await
+		.map_err(HttpError::in_err)?
+		.ok_or_else(|| {
+			Error::from(format!(
+				"{auth_url}: authorization still not valid after {}s",
+				timeout.as_secs()
+			))
+		})?;
+	drop(data_builder);
+	if auth.status != AuthorizationStatus::Valid {
+		let mut msg = format!(
+			"{}: authorization status is {}",
+			auth.identifier, auth.status
+		);
+		if let Some(e) = auth.get_error() {
+			msg += &format!(": {}", e.message);
+		}
+		return Err(msg.into());
+	}
+	events::emit(
+		"authorization_valid",
+		Some(cert.get_id()),
+		json!({"identifier": auth.identifier.value}),
+	);
+	Ok(())
+}
+
+async fn check_caa(cert: &Certificate, endpoint_s: &EndpointSync) -> Result<(), Error> {
+	let caa_identities = endpoint_s
+		.read()
+		.await
+		.dir
+		.meta
+		.as_ref()
+		.and_then(|m| m.caa_identities.clone())
+		.unwrap_or_default();
+	let res =
+		if caa_identities.is_empty() {
+			Err("the endpoint does not specify its CAA identities, the CAA records cannot be checked".into())
+		} else {
+			caa::check_identifiers(cert, &caa_identities).await
+		};
+	match res {
+		Ok(_) => Ok(()),
+		Err(e) if cert.caa_check == CaaCheck::Deny => Err(e),
+		Err(e) => {
+			cert.warn(&e.message);
+			Ok(())
+		}
+	}
+}
+
+/// Request a new certificate and store it. Return whether or not the stored certificate has been
+/// replaced, which may not be the case when fetching the latest certificate of a STAR order.
 pub async fn request_certificate(
 	cert: &Certificate,
 	account_s: AccountSync,
 	endpoint_s: EndpointSync,
-) -> Result<(), Error> {
-	let mut hook_datas = vec![];
+) -> Result<bool, Error> {
 	let endpoint_name = endpoint_s.read().await.name.clone();
+	let is_renewal = storage::certificate_files_exists(&cert.file_manager);
 
 	// Refresh the directory
 	http::refresh_directory(&mut *(endpoint_s.write().await))
 		.await
 		.map_err(HttpError::in_err)?;
 
+	// While the current STAR order runs, the CA issues the certificates on its own.
+	let star_order = cert.get_star_order()?;
+	match (&cert.star, star_order) {
+		(Some(star), Some(star_order))
+			if is_star_order_active(star, &star_order, &endpoint_name) =>
+		{
+			return fetch_star_certificate(cert, &account_s, &endpoint_s, star_order).await;
+		}
+		(None, Some(star_order)) => {
+			cancel_star_order(cert, &account_s, &endpoint_s, &star_order).await;
+		}
+		(Some(star), _) => check_star_support(&endpoint_s, star).await?,
+		(None, None) => {}
+	}
+
+	// Check the CAA records
+	if cert.caa_check != CaaCheck::Off {
+		check_caa(cert, &endpoint_s).await?;
+	}
+
 	// Synchronize the account
 	account_s
 		.write()
@@ -106,17 +437,140 @@ pub async fn request_certificate(
 		.await?;
 
 	// Create a new order
+	let profile = match &cert.profile {
+		Some(p) => select_profile(cert, &*endpoint_s.read().await, p)?,
+		None => None,
+	};
+	let mut nb_recreations = 0;
+	loop {
+		match run_order(cert, &account_s, &endpoint_s, &endpoint_name, &profile).await? {
+			OrderOutcome::Valid => break,
+			OrderOutcome::Stuck(order_url) => {
+				let msg = format!(
+					"{order_url}: order still processing after {}s",
+					endpoint_s.read().await.finalization_timeout.as_secs()
+				);
+				if nb_recreations >= crate::DEFAULT_STUCK_ORDER_MAX_RECREATIONS {
+					return Err(msg.into());
+				}
+				cert.warn(&format!("{msg}: abandoning it and creating a new one"));
+				nb_recreations += 1;
+			}
+		}
+	}
+
+	let (event, action) = if is_renewal {
+		(LogEvent::CertificateRenewed, "renewed")
+	} else {
+		(LogEvent::CertificateIssued, "issued")
+	};
+	event.log(|| {
+		cert.info(&format!(
+			"certificate {action} (identifiers: {})",
+			cert.identifier_list()
+		))
+	});
+	Ok(true)
+}
+
+/// Create a new order, registering the account or agreeing to the terms of service if needed.
+async fn create_order(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	profile: &Option<String>,
+) -> Result<(Order, String), Error> {
+	endpoint_s
+		.read()
+		.await
+		.check_order_limits(&cert.account_name)
+		.await?;
 	let mut new_reg = false;
-	let (order, order_url) = loop {
-		let new_order = NewOrder::new(&cert.identifiers);
+	let mut tos_agreed = false;
+	loop {
+		let mut new_order = NewOrder::new(&cert.identifiers, profile.clone());
+		new_order.auto_renewal = cert.star.as_ref().map(|s| AutoRenewal {
+			end_date: format_timestamp(now() + s.duration.as_secs()),
+			lifetime: s.lifetime.as_secs(),
+			lifetime_adjust: s.lifetime_adjust.map(|d| d.as_secs()),
+		});
 		let new_order = serde_json::to_string(&new_order)?;
 		let data_builder = set_data_builder!(account_s, endpoint_name, new_order.as_bytes()).await;
-		match http::new_order(&mut *(endpoint_s.write().await), &data_builder).await {
+		let res = http::new_order(&mut *(endpoint_s.write().await), &data_builder).await;
+		match res {
 			Ok((order, order_url)) => {
 				if let Some(e) = order.get_error() {
 					cert.warn(&e.prefix("Error").message);
 				}
-				break (order, order_url);
+				// Only the orders actually created count toward the limits.
+				if let Err(e) = endpoint_s
+					.read()
+					.await
+					.register_order(&cert.account_name)
+					.await
+				{
+					cert.warn(&e.message);
+				}
+				events::emit(
+					"order_created",
+					Some(cert.get_id()),
+					json!({"url": order_url}),
+				);
+				// The terms of service may have been agreed to by other means.
+				if let Err(e) = endpoint_s
+					.read()
+					.await
+					.tos_changes
+					.clear(&cert.account_name)
+				{
+					cert.warn(&e.message);
+				}
+				return Ok((order, order_url));
+			}
+			Err(e) if !tos_agreed && e.is_acme_err(AcmeError::UserActionRequired) => {
+				drop(data_builder);
+				let change = TosChange {
+					url: e.get_api_error().and_then(|e| e.get_terms_of_service()),
+					instance: e.get_api_error().and_then(|e| e.get_instance()),
+					detected: now(),
+				};
+				let tos_auto_agree = endpoint_s.read().await.tos_auto_agree;
+				if tos_auto_agree && change.url.is_some() {
+					cert.info(&format!(
+						"endpoint \"{endpoint_name}\": {change}, agreeing to them"
+					));
+					// The account lock is always taken before the endpoint one.
+					account_s
+						.write()
+						.await
+						.agree_to_terms_of_service(&mut *(endpoint_s.write().await))
+						.await?;
+					tos_agreed = true;
+					continue;
+				}
+				if let Err(e) = endpoint_s
+					.read()
+					.await
+					.tos_changes
+					.save(&cert.account_name, &change)
+				{
+					cert.warn(&e.message);
+				}
+				events::emit(
+					"terms_of_service_changed",
+					Some(cert.get_id()),
+					json!({
+						"endpoint": endpoint_name,
+						"account": cert.account_name,
+						"url": change.url,
+						"instance": change.instance,
+					}),
+				);
+				let msg = format!(
+					"endpoint \"{endpoint_name}\": {change}: run `acmed account agree-tos {endpoint_name}` to agree to them"
+				);
+				return Err(msg.into());
 			}
 			Err(e) => {
 				if !new_reg && e.is_acme_err(AcmeError::AccountDoesNotExist) {
@@ -124,7 +578,7 @@ pub async fn request_certificate(
 					account_s
 						.write()
 						.await
-						.register(&mut *(endpoint_s.write().await))
+						.auto_register(&mut *(endpoint_s.write().await))
 						.await?;
 					new_reg = true;
 				} else {
@@ -132,11 +586,49 @@ pub async fn request_certificate(
 				}
 			}
 		};
+	}
+}
+
+/// Resume the pending order or create a new one, solve its authorizations and finalize it.
+async fn run_order(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	profile: &Option<String>,
+) -> Result<OrderOutcome, Error> {
+	let (order, order_url) =
+		match get_resumable_order(cert, account_s, endpoint_s, endpoint_name).await {
+			Some(o) => o,
+			None => create_order(cert, account_s, endpoint_s, endpoint_name, profile).await?,
+		};
+	let state = PendingOrder {
+		endpoint: endpoint_name.to_string(),
+		order_url: order_url.to_owned(),
+		authorizations: order.authorizations.clone(),
 	};
+	if let Err(e) = cert.save_pending_order(&state) {
+		cert.warn(&e.prefix("unable to save the pending order").message);
+	}
+	if order.status == OrderStatus::Processing || order.status == OrderStatus::Valid {
+		// The order has been finalized before the interruption, using the stored key pair.
+		let key_pair = certificate::read_key_pair(cert).await?;
+		let res = download_certificate(
+			cert,
+			account_s,
+			endpoint_s,
+			endpoint_name,
+			&order_url,
+			&key_pair,
+		)
+		.await;
+		remove_pending_order(cert);
+		return res;
+	}
 
-	// Begin iter over authorizations
+	// Fetch the pending authorizations
+	let mut pending = vec![];
 	for auth_url in order.authorizations.iter() {
-		// Fetch the authorization
 		let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
 		let auth =
 			http::get_authorization(&mut *(endpoint_s.write().await), &data_builder, auth_url)
@@ -156,70 +648,369 @@ pub async fn request_certificate(
 			);
 			return Err(msg.into());
 		}
+		pending.push((auth_url, auth));
+	}
+	// Solve the challenges and finalize the order, then clean up the challenges in any case
+	let mut prepared = vec![];
+	let res = match solve_authorizations(
+		cert,
+		account_s,
+		endpoint_s,
+		endpoint_name,
+		&pending,
+		&mut prepared,
+	)
+	.await
+	{
+		Ok(_) => finalize_order(cert, account_s, endpoint_s, endpoint_name, &order_url).await,
+		Err(e) => Err(e),
+	};
+	if res.is_err() {
+		// The order is abandoned, hence the authorizations which could not be solved.
+		if let Err(e) = deactivate_authorizations(cert, account_s, endpoint_s, &state).await {
+			cert.warn(
+				&e.prefix("unable to deactivate the pending authorizations")
+					.message,
+			);
+		}
+	}
+	remove_pending_order(cert);
+	if res.is_ok() && !prepared.is_empty() && !cert.dns_cleanup_delay.is_zero() {
+		cert.debug(&format!(
+			"waiting {}s before cleaning up the dns-01 challenges",
+			cert.dns_cleanup_delay.as_secs()
+		));
+		tokio::time::sleep(cert.dns_cleanup_delay).await;
+	}
+	for (data, solver) in prepared.iter() {
+		if let Err(e) = solver.cleanup_all(cert, data).await {
+			if res.is_ok() {
+				return Err(e);
+			}
+			cert.warn(&e.message);
+		}
+	}
+	res
+}
 
-		// Fetch the associated challenges
-		let current_identifier = cert.get_identifier_from_str(&auth.identifier.value)?;
-		let current_challenge = current_identifier.challenge;
-		for challenge in auth.challenges.iter() {
-			if current_challenge == *challenge {
-				let proof = challenge.get_proof(&account_s.read().await.current_key.key)?;
-				let file_name = challenge.get_file_name();
-				let identifier = auth.identifier.value.to_owned();
+/// Return the profile to request, which must be one of those advertised by the endpoint. If the
+/// endpoint does not support the profiles, none is requested.
+fn select_profile(
+	cert: &Certificate,
+	endpoint: &Endpoint,
+	profile: &str,
+) -> Result<Option<String>, Error> {
+	let advertised = match endpoint.dir.meta.as_ref().and_then(|m| m.profiles.as_ref()) {
+		Some(p) => p,
+		None => {
+			cert.warn(&format!(
+				"endpoint \"{}\" does not advertise any profile, the \"{profile}\" profile is not requested",
+				endpoint.name
+			));
+			return Ok(None);
+		}
+	};
+	if !advertised.contains_key(profile) {
+		let names: Vec<&str> = advertised.keys().map(|k| k.as_str()).collect();
+		let msg = format!(
+			"{profile}: profile not offered by endpoint \"{}\", available profiles are: {}",
+			endpoint.name,
+			names.join(", ")
+		);
+		return Err(Error::new(ErrorKind::Config, &msg));
+	}
+	Ok(Some(profile.to_string()))
+}
 
-				// Call the challenge hook in order to complete it
-				let mut data = cert
-					.call_challenge_hooks(&file_name, &proof, &identifier)
-					.await?;
-				data.0.is_clean_hook = true;
-				hook_datas.push(data);
-
-				// Tell the server the challenge has been completed
-				let chall_url = challenge.get_url();
-				let data_builder = set_data_builder!(account_s, endpoint_name, b"{}").await;
-				http::post_jose_no_response(
-					&mut *(endpoint_s.write().await),
-					&data_builder,
-					&chall_url,
-					None,
-				)
-				.await
-				.map_err(HttpError::in_err)?;
-				drop(data_builder);
-			}
+/// Return whether or not the STAR order may still be used, which is the case until its last
+/// certificate is issued.
+fn is_star_order_active(star: &Star, star_order: &StarOrder, endpoint_name: &str) -> bool {
+	star_order.endpoint == endpoint_name && star_order.end_date > now() + star.lifetime.as_secs()
+}
+
+async fn check_star_support(endpoint_s: &EndpointSync, star: &Star) -> Result<(), Error> {
+	let endpoint = endpoint_s.read().await;
+	let meta = endpoint
+		.dir
+		.meta
+		.as_ref()
+		.and_then(|m| m.auto_renewal.as_ref())
+		.ok_or_else(|| {
+			Error::from(format!(
+				"endpoint \"{}\" does not support STAR certificates",
+				endpoint.name
+			))
+		})?;
+	if let Some(min) = meta.min_lifetime {
+		if star.lifetime.as_secs() < min {
+			let msg = format!("star: the lifetime must be at least {min} seconds");
+			return Err(msg.into());
 		}
+	}
+	if let Some(max) = meta.max_duration {
+		if star.duration.as_secs() > max {
+			let msg = format!("star: the duration must not exceed {max} seconds");
+			return Err(msg.into());
+		}
+	}
+	Ok(())
+}
 
-		// Pool the authorization in order to see whether or not it is valid
-		let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
-		let break_fn = |a: &Authorization| a.status == AuthorizationStatus::Valid;
-		let _ = http::pool_authorization(
+/// Fetch the latest certificate of a STAR order and store it if it has not been already.
+async fn fetch_star_certificate(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	mut star_order: StarOrder,
+) -> Result<bool, Error> {
+	let endpoint_name = &star_order.endpoint;
+	let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
+	let res = http::get_certificate(
+		&mut *(endpoint_s.write().await),
+		&data_builder,
+		&star_order.certificate_url,
+	)
+	.await;
+	drop(data_builder);
+	let (crt, _) = res.map_err(HttpError::in_err)?;
+	star_order.fetched = now();
+	cert.save_star_order(&star_order)?;
+	let fingerprint =
+		X509Certificate::from_pem(crt.as_bytes())?.fingerprint(HashFunction::Sha256)?;
+	if storage::certificate_files_exists(&cert.file_manager) {
+		let current = storage::get_certificate(&cert.file_manager).await?;
+		if current.fingerprint(HashFunction::Sha256)? == fingerprint {
+			cert.debug("the CA has not issued a new STAR certificate yet");
+			return Ok(false);
+		}
+	}
+	let key_pair = certificate::read_key_pair(cert).await?;
+	store_certificate(cert, endpoint_s, &key_pair, &crt).await?;
+	LogEvent::CertificateRenewed.log(|| {
+		cert.info(&format!(
+			"STAR certificate fetched (identifiers: {})",
+			cert.identifier_list()
+		))
+	});
+	Ok(true)
+}
+
+/// Cancel the STAR order of a certificate which is no longer configured as such, so the CA
+/// stops issuing its certificates.
+async fn cancel_star_order(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	star_order: &StarOrder,
+) {
+	if star_order.endpoint == endpoint_s.read().await.name && star_order.end_date > now() {
+		let endpoint_name = &star_order.endpoint;
+		let data_builder =
+			set_data_builder!(account_s, endpoint_name, br#"{"status":"canceled"}"#).await;
+		let res = http::post_jose_no_response(
 			&mut *(endpoint_s.write().await),
 			&data_builder,
-			&break_fn,
-			auth_url,
+			&star_order.order_url,
+			None,
 		)
+		.await;
+		match res {
+			Ok(_) => cert.info("STAR order canceled"),
+			Err(e) => cert.warn(
+				&HttpError::in_err(e)
+					.prefix("unable to cancel the STAR order")
+					.message,
+			),
+		}
+	}
+	if let Err(e) = cert.remove_star_order() {
+		cert.warn(&e.message);
+	}
+}
+
+/// Deactivate the pending authorizations of an order left by an interrupted renewal, for
+/// example by a crash, so they do not count toward the CA's limit on pending authorizations.
+/// Unless the order is abandoned, for example because the certificate has been removed from the
+/// configuration, it is kept if it may be resumed by the next renewal, which is the case if it is
+/// still usable, was created on the first of the given endpoints and is for the certificate's
+/// current identifiers. Each endpoint is given along with the account used on it.
+pub async fn deactivate_stale_authorizations(
+	cert: &Certificate,
+	endpoints: &[(AccountSync, EndpointSync)],
+	is_abandoned: bool,
+) -> Result<(), Error> {
+	let stale = match cert.get_pending_order()? {
+		Some(s) => s,
+		None => return Ok(()),
+	};
+	let mut found = None;
+	for (acc, ep) in endpoints.iter() {
+		if ep.read().await.name == stale.endpoint {
+			found = Some((acc, ep.clone()));
+		}
+	}
+	let (account_s, endpoint_s) = match found {
+		Some(v) => v,
+		None => {
+			cert.remove_pending_order()?;
+			let msg = format!(
+				"{}: endpoint no longer used, the stale authorizations are not deactivated",
+				stale.endpoint
+			);
+			return Err(msg.into());
+		}
+	};
+	let endpoint_name = stale.endpoint.as_str();
+	http::refresh_directory(&mut *(endpoint_s.write().await))
 		.await
 		.map_err(HttpError::in_err)?;
+	let is_first = match endpoints.first() {
+		Some((_, ep)) => ep.read().await.name == stale.endpoint,
+		None => false,
+	};
+	if is_first && !is_abandoned {
+		let order = get_order(account_s, &endpoint_s, endpoint_name, &stale.order_url).await?;
+		if is_resumable(cert, &order) {
+			cert.debug(&format!(
+				"{}: pending order kept in order to be resumed (status: {})",
+				stale.order_url, order.status
+			));
+			return Ok(());
+		}
+	}
+	deactivate_authorizations(cert, account_s, &endpoint_s, &stale).await?;
+	cert.remove_pending_order()
+}
+
+/// Deactivate the authorizations of the order which are still pending.
+async fn deactivate_authorizations(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	stale: &PendingOrder,
+) -> Result<(), Error> {
+	let endpoint_name = stale.endpoint.as_str();
+	for auth_url in stale.authorizations.iter() {
+		let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
+		let auth =
+			http::get_authorization(&mut *(endpoint_s.write().await), &data_builder, auth_url)
+				.await
+				.map_err(HttpError::in_err)?;
 		drop(data_builder);
-		for (data, hook_type) in hook_datas.iter() {
-			cert.call_challenge_hooks_clean(data, (*hook_type).to_owned())
-				.await?;
+		if auth.status != AuthorizationStatus::Pending {
+			continue;
+		}
+		let deactivation = serde_json::to_string(&AuthorizationDeactivation::new())?;
+		let data_builder =
+			set_data_builder!(account_s, endpoint_name, deactivation.as_bytes()).await;
+		http::get_authorization(&mut *(endpoint_s.write().await), &data_builder, auth_url)
+			.await
+			.map_err(HttpError::in_err)?;
+		cert.info(&format!(
+			"{}: stale pending authorization deactivated",
+			auth.identifier
+		));
+	}
+	Ok(())
+}
+
+async fn get_order(
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	order_url: &str,
+) -> Result<Order, Error> {
+	let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
+	http::get_order(&mut *(endpoint_s.write().await), &data_builder, order_url)
+		.await
+		.map_err(HttpError::in_err)
+}
+
+/// Return whether or not an order created by an interrupted renewal may be used instead of
+/// creating a new one.
+fn is_resumable(cert: &Certificate, order: &Order) -> bool {
+	let is_usable = matches!(
+		order.status,
+		OrderStatus::Pending | OrderStatus::Ready | OrderStatus::Processing | OrderStatus::Valid
+	);
+	is_usable && order.has_identifiers(&cert.identifiers)
+}
+
+/// Return the order left by an interrupted renewal on this endpoint, if it can be resumed. If
+/// it cannot, its pending authorizations are deactivated.
+async fn get_resumable_order(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+) -> Option<(Order, String)> {
+	let pending = match cert.get_pending_order() {
+		Ok(Some(p)) if p.endpoint == endpoint_name => p,
+		Ok(_) => return None,
+		Err(e) => {
+			cert.warn(&e.prefix("unable to read the pending order").message);
+			return None;
 		}
-		hook_datas.clear();
+	};
+	match get_order(account_s, endpoint_s, endpoint_name, &pending.order_url).await {
+		Ok(order) if is_resumable(cert, &order) => {
+			cert.info(&format!(
+				"{}: resuming the pending order (status: {})",
+				pending.order_url, order.status
+			));
+			return Some((order, pending.order_url));
+		}
+		Ok(order) => cert.debug(&format!(
+			"{}: the pending order cannot be resumed (status: {})",
+			pending.order_url, order.status
+		)),
+		Err(e) => cert.warn(&e.prefix(&pending.order_url).message),
+	}
+	if let Err(e) = deactivate_authorizations(cert, account_s, endpoint_s, &pending).await {
+		cert.warn(
+			&e.prefix("unable to deactivate the stale authorizations")
+				.message,
+		);
 	}
-	// End iter over authorizations
+	None
+}
+
+fn remove_pending_order(cert: &Certificate) {
+	if let Err(e) = cert.remove_pending_order() {
+		cert.warn(&e.prefix("unable to remove the pending order").message);
+	}
+}
+
+/// Finalize an order whose authorizations are valid by sending the CSR.
+async fn finalize_order(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	order_url: &str,
+) -> Result<OrderOutcome, Error> {
+	let authorization_timeout = endpoint_s.read().await.authorization_timeout;
 
 	// Pool the order in order to see whether or not it is ready
 	let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
-	let break_fn = |o: &Order| o.status == OrderStatus::Ready;
+	let break_fn = |o: &Order| o.status == OrderStatus::Ready || o.status == OrderStatus::Invalid;
 	let order = http::pool_order(
-		&mut *(endpoint_s.write().await),
+		endpoint_s,
 		&data_builder,
 		&break_fn,
-		&order_url,
+		order_url,
+		authorization_timeout,
 	)
 	.await
-	.map_err(HttpError::in_err)?;
+	.map_err(HttpError::in_err)?
+	.ok_or_else(|| {
+		Error::from(format!(
+			"{order_url}: order still not ready after {}s",
+			authorization_timeout.as_secs()
+		))
+	})?;
 	drop(data_builder);
+	check_order_status(&order, order_url)?;
 
 	// Finalize the order by sending the CSR
 	let key_pair = certificate::get_key_pair(cert).await?;
@@ -260,33 +1051,200 @@ pub async fn request_certificate(
 		cert.warn(&e.prefix("error").message);
 	}
 
+	download_certificate(
+		cert,
+		account_s,
+		endpoint_s,
+		endpoint_name,
+		order_url,
+		&key_pair,
+	)
+	.await
+}
+
+/// Wait for the finalized order to be valid, then download and store the certificate.
+async fn download_certificate(
+	cert: &Certificate,
+	account_s: &AccountSync,
+	endpoint_s: &EndpointSync,
+	endpoint_name: &str,
+	order_url: &str,
+	key_pair: &KeyPair,
+) -> Result<OrderOutcome, Error> {
+	let finalization_timeout = endpoint_s.read().await.finalization_timeout;
+
 	// Pool the order in order to see whether or not it is valid
 	let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
-	let break_fn = |o: &Order| o.status == OrderStatus::Valid;
+	let break_fn = |o: &Order| o.status == OrderStatus::Valid || o.status == OrderStatus::Invalid;
 	let order = http::pool_order(
-		&mut *(endpoint_s.write().await),
+		endpoint_s,
 		&data_builder,
 		&break_fn,
-		&order_url,
+		order_url,
+		finalization_timeout,
 	)
 	.await
 	.map_err(HttpError::in_err)?;
 	drop(data_builder);
+	let order = match order {
+		Some(o) => o,
+		None => return Ok(OrderOutcome::Stuck(order_url.to_string())),
+	};
+	check_order_status(&order, order_url)?;
 
 	// Download the certificate
-	let crt_url = order
-		.certificate
-		.ok_or_else(|| Error::from("no certificate available for download"))?;
+	let crt_url = match (&cert.star, order.star_certificate) {
+		(Some(star), Some(url)) => {
+			cert.save_star_order(&StarOrder {
+				endpoint: endpoint_name.to_string(),
+				order_url: order_url.to_string(),
+				certificate_url: url.clone(),
+				end_date: now() + star.duration.as_secs(),
+				fetched: now(),
+			})?;
+			url
+		}
+		_ => order
+			.certificate
+			.ok_or_else(|| Error::from("no certificate available for download"))?,
+	};
 	let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
-	let crt = http::get_certificate(&mut *(endpoint_s.write().await), &data_builder, &crt_url)
+	let (mut crt, alternates) =
+		http::get_certificate(&mut *(endpoint_s.write().await), &data_builder, &crt_url)
+			.await
+			.map_err(HttpError::in_err)?;
+	drop(data_builder);
+	if let Some(preferred_chain) = &cert.preferred_chain {
+		if !is_preferred_chain(&crt, preferred_chain) {
+			let mut found = false;
+			for alt_url in alternates.iter() {
+				let data_builder = set_data_builder!(account_s, endpoint_name, b"").await;
+				let (alt_crt, _) =
+					http::get_certificate(&mut *(endpoint_s.write().await), &data_builder, alt_url)
+						.await
+						.map_err(HttpError::in_err)?;
+				drop(data_builder);
+				if is_preferred_chain(&alt_crt, preferred_chain) {
+					crt = alt_crt;
+					found = true;
+					break;
+				}
+			}
+			if !found {
+				cert.info(&format!(
+					"preferred chain \"{preferred_chain}\" not available: using the default one"
+				));
+			}
+		}
+	}
+	store_certificate(cert, endpoint_s, key_pair, &crt).await?;
+	Ok(OrderOutcome::Valid)
+}
+
+fn check_order_status(order: &Order, order_url: &str) -> Result<(), Error> {
+	if order.status != OrderStatus::Invalid {
+		return Ok(());
+	}
+	let mut msg = format!("{order_url}: order status is {}", order.status);
+	if let Some(e) = order.get_error() {
+		msg += &format!(": {}", e.message);
+	}
+	Err(msg.into())
+}
+
+async fn store_certificate(
+	cert: &Certificate,
+	endpoint_s: &EndpointSync,
+	key_pair: &KeyPair,
+	crt: &str,
+) -> Result<(), Error> {
+	storage::write_certificate(&cert.file_manager, crt.as_bytes()).await?;
+	kubernetes::update_secret(cert, key_pair, crt.as_bytes()).await;
+	if cert.ocsp_staple {
+		// The certificate is valid even if its OCSP response cannot be fetched yet.
+		if let Err(e) = ocsp::update_staple(cert, endpoint_s).await {
+			cert.warn_error("ocsp", &e.prefix("OCSP response"));
+		}
+	}
+
+	Ok(())
+}
+
+fn is_preferred_chain(chain: &str, preferred_chain: &str) -> bool {
+	match X509Certificate::from_pem_chain(chain.as_bytes()) {
+		Ok(certs) => certs
+			.last()
+			.and_then(|c| c.issuer_common_name())
+			.map(|cn| cn == preferred_chain)
+			.unwrap_or(false),
+		Err(_) => false,
+	}
+}
+
+/// Refresh the endpoint's directory and return its terms of service, if any.
+pub async fn get_terms_of_service(endpoint: &mut Endpoint) -> Result<Option<String>, Error> {
+	http::refresh_directory(endpoint)
+		.await
+		.map_err(HttpError::in_err)?;
+	Ok(endpoint
+		.dir
+		.meta
+		.as_ref()
+		.and_then(|m| m.terms_of_service.clone()))
+}
+
+pub async fn revoke_certificate(
+	cert: &Certificate,
+	account_s: AccountSync,
+	endpoint_s: EndpointSync,
+	reason: RevocationReason,
+) -> Result<(), Error> {
+	let endpoint_name = endpoint_s.read().await.name.clone();
+	let crt = storage::get_certificate(&cert.file_manager).await?;
+
+	// Refresh the directory
+	http::refresh_directory(&mut *(endpoint_s.write().await))
+		.await
+		.map_err(HttpError::in_err)?;
+
+	// Synchronize the account
+	account_s
+		.write()
+		.await
+		.synchronize(&mut *(endpoint_s.write().await))
+		.await?;
+
+	// Revoke the certificate
+	let payload = json!({
+		"certificate": crt.to_der_base64()?,
+		"reason": reason.code(),
+	});
+	let payload = payload.to_string();
+	let data_builder = set_data_builder!(account_s, endpoint_name, payload.as_bytes()).await;
+	http::revoke_certificate(&mut *(endpoint_s.write().await), &data_builder)
 		.await
 		.map_err(HttpError::in_err)?;
 	drop(data_builder);
-	storage::write_certificate(&cert.file_manager, crt.as_bytes()).await?;
 
 	cert.info(&format!(
-		"certificate renewed (identifiers: {})",
+		"certificate revoked (identifiers: {})",
 		cert.identifier_list()
 	));
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::RevocationReason;
+	use std::str::FromStr;
+
+	#[test]
+	fn test_revocation_reason() {
+		let r = RevocationReason::from_str("key-compromise").unwrap();
+		assert_eq!(r, RevocationReason::KeyCompromise);
+		assert_eq!(r.code(), 1);
+		let r = RevocationReason::from_str("Superseded").unwrap();
+		assert_eq!(r.code(), 4);
+		assert!(RevocationReason::from_str("certificateHold").is_err());
+	}
+}
@@ -1,6 +1,8 @@
 use crate::account::Account as BaseAccount;
 use crate::acme_proto::http;
-use crate::acme_proto::structs::{Account, AccountKeyRollover, AccountUpdate, AcmeError};
+use crate::acme_proto::structs::{
+	Account, AccountKeyRollover, AccountTosAgreement, AccountUpdate, AcmeError,
+};
 use crate::endpoint::Endpoint;
 use crate::http::HttpError;
 use crate::jws::{encode_jwk, encode_kid};
@@ -20,7 +22,7 @@ macro_rules! create_account_if_does_not_exist {
 							$endpoint.name
 						);
 						$account.debug(&msg);
-						return register_account($endpoint, $account).await;
+						return $account.auto_register($endpoint).await;
 					}
 					_ => Err(HttpError::in_err(he.to_owned())),
 				},
@@ -108,6 +110,30 @@ pub async fn update_account_contacts(
 	Ok(())
 }
 
+pub async fn agree_to_terms_of_service(
+	endpoint: &mut Endpoint,
+	account: &mut BaseAccount,
+) -> Result<(), Error> {
+	let endpoint_name = endpoint.name.clone();
+	account.debug(&format!(
+		"agreeing to the terms of service of endpoint \"{endpoint_name}\"..."
+	));
+	let agreement = serde_json::to_string(&AccountTosAgreement::new())?;
+	let account_owned = account.clone();
+	let data_builder = set_data_builder_sync!(account_owned, endpoint_name, agreement.as_bytes());
+	let url = account.get_endpoint(&endpoint_name)?.account_url.clone();
+	create_account_if_does_not_exist!(
+		http::post_jose_no_response(endpoint, &data_builder, &url, None).await,
+		endpoint,
+		account
+	)?;
+	endpoint.tos_changes.clear(&account.name)?;
+	account.info(&format!(
+		"terms of service of endpoint \"{endpoint_name}\" agreed"
+	));
+	Ok(())
+}
+
 pub async fn update_account_key(
 	endpoint: &mut Endpoint,
 	account: &mut BaseAccount,
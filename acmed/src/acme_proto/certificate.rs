@@ -9,7 +9,7 @@ async fn gen_key_pair(cert: &Certificate) -> Result<KeyPair, Error> {
 	Ok(key_pair)
 }
 
-async fn read_key_pair(cert: &Certificate) -> Result<KeyPair, Error> {
+pub async fn read_key_pair(cert: &Certificate) -> Result<KeyPair, Error> {
 	storage::get_keypair(&cert.file_manager).await
 }
 
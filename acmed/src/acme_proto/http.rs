@@ -2,24 +2,52 @@ use crate::acme_proto::structs::{AccountResponse, Authorization, Directory, Orde
 use crate::config::NamedAcmeResource;
 use crate::endpoint::Endpoint;
 use crate::http;
+use crate::EndpointSync;
 use acme_common::error::Error;
-use std::{thread, time};
+use acme_common::now;
+use std::time::{Duration, Instant};
 
+/// Fetch the object until it reaches the expected state or the timeout elapses, in which case
+/// `None` is returned. The endpoint is only locked during each request, hence the other
+/// certificates using the same endpoint are not blocked while waiting.
 macro_rules! pool_object {
-	($obj_type: ty, $obj_name: expr, $endpoint: expr, $url: expr, $resource: expr, $data_builder: expr, $break: expr) => {{
-		for _ in 0..crate::DEFAULT_POOL_NB_TRIES {
-			thread::sleep(time::Duration::from_secs(crate::DEFAULT_POOL_WAIT_SEC));
-			let response = http::post_jose($endpoint, $url, $resource, $data_builder).await?;
+	($obj_type: ty, $obj_name: expr, $endpoint_s: expr, $url: expr, $resource: expr, $data_builder: expr, $break: expr, $timeout: expr) => {{
+		let deadline = Instant::now() + $timeout;
+		let interval = $endpoint_s.read().await.poll_interval;
+		let mut wait = interval;
+		loop {
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			if remaining.is_zero() {
+				log::debug!("{} pooling timed out on {}", $obj_name, $url);
+				return Ok(None);
+			}
+			tokio::time::sleep(wait.min(remaining)).await;
+			let response = http::post_jose(
+				&mut *($endpoint_s.write().await),
+				$url,
+				$resource,
+				$data_builder,
+			)
+			.await?;
 			let obj = response.json::<$obj_type>()?;
 			if $break(&obj) {
-				return Ok(obj);
+				return Ok(Some(obj));
 			}
+			wait = get_poll_delay(response.get_header("Retry-After"), interval, now());
 		}
-		let msg = format!("{} pooling failed on {}", $obj_name, $url);
-		Err(msg.into())
 	}};
 }
 
+/// Return how long to wait before fetching the object again. The server may ask to wait longer
+/// than the endpoint's polling interval using the `Retry-After` header.
+fn get_poll_delay(retry_after: Option<String>, interval: Duration, now: u64) -> Duration {
+	retry_after
+		.and_then(|v| http::parse_retry_after(&v, now))
+		.map(|ts| Duration::from_secs(ts.saturating_sub(now)))
+		.filter(|d| *d > interval)
+		.unwrap_or(interval)
+}
+
 pub async fn refresh_directory(endpoint: &mut Endpoint) -> Result<(), http::HttpError> {
 	let url = endpoint.url.clone();
 	let response = http::get(endpoint, &url, Some(NamedAcmeResource::Directory)).await?;
@@ -84,6 +112,24 @@ where
 	Ok((order_resp, order_uri))
 }
 
+pub async fn revoke_certificate<F>(
+	endpoint: &mut Endpoint,
+	data_builder: &F,
+) -> Result<(), http::HttpError>
+where
+	F: Fn(&str, &str) -> Result<String, Error>,
+{
+	let url = endpoint.dir.revoke_cert.clone();
+	let _ = http::post_jose(
+		endpoint,
+		&url,
+		Some(NamedAcmeResource::RevokeCert),
+		data_builder,
+	)
+	.await?;
+	Ok(())
+}
+
 pub async fn get_authorization<F>(
 	endpoint: &mut Endpoint,
 	data_builder: &F,
@@ -97,12 +143,26 @@ where
 	Ok(auth)
 }
 
-pub async fn pool_authorization<F, S>(
+pub async fn get_order<F>(
 	endpoint: &mut Endpoint,
 	data_builder: &F,
+	url: &str,
+) -> Result<Order, http::HttpError>
+where
+	F: Fn(&str, &str) -> Result<String, Error>,
+{
+	let response = http::post_jose(endpoint, url, None, data_builder).await?;
+	let order = response.json::<Order>()?;
+	Ok(order)
+}
+
+pub async fn pool_authorization<F, S>(
+	endpoint_s: &EndpointSync,
+	data_builder: &F,
 	break_fn: &S,
 	url: &str,
-) -> Result<Authorization, http::HttpError>
+	timeout: Duration,
+) -> Result<Option<Authorization>, http::HttpError>
 where
 	F: Fn(&str, &str) -> Result<String, Error>,
 	S: Fn(&Authorization) -> bool,
@@ -110,25 +170,36 @@ where
 	pool_object!(
 		Authorization,
 		"authorization",
-		endpoint,
+		endpoint_s,
 		url,
 		None,
 		data_builder,
-		break_fn
+		break_fn,
+		timeout
 	)
 }
 
 pub async fn pool_order<F, S>(
-	endpoint: &mut Endpoint,
+	endpoint_s: &EndpointSync,
 	data_builder: &F,
 	break_fn: &S,
 	url: &str,
-) -> Result<Order, http::HttpError>
+	timeout: Duration,
+) -> Result<Option<Order>, http::HttpError>
 where
 	F: Fn(&str, &str) -> Result<String, Error>,
 	S: Fn(&Order) -> bool,
 {
-	pool_object!(Order, "order", endpoint, url, None, data_builder, break_fn)
+	pool_object!(
+		Order,
+		"order",
+		endpoint_s,
+		url,
+		None,
+		data_builder,
+		break_fn,
+		timeout
+	)
 }
 
 pub async fn finalize_order<F>(
@@ -148,7 +219,7 @@ pub async fn get_certificate<F>(
 	endpoint: &mut Endpoint,
 	data_builder: &F,
 	url: &str,
-) -> Result<String, http::HttpError>
+) -> Result<(String, Vec<String>), http::HttpError>
 where
 	F: Fn(&str, &str) -> Result<String, Error>,
 {
@@ -161,5 +232,39 @@ where
 		http::CONTENT_TYPE_PEM,
 	)
 	.await?;
-	Ok(response.body)
+	let alternates = response.get_links("alternate");
+	Ok((response.body, alternates))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::get_poll_delay;
+	use std::time::Duration;
+
+	#[test]
+	fn test_get_poll_delay() {
+		let interval = Duration::from_secs(5);
+		let now = 1_700_000_000;
+		assert_eq!(get_poll_delay(None, interval, now), interval);
+		assert_eq!(
+			get_poll_delay(Some("30".to_string()), interval, now),
+			Duration::from_secs(30)
+		);
+		assert_eq!(
+			get_poll_delay(Some("1".to_string()), interval, now),
+			interval
+		);
+		assert_eq!(
+			get_poll_delay(Some("invalid".to_string()), interval, now),
+			interval
+		);
+		assert_eq!(
+			get_poll_delay(
+				Some("Tue, 14 Nov 2023 22:13:27 GMT".to_string()),
+				interval,
+				now
+			),
+			Duration::from_secs(7)
+		);
+	}
 }
@@ -19,10 +19,10 @@ mod error;
 mod order;
 
 pub use account::{
-	Account, AccountDeactivation, AccountKeyRollover, AccountResponse, AccountUpdate,
+	Account, AccountKeyRollover, AccountResponse, AccountTosAgreement, AccountUpdate,
 };
-pub use authorization::{Authorization, AuthorizationStatus, Challenge};
+pub use authorization::{Authorization, AuthorizationDeactivation, AuthorizationStatus, Challenge};
 pub use deserialize_from_str;
 pub use directory::Directory;
 pub use error::{AcmeError, ApiError, HttpApiError};
-pub use order::{Identifier, NewOrder, Order, OrderStatus};
+pub use order::{AutoRenewal, Identifier, NewOrder, Order, OrderStatus};
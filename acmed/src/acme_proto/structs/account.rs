@@ -46,6 +46,7 @@ impl Account {
 	}
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountResponse {
@@ -71,6 +72,20 @@ impl AccountUpdate {
 	}
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountTosAgreement {
+	pub terms_of_service_agreed: bool,
+}
+
+impl AccountTosAgreement {
+	pub fn new() -> Self {
+		AccountTosAgreement {
+			terms_of_service_agreed: true,
+		}
+	}
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountKeyRollover {
@@ -120,8 +135,8 @@ mod tests {
 			external_account_binding: None,
 		};
 		assert_eq!(a.contact.len(), 2);
-		assert_eq!(a.terms_of_service_agreed, true);
-		assert_eq!(a.only_return_existing, false);
+		assert!(a.terms_of_service_agreed);
+		assert!(!a.only_return_existing);
 		let a_str = serde_json::to_string(&a);
 		assert!(a_str.is_ok());
 		let a_str = a_str.unwrap();
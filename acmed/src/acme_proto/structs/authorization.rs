@@ -2,7 +2,7 @@ use crate::acme_proto::structs::{ApiError, HttpApiError, Identifier};
 use acme_common::b64_encode;
 use acme_common::crypto::{HashFunction, KeyPair};
 use acme_common::error::Error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 
@@ -11,6 +11,7 @@ const ID_PE_ACME_ID: usize = 31;
 const DER_OCTET_STRING_ID: usize = 0x04;
 const DER_STRUCT_NAME: &str = "DER";
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct Authorization {
 	pub identifier: Identifier,
@@ -42,6 +43,20 @@ impl ApiError for Authorization {
 	}
 }
 
+/// Request the deactivation of an authorization, see section 7.5.2 of RFC 8555.
+#[derive(Serialize)]
+pub struct AuthorizationDeactivation {
+	pub status: String,
+}
+
+impl AuthorizationDeactivation {
+	pub fn new() -> Self {
+		AuthorizationDeactivation {
+			status: "deactivated".into(),
+		}
+	}
+}
+
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AuthorizationStatus {
@@ -152,9 +167,7 @@ pub struct TokenChallenge {
 
 impl TokenChallenge {
 	fn key_authorization(&self, key_pair: &KeyPair) -> Result<String, Error> {
-		let thumbprint = key_pair.jwk_public_key_thumbprint()?;
-		let thumbprint = HashFunction::Sha256.hash(thumbprint.to_string().as_bytes());
-		let thumbprint = b64_encode(&thumbprint);
+		let thumbprint = key_pair.jwk_thumbprint()?;
 		let auth = format!("{}.{thumbprint}", self.token);
 		Ok(auth)
 	}
@@ -171,7 +184,9 @@ pub enum ChallengeStatus {
 
 #[cfg(test)]
 mod tests {
-	use super::{Authorization, AuthorizationStatus, Challenge, ChallengeStatus};
+	use super::{
+		Authorization, AuthorizationDeactivation, AuthorizationStatus, Challenge, ChallengeStatus,
+	};
 	use crate::identifier::IdentifierType;
 	use std::str::FromStr;
 
@@ -277,8 +292,7 @@ mod tests {
 		let c = match challenge {
 			Challenge::Http01(c) => c,
 			_ => {
-				assert!(false);
-				return;
+				panic!("invalid challenge type");
 			}
 		};
 		assert_eq!(
@@ -308,8 +322,7 @@ mod tests {
 		let c = match challenge {
 			Challenge::Http01(c) => c,
 			_ => {
-				assert!(false);
-				return;
+				panic!("invalid challenge type");
 			}
 		};
 		assert_eq!(
@@ -335,9 +348,12 @@ mod tests {
 }";
 		let challenge = Challenge::from_str(data);
 		assert!(challenge.is_ok());
-		match challenge.unwrap() {
-			Challenge::Unknown => assert!(true),
-			_ => assert!(false),
-		}
+		assert!(matches!(challenge.unwrap(), Challenge::Unknown));
+	}
+
+	#[test]
+	fn test_authorization_deactivation() {
+		let ad = serde_json::to_string(&AuthorizationDeactivation::new()).unwrap();
+		assert_eq!(ad, r#"{"status":"deactivated"}"#);
 	}
 }
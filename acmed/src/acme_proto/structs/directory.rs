@@ -1,7 +1,9 @@
 use acme_common::error::Error;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+#[allow(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DirectoryMeta {
@@ -9,8 +11,23 @@ pub struct DirectoryMeta {
 	pub website: Option<String>,
 	pub caa_identities: Option<Vec<String>>,
 	pub external_account_required: Option<bool>,
+	#[serde(rename = "auto-renewal")]
+	pub auto_renewal: Option<AutoRenewalMeta>,
+	/// Certificate profiles offered by the CA, along with their description.
+	pub profiles: Option<BTreeMap<String, String>>,
 }
 
+/// Limits of the STAR orders (RFC 8739), in seconds.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AutoRenewalMeta {
+	pub min_lifetime: Option<u64>,
+	pub max_duration: Option<u64>,
+	pub allow_certificate_get: Option<bool>,
+}
+
+#[allow(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Directory {
@@ -90,6 +107,10 @@ mod tests {
 		\"caaIdentities\": [
 			\"example.org\"
 		],
+		\"profiles\": {
+			\"classic\": \"The same profile you're accustomed to\",
+			\"tlsserver\": \"https://example.org/docs/profiles#tlsserver\"
+		},
 		\"termsOfService\": \"https://example.org/documents/tos.pdf\",
 		\"website\": \"https://example.org/\"
 	},
@@ -98,7 +119,7 @@ mod tests {
 	\"newOrder\": \"https://example.org/acme/new-order\",
 	\"revokeCert\": \"https://example.org/acme/revoke-cert\"
 }";
-		let parsed_dir = Directory::from_str(&data);
+		let parsed_dir = Directory::from_str(data);
 		assert!(parsed_dir.is_ok());
 		let parsed_dir = parsed_dir.unwrap();
 		assert!(parsed_dir.meta.is_some());
@@ -113,6 +134,8 @@ mod tests {
 		assert_eq!(caa_identities.len(), 1);
 		assert_eq!(caa_identities.first(), Some(&"example.org".to_string()));
 		assert!(meta.external_account_required.is_none());
+		let profiles: Vec<&String> = meta.profiles.as_ref().unwrap().keys().collect();
+		assert_eq!(profiles, vec!["classic", "tlsserver"]);
 	}
 
 	#[test]
@@ -127,7 +150,7 @@ mod tests {
 	\"newOrder\": \"https://example.org/acme/new-order\",
 	\"revokeCert\": \"https://example.org/acme/revoke-cert\"
 }";
-		let parsed_dir = Directory::from_str(&data);
+		let parsed_dir = Directory::from_str(data);
 		assert!(parsed_dir.is_ok());
 		let parsed_dir = parsed_dir.unwrap();
 		assert_eq!(parsed_dir.new_nonce, "https://example.org/acme/new-nonce");
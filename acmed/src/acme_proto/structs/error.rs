@@ -1,4 +1,5 @@
-use acme_common::error::Error;
+use crate::acme_proto::structs::Identifier;
+use acme_common::error::{Error, ErrorKind};
 use serde::Deserialize;
 use std::fmt;
 use std::str::FromStr;
@@ -134,8 +135,13 @@ pub struct HttpApiError {
 	// title: Option<String>,
 	status: Option<usize>,
 	detail: Option<String>,
-	// instance: Option<String>,
-	// TODO: implement subproblems
+	instance: Option<String>,
+	#[serde(default)]
+	subproblems: Vec<HttpApiSubproblem>,
+	/// URL of the terms of service, sent in a `Link` header along with a `userActionRequired`
+	/// error when they have changed.
+	#[serde(skip)]
+	terms_of_service: Option<String>,
 }
 
 crate::acme_proto::structs::deserialize_from_str!(HttpApiError);
@@ -146,10 +152,14 @@ impl fmt::Display for HttpApiError {
 			.detail
 			.to_owned()
 			.unwrap_or_else(|| self.get_acme_type().to_string());
-		let msg = match self.status {
+		let mut msg = match self.status {
 			Some(s) => format!("status {s}: {msg}"),
 			None => msg,
 		};
+		if !self.subproblems.is_empty() {
+			let sub: Vec<String> = self.subproblems.iter().map(|s| s.to_string()).collect();
+			msg += &format!(" ({})", sub.join("; "));
+		}
 		write!(f, "{msg}")
 	}
 }
@@ -164,10 +174,126 @@ impl HttpApiError {
 	pub fn get_acme_type(&self) -> AcmeError {
 		self.get_type().into()
 	}
+
+	pub fn get_subproblems(&self) -> &[HttpApiSubproblem] {
+		&self.subproblems
+	}
+
+	pub fn get_instance(&self) -> Option<String> {
+		self.instance.to_owned()
+	}
+
+	pub fn get_terms_of_service(&self) -> Option<String> {
+		self.terms_of_service.to_owned()
+	}
+
+	pub fn with_terms_of_service(mut self, url: Option<String>) -> Self {
+		self.terms_of_service = url;
+		self
+	}
 }
 
 impl From<HttpApiError> for Error {
 	fn from(error: HttpApiError) -> Self {
-		error.to_string().into()
+		let e = Error::new(ErrorKind::Acme, &error.to_string());
+		match &error.error_type {
+			Some(t) => e.with_problem_type(t),
+			None => e,
+		}
+	}
+}
+
+/// Error affecting a single identifier, several of which may be returned in the `subproblems`
+/// of a problem document (RFC 8555, section 6.7.1).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct HttpApiSubproblem {
+	#[serde(rename = "type")]
+	error_type: Option<String>,
+	detail: Option<String>,
+	identifier: Option<Identifier>,
+}
+
+impl HttpApiSubproblem {
+	pub fn get_acme_type(&self) -> AcmeError {
+		self.error_type
+			.to_owned()
+			.unwrap_or_else(|| String::from("about:blank"))
+			.into()
+	}
+
+	pub fn get_identifier(&self) -> Option<&Identifier> {
+		self.identifier.as_ref()
+	}
+}
+
+impl fmt::Display for HttpApiSubproblem {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(id) = &self.identifier {
+			write!(f, "{id}: ")?;
+		}
+		// The short name of the type tells the reason at a glance, e.g. `caa` or `dns`.
+		if let Some(t) = &self.error_type {
+			let name = t.strip_prefix("urn:ietf:params:acme:error:").unwrap_or(t);
+			write!(f, "{name}: ")?;
+		}
+		let detail = self
+			.detail
+			.to_owned()
+			.unwrap_or_else(|| self.get_acme_type().to_string());
+		write!(f, "{detail}")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AcmeError, HttpApiError};
+	use acme_common::error::{Error, ErrorKind};
+	use std::str::FromStr;
+
+	#[test]
+	fn test_subproblems() {
+		let data = r#"{
+	"type": "urn:ietf:params:acme:error:rejectedIdentifier",
+	"detail": "Some of the identifiers requested were rejected",
+	"status": 400,
+	"subproblems": [
+		{
+			"type": "urn:ietf:params:acme:error:caa",
+			"detail": "CAA record for example.org prevents issuance",
+			"identifier": {
+				"type": "dns",
+				"value": "example.org"
+			}
+		},
+		{
+			"type": "urn:ietf:params:acme:error:dns",
+			"identifier": {
+				"type": "dns",
+				"value": "www.example.org"
+			}
+		}
+	]
+}"#;
+		let e = HttpApiError::from_str(data).unwrap();
+		let sub = e.get_subproblems();
+		assert_eq!(sub.len(), 2);
+		assert_eq!(sub[0].get_acme_type(), AcmeError::Caa);
+		assert_eq!(sub[1].get_identifier().unwrap().value, "www.example.org");
+		assert_eq!(
+			e.to_string(),
+			"status 400: Some of the identifiers requested were rejected (dns:example.org: caa: CAA record for example.org prevents issuance; dns:www.example.org: dns: there was a problem with a DNS query during identifier validation)"
+		);
+		assert!(!Error::from(e).is_transient());
+	}
+
+	#[test]
+	fn test_no_subproblems() {
+		let data = r#"{"type": "urn:ietf:params:acme:error:badNonce", "detail": "JWS has an invalid anti-replay nonce"}"#;
+		let e = HttpApiError::from_str(data).unwrap();
+		assert!(e.get_subproblems().is_empty());
+		assert_eq!(e.to_string(), "JWS has an invalid anti-replay nonce");
+		let e = Error::from(e);
+		assert_eq!(e.kind, ErrorKind::Acme);
+		assert!(e.is_transient());
 	}
 }
@@ -2,6 +2,7 @@ use crate::acme_proto::structs::{ApiError, HttpApiError};
 use crate::identifier::{self, IdentifierType};
 use acme_common::error::Error;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fmt;
 use std::str::FromStr;
 
@@ -13,18 +14,35 @@ pub struct NewOrder {
 	pub not_before: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub not_after: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub profile: Option<String>,
+	#[serde(rename = "auto-renewal", skip_serializing_if = "Option::is_none")]
+	pub auto_renewal: Option<AutoRenewal>,
 }
 
 impl NewOrder {
-	pub fn new(identifiers: &[identifier::Identifier]) -> Self {
+	pub fn new(identifiers: &[identifier::Identifier], profile: Option<String>) -> Self {
 		NewOrder {
 			identifiers: identifiers.iter().map(Identifier::from_generic).collect(),
 			not_before: None,
 			not_after: None,
+			profile,
+			auto_renewal: None,
 		}
 	}
 }
 
+/// Parameters of a STAR order (RFC 8739), whose durations are in seconds.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AutoRenewal {
+	pub end_date: String,
+	pub lifetime: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub lifetime_adjust: Option<u64>,
+}
+
+#[allow(dead_code)]
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Order {
@@ -37,6 +55,24 @@ pub struct Order {
 	pub authorizations: Vec<String>,
 	pub finalize: String,
 	pub certificate: Option<String>,
+	#[serde(rename = "star-certificate")]
+	pub star_certificate: Option<String>,
+}
+
+impl Order {
+	/// Return whether or not the order is for the given identifiers, regardless of their order.
+	pub fn has_identifiers(&self, identifiers: &[identifier::Identifier]) -> bool {
+		let own: BTreeSet<(String, &str)> = self
+			.identifiers
+			.iter()
+			.map(|i| (i.id_type.to_string(), i.value.as_str()))
+			.collect();
+		let other: BTreeSet<(String, &str)> = identifiers
+			.iter()
+			.map(|i| (i.id_type.to_string(), i.value.as_str()))
+			.collect();
+		own == other
+	}
 }
 
 impl ApiError for Order {
@@ -55,6 +91,8 @@ pub enum OrderStatus {
 	Processing,
 	Valid,
 	Invalid,
+	/// STAR order canceled by the client (RFC 8739).
+	Canceled,
 }
 
 impl fmt::Display for OrderStatus {
@@ -65,12 +103,13 @@ impl fmt::Display for OrderStatus {
 			OrderStatus::Processing => "processing",
 			OrderStatus::Valid => "valid",
 			OrderStatus::Invalid => "invalid",
+			OrderStatus::Canceled => "canceled",
 		};
 		write!(f, "{s}")
 	}
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Identifier {
 	#[serde(rename = "type")]
 	pub id_type: IdentifierType,
@@ -96,9 +135,54 @@ impl fmt::Display for Identifier {
 
 #[cfg(test)]
 mod tests {
-	use super::{Identifier, IdentifierType};
+	use super::{AutoRenewal, Identifier, IdentifierType, NewOrder, Order};
+	use crate::identifier;
+	use std::collections::HashMap;
 	use std::str::FromStr;
 
+	#[test]
+	fn order_has_identifiers() {
+		let order = Order::from_str(
+			r#"{
+				"status": "processing",
+				"identifiers": [
+					{"type": "dns", "value": "www.example.org"},
+					{"type": "dns", "value": "example.org"}
+				],
+				"authorizations": [],
+				"finalize": "https://example.org/finalize/1"
+			}"#,
+		)
+		.unwrap();
+		let env = HashMap::new();
+		let id =
+			|v: &str| identifier::Identifier::new(IdentifierType::Dns, v, "http-01", &env).unwrap();
+		assert!(order.has_identifiers(&[id("example.org"), id("www.example.org")]));
+		assert!(!order.has_identifiers(&[id("example.org")]));
+		assert!(!order.has_identifiers(&[
+			id("example.org"),
+			id("www.example.org"),
+			id("mail.example.org")
+		]));
+		let ip =
+			identifier::Identifier::new(IdentifierType::Ip, "192.0.2.1", "http-01", &env).unwrap();
+		assert!(!order.has_identifiers(&[id("example.org"), ip]));
+	}
+
+	#[test]
+	fn new_order_auto_renewal_serialize() {
+		let mut order = NewOrder::new(&[], None);
+		order.auto_renewal = Some(AutoRenewal {
+			end_date: "2026-01-01T00:00:00Z".to_string(),
+			lifetime: 86400,
+			lifetime_adjust: None,
+		});
+		assert_eq!(
+			serde_json::to_string(&order).unwrap(),
+			"{\"identifiers\":[],\"auto-renewal\":{\"end-date\":\"2026-01-01T00:00:00Z\",\"lifetime\":86400}}"
+		);
+	}
+
 	#[test]
 	fn id_serialize() {
 		let reference = "{\"type\":\"dns\",\"value\":\"test.example.org\"}";
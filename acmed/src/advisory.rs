@@ -0,0 +1,218 @@
+use crate::hooks::{self, Hook, HookEnvData, HookRecorder, HookType, VersionAdvisoryHookData};
+use crate::http;
+use crate::logs::HasLogger;
+use acme_common::error::{Error, ErrorKind};
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Latest advisory announcing a newer version than the running one.
+static LATEST: Mutex<Option<Advisory>> = Mutex::new(None);
+
+/// Document published at the advisory URL, which describes the latest release.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Advisory {
+	pub latest_version: String,
+	#[serde(default)]
+	pub security_fix: bool,
+	#[serde(default)]
+	pub message: Option<String>,
+	#[serde(default)]
+	pub url: Option<String>,
+}
+
+impl Advisory {
+	pub fn summary(&self) -> String {
+		let mut msg = if self.security_fix {
+			format!(
+				"acmed {} is available and includes security fixes",
+				self.latest_version
+			)
+		} else {
+			format!("acmed {} is available", self.latest_version)
+		};
+		if let Some(m) = &self.message {
+			msg += &format!(": {m}");
+		}
+		if let Some(u) = &self.url {
+			msg += &format!(" ({u})");
+		}
+		msg
+	}
+}
+
+/// Periodic comparison of the running version against the advisory. Nothing is ever installed:
+/// a newer version is only logged, displayed in the status and notified to the hooks.
+#[derive(Clone, Debug)]
+pub struct VersionAdvisory {
+	pub url: String,
+	pub interval: Duration,
+	pub hooks: Vec<Arc<Hook>>,
+	pub env: HashMap<String, String>,
+}
+
+impl HasLogger for VersionAdvisory {
+	fn warn(&self, msg: &str) {
+		log::warn!("version advisory: {msg}");
+	}
+
+	fn info(&self, msg: &str) {
+		log::info!("version advisory: {msg}");
+	}
+
+	fn debug(&self, msg: &str) {
+		log::debug!("version advisory: {msg}");
+	}
+
+	fn trace(&self, msg: &str) {
+		log::trace!("version advisory: {msg}");
+	}
+}
+
+impl VersionAdvisory {
+	pub async fn run(self) {
+		loop {
+			if let Err(e) = self.check().await {
+				self.warn(&e.message);
+			}
+			tokio::time::sleep(self.interval).await;
+		}
+	}
+
+	/// Check the templates of the hooks against sample data.
+	pub fn check_hooks(&self) -> Result<(), Error> {
+		let mut hook_data = VersionAdvisoryHookData {
+			current_version: crate::APP_VERSION.to_string(),
+			latest_version: crate::APP_VERSION.to_string(),
+			security_fix: false,
+			message: String::new(),
+			url: String::new(),
+			env: HashMap::new(),
+		};
+		hook_data.set_env(&self.env);
+		hooks::check(&self.hooks, &hook_data, HookType::VersionAdvisory)
+	}
+
+	async fn check(&self) -> Result<(), Error> {
+		let advisory = match fetch(&self.url).await? {
+			Some(a) => a,
+			None => {
+				self.debug(&format!("acmed {} is up to date", crate::APP_VERSION));
+				set_latest(None);
+				return Ok(());
+			}
+		};
+		let is_new = get_latest().as_ref() != Some(&advisory);
+		set_latest(Some(advisory.clone()));
+		if is_new {
+			self.warn(&advisory.summary());
+			let mut hook_data = VersionAdvisoryHookData {
+				current_version: crate::APP_VERSION.to_string(),
+				latest_version: advisory.latest_version.to_owned(),
+				security_fix: advisory.security_fix,
+				message: advisory.message.unwrap_or_default(),
+				url: advisory.url.unwrap_or_default(),
+				env: HashMap::new(),
+			};
+			hook_data.set_env(&self.env);
+			hooks::call(
+				self,
+				&HookRecorder::default(),
+				&self.hooks,
+				&hook_data,
+				HookType::VersionAdvisory,
+			)
+			.await
+			.map_err(|e| e.prefix("version-advisory hook error"))?;
+		}
+		Ok(())
+	}
+}
+
+/// Return the advisory if it announces a newer version than the running one.
+pub async fn fetch(url: &str) -> Result<Option<Advisory>, Error> {
+	let client = http::get_api_client()?;
+	let response = http::set_timeout(client.get(url))
+		.header(header::ACCEPT, http::CONTENT_TYPE_JSON)
+		.send()
+		.await?;
+	let status = response.status();
+	if !status.is_success() {
+		let msg = format!("{url}: HTTP error: {}", status.as_u16());
+		return Err(Error::new(ErrorKind::Http, &msg));
+	}
+	let body = response.text().await?;
+	let advisory: Advisory = serde_json::from_str(&body).map_err(|e| Error::from(e).prefix(url))?;
+	if is_newer(&advisory.latest_version, crate::APP_VERSION) {
+		Ok(Some(advisory))
+	} else {
+		Ok(None)
+	}
+}
+
+pub fn get_latest() -> Option<Advisory> {
+	LATEST.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+fn set_latest(advisory: Option<Advisory>) {
+	*LATEST.lock().unwrap_or_else(|e| e.into_inner()) = advisory;
+}
+
+/// Compare two versions, the pre-releases preceding the release they are based on.
+fn is_newer(version: &str, current: &str) -> bool {
+	fn parse(version: &str) -> (Vec<u64>, bool) {
+		let version = version.trim().trim_start_matches('v');
+		let version = version.split('+').next().unwrap_or_default();
+		let (release, is_pre_release) = match version.split_once('-') {
+			Some((r, _)) => (r, true),
+			None => (version, false),
+		};
+		let mut parts: Vec<u64> = release.split('.').map(|p| p.parse().unwrap_or(0)).collect();
+		while parts.last() == Some(&0) {
+			parts.pop();
+		}
+		(parts, is_pre_release)
+	}
+	let (version, version_pre) = parse(version);
+	let (current, current_pre) = parse(current);
+	version > current || (version == current && current_pre && !version_pre)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{is_newer, Advisory};
+
+	#[test]
+	fn test_is_newer() {
+		assert!(is_newer("0.22.0", "0.21.0"));
+		assert!(is_newer("v0.21.1", "0.21.0"));
+		assert!(is_newer("1.0", "0.21.0"));
+		assert!(is_newer("0.21.0", "0.21.0-rc.1"));
+		assert!(!is_newer("0.21.0", "0.21.0"));
+		assert!(!is_newer("0.21", "0.21.0"));
+		assert!(!is_newer("0.21.0-rc.1", "0.21.0"));
+		assert!(!is_newer("0.20.5", "0.21.0"));
+	}
+
+	#[test]
+	fn test_summary() {
+		let advisory: Advisory =
+			serde_json::from_str(r#"{"latest_version": "0.22.0", "security_fix": true}"#).unwrap();
+		assert_eq!(
+			advisory.summary(),
+			"acmed 0.22.0 is available and includes security fixes"
+		);
+		let advisory = Advisory {
+			message: Some("fixes the renewal of wildcards".to_string()),
+			url: Some("https://example.org/acmed".to_string()),
+			security_fix: false,
+			..advisory
+		};
+		assert_eq!(
+			advisory.summary(),
+			"acmed 0.22.0 is available: fixes the renewal of wildcards (https://example.org/acmed)"
+		);
+	}
+}
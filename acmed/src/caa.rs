@@ -0,0 +1,192 @@
+use crate::certificate::Certificate;
+use crate::dns::{self, CaaRecord, Record, RecordData, RecordType, MAX_CNAME_DEPTH};
+use crate::identifier::IdentifierType;
+use crate::logs::HasLogger;
+use acme_common::error::Error;
+use std::net::SocketAddr;
+
+/// Check that the CAA records of each DNS identifier of the certificate authorize one of the CA's
+/// identities to issue it, as specified in RFC 8659. IP identifiers are not covered by CAA.
+pub async fn check_identifiers(cert: &Certificate, caa_identities: &[String]) -> Result<(), Error> {
+	let resolvers = dns::get_system_nameservers()?;
+	for id in cert.identifiers.iter() {
+		if id.id_type != IdentifierType::Dns {
+			continue;
+		}
+		let (domain, wildcard) = match id.value.strip_prefix("*.") {
+			Some(d) => (d, true),
+			None => (id.value.as_str(), false),
+		};
+		let records = get_relevant_records(&resolvers, domain).await?;
+		cert.trace(&format!("CAA records for {}: {records:?}", id.value));
+		if !is_authorized(&records, caa_identities, wildcard) {
+			let msg = format!(
+				"{}: the CAA records do not authorize the CA to issue the certificate",
+				id.value
+			);
+			return Err(msg.into());
+		}
+	}
+	Ok(())
+}
+
+/// Return the CAA records of the closest domain, starting from the identifier itself and climbing
+/// up to the root, having any.
+async fn get_relevant_records(
+	resolvers: &[SocketAddr],
+	domain: &str,
+) -> Result<Vec<CaaRecord>, Error> {
+	let mut name = domain.trim_end_matches('.');
+	loop {
+		let records = get_records(resolvers, name)
+			.await
+			.map_err(|e| e.prefix(&format!("{name}: CAA lookup failed")))?;
+		if !records.is_empty() {
+			return Ok(records);
+		}
+		match name.split_once('.') {
+			Some((_, parent)) if !parent.is_empty() => name = parent,
+			_ => return Ok(vec![]),
+		}
+	}
+}
+
+/// Return the CAA records of the name, following its aliases as required by RFC 8659. Since the
+/// resolver may not have followed them entirely, the target of the last alias is queried again.
+async fn get_records(resolvers: &[SocketAddr], name: &str) -> Result<Vec<CaaRecord>, Error> {
+	let mut name = name.to_lowercase();
+	for _ in 0..MAX_CNAME_DEPTH {
+		let answer = dns::query_resolvers(resolvers, &name, RecordType::Caa).await?;
+		let records: Vec<CaaRecord> = answer
+			.iter()
+			.filter_map(|r| match &r.data {
+				RecordData::Caa(caa) => Some(caa.to_owned()),
+				_ => None,
+			})
+			.collect();
+		if !records.is_empty() {
+			return Ok(records);
+		}
+		let target = follow_aliases(&answer, &name);
+		if target == name {
+			return Ok(vec![]);
+		}
+		name = target;
+	}
+	Err(format!("{name}: too many aliases").into())
+}
+
+/// Return the target of the chain of aliases of the name included in the answer, which is the
+/// name itself if it has none.
+fn follow_aliases(answer: &[Record], name: &str) -> String {
+	let mut name = name.to_string();
+	for _ in 0..MAX_CNAME_DEPTH {
+		let target = answer.iter().find_map(|r| match &r.data {
+			RecordData::Cname(target) if r.name == name => Some(target.to_owned()),
+			_ => None,
+		});
+		match target {
+			Some(target) => name = target,
+			None => break,
+		}
+	}
+	name
+}
+
+fn is_authorized(records: &[CaaRecord], caa_identities: &[String], wildcard: bool) -> bool {
+	let known_tags = ["issue", "issuewild", "iodef"];
+	if records
+		.iter()
+		.any(|r| r.critical && !known_tags.contains(&r.tag.as_str()))
+	{
+		return false;
+	}
+	let has_tag = |tag: &str| records.iter().any(|r| r.tag == tag);
+	let tag = if wildcard && has_tag("issuewild") {
+		"issuewild"
+	} else {
+		"issue"
+	};
+	let mut issuers = records.iter().filter(|r| r.tag == tag).peekable();
+	if issuers.peek().is_none() {
+		return true;
+	}
+	issuers.any(|r| {
+		let issuer = r.value.split(';').next().unwrap_or_default().trim();
+		!issuer.is_empty()
+			&& caa_identities
+				.iter()
+				.any(|i| i.eq_ignore_ascii_case(issuer))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{follow_aliases, is_authorized, CaaRecord};
+	use crate::dns::{Record, RecordData};
+
+	fn record(critical: bool, tag: &str, value: &str) -> CaaRecord {
+		CaaRecord {
+			critical,
+			tag: tag.to_string(),
+			value: value.to_string(),
+		}
+	}
+
+	#[test]
+	fn test_is_authorized() {
+		let ids = vec!["letsencrypt.org".to_string()];
+		assert!(is_authorized(&[], &ids, false));
+		assert!(is_authorized(
+			&[record(false, "iodef", "mailto:a@example.org")],
+			&ids,
+			false
+		));
+		assert!(is_authorized(
+			&[record(
+				false,
+				"issue",
+				"LetsEncrypt.org; validationmethods=dns-01"
+			)],
+			&ids,
+			false
+		));
+		assert!(!is_authorized(
+			&[record(false, "issue", "example.net")],
+			&ids,
+			false
+		));
+		assert!(!is_authorized(&[record(false, "issue", ";")], &ids, false));
+		let wild = [
+			record(false, "issue", "letsencrypt.org"),
+			record(false, "issuewild", ";"),
+		];
+		assert!(is_authorized(&wild, &ids, false));
+		assert!(!is_authorized(&wild, &ids, true));
+		assert!(is_authorized(
+			&[record(false, "issue", "letsencrypt.org")],
+			&ids,
+			true
+		));
+		assert!(!is_authorized(&[record(true, "unknown", "")], &ids, false));
+		assert!(is_authorized(&[record(false, "unknown", "")], &ids, false));
+	}
+
+	#[test]
+	fn test_follow_aliases() {
+		let cname = |name: &str, target: &str| Record {
+			name: name.to_string(),
+			data: RecordData::Cname(target.to_string()),
+		};
+		assert_eq!(follow_aliases(&[], "example.org"), "example.org");
+		let answer = vec![
+			cname("b.example.org", "c.example.net"),
+			cname("www.example.org", "b.example.org"),
+			cname("other.example.org", "d.example.net"),
+		];
+		assert_eq!(follow_aliases(&answer, "www.example.org"), "c.example.net");
+		assert_eq!(follow_aliases(&answer, "example.org"), "example.org");
+		let answer = vec![cname("a.example.org", "a.example.org")];
+		assert_eq!(follow_aliases(&answer, "a.example.org"), "a.example.org");
+	}
+}
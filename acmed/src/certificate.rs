@@ -1,33 +1,152 @@
+use crate::account::AccountIdentity;
 use crate::acme_proto::Challenge;
-use crate::hooks::{self, ChallengeHookData, Hook, HookEnvData, HookType, PostOperationHookData};
+use crate::config::CaaCheck;
+use crate::dns_propagation::get_delegated_name;
+use crate::dns_provider::get_record_name;
+use crate::email::EmailNotifier;
+use crate::endpoint::{format_timestamp, get_state_file};
+use crate::events;
+use crate::hooks::{
+	self, CertificateDetails, ChallengeHookData, Hook, HookEnvData, HookRecorder, HookType,
+	PostOperationHookData,
+};
 use crate::identifier::{Identifier, IdentifierType};
+use crate::kubernetes::KubernetesSecret;
 use crate::logs::HasLogger;
-use crate::storage::{certificate_files_exists, get_certificate, FileManager};
+use crate::platform::{Current, Platform};
+use crate::renewal_window::{wait_for_window, RenewalWindow};
+use crate::solver::{get_hook_types, Solver, SolverType};
+use crate::storage::{self, certificate_files_exists, get_certificate, FileManager};
+use crate::watched::fetch_certificate;
 use acme_common::crypto::{HashFunction, KeyType, SubjectAttribute, X509Certificate};
-use acme_common::error::Error;
+use acme_common::error::{Error, ErrorContext, ErrorKind};
+use acme_common::logs::{with_log_context, LogContext};
+use acme_common::now;
 use log::{debug, info, trace, warn};
 use rand::{thread_rng, Rng};
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
 
-#[derive(Clone, Debug)]
+/// TLS servers which should serve the certificate once it has been deployed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeployCheck {
+	pub addresses: Vec<String>,
+	pub server_name: Option<String>,
+	pub delay: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Certificate {
 	pub account_name: String,
 	pub identifiers: Vec<Identifier>,
 	pub subject_attributes: HashMap<SubjectAttribute, String>,
 	pub key_type: KeyType,
 	pub csr_digest: HashFunction,
+	pub deploy_check: Option<DeployCheck>,
 	pub kp_reuse: bool,
+	pub kubernetes_secret: Option<KubernetesSecret>,
+	pub ocsp_staple: bool,
+	pub star: Option<Star>,
+	pub caa_check: CaaCheck,
 	pub endpoint_name: String,
-	pub hooks: Vec<Hook>,
+	pub fallback_endpoints: Vec<FallbackEndpoint>,
+	pub failover_attempts: usize,
+	pub hooks: Vec<Arc<Hook>>,
+	pub email: Option<Arc<EmailNotifier>>,
 	pub crt_name: String,
 	pub env: HashMap<String, String>,
+	pub labels: BTreeMap<String, String>,
 	pub random_early_renew: Duration,
+	pub renew_adaptive_factor: f64,
 	pub renew_delay: Duration,
+	pub renew_at: Option<f64>,
+	pub renew_jitter: Duration,
+	pub renew_windows: Vec<RenewalWindow>,
+	pub renew_window_critical: Duration,
+	pub challenge_ready_interval: Duration,
+	pub challenge_ready_timeout: Duration,
+	pub dns_cleanup_delay: Duration,
+	pub dns_propagation_check: bool,
+	pub dns_propagation_resolvers: Vec<SocketAddr>,
+	pub dns_propagation_timeout: Duration,
+	pub http_self_check: bool,
+	pub http_self_check_address: Option<IpAddr>,
+	pub stateless_http_01: bool,
+	pub timeout: Option<Duration>,
+	pub preferred_chain: Option<String>,
+	pub profile: Option<String>,
+	pub state_dir: String,
+	pub hook_recorder: HookRecorder,
+	pub expiring_margin: Duration,
 	pub file_manager: FileManager,
 }
 
+/// Endpoint to request the certificate from when the main one fails, along with the account and
+/// the options to use on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FallbackEndpoint {
+	pub endpoint_name: String,
+	pub account_name: String,
+	pub preferred_chain: Option<String>,
+	pub profile: Option<String>,
+}
+
+/// Result of the latest renewal, stored in a state file so it can be displayed by the status
+/// command.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RenewalStatus {
+	pub timestamp: u64,
+	pub success: bool,
+	pub status: String,
+	#[serde(default)]
+	pub error_source: Option<String>,
+}
+
+/// Endpoint and account the current certificate has been issued by, stored in a state file since
+/// it may have been issued by one of the fallback endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Issuer {
+	pub endpoint: String,
+	pub account: String,
+}
+
+/// Short-term and automatically renewed certificate (STAR, RFC 8739): the CA periodically issues
+/// a new certificate, which is fetched by ACMEd, until the end of the order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Star {
+	pub duration: Duration,
+	pub lifetime: Duration,
+	pub lifetime_adjust: Option<Duration>,
+}
+
+/// STAR order whose certificates are issued by the CA, stored in a state file so the latest
+/// certificate can be fetched instead of creating a new order.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StarOrder {
+	pub endpoint: String,
+	pub order_url: String,
+	pub certificate_url: String,
+	pub end_date: u64,
+	/// Timestamp of the latest download of the certificate.
+	pub fetched: u64,
+}
+
+/// Order being processed, stored in a state file so it can be resumed if the process is
+/// interrupted before the order reaches its final state, or have its pending authorizations
+/// deactivated if it cannot.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PendingOrder {
+	pub endpoint: String,
+	pub order_url: String,
+	pub authorizations: Vec<String>,
+}
+
 impl fmt::Display for Certificate {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}", self.get_id())
@@ -36,27 +155,132 @@ impl fmt::Display for Certificate {
 
 impl HasLogger for Certificate {
 	fn warn(&self, msg: &str) {
-		warn!("certificate \"{self}\": {msg}");
+		with_log_context(self.get_log_context(), || {
+			warn!("certificate \"{self}\": {msg}");
+		});
 	}
 
 	fn info(&self, msg: &str) {
-		info!("certificate \"{self}\": {msg}");
+		with_log_context(self.get_log_context(), || {
+			info!("certificate \"{self}\": {msg}");
+		});
 	}
 
 	fn debug(&self, msg: &str) {
-		debug!("certificate \"{self}\": {msg}");
+		with_log_context(self.get_log_context(), || {
+			debug!("certificate \"{self}\": {msg}");
+		});
 	}
 
 	fn trace(&self, msg: &str) {
-		trace!("certificate \"{self}\": {msg}");
+		with_log_context(self.get_log_context(), || {
+			trace!("certificate \"{self}\": {msg}");
+		});
 	}
 }
 
+/// Return the offset, within the given jitter, applied to the renewal of a certificate and
+/// whether it postpones or advances the renewal. The offset only depends on the certificate's
+/// id, hence it remains the same across restarts and renewals.
+fn get_jitter(id: &str, jitter: Duration) -> (Duration, bool) {
+	if jitter.is_zero() {
+		return (Duration::ZERO, false);
+	}
+	let hash = HashFunction::Sha256.hash(id.as_bytes());
+	let mut bytes = [0; 8];
+	bytes.copy_from_slice(&hash[..8]);
+	let value = u64::from_be_bytes(bytes);
+	let range = 2 * jitter.as_secs() + 1;
+	let offset = (value % range) as i64 - jitter.as_secs() as i64;
+	(Duration::from_secs(offset.unsigned_abs()), offset > 0)
+}
+
+/// Apply the jitter's offset to the time left before the renewal. A renewal which is already due
+/// is never postponed, and a late offset never exceeds half of the renewal delay nor the time left
+/// before the certificate expires, so the certificate is always renewed before it expires.
+fn apply_jitter(
+	renew_in: Duration,
+	expires_in: Duration,
+	renew_delay: Duration,
+	(offset, is_late): (Duration, bool),
+) -> Duration {
+	if !is_late {
+		return renew_in.saturating_sub(offset);
+	}
+	if renew_in.is_zero() {
+		return renew_in;
+	}
+	let max_offset = (renew_delay / 2).min(expires_in.saturating_sub(renew_in));
+	renew_in + offset.min(max_offset)
+}
+
+/// Return the extension of the renewal delay, which is the median of the latest issuance
+/// durations multiplied by the factor. The extension never exceeds the renewal delay itself, nor
+/// half of the time between the issuance and the renewal, so a certificate whose renewal delay
+/// is a large part of its lifetime is not renewed as soon as it is issued.
+fn get_adaptive_delay(
+	durations: &[u64],
+	factor: f64,
+	renew_delay: Duration,
+	lifetime: Duration,
+) -> Duration {
+	if durations.is_empty() || factor == 0.0 {
+		return Duration::ZERO;
+	}
+	let mut durations = durations.to_vec();
+	durations.sort_unstable();
+	let median = Duration::from_secs(durations[durations.len() / 2]);
+	let max_extension = renew_delay.min(lifetime.saturating_sub(renew_delay) / 2);
+	median.mul_f64(factor).min(max_extension)
+}
+
 impl Certificate {
 	pub fn get_id(&self) -> String {
 		format!("{}_{}", self.crt_name, self.key_type)
 	}
 
+	/// Return the certificate as it is requested from the fallback endpoint.
+	pub fn with_fallback(&self, fallback: &FallbackEndpoint) -> Self {
+		let mut crt = self.clone();
+		crt.endpoint_name = fallback.endpoint_name.to_owned();
+		crt.account_name = fallback.account_name.to_owned();
+		crt.file_manager.endpoint_name = fallback.endpoint_name.to_owned();
+		crt.preferred_chain = fallback.preferred_chain.to_owned();
+		crt.profile = fallback.profile.to_owned();
+		crt
+	}
+
+	fn get_log_context(&self) -> LogContext {
+		LogContext {
+			certificate: Some(self.get_id()),
+			endpoint: Some(self.endpoint_name.clone()),
+			labels: self.labels.clone(),
+			..Default::default()
+		}
+	}
+
+	/// Attach the certificate, its endpoint and the given operation to the error's context.
+	pub fn add_error_context(&self, operation: &str, error: Error) -> Error {
+		error
+			.with_context(ErrorContext::Operation(operation.to_string()))
+			.with_context(ErrorContext::Certificate(self.get_id()))
+			.with_context(ErrorContext::Endpoint(self.endpoint_name.clone()))
+	}
+
+	/// Log an error which occurred during the given operation, along with its context and the
+	/// kind of its cause.
+	pub fn warn_error(&self, operation: &str, error: &Error) {
+		let ctx = LogContext {
+			labels: self.labels.clone(),
+			..self
+				.add_error_context(operation, error.clone())
+				.get_log_context()
+		};
+		with_log_context(ctx, || {
+			warn!("certificate \"{self}\": {}", error.message);
+		});
+	}
+
 	pub fn get_identifier_from_str(&self, identifier: &str) -> Result<Identifier, Error> {
 		let identifier = identifier.to_string();
 		for d in self.identifiers.iter() {
@@ -72,16 +296,76 @@ impl Certificate {
 		Err(format!("{identifier}: identifier not found").into())
 	}
 
-	fn renew_in(&self, cert: &X509Certificate) -> Result<Duration, Error> {
+	async fn renew_in(&self, cert: &X509Certificate) -> Result<Duration, Error> {
 		let expires_in = cert.expires_in()?;
+		let lifetime = cert.lifetime()?;
+		let renew_delay = match (&self.star, self.renew_at) {
+			// The CA issues the next STAR certificate before the current one expires, hence it
+			// is fetched halfway through the lifetime.
+			(Some(_), _) => lifetime / 2,
+			(None, Some(p)) => lifetime.mul_f64(p / 100.0),
+			(None, None) => self.renew_delay,
+		};
+		let renew_delay = if self.renew_adaptive_factor > 0.0 {
+			let durations = self.get_issuance_durations().await.unwrap_or_else(|e| {
+				self.warn(&format!("unable to read the issuance durations: {e}"));
+				vec![]
+			});
+			let extension = get_adaptive_delay(
+				&durations,
+				self.renew_adaptive_factor,
+				renew_delay,
+				lifetime,
+			);
+			if !extension.is_zero() {
+				self.debug(&format!(
+					"renewal delay extended by {} minutes based on the past issuance durations",
+					extension.as_secs() / 60
+				));
+			}
+			renew_delay + extension
+		} else {
+			renew_delay
+		};
 		self.debug(&format!(
 			"certificate expires in {} days ({} days delay)",
 			expires_in.as_secs() / 86400,
-			self.renew_delay.as_secs() / 86400,
+			renew_delay.as_secs() / 86400,
+		));
+		let renew_in = expires_in
+			.saturating_sub(renew_delay)
+			.saturating_sub(thread_rng().gen_range(Duration::ZERO..=self.random_early_renew));
+		let jitter = get_jitter(&self.get_id(), self.renew_jitter);
+		let renew_in = apply_jitter(renew_in, expires_in, renew_delay, jitter);
+		Ok(self.apply_renew_windows(renew_in, expires_in))
+	}
+
+	/// Postpone the renewal until one of the renewal windows is open, unless the certificate
+	/// would then be about to expire, in which case it is renewed regardless of the windows.
+	fn apply_renew_windows(&self, renew_in: Duration, expires_in: Duration) -> Duration {
+		if self.renew_windows.is_empty() {
+			return renew_in;
+		}
+		let latest = expires_in.saturating_sub(self.renew_window_critical);
+		if renew_in >= latest {
+			return renew_in;
+		}
+		let renew_ts = now() + renew_in.as_secs();
+		let offset = Current::utc_offset(renew_ts).unwrap_or_else(|| {
+			self.warn("unable to get the local time, the renewal windows are evaluated in UTC");
+			0
+		});
+		let local_time = renew_ts as i64 + offset;
+		let wait = Duration::from_secs(wait_for_window(&self.renew_windows, local_time));
+		if wait.is_zero() {
+			return renew_in;
+		}
+		let postponed = (renew_in + wait).min(latest);
+		self.debug(&format!(
+			"renewal postponed by {} minutes to fit in the renewal windows",
+			(postponed - renew_in).as_secs() / 60
 		));
-		Ok(expires_in
-			.saturating_sub(self.renew_delay)
-			.saturating_sub(thread_rng().gen_range(Duration::ZERO..self.random_early_renew)))
+		postponed
 	}
 
 	fn has_missing_identifiers(&self, cert: &X509Certificate) -> bool {
@@ -105,7 +389,163 @@ impl Certificate {
 		has_miss
 	}
 
-	/// Return a comma-separated list of the domains this certificate is valid for.
+	/// Return the status of the latest renewal, if any.
+	pub fn get_renewal_status(&self) -> Result<Option<RenewalStatus>, Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "renewal");
+		if !state_file.is_file() {
+			return Ok(None);
+		}
+		let path = state_file.display().to_string();
+		let data = fs::read(&state_file).map_err(|e| Error::from(e).prefix(&path))?;
+		let status = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(Some(status))
+	}
+
+	/// Save the status of the latest renewal, which failed if the kind of the error is given.
+	pub fn save_renewal_status(
+		&self,
+		status: &str,
+		error_source: Option<ErrorKind>,
+	) -> Result<(), Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "renewal");
+		let path = state_file.display().to_string();
+		let data = serde_json::to_vec(&RenewalStatus {
+			timestamp: now(),
+			success: error_source.is_none(),
+			status: status.to_string(),
+			error_source: error_source.map(|e| e.to_string()),
+		})?;
+		fs::write(&state_file, data).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	pub fn get_issuer(&self) -> Result<Option<Issuer>, Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "issuer");
+		if !state_file.is_file() {
+			return Ok(None);
+		}
+		let path = state_file.display().to_string();
+		let data = fs::read(&state_file).map_err(|e| Error::from(e).prefix(&path))?;
+		let issuer = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(Some(issuer))
+	}
+
+	/// Save the endpoint and account the certificate has just been issued by.
+	pub fn save_issuer(&self) -> Result<(), Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "issuer");
+		let path = state_file.display().to_string();
+		let data = serde_json::to_vec(&Issuer {
+			endpoint: self.endpoint_name.to_owned(),
+			account: self.account_name.to_owned(),
+		})?;
+		fs::write(&state_file, data).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	/// Return the certificate as it has been issued, which is from one of its fallback endpoints
+	/// if the latest renewal fell back to it.
+	pub fn as_issued(self: &Arc<Self>) -> Arc<Self> {
+		let issuer = match self.get_issuer() {
+			Ok(Some(i)) => i,
+			Ok(None) => return self.clone(),
+			Err(e) => {
+				self.warn(&e.prefix("unable to read the issuer").message);
+				return self.clone();
+			}
+		};
+		match self
+			.fallback_endpoints
+			.iter()
+			.find(|f| f.endpoint_name == issuer.endpoint && f.account_name == issuer.account)
+		{
+			Some(fallback) => Arc::new(self.with_fallback(fallback)),
+			None => self.clone(),
+		}
+	}
+
+	/// Return the durations, in seconds, of the latest successful issuances.
+	pub async fn get_issuance_durations(&self) -> Result<Vec<u64>, Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "issuance");
+		let path = state_file.display().to_string();
+		let data = match tokio::fs::read(&state_file).await {
+			Ok(data) => data,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(Error::from(e).prefix(&path)),
+		};
+		let durations = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(durations)
+	}
+
+	/// Save the duration of a successful issuance, from the start of the renewal to the end of
+	/// the post-operation hooks, keeping only the latest ones.
+	pub async fn save_issuance_duration(&self, duration: Duration) -> Result<(), Error> {
+		let mut durations = self.get_issuance_durations().await?;
+		durations.push(duration.as_secs());
+		let excess = durations
+			.len()
+			.saturating_sub(crate::DEFAULT_ISSUANCE_HISTORY_SIZE);
+		durations.drain(..excess);
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "issuance");
+		let path = state_file.display().to_string();
+		let data = serde_json::to_vec(&durations)?;
+		tokio::fs::write(&state_file, data)
+			.await
+			.map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	pub fn get_pending_order(&self) -> Result<Option<PendingOrder>, Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "order");
+		if !state_file.is_file() {
+			return Ok(None);
+		}
+		let path = state_file.display().to_string();
+		let data = fs::read(&state_file).map_err(|e| Error::from(e).prefix(&path))?;
+		let pending = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(Some(pending))
+	}
+
+	pub fn save_pending_order(&self, pending: &PendingOrder) -> Result<(), Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "order");
+		let path = state_file.display().to_string();
+		let data = serde_json::to_vec(pending)?;
+		fs::write(&state_file, data).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	pub fn remove_pending_order(&self) -> Result<(), Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "order");
+		if !state_file.is_file() {
+			return Ok(());
+		}
+		let path = state_file.display().to_string();
+		fs::remove_file(&state_file).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	pub fn get_star_order(&self) -> Result<Option<StarOrder>, Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "star");
+		if !state_file.is_file() {
+			return Ok(None);
+		}
+		let path = state_file.display().to_string();
+		let data = fs::read(&state_file).map_err(|e| Error::from(e).prefix(&path))?;
+		let order = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(Some(order))
+	}
+
+	pub fn save_star_order(&self, order: &StarOrder) -> Result<(), Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "star");
+		let path = state_file.display().to_string();
+		let data = serde_json::to_vec(order)?;
+		fs::write(&state_file, data).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	pub fn remove_star_order(&self) -> Result<(), Error> {
+		let state_file = get_state_file(&self.state_dir, &self.get_id(), "star");
+		if !state_file.is_file() {
+			return Ok(());
+		}
+		let path = state_file.display().to_string();
+		fs::remove_file(&state_file).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	/// Return a comma-separated list of the identifiers this certificate is valid for.
 	pub fn identifier_list(&self) -> String {
 		self.identifiers
 			.iter()
@@ -129,68 +569,362 @@ impl Certificate {
 			self.debug("the current certificate doesn't include all the required identifiers");
 			return Ok(Duration::ZERO);
 		}
-		self.renew_in(&cert)
+		let renew_in = self.renew_in(&cert).await?;
+		if self.star.is_none() {
+			return Ok(renew_in);
+		}
+		// Until the CA issues the next STAR certificate, it is fetched again periodically.
+		let retry_in = match self.get_star_order()? {
+			Some(o) => (o.fetched + crate::DEFAULT_STAR_FETCH_RETRY_SEC).saturating_sub(now()),
+			None => 0,
+		};
+		Ok(renew_in.max(Duration::from_secs(retry_in)))
 	}
 
-	pub async fn call_challenge_hooks(
+	pub async fn get_challenge_data(
 		&self,
 		file_name: &str,
 		proof: &str,
 		identifier: &str,
-	) -> Result<(ChallengeHookData, HookType), Error> {
+		account: &AccountIdentity,
+		account_thumbprint: &str,
+	) -> Result<(ChallengeHookData, Box<dyn Solver>), Error> {
 		let identifier = self.get_identifier_from_str(identifier)?;
+		let record_name = if identifier.challenge == Challenge::Dns01 {
+			get_delegated_name(self, &get_record_name(&identifier.value)).await
+		} else {
+			String::new()
+		};
 		let mut hook_data = ChallengeHookData {
+			account_contacts: account.contacts.to_owned(),
+			account_name: account.name.to_owned(),
+			account_thumbprint: account_thumbprint.to_string(),
+			account_url: account.url.to_owned(),
 			challenge: identifier.challenge.to_string(),
 			identifier: identifier.value.to_owned(),
 			identifier_tls_alpn: identifier.get_tls_alpn_name().unwrap_or_default(),
 			file_name: file_name.to_string(),
 			proof: proof.to_string(),
+			record_name,
 			is_clean_hook: false,
+			labels: self.labels.clone(),
 			env: HashMap::new(),
 		};
 		hook_data.set_env(&self.env);
 		hook_data.set_env(&identifier.env);
-		let hook_type = match identifier.challenge {
-			Challenge::Http01 => (HookType::ChallengeHttp01, HookType::ChallengeHttp01Clean),
-			Challenge::Dns01 => (HookType::ChallengeDns01, HookType::ChallengeDns01Clean),
-			Challenge::TlsAlpn01 => (
-				HookType::ChallengeTlsAlpn01,
-				HookType::ChallengeTlsAlpn01Clean,
-			),
+		let solver = match &identifier.solver {
+			Some(s) => {
+				self.trace(&format!("{}: using the {s} solver", identifier.value));
+				s.get_solver()
+			}
+			None => {
+				let solver_type =
+					if identifier.challenge == Challenge::Http01 && self.stateless_http_01 {
+						SolverType::StatelessHttp01
+					} else {
+						SolverType::Hooks
+					};
+				self.trace(&format!(
+					"{}: using the {solver_type} solver",
+					identifier.value
+				));
+				solver_type.get_solver()
+			}
 		};
-		hooks::call(self, &self.hooks, &hook_data, hook_type.0).await?;
-		Ok((hook_data, hook_type.1))
+		Ok((hook_data, solver))
 	}
 
-	pub async fn call_challenge_hooks_clean(
-		&self,
-		data: &ChallengeHookData,
-		hook_type: HookType,
-	) -> Result<(), Error> {
-		hooks::call(self, &self.hooks, data, hook_type).await
+	pub async fn call_challenge_ready_hooks(&self, data: &ChallengeHookData) -> Result<(), Error> {
+		hooks::call_until_success(
+			self,
+			&self.hook_recorder,
+			&self.hooks,
+			data,
+			HookType::ChallengeReady,
+			self.challenge_ready_interval,
+			self.challenge_ready_timeout,
+		)
+		.await
 	}
 
+	/// Call the post-operation hooks. The operation failed if the kind of the error is given.
 	pub async fn call_post_operation_hooks(
 		&self,
+		account: &AccountIdentity,
 		status: &str,
-		is_success: bool,
+		error_source: Option<ErrorKind>,
 	) -> Result<(), Error> {
+		let is_success = error_source.is_none();
 		let identifiers = self
 			.identifiers
 			.iter()
 			.map(|d| d.value.to_owned())
 			.collect::<Vec<String>>();
 		let mut hook_data = PostOperationHookData {
+			account_contacts: account.contacts.to_owned(),
+			account_name: account.name.to_owned(),
+			account_url: account.url.to_owned(),
+			endpoint_name: self.endpoint_name.to_owned(),
 			identifiers,
 			key_type: self.key_type.to_string(),
+			labels: self.labels.clone(),
 			status: status.to_string(),
 			is_success,
+			error_source: error_source.map(|e| e.to_string()).unwrap_or_default(),
 			certificate_path: crate::storage::get_certificate_path(&self.file_manager).await?,
 			private_key_path: crate::storage::get_keypair_path(&self.file_manager).await?,
+			certificate: CertificateDetails::default(),
 			env: HashMap::new(),
 		};
+		if is_success {
+			let details = crate::storage::get_certificate_chain(&self.file_manager)
+				.await
+				.and_then(|chain| CertificateDetails::from_chain(&chain));
+			match details {
+				Ok(details) => hook_data.certificate = details,
+				Err(e) => self.warn(&e.prefix("unable to read the certificate's details").message),
+			}
+		}
 		hook_data.set_env(&self.env);
-		hooks::call(self, &self.hooks, &hook_data, HookType::PostOperation).await?;
+		hooks::call(
+			self,
+			&self.hook_recorder,
+			&self.hooks,
+			&hook_data,
+			HookType::PostOperation,
+		)
+		.await?;
+		Ok(())
+	}
+
+	/// Check that the servers of the deploy check serve the certificate which has just been
+	/// written, which shows the post-operation hooks have reloaded them. Each server is retried a
+	/// few times since the reload may take a moment.
+	pub async fn check_deployment(&self) -> Result<(), Error> {
+		let check = match &self.deploy_check {
+			Some(c) => c,
+			None => return Ok(()),
+		};
+		let expected = get_certificate(&self.file_manager)
+			.await?
+			.fingerprint(HashFunction::Sha256)?;
+		let server_name = match &check.server_name {
+			Some(n) => n.to_owned(),
+			None => self
+				.identifiers
+				.first()
+				.map(|i| i.value.trim_start_matches("*.").to_string())
+				.unwrap_or_default(),
+		};
+		let mut nb_errors = 0;
+		for address in check.addresses.iter() {
+			let mut res = Ok(());
+			for _ in 0..crate::DEFAULT_DEPLOY_CHECK_NB_TRIES {
+				tokio::time::sleep(check.delay).await;
+				res = match fetch_certificate(address, &server_name).await {
+					Ok(c) if c.fingerprint(HashFunction::Sha256)? == expected => Ok(()),
+					Ok(c) => Err(Error::from(format!(
+						"{address}: another certificate is served (serial number {}, expires on {})",
+						c.serial_number()?,
+						format_timestamp(c.not_after()?)
+					))),
+					Err(e) => Err(e),
+				};
+				if res.is_ok() {
+					break;
+				}
+			}
+			events::emit(
+				"deployment_checked",
+				Some(self.get_id()),
+				json!({
+					"address": address,
+					"success": res.is_ok(),
+					"error": res.as_ref().err().map(|e| e.message.to_owned()),
+				}),
+			);
+			match res {
+				Ok(_) => self.debug(&format!("{address}: the certificate is served")),
+				Err(e) => {
+					self.warn_error("deploy-check", &e);
+					nb_errors += 1;
+				}
+			}
+		}
+		if nb_errors != 0 {
+			let msg = format!("the certificate is not served by {nb_errors} server(s)");
+			return Err(msg.into());
+		}
 		Ok(())
 	}
+
+	/// Check the templates of the hooks against sample data matching each type of hook. The
+	/// values are placeholders, but the variables and their types are those of an actual
+	/// renewal.
+	pub async fn check_hooks(&self) -> Result<(), Error> {
+		storage::check_hooks(&self.file_manager)?;
+		for identifier in self.identifiers.iter() {
+			let (hook_type, clean_hook_type) = get_hook_types(&identifier.challenge.to_string())?;
+			let mut hook_data = ChallengeHookData {
+				account_contacts: vec!["mailto:admin@example.org".to_string()],
+				account_name: self.account_name.to_owned(),
+				account_thumbprint: "thumbprint".to_string(),
+				account_url: "https://acme.example.org/account/1".to_string(),
+				challenge: identifier.challenge.to_string(),
+				identifier: identifier.value.to_owned(),
+				identifier_tls_alpn: identifier.get_tls_alpn_name().unwrap_or_default(),
+				file_name: "token".to_string(),
+				proof: "proof".to_string(),
+				record_name: if identifier.challenge == Challenge::Dns01 {
+					get_record_name(&identifier.value)
+				} else {
+					String::new()
+				},
+				is_clean_hook: false,
+				labels: self.labels.clone(),
+				env: HashMap::new(),
+			};
+			hook_data.set_env(&self.env);
+			hook_data.set_env(&identifier.env);
+			hooks::check(&self.hooks, &hook_data, hook_type)?;
+			hooks::check(&self.hooks, &hook_data, HookType::ChallengeReady)?;
+			hook_data.is_clean_hook = true;
+			hooks::check(&self.hooks, &hook_data, clean_hook_type)?;
+		}
+		let mut hook_data = PostOperationHookData {
+			account_contacts: vec!["mailto:admin@example.org".to_string()],
+			account_name: self.account_name.to_owned(),
+			account_url: "https://acme.example.org/account/1".to_string(),
+			endpoint_name: self.endpoint_name.to_owned(),
+			identifiers: self
+				.identifiers
+				.iter()
+				.map(|d| d.value.to_owned())
+				.collect(),
+			key_type: self.key_type.to_string(),
+			labels: self.labels.clone(),
+			status: "success".to_string(),
+			is_success: true,
+			error_source: String::new(),
+			certificate_path: storage::get_certificate_path(&self.file_manager).await?,
+			private_key_path: storage::get_keypair_path(&self.file_manager).await?,
+			certificate: CertificateDetails::sample(
+				self.identifiers
+					.iter()
+					.map(|d| d.value.to_owned())
+					.collect(),
+			),
+			env: HashMap::new(),
+		};
+		hook_data.set_env(&self.env);
+		hooks::check(&self.hooks, &hook_data, HookType::PostOperation)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{apply_jitter, get_adaptive_delay, get_jitter};
+	use std::time::Duration;
+
+	#[test]
+	fn test_jitter() {
+		assert_eq!(
+			get_jitter("example.org_rsa2048", Duration::ZERO),
+			(Duration::ZERO, false)
+		);
+		let jitter = Duration::from_secs(6 * 60 * 60);
+		let (offset, is_late) = get_jitter("example.org_rsa2048", jitter);
+		assert_eq!(get_jitter("example.org_rsa2048", jitter), (offset, is_late));
+		assert!(offset <= jitter);
+		let offsets: Vec<(Duration, bool)> = (0..100)
+			.map(|i| get_jitter(&format!("{i}.example.org_rsa2048"), jitter))
+			.collect();
+		assert!(offsets.iter().all(|(o, _)| *o <= jitter));
+		assert!(offsets.iter().any(|(_, l)| *l));
+		assert!(offsets.iter().any(|(_, l)| !*l));
+		let mut distinct = offsets.clone();
+		distinct.sort();
+		distinct.dedup();
+		assert!(distinct.len() > 90);
+	}
+
+	#[test]
+	fn test_apply_jitter() {
+		let day = Duration::from_secs(24 * 60 * 60);
+		let hour = Duration::from_secs(60 * 60);
+		let delay = 30 * day;
+		assert_eq!(
+			apply_jitter(10 * day, 40 * day, delay, (hour, true)),
+			10 * day + hour
+		);
+		assert_eq!(
+			apply_jitter(10 * day, 40 * day, delay, (hour, false)),
+			10 * day - hour
+		);
+		assert_eq!(
+			apply_jitter(10 * day, 40 * day, delay, (20 * day, true)),
+			25 * day
+		);
+		assert_eq!(
+			apply_jitter(hour, hour, delay, (hour, false)),
+			Duration::ZERO
+		);
+	}
+
+	#[test]
+	fn test_apply_jitter_overdue() {
+		let day = Duration::from_secs(24 * 60 * 60);
+		let hour = Duration::from_secs(60 * 60);
+		let delay = 30 * day;
+		// Overdue and expired certificates are renewed right away.
+		assert_eq!(
+			apply_jitter(Duration::ZERO, 2 * day, delay, (hour, true)),
+			Duration::ZERO
+		);
+		assert_eq!(
+			apply_jitter(Duration::ZERO, Duration::ZERO, delay, (hour, true)),
+			Duration::ZERO
+		);
+		// The renewal is never postponed past the expiration.
+		assert_eq!(
+			apply_jitter(2 * day, 2 * day + hour, 6 * hour, (3 * hour, true)),
+			2 * day + hour
+		);
+	}
+
+	#[test]
+	fn test_adaptive_delay() {
+		let day = 24 * 60 * 60;
+		let delay = Duration::from_secs(30 * day);
+		let lifetime = Duration::from_secs(90 * day);
+		assert_eq!(
+			get_adaptive_delay(&[], 4.0, delay, lifetime),
+			Duration::ZERO
+		);
+		assert_eq!(
+			get_adaptive_delay(&[3600], 0.0, delay, lifetime),
+			Duration::ZERO
+		);
+		assert_eq!(
+			get_adaptive_delay(&[60, 7200, 3600, 86400, 3600], 4.0, delay, lifetime),
+			Duration::from_secs(4 * 3600)
+		);
+		assert_eq!(
+			get_adaptive_delay(&[60, 7200], 0.5, delay, lifetime),
+			Duration::from_secs(3600)
+		);
+		assert_eq!(get_adaptive_delay(&[30 * day], 2.0, delay, lifetime), delay);
+		// A renewal delay of more than half the lifetime, e.g. with STAR certificates or a
+		// high `renew_at`, leaves less room for the extension.
+		let lifetime = Duration::from_secs(7 * day);
+		let delay = Duration::from_secs(5 * day);
+		assert_eq!(
+			get_adaptive_delay(&[30 * day], 2.0, delay, lifetime),
+			Duration::from_secs(day)
+		);
+		assert_eq!(
+			get_adaptive_delay(&[day], 2.0, lifetime, lifetime),
+			Duration::ZERO
+		);
+	}
 }
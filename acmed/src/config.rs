@@ -1,20 +1,38 @@
+use crate::advisory::VersionAdvisory;
+use crate::dns_provider::Secret;
 use crate::duration::parse_duration;
+use crate::email::EmailNotifier;
+use crate::endpoint::RateLimits;
 use crate::hooks;
 use crate::identifier::IdentifierType;
+use crate::interpolation::interpolate_env;
+use crate::platform::{Current, Platform};
+use crate::privileges;
+use crate::renewal_window::RenewalWindow;
+use crate::solver::{NamedSolver, SolverType, Webroot};
 use crate::storage::FileManager;
+use crate::template::compile_template;
+use crate::watched;
+use crate::web_ui::WebUi;
+use crate::webhooks;
 use acme_common::b64_decode;
-use acme_common::crypto::{HashFunction, JwsSignatureAlgorithm, KeyType, SubjectAttribute};
-use acme_common::error::Error;
-use glob::glob;
+use acme_common::crypto::{
+	HashFunction, JwsSignatureAlgorithm, KeyEncoding, KeyType, PrivateKeyFormat, SubjectAttribute,
+};
+use acme_common::error::{Error, ErrorKind};
+use acme_common::logs::{LogFormat, DEFAULT_LOG_FORMAT};
+use glob::{glob_with, MatchOptions};
 use log::info;
 use serde::{de, Deserialize, Deserializer};
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
 use std::fs::{self, File};
 use std::io::prelude::*;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroU32;
 use std::path::{Path, PathBuf};
 use std::result::Result;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 macro_rules! set_cfg_attr {
@@ -33,6 +51,81 @@ macro_rules! push_subject_attr {
 	};
 }
 
+fn parse_percentage(input: &str) -> Result<f64, Error> {
+	let err = || Error::from(format!("{input}: invalid percentage"));
+	let nb = input
+		.trim()
+		.strip_suffix('%')
+		.ok_or_else(err)?
+		.trim_end()
+		.parse::<f64>()
+		.map_err(|_| err())?;
+	if nb > 0.0 && nb < 100.0 {
+		Ok(nb)
+	} else {
+		Err(err())
+	}
+}
+
+fn parse_adaptive_factor(factor: f64) -> Result<f64, Error> {
+	if factor.is_finite() && factor >= 0.0 {
+		Ok(factor)
+	} else {
+		Err(format!("{factor}: invalid adaptive renewal factor").into())
+	}
+}
+
+fn get_signature(
+	algorithm: &Option<String>,
+	key_file: &Option<String>,
+) -> Result<Option<hooks::HookSignature>, Error> {
+	let (algorithm, key_file) = match (algorithm, key_file) {
+		(Some(a), Some(k)) => (a.parse::<JwsSignatureAlgorithm>()?, k.to_owned()),
+		(None, None) => return Ok(None),
+		_ => return Err("signature_algorithm and signature_key must be specified together".into()),
+	};
+	let signature = hooks::HookSignature {
+		algorithm,
+		key_file,
+	};
+	signature.check()?;
+	Ok(Some(signature))
+}
+
+fn parse_renew_windows(windows: &Option<Vec<String>>) -> Result<Vec<RenewalWindow>, Error> {
+	let windows: Vec<RenewalWindow> = windows
+		.iter()
+		.flatten()
+		.map(|w| w.parse())
+		.collect::<Result<_, _>>()?;
+	// The windows are in local time, which would silently be replaced by UTC otherwise.
+	if !windows.is_empty() && Current::utc_offset(acme_common::now()).is_none() {
+		return Err("renewal windows are not supported on this platform since the local time zone is unknown".into());
+	}
+	Ok(windows)
+}
+
+fn check_labels(labels: &BTreeMap<String, String>) -> Result<(), Error> {
+	for name in labels.keys() {
+		let is_valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+			&& name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+		if !is_valid {
+			return Err(format!("{name}: invalid label name").into());
+		}
+	}
+	Ok(())
+}
+
+/// Return the host of an address in the `host:port` format.
+fn parse_tls_address(address: &str) -> Result<&str, Error> {
+	match address.rsplit_once(':') {
+		Some((h, p)) if !h.is_empty() && p.parse::<u16>().is_ok() => {
+			Ok(h.trim_start_matches('[').trim_end_matches(']'))
+		}
+		_ => Err(format!("{address}: the address must be host:port").into()),
+	}
+}
+
 fn get_stdin(hook: &Hook) -> Result<hooks::HookStdin, Error> {
 	match &hook.stdin {
 		Some(file) => match &hook.stdin_str {
@@ -61,6 +154,8 @@ pub struct Config {
 	#[serde(default, rename = "rate-limit")]
 	pub rate_limit: Vec<RateLimit>,
 	#[serde(default)]
+	pub solver: Vec<Solver>,
+	#[serde(default)]
 	pub hook: Vec<Hook>,
 	#[serde(default)]
 	pub group: Vec<Group>,
@@ -70,6 +165,15 @@ pub struct Config {
 	pub certificate: Vec<Certificate>,
 	#[serde(default)]
 	pub include: Vec<String>,
+	#[serde(default)]
+	pub watch: Vec<Watch>,
+	#[serde(default)]
+	pub webhook: Vec<Webhook>,
+	#[serde(skip)]
+	hook_cache: Mutex<HashMap<String, Vec<Arc<hooks::Hook>>>>,
+	/// Paths of the configuration files which have been loaded, included ones included.
+	#[serde(skip)]
+	pub loaded_files: Vec<PathBuf>,
 }
 
 impl Config {
@@ -82,6 +186,29 @@ impl Config {
 		Err(format!("{name}: rate limit not found").into())
 	}
 
+	fn get_solver(&self, name: &str) -> Result<NamedSolver, Error> {
+		for s in self.solver.iter() {
+			if s.name == name {
+				let prefix = format!("solver \"{name}\"");
+				let solver_type = SolverType::from_config(s).map_err(|e| e.prefix(&prefix))?;
+				let mut limits = vec![];
+				for rl_name in s.rate_limits.iter() {
+					limits.push(
+						self.get_rate_limit(rl_name)
+							.map_err(|e| e.prefix(&prefix))?,
+					);
+				}
+				RateLimits::new(&limits).map_err(|e| e.prefix(&prefix))?;
+				return Ok(NamedSolver {
+					name: name.to_string(),
+					solver_type,
+					rate_limits: limits,
+				});
+			}
+		}
+		Err(format!("{name}: solver not found").into())
+	}
+
 	pub fn get_account_dir(&self) -> String {
 		let account_dir = match &self.global {
 			Some(g) => match &g.accounts_directory {
@@ -93,22 +220,115 @@ impl Config {
 		account_dir.to_string()
 	}
 
-	pub fn get_hook(&self, name: &str) -> Result<Vec<hooks::Hook>, Error> {
+	pub fn get_state_dir(&self) -> String {
+		let state_dir = match &self.global {
+			Some(g) => match &g.state_directory {
+				Some(d) => d,
+				None => crate::DEFAULT_STATE_DIR,
+			},
+			None => crate::DEFAULT_STATE_DIR,
+		};
+		state_dir.to_string()
+	}
+
+	pub fn get_max_parallel_renewals(&self) -> Result<usize, Error> {
+		let nb = match &self.global {
+			Some(g) => g
+				.max_parallel_renewals
+				.unwrap_or(crate::DEFAULT_MAX_PARALLEL_RENEWALS),
+			None => crate::DEFAULT_MAX_PARALLEL_RENEWALS,
+		};
+		if nb == 0 {
+			return Err("max_parallel_renewals must be non-zero".into());
+		}
+		Ok(nb)
+	}
+
+	pub fn get_control_socket(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.control_socket.to_owned(),
+			None => None,
+		}
+	}
+
+	pub fn get_control_socket_mode(&self) -> u32 {
+		match &self.global {
+			Some(g) => g
+				.control_socket_mode
+				.unwrap_or(crate::DEFAULT_CONTROL_SOCKET_MODE),
+			None => crate::DEFAULT_CONTROL_SOCKET_MODE,
+		}
+	}
+
+	/// Return the hooks registered under the given name, which is either a hook or a group. Each
+	/// hook, and each group's list of hooks, is built once and then shared between every account
+	/// and certificate using it.
+	pub fn get_hook(&self, name: &str) -> Result<Vec<Arc<hooks::Hook>>, Error> {
+		// The cache is only a shortcut, hence it remains usable even if poisoned.
+		let cached = self
+			.hook_cache
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.get(name)
+			.cloned();
+		if let Some(h) = cached {
+			return Ok(h);
+		}
+		let hooks = self.build_hook(name)?;
+		self.hook_cache
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.insert(name.to_string(), hooks.clone());
+		Ok(hooks)
+	}
+
+	fn build_hook(&self, name: &str) -> Result<Vec<Arc<hooks::Hook>>, Error> {
 		for hook in self.hook.iter() {
 			if name == hook.name {
+				hook.compile_templates()
+					.map_err(|e| e.prefix(&format!("hook \"{name}\"")))?;
+				let (uid, gid) = privileges::get_ids(hook.user.as_deref(), hook.group.as_deref())
+					.map_err(|e| e.prefix(&format!("hook \"{name}\"")))?;
 				let h = hooks::Hook {
 					name: hook.name.to_owned(),
 					hook_type: hook.hook_type.iter().map(|e| e.to_owned()).collect(),
 					cmd: hook.cmd.to_owned(),
 					args: hook.args.to_owned(),
+					cwd: hook.cwd.to_owned(),
+					umask: match hook.umask {
+						Some(m) if m > 0o777 => {
+							let msg = format!("hook \"{name}\": invalid umask: {m:o}");
+							return Err(msg.into());
+						}
+						m => m,
+					},
 					stdin: get_stdin(hook)?,
 					stdout: hook.stdout.to_owned(),
 					stderr: hook.stderr.to_owned(),
 					allow_failure: hook
 						.allow_failure
 						.unwrap_or(crate::DEFAULT_HOOK_ALLOW_FAILURE),
+					log_output: hook.log_output.unwrap_or(crate::DEFAULT_HOOK_LOG_OUTPUT),
+					retries: hook.retries.unwrap_or(crate::DEFAULT_HOOK_RETRIES),
+					retry_delay: match &hook.retry_delay {
+						Some(d) => {
+							parse_duration(d).map_err(|e| e.prefix(&format!("hook \"{name}\"")))?
+						}
+						None => Duration::from_secs(crate::DEFAULT_HOOK_RETRY_DELAY_SEC),
+					},
+					signature: hook
+						.get_signature()
+						.map_err(|e| e.prefix(&format!("hook \"{name}\"")))?,
+					timeout: match &hook.timeout {
+						Some(d) => Some(
+							parse_duration(d).map_err(|e| e.prefix(&format!("hook \"{name}\"")))?,
+						),
+						None => None,
+					},
+					uid,
+					gid,
 				};
-				return Ok(vec![h]);
+				return Ok(vec![Arc::new(h)]);
 			}
 		}
 		for grp in self.group.iter() {
@@ -124,6 +344,348 @@ impl Config {
 		Err(format!("{name}: hook not found").into())
 	}
 
+	pub fn get_log_format(&self) -> Result<LogFormat, Error> {
+		match &self.global {
+			Some(g) => match &g.log_format {
+				Some(f) => f.parse(),
+				None => Ok(DEFAULT_LOG_FORMAT),
+			},
+			None => Ok(DEFAULT_LOG_FORMAT),
+		}
+	}
+
+	/// Return the offset, in seconds, applied to the system's clock. The environment variable
+	/// takes precedence over the configuration.
+	pub fn get_time_offset(&self) -> Result<i64, Error> {
+		let offset = match std::env::var(crate::TIME_OFFSET_ENV_VAR) {
+			Ok(o) => Some(o),
+			Err(_) => self.global.as_ref().and_then(|g| g.time_offset.to_owned()),
+		};
+		let offset = match offset {
+			Some(o) => o,
+			None => return Ok(0),
+		};
+		if !cfg!(feature = "time_override") {
+			let msg = format!("{offset}: overriding the time requires acmed to be built with the time_override feature");
+			return Err(Error::new(ErrorKind::Config, &msg));
+		}
+		let (is_negative, duration) = match offset.strip_prefix('-') {
+			Some(d) => (true, d),
+			None => (false, offset.trim_start_matches('+')),
+		};
+		let secs = parse_duration(duration)?.as_secs() as i64;
+		Ok(if is_negative { -secs } else { secs })
+	}
+
+	pub fn get_email_notifier(&self) -> Result<Option<EmailNotifier>, Error> {
+		let g = match &self.global {
+			Some(g) => g,
+			None => return Ok(None),
+		};
+		let host = match &g.email_smtp_host {
+			Some(h) => h.to_owned(),
+			None => {
+				if g.email_from.is_some() || g.email_to.is_some() {
+					return Err("the email notifications require an email_smtp_host".into());
+				}
+				return Ok(None);
+			}
+		};
+		let from = match &g.email_from {
+			Some(f) => f.to_owned(),
+			None => return Err("the email notifications require an email_from address".into()),
+		};
+		let to = g.email_to.to_owned().unwrap_or_default();
+		if to.is_empty() {
+			return Err("the email notifications require at least one email_to address".into());
+		}
+		let tls = g.email_smtp_tls.unwrap_or(crate::DEFAULT_EMAIL_SMTP_TLS);
+		let password = match &g.email_smtp_user {
+			Some(_) => Some(Secret::from_config(
+				"email_smtp_password",
+				g.email_smtp_password_file.as_deref(),
+				g.email_smtp_password_env.as_deref(),
+			)?),
+			None => None,
+		};
+		if password.is_some() && tls == SmtpTls::None {
+			return Err(
+				"the SMTP credentials must not be sent over an unencrypted connection".into(),
+			);
+		}
+		if g.email_smtp_ca_file.is_some() && tls == SmtpTls::None {
+			return Err("the email_smtp_ca_file option requires a TLS connection".into());
+		}
+		#[cfg(not(feature = "crypto_openssl"))]
+		if tls != SmtpTls::None {
+			return Err("the SMTP TLS connections require the crypto_openssl feature".into());
+		}
+		let expiration_margin = match &g.email_expiration_margin {
+			Some(m) => parse_duration(m)?,
+			None => Duration::from_secs(crate::DEFAULT_EMAIL_EXPIRATION_MARGIN_SEC),
+		};
+		Ok(Some(EmailNotifier {
+			host,
+			port: g.email_smtp_port.unwrap_or_else(|| tls.default_port()),
+			tls,
+			ca_file: g.email_smtp_ca_file.to_owned(),
+			user: g.email_smtp_user.to_owned(),
+			password,
+			from,
+			to,
+			failures: g.email_failures.unwrap_or(crate::DEFAULT_EMAIL_FAILURES),
+			expiration_margin,
+		}))
+	}
+
+	pub fn get_web_ui(&self) -> Result<Option<WebUi>, Error> {
+		let g = match &self.global {
+			Some(g) => g,
+			None => return Ok(None),
+		};
+		let listen: SocketAddr = match &g.web_ui_listen {
+			Some(l) => l
+				.parse()
+				.map_err(|_| Error::from(format!("{l}: invalid web_ui_listen address")))?,
+			None => return Ok(None),
+		};
+		let certificate = g.web_ui_certificate.to_owned();
+		#[cfg(not(feature = "crypto_openssl"))]
+		if certificate.is_some() {
+			return Err("the web_ui_certificate option requires the crypto_openssl feature".into());
+		}
+		if certificate.is_none() && !listen.ip().is_loopback() {
+			let msg = format!("{listen}: unless the web UI is served over HTTPS using the web_ui_certificate option, it must listen on a loopback address");
+			return Err(msg.into());
+		}
+		let password = match &g.web_ui_password_file {
+			Some(f) => Secret::File(f.to_owned()).get()?,
+			None => return Err("the web UI requires a web_ui_password_file".into()),
+		};
+		let user = match &g.web_ui_user {
+			Some(u) => u.to_owned(),
+			None => crate::DEFAULT_WEB_UI_USER.to_string(),
+		};
+		if user.contains(':') {
+			return Err(format!("{user}: the web_ui_user must not contain a colon").into());
+		}
+		Ok(Some(WebUi {
+			listen,
+			user,
+			password,
+			certificate,
+		}))
+	}
+
+	pub fn get_watched_certificates(&self) -> Result<Vec<watched::WatchedCertificate>, Error> {
+		let email = self.get_email_notifier()?.map(Arc::new);
+		let expiring_margin = self.get_expiring_margin()?;
+		let mut res = vec![];
+		for w in self.watch.iter() {
+			let prefix = format!("watched certificate \"{}\"", w.name);
+			if res
+				.iter()
+				.any(|e: &watched::WatchedCertificate| e.name == w.name)
+			{
+				return Err(format!("{prefix}: duplicate watched certificate name").into());
+			}
+			check_labels(&w.labels).map_err(|e| e.prefix(&prefix))?;
+			res.push(watched::WatchedCertificate {
+				name: w.name.to_owned(),
+				source: w.get_source().map_err(|e| e.prefix(&prefix))?,
+				labels: w.labels.clone(),
+				interval: w.get_interval().map_err(|e| e.prefix(&prefix))?,
+				expiration_margin: w
+					.get_expiration_margin(expiring_margin)
+					.map_err(|e| e.prefix(&prefix))?,
+				email: email.clone(),
+			});
+		}
+		Ok(res)
+	}
+
+	pub fn get_webhooks(&self) -> Result<Vec<webhooks::Webhook>, Error> {
+		let env = self
+			.global
+			.as_ref()
+			.map(|g| g.env.clone())
+			.unwrap_or_default();
+		let mut res = vec![];
+		for wh in self.webhook.iter() {
+			let prefix = format!("webhook \"{}\"", wh.name);
+			if res.iter().any(|w: &webhooks::Webhook| w.name == wh.name) {
+				return Err(format!("{prefix}: duplicate webhook name").into());
+			}
+			if wh.events.is_empty() {
+				return Err(format!("{prefix}: at least one event must be specified").into());
+			}
+			for event in wh.events.iter() {
+				if !webhooks::EVENTS.contains(&event.as_str()) {
+					return Err(format!("{prefix}: {event}: unknown event").into());
+				}
+			}
+			for tpl in std::iter::once(&wh.url)
+				.chain(wh.headers.values())
+				.chain(wh.body.iter())
+			{
+				compile_template(tpl).map_err(|e| e.prefix(&prefix))?;
+			}
+			let signature = get_signature(&wh.signature_algorithm, &wh.signature_key)
+				.map_err(|e| e.prefix(&prefix))?;
+			if signature.is_some() {
+				for name in wh.headers.keys() {
+					if webhooks::SIGNATURE_HEADERS.contains(&name.to_lowercase().as_str()) {
+						let msg = format!("{prefix}: {name}: reserved for the signature");
+						return Err(msg.into());
+					}
+				}
+			}
+			res.push(webhooks::Webhook {
+				name: wh.name.to_owned(),
+				url: wh.url.to_owned(),
+				events: wh.events.to_owned(),
+				headers: wh.headers.to_owned(),
+				body: wh.body.to_owned(),
+				signature,
+				env: env.clone(),
+			});
+		}
+		Ok(res)
+	}
+
+	pub fn get_version_advisory(&self) -> Result<Option<VersionAdvisory>, Error> {
+		let g = match &self.global {
+			Some(g) => g,
+			None => return Ok(None),
+		};
+		let url = match &g.version_advisory_url {
+			Some(u) => u.to_owned(),
+			None => {
+				if !g.version_advisory_hooks.is_empty() || g.version_advisory_interval.is_some() {
+					return Err("the version advisory requires a version_advisory_url".into());
+				}
+				return Ok(None);
+			}
+		};
+		let interval = match &g.version_advisory_interval {
+			Some(i) => parse_duration(i)?,
+			None => Duration::from_secs(crate::DEFAULT_VERSION_ADVISORY_INTERVAL_SEC),
+		};
+		if interval.is_zero() {
+			return Err("version_advisory_interval must be non-zero".into());
+		}
+		let mut hooks = vec![];
+		for name in g.version_advisory_hooks.iter() {
+			hooks.extend(self.get_hook(name)?);
+		}
+		Ok(Some(VersionAdvisory {
+			url,
+			interval,
+			hooks,
+			env: g.env.clone(),
+		}))
+	}
+
+	pub fn get_expiring_margin(&self) -> Result<Duration, Error> {
+		match self
+			.global
+			.as_ref()
+			.and_then(|g| g.expiring_margin.as_ref())
+		{
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::from_secs(crate::DEFAULT_CERTIFICATE_EXPIRING_SEC)),
+		}
+	}
+
+	pub fn get_shutdown_timeout(&self) -> Result<Duration, Error> {
+		match self
+			.global
+			.as_ref()
+			.and_then(|g| g.shutdown_timeout.as_ref())
+		{
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::from_secs(crate::DEFAULT_SHUTDOWN_TIMEOUT_SEC)),
+		}
+	}
+
+	pub fn get_hook_history_size(&self) -> usize {
+		match &self.global {
+			Some(g) => g
+				.hook_history_size
+				.unwrap_or(crate::DEFAULT_HOOK_HISTORY_SIZE),
+			None => crate::DEFAULT_HOOK_HISTORY_SIZE,
+		}
+	}
+
+	pub fn get_log_syslog(&self) -> bool {
+		match &self.global {
+			Some(g) => g.log_syslog.unwrap_or(false),
+			None => false,
+		}
+	}
+
+	pub fn get_syslog_facility(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.syslog_facility.to_owned(),
+			None => None,
+		}
+	}
+
+	pub fn get_syslog_tag(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.syslog_tag.to_owned(),
+			None => None,
+		}
+	}
+
+	pub fn get_account_dir_mode(&self) -> u32 {
+		match &self.global {
+			Some(g) => match g.account_dir_mode {
+				Some(m) => m,
+				None => crate::DEFAULT_ACCOUNT_DIR_MODE,
+			},
+			None => crate::DEFAULT_ACCOUNT_DIR_MODE,
+		}
+	}
+
+	pub fn get_account_dir_user(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.account_dir_user.to_owned(),
+			None => None,
+		}
+	}
+
+	pub fn get_account_dir_group(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.account_dir_group.to_owned(),
+			None => None,
+		}
+	}
+
+	pub fn get_cert_dir_mode(&self) -> u32 {
+		match &self.global {
+			Some(g) => match g.cert_dir_mode {
+				Some(m) => m,
+				None => crate::DEFAULT_CERT_DIR_MODE,
+			},
+			None => crate::DEFAULT_CERT_DIR_MODE,
+		}
+	}
+
+	pub fn get_cert_dir_user(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.cert_dir_user.to_owned(),
+			None => None,
+		}
+	}
+
+	pub fn get_cert_dir_group(&self) -> Option<String> {
+		match &self.global {
+			Some(g) => g.cert_dir_group.to_owned(),
+			None => None,
+		}
+	}
+
 	pub fn get_cert_file_mode(&self) -> u32 {
 		match &self.global {
 			Some(g) => match g.cert_file_mode {
@@ -176,20 +738,64 @@ impl Config {
 #[derive(Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct GlobalOptions {
+	pub account_dir_group: Option<String>,
+	pub account_dir_mode: Option<u32>,
+	pub account_dir_user: Option<String>,
 	pub accounts_directory: Option<String>,
+	pub auto_register: Option<bool>,
+	pub cert_dir_group: Option<String>,
+	pub cert_dir_mode: Option<u32>,
+	pub cert_dir_user: Option<String>,
 	pub cert_file_group: Option<String>,
 	pub cert_file_mode: Option<u32>,
 	pub cert_file_user: Option<String>,
 	pub certificates_directory: Option<String>,
+	pub control_socket: Option<String>,
+	pub control_socket_mode: Option<u32>,
+	pub duplicate_identifiers: Option<DuplicateIdentifiers>,
+	pub email_expiration_margin: Option<String>,
+	pub email_failures: Option<usize>,
+	pub email_from: Option<String>,
+	pub email_smtp_ca_file: Option<String>,
+	pub email_smtp_host: Option<String>,
+	pub email_smtp_password_env: Option<String>,
+	pub email_smtp_password_file: Option<String>,
+	pub email_smtp_port: Option<u16>,
+	pub email_smtp_tls: Option<SmtpTls>,
+	pub email_smtp_user: Option<String>,
+	pub email_to: Option<Vec<String>>,
 	#[serde(default)]
 	pub env: HashMap<String, String>,
+	pub expiring_margin: Option<String>,
 	pub file_name_format: Option<String>,
+	pub hook_history_size: Option<usize>,
+	pub log_format: Option<String>,
+	pub log_syslog: Option<bool>,
+	pub max_parallel_renewals: Option<usize>,
 	pub pk_file_group: Option<String>,
 	pub pk_file_mode: Option<u32>,
 	pub pk_file_user: Option<String>,
 	pub random_early_renew: Option<String>,
+	pub renew_adaptive_factor: Option<f64>,
+	pub renew_at: Option<String>,
 	pub renew_delay: Option<String>,
+	pub renew_jitter: Option<String>,
+	pub renew_window_critical: Option<String>,
+	pub renew_windows: Option<Vec<String>>,
 	pub root_certificates: Option<Vec<String>>,
+	pub shutdown_timeout: Option<String>,
+	pub state_directory: Option<String>,
+	pub syslog_facility: Option<String>,
+	pub syslog_tag: Option<String>,
+	pub time_offset: Option<String>,
+	#[serde(default)]
+	pub version_advisory_hooks: Vec<String>,
+	pub version_advisory_interval: Option<String>,
+	pub version_advisory_url: Option<String>,
+	pub web_ui_certificate: Option<String>,
+	pub web_ui_listen: Option<String>,
+	pub web_ui_password_file: Option<String>,
+	pub web_ui_user: Option<String>,
 }
 
 impl GlobalOptions {
@@ -207,6 +813,38 @@ impl GlobalOptions {
 		}
 	}
 
+	pub fn get_renew_adaptive_factor(&self) -> Result<f64, Error> {
+		match self.renew_adaptive_factor {
+			Some(f) => parse_adaptive_factor(f),
+			None => Ok(crate::DEFAULT_CERT_RENEW_ADAPTIVE_FACTOR),
+		}
+	}
+
+	pub fn get_renew_at(&self) -> Result<Option<f64>, Error> {
+		match &self.renew_at {
+			Some(p) => Ok(Some(parse_percentage(p)?)),
+			None => Ok(None),
+		}
+	}
+
+	pub fn get_renew_jitter(&self) -> Result<Duration, Error> {
+		match &self.renew_jitter {
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::new(crate::DEFAULT_CERT_RENEW_JITTER, 0)),
+		}
+	}
+
+	pub fn get_renew_window_critical(&self) -> Result<Duration, Error> {
+		match &self.renew_window_critical {
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::new(crate::DEFAULT_RENEW_WINDOW_CRITICAL_SEC, 0)),
+		}
+	}
+
+	pub fn get_renew_windows(&self) -> Result<Vec<RenewalWindow>, Error> {
+		parse_renew_windows(&self.renew_windows)
+	}
+
 	pub fn get_crt_name_format(&self) -> String {
 		match &self.file_name_format {
 			Some(n) => n.to_string(),
@@ -218,18 +856,45 @@ impl GlobalOptions {
 #[derive(Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Endpoint {
+	pub authorization_timeout: Option<String>,
+	pub dual_stack_preference: Option<IpFamily>,
 	pub file_name_format: Option<String>,
+	pub finalization_timeout: Option<String>,
+	pub force_ipv4: Option<bool>,
+	pub http_max_attempts: Option<usize>,
+	pub http_retry_delay: Option<String>,
 	pub name: String,
+	#[serde(default)]
+	pub order_limits: Vec<String>,
+	pub poll_interval: Option<String>,
+	pub preferred_chain: Option<String>,
+	pub profile: Option<String>,
+	pub proxy: Option<String>,
 	pub random_early_renew: Option<String>,
 	#[serde(default)]
 	pub rate_limits: Vec<String>,
+	pub renew_adaptive_factor: Option<f64>,
+	pub renew_at: Option<String>,
 	pub renew_delay: Option<String>,
+	pub renew_jitter: Option<String>,
+	pub renew_window_critical: Option<String>,
+	pub renew_windows: Option<Vec<String>>,
 	pub root_certificates: Option<Vec<String>>,
+	pub system_root_certificates: Option<bool>,
+	pub timeout: Option<String>,
 	pub tos_agreed: bool,
+	pub tos_auto_agree: Option<bool>,
 	pub url: String,
 }
 
 impl Endpoint {
+	pub fn get_timeout(&self) -> Result<Option<Duration>, Error> {
+		match &self.timeout {
+			Some(d) => Ok(Some(parse_duration(d)?)),
+			None => Ok(None),
+		}
+	}
+
 	pub fn get_random_early_renew(&self, cnf: &Config) -> Result<Duration, Error> {
 		match &self.random_early_renew {
 			Some(d) => parse_duration(d),
@@ -250,6 +915,59 @@ impl Endpoint {
 		}
 	}
 
+	pub fn get_renew_adaptive_factor(&self, cnf: &Config) -> Result<f64, Error> {
+		match self.renew_adaptive_factor {
+			Some(f) => parse_adaptive_factor(f),
+			None => match &cnf.global {
+				Some(g) => g.get_renew_adaptive_factor(),
+				None => Ok(crate::DEFAULT_CERT_RENEW_ADAPTIVE_FACTOR),
+			},
+		}
+	}
+
+	/// Return the renewal threshold. Since it conflicts with the renewal delay, it is only
+	/// inherited from the global section if this endpoint sets neither.
+	pub fn get_renew_at(&self, cnf: &Config) -> Result<Option<f64>, Error> {
+		match (&self.renew_at, &self.renew_delay) {
+			(Some(p), _) => Ok(Some(parse_percentage(p)?)),
+			(None, Some(_)) => Ok(None),
+			(None, None) => match &cnf.global {
+				Some(g) => g.get_renew_at(),
+				None => Ok(None),
+			},
+		}
+	}
+
+	pub fn get_renew_jitter(&self, cnf: &Config) -> Result<Duration, Error> {
+		match &self.renew_jitter {
+			Some(d) => parse_duration(d),
+			None => match &cnf.global {
+				Some(g) => g.get_renew_jitter(),
+				None => Ok(Duration::new(crate::DEFAULT_CERT_RENEW_JITTER, 0)),
+			},
+		}
+	}
+
+	pub fn get_renew_window_critical(&self, cnf: &Config) -> Result<Duration, Error> {
+		match &self.renew_window_critical {
+			Some(d) => parse_duration(d),
+			None => match &cnf.global {
+				Some(g) => g.get_renew_window_critical(),
+				None => Ok(Duration::new(crate::DEFAULT_RENEW_WINDOW_CRITICAL_SEC, 0)),
+			},
+		}
+	}
+
+	pub fn get_renew_windows(&self, cnf: &Config) -> Result<Vec<RenewalWindow>, Error> {
+		match &self.renew_windows {
+			Some(_) => parse_renew_windows(&self.renew_windows),
+			None => match &cnf.global {
+				Some(g) => g.get_renew_windows(),
+				None => Ok(vec![]),
+			},
+		}
+	}
+
 	pub fn get_crt_name_format(&self, cnf: &Config) -> String {
 		match &self.file_name_format {
 			Some(n) => n.to_string(),
@@ -279,25 +997,221 @@ impl Endpoint {
 				root_lst.extend(crt_lst.iter().map(|v| v.to_owned()));
 			}
 		}
-		crate::endpoint::Endpoint::new(
+		#[cfg(feature = "crypto_openssl")]
+		for crt_file in root_lst.iter() {
+			crate::http::load_root_certificate(crt_file)
+				.map_err(|e| e.prefix(&format!("{}: invalid root certificate", self.name)))?;
+		}
+		let system_root_certificates = self.system_root_certificates.unwrap_or(true);
+		if !system_root_certificates && root_lst.is_empty() {
+			let msg = format!(
+				"{}: the system root certificates are disabled but no root certificate is specified",
+				self.name
+			);
+			return Err(msg.into());
+		}
+		let mut endpoint = crate::endpoint::Endpoint::new(
 			&self.name,
 			&self.url,
 			self.tos_agreed,
 			&limits,
+			&self.order_limits,
+			&cnf.get_state_dir(),
 			root_lst.as_slice(),
-		)
+		)?;
+		if let Some(proxy) = &self.proxy {
+			reqwest::Proxy::all(proxy)
+				.map_err(|e| Error::from(e).prefix(&format!("{}: invalid proxy", self.name)))?;
+		}
+		endpoint.proxy = self.proxy.clone();
+		endpoint.tos_auto_agree = self.tos_auto_agree.unwrap_or(false);
+		endpoint.system_root_certificates = system_root_certificates;
+		endpoint.force_ipv4 = self.force_ipv4.unwrap_or(false);
+		if endpoint.force_ipv4 && self.dual_stack_preference == Some(IpFamily::Ipv6) {
+			let msg = format!(
+				"{}: IPv6 cannot be preferred when IPv4 is forced",
+				self.name
+			);
+			return Err(msg.into());
+		}
+		endpoint.dual_stack_preference = self.dual_stack_preference;
+		if let Some(nb) = self.http_max_attempts {
+			if nb == 0 {
+				let msg = format!("{}: http_max_attempts must be non-zero", self.name);
+				return Err(msg.into());
+			}
+			endpoint.http_max_attempts = nb;
+		}
+		if let Some(d) = &self.http_retry_delay {
+			endpoint.http_retry_delay = parse_duration(d)?;
+		}
+		if let Some(d) = &self.poll_interval {
+			endpoint.poll_interval = parse_duration(d)?;
+			if endpoint.poll_interval.is_zero() {
+				let msg = format!("{}: poll_interval must be non-zero", self.name);
+				return Err(msg.into());
+			}
+		}
+		if let Some(d) = &self.authorization_timeout {
+			endpoint.authorization_timeout = parse_duration(d)?;
+		}
+		if let Some(d) = &self.finalization_timeout {
+			endpoint.finalization_timeout = parse_duration(d)?;
+		}
+		Ok(endpoint)
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+	pub name: String,
+	pub number: NonZeroU32,
+	pub period: String,
+	#[serde(default)]
+	pub acme_resources: Vec<NamedAcmeResource>,
+	pub path: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+	pub address: Option<String>,
+	pub expiration_margin: Option<String>,
+	pub file: Option<String>,
+	pub interval: Option<String>,
+	#[serde(default)]
+	pub labels: BTreeMap<String, String>,
+	pub name: String,
+	pub server_name: Option<String>,
+}
+
+impl Watch {
+	fn get_source(&self) -> Result<watched::WatchSource, Error> {
+		match (&self.file, &self.address) {
+			(Some(f), None) => {
+				if self.server_name.is_some() {
+					return Err("server_name requires an address".into());
+				}
+				Ok(watched::WatchSource::File(f.to_owned()))
+			}
+			#[cfg(not(feature = "crypto_openssl"))]
+			(None, Some(_)) => Err("the address option requires the crypto_openssl feature".into()),
+			#[cfg(feature = "crypto_openssl")]
+			(None, Some(a)) => {
+				let host = parse_tls_address(a)?;
+				Ok(watched::WatchSource::Tls {
+					address: a.to_owned(),
+					server_name: self.server_name.as_deref().unwrap_or(host).to_string(),
+				})
+			}
+			_ => Err("either a file or an address must be specified".into()),
+		}
+	}
+
+	fn get_interval(&self) -> Result<Duration, Error> {
+		let interval = match &self.interval {
+			Some(i) => parse_duration(i)?,
+			None => Duration::from_secs(crate::DEFAULT_WATCH_INTERVAL_SEC),
+		};
+		if interval.is_zero() {
+			return Err("interval must be non-zero".into());
+		}
+		Ok(interval)
+	}
+
+	fn get_expiration_margin(&self, default: Duration) -> Result<Duration, Error> {
+		match &self.expiration_margin {
+			Some(m) => parse_duration(m),
+			None => Ok(default),
+		}
 	}
 }
 
-#[derive(Clone, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct RateLimit {
-	pub name: String,
-	pub number: NonZeroU32,
-	pub period: String,
-	#[serde(default)]
-	pub acme_resources: Vec<NamedAcmeResource>,
-	pub path: Option<String>,
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+	pub body: Option<String>,
+	pub events: Vec<String>,
+	#[serde(default)]
+	pub headers: BTreeMap<String, String>,
+	pub name: String,
+	pub signature_algorithm: Option<String>,
+	pub signature_key: Option<String>,
+	pub url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Solver {
+	pub name: String,
+	#[serde(rename = "type")]
+	pub solver_type: String,
+	pub access_key_id: Option<String>,
+	#[serde(default)]
+	pub allow_from: Vec<String>,
+	pub api_token_env: Option<String>,
+	pub api_token_file: Option<String>,
+	pub file_group: Option<String>,
+	pub file_mode: Option<u32>,
+	pub file_user: Option<String>,
+	pub nameserver: Option<String>,
+	pub path: Option<String>,
+	#[serde(default)]
+	pub rate_limits: Vec<String>,
+	pub tsig_algorithm: Option<String>,
+	pub tsig_key_name: Option<String>,
+	pub tsig_secret_env: Option<String>,
+	pub tsig_secret_file: Option<String>,
+	pub url: Option<String>,
+	pub zone: Option<String>,
+}
+
+/// What to do when an identifier is included in several certificates requested to the same
+/// endpoint.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateIdentifiers {
+	Allow,
+	Warn,
+	Skip,
+}
+
+/// What to do when the CAA records of an identifier do not authorize the CA to issue the
+/// certificate.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CaaCheck {
+	Off,
+	Warn,
+	Deny,
+}
+
+/// Address family of the IP addresses.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+	Ipv4,
+	Ipv6,
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+	None,
+	StartTls,
+	Tls,
+}
+
+impl SmtpTls {
+	fn default_port(&self) -> u16 {
+		match self {
+			SmtpTls::None => 25,
+			SmtpTls::StartTls => 587,
+			SmtpTls::Tls => 465,
+		}
+	}
 }
 
 #[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
@@ -312,19 +1226,144 @@ pub enum NamedAcmeResource {
 	KeyChange,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PkEncryption {
+	pub keep_unencrypted: Option<bool>,
+	pub passphrase_env: Option<String>,
+	pub passphrase_file: Option<String>,
+}
+
+impl PkEncryption {
+	pub fn to_generic(&self) -> Result<crate::storage::PkEncryption, Error> {
+		let passphrase = match (&self.passphrase_file, &self.passphrase_env) {
+			(Some(f), None) => crate::storage::Passphrase::File(f.to_owned()),
+			(None, Some(e)) => crate::storage::Passphrase::Env(e.to_owned()),
+			_ => {
+				return Err(
+					"pk_encryption: one and only one of passphrase_file and passphrase_env must be specified"
+						.into(),
+				)
+			}
+		};
+		Ok(crate::storage::PkEncryption {
+			passphrase,
+			keep_unencrypted: self.keep_unencrypted.unwrap_or(true),
+		})
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Star {
+	pub duration: String,
+	pub lifetime: String,
+	pub lifetime_adjust: Option<String>,
+}
+
+impl Star {
+	pub fn to_generic(&self) -> Result<crate::certificate::Star, Error> {
+		let duration = parse_duration(&self.duration)?;
+		let lifetime = parse_duration(&self.lifetime)?;
+		if lifetime.is_zero() || lifetime > duration {
+			return Err("star: the lifetime must be non-zero and shorter than the duration".into());
+		}
+		let lifetime_adjust = match &self.lifetime_adjust {
+			Some(d) => Some(parse_duration(d)?),
+			None => None,
+		};
+		Ok(crate::certificate::Star {
+			duration,
+			lifetime,
+			lifetime_adjust,
+		})
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesSecret {
+	pub api_server: Option<String>,
+	pub ca_file: Option<String>,
+	pub kubeconfig: Option<String>,
+	pub name: String,
+	pub namespace: Option<String>,
+	pub token_file: Option<String>,
+}
+
+impl KubernetesSecret {
+	pub fn to_generic(&self) -> Result<crate::kubernetes::KubernetesSecret, Error> {
+		#[cfg(not(feature = "crypto_openssl"))]
+		if self.ca_file.is_some() {
+			let msg = format!(
+				"kubernetes secret \"{}\": the ca_file option requires the crypto_openssl feature",
+				self.name
+			);
+			return Err(msg.into());
+		}
+		Ok(crate::kubernetes::KubernetesSecret {
+			name: self.name.clone(),
+			namespace: self.namespace.clone(),
+			api_server: self.api_server.clone(),
+			token_file: self.token_file.clone(),
+			ca_file: self.ca_file.clone(),
+			kubeconfig: self.kubeconfig.clone(),
+		})
+	}
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Hook {
 	pub allow_failure: Option<bool>,
 	pub args: Option<Vec<String>>,
 	pub cmd: String,
+	pub cwd: Option<String>,
+	pub group: Option<String>,
+	pub log_output: Option<bool>,
 	pub name: String,
+	pub retries: Option<usize>,
+	pub retry_delay: Option<String>,
+	pub signature_algorithm: Option<String>,
+	pub signature_key: Option<String>,
 	pub stderr: Option<String>,
 	pub stdin: Option<String>,
 	pub stdin_str: Option<String>,
 	pub stdout: Option<String>,
+	pub timeout: Option<String>,
 	#[serde(rename = "type")]
 	pub hook_type: Vec<HookType>,
+	pub umask: Option<u32>,
+	pub user: Option<String>,
+}
+
+impl Hook {
+	fn compile_templates(&self) -> Result<(), Error> {
+		let templates = [
+			&self.cwd,
+			&self.stderr,
+			&self.stdin,
+			&self.stdin_str,
+			&self.stdout,
+		];
+		for tpl in self
+			.args
+			.iter()
+			.flatten()
+			.chain(templates.iter().copied().flatten())
+		{
+			compile_template(tpl)?;
+		}
+		Ok(())
+	}
+
+	fn get_signature(&self) -> Result<Option<hooks::HookSignature>, Error> {
+		let signature = get_signature(&self.signature_algorithm, &self.signature_key)?;
+		if signature.is_some() && self.stdin.is_none() && self.stdin_str.is_none() {
+			return Err("only the standard input can be signed, but none is set".into());
+		}
+		Ok(signature)
+	}
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Deserialize)]
@@ -346,7 +1385,30 @@ pub enum HookType {
 	ChallengeTlsAlpn01,
 	#[serde(rename = "challenge-tls-alpn-01-clean")]
 	ChallengeTlsAlpn01Clean,
+	ChallengeReady,
 	PostOperation,
+	VersionAdvisory,
+}
+
+impl fmt::Display for HookType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match self {
+			HookType::FilePreCreate => "file-pre-create",
+			HookType::FilePostCreate => "file-post-create",
+			HookType::FilePreEdit => "file-pre-edit",
+			HookType::FilePostEdit => "file-post-edit",
+			HookType::ChallengeHttp01 => "challenge-http-01",
+			HookType::ChallengeHttp01Clean => "challenge-http-01-clean",
+			HookType::ChallengeDns01 => "challenge-dns-01",
+			HookType::ChallengeDns01Clean => "challenge-dns-01-clean",
+			HookType::ChallengeTlsAlpn01 => "challenge-tls-alpn-01",
+			HookType::ChallengeTlsAlpn01Clean => "challenge-tls-alpn-01-clean",
+			HookType::ChallengeReady => "challenge-ready",
+			HookType::PostOperation => "post-operation",
+			HookType::VersionAdvisory => "version-advisory",
+		};
+		write!(f, "{s}")
+	}
 }
 
 #[derive(Deserialize)]
@@ -389,6 +1451,7 @@ impl ExternalAccount {
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Account {
+	pub auto_register: Option<bool>,
 	pub contacts: Vec<AccountContact>,
 	#[serde(default)]
 	pub env: HashMap<String, String>,
@@ -396,11 +1459,13 @@ pub struct Account {
 	pub hooks: Option<Vec<String>>,
 	pub key_type: Option<String>,
 	pub name: String,
+	#[serde(default)]
+	pub order_limits: Vec<String>,
 	pub signature_algorithm: Option<String>,
 }
 
 impl Account {
-	pub fn get_hooks(&self, cnf: &Config) -> Result<Vec<hooks::Hook>, Error> {
+	pub fn get_hooks(&self, cnf: &Config) -> Result<Vec<Arc<hooks::Hook>>, Error> {
 		let lst = match &self.hooks {
 			Some(h) => {
 				let mut res = vec![];
@@ -415,44 +1480,64 @@ impl Account {
 		Ok(lst)
 	}
 
+	pub fn get_auto_register(&self, cnf: &Config) -> bool {
+		match self.auto_register {
+			Some(a) => a,
+			None => match &cnf.global {
+				Some(g) => g.auto_register.unwrap_or(crate::DEFAULT_AUTO_REGISTER),
+				None => crate::DEFAULT_AUTO_REGISTER,
+			},
+		}
+	}
+
 	pub async fn to_generic(
 		&self,
+		cnf: &Config,
 		file_manager: &FileManager,
+		update_keys: bool,
 	) -> Result<crate::account::Account, Error> {
 		let contacts: Vec<(String, String)> = self
 			.contacts
 			.iter()
-			.map(|e| (e.get_type(), e.get_value()))
-			.collect();
+			.map(|e| e.get_contact())
+			.collect::<Result<_, _>>()?;
 		let external_account = match &self.external_account {
 			Some(a) => Some(a.to_generic()?),
 			None => None,
 		};
-		crate::account::Account::load(
+		let mut account = crate::account::Account::load(
 			file_manager,
 			&self.name,
 			&contacts,
 			&self.key_type,
 			&self.signature_algorithm,
 			&external_account,
+			update_keys,
 		)
-		.await
+		.await?;
+		account.auto_register = self.get_auto_register(cnf);
+		Ok(account)
 	}
 }
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct AccountContact {
-	pub mailto: String,
+	pub mailto: Option<String>,
+	pub uri: Option<String>,
 }
 
 impl AccountContact {
-	pub fn get_type(&self) -> String {
-		"mailto".to_string()
-	}
-
-	pub fn get_value(&self) -> String {
-		self.mailto.clone()
+	/// Return the type of the contact, which is the scheme of its URI, and its value.
+	pub fn get_contact(&self) -> Result<(String, String), Error> {
+		match (&self.mailto, &self.uri) {
+			(Some(m), None) => Ok(("mailto".to_string(), m.to_owned())),
+			(None, Some(u)) => match u.split_once(':') {
+				Some((t, v)) => Ok((t.to_string(), v.to_string())),
+				None => Err(format!("{u}: invalid contact URI").into()),
+			},
+			_ => Err("contacts: one and only one of mailto and uri must be specified".into()),
+		}
 	}
 }
 
@@ -460,21 +1545,56 @@ impl AccountContact {
 #[serde(deny_unknown_fields)]
 pub struct Certificate {
 	pub account: String,
+	pub caa_check: Option<CaaCheck>,
+	pub challenge_ready_interval: Option<String>,
+	pub challenge_ready_timeout: Option<String>,
 	pub csr_digest: Option<String>,
+	#[serde(default)]
+	pub deploy_check: Vec<String>,
+	pub deploy_check_delay: Option<String>,
+	pub deploy_check_server_name: Option<String>,
 	pub directory: Option<String>,
+	pub dns_cleanup_delay: Option<String>,
+	pub dns_propagation_check: Option<bool>,
+	#[serde(default)]
+	pub dns_propagation_resolvers: Vec<String>,
+	pub dns_propagation_timeout: Option<String>,
+	pub duplicate_identifiers: Option<DuplicateIdentifiers>,
 	pub endpoint: String,
 	#[serde(default)]
 	pub env: HashMap<String, String>,
+	pub failover_attempts: Option<usize>,
+	#[serde(default)]
+	pub fallback_endpoints: Vec<FallbackEndpoint>,
 	pub file_name_format: Option<String>,
 	pub hooks: Vec<String>,
+	pub http_self_check: Option<bool>,
+	pub http_self_check_address: Option<String>,
 	pub identifiers: Vec<Identifier>,
 	pub key_type: Option<String>,
 	pub kp_reuse: Option<bool>,
+	pub kubernetes_secret: Option<KubernetesSecret>,
+	#[serde(default)]
+	pub labels: BTreeMap<String, String>,
 	pub name: Option<String>,
+	pub ocsp_staple: Option<bool>,
+	pub pk_encryption: Option<PkEncryption>,
+	pub pk_encoding: Option<String>,
+	pub pk_format: Option<String>,
+	pub preferred_chain: Option<String>,
+	pub profile: Option<String>,
 	pub random_early_renew: Option<String>,
+	pub renew_adaptive_factor: Option<f64>,
+	pub renew_at: Option<String>,
 	pub renew_delay: Option<String>,
+	pub renew_jitter: Option<String>,
+	pub renew_window_critical: Option<String>,
+	pub renew_windows: Option<Vec<String>>,
+	pub star: Option<Star>,
+	pub stateless_http_01: Option<bool>,
 	#[serde(default)]
 	pub subject_attributes: SubjectAttributes,
+	pub timeout: Option<String>,
 }
 
 impl Certificate {
@@ -485,6 +1605,22 @@ impl Certificate {
 		}
 	}
 
+	pub fn get_pk_format(&self) -> Result<PrivateKeyFormat, Error> {
+		let format = match &self.pk_format {
+			Some(f) => f.parse()?,
+			None => crate::DEFAULT_PK_FORMAT,
+		};
+		format.check_key_type_compatibility(self.get_key_type()?)?;
+		Ok(format)
+	}
+
+	pub fn get_pk_encoding(&self) -> Result<KeyEncoding, Error> {
+		match &self.pk_encoding {
+			Some(e) => e.parse(),
+			None => Ok(crate::DEFAULT_PK_ENCODING),
+		}
+	}
+
 	pub fn get_csr_digest(&self) -> Result<HashFunction, Error> {
 		match &self.csr_digest {
 			Some(d) => d.parse(),
@@ -492,10 +1628,13 @@ impl Certificate {
 		}
 	}
 
-	pub fn get_identifiers(&self) -> Result<Vec<crate::identifier::Identifier>, Error> {
+	pub fn get_identifiers(
+		&self,
+		cnf: &Config,
+	) -> Result<Vec<crate::identifier::Identifier>, Error> {
 		let mut ret = vec![];
 		for id in self.identifiers.iter() {
-			ret.push(id.to_generic()?);
+			ret.push(id.to_generic(cnf)?);
 		}
 		Ok(ret)
 	}
@@ -507,6 +1646,29 @@ impl Certificate {
 		}
 	}
 
+	pub fn get_caa_check(&self) -> CaaCheck {
+		self.caa_check.unwrap_or(crate::DEFAULT_CAA_CHECK)
+	}
+
+	pub fn get_ocsp_staple(&self) -> bool {
+		match self.ocsp_staple {
+			Some(b) => b,
+			None => crate::DEFAULT_OCSP_STAPLE,
+		}
+	}
+
+	pub fn get_duplicate_identifiers(&self, cnf: &Config) -> DuplicateIdentifiers {
+		match self.duplicate_identifiers {
+			Some(d) => d,
+			None => match &cnf.global {
+				Some(g) => g
+					.duplicate_identifiers
+					.unwrap_or(crate::DEFAULT_DUPLICATE_IDENTIFIERS),
+				None => crate::DEFAULT_DUPLICATE_IDENTIFIERS,
+			},
+		}
+	}
+
 	pub fn get_crt_name(&self) -> Result<String, Error> {
 		let name = match &self.name {
 			Some(n) => n.to_string(),
@@ -522,14 +1684,23 @@ impl Certificate {
 		Ok(name)
 	}
 
+	/// Return the labels, whose names are restricted so they can be used as template variables
+	/// and as the names of metrics labels.
+	pub fn get_labels(&self) -> Result<BTreeMap<String, String>, Error> {
+		check_labels(&self.labels)?;
+		Ok(self.labels.clone())
+	}
+
 	pub fn get_crt_name_format(&self, cnf: &Config) -> Result<String, Error> {
-		match &self.file_name_format {
-			Some(n) => Ok(n.to_string()),
+		let fmt = match &self.file_name_format {
+			Some(n) => n.to_string(),
 			None => {
 				let ep = self.do_get_endpoint(cnf)?;
-				Ok(ep.get_crt_name_format(cnf))
+				ep.get_crt_name_format(cnf)
 			}
-		}
+		};
+		compile_template(&fmt).map_err(|e| e.prefix("file_name_format"))?;
+		Ok(fmt)
 	}
 
 	pub fn get_crt_dir(&self, cnf: &Config) -> String {
@@ -564,7 +1735,63 @@ impl Certificate {
 		endpoint.to_generic(cnf, root_certs)
 	}
 
-	pub fn get_hooks(&self, cnf: &Config) -> Result<Vec<hooks::Hook>, Error> {
+	/// Return the endpoints used when the main one fails, in priority order, along with the
+	/// account and the options to use on each of them. The certificate's `preferred_chain` and
+	/// `profile` are specific to the main endpoint's CA, hence they do not apply to the others.
+	pub fn get_fallback_endpoints(
+		&self,
+		cnf: &Config,
+		root_certs: &[&str],
+	) -> Result<
+		Vec<(
+			crate::endpoint::Endpoint,
+			crate::certificate::FallbackEndpoint,
+		)>,
+		Error,
+	> {
+		let mut res = vec![];
+		let mut seen = vec![&self.endpoint];
+		for fallback in self.fallback_endpoints.iter() {
+			let name = &fallback.endpoint;
+			if seen.contains(&name) {
+				return Err(format!("{name}: endpoint listed more than once").into());
+			}
+			seen.push(name);
+			let endpoint = cnf
+				.endpoint
+				.iter()
+				.find(|e| e.name == *name)
+				.ok_or_else(|| Error::from(format!("{name}: unknown endpoint")))?;
+			let generic = crate::certificate::FallbackEndpoint {
+				endpoint_name: name.to_owned(),
+				account_name: fallback
+					.account
+					.as_ref()
+					.unwrap_or(&self.account)
+					.to_owned(),
+				preferred_chain: fallback
+					.preferred_chain
+					.to_owned()
+					.or_else(|| endpoint.preferred_chain.to_owned()),
+				profile: fallback
+					.profile
+					.to_owned()
+					.or_else(|| endpoint.profile.to_owned()),
+			};
+			res.push((endpoint.to_generic(cnf, root_certs)?, generic));
+		}
+		Ok(res)
+	}
+
+	pub fn get_failover_attempts(&self) -> Result<usize, Error> {
+		match self.failover_attempts {
+			Some(0) => Err("failover_attempts: must be at least 1".into()),
+			Some(n) => Ok(n),
+			None => Ok(crate::DEFAULT_FAILOVER_ATTEMPTS),
+		}
+	}
+
+	pub fn get_hooks(&self, cnf: &Config) -> Result<Vec<Arc<hooks::Hook>>, Error> {
 		let mut res = vec![];
 		for name in self.hooks.iter() {
 			let mut h = cnf.get_hook(name)?;
@@ -592,6 +1819,227 @@ impl Certificate {
 			}
 		}
 	}
+
+	pub fn get_renew_adaptive_factor(&self, cnf: &Config) -> Result<f64, Error> {
+		match self.renew_adaptive_factor {
+			Some(f) => parse_adaptive_factor(f),
+			None => {
+				let endpoint = self.do_get_endpoint(cnf)?;
+				endpoint.get_renew_adaptive_factor(cnf)
+			}
+		}
+	}
+
+	/// Return the renewal threshold. Since it conflicts with the renewal delay, it is only
+	/// inherited from the endpoint if this certificate sets neither.
+	pub fn get_renew_at(&self, cnf: &Config) -> Result<Option<f64>, Error> {
+		match (&self.renew_at, &self.renew_delay) {
+			(Some(p), _) => Ok(Some(parse_percentage(p)?)),
+			(None, Some(_)) => Ok(None),
+			(None, None) => {
+				let endpoint = self.do_get_endpoint(cnf)?;
+				endpoint.get_renew_at(cnf)
+			}
+		}
+	}
+
+	pub fn get_renew_jitter(&self, cnf: &Config) -> Result<Duration, Error> {
+		match &self.renew_jitter {
+			Some(d) => parse_duration(d),
+			None => {
+				let endpoint = self.do_get_endpoint(cnf)?;
+				endpoint.get_renew_jitter(cnf)
+			}
+		}
+	}
+
+	pub fn get_renew_window_critical(&self, cnf: &Config) -> Result<Duration, Error> {
+		match &self.renew_window_critical {
+			Some(d) => parse_duration(d),
+			None => {
+				let endpoint = self.do_get_endpoint(cnf)?;
+				endpoint.get_renew_window_critical(cnf)
+			}
+		}
+	}
+
+	pub fn get_renew_windows(&self, cnf: &Config) -> Result<Vec<RenewalWindow>, Error> {
+		match &self.renew_windows {
+			Some(_) => parse_renew_windows(&self.renew_windows),
+			None => {
+				let endpoint = self.do_get_endpoint(cnf)?;
+				endpoint.get_renew_windows(cnf)
+			}
+		}
+	}
+
+	pub fn get_timeout(&self, cnf: &Config) -> Result<Option<Duration>, Error> {
+		match &self.timeout {
+			Some(d) => Ok(Some(parse_duration(d)?)),
+			None => self.do_get_endpoint(cnf)?.get_timeout(),
+		}
+	}
+
+	pub fn get_challenge_ready_interval(&self) -> Result<Duration, Error> {
+		match &self.challenge_ready_interval {
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::from_secs(
+				crate::DEFAULT_CHALLENGE_READY_INTERVAL_SEC,
+			)),
+		}
+	}
+
+	pub fn get_challenge_ready_timeout(&self) -> Result<Duration, Error> {
+		match &self.challenge_ready_timeout {
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::from_secs(
+				crate::DEFAULT_CHALLENGE_READY_TIMEOUT_SEC,
+			)),
+		}
+	}
+
+	pub fn get_dns_cleanup_delay(&self) -> Result<Duration, Error> {
+		match &self.dns_cleanup_delay {
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::from_secs(crate::DEFAULT_DNS_CLEANUP_DELAY_SEC)),
+		}
+	}
+
+	pub fn get_dns_propagation_check(&self) -> bool {
+		self.dns_propagation_check
+			.unwrap_or(crate::DEFAULT_DNS_PROPAGATION_CHECK)
+	}
+
+	/// Return the addresses of the resolvers, which default to port 53.
+	pub fn get_dns_propagation_resolvers(&self) -> Result<Vec<SocketAddr>, Error> {
+		let mut res = vec![];
+		for resolver in self.dns_propagation_resolvers.iter() {
+			let addr = match resolver.parse::<SocketAddr>() {
+				Ok(addr) => addr,
+				Err(_) => {
+					let ip = resolver.parse::<IpAddr>().map_err(|_| {
+						Error::from(format!("{resolver}: invalid resolver address"))
+					})?;
+					SocketAddr::new(ip, crate::dns::DNS_PORT)
+				}
+			};
+			res.push(addr);
+		}
+		Ok(res)
+	}
+
+	pub fn get_http_self_check(&self) -> bool {
+		self.http_self_check
+			.unwrap_or(crate::DEFAULT_HTTP_SELF_CHECK)
+	}
+
+	pub fn get_http_self_check_address(&self) -> Result<Option<IpAddr>, Error> {
+		match &self.http_self_check_address {
+			Some(a) => {
+				let addr = a
+					.parse::<IpAddr>()
+					.map_err(|_| Error::from(format!("{a}: invalid IP address")))?;
+				Ok(Some(addr))
+			}
+			None => Ok(None),
+		}
+	}
+
+	pub fn get_deploy_check(&self) -> Result<Option<crate::certificate::DeployCheck>, Error> {
+		if self.deploy_check.is_empty() {
+			if self.deploy_check_delay.is_some() || self.deploy_check_server_name.is_some() {
+				return Err(
+					"deploy_check_delay and deploy_check_server_name require deploy_check".into(),
+				);
+			}
+			return Ok(None);
+		}
+		for address in self.deploy_check.iter() {
+			parse_tls_address(address).map_err(|e| e.prefix("deploy_check"))?;
+		}
+		let delay = match &self.deploy_check_delay {
+			Some(d) => parse_duration(d)?,
+			None => Duration::from_secs(crate::DEFAULT_DEPLOY_CHECK_DELAY_SEC),
+		};
+		Ok(Some(crate::certificate::DeployCheck {
+			addresses: self.deploy_check.clone(),
+			server_name: self.deploy_check_server_name.clone(),
+			delay,
+		}))
+	}
+
+	pub fn get_dns_propagation_timeout(&self) -> Result<Duration, Error> {
+		match &self.dns_propagation_timeout {
+			Some(d) => parse_duration(d),
+			None => Ok(Duration::from_secs(
+				crate::DEFAULT_DNS_PROPAGATION_TIMEOUT_SEC,
+			)),
+		}
+	}
+
+	pub fn get_preferred_chain(&self, cnf: &Config) -> Result<Option<String>, Error> {
+		match &self.preferred_chain {
+			Some(c) => Ok(Some(c.to_owned())),
+			None => Ok(self.do_get_endpoint(cnf)?.preferred_chain),
+		}
+	}
+
+	pub fn get_profile(&self, cnf: &Config) -> Result<Option<String>, Error> {
+		match &self.profile {
+			Some(p) => Ok(Some(p.to_owned())),
+			None => Ok(self.do_get_endpoint(cnf)?.profile),
+		}
+	}
+}
+
+/// Endpoint to request the certificate from when the main one fails, which is either its name or
+/// a table also specifying the account and the options to use on it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(remote = "Self")]
+#[serde(deny_unknown_fields)]
+pub struct FallbackEndpoint {
+	pub account: Option<String>,
+	pub endpoint: String,
+	pub preferred_chain: Option<String>,
+	pub profile: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for FallbackEndpoint {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		struct FallbackEndpointVisitor;
+
+		impl<'de> de::Visitor<'de> for FallbackEndpointVisitor {
+			type Value = FallbackEndpoint;
+
+			fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+				formatter.write_str("an endpoint name or a table")
+			}
+
+			fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+			where
+				E: de::Error,
+			{
+				Ok(FallbackEndpoint {
+					account: None,
+					endpoint: value.to_string(),
+					preferred_chain: None,
+					profile: None,
+				})
+			}
+
+			fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+			where
+				M: de::MapAccess<'de>,
+			{
+				FallbackEndpoint::deserialize(de::value::MapAccessDeserializer::new(map))
+			}
+		}
+
+		deserializer.deserialize_any(FallbackEndpointVisitor)
+	}
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -603,6 +2051,11 @@ pub struct Identifier {
 	#[serde(default)]
 	pub env: HashMap<String, String>,
 	pub ip: Option<String>,
+	pub solver: Option<String>,
+	pub webroot: Option<String>,
+	pub webroot_file_group: Option<String>,
+	pub webroot_file_mode: Option<u32>,
+	pub webroot_file_user: Option<String>,
 }
 
 impl<'de> Deserialize<'de> for Identifier {
@@ -621,6 +2074,19 @@ impl<'de> Deserialize<'de> for Identifier {
 				"one and only one of `dns` or `ip` must be specified",
 			));
 		}
+		if unchecked.webroot.is_some() && unchecked.solver.is_some() {
+			return Err(de::Error::custom(
+				"`webroot` and `solver` cannot be both specified",
+			));
+		}
+		let has_webroot_file_opts = unchecked.webroot_file_group.is_some()
+			|| unchecked.webroot_file_mode.is_some()
+			|| unchecked.webroot_file_user.is_some();
+		if has_webroot_file_opts && unchecked.webroot.is_none() {
+			return Err(de::Error::custom(
+				"the `webroot_file_*` fields require `webroot`",
+			));
+		}
 		Ok(unchecked)
 	}
 }
@@ -634,7 +2100,7 @@ impl fmt::Display for Identifier {
 }
 
 impl Identifier {
-	fn to_generic(&self) -> Result<crate::identifier::Identifier, Error> {
+	fn to_generic(&self, cnf: &Config) -> Result<crate::identifier::Identifier, Error> {
 		let (t, v) = match &self.dns {
 			Some(d) => (IdentifierType::Dns, d),
 			None => match &self.ip {
@@ -644,7 +2110,39 @@ impl Identifier {
 				}
 			},
 		};
-		crate::identifier::Identifier::new(t, v, &self.challenge, &self.env)
+		let mut id = crate::identifier::Identifier::new(t, v, &self.challenge, &self.env)?;
+		let solver = match (&self.solver, &self.webroot) {
+			(Some(name), _) => Some(cnf.get_solver(name)?),
+			(None, Some(path)) => {
+				let webroot = Webroot::new(
+					path,
+					self.webroot_file_mode,
+					self.webroot_file_user.as_deref(),
+					self.webroot_file_group.as_deref(),
+				);
+				Some(NamedSolver {
+					name: path.to_owned(),
+					solver_type: SolverType::Webroot(webroot),
+					rate_limits: vec![],
+				})
+			}
+			(None, None) => None,
+		};
+		if let Some(solver) = solver {
+			if !solver
+				.solver_type
+				.supported_challenges()
+				.contains(&id.challenge)
+			{
+				let msg = format!(
+					"{}: solver \"{}\" cannot be used with the {} challenge",
+					id.value, solver.name, id.challenge
+				);
+				return Err(msg.into());
+			}
+			id.solver = Some(solver);
+		}
+		Ok(id)
 	}
 }
 
@@ -690,20 +2188,45 @@ impl SubjectAttributes {
 	}
 }
 
-fn create_dir(path: &str) -> Result<(), Error> {
+fn create_dir(
+	path: &str,
+	mode: u32,
+	user: Option<String>,
+	group: Option<String>,
+) -> Result<(), Error> {
 	if Path::new(path).is_dir() {
-		Ok(())
-	} else {
-		fs::create_dir_all(path)?;
-		Ok(())
+		return Ok(());
 	}
+	info!("{path}: creating directory");
+	fs::create_dir_all(path).map_err(|e| Error::from(e).prefix(path))?;
+	// The permissions are set explicitly so they do not depend on the umask.
+	Current::set_mode(Path::new(path), mode).map_err(|e| e.prefix(path))?;
+	Current::set_owner(Path::new(path), user.as_deref(), group.as_deref())
+		.map_err(|e| e.prefix(path))?;
+	Ok(())
 }
 
 fn init_directories(config: &Config) -> Result<(), Error> {
-	create_dir(&config.get_account_dir())?;
+	create_dir(
+		&config.get_account_dir(),
+		config.get_account_dir_mode(),
+		config.get_account_dir_user(),
+		config.get_account_dir_group(),
+	)?;
 	for crt in config.certificate.iter() {
-		create_dir(&crt.get_crt_dir(config))?;
+		create_dir(
+			&crt.get_crt_dir(config),
+			config.get_cert_dir_mode(),
+			config.get_cert_dir_user(),
+			config.get_cert_dir_group(),
+		)?;
 	}
+	create_dir(
+		&config.get_state_dir(),
+		crate::DEFAULT_STATE_DIR_MODE,
+		None,
+		None,
+	)?;
 	Ok(())
 }
 
@@ -711,11 +2234,22 @@ fn get_cnf_path(from: &Path, file: &str) -> Result<Vec<PathBuf>, Error> {
 	let mut path = from.to_path_buf().canonicalize()?;
 	path.pop();
 	path.push(file);
+	// The files of an included directory are loaded, as those of a conf.d directory.
+	if path.is_dir() {
+		path.push("*.toml");
+	}
 	let err = format!("{path:?}: invalid UTF-8 path");
 	let raw_path = path.to_str().ok_or(err)?;
-	let g = glob(raw_path)?
+	// Hidden files, such as those left by text editors, are not matched by the wildcards.
+	let options = MatchOptions {
+		require_literal_leading_dot: true,
+		..MatchOptions::new()
+	};
+	let mut g = glob_with(raw_path, options)?
 		.filter_map(Result::ok)
 		.collect::<Vec<PathBuf>>();
+	// The files are loaded in alphabetical order, whatever the order of the directory entries.
+	g.sort();
 	if g.is_empty() {
 		log::warn!(
 			"pattern `{file}` (expanded as `{raw_path}`): no matching configuration file found"
@@ -724,6 +2258,19 @@ fn get_cnf_path(from: &Path, file: &str) -> Result<Vec<PathBuf>, Error> {
 	Ok(g)
 }
 
+/// Parse the content of a configuration file, without loading the files it includes.
+pub fn parse_cnf(contents: &str) -> Result<Config, Error> {
+	// Parsing the file as a table loses the position of the errors, hence it is only done if
+	// environment variables are referenced.
+	if contents.contains("${") {
+		let mut table: toml::Table = toml::from_str(contents)?;
+		interpolate_env(&mut table)?;
+		Ok(toml::Value::Table(table).try_into()?)
+	} else {
+		Ok(toml::from_str(contents)?)
+	}
+}
+
 fn read_cnf(path: &Path, loaded_files: &mut BTreeSet<PathBuf>) -> Result<Config, Error> {
 	let path = path
 		.canonicalize()
@@ -739,17 +2286,19 @@ fn read_cnf(path: &Path, loaded_files: &mut BTreeSet<PathBuf>) -> Result<Config,
 	let mut contents = String::new();
 	file.read_to_string(&mut contents)
 		.map_err(|e| Error::from(e).prefix(&path.display().to_string()))?;
-	let mut config: Config = toml::from_str(&contents)
-		.map_err(|e| Error::from(e).prefix(&path.display().to_string()))?;
+	let mut config = parse_cnf(&contents).map_err(|e| e.prefix(&path.display().to_string()))?;
 	for cnf_name in config.include.iter() {
 		for cnf_path in get_cnf_path(&path, cnf_name)? {
 			let mut add_cnf = read_cnf(&cnf_path, loaded_files)?;
 			config.endpoint.append(&mut add_cnf.endpoint);
 			config.rate_limit.append(&mut add_cnf.rate_limit);
+			config.solver.append(&mut add_cnf.solver);
 			config.hook.append(&mut add_cnf.hook);
 			config.group.append(&mut add_cnf.group);
 			config.account.append(&mut add_cnf.account);
 			config.certificate.append(&mut add_cnf.certificate);
+			config.watch.append(&mut add_cnf.watch);
+			config.webhook.append(&mut add_cnf.webhook);
 			if config.global.is_none() {
 				config.global = add_cnf.global;
 			} else if let Some(new_glob) = add_cnf.global {
@@ -759,12 +2308,72 @@ fn read_cnf(path: &Path, loaded_files: &mut BTreeSet<PathBuf>) -> Result<Config,
 					tmp_glob.certificates_directory,
 					new_glob.certificates_directory
 				);
+				set_cfg_attr!(tmp_glob.account_dir_mode, new_glob.account_dir_mode);
+				set_cfg_attr!(tmp_glob.account_dir_user, new_glob.account_dir_user);
+				set_cfg_attr!(tmp_glob.account_dir_group, new_glob.account_dir_group);
+				set_cfg_attr!(tmp_glob.cert_dir_mode, new_glob.cert_dir_mode);
+				set_cfg_attr!(tmp_glob.cert_dir_user, new_glob.cert_dir_user);
+				set_cfg_attr!(tmp_glob.cert_dir_group, new_glob.cert_dir_group);
 				set_cfg_attr!(tmp_glob.cert_file_mode, new_glob.cert_file_mode);
 				set_cfg_attr!(tmp_glob.cert_file_user, new_glob.cert_file_user);
 				set_cfg_attr!(tmp_glob.cert_file_group, new_glob.cert_file_group);
+				set_cfg_attr!(tmp_glob.log_format, new_glob.log_format);
+				set_cfg_attr!(tmp_glob.log_syslog, new_glob.log_syslog);
+				set_cfg_attr!(tmp_glob.auto_register, new_glob.auto_register);
+				set_cfg_attr!(tmp_glob.expiring_margin, new_glob.expiring_margin);
+				set_cfg_attr!(tmp_glob.hook_history_size, new_glob.hook_history_size);
+				set_cfg_attr!(tmp_glob.syslog_facility, new_glob.syslog_facility);
+				set_cfg_attr!(tmp_glob.syslog_tag, new_glob.syslog_tag);
 				set_cfg_attr!(tmp_glob.pk_file_mode, new_glob.pk_file_mode);
 				set_cfg_attr!(tmp_glob.pk_file_user, new_glob.pk_file_user);
 				set_cfg_attr!(tmp_glob.pk_file_group, new_glob.pk_file_group);
+				set_cfg_attr!(
+					tmp_glob.renew_adaptive_factor,
+					new_glob.renew_adaptive_factor
+				);
+				set_cfg_attr!(tmp_glob.renew_at, new_glob.renew_at);
+				set_cfg_attr!(tmp_glob.renew_jitter, new_glob.renew_jitter);
+				set_cfg_attr!(
+					tmp_glob.renew_window_critical,
+					new_glob.renew_window_critical
+				);
+				set_cfg_attr!(tmp_glob.renew_windows, new_glob.renew_windows);
+				set_cfg_attr!(tmp_glob.shutdown_timeout, new_glob.shutdown_timeout);
+				set_cfg_attr!(tmp_glob.state_directory, new_glob.state_directory);
+				set_cfg_attr!(tmp_glob.control_socket, new_glob.control_socket);
+				set_cfg_attr!(tmp_glob.control_socket_mode, new_glob.control_socket_mode);
+				set_cfg_attr!(tmp_glob.web_ui_certificate, new_glob.web_ui_certificate);
+				set_cfg_attr!(tmp_glob.web_ui_listen, new_glob.web_ui_listen);
+				set_cfg_attr!(tmp_glob.web_ui_password_file, new_glob.web_ui_password_file);
+				set_cfg_attr!(tmp_glob.web_ui_user, new_glob.web_ui_user);
+				set_cfg_attr!(
+					tmp_glob.email_expiration_margin,
+					new_glob.email_expiration_margin
+				);
+				set_cfg_attr!(tmp_glob.email_failures, new_glob.email_failures);
+				set_cfg_attr!(tmp_glob.email_from, new_glob.email_from);
+				set_cfg_attr!(tmp_glob.email_smtp_ca_file, new_glob.email_smtp_ca_file);
+				set_cfg_attr!(tmp_glob.email_smtp_host, new_glob.email_smtp_host);
+				set_cfg_attr!(
+					tmp_glob.email_smtp_password_env,
+					new_glob.email_smtp_password_env
+				);
+				set_cfg_attr!(
+					tmp_glob.email_smtp_password_file,
+					new_glob.email_smtp_password_file
+				);
+				set_cfg_attr!(tmp_glob.email_smtp_port, new_glob.email_smtp_port);
+				set_cfg_attr!(tmp_glob.email_smtp_tls, new_glob.email_smtp_tls);
+				set_cfg_attr!(tmp_glob.email_smtp_user, new_glob.email_smtp_user);
+				set_cfg_attr!(tmp_glob.email_to, new_glob.email_to);
+				set_cfg_attr!(
+					tmp_glob.duplicate_identifiers,
+					new_glob.duplicate_identifiers
+				);
+				set_cfg_attr!(
+					tmp_glob.max_parallel_renewals,
+					new_glob.max_parallel_renewals
+				);
 				config.global = Some(tmp_glob);
 			}
 		}
@@ -775,7 +2384,7 @@ fn read_cnf(path: &Path, loaded_files: &mut BTreeSet<PathBuf>) -> Result<Config,
 fn dispatch_global_env_vars(config: &mut Config) {
 	if let Some(glob) = &config.global {
 		if !glob.env.is_empty() {
-			for mut cert in config.certificate.iter_mut() {
+			for cert in config.certificate.iter_mut() {
 				let mut new_vars = glob.env.clone();
 				for (k, v) in cert.env.iter() {
 					new_vars.insert(k.to_string(), v.to_string());
@@ -786,11 +2395,19 @@ fn dispatch_global_env_vars(config: &mut Config) {
 	}
 }
 
-pub fn from_file(file_name: &str) -> Result<Config, Error> {
+/// Load the configuration file along with the files it includes, without creating any
+/// directory.
+pub fn read_files(file_name: &str) -> Result<Config, Error> {
 	let path = PathBuf::from(file_name);
 	let mut loaded_files = BTreeSet::new();
 	let mut config = read_cnf(&path, &mut loaded_files)?;
+	config.loaded_files = loaded_files.into_iter().collect();
 	dispatch_global_env_vars(&mut config);
+	Ok(config)
+}
+
+pub fn from_file(file_name: &str) -> Result<Config, Error> {
+	let config = read_files(file_name)?;
 	init_directories(&config)?;
 	Ok(config)
 }
@@ -0,0 +1,675 @@
+use acme_common::error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::sleep;
+
+#[cfg(unix)]
+use crate::events::{self, Event};
+#[cfg(unix)]
+use std::fs::{self, Permissions};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::sync::broadcast::{self, error::RecvError};
+#[cfg(unix)]
+use tokio::sync::Semaphore;
+
+/// Maximal size of a request, in bytes.
+#[cfg(unix)]
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+/// Maximal number of connections handled at the same time. The other ones wait in the
+/// listener's backlog until a connection is closed.
+pub const MAX_CONNECTIONS: usize = 64;
+const ACCEPT_MIN_DELAY_MS: u64 = 10;
+const ACCEPT_MAX_DELAY_MS: u64 = 5_000;
+
+/// Delay before accepting connections again once the listener failed to, which avoids spinning
+/// on a persistent error such as running out of file descriptors. It doubles on each consecutive
+/// failure.
+#[derive(Default)]
+pub struct AcceptBackoff {
+	delay: Duration,
+}
+
+impl AcceptBackoff {
+	pub fn reset(&mut self) {
+		self.delay = Duration::ZERO;
+	}
+
+	pub async fn wait(&mut self) {
+		self.delay = cmp::min(
+			cmp::max(self.delay * 2, Duration::from_millis(ACCEPT_MIN_DELAY_MS)),
+			Duration::from_millis(ACCEPT_MAX_DELAY_MS),
+		);
+		sleep(self.delay).await;
+	}
+}
+
+/// Latest version of the control protocol. In version 1, each connection carries a single
+/// request, encoded as a JSON object on one line, and receives a single response, encoded the
+/// same way. Version 2 allows several requests on the same connection, echoes the request's id in
+/// the response and wraps each streamed line in a chunk carrying this id.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Features of the protocol versions, as advertised in response to a hello request.
+const CAPABILITIES: &[(&str, u32)] = &[("request_id", 2), ("pipelining", 2), ("streaming", 1)];
+
+#[cfg(unix)]
+fn default_version() -> u32 {
+	1
+}
+
+#[cfg(unix)]
+/// A request along with its envelope. The envelope's fields are optional so version 1 clients,
+/// which only send the request, are still supported.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Message {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<Value>,
+	#[serde(default = "default_version")]
+	pub version: u32,
+	#[serde(flatten)]
+	pub request: Request,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+	/// Negotiate the protocol version, which is the highest version supported by both ends, and
+	/// list the available commands and capabilities.
+	Hello,
+	Renew {
+		#[serde(default)]
+		certificates: Vec<String>,
+	},
+	Deploy {
+		#[serde(default)]
+		certificates: Vec<String>,
+	},
+	Status {
+		#[serde(default)]
+		certificates: Vec<String>,
+	},
+	Hooks {
+		#[serde(default)]
+		certificates: Vec<String>,
+	},
+	/// Stream the events related to the certificates. Once the response has been sent, each
+	/// event is sent on its own line until the connection is closed.
+	Watch {
+		#[serde(default)]
+		certificates: Vec<String>,
+	},
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Response {
+	pub success: bool,
+	pub message: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data: Option<Value>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<Value>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub version: Option<u32>,
+}
+
+impl Response {
+	pub fn new(success: bool, message: &str) -> Self {
+		Self {
+			success,
+			message: message.to_string(),
+			data: None,
+			id: None,
+			version: None,
+		}
+	}
+
+	/// Response to a hello request sent by a client supporting the given protocol version.
+	pub fn hello(version: u32) -> Self {
+		let version = version.min(PROTOCOL_VERSION);
+		let capabilities: Vec<&str> = CAPABILITIES
+			.iter()
+			.filter(|(_, v)| *v <= version)
+			.map(|(c, _)| *c)
+			.collect();
+		let mut resp = Self::new(true, &format!("acmed {}", crate::APP_VERSION));
+		resp.data = Some(serde_json::json!({
+			"version": version,
+			"min_version": 1,
+			"commands": ["hello", "renew", "deploy", "status", "hooks", "watch"],
+			"capabilities": capabilities,
+		}));
+		resp
+	}
+
+	pub fn error(e: &Error) -> Self {
+		Self::new(false, &e.message)
+	}
+}
+
+#[cfg(unix)]
+/// A line streamed after the response of a version 2 request.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Chunk {
+	#[serde(default)]
+	pub id: Option<Value>,
+	pub event: Value,
+}
+
+pub type ControlRequest = (Request, oneshot::Sender<Response>);
+
+#[cfg(unix)]
+pub type Listener = std::os::unix::net::UnixListener;
+
+/// The control socket is a Unix socket, which is not available on this platform.
+#[cfg(not(unix))]
+pub enum Listener {}
+
+#[cfg(not(unix))]
+const UNSUPPORTED: &str = "the control socket is not supported on this platform";
+
+/// Create the control socket. This does not require any runtime, so it may be done before the
+/// privileges are dropped.
+#[cfg(unix)]
+pub fn bind(path: &str, mode: u32) -> Result<Listener, Error> {
+	let socket_path = Path::new(path);
+	// The socket left behind by a previous instance is replaced, but not any other file.
+	if let Ok(metadata) = fs::symlink_metadata(socket_path) {
+		if !metadata.file_type().is_socket() {
+			return Err(format!("{path}: file exists and is not a socket").into());
+		}
+		fs::remove_file(socket_path).map_err(|e| Error::from(e).prefix(path))?;
+	}
+	let listener = Listener::bind(socket_path).map_err(|e| Error::from(e).prefix(path))?;
+	fs::set_permissions(socket_path, Permissions::from_mode(mode))
+		.map_err(|e| Error::from(e).prefix(path))?;
+	listener.set_nonblocking(true)?;
+	Ok(listener)
+}
+
+/// Remove the control socket once the daemon stops listening on it.
+#[cfg(unix)]
+pub fn remove(path: &str) {
+	let socket_path = Path::new(path);
+	let is_socket = fs::symlink_metadata(socket_path)
+		.map(|m| m.file_type().is_socket())
+		.unwrap_or(false);
+	if is_socket {
+		if let Err(e) = fs::remove_file(socket_path) {
+			log::warn!("{path}: unable to remove the control socket: {e}");
+		}
+	}
+}
+
+/// Listen on the control socket. The requests are forwarded to the returned channel along with
+/// the channel the response should be sent to.
+#[cfg(unix)]
+pub fn listen(listener: Listener) -> Result<mpsc::Receiver<ControlRequest>, Error> {
+	let listener = UnixListener::from_std(listener)?;
+	let (tx, rx) = mpsc::channel(16);
+	let permits = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+	tokio::spawn(async move {
+		let mut backoff = AcceptBackoff::default();
+		loop {
+			let permit = match permits.clone().acquire_owned().await {
+				Ok(p) => p,
+				Err(_) => return,
+			};
+			match listener.accept().await {
+				Ok((stream, _)) => {
+					backoff.reset();
+					let tx = tx.clone();
+					tokio::spawn(async move {
+						if let Err(e) = handle_connection(stream, tx).await {
+							log::warn!("control socket: {e}");
+						}
+						drop(permit);
+					});
+				}
+				Err(e) => {
+					log::warn!("control socket: unable to accept a connection: {e}");
+					backoff.wait().await;
+				}
+			}
+		}
+	});
+	Ok(rx)
+}
+
+#[cfg(not(unix))]
+pub fn bind(_path: &str, _mode: u32) -> Result<Listener, Error> {
+	Err(UNSUPPORTED.into())
+}
+
+#[cfg(not(unix))]
+pub fn remove(_path: &str) {}
+
+#[cfg(not(unix))]
+pub fn listen(listener: Listener) -> Result<mpsc::Receiver<ControlRequest>, Error> {
+	match listener {}
+}
+
+#[cfg(unix)]
+async fn handle_connection(
+	stream: UnixStream,
+	tx: mpsc::Sender<ControlRequest>,
+) -> Result<(), Error> {
+	let (read, mut write) = stream.into_split();
+	let mut reader = BufReader::new(read);
+	loop {
+		let mut line = String::new();
+		let size = (&mut reader)
+			.take(MAX_REQUEST_SIZE)
+			.read_line(&mut line)
+			.await?;
+		if size == 0 {
+			return Ok(());
+		}
+		let message = match serde_json::from_str::<Message>(&line) {
+			Ok(m) => m,
+			Err(e) => {
+				let response = Response::new(false, &format!("invalid request: {e}"));
+				return write_line(&mut write, &response).await;
+			}
+		};
+		log::debug!("control socket: {message:?}");
+		// A hello request negotiates the version, hence it is accepted whatever the version
+		// supported by the client, which may be newer than the daemon.
+		let is_hello = matches!(message.request, Request::Hello);
+		let version = if is_hello {
+			message.version.min(PROTOCOL_VERSION)
+		} else {
+			message.version
+		};
+		if version == 0 || version > PROTOCOL_VERSION {
+			let msg = format!(
+				"unsupported protocol version {}, the latest supported one is {PROTOCOL_VERSION}",
+				message.version
+			);
+			let mut response = Response::new(false, &msg);
+			response.id = message.id;
+			return write_line(&mut write, &response).await;
+		}
+		let is_watch = matches!(message.request, Request::Watch { .. });
+		// Subscribe before the request is processed so no event is missed.
+		let events = if is_watch {
+			Some(events::subscribe())
+		} else {
+			None
+		};
+		let mut response = match message.request {
+			Request::Hello => Response::hello(version),
+			request => {
+				let (resp_tx, resp_rx) = oneshot::channel();
+				match tx.send((request, resp_tx)).await {
+					Ok(_) => resp_rx
+						.await
+						.unwrap_or_else(|_| Response::new(false, "the request has been dropped")),
+					Err(_) => Response::new(false, "the daemon is shutting down"),
+				}
+			}
+		};
+		if version >= 2 {
+			response.id = message.id.clone();
+			response.version = Some(version);
+		}
+		write_line(&mut write, &response).await?;
+		if let (true, Some(events)) = (response.success, events) {
+			// The main event loop answers with the ids of the watched certificates.
+			let ids: Vec<String> = response
+				.data
+				.and_then(|d| serde_json::from_value(d).ok())
+				.unwrap_or_default();
+			let id = if version >= 2 { Some(message.id) } else { None };
+			return stream_events(&mut reader, &mut write, events, &ids, id).await;
+		}
+		if version < 2 {
+			return Ok(());
+		}
+	}
+}
+
+#[cfg(unix)]
+/// Send the events related to the certificates until the connection is closed. If the id of a
+/// version 2 request is given, each event is wrapped in a chunk.
+async fn stream_events<R, W>(
+	reader: &mut R,
+	write: &mut W,
+	mut events: broadcast::Receiver<Event>,
+	ids: &[String],
+	id: Option<Option<Value>>,
+) -> Result<(), Error>
+where
+	R: AsyncBufReadExt + Unpin,
+	W: AsyncWriteExt + Unpin,
+{
+	let mut closed = String::new();
+	loop {
+		let event = tokio::select! {
+			event = events.recv() => match event {
+				Ok(e) => {
+					let is_watched = match &e.certificate {
+						Some(id) => ids.contains(id),
+						None => true,
+					};
+					if !is_watched {
+						continue;
+					}
+					e
+				}
+				Err(RecvError::Lagged(nb)) => Event {
+					timestamp: acme_common::now(),
+					kind: "events_lost".to_string(),
+					certificate: None,
+					labels: Default::default(),
+					details: serde_json::json!({"count": nb}),
+				},
+				Err(RecvError::Closed) => return Ok(()),
+			},
+			// Once watching, the client does not send anything else, so this only returns once
+			// the connection is closed.
+			_ = reader.read_line(&mut closed) => return Ok(()),
+		};
+		match &id {
+			Some(id) => {
+				let chunk = Chunk {
+					id: id.clone(),
+					event: serde_json::to_value(&event)?,
+				};
+				write_line(write, &chunk).await?;
+			}
+			None => write_line(write, &event).await?,
+		}
+	}
+}
+
+#[cfg(unix)]
+async fn write_line<W, T>(writer: &mut W, value: &T) -> Result<(), Error>
+where
+	W: AsyncWriteExt + Unpin,
+	T: Serialize,
+{
+	let mut line = serde_json::to_string(value)?;
+	line.push('\n');
+	writer.write_all(line.as_bytes()).await?;
+	Ok(())
+}
+
+/// Send a request to the daemon listening on the control socket and return its response.
+pub async fn send(path: &str, request: &Request) -> Result<Response, Error> {
+	watch(path, request, |_| {}).await
+}
+
+/// Send a request to the daemon listening on the control socket and return its response. Every
+/// event received after the response, for a watch request, is passed to `on_event` until the
+/// connection is closed.
+///
+/// The protocol version is first negotiated using a hello request. Daemons only supporting the
+/// version 1 of the protocol close the connection after the hello request, or reject it if they
+/// predate it, hence the request is then sent on a new connection without any envelope being
+/// used by the daemon, in which case the events are not wrapped in chunks.
+#[cfg(unix)]
+pub async fn watch<F>(path: &str, request: &Request, mut on_event: F) -> Result<Response, Error>
+where
+	F: FnMut(&str),
+{
+	let (mut reader, mut write) = connect(path).await?;
+	let hello = Message {
+		id: Some(Value::from(0)),
+		version: PROTOCOL_VERSION,
+		request: Request::Hello,
+	};
+	write_line(&mut write, &hello).await?;
+	let hello = read_response(&mut reader, path).await?;
+	if matches!(request, Request::Hello) {
+		return Ok(hello);
+	}
+	let version = match hello.version {
+		Some(v) if hello.success => v,
+		_ => 1,
+	};
+	let (mut reader, mut write) = if version >= 2 {
+		(reader, write)
+	} else {
+		connect(path).await?
+	};
+	let message = Message {
+		id: Some(Value::from(1)),
+		version,
+		request: request.to_owned(),
+	};
+	write_line(&mut write, &message).await?;
+	let response = read_response(&mut reader, path).await?;
+	if response.success && matches!(request, Request::Watch { .. }) {
+		let is_chunked = response.version.unwrap_or(1) >= 2;
+		let mut line = String::new();
+		loop {
+			line.clear();
+			if reader.read_line(&mut line).await? == 0 {
+				break;
+			}
+			if is_chunked {
+				let chunk: Chunk = serde_json::from_str(&line)?;
+				on_event(&chunk.event.to_string());
+			} else {
+				on_event(line.trim_end());
+			}
+		}
+	}
+	Ok(response)
+}
+
+#[cfg(unix)]
+async fn connect(path: &str) -> Result<(BufReader<OwnedReadHalf>, OwnedWriteHalf), Error> {
+	let stream = UnixStream::connect(path)
+		.await
+		.map_err(|e| Error::from(e).prefix(path))?;
+	let (read, write) = stream.into_split();
+	Ok((BufReader::new(read), write))
+}
+
+#[cfg(unix)]
+async fn read_response<R>(reader: &mut R, path: &str) -> Result<Response, Error>
+where
+	R: AsyncBufReadExt + Unpin,
+{
+	let mut line = String::new();
+	reader.read_line(&mut line).await?;
+	if line.is_empty() {
+		return Err(format!("{path}: no response received").into());
+	}
+	Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+pub async fn watch<F>(_path: &str, _request: &Request, _on_event: F) -> Result<Response, Error>
+where
+	F: FnMut(&str),
+{
+	Err(UNSUPPORTED.into())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::{
+		bind, handle_connection, remove, ControlRequest, Message, Request, Response,
+		PROTOCOL_VERSION,
+	};
+	use crate::storage::TestDir;
+	use serde_json::Value;
+	use std::path::Path;
+	use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+	use tokio::net::UnixStream;
+	use tokio::sync::mpsc;
+
+	#[test]
+	fn test_bind() {
+		let tmp = TestDir::new("control");
+		let dir = tmp.path();
+		let file = dir.join("file").display().to_string();
+		std::fs::write(&file, "data").unwrap();
+		let socket = dir.join("socket").display().to_string();
+		let res = (
+			bind(&file, 0o600).is_err(),
+			bind(&socket, 0o600),
+			bind(&socket, 0o600),
+		);
+		remove(&file);
+		remove(&socket);
+		let exists = (Path::new(&file).exists(), Path::new(&socket).exists());
+		assert!(res.0);
+		assert!(res.1.is_ok());
+		assert!(res.2.is_ok());
+		assert_eq!(exists, (true, false));
+	}
+
+	#[test]
+	fn test_request() {
+		let req: Request =
+			serde_json::from_str(r#"{"command":"renew","certificates":["example.org"]}"#).unwrap();
+		assert_eq!(
+			req,
+			Request::Renew {
+				certificates: vec!["example.org".to_string()]
+			}
+		);
+		let req: Request = serde_json::from_str(r#"{"command":"status"}"#).unwrap();
+		assert_eq!(
+			req,
+			Request::Status {
+				certificates: vec![]
+			}
+		);
+		let req: Request = serde_json::from_str(r#"{"command":"hooks"}"#).unwrap();
+		assert_eq!(
+			req,
+			Request::Hooks {
+				certificates: vec![]
+			}
+		);
+		assert!(serde_json::from_str::<Request>(r#"{"command":"unknown"}"#).is_err());
+	}
+
+	#[test]
+	fn test_response() {
+		let resp = Response::new(true, "ok");
+		assert_eq!(
+			serde_json::to_string(&resp).unwrap(),
+			r#"{"success":true,"message":"ok"}"#
+		);
+	}
+
+	#[test]
+	fn test_message() {
+		let msg: Message = serde_json::from_str(r#"{"command":"status"}"#).unwrap();
+		assert_eq!(msg.id, None);
+		assert_eq!(msg.version, 1);
+		let msg: Message = serde_json::from_str(
+			r#"{"id":"a1","version":2,"command":"renew","certificates":["example.org"]}"#,
+		)
+		.unwrap();
+		assert_eq!(msg.id, Some(Value::from("a1")));
+		assert_eq!(msg.version, 2);
+		assert_eq!(
+			msg.request,
+			Request::Renew {
+				certificates: vec!["example.org".to_string()]
+			}
+		);
+	}
+
+	#[test]
+	fn test_hello() {
+		let data = Response::hello(1).data.unwrap();
+		assert_eq!(data["version"], 1);
+		assert_eq!(data["capabilities"], serde_json::json!(["streaming"]));
+		let data = Response::hello(PROTOCOL_VERSION + 1).data.unwrap();
+		assert_eq!(data["version"], PROTOCOL_VERSION);
+		assert_eq!(
+			data["capabilities"],
+			serde_json::json!(["request_id", "pipelining", "streaming"])
+		);
+	}
+
+	#[tokio::test]
+	async fn test_pipelining() {
+		let (client, server) = UnixStream::pair().unwrap();
+		let (tx, mut rx) = mpsc::channel::<ControlRequest>(1);
+		tokio::spawn(async move {
+			while let Some((_, reply)) = rx.recv().await {
+				let _ = reply.send(Response::new(true, "ok"));
+			}
+		});
+		tokio::spawn(handle_connection(server, tx));
+		let (read, mut write) = client.into_split();
+		let mut reader = BufReader::new(read);
+		let mut line = String::new();
+		for id in 1..=2 {
+			let req = format!("{{\"id\":{id},\"version\":2,\"command\":\"status\"}}\n");
+			write.write_all(req.as_bytes()).await.unwrap();
+			line.clear();
+			reader.read_line(&mut line).await.unwrap();
+			let resp: Response = serde_json::from_str(&line).unwrap();
+			assert!(resp.success);
+			assert_eq!(resp.id, Some(Value::from(id)));
+			assert_eq!(resp.version, Some(2));
+		}
+		// A newer client is downgraded to the latest version supported by the daemon.
+		write
+			.write_all(b"{\"id\":3,\"version\":9,\"command\":\"hello\"}\n")
+			.await
+			.unwrap();
+		line.clear();
+		reader.read_line(&mut line).await.unwrap();
+		let resp: Response = serde_json::from_str(&line).unwrap();
+		assert!(resp.success);
+		assert_eq!(resp.id, Some(Value::from(3)));
+		assert_eq!(resp.version, Some(PROTOCOL_VERSION));
+		assert_eq!(resp.data.unwrap()["version"], PROTOCOL_VERSION);
+		// Other requests must use a supported version.
+		write
+			.write_all(b"{\"id\":4,\"version\":9,\"command\":\"status\"}\n")
+			.await
+			.unwrap();
+		line.clear();
+		reader.read_line(&mut line).await.unwrap();
+		let resp: Response = serde_json::from_str(&line).unwrap();
+		assert!(!resp.success);
+		assert_eq!(resp.id, Some(Value::from(4)));
+		line.clear();
+		assert_eq!(reader.read_line(&mut line).await.unwrap(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_version_1_hello() {
+		let (client, server) = UnixStream::pair().unwrap();
+		let (tx, _rx) = mpsc::channel::<ControlRequest>(1);
+		tokio::spawn(handle_connection(server, tx));
+		let (read, mut write) = client.into_split();
+		let mut reader = BufReader::new(read);
+		write.write_all(b"{\"command\":\"hello\"}\n").await.unwrap();
+		let mut line = String::new();
+		reader.read_line(&mut line).await.unwrap();
+		let resp: Response = serde_json::from_str(&line).unwrap();
+		assert!(resp.success);
+		assert_eq!(resp.version, None);
+		assert_eq!(resp.data.unwrap()["version"], 1);
+		line.clear();
+		assert_eq!(reader.read_line(&mut line).await.unwrap(), 0);
+	}
+}
@@ -0,0 +1,416 @@
+use acme_common::error::{Error, ErrorKind};
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+pub const DNS_PORT: u16 = 53;
+pub const QUERY_TIMEOUT_SEC: u64 = 5;
+pub const MAX_CNAME_DEPTH: usize = 8;
+pub const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+const MAX_UDP_SIZE: usize = 4096;
+const MAX_NAME_POINTERS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordType {
+	A,
+	Ns,
+	Cname,
+	Txt,
+	Aaaa,
+	Caa,
+}
+
+impl RecordType {
+	pub fn code(&self) -> u16 {
+		match self {
+			RecordType::A => 1,
+			RecordType::Ns => 2,
+			RecordType::Cname => 5,
+			RecordType::Txt => 16,
+			RecordType::Aaaa => 28,
+			RecordType::Caa => 257,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaaRecord {
+	pub critical: bool,
+	pub tag: String,
+	pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordData {
+	Addr(IpAddr),
+	Caa(CaaRecord),
+	Cname(String),
+	Ns(String),
+	Txt(String),
+	Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+	pub name: String,
+	pub data: RecordData,
+}
+
+/// Return the addresses of the nameservers of the system's resolver configuration, in order.
+pub fn get_system_nameservers() -> Result<Vec<SocketAddr>, Error> {
+	let conf =
+		std::fs::read_to_string(RESOLV_CONF).map_err(|e| Error::from(e).prefix(RESOLV_CONF))?;
+	let nameservers = parse_resolv_conf(&conf);
+	if nameservers.is_empty() {
+		return Err(format!("{RESOLV_CONF}: no usable nameserver").into());
+	}
+	Ok(nameservers)
+}
+
+fn parse_resolv_conf(conf: &str) -> Vec<SocketAddr> {
+	conf.lines()
+		.filter_map(|line| {
+			let mut parts = line.split_whitespace();
+			match parts.next() {
+				Some("nameserver") => parts.next()?.parse::<IpAddr>().ok(),
+				_ => None,
+			}
+		})
+		.map(|ip| SocketAddr::new(ip, DNS_PORT))
+		.collect()
+}
+
+/// Send a recursive query to each of the resolvers in turn, as the system's resolver does, until
+/// one of them answers. The error of the last resolver is returned if none does.
+pub async fn query_resolvers(
+	resolvers: &[SocketAddr],
+	name: &str,
+	rr_type: RecordType,
+) -> Result<Vec<Record>, Error> {
+	let mut last_error: Error = "no resolver to query".into();
+	for resolver in resolvers.iter() {
+		match query(*resolver, name, rr_type, true).await {
+			Ok(records) => return Ok(records),
+			Err(e) => last_error = e,
+		}
+	}
+	Err(last_error)
+}
+
+/// Query the nameserver and return the records of the answer section. A non-existent domain has
+/// no records. Recursion should not be desired when querying an authoritative nameserver.
+pub async fn query(
+	nameserver: SocketAddr,
+	name: &str,
+	rr_type: RecordType,
+	recursion_desired: bool,
+) -> Result<Vec<Record>, Error> {
+	let id = rand::random::<u16>();
+	let query = build_query(id, name, rr_type, recursion_desired)?;
+	let timeout = Duration::from_secs(QUERY_TIMEOUT_SEC);
+	let bind_addr = match nameserver {
+		SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+		SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+	};
+	let buf = tokio::time::timeout(timeout, query_udp(bind_addr, nameserver, &query))
+		.await
+		.map_err(|_| no_answer_error(nameserver))?
+		.map_err(|e| network_error(nameserver, e))?;
+	match parse_response(id, &buf)? {
+		Some(records) => Ok(records),
+		None => {
+			// The response has been truncated, hence the query is sent again over TCP.
+			let data = tokio::time::timeout(timeout, query_tcp(nameserver, &query))
+				.await
+				.map_err(|_| no_answer_error(nameserver))??;
+			parse_response(id, &data)?.ok_or_else(|| "truncated DNS response over TCP".into())
+		}
+	}
+}
+
+/// Error returned when the nameserver does not answer before the timeout.
+pub fn no_answer_error(nameserver: SocketAddr) -> Error {
+	Error::new(ErrorKind::Http, &format!("{nameserver}: no answer in time"))
+}
+
+/// The socket errors are network errors, which are usually temporary, rather than file ones.
+fn network_error(nameserver: SocketAddr, error: std::io::Error) -> Error {
+	Error::from(error)
+		.with_kind(ErrorKind::Http)
+		.prefix(&nameserver.to_string())
+}
+
+async fn query_udp(
+	bind_addr: SocketAddr,
+	nameserver: SocketAddr,
+	query: &[u8],
+) -> std::io::Result<Vec<u8>> {
+	let socket = UdpSocket::bind(bind_addr).await?;
+	socket.connect(nameserver).await?;
+	socket.send(query).await?;
+	let mut buf = vec![0; MAX_UDP_SIZE];
+	let len = socket.recv(&mut buf).await?;
+	buf.truncate(len);
+	Ok(buf)
+}
+
+pub async fn query_tcp(nameserver: SocketAddr, query: &[u8]) -> Result<Vec<u8>, Error> {
+	send_tcp(nameserver, query)
+		.await
+		.map_err(|e| network_error(nameserver, e))
+}
+
+async fn send_tcp(nameserver: SocketAddr, query: &[u8]) -> std::io::Result<Vec<u8>> {
+	let mut stream = TcpStream::connect(nameserver).await?;
+	let mut msg = (query.len() as u16).to_be_bytes().to_vec();
+	msg.extend_from_slice(query);
+	stream.write_all(&msg).await?;
+	let len = stream.read_u16().await?;
+	let mut data = vec![0; len as usize];
+	stream.read_exact(&mut data).await?;
+	Ok(data)
+}
+
+fn build_query(
+	id: u16,
+	name: &str,
+	rr_type: RecordType,
+	recursion_desired: bool,
+) -> Result<Vec<u8>, Error> {
+	let mut query = Vec::with_capacity(12 + name.len() + 6);
+	query.extend_from_slice(&id.to_be_bytes());
+	// Standard query with a single question.
+	query.push(if recursion_desired { 0x01 } else { 0x00 });
+	query.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+	encode_name(&mut query, name)?;
+	query.extend_from_slice(&rr_type.code().to_be_bytes());
+	query.extend_from_slice(&CLASS_IN.to_be_bytes());
+	Ok(query)
+}
+
+/// Append the name in the uncompressed wire format.
+pub fn encode_name(buf: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+	for label in name.split('.').filter(|l| !l.is_empty()) {
+		if label.len() > 63 {
+			return Err(format!("{name}: invalid domain name").into());
+		}
+		buf.push(label.len() as u8);
+		buf.extend_from_slice(label.as_bytes());
+	}
+	buf.push(0);
+	Ok(())
+}
+
+/// Extract the records of the answer section from a DNS response, or return `None` if the
+/// response is truncated.
+fn parse_response(id: u16, data: &[u8]) -> Result<Option<Vec<Record>>, Error> {
+	let err = || Error::from("invalid DNS response");
+	let read_u16 = |pos: usize| -> Result<u16, Error> {
+		let b = data.get(pos..pos + 2).ok_or_else(err)?;
+		Ok(u16::from_be_bytes([b[0], b[1]]))
+	};
+	if read_u16(0)? != id {
+		return Err("the DNS response does not match the query".into());
+	}
+	let flags = read_u16(2)?;
+	if flags & 0x8000 == 0 {
+		return Err(err());
+	}
+	if flags & 0x0200 != 0 {
+		return Ok(None);
+	}
+	match flags & 0x000f {
+		0 => {}
+		RCODE_NXDOMAIN => return Ok(Some(vec![])),
+		rcode => return Err(format!("the nameserver returned the error code {rcode}").into()),
+	}
+	let qd_count = read_u16(4)?;
+	let an_count = read_u16(6)?;
+	let mut pos = 12;
+	for _ in 0..qd_count {
+		pos = read_name(data, pos).ok_or_else(err)?.1 + 4;
+	}
+	let mut records = vec![];
+	for _ in 0..an_count {
+		let (name, next) = read_name(data, pos).ok_or_else(err)?;
+		pos = next;
+		let rr_type = read_u16(pos)?;
+		let rd_len = read_u16(pos + 8)? as usize;
+		pos += 10;
+		let rdata = data.get(pos..pos + rd_len).ok_or_else(err)?;
+		let record_data = match rr_type {
+			1 => {
+				let addr: [u8; 4] = rdata.try_into().map_err(|_| err())?;
+				RecordData::Addr(IpAddr::from(addr))
+			}
+			28 => {
+				let addr: [u8; 16] = rdata.try_into().map_err(|_| err())?;
+				RecordData::Addr(IpAddr::from(addr))
+			}
+			2 => RecordData::Ns(read_name(data, pos).ok_or_else(err)?.0),
+			5 => RecordData::Cname(read_name(data, pos).ok_or_else(err)?.0),
+			16 => RecordData::Txt(parse_txt(rdata).ok_or_else(err)?),
+			257 => RecordData::Caa(parse_caa(rdata).ok_or_else(err)?),
+			_ => RecordData::Other,
+		};
+		pos += rd_len;
+		records.push(Record {
+			name,
+			data: record_data,
+		});
+	}
+	Ok(Some(records))
+}
+
+/// Return the lowercase domain name starting at `pos` and the position following it.
+pub fn read_name(data: &[u8], mut pos: usize) -> Option<(String, usize)> {
+	let mut labels = vec![];
+	let mut end = None;
+	let mut nb_pointers = 0;
+	loop {
+		let len = *data.get(pos)? as usize;
+		if len & 0xc0 == 0xc0 {
+			nb_pointers += 1;
+			if nb_pointers > MAX_NAME_POINTERS {
+				return None;
+			}
+			let target = ((len & 0x3f) << 8) | *data.get(pos + 1)? as usize;
+			end.get_or_insert(pos + 2);
+			pos = target;
+			continue;
+		}
+		pos += 1;
+		if len == 0 {
+			break;
+		}
+		let label = data.get(pos..pos + len)?;
+		labels.push(String::from_utf8_lossy(label).to_lowercase());
+		pos += len;
+	}
+	Some((labels.join("."), end.unwrap_or(pos)))
+}
+
+/// A TXT record is made of several character strings, which are concatenated.
+fn parse_txt(rdata: &[u8]) -> Option<String> {
+	let mut txt = Vec::with_capacity(rdata.len());
+	let mut pos = 0;
+	while pos < rdata.len() {
+		let len = rdata[pos] as usize;
+		txt.extend_from_slice(rdata.get(pos + 1..pos + 1 + len)?);
+		pos += 1 + len;
+	}
+	Some(String::from_utf8_lossy(&txt).to_string())
+}
+
+fn parse_caa(rdata: &[u8]) -> Option<CaaRecord> {
+	let (flags, tag_len) = match rdata {
+		[f, l, ..] => (*f, *l as usize),
+		_ => return None,
+	};
+	let tag = rdata.get(2..2 + tag_len)?;
+	let value = &rdata[2 + tag_len..];
+	Some(CaaRecord {
+		critical: flags & 0x80 != 0,
+		tag: String::from_utf8_lossy(tag).to_lowercase(),
+		value: String::from_utf8_lossy(value).to_string(),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		build_query, network_error, parse_resolv_conf, parse_response, CaaRecord, Record,
+		RecordData, RecordType,
+	};
+	use acme_common::error::ErrorKind;
+
+	#[test]
+	fn test_parse_response() {
+		let mut data = build_query(0x1234, "Example.org", RecordType::Caa, true).unwrap();
+		data[2] = 0x81;
+		data[3] = 0x80;
+		data[7] = 3;
+		// CAA record using a compressed name.
+		data.extend_from_slice(&[0xc0, 0x0c, 0x01, 0x01, 0x00, 0x01, 0, 0, 0x0e, 0x10, 0, 22]);
+		data.extend_from_slice(b"\x00\x05issueletsencrypt.org");
+		// CNAME record pointing to a name prefixed with a label.
+		data.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0, 0, 0x0e, 0x10, 0, 6]);
+		data.extend_from_slice(&[0x03, b'w', b'w', b'w', 0xc0, 0x0c]);
+		// TXT record made of two character strings.
+		data.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x10, 0x00, 0x01, 0, 0, 0x0e, 0x10, 0, 6]);
+		data.extend_from_slice(b"\x02ab\x02cd");
+		let records = parse_response(0x1234, &data).unwrap().unwrap();
+		let expected = vec![
+			Record {
+				name: "example.org".to_string(),
+				data: RecordData::Caa(CaaRecord {
+					critical: false,
+					tag: "issue".to_string(),
+					value: "letsencrypt.org".to_string(),
+				}),
+			},
+			Record {
+				name: "example.org".to_string(),
+				data: RecordData::Cname("www.example.org".to_string()),
+			},
+			Record {
+				name: "example.org".to_string(),
+				data: RecordData::Txt("abcd".to_string()),
+			},
+		];
+		assert_eq!(records, expected);
+		assert!(parse_response(0x4321, &data).is_err());
+		data[2] = 0x83;
+		assert!(parse_response(0x1234, &data).unwrap().is_none());
+		data[2] = 0x81;
+		data[3] = 0x83;
+		assert!(parse_response(0x1234, &data).unwrap().unwrap().is_empty());
+		data[3] = 0x82;
+		assert!(parse_response(0x1234, &data).is_err());
+		data[3] = 0x80;
+		assert!(parse_response(0x1234, &data[..data.len() - 3]).is_err());
+	}
+
+	#[test]
+	fn test_name_pointer_loop() {
+		let mut data = build_query(0x1234, "example.org", RecordType::A, false).unwrap();
+		assert_eq!(data[2], 0x00);
+		data[2] = 0x80;
+		data[7] = 1;
+		let pos = data.len() as u8;
+		data.extend_from_slice(&[0xc0, pos, 0x00, 0x01, 0x00, 0x01, 0, 0, 0x0e, 0x10, 0, 4]);
+		data.extend_from_slice(&[192, 0, 2, 1]);
+		assert!(parse_response(0x1234, &data).is_err());
+	}
+
+	#[test]
+	fn test_network_error() {
+		let nameserver = "192.0.2.53:53".parse().unwrap();
+		let e = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+		let e = network_error(nameserver, e);
+		assert_eq!(e.kind, ErrorKind::Http);
+		assert!(e.message.starts_with("192.0.2.53:53: "));
+		assert!(e.is_transient());
+	}
+
+	#[test]
+	fn test_parse_resolv_conf() {
+		let conf = "# comment\nsearch example.org\nnameserver fe80::1%eth0\nnameserver 192.0.2.1\nnameserver 2001:db8::53\n";
+		assert_eq!(
+			parse_resolv_conf(conf),
+			vec![
+				"192.0.2.1:53".parse().unwrap(),
+				"[2001:db8::53]:53".parse().unwrap()
+			]
+		);
+		assert_eq!(
+			parse_resolv_conf("nameserver ::1"),
+			vec!["[::1]:53".parse().unwrap()]
+		);
+		assert!(parse_resolv_conf("search example.org").is_empty());
+	}
+}
@@ -0,0 +1,188 @@
+use crate::certificate::Certificate;
+use crate::dns::{self, RecordData, RecordType, DNS_PORT, MAX_CNAME_DEPTH};
+use crate::dns_provider::get_record_name;
+use crate::logs::HasLogger;
+use acme_common::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+const CHECK_INTERVAL_SEC: u64 = 10;
+
+/// Wait until the authoritative nameservers of the identifier's zone, as well as the additional
+/// resolvers, return the dns-01 challenge's proof in the `_acme-challenge` TXT record.
+pub async fn wait_for_propagation(
+	cert: &Certificate,
+	identifier: &str,
+	proof: &str,
+) -> Result<(), Error> {
+	let resolvers = dns::get_system_nameservers()?;
+	let record_name = get_record_name(identifier);
+	let name = resolve_cname(&resolvers, &record_name).await?;
+	let mut servers: Vec<(SocketAddr, bool)> = get_authoritative_nameservers(&resolvers, &name)
+		.await?
+		.into_iter()
+		.map(|addr| (addr, false))
+		.collect();
+	servers.extend(
+		cert.dns_propagation_resolvers
+			.iter()
+			.map(|addr| (*addr, true)),
+	);
+	cert.debug(&format!(
+		"{record_name}: checking the propagation of the TXT record on {} nameservers",
+		servers.len()
+	));
+	let start = Instant::now();
+	loop {
+		let mut pending = vec![];
+		for (addr, recursion_desired) in servers.into_iter() {
+			match has_txt_record(addr, &name, proof, recursion_desired).await {
+				Ok(true) => {}
+				Ok(false) => pending.push((addr, recursion_desired)),
+				Err(e) => {
+					cert.debug(&e.prefix(&addr.to_string()).message);
+					pending.push((addr, recursion_desired));
+				}
+			}
+		}
+		if pending.is_empty() {
+			cert.debug(&format!(
+				"{record_name}: the TXT record has been propagated"
+			));
+			return Ok(());
+		}
+		servers = pending;
+		if start.elapsed() >= cert.dns_propagation_timeout {
+			let lst = servers
+				.iter()
+				.map(|(addr, _)| addr.to_string())
+				.collect::<Vec<String>>()
+				.join(", ");
+			let msg = format!("{record_name}: the TXT record has not been propagated to {lst}");
+			return Err(msg.into());
+		}
+		tokio::time::sleep(Duration::from_secs(CHECK_INTERVAL_SEC)).await;
+	}
+}
+
+/// Return the name to which the record is delegated using CNAME records, which is the one to
+/// update. If the aliases cannot be resolved, the record's own name is returned.
+pub async fn get_delegated_name(cert: &Certificate, record_name: &str) -> String {
+	let target = match dns::get_system_nameservers() {
+		Ok(resolvers) => resolve_cname(&resolvers, record_name).await,
+		Err(e) => Err(e),
+	};
+	match target {
+		Ok(name) => {
+			if name != record_name {
+				cert.debug(&format!("{record_name}: delegated to {name}"));
+			}
+			name
+		}
+		Err(e) => {
+			let msg =
+				format!("{record_name}: unable to resolve the aliases, assuming no delegation");
+			cert.warn(&e.prefix(&msg).message);
+			record_name.to_string()
+		}
+	}
+}
+
+/// Follow the aliases of the record's name, since the challenge may be delegated to another zone.
+pub async fn resolve_cname(resolvers: &[SocketAddr], name: &str) -> Result<String, Error> {
+	let mut name = name.to_lowercase();
+	for _ in 0..MAX_CNAME_DEPTH {
+		let target = dns::query_resolvers(resolvers, &name, RecordType::Cname)
+			.await?
+			.into_iter()
+			.find_map(|r| match r.data {
+				RecordData::Cname(target) if r.name == name => Some(target),
+				_ => None,
+			});
+		match target {
+			Some(target) => name = target,
+			None => return Ok(name),
+		}
+	}
+	Err(format!("{name}: too many aliases").into())
+}
+
+/// Return the closest zone including the name, along with the hosts of its nameservers.
+pub async fn get_zone(
+	resolvers: &[SocketAddr],
+	name: &str,
+) -> Result<(String, Vec<String>), Error> {
+	let mut zone = name;
+	loop {
+		let hosts: Vec<String> = dns::query_resolvers(resolvers, zone, RecordType::Ns)
+			.await?
+			.into_iter()
+			.filter_map(|r| match r.data {
+				RecordData::Ns(host) if r.name == zone => Some(host),
+				_ => None,
+			})
+			.collect();
+		if !hosts.is_empty() {
+			return Ok((zone.to_string(), hosts));
+		}
+		match zone.split_once('.') {
+			Some((_, parent)) if !parent.is_empty() => zone = parent,
+			_ => return Err(format!("{name}: no authoritative nameserver found").into()),
+		}
+	}
+}
+
+/// Return the addresses of the nameservers of the closest zone including the name. IPv6
+/// addresses are only used for the nameservers having no IPv4 address.
+async fn get_authoritative_nameservers(
+	resolvers: &[SocketAddr],
+	name: &str,
+) -> Result<Vec<SocketAddr>, Error> {
+	let (zone, hosts) = get_zone(resolvers, name).await?;
+	let mut addresses = vec![];
+	for host in hosts.iter() {
+		let mut host_addr = get_addresses(resolvers, host, RecordType::A).await?;
+		if host_addr.is_empty() {
+			host_addr = get_addresses(resolvers, host, RecordType::Aaaa).await?;
+		}
+		addresses.extend(
+			host_addr
+				.into_iter()
+				.map(|ip| SocketAddr::new(ip, DNS_PORT)),
+		);
+	}
+	if addresses.is_empty() {
+		let msg = format!("{zone}: unable to resolve the address of the nameservers");
+		return Err(msg.into());
+	}
+	Ok(addresses)
+}
+
+async fn get_addresses(
+	resolvers: &[SocketAddr],
+	host: &str,
+	rr_type: RecordType,
+) -> Result<Vec<IpAddr>, Error> {
+	let addresses = dns::query_resolvers(resolvers, host, rr_type)
+		.await?
+		.into_iter()
+		.filter_map(|r| match r.data {
+			RecordData::Addr(ip) => Some(ip),
+			_ => None,
+		})
+		.collect();
+	Ok(addresses)
+}
+
+async fn has_txt_record(
+	nameserver: SocketAddr,
+	name: &str,
+	proof: &str,
+	recursion_desired: bool,
+) -> Result<bool, Error> {
+	let found = dns::query(nameserver, name, RecordType::Txt, recursion_desired)
+		.await?
+		.into_iter()
+		.any(|r| r.data == RecordData::Txt(proof.to_string()));
+	Ok(found)
+}
@@ -0,0 +1,320 @@
+mod cloudflare;
+mod gandi;
+mod hetzner;
+mod route53;
+
+use crate::certificate::Certificate;
+use crate::dns_propagation::get_delegated_name;
+use crate::http;
+use crate::logs::HasLogger;
+use acme_common::error::{Error, ErrorKind};
+use reqwest::{header, RequestBuilder, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::future::Future;
+
+/// TTL of the published TXT records. This is the lowest value accepted by every provider.
+const RECORD_TTL: u32 = 300;
+
+/// DNS providers whose API is natively supported in order to publish the dns-01 proofs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderType {
+	Cloudflare,
+	Gandi,
+	Hetzner,
+	Route53,
+}
+
+impl ProviderType {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"cloudflare" => Some(ProviderType::Cloudflare),
+			"gandi" => Some(ProviderType::Gandi),
+			"hetzner" => Some(ProviderType::Hetzner),
+			"route53" => Some(ProviderType::Route53),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for ProviderType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let s = match self {
+			ProviderType::Cloudflare => "cloudflare",
+			ProviderType::Gandi => "gandi",
+			ProviderType::Hetzner => "hetzner",
+			ProviderType::Route53 => "route53",
+		};
+		write!(f, "{s}")
+	}
+}
+
+/// Where a secret, such as an API token, is read from. It is read each time it is used, so it can
+/// be rotated without reloading the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Secret {
+	File(String),
+	Env(String),
+}
+
+impl Secret {
+	/// Return the secret from the `<name>_file` and `<name>_env` configuration fields, one and
+	/// only one of them being required.
+	pub fn from_config(name: &str, file: Option<&str>, env: Option<&str>) -> Result<Self, Error> {
+		match (file, env) {
+			(Some(f), None) => Ok(Secret::File(f.to_string())),
+			(None, Some(e)) => Ok(Secret::Env(e.to_string())),
+			_ => {
+				let msg =
+					format!("one and only one of {name}_file and {name}_env must be specified");
+				Err(msg.into())
+			}
+		}
+	}
+
+	pub fn get(&self) -> Result<String, Error> {
+		let (src, secret) = match self {
+			Secret::File(path) => {
+				let secret =
+					std::fs::read_to_string(path).map_err(|e| Error::from(e).prefix(path))?;
+				(path, secret)
+			}
+			Secret::Env(name) => (name, std::env::var(name).unwrap_or_default()),
+		};
+		let secret = secret.trim_end_matches(&['\r', '\n'][..]);
+		if secret.is_empty() {
+			return Err(Error::new(
+				ErrorKind::Config,
+				&format!("{src}: empty secret"),
+			));
+		}
+		Ok(secret.to_string())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsProvider {
+	pub provider_type: ProviderType,
+	pub api_token: Secret,
+	pub access_key_id: Option<String>,
+	pub zone: Option<String>,
+}
+
+impl DnsProvider {
+	pub fn new(
+		provider_type: ProviderType,
+		api_token: Secret,
+		access_key_id: Option<&str>,
+		zone: Option<&str>,
+	) -> Result<Self, Error> {
+		if provider_type == ProviderType::Route53 {
+			if access_key_id.is_none() {
+				return Err("the route53 solver requires an access_key_id".into());
+			}
+			if zone.is_none() {
+				return Err("the route53 solver requires the hosted zone ID as zone".into());
+			}
+		} else if access_key_id.is_some() {
+			let msg = format!("access_key_id: unused by the {provider_type} solver");
+			return Err(msg.into());
+		}
+		Ok(DnsProvider {
+			provider_type,
+			api_token,
+			access_key_id: access_key_id.map(|k| k.to_string()),
+			zone: zone.map(|z| z.trim_end_matches('.').to_lowercase()),
+		})
+	}
+
+	/// Publish the proofs in the identifiers' `_acme-challenge` TXT records, or in the records
+	/// they are delegated to. Other values of the records are kept, since both a domain and its
+	/// wildcard may be validated at the same time. The changes are sent in as few requests as the
+	/// provider's API allows.
+	pub async fn add_records(
+		&self,
+		cert: &Certificate,
+		records: &[(&str, &str)],
+	) -> Result<(), Error> {
+		let records = self.group_by_name(cert, records, "adding").await;
+		let token = self.api_token.get()?;
+		let zone = self.zone.as_deref();
+		let res = match self.provider_type {
+			ProviderType::Cloudflare => cloudflare::add_records(&token, zone, &records).await,
+			ProviderType::Gandi => gandi::add_records(&token, zone, &records).await,
+			ProviderType::Hetzner => hetzner::add_records(&token, zone, &records).await,
+			ProviderType::Route53 => route53::add_records(self, &token, &records).await,
+		};
+		res.map_err(|e| e.prefix(&self.provider_type.to_string()))
+	}
+
+	/// Remove the proofs from the identifiers' `_acme-challenge` TXT records.
+	pub async fn remove_records(
+		&self,
+		cert: &Certificate,
+		records: &[(&str, &str)],
+	) -> Result<(), Error> {
+		let records = self.group_by_name(cert, records, "removing").await;
+		let token = self.api_token.get()?;
+		let zone = self.zone.as_deref();
+		let res = match self.provider_type {
+			ProviderType::Cloudflare => cloudflare::remove_records(&token, zone, &records).await,
+			ProviderType::Gandi => gandi::remove_records(&token, zone, &records).await,
+			ProviderType::Hetzner => hetzner::remove_records(&token, zone, &records).await,
+			ProviderType::Route53 => route53::remove_records(self, &token, &records).await,
+		};
+		res.map_err(|e| e.prefix(&self.provider_type.to_string()))
+	}
+
+	/// Group the proofs by the name of the TXT record they are published in.
+	async fn group_by_name<'a>(
+		&self,
+		cert: &Certificate,
+		records: &[(&str, &'a str)],
+		action: &str,
+	) -> RecordProofs<'a> {
+		let mut ret = RecordProofs::new();
+		for (identifier, proof) in records {
+			let name = get_delegated_name(cert, &get_record_name(identifier)).await;
+			ret.entry(name).or_default().push(*proof);
+		}
+		for name in ret.keys() {
+			cert.debug(&format!(
+				"{name}: {action} the TXT record using the {} API",
+				self.provider_type
+			));
+		}
+		ret
+	}
+}
+
+/// Proofs to publish, or to remove, indexed by the name of their TXT record.
+type RecordProofs<'a> = BTreeMap<String, Vec<&'a str>>;
+
+/// Return the first of the record's candidate zones, or the configured zone, known to the
+/// provider. Lookups are cached, so a zone is only queried once for several records.
+async fn find_zone<T, F, Fut>(
+	cache: &mut HashMap<String, Option<T>>,
+	zone: Option<&str>,
+	name: &str,
+	mut lookup: F,
+) -> Result<T, Error>
+where
+	T: Clone,
+	F: FnMut(String) -> Fut,
+	Fut: Future<Output = Result<Option<T>, Error>>,
+{
+	let candidates = match zone {
+		Some(z) => vec![z],
+		None => get_candidate_zones(name),
+	};
+	for zone in candidates {
+		if !cache.contains_key(zone) {
+			let found = lookup(zone.to_string()).await?;
+			cache.insert(zone.to_string(), found);
+		}
+		if let Some(Some(found)) = cache.get(zone) {
+			return Ok(found.clone());
+		}
+	}
+	Err(format!("{name}: zone not found").into())
+}
+
+pub fn get_record_name(identifier: &str) -> String {
+	let domain = identifier.trim_start_matches("*.").trim_end_matches('.');
+	format!("_acme-challenge.{}", domain.to_lowercase())
+}
+
+/// Return the zones which may include the record, from the closest one to the furthest. Top
+/// level domains are excluded.
+fn get_candidate_zones(name: &str) -> Vec<&str> {
+	let mut zones = vec![];
+	let mut zone = name;
+	while let Some((_, parent)) = zone.split_once('.') {
+		if !parent.contains('.') {
+			break;
+		}
+		zones.push(parent);
+		zone = parent;
+	}
+	zones
+}
+
+/// Return the name of the record relative to its zone, `@` being the zone's apex.
+fn get_relative_name(name: &str, zone: &str) -> String {
+	match name.strip_suffix(zone) {
+		Some("") => "@".to_string(),
+		Some(sub) if sub.ends_with('.') => sub.trim_end_matches('.').to_string(),
+		_ => name.to_string(),
+	}
+}
+
+pub fn with_json(request: RequestBuilder, body: &serde_json::Value) -> RequestBuilder {
+	request
+		.header(header::CONTENT_TYPE, http::CONTENT_TYPE_JSON)
+		.body(body.to_string())
+}
+
+/// Send a request to a provider's API and return the response's body, or `None` if the
+/// requested resource does not exist.
+async fn send(request: RequestBuilder) -> Result<Option<String>, Error> {
+	let response = http::set_timeout(request).send().await?;
+	let status = response.status();
+	let url = response.url().to_string();
+	let body = response.text().await?;
+	log::trace!("DNS provider API response body: {body}");
+	if status == StatusCode::NOT_FOUND {
+		return Ok(None);
+	}
+	if !status.is_success() {
+		let msg = format!("{url}: HTTP error: {}: {}", status.as_u16(), body.trim());
+		return Err(Error::new(ErrorKind::Http, &msg));
+	}
+	Ok(Some(body))
+}
+
+/// Send a request to a provider's API and return the response's body, a missing resource being
+/// an error.
+pub async fn send_expect(request: RequestBuilder) -> Result<String, Error> {
+	send(request)
+		.await?
+		.ok_or_else(|| Error::new(ErrorKind::Http, "HTTP error: 404: resource not found"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_candidate_zones, get_record_name, get_relative_name};
+
+	#[test]
+	fn test_record_name() {
+		assert_eq!(
+			get_record_name("*.Example.org"),
+			"_acme-challenge.example.org"
+		);
+		assert_eq!(
+			get_record_name("www.example.org."),
+			"_acme-challenge.www.example.org"
+		);
+	}
+
+	#[test]
+	fn test_candidate_zones() {
+		assert_eq!(
+			get_candidate_zones("_acme-challenge.www.example.co.uk"),
+			vec!["www.example.co.uk", "example.co.uk", "co.uk"]
+		);
+		assert!(get_candidate_zones("_acme-challenge.org").is_empty());
+	}
+
+	#[test]
+	fn test_relative_name() {
+		assert_eq!(
+			get_relative_name("_acme-challenge.www.example.org", "example.org"),
+			"_acme-challenge.www"
+		);
+		assert_eq!(get_relative_name("example.org", "example.org"), "@");
+		assert_eq!(
+			get_relative_name("_acme-challenge.notexample.org", "example.org"),
+			"_acme-challenge.notexample.org"
+		);
+	}
+}
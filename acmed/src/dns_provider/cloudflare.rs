@@ -0,0 +1,112 @@
+use super::{find_zone, send_expect, with_json, RecordProofs, RECORD_TTL};
+use crate::http;
+use acme_common::error::Error;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+
+const API_URL: &str = "https://api.cloudflare.com/client/v4";
+
+#[derive(Deserialize)]
+struct ListResponse {
+	result: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Item {
+	id: String,
+	#[serde(default)]
+	content: String,
+}
+
+async fn list(client: &Client, token: &str, url: &str) -> Result<Vec<Item>, Error> {
+	let body = send_expect(client.get(url).bearer_auth(token)).await?;
+	let lst: ListResponse = serde_json::from_str(&body)?;
+	Ok(lst.result)
+}
+
+async fn get_zone_id(
+	client: &Client,
+	token: &str,
+	cache: &mut HashMap<String, Option<String>>,
+	zone: Option<&str>,
+	name: &str,
+) -> Result<String, Error> {
+	find_zone(cache, zone, name, |zone| async move {
+		let url = format!("{API_URL}/zones?name={zone}");
+		Ok(list(client, token, &url)
+			.await?
+			.into_iter()
+			.next()
+			.map(|i| i.id))
+	})
+	.await
+}
+
+/// Send the changes of a zone's records in a single request.
+async fn send_batch(
+	client: &Client,
+	token: &str,
+	zone_id: &str,
+	batch: &serde_json::Value,
+) -> Result<(), Error> {
+	let url = format!("{API_URL}/zones/{zone_id}/dns_records/batch");
+	send_expect(with_json(client.post(&url).bearer_auth(token), batch)).await?;
+	Ok(())
+}
+
+pub async fn add_records(
+	token: &str,
+	zone: Option<&str>,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = http::get_api_client()?;
+	let mut zones = HashMap::new();
+	let mut posts: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+	for (name, proofs) in records {
+		let zone_id = get_zone_id(&client, token, &mut zones, zone, name).await?;
+		posts
+			.entry(zone_id)
+			.or_default()
+			.extend(proofs.iter().map(|proof| {
+				json!({
+					"type": "TXT",
+					"name": name,
+					"content": proof,
+					"ttl": RECORD_TTL,
+				})
+			}));
+	}
+	for (zone_id, posts) in posts {
+		send_batch(&client, token, &zone_id, &json!({ "posts": posts })).await?;
+	}
+	Ok(())
+}
+
+pub async fn remove_records(
+	token: &str,
+	zone: Option<&str>,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = http::get_api_client()?;
+	let mut zones = HashMap::new();
+	let mut deletes: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+	for (name, proofs) in records {
+		let zone_id = get_zone_id(&client, token, &mut zones, zone, name).await?;
+		let url = format!("{API_URL}/zones/{zone_id}/dns_records?type=TXT&name={name}");
+		let ids: Vec<serde_json::Value> = list(&client, token, &url)
+			.await?
+			.into_iter()
+			.filter(|i| proofs.contains(&i.content.trim_matches('"')))
+			.map(|i| json!({ "id": i.id }))
+			.collect();
+		deletes.entry(zone_id).or_default().extend(ids);
+	}
+	for (zone_id, deletes) in deletes {
+		if !deletes.is_empty() {
+			send_batch(&client, token, &zone_id, &json!({ "deletes": deletes })).await?;
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,115 @@
+use super::{find_zone, get_relative_name, send, send_expect, with_json, RecordProofs, RECORD_TTL};
+use crate::http;
+use acme_common::error::Error;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+const API_URL: &str = "https://api.gandi.net/v5/livedns";
+
+#[derive(Deserialize)]
+struct RecordSet {
+	rrset_values: Vec<String>,
+}
+
+/// Return the URL of the record's TXT record set.
+async fn get_rrset_url(
+	client: &Client,
+	token: &str,
+	cache: &mut HashMap<String, Option<String>>,
+	zone: Option<&str>,
+	name: &str,
+) -> Result<String, Error> {
+	let zone = match zone {
+		Some(zone) => zone.to_string(),
+		None => {
+			find_zone(cache, None, name, |zone| async move {
+				let url = format!("{API_URL}/domains/{zone}");
+				let found = send(client.get(&url).bearer_auth(token)).await?;
+				Ok(found.map(|_| zone))
+			})
+			.await?
+		}
+	};
+	let rel_name = get_relative_name(name, &zone);
+	Ok(format!("{API_URL}/domains/{zone}/records/{rel_name}/TXT"))
+}
+
+async fn get_values(client: &Client, token: &str, url: &str) -> Result<Vec<String>, Error> {
+	match send(client.get(url).bearer_auth(token)).await? {
+		Some(body) => {
+			let rrset: RecordSet = serde_json::from_str(&body)?;
+			Ok(rrset.rrset_values)
+		}
+		None => Ok(vec![]),
+	}
+}
+
+async fn set_values(
+	client: &Client,
+	token: &str,
+	url: &str,
+	values: &[String],
+) -> Result<(), Error> {
+	let request = if values.is_empty() {
+		client.delete(url).bearer_auth(token)
+	} else {
+		let rrset = json!({
+			"rrset_values": values,
+			"rrset_ttl": RECORD_TTL,
+		});
+		with_json(client.put(url).bearer_auth(token), &rrset)
+	};
+	send_expect(request).await?;
+	Ok(())
+}
+
+fn is_proof(value: &str, proof: &str) -> bool {
+	value.trim_matches('"') == proof
+}
+
+pub async fn add_records(
+	token: &str,
+	zone: Option<&str>,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = http::get_api_client()?;
+	let mut zones = HashMap::new();
+	for (name, proofs) in records {
+		let url = get_rrset_url(&client, token, &mut zones, zone, name).await?;
+		let mut values = get_values(&client, token, &url).await?;
+		let len = values.len();
+		for proof in proofs {
+			if !values.iter().any(|v| is_proof(v, proof)) {
+				values.push(format!("\"{proof}\""));
+			}
+		}
+		if values.len() != len {
+			set_values(&client, token, &url, &values).await?;
+		}
+	}
+	Ok(())
+}
+
+pub async fn remove_records(
+	token: &str,
+	zone: Option<&str>,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = http::get_api_client()?;
+	let mut zones = HashMap::new();
+	for (name, proofs) in records {
+		let url = get_rrset_url(&client, token, &mut zones, zone, name).await?;
+		let values = get_values(&client, token, &url).await?;
+		let remaining: Vec<String> = values
+			.iter()
+			.filter(|v| !proofs.iter().any(|p| is_proof(v, p)))
+			.cloned()
+			.collect();
+		if remaining.len() != values.len() {
+			set_values(&client, token, &url, &remaining).await?;
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,118 @@
+use super::{find_zone, get_relative_name, send, send_expect, with_json, RecordProofs, RECORD_TTL};
+use crate::http;
+use acme_common::error::Error;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+
+const API_URL: &str = "https://dns.hetzner.com/api/v1";
+
+#[derive(Deserialize)]
+struct ZoneList {
+	zones: Vec<Zone>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Zone {
+	id: String,
+	name: String,
+}
+
+#[derive(Deserialize)]
+struct RecordList {
+	records: Vec<Record>,
+}
+
+#[derive(Deserialize)]
+struct Record {
+	id: String,
+	#[serde(rename = "type")]
+	record_type: String,
+	name: String,
+	value: String,
+}
+
+fn auth(request: RequestBuilder, token: &str) -> RequestBuilder {
+	request.header("Auth-API-Token", token)
+}
+
+/// Return the ID of the record's zone as well as the record's name relative to it.
+async fn get_zone(
+	client: &Client,
+	token: &str,
+	cache: &mut HashMap<String, Option<Zone>>,
+	zone: Option<&str>,
+	name: &str,
+) -> Result<(String, String), Error> {
+	let zone = find_zone(cache, zone, name, |zone| async move {
+		let url = format!("{API_URL}/zones?name={zone}");
+		match send(auth(client.get(&url), token)).await? {
+			Some(body) => {
+				let lst: ZoneList = serde_json::from_str(&body)?;
+				Ok(lst.zones.into_iter().find(|z| z.name == zone))
+			}
+			None => Ok(None),
+		}
+	})
+	.await?;
+	Ok((zone.id, get_relative_name(name, &zone.name)))
+}
+
+pub async fn add_records(
+	token: &str,
+	zone: Option<&str>,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = http::get_api_client()?;
+	let mut zones = HashMap::new();
+	let mut lst = vec![];
+	for (name, proofs) in records {
+		let (zone_id, rel_name) = get_zone(&client, token, &mut zones, zone, name).await?;
+		lst.extend(proofs.iter().map(|proof| {
+			json!({
+				"zone_id": zone_id,
+				"type": "TXT",
+				"name": rel_name,
+				"value": proof,
+				"ttl": RECORD_TTL,
+			})
+		}));
+	}
+	let url = format!("{API_URL}/records/bulk");
+	let body = json!({ "records": lst });
+	send_expect(with_json(auth(client.post(&url), token), &body)).await?;
+	Ok(())
+}
+
+pub async fn remove_records(
+	token: &str,
+	zone: Option<&str>,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = http::get_api_client()?;
+	let mut zones = HashMap::new();
+	let mut by_zone: BTreeMap<String, Vec<(String, &[&str])>> = BTreeMap::new();
+	for (name, proofs) in records {
+		let (zone_id, rel_name) = get_zone(&client, token, &mut zones, zone, name).await?;
+		by_zone
+			.entry(zone_id)
+			.or_default()
+			.push((rel_name, proofs.as_slice()));
+	}
+	for (zone_id, names) in by_zone {
+		let url = format!("{API_URL}/records?zone_id={zone_id}");
+		let body = send_expect(auth(client.get(&url), token)).await?;
+		let lst: RecordList = serde_json::from_str(&body)?;
+		for record in lst.records.iter().filter(|r| {
+			r.record_type == "TXT"
+				&& names.iter().any(|(rel_name, proofs)| {
+					r.name == *rel_name && proofs.contains(&r.value.trim_matches('"'))
+				})
+		}) {
+			let url = format!("{API_URL}/records/{}", record.id);
+			send_expect(auth(client.delete(&url), token)).await?;
+		}
+	}
+	Ok(())
+}
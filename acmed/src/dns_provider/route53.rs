@@ -0,0 +1,363 @@
+use super::{send_expect, DnsProvider, RecordProofs, RECORD_TTL};
+use crate::http;
+use acme_common::crypto::HashFunction;
+use acme_common::error::Error;
+use acme_common::system_now;
+use reqwest::Client;
+
+const API_HOST: &str = "route53.amazonaws.com";
+const API_VERSION: &str = "2013-04-01";
+const REGION: &str = "us-east-1";
+const SERVICE: &str = "route53";
+
+/// A request signed using the AWS Signature Version 4.
+struct SignedRequest<'a> {
+	method: &'a str,
+	path: &'a str,
+	query: &'a str,
+	payload: &'a str,
+}
+
+impl SignedRequest<'_> {
+	/// Return the values of the `X-Amz-Date` and `Authorization` headers.
+	fn sign(
+		&self,
+		access_key_id: &str,
+		secret: &str,
+		timestamp: u64,
+	) -> Result<(String, String), Error> {
+		let amz_date = format_amz_date(timestamp);
+		let date = &amz_date[..8];
+		let signed_headers = "host;x-amz-date";
+		let canonical_request = format!(
+			"{}\n{}\n{}\nhost:{API_HOST}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{}",
+			self.method,
+			self.path,
+			self.query,
+			to_hex(&HashFunction::Sha256.hash(self.payload.as_bytes()))
+		);
+		let scope = format!("{date}/{REGION}/{SERVICE}/aws4_request");
+		let string_to_sign = format!(
+			"AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+			to_hex(&HashFunction::Sha256.hash(canonical_request.as_bytes()))
+		);
+		let mut signature = format!("AWS4{secret}").into_bytes();
+		for data in [date, REGION, SERVICE, "aws4_request", &string_to_sign] {
+			signature = HashFunction::Sha256.hmac(&signature, data.as_bytes())?;
+		}
+		let authorization = format!(
+			"AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={}",
+			to_hex(&signature)
+		);
+		Ok((amz_date, authorization))
+	}
+
+	async fn send(
+		&self,
+		client: &Client,
+		access_key_id: &str,
+		secret: &str,
+	) -> Result<String, Error> {
+		let (amz_date, authorization) = self.sign(access_key_id, secret, system_now())?;
+		let mut url = format!("https://{API_HOST}{}", self.path);
+		if !self.query.is_empty() {
+			url += "?";
+			url += self.query;
+		}
+		let request = match self.method {
+			"POST" => client
+				.post(&url)
+				.header("Content-Type", "text/xml")
+				.body(self.payload.to_string()),
+			_ => client.get(&url),
+		};
+		let request = request
+			.header("X-Amz-Date", amz_date)
+			.header("Authorization", authorization);
+		send_expect(request).await
+	}
+}
+
+fn to_hex(data: &[u8]) -> String {
+	data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Format a timestamp as required by the `X-Amz-Date` header, e.g. `20150830T123600Z`.
+fn format_amz_date(timestamp: u64) -> String {
+	let days = (timestamp / 86400) as i64;
+	let secs = timestamp % 86400;
+	// Civil date from the days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719468;
+	let era = z / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+	format!(
+		"{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+		secs / 3600,
+		secs % 3600 / 60,
+		secs % 60
+	)
+}
+
+/// Return the TTL and the raw values of the TXT record set, as listed by the API.
+fn parse_record_set(xml: &str, name: &str) -> Option<(String, Vec<String>)> {
+	let rrset = get_element(xml, "ResourceRecordSet")?;
+	if get_element(rrset, "Name")? != name || get_element(rrset, "Type")? != "TXT" {
+		return None;
+	}
+	let ttl = get_element(rrset, "TTL")?.to_string();
+	let mut values = vec![];
+	let mut rest = rrset;
+	while let Some(value) = get_element(rest, "Value") {
+		values.push(value.to_string());
+		rest = rest
+			.split_once("</Value>")
+			.map(|(_, r)| r)
+			.unwrap_or_default();
+	}
+	Some((ttl, values))
+}
+
+/// Return the content of the first element having the given name.
+fn get_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+	let (_, content) = xml.split_once(&format!("<{name}>"))?;
+	let (content, _) = content.split_once(&format!("</{name}>"))?;
+	Some(content)
+}
+
+/// A change of a TXT record set.
+struct RecordSetChange {
+	action: &'static str,
+	name: String,
+	ttl: String,
+	values: Vec<String>,
+}
+
+fn get_change_batch(changes: &[RecordSetChange]) -> String {
+	let changes: String = changes
+		.iter()
+		.map(|c| {
+			let records: String = c
+				.values
+				.iter()
+				.map(|v| format!("<ResourceRecord><Value>{v}</Value></ResourceRecord>"))
+				.collect();
+			format!(
+				"<Change><Action>{}</Action>\
+				<ResourceRecordSet><Name>{}</Name><Type>TXT</Type><TTL>{}</TTL>\
+				<ResourceRecords>{records}</ResourceRecords></ResourceRecordSet></Change>",
+				c.action, c.name, c.ttl
+			)
+		})
+		.collect();
+	format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+		<ChangeResourceRecordSetsRequest xmlns=\"https://route53.amazonaws.com/doc/{API_VERSION}/\">\
+		<ChangeBatch><Changes>{changes}</Changes></ChangeBatch></ChangeResourceRecordSetsRequest>"
+	)
+}
+
+struct Route53Client<'a> {
+	client: Client,
+	access_key_id: &'a str,
+	secret: &'a str,
+	path: String,
+}
+
+impl<'a> Route53Client<'a> {
+	fn new(provider: &'a DnsProvider, secret: &'a str) -> Result<Self, Error> {
+		let zone_id = provider.zone.as_deref().unwrap_or_default();
+		let zone_id = zone_id.trim_start_matches("/hostedzone/");
+		Ok(Route53Client {
+			client: http::get_api_client()?,
+			access_key_id: provider.access_key_id.as_deref().unwrap_or_default(),
+			secret,
+			path: format!("/{API_VERSION}/hostedzone/{zone_id}/rrset"),
+		})
+	}
+
+	async fn get_record_set(&self, name: &str) -> Result<Option<(String, Vec<String>)>, Error> {
+		let query = format!("maxitems=1&name={name}&type=TXT");
+		let body = SignedRequest {
+			method: "GET",
+			path: &self.path,
+			query: &query,
+			payload: "",
+		}
+		.send(&self.client, self.access_key_id, self.secret)
+		.await?;
+		Ok(parse_record_set(&body, name))
+	}
+
+	/// Apply every change at once.
+	async fn change_record_sets(&self, changes: &[RecordSetChange]) -> Result<(), Error> {
+		if changes.is_empty() {
+			return Ok(());
+		}
+		let payload = get_change_batch(changes);
+		SignedRequest {
+			method: "POST",
+			path: &self.path,
+			query: "",
+			payload: &payload,
+		}
+		.send(&self.client, self.access_key_id, self.secret)
+		.await?;
+		Ok(())
+	}
+}
+
+pub async fn add_records(
+	provider: &DnsProvider,
+	secret: &str,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = Route53Client::new(provider, secret)?;
+	let mut changes = vec![];
+	for (name, proofs) in records {
+		let name = format!("{name}.");
+		let (ttl, mut values) = client
+			.get_record_set(&name)
+			.await?
+			.unwrap_or_else(|| (RECORD_TTL.to_string(), vec![]));
+		let len = values.len();
+		for proof in proofs {
+			let proof = format!("\"{proof}\"");
+			if !values.contains(&proof) {
+				values.push(proof);
+			}
+		}
+		if values.len() != len {
+			changes.push(RecordSetChange {
+				action: "UPSERT",
+				name,
+				ttl,
+				values,
+			});
+		}
+	}
+	client.change_record_sets(&changes).await
+}
+
+pub async fn remove_records(
+	provider: &DnsProvider,
+	secret: &str,
+	records: &RecordProofs<'_>,
+) -> Result<(), Error> {
+	let client = Route53Client::new(provider, secret)?;
+	let mut changes = vec![];
+	for (name, proofs) in records {
+		let name = format!("{name}.");
+		if let Some((ttl, values)) = client.get_record_set(&name).await? {
+			let remaining: Vec<String> = values
+				.iter()
+				.filter(|v| !proofs.iter().any(|p| v.trim_matches('"') == *p))
+				.cloned()
+				.collect();
+			if remaining.is_empty() {
+				changes.push(RecordSetChange {
+					action: "DELETE",
+					name,
+					ttl,
+					values,
+				});
+			} else if remaining.len() != values.len() {
+				changes.push(RecordSetChange {
+					action: "UPSERT",
+					name,
+					ttl,
+					values: remaining,
+				});
+			}
+		}
+	}
+	client.change_record_sets(&changes).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		format_amz_date, get_change_batch, parse_record_set, RecordSetChange, SignedRequest,
+	};
+
+	#[test]
+	fn test_format_amz_date() {
+		assert_eq!(format_amz_date(0), "19700101T000000Z");
+		assert_eq!(format_amz_date(1440938160), "20150830T123600Z");
+		assert_eq!(format_amz_date(1709210096), "20240229T123456Z");
+	}
+
+	#[test]
+	fn test_sign() {
+		let req = SignedRequest {
+			method: "GET",
+			path: "/2013-04-01/hostedzone/Z1D633PJN98FT9/rrset",
+			query: "maxitems=1&name=_acme-challenge.example.org.&type=TXT",
+			payload: "",
+		};
+		let (date, auth) = req
+			.sign(
+				"AKIDEXAMPLE",
+				"wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+				1440938160,
+			)
+			.unwrap();
+		assert_eq!(date, "20150830T123600Z");
+		assert_eq!(
+			auth,
+			"AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/route53/aws4_request, \
+			SignedHeaders=host;x-amz-date, \
+			Signature=d4e38ac9108bec13654ed891eff424afc1054c92d7c6f81acde8cace08551e20"
+		);
+	}
+
+	#[test]
+	fn test_record_set() {
+		let name = "_acme-challenge.example.org.";
+		let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+			<ListResourceRecordSetsResponse><ResourceRecordSets><ResourceRecordSet>\
+			<Name>_acme-challenge.example.org.</Name><Type>TXT</Type><TTL>60</TTL>\
+			<ResourceRecords><ResourceRecord><Value>\"a\"</Value></ResourceRecord>\
+			<ResourceRecord><Value>\"b\"</Value></ResourceRecord></ResourceRecords>\
+			</ResourceRecordSet></ResourceRecordSets><IsTruncated>false</IsTruncated>\
+			</ListResourceRecordSetsResponse>";
+		let (ttl, values) = parse_record_set(xml, name).unwrap();
+		assert_eq!(ttl, "60");
+		assert_eq!(values, vec!["\"a\"", "\"b\""]);
+		assert!(parse_record_set(xml, "example.org.").is_none());
+		let empty = "<ListResourceRecordSetsResponse><ResourceRecordSets>\
+			</ResourceRecordSets></ListResourceRecordSetsResponse>";
+		assert!(parse_record_set(empty, name).is_none());
+		let batch = get_change_batch(&[
+			RecordSetChange {
+				action: "DELETE",
+				name: name.to_string(),
+				ttl: "60".to_string(),
+				values: vec!["\"a\"".to_string()],
+			},
+			RecordSetChange {
+				action: "UPSERT",
+				name: "_acme-challenge.www.example.org.".to_string(),
+				ttl: "300".to_string(),
+				values: vec!["\"b\"".to_string(), "\"c\"".to_string()],
+			},
+		]);
+		assert!(batch.contains(
+			"<Changes><Change><Action>DELETE</Action><ResourceRecordSet><Name>_acme-challenge.example.org.</Name>"
+		));
+		assert!(batch.contains("<ResourceRecords><ResourceRecord><Value>\"a\"</Value></ResourceRecord></ResourceRecords>"));
+		assert!(batch.contains(
+			"</Change><Change><Action>UPSERT</Action><ResourceRecordSet><Name>_acme-challenge.www.example.org.</Name>"
+		));
+		assert!(batch
+			.contains("<Value>\"b\"</Value></ResourceRecord><ResourceRecord><Value>\"c\"</Value>"));
+		assert!(
+			batch.ends_with("</Change></Changes></ChangeBatch></ChangeResourceRecordSetsRequest>")
+		);
+	}
+}
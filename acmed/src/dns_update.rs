@@ -0,0 +1,483 @@
+use crate::certificate::Certificate;
+use crate::dns::{self, RecordType, CLASS_IN, QUERY_TIMEOUT_SEC};
+use crate::dns_propagation;
+use crate::dns_provider::{get_record_name, Secret};
+use crate::logs::HasLogger;
+use acme_common::crypto::HashFunction;
+use acme_common::error::{Error, ErrorKind};
+use acme_common::system_now;
+use base64::Engine;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+const OPCODE_UPDATE: u8 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const RECORD_TTL: u32 = 300;
+const TSIG_FUDGE: u16 = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+	HmacSha256,
+	HmacSha384,
+	HmacSha512,
+}
+
+impl TsigAlgorithm {
+	pub fn from_name(name: &str) -> Result<Self, Error> {
+		match name.to_lowercase().trim_end_matches('.') {
+			"hmac-sha256" => Ok(TsigAlgorithm::HmacSha256),
+			"hmac-sha384" => Ok(TsigAlgorithm::HmacSha384),
+			"hmac-sha512" => Ok(TsigAlgorithm::HmacSha512),
+			_ => Err(format!("{name}: unsupported TSIG algorithm").into()),
+		}
+	}
+
+	fn name(&self) -> &'static str {
+		match self {
+			TsigAlgorithm::HmacSha256 => "hmac-sha256",
+			TsigAlgorithm::HmacSha384 => "hmac-sha384",
+			TsigAlgorithm::HmacSha512 => "hmac-sha512",
+		}
+	}
+
+	fn hash_function(&self) -> HashFunction {
+		match self {
+			TsigAlgorithm::HmacSha256 => HashFunction::Sha256,
+			TsigAlgorithm::HmacSha384 => HashFunction::Sha384,
+			TsigAlgorithm::HmacSha512 => HashFunction::Sha512,
+		}
+	}
+}
+
+/// Key used to sign the updates, as specified in RFC 8945. The secret is encoded in base64, as
+/// generated by `tsig-keygen` or `keymgr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsigKey {
+	pub name: String,
+	pub algorithm: TsigAlgorithm,
+	pub secret: Secret,
+}
+
+impl TsigKey {
+	fn get_secret(&self) -> Result<Vec<u8>, Error> {
+		let engine = base64::engine::general_purpose::STANDARD;
+		engine.decode(self.secret.get()?.trim()).map_err(|e| {
+			let msg = format!("TSIG key \"{}\": invalid secret: {e}", self.name);
+			Error::new(ErrorKind::Config, &msg)
+		})
+	}
+}
+
+/// Publish the dns-01 proofs by sending dynamic updates, as specified in RFC 2136, to the primary
+/// nameserver of the zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsUpdate {
+	pub nameserver: SocketAddr,
+	pub zone: Option<String>,
+	pub key: Option<TsigKey>,
+}
+
+impl DnsUpdate {
+	/// The nameserver's port defaults to 53.
+	pub fn new(nameserver: &str, zone: Option<&str>, key: Option<TsigKey>) -> Result<Self, Error> {
+		let nameserver = match nameserver.parse::<SocketAddr>() {
+			Ok(addr) => addr,
+			Err(_) => {
+				let ip = nameserver.parse::<IpAddr>().map_err(|_| {
+					Error::from(format!("{nameserver}: invalid nameserver address"))
+				})?;
+				SocketAddr::new(ip, dns::DNS_PORT)
+			}
+		};
+		Ok(DnsUpdate {
+			nameserver,
+			zone: zone.map(|z| z.trim_end_matches('.').to_lowercase()),
+			key,
+		})
+	}
+
+	pub async fn add_record(
+		&self,
+		cert: &Certificate,
+		identifier: &str,
+		proof: &str,
+	) -> Result<(), Error> {
+		self.update(cert, identifier, proof, true).await
+	}
+
+	pub async fn remove_record(
+		&self,
+		cert: &Certificate,
+		identifier: &str,
+		proof: &str,
+	) -> Result<(), Error> {
+		self.update(cert, identifier, proof, false).await
+	}
+
+	async fn update(
+		&self,
+		cert: &Certificate,
+		identifier: &str,
+		proof: &str,
+		add: bool,
+	) -> Result<(), Error> {
+		let name = dns_propagation::get_delegated_name(cert, &get_record_name(identifier)).await;
+		let zone = match &self.zone {
+			Some(z) => z.to_owned(),
+			None => {
+				let resolvers = dns::get_system_nameservers()?;
+				dns_propagation::get_zone(&resolvers, &name).await?.0
+			}
+		};
+		let action = if add { "adding" } else { "removing" };
+		cert.debug(&format!(
+			"{name}: {action} the TXT record in zone {zone} using a dynamic update sent to {}",
+			self.nameserver
+		));
+		let id = rand::random::<u16>();
+		let mut msg = build_update(id, &zone, &name, proof, add)?;
+		let signature = match &self.key {
+			Some(key) => {
+				let secret = key.get_secret()?;
+				let mac = sign(&mut msg, key, &secret, system_now(), None)?;
+				Some((key, secret, mac))
+			}
+			None => None,
+		};
+		let timeout = Duration::from_secs(QUERY_TIMEOUT_SEC);
+		let response = tokio::time::timeout(timeout, dns::query_tcp(self.nameserver, &msg))
+			.await
+			.map_err(|_| dns::no_answer_error(self.nameserver))??;
+		let prefix = format!("{name}: dynamic update");
+		check_response(id, &response).map_err(|e| e.prefix(&prefix))?;
+		if let Some((key, secret, mac)) = signature {
+			verify_response(&response, key, &secret, &mac, system_now())
+				.map_err(|e| e.prefix(&prefix))?;
+		}
+		Ok(())
+	}
+}
+
+/// Build an update message adding the proof to the TXT record, or deleting it.
+fn build_update(id: u16, zone: &str, name: &str, proof: &str, add: bool) -> Result<Vec<u8>, Error> {
+	if proof.len() > 255 {
+		return Err("the proof is too long for a TXT record".into());
+	}
+	let mut msg = Vec::with_capacity(512);
+	msg.extend_from_slice(&id.to_be_bytes());
+	msg.extend_from_slice(&[OPCODE_UPDATE << 3, 0x00]);
+	// One zone, no prerequisite, one update and no additional record.
+	msg.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]);
+	dns::encode_name(&mut msg, zone)?;
+	msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+	msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+	let (class, ttl) = if add {
+		(CLASS_IN, RECORD_TTL)
+	} else {
+		(CLASS_NONE, 0)
+	};
+	dns::encode_name(&mut msg, name)?;
+	msg.extend_from_slice(&RecordType::Txt.code().to_be_bytes());
+	msg.extend_from_slice(&class.to_be_bytes());
+	msg.extend_from_slice(&ttl.to_be_bytes());
+	msg.extend_from_slice(&(proof.len() as u16 + 1).to_be_bytes());
+	msg.push(proof.len() as u8);
+	msg.extend_from_slice(proof.as_bytes());
+	Ok(msg)
+}
+
+/// TSIG variables covered by the MAC, besides the key's name and algorithm.
+struct TsigVariables<'a> {
+	timestamp: u64,
+	fudge: u16,
+	error: u16,
+	other: &'a [u8],
+}
+
+/// Compute the MAC of a message, which does not include the TSIG record. The MAC of a response
+/// also covers the MAC of the request.
+fn compute_mac(
+	msg: &[u8],
+	key: &TsigKey,
+	secret: &[u8],
+	vars: &TsigVariables,
+	request_mac: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+	let mut data = vec![];
+	if let Some(mac) = request_mac {
+		data.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+		data.extend_from_slice(mac);
+	}
+	data.extend_from_slice(msg);
+	dns::encode_name(&mut data, &key.name.to_lowercase())?;
+	data.extend_from_slice(&CLASS_ANY.to_be_bytes());
+	data.extend_from_slice(&0_u32.to_be_bytes());
+	dns::encode_name(&mut data, key.algorithm.name())?;
+	data.extend_from_slice(&vars.timestamp.to_be_bytes()[2..]);
+	data.extend_from_slice(&vars.fudge.to_be_bytes());
+	data.extend_from_slice(&vars.error.to_be_bytes());
+	data.extend_from_slice(&(vars.other.len() as u16).to_be_bytes());
+	data.extend_from_slice(vars.other);
+	key.algorithm.hash_function().hmac(secret, &data)
+}
+
+/// Append the TSIG record to the message and return its MAC.
+fn sign(
+	msg: &mut Vec<u8>,
+	key: &TsigKey,
+	secret: &[u8],
+	timestamp: u64,
+	request_mac: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+	let vars = TsigVariables {
+		timestamp,
+		fudge: TSIG_FUDGE,
+		error: 0,
+		other: &[],
+	};
+	let mac = compute_mac(msg, key, secret, &vars, request_mac)?;
+	let mut rdata = vec![];
+	dns::encode_name(&mut rdata, key.algorithm.name())?;
+	rdata.extend_from_slice(&timestamp.to_be_bytes()[2..]);
+	rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+	rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+	rdata.extend_from_slice(&mac);
+	rdata.extend_from_slice(&msg[0..2]);
+	// No error and no other data.
+	rdata.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+	dns::encode_name(msg, &key.name.to_lowercase())?;
+	msg.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+	msg.extend_from_slice(&CLASS_ANY.to_be_bytes());
+	msg.extend_from_slice(&0_u32.to_be_bytes());
+	msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+	msg.extend_from_slice(&rdata);
+	let ar_count = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+	msg[10..12].copy_from_slice(&ar_count.to_be_bytes());
+	Ok(mac)
+}
+
+/// Check the TSIG record of a response, as specified in section 5.3 of RFC 8945: it must be the
+/// last additional record, signed using the same key as the request and in time.
+fn verify_response(
+	data: &[u8],
+	key: &TsigKey,
+	secret: &[u8],
+	request_mac: &[u8],
+	now: u64,
+) -> Result<(), Error> {
+	let err = || Error::from("invalid DNS response");
+	let read_u16 = |pos: usize| -> Result<u16, Error> {
+		let b = data.get(pos..pos + 2).ok_or_else(err)?;
+		Ok(u16::from_be_bytes([b[0], b[1]]))
+	};
+	let ar_count = read_u16(10)?;
+	if ar_count == 0 {
+		return Err("the response is not signed".into());
+	}
+	let mut pos = 12;
+	for _ in 0..read_u16(4)? {
+		pos = dns::read_name(data, pos).ok_or_else(err)?.1 + 4;
+	}
+	let nb_records = read_u16(6)? as usize + read_u16(8)? as usize + ar_count as usize - 1;
+	for _ in 0..nb_records {
+		pos = dns::read_name(data, pos).ok_or_else(err)?.1;
+		pos += 10 + read_u16(pos + 8)? as usize;
+	}
+	let tsig_start = pos;
+	let (name, next) = dns::read_name(data, pos).ok_or_else(err)?;
+	pos = next;
+	if read_u16(pos)? != TYPE_TSIG {
+		return Err("the response is not signed".into());
+	}
+	pos += 10;
+	let (algorithm, next) = dns::read_name(data, pos).ok_or_else(err)?;
+	pos = next;
+	if name != key.name.to_lowercase().trim_end_matches('.') || algorithm != key.algorithm.name() {
+		return Err("the response is signed using another key".into());
+	}
+	let time = data.get(pos..pos + 6).ok_or_else(err)?;
+	let timestamp = time.iter().fold(0_u64, |t, b| (t << 8) | u64::from(*b));
+	let fudge = read_u16(pos + 6)?;
+	let mac_len = read_u16(pos + 8)? as usize;
+	pos += 10;
+	let mac = data.get(pos..pos + mac_len).ok_or_else(err)?;
+	pos += mac_len;
+	let original_id = data.get(pos..pos + 2).ok_or_else(err)?;
+	let error = read_u16(pos + 2)?;
+	let other_len = read_u16(pos + 4)? as usize;
+	let other = data.get(pos + 6..pos + 6 + other_len).ok_or_else(err)?;
+	let tsig_error = match error {
+		0 => None,
+		16 => Some("BADSIG"),
+		17 => Some("BADKEY"),
+		18 => Some("BADTIME"),
+		22 => Some("BADTRUNC"),
+		_ => Some("unknown error"),
+	};
+	if let Some(e) = tsig_error {
+		return Err(format!("the nameserver answered the TSIG error {e}").into());
+	}
+	// The MAC covers the message as it was before the TSIG record was added.
+	let mut msg = data[..tsig_start].to_vec();
+	msg[0..2].copy_from_slice(original_id);
+	msg[10..12].copy_from_slice(&(ar_count - 1).to_be_bytes());
+	let vars = TsigVariables {
+		timestamp,
+		fudge,
+		error,
+		other,
+	};
+	let expected = compute_mac(&msg, key, secret, &vars, Some(request_mac))?;
+	let diff = expected.len() ^ mac.len();
+	let diff = expected
+		.iter()
+		.zip(mac.iter())
+		.fold(diff, |d, (a, b)| d | (a ^ b) as usize);
+	if diff != 0 {
+		return Err("invalid TSIG signature".into());
+	}
+	if now.abs_diff(timestamp) > u64::from(fudge) {
+		return Err("the TSIG signature has expired".into());
+	}
+	Ok(())
+}
+
+fn check_response(id: u16, data: &[u8]) -> Result<(), Error> {
+	if data.len() < 12 || data[0..2] != id.to_be_bytes() || data[2] & 0x80 == 0 {
+		return Err("invalid DNS response".into());
+	}
+	let rcode = match data[3] & 0x0f {
+		0 => return Ok(()),
+		1 => "FORMERR",
+		2 => "SERVFAIL",
+		4 => "NOTIMP",
+		5 => "REFUSED",
+		8 => "NOTAUTH",
+		9 => "NOTZONE",
+		_ => "unknown error",
+	};
+	Err(format!("the nameserver answered {rcode}").into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{build_update, check_response, sign, verify_response, TsigAlgorithm, TsigKey};
+	use crate::dns_provider::Secret;
+
+	#[test]
+	fn test_build_update() {
+		let msg = build_update(
+			0x1234,
+			"example.org",
+			"_acme-challenge.example.org",
+			"proof",
+			false,
+		)
+		.unwrap();
+		let expected = b"\x12\x34\x28\x00\x00\x01\x00\x00\x00\x01\x00\x00\
+			\x07example\x03org\x00\x00\x06\x00\x01\
+			\x0f_acme-challenge\x07example\x03org\x00\x00\x10\x00\xfe\x00\x00\x00\x00\x00\x06\
+			\x05proof";
+		assert_eq!(msg, expected.to_vec());
+		let msg = build_update(
+			0x1234,
+			"example.org",
+			"_acme-challenge.example.org",
+			"proof",
+			true,
+		)
+		.unwrap();
+		assert_eq!(&msg[60..66], b"\x00\x01\x00\x00\x01\x2c");
+	}
+
+	#[test]
+	fn test_sign() {
+		let key = TsigKey {
+			name: "ACMEd.".to_string(),
+			algorithm: TsigAlgorithm::HmacSha256,
+			secret: Secret::Env("UNUSED".to_string()),
+		};
+		let mut msg = build_update(
+			0x1234,
+			"example.org",
+			"_acme-challenge.example.org",
+			"proof",
+			true,
+		)
+		.unwrap();
+		let len = msg.len();
+		sign(&mut msg, &key, b"secret", 1700000000, None).unwrap();
+		assert_eq!(&msg[10..12], b"\x00\x01");
+		let tsig = &msg[len..];
+		let expected_start = b"\x05acmed\x00\x00\xfa\x00\xff\x00\x00\x00\x00\x00\x3d\
+			\x0bhmac-sha256\x00\x00\x00\x65\x53\xf1\x00\x01\x2c\x00\x20";
+		assert_eq!(&tsig[..expected_start.len()], expected_start);
+		let mac: String = tsig[expected_start.len()..expected_start.len() + 32]
+			.iter()
+			.map(|b| format!("{b:02x}"))
+			.collect();
+		assert_eq!(
+			mac,
+			"ed7a761d61642a7529badeee594c033fe9980a89844ea727f11cc4e5bc5652ad"
+		);
+		assert_eq!(&tsig[tsig.len() - 6..], b"\x12\x34\x00\x00\x00\x00");
+		assert!(TsigAlgorithm::from_name("HMAC-SHA512.").is_ok());
+		assert!(TsigAlgorithm::from_name("hmac-md5").is_err());
+	}
+
+	#[test]
+	fn test_check_response() {
+		assert!(
+			check_response(0x1234, b"\x12\x34\xa8\x00\x00\x01\x00\x00\x00\x00\x00\x00").is_ok()
+		);
+		let err = check_response(0x1234, b"\x12\x34\xa8\x05\x00\x01\x00\x00\x00\x00\x00\x00");
+		assert_eq!(err.unwrap_err().message, "the nameserver answered REFUSED");
+		assert!(
+			check_response(0x4321, b"\x12\x34\xa8\x00\x00\x01\x00\x00\x00\x00\x00\x00").is_err()
+		);
+		assert!(check_response(0x1234, b"\x12\x34\x28\x00").is_err());
+	}
+
+	#[test]
+	fn test_verify_response() {
+		let key = TsigKey {
+			name: "acmed".to_string(),
+			algorithm: TsigAlgorithm::HmacSha256,
+			secret: Secret::Env("UNUSED".to_string()),
+		};
+		let request_mac = b"request mac";
+		let unsigned = b"\x12\x34\xa8\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+			\x07example\x03org\x00\x00\x06\x00\x01"
+			.to_vec();
+		let mut response = unsigned.clone();
+		sign(
+			&mut response,
+			&key,
+			b"secret",
+			1700000000,
+			Some(request_mac),
+		)
+		.unwrap();
+		assert!(verify_response(&response, &key, b"secret", request_mac, 1700000100).is_ok());
+		let err = verify_response(&response, &key, b"other", request_mac, 1700000100);
+		assert_eq!(err.unwrap_err().message, "invalid TSIG signature");
+		let err = verify_response(&response, &key, b"secret", b"other mac", 1700000100);
+		assert_eq!(err.unwrap_err().message, "invalid TSIG signature");
+		let err = verify_response(&response, &key, b"secret", request_mac, 1700001000);
+		assert_eq!(err.unwrap_err().message, "the TSIG signature has expired");
+		let err = verify_response(&unsigned, &key, b"secret", request_mac, 1700000100);
+		assert_eq!(err.unwrap_err().message, "the response is not signed");
+		let other_key = TsigKey {
+			name: "other".to_string(),
+			..key.clone()
+		};
+		let err = verify_response(&response, &other_key, b"secret", request_mac, 1700000100);
+		assert_eq!(
+			err.unwrap_err().message,
+			"the response is signed using another key"
+		);
+	}
+}
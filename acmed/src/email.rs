@@ -0,0 +1,364 @@
+use crate::config::SmtpTls;
+use crate::dns_provider::Secret;
+use crate::endpoint::format_timestamp;
+use acme_common::error::Error;
+use acme_common::system_now;
+use base64::Engine;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+const MONTHS: [&str; 12] = [
+	"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Sends an email when a certificate repeatedly fails to renew or is about to expire without
+/// having been renewed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmailNotifier {
+	pub host: String,
+	pub port: u16,
+	pub tls: SmtpTls,
+	pub ca_file: Option<String>,
+	pub user: Option<String>,
+	pub password: Option<Secret>,
+	pub from: String,
+	pub to: Vec<String>,
+	pub failures: usize,
+	pub expiration_margin: Duration,
+}
+
+impl EmailNotifier {
+	pub async fn send(&self, subject: &str, body: &str) -> Result<(), Error> {
+		let timeout = Duration::from_secs(crate::DEFAULT_EMAIL_TIMEOUT_SEC);
+		tokio::time::timeout(
+			timeout,
+			self.send_message(&self.format_message(subject, body)),
+		)
+		.await
+		.map_err(|_| Error::from(format!("{}:{}: SMTP timeout", self.host, self.port)))?
+		.map_err(|e| e.prefix(&format!("{}:{}", self.host, self.port)))
+	}
+
+	async fn send_message(&self, message: &str) -> Result<(), Error> {
+		let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+		match self.tls {
+			SmtpTls::None => {
+				let mut stream = BufReader::new(stream);
+				expect(&mut stream, 2).await?;
+				self.transaction(&mut stream, message).await
+			}
+			#[cfg(feature = "crypto_openssl")]
+			SmtpTls::StartTls => {
+				let mut stream = BufReader::new(stream);
+				expect(&mut stream, 2).await?;
+				let ehlo = format!("EHLO {}", self.get_domain());
+				let capabilities = command(&mut stream, &ehlo, 2).await?;
+				if !has_capability(&capabilities, "STARTTLS") {
+					return Err("the SMTP server does not support STARTTLS".into());
+				}
+				command(&mut stream, "STARTTLS", 2).await?;
+				let mut stream = BufReader::new(self.connect_tls(stream.into_inner()).await?);
+				self.transaction(&mut stream, message).await
+			}
+			#[cfg(feature = "crypto_openssl")]
+			SmtpTls::Tls => {
+				let mut stream = BufReader::new(self.connect_tls(stream).await?);
+				expect(&mut stream, 2).await?;
+				self.transaction(&mut stream, message).await
+			}
+			#[cfg(not(feature = "crypto_openssl"))]
+			SmtpTls::StartTls | SmtpTls::Tls => {
+				Err("the SMTP TLS connections require the crypto_openssl feature".into())
+			}
+		}
+	}
+
+	#[cfg(feature = "crypto_openssl")]
+	async fn connect_tls(
+		&self,
+		stream: TcpStream,
+	) -> Result<tokio_native_tls::TlsStream<TcpStream>, Error> {
+		let mut builder = native_tls::TlsConnector::builder();
+		if let Some(ca_file) = &self.ca_file {
+			let ca_crt = tokio::fs::read(ca_file)
+				.await
+				.map_err(|e| Error::from(e).prefix(ca_file))?;
+			let ca_crt = native_tls::Certificate::from_pem(&ca_crt)
+				.map_err(|e| Error::from(e).prefix(ca_file))?;
+			builder.add_root_certificate(ca_crt);
+		}
+		let connector = tokio_native_tls::TlsConnector::from(builder.build()?);
+		let stream = connector.connect(&self.host, stream).await?;
+		Ok(stream)
+	}
+
+	async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+		&self,
+		stream: &mut BufReader<S>,
+		message: &str,
+	) -> Result<(), Error> {
+		command(stream, &format!("EHLO {}", self.get_domain()), 2).await?;
+		if let Some(user) = &self.user {
+			let password = match &self.password {
+				Some(p) => p.get()?,
+				None => String::new(),
+			};
+			let credentials = format!("\0{user}\0{password}");
+			let credentials = base64::engine::general_purpose::STANDARD.encode(credentials);
+			command(stream, &format!("AUTH PLAIN {credentials}"), 2).await?;
+		}
+		command(stream, &format!("MAIL FROM:<{}>", self.from), 2).await?;
+		for to in self.to.iter() {
+			command(stream, &format!("RCPT TO:<{to}>"), 2).await?;
+		}
+		command(stream, "DATA", 3).await?;
+		stream.write_all(message.as_bytes()).await?;
+		command(stream, ".", 2).await?;
+		let _ = command(stream, "QUIT", 2).await;
+		Ok(())
+	}
+
+	/// Domain announced to the server, which is the one of the sender's address.
+	fn get_domain(&self) -> &str {
+		match self.from.rsplit_once('@') {
+			Some((_, d)) if !d.is_empty() => d,
+			_ => "localhost",
+		}
+	}
+
+	fn format_message(&self, subject: &str, body: &str) -> String {
+		let subject = subject.replace(['\r', '\n'], " ");
+		let subject = if subject.is_ascii() {
+			subject
+		} else {
+			let subject = base64::engine::general_purpose::STANDARD.encode(subject);
+			format!("=?utf-8?B?{subject}?=")
+		};
+		let mut message = format!(
+			"Date: {}\r\nFrom: <{}>\r\nTo: {}\r\nSubject: {subject}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+			format_date(system_now()),
+			self.from,
+			self.to
+				.iter()
+				.map(|t| format!("<{t}>"))
+				.collect::<Vec<String>>()
+				.join(", "),
+		);
+		// Lines starting with a dot are escaped, so they are not mistaken for the end of the data.
+		for line in body.lines() {
+			if line.starts_with('.') {
+				message.push('.');
+			}
+			message += line;
+			message += "\r\n";
+		}
+		message
+	}
+}
+
+/// Consecutive renewal failures of a certificate. Each alert is sent only once until the next
+/// successful renewal.
+#[derive(Debug, Default)]
+pub struct FailureTracker {
+	pub nb_failures: usize,
+	expiration_alerted: bool,
+	expiring_emitted: bool,
+	permanent_alerted: bool,
+}
+
+impl FailureTracker {
+	pub fn success(&mut self) {
+		*self = FailureTracker::default();
+	}
+
+	/// Register a failed renewal and return whether or not it should be notified, which is the
+	/// case once the number of consecutive failures is reached, once the certificate enters
+	/// the expiration margin and as soon as a renewal fails because of a permanent error, which
+	/// would not go away by retrying.
+	pub fn failure(
+		&mut self,
+		notifier: &EmailNotifier,
+		expires_in: Option<Duration>,
+		is_transient: bool,
+	) -> bool {
+		self.nb_failures += 1;
+		let mut alert = self.nb_failures == notifier.failures;
+		if !is_transient && !self.permanent_alerted {
+			self.permanent_alerted = true;
+			alert = true;
+		}
+		if let Some(d) = expires_in {
+			if d <= notifier.expiration_margin && !self.expiration_alerted {
+				self.expiration_alerted = true;
+				alert = true;
+			}
+		}
+		alert
+	}
+
+	/// Return whether or not the `certificate_expiring` event should be emitted, which is the
+	/// case once the certificate enters the given margin before its expiration.
+	pub fn is_expiring(&mut self, expires_in: Option<Duration>, margin: Duration) -> bool {
+		match expires_in {
+			Some(d) if d <= margin && !self.expiring_emitted => {
+				self.expiring_emitted = true;
+				true
+			}
+			_ => false,
+		}
+	}
+}
+
+/// Return whether or not the server announced the given extension in its reply to EHLO.
+#[cfg(any(feature = "crypto_openssl", test))]
+fn has_capability(reply: &[String], capability: &str) -> bool {
+	reply
+		.iter()
+		.skip(1)
+		.filter_map(|l| l.split_whitespace().next())
+		.any(|c| c.eq_ignore_ascii_case(capability))
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+	stream: &mut BufReader<S>,
+	cmd: &str,
+	class: u16,
+) -> Result<Vec<String>, Error> {
+	stream.write_all(format!("{cmd}\r\n").as_bytes()).await?;
+	stream.flush().await?;
+	expect(stream, class).await
+}
+
+/// Read a reply, which may span several lines, and check its code is in the expected class.
+/// Return the text of each line of the reply.
+async fn expect<S: AsyncRead + Unpin>(
+	stream: &mut BufReader<S>,
+	class: u16,
+) -> Result<Vec<String>, Error> {
+	let mut reply = vec![];
+	loop {
+		let mut line = String::new();
+		if stream.read_line(&mut line).await? == 0 {
+			return Err("SMTP connection closed unexpectedly".into());
+		}
+		let line = line.trim_end();
+		let code: u16 = line
+			.get(..3)
+			.and_then(|c| c.parse().ok())
+			.ok_or_else(|| Error::from(format!("{line}: invalid SMTP reply")))?;
+		reply.push(line.get(4..).unwrap_or_default().to_string());
+		if line.as_bytes().get(3) == Some(&b'-') {
+			continue;
+		}
+		if code / 100 != class {
+			return Err(format!("SMTP error: {line}").into());
+		}
+		return Ok(reply);
+	}
+}
+
+/// Format a Unix timestamp as an RFC 5322 date.
+fn format_date(ts: u64) -> String {
+	let date = format_timestamp(ts);
+	let month: usize = date[5..7].parse().unwrap_or(1);
+	format!(
+		"{} {} {} {} +0000",
+		&date[8..10],
+		MONTHS[month - 1],
+		&date[..4],
+		&date[11..19]
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{expect, format_date, has_capability, EmailNotifier, FailureTracker};
+	use crate::config::SmtpTls;
+	use std::time::Duration;
+	use tokio::io::BufReader;
+
+	fn notifier() -> EmailNotifier {
+		EmailNotifier {
+			host: "smtp.example.org".to_string(),
+			port: 587,
+			tls: SmtpTls::StartTls,
+			ca_file: None,
+			user: None,
+			password: None,
+			from: "acmed@example.org".to_string(),
+			to: vec![
+				"admin@example.org".to_string(),
+				"ops@example.org".to_string(),
+			],
+			failures: 3,
+			expiration_margin: Duration::from_secs(7 * 24 * 60 * 60),
+		}
+	}
+
+	#[test]
+	fn test_format_date() {
+		assert_eq!(format_date(1582915316), "28 Feb 2020 18:41:56 +0000");
+	}
+
+	#[test]
+	fn test_format_message() {
+		let msg = notifier().format_message("renewal\r\nBcc: x@example.org", "hello\n.\nworld");
+		let (headers, body) = msg.split_once("\r\n\r\n").unwrap();
+		assert!(headers.contains("\r\nTo: <admin@example.org>, <ops@example.org>\r\n"));
+		assert!(headers.contains("\r\nSubject: renewal  Bcc: x@example.org\r\n"));
+		assert!(!headers.contains("\r\nBcc"));
+		assert_eq!(body, "hello\r\n..\r\nworld\r\n");
+		let msg = notifier().format_message("é", "");
+		assert!(msg.contains("\r\nSubject: =?utf-8?B?w6k=?=\r\n"));
+	}
+
+	#[tokio::test]
+	async fn test_expect() {
+		let mut reply =
+			BufReader::new("250-smtp.example.org\r\n250-PIPELINING\r\n250 8BITMIME\r\n".as_bytes());
+		let lines = expect(&mut reply, 2).await.unwrap();
+		assert_eq!(lines, ["smtp.example.org", "PIPELINING", "8BITMIME"]);
+		assert!(has_capability(&lines, "PIPELINING"));
+		assert!(!has_capability(&lines, "STARTTLS"));
+		assert!(!has_capability(&lines, "smtp.example.org"));
+		let mut reply = BufReader::new("250-smtp.example.org\r\n250 STARTTLS\r\n".as_bytes());
+		assert!(has_capability(
+			&expect(&mut reply, 2).await.unwrap(),
+			"STARTTLS"
+		));
+		let mut reply = BufReader::new("354 go ahead\r\n".as_bytes());
+		assert!(expect(&mut reply, 3).await.is_ok());
+		let mut reply = BufReader::new("535 5.7.8 authentication failed\r\n".as_bytes());
+		let e = expect(&mut reply, 2).await.unwrap_err();
+		assert_eq!(e.message, "SMTP error: 535 5.7.8 authentication failed");
+		let mut reply = BufReader::new("250-truncated\r\n".as_bytes());
+		assert!(expect(&mut reply, 2).await.is_err());
+	}
+
+	#[test]
+	fn test_failure_tracker() {
+		let notifier = notifier();
+		let long = Some(Duration::from_secs(30 * 24 * 60 * 60));
+		let short = Some(Duration::from_secs(24 * 60 * 60));
+		let mut tracker = FailureTracker::default();
+		assert!(!tracker.failure(&notifier, long, true));
+		assert!(!tracker.failure(&notifier, long, true));
+		assert!(tracker.failure(&notifier, long, true));
+		assert!(!tracker.failure(&notifier, long, true));
+		assert!(tracker.failure(&notifier, short, true));
+		assert!(!tracker.failure(&notifier, short, true));
+		tracker.success();
+		assert!(tracker.failure(&notifier, short, true));
+		assert!(!tracker.failure(&notifier, None, true));
+		assert!(tracker.failure(&notifier, None, true));
+		tracker.success();
+		assert!(tracker.failure(&notifier, long, false));
+		assert!(!tracker.failure(&notifier, long, false));
+		assert!(tracker.failure(&notifier, long, true));
+		let margin = Duration::from_secs(crate::DEFAULT_CERTIFICATE_EXPIRING_SEC);
+		assert!(!tracker.is_expiring(long, margin));
+		assert!(tracker.is_expiring(short, margin));
+		assert!(!tracker.is_expiring(short, margin));
+	}
+}
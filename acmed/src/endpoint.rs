@@ -1,7 +1,9 @@
 use crate::config::NamedAcmeResource;
 use crate::duration::parse_duration;
 use crate::{acme_proto::structs::Directory, config};
+use acme_common::b64_encode;
 use acme_common::error::Error;
+use acme_common::now;
 use governor::{
 	clock::DefaultClock,
 	state::{direct::NotKeyed, InMemoryState},
@@ -9,7 +11,15 @@ use governor::{
 };
 use itertools::Itertools;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
 #[derive(Debug)]
@@ -17,10 +27,25 @@ pub struct Endpoint {
 	pub name: String,
 	pub url: String,
 	pub tos_agreed: bool,
+	pub tos_auto_agree: bool,
+	pub tos_changes: TosChanges,
 	pub nonce: Option<String>,
 	pub rl: RateLimits,
+	pub order_limits: OrderLimits,
+	pub account_order_limits: HashMap<String, OrderLimits>,
+	pub ca_rate_limits: CaRateLimits,
 	pub dir: Directory,
 	pub root_certificates: Vec<String>,
+	pub system_root_certificates: bool,
+	pub proxy: Option<String>,
+	pub dual_stack_preference: Option<config::IpFamily>,
+	pub force_ipv4: bool,
+	pub http_max_attempts: usize,
+	pub http_retry_delay: Duration,
+	pub poll_interval: Duration,
+	pub authorization_timeout: Duration,
+	pub finalization_timeout: Duration,
+	pub client: Option<reqwest::Client>,
 }
 
 impl Endpoint {
@@ -29,14 +54,21 @@ impl Endpoint {
 		url: &str,
 		tos_agreed: bool,
 		limits: &[config::RateLimit],
+		order_limits: &[String],
+		state_dir: &str,
 		root_certs: &[String],
 	) -> Result<Self, Error> {
 		Ok(Self {
 			name: name.to_string(),
 			url: url.to_string(),
 			tos_agreed,
+			tos_auto_agree: false,
+			tos_changes: TosChanges::new(name, state_dir),
 			nonce: None,
 			rl: RateLimits::new(limits)?,
+			order_limits: OrderLimits::new(name, order_limits, state_dir)?,
+			account_order_limits: HashMap::new(),
+			ca_rate_limits: CaRateLimits::new(name, state_dir),
 			dir: Directory {
 				meta: None,
 				new_nonce: String::new(),
@@ -47,13 +79,94 @@ impl Endpoint {
 				key_change: String::new(),
 			},
 			root_certificates: root_certs.to_vec(),
+			system_root_certificates: true,
+			proxy: None,
+			dual_stack_preference: None,
+			force_ipv4: false,
+			http_max_attempts: crate::DEFAULT_HTTP_FAIL_NB_RETRY,
+			http_retry_delay: Duration::from_secs(crate::DEFAULT_HTTP_FAIL_WAIT_SEC),
+			poll_interval: Duration::from_secs(crate::DEFAULT_POLL_INTERVAL_SEC),
+			authorization_timeout: Duration::from_secs(crate::DEFAULT_AUTHORIZATION_TIMEOUT_SEC),
+			finalization_timeout: Duration::from_secs(crate::DEFAULT_FINALIZATION_TIMEOUT_SEC),
+			client: None,
 		})
 	}
+
+	/// Take over the in-memory state of the previous version of the endpoint, which would
+	/// otherwise be lost when the configuration is reloaded: the nonce, if the server is the
+	/// same, the consumed rate limits, if they are unchanged, and the latest rate-limit
+	/// information sent by the CA.
+	pub fn take_state(&mut self, previous: &mut Endpoint) {
+		if self.url == previous.url {
+			self.nonce = previous.nonce.take();
+		}
+		if self.rl.has_same_limits(&previous.rl) {
+			std::mem::swap(&mut self.rl, &mut previous.rl);
+		}
+		if self.ca_rate_limits.state_file == previous.ca_rate_limits.state_file {
+			self.ca_rate_limits = previous.ca_rate_limits.clone();
+		}
+	}
+
+	/// Limit the number of orders the account may create on this endpoint, independently of
+	/// the other accounts.
+	pub fn set_account_order_limits(
+		&mut self,
+		account_name: &str,
+		order_limits: &[String],
+		state_dir: &str,
+	) -> Result<(), Error> {
+		let limits = OrderLimits::for_account(&self.name, account_name, order_limits, state_dir)?;
+		self.account_order_limits
+			.insert(account_name.to_string(), limits);
+		Ok(())
+	}
+
+	/// Return how long the account has to wait before creating a new order because of its own
+	/// order limits.
+	pub async fn account_time_until_allowed(&self, account_name: &str) -> Result<Duration, Error> {
+		match self.account_order_limits.get(account_name) {
+			Some(limits) => limits.time_until_allowed().await,
+			None => Ok(Duration::ZERO),
+		}
+	}
+
+	/// Return an error if one of the endpoint's or the account's order limits has been reached.
+	pub async fn check_order_limits(&self, account_name: &str) -> Result<(), Error> {
+		let waits = [
+			(self.order_limits.time_until_allowed().await?, "order limit"),
+			(
+				self.account_time_until_allowed(account_name).await?,
+				"account order limit",
+			),
+		];
+		for (wait, limit) in waits {
+			if !wait.is_zero() {
+				let msg = format!(
+					"{limit} reached, a new order may be created in {} seconds",
+					wait.as_secs()
+				);
+				return Err(msg.into());
+			}
+		}
+		Ok(())
+	}
+
+	/// Record a new order created by the account, which counts toward the endpoint's and the
+	/// account's limits.
+	pub async fn register_order(&self, account_name: &str) -> Result<(), Error> {
+		self.order_limits.register_order().await?;
+		match self.account_order_limits.get(account_name) {
+			Some(limits) => limits.register_order().await,
+			None => Ok(()),
+		}
+	}
 }
 
 #[derive(Debug)]
 pub struct RateLimits {
 	limits: Vec<RateLimit>,
+	raw_limits: Vec<config::RateLimit>,
 }
 
 impl RateLimits {
@@ -66,7 +179,21 @@ impl RateLimits {
 			.rev()
 			.map(|raw| raw.try_into())
 			.collect();
-		Ok(Self { limits: limits? })
+		Ok(Self {
+			limits: limits?,
+			raw_limits: raw_limits.to_vec(),
+		})
+	}
+
+	pub fn has_same_limits(&self, other: &RateLimits) -> bool {
+		self.raw_limits == other.raw_limits
+	}
+
+	/// Wait until every limit allows a new request, regardless of the resources and paths.
+	pub async fn block_until_ready(&self) {
+		for limit in &self.limits {
+			limit.until_ready().await
+		}
 	}
 
 	pub async fn block_until_allowed(&mut self, resource: Option<NamedAcmeResource>, path: &str) {
@@ -134,11 +261,373 @@ impl TryFrom<&config::RateLimit> for RateLimit {
 	}
 }
 
+#[derive(Debug)]
+struct OrderLimit {
+	number: usize,
+	period: Duration,
+}
+
+impl FromStr for OrderLimit {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let err = || Error::from(format!("{s}: invalid order limit"));
+		let (number, period) = s.split_once('/').ok_or_else(err)?;
+		let number = number.trim().parse::<usize>().map_err(|_| err())?;
+		let period = parse_duration(period.trim())?;
+		if number == 0 || period.is_zero() {
+			return Err(err());
+		}
+		Ok(Self { number, period })
+	}
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct OrderLimitsState {
+	orders: Vec<u64>,
+}
+
+/// Limits on the number of orders created on an endpoint. Unlike the HTTPS rate limits, the
+/// orders are recorded in a state file so the limits also apply across restarts.
+#[derive(Debug)]
+pub struct OrderLimits {
+	limits: Vec<OrderLimit>,
+	state_file: PathBuf,
+}
+
+impl OrderLimits {
+	pub fn new(endpoint_name: &str, raw_limits: &[String], state_dir: &str) -> Result<Self, Error> {
+		let limits = raw_limits
+			.iter()
+			.map(|l| l.parse())
+			.collect::<Result<Vec<OrderLimit>, Error>>()?;
+		let state_file = get_state_file(state_dir, endpoint_name, "orders");
+		Ok(Self { limits, state_file })
+	}
+
+	fn for_account(
+		endpoint_name: &str,
+		account_name: &str,
+		raw_limits: &[String],
+		state_dir: &str,
+	) -> Result<Self, Error> {
+		let mut ret = Self::new(endpoint_name, raw_limits, state_dir)?;
+		let kind = format!("{}.orders", b64_encode(account_name));
+		ret.state_file = get_state_file(state_dir, endpoint_name, &kind);
+		Ok(ret)
+	}
+
+	async fn load(&self) -> Result<Vec<u64>, Error> {
+		let path = self.state_file.display().to_string();
+		let data = match tokio::fs::read(&self.state_file).await {
+			Ok(d) => d,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+			Err(e) => return Err(Error::from(e).prefix(&path)),
+		};
+		let state: OrderLimitsState =
+			serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(state.orders)
+	}
+
+	async fn save(&self, orders: Vec<u64>) -> Result<(), Error> {
+		let path = self.state_file.display().to_string();
+		let data = serde_json::to_vec(&OrderLimitsState { orders })?;
+		tokio::fs::write(&self.state_file, data)
+			.await
+			.map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	/// Return how long to wait before a new order can be created.
+	pub async fn time_until_allowed(&self) -> Result<Duration, Error> {
+		if self.limits.is_empty() {
+			return Ok(Duration::ZERO);
+		}
+		Ok(wait_time(&self.limits, &self.load().await?, now()))
+	}
+
+	/// Record a new order, once it has been created by the CA.
+	pub async fn register_order(&self) -> Result<(), Error> {
+		if self.limits.is_empty() {
+			return Ok(());
+		}
+		let now = now();
+		let mut orders = self.load().await?;
+		let max_period = self
+			.limits
+			.iter()
+			.map(|l| l.period.as_secs())
+			.max()
+			.unwrap_or(0);
+		orders.retain(|ts| ts + max_period > now);
+		orders.push(now);
+		self.save(orders).await
+	}
+}
+
+/// Rate-limit information sent by the CA.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CaRateLimitStatus {
+	pub limit: Option<u64>,
+	pub remaining: Option<u64>,
+	/// Timestamp at which the limit is reset.
+	pub reset: Option<u64>,
+	/// Timestamp before which no new request should be sent.
+	pub retry_after: Option<u64>,
+	pub detail: Option<String>,
+	pub updated: u64,
+}
+
+impl CaRateLimitStatus {
+	pub fn is_empty(&self) -> bool {
+		self.limit.is_none()
+			&& self.remaining.is_none()
+			&& self.reset.is_none()
+			&& self.retry_after.is_none()
+			&& self.detail.is_none()
+	}
+}
+
+impl fmt::Display for CaRateLimitStatus {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let now = now();
+		let mut parts = vec![];
+		match (self.remaining, self.limit) {
+			(Some(r), Some(l)) => parts.push(format!("{r}/{l} requests remaining")),
+			(Some(r), None) => parts.push(format!("{r} requests remaining")),
+			(None, Some(l)) => parts.push(format!("limit of {l} requests")),
+			(None, None) => {}
+		};
+		if let Some(ts) = self.reset {
+			parts.push(format!("reset in {} seconds", ts.saturating_sub(now)));
+		}
+		if let Some(ts) = self.retry_after {
+			if ts > now {
+				parts.push(format!("retry in {} seconds", ts - now));
+			}
+		}
+		if let Some(d) = &self.detail {
+			parts.push(format!("last error: {d}"));
+		}
+		write!(
+			f,
+			"{} (updated {} seconds ago)",
+			parts.join(", "),
+			now.saturating_sub(self.updated)
+		)
+	}
+}
+
+/// Latest rate-limit information sent by the CA, stored in a state file so it can be
+/// displayed by the status command. The state file is written shortly after the information is
+/// received, and outside of the endpoint's lock, so successive responses only trigger a single
+/// write. Clones share the same information.
+#[derive(Clone, Debug)]
+pub struct CaRateLimits {
+	state_file: PathBuf,
+	latest: Arc<Mutex<Option<CaRateLimitStatus>>>,
+	is_write_scheduled: Arc<AtomicBool>,
+}
+
+impl CaRateLimits {
+	pub fn new(endpoint_name: &str, state_dir: &str) -> Self {
+		Self {
+			state_file: get_state_file(state_dir, endpoint_name, "rate_limits"),
+			latest: Arc::new(Mutex::new(None)),
+			is_write_scheduled: Arc::new(AtomicBool::new(false)),
+		}
+	}
+
+	fn get_latest(&self) -> MutexGuard<'_, Option<CaRateLimitStatus>> {
+		self.latest.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	pub fn load(&self) -> Result<Option<CaRateLimitStatus>, Error> {
+		let mut latest = self.get_latest();
+		if latest.is_none() && self.state_file.is_file() {
+			let path = self.state_file.display().to_string();
+			let data = fs::read(&self.state_file).map_err(|e| Error::from(e).prefix(&path))?;
+			let status = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+			*latest = Some(status);
+		}
+		Ok(latest.clone())
+	}
+
+	/// Return how long to wait before sending new requests, as asked by the CA.
+	pub fn retry_in(&self) -> Result<Duration, Error> {
+		let retry_after = self.load()?.and_then(|s| s.retry_after).unwrap_or(0);
+		Ok(Duration::from_secs(retry_after.saturating_sub(now())))
+	}
+
+	/// Record the latest information and schedule the writing of the state file, unless it
+	/// already is.
+	pub fn update(&self, status: CaRateLimitStatus) {
+		*self.get_latest() = Some(status);
+		if self.is_write_scheduled.swap(true, Ordering::SeqCst) {
+			return;
+		}
+		let limits = self.clone();
+		tokio::spawn(async move {
+			let delay = Duration::from_secs(crate::DEFAULT_CA_RATE_LIMITS_WRITE_DELAY_SEC);
+			tokio::time::sleep(delay).await;
+			limits.flush().await;
+		});
+	}
+
+	/// Write the latest information to the state file if it has not been written yet.
+	pub async fn flush(&self) {
+		if !self.is_write_scheduled.swap(false, Ordering::SeqCst) {
+			return;
+		}
+		let status = match self.get_latest().clone() {
+			Some(s) => s,
+			None => return,
+		};
+		if let Err(e) = self.save(&status).await {
+			log::warn!("{e}");
+		}
+	}
+
+	async fn save(&self, status: &CaRateLimitStatus) -> Result<(), Error> {
+		let path = self.state_file.display().to_string();
+		let data = serde_json::to_vec(status)?;
+		tokio::fs::write(&self.state_file, data)
+			.await
+			.map_err(|e| Error::from(e).prefix(&path))
+	}
+}
+
+/// Change of the terms of service reported by the CA to an account, which has to agree to them
+/// before creating new orders.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TosChange {
+	pub url: Option<String>,
+	/// URL of the page where the required actions are described.
+	pub instance: Option<String>,
+	pub detected: u64,
+}
+
+impl fmt::Display for TosChange {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut parts = vec![];
+		if let Some(url) = &self.url {
+			parts.push(format!("new terms of service at {url}"));
+		}
+		if let Some(url) = &self.instance {
+			parts.push(format!("see {url}"));
+		}
+		if parts.is_empty() {
+			parts.push("user action required".to_string());
+		}
+		write!(f, "{}", parts.join(", "))
+	}
+}
+
+/// Terms of service changes the accounts have not agreed to yet, stored in state files so they
+/// can be displayed by the status command.
+#[derive(Debug)]
+pub struct TosChanges {
+	endpoint_name: String,
+	state_dir: String,
+}
+
+impl TosChanges {
+	pub fn new(endpoint_name: &str, state_dir: &str) -> Self {
+		Self {
+			endpoint_name: endpoint_name.to_string(),
+			state_dir: state_dir.to_string(),
+		}
+	}
+
+	fn get_state_file(&self, account_name: &str) -> PathBuf {
+		let kind = format!("{}.tos", b64_encode(account_name));
+		get_state_file(&self.state_dir, &self.endpoint_name, &kind)
+	}
+
+	pub fn load(&self, account_name: &str) -> Result<Option<TosChange>, Error> {
+		let state_file = self.get_state_file(account_name);
+		if !state_file.is_file() {
+			return Ok(None);
+		}
+		let path = state_file.display().to_string();
+		let data = fs::read(&state_file).map_err(|e| Error::from(e).prefix(&path))?;
+		let change = serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))?;
+		Ok(Some(change))
+	}
+
+	pub fn save(&self, account_name: &str, change: &TosChange) -> Result<(), Error> {
+		let state_file = self.get_state_file(account_name);
+		let path = state_file.display().to_string();
+		let data = serde_json::to_vec(change)?;
+		fs::write(&state_file, data).map_err(|e| Error::from(e).prefix(&path))
+	}
+
+	pub fn clear(&self, account_name: &str) -> Result<(), Error> {
+		let state_file = self.get_state_file(account_name);
+		if !state_file.is_file() {
+			return Ok(());
+		}
+		let path = state_file.display().to_string();
+		fs::remove_file(&state_file).map_err(|e| Error::from(e).prefix(&path))
+	}
+}
+
+pub fn get_state_file(state_dir: &str, name: &str, kind: &str) -> PathBuf {
+	let mut state_file = PathBuf::from(state_dir);
+	state_file.push(format!("{}.{kind}.json", b64_encode(name)));
+	state_file
+}
+
+/// Format a Unix timestamp as an RFC 3339 UTC date.
+pub fn format_timestamp(ts: u64) -> String {
+	let days = (ts / 86_400) as i64;
+	let secs = ts % 86_400;
+	// Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+	let z = days + 719_468;
+	let era = z.div_euclid(146_097);
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + i64::from(month <= 2);
+	format!(
+		"{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+		secs / 3600,
+		secs % 3600 / 60,
+		secs % 60
+	)
+}
+
+fn wait_time(limits: &[OrderLimit], orders: &[u64], now: u64) -> Duration {
+	let mut orders = orders.to_vec();
+	orders.sort_unstable();
+	limits
+		.iter()
+		.filter_map(|limit| {
+			let period = limit.period.as_secs();
+			let in_period: Vec<u64> = orders
+				.iter()
+				.filter(|ts| *ts + period > now)
+				.copied()
+				.collect();
+			if in_period.len() < limit.number {
+				return None;
+			}
+			// The new order is allowed once enough of the recorded orders are out of the period.
+			let ts = in_period[in_period.len() - limit.number];
+			Some(Duration::from_secs(ts + period - now))
+		})
+		.max()
+		.unwrap_or(Duration::ZERO)
+}
+
 #[cfg(test)]
 mod tests {
-	use std::{cmp::Ordering, num::NonZeroU32};
+	use std::{cmp::Ordering, num::NonZeroU32, time::Duration};
 
 	use crate::config;
+	use crate::storage::TestDir;
 
 	#[test]
 	fn check_ratelimit_ordering() {
@@ -168,4 +657,126 @@ mod tests {
 			path: None,
 		}
 	}
+
+	#[test]
+	fn check_order_limit_wait_time() {
+		let limits: Vec<super::OrderLimit> = vec!["2/1h".parse().unwrap(), "3/1d".parse().unwrap()];
+		let now = 100_000;
+		assert_eq!(super::wait_time(&limits, &[], now), Duration::ZERO);
+		assert_eq!(super::wait_time(&limits, &[now - 10], now), Duration::ZERO);
+		assert_eq!(
+			super::wait_time(&limits, &[now - 10, now - 600], now),
+			Duration::from_secs(3600 - 600)
+		);
+		assert_eq!(
+			super::wait_time(&limits, &[now - 7200, now - 3700], now),
+			Duration::ZERO
+		);
+		assert_eq!(
+			super::wait_time(&limits, &[now - 7200, now - 3700, now - 10], now),
+			Duration::from_secs(86400 - 7200)
+		);
+	}
+
+	#[test]
+	fn check_order_limit_parsing() {
+		assert!("20/3h".parse::<super::OrderLimit>().is_ok());
+		assert!(" 20 / 3h ".parse::<super::OrderLimit>().is_ok());
+		assert!("20".parse::<super::OrderLimit>().is_err());
+		assert!("0/3h".parse::<super::OrderLimit>().is_err());
+		assert!("20/0s".parse::<super::OrderLimit>().is_err());
+		assert!("a/3h".parse::<super::OrderLimit>().is_err());
+	}
+
+	#[tokio::test]
+	async fn check_account_order_limits() {
+		let dir = TestDir::new("endpoint-orders");
+		let state_dir = dir.path().to_str().unwrap();
+		let mut endpoint = super::Endpoint::new(
+			"ep",
+			"https://example.org/dir",
+			true,
+			&[],
+			&[],
+			state_dir,
+			&[],
+		)
+		.unwrap();
+		endpoint
+			.set_account_order_limits("tenant-a", &["1/1h".to_string()], state_dir)
+			.unwrap();
+		endpoint.check_order_limits("tenant-a").await.unwrap();
+		endpoint.register_order("tenant-a").await.unwrap();
+		assert!(endpoint.check_order_limits("tenant-a").await.is_err());
+		assert!(!endpoint
+			.account_time_until_allowed("tenant-a")
+			.await
+			.unwrap()
+			.is_zero());
+		// The other accounts have their own budget.
+		endpoint.check_order_limits("tenant-b").await.unwrap();
+		endpoint.register_order("tenant-b").await.unwrap();
+		assert!(endpoint
+			.account_time_until_allowed("tenant-b")
+			.await
+			.unwrap()
+			.is_zero());
+	}
+
+	#[test]
+	fn check_take_state() {
+		let limits = [cfg_ratelimit_helper(
+			NonZeroU32::new(1).unwrap(),
+			"1h".into(),
+		)];
+		let new_endpoint = |url: &str, limits: &[config::RateLimit]| {
+			super::Endpoint::new("ep", url, true, limits, &[], "/nonexistent", &[]).unwrap()
+		};
+		let mut previous = new_endpoint("https://example.org/dir", &limits);
+		previous.nonce = Some("nonce".to_string());
+		futures::executor::block_on(previous.rl.block_until_ready());
+		let mut endpoint = new_endpoint("https://example.org/dir", &limits);
+		endpoint.take_state(&mut previous);
+		assert_eq!(endpoint.nonce.as_deref(), Some("nonce"));
+		// The consumed rate limit has been kept.
+		assert!(endpoint.rl.limits[0].limiter.check().is_err());
+
+		let mut previous = endpoint;
+		let mut endpoint = new_endpoint("https://example.net/dir", &[]);
+		endpoint.take_state(&mut previous);
+		assert_eq!(endpoint.nonce, None);
+		assert!(endpoint.rl.limits.is_empty());
+	}
+
+	#[test]
+	fn check_tos_changes() {
+		let dir = TestDir::new("endpoint-tos");
+		let changes = super::TosChanges::new("ep", dir.path().to_str().unwrap());
+		assert_eq!(changes.load("a").unwrap(), None);
+		let change = super::TosChange {
+			url: Some("https://example.org/tos-v2".to_string()),
+			instance: None,
+			detected: 1_700_000_000,
+		};
+		assert_eq!(
+			change.to_string(),
+			"new terms of service at https://example.org/tos-v2"
+		);
+		changes.save("a", &change).unwrap();
+		assert_eq!(changes.load("a").unwrap(), Some(change));
+		assert_eq!(changes.load("b").unwrap(), None);
+		changes.clear("a").unwrap();
+		changes.clear("a").unwrap();
+		assert_eq!(changes.load("a").unwrap(), None);
+	}
+
+	#[test]
+	fn test_format_timestamp() {
+		assert_eq!(super::format_timestamp(0), "1970-01-01T00:00:00Z");
+		assert_eq!(super::format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+		assert_eq!(
+			super::format_timestamp(1_700_000_000),
+			"2023-11-14T22:13:20Z"
+		);
+	}
 }
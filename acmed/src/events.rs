@@ -0,0 +1,97 @@
+use acme_common::now;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Number of events kept for each watcher. A watcher which falls behind loses the oldest events.
+#[cfg(any(unix, feature = "crypto_openssl", test))]
+const EVENTS_CAPACITY: usize = 256;
+
+static EVENTS: Mutex<Option<broadcast::Sender<Event>>> = Mutex::new(None);
+static LABELS: Mutex<Option<HashMap<String, BTreeMap<String, String>>>> = Mutex::new(None);
+
+/// An event streamed to the watchers of the control socket.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Event {
+	pub timestamp: u64,
+	pub kind: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub certificate: Option<String>,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub labels: BTreeMap<String, String>,
+	#[serde(default, skip_serializing_if = "Value::is_null")]
+	pub details: Value,
+}
+
+/// Set the labels of the certificates, indexed by their id, which are attached to their events.
+pub fn set_labels(labels: HashMap<String, BTreeMap<String, String>>) {
+	*LABELS.lock().unwrap_or_else(|e| e.into_inner()) = Some(labels);
+}
+
+fn get_labels(certificate: &Option<String>) -> BTreeMap<String, String> {
+	let labels = LABELS.lock().unwrap_or_else(|e| e.into_inner());
+	certificate
+		.as_ref()
+		.and_then(|c| labels.as_ref()?.get(c))
+		.cloned()
+		.unwrap_or_default()
+}
+
+/// Send an event to the current watchers, if any.
+pub fn emit(kind: &str, certificate: Option<String>, details: Value) {
+	let events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+	if let Some(tx) = events.as_ref() {
+		if tx.receiver_count() > 0 {
+			let _ = tx.send(Event {
+				timestamp: now(),
+				kind: kind.to_string(),
+				labels: get_labels(&certificate),
+				certificate,
+				details,
+			});
+		}
+	}
+}
+
+/// The events are watched on the control socket, which requires Unix, and by the web UI served
+/// over HTTPS, which requires OpenSSL.
+#[cfg(any(unix, feature = "crypto_openssl", test))]
+pub fn subscribe() -> broadcast::Receiver<Event> {
+	let mut events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+	events
+		.get_or_insert_with(|| broadcast::channel(EVENTS_CAPACITY).0)
+		.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{emit, set_labels, subscribe};
+	use serde_json::json;
+	use std::collections::{BTreeMap, HashMap};
+
+	#[test]
+	fn test_events() {
+		emit("ignored", None, json!(null));
+		let labels = BTreeMap::from([("team".to_string(), "payments".to_string())]);
+		set_labels(HashMap::from([(
+			"example.org_rsa2048".to_string(),
+			labels.clone(),
+		)]));
+		let mut rx = subscribe();
+		emit(
+			"file_written",
+			Some("example.org_rsa2048".to_string()),
+			json!({"file_type": "crt"}),
+		);
+		let event = rx.try_recv().unwrap();
+		assert_eq!(event.kind, "file_written");
+		assert_eq!(event.certificate, Some("example.org_rsa2048".to_string()));
+		assert_eq!(event.labels, labels);
+		assert_eq!(event.details["file_type"], "crt");
+		emit("storage_writable", None, json!(null));
+		assert!(rx.try_recv().unwrap().labels.is_empty());
+		assert!(rx.try_recv().is_err());
+	}
+}
@@ -1,21 +1,34 @@
 pub use crate::config::HookType;
-use crate::logs::HasLogger;
-use crate::template::render_template;
-use acme_common::error::Error;
+use crate::endpoint::get_state_file;
+use crate::events;
+use crate::http;
+use crate::logs::{HasLogger, LogEvent};
+use crate::platform::{Current, Platform};
+use crate::privileges;
+use crate::template::{check_template, render_template};
+use acme_common::crypto::{HashFunction, JwsSignatureAlgorithm, KeyPair, X509Certificate};
+use acme_common::error::{Error, ErrorKind};
+use acme_common::{b64_decode, b64_encode, now};
+#[cfg(unix)]
+use async_process::unix::CommandExt;
 use async_process::{Command, Stdio};
-use futures::AsyncWriteExt;
-use serde::Serialize;
+use futures::io::BufReader;
+use futures::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Iter;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{env, fmt};
+use tokio::time::{sleep, Instant};
+use tokio_util::compat::TokioAsyncReadCompatExt;
 
 pub trait HookEnvData {
 	fn set_env(&mut self, env: &HashMap<String, String>);
-	fn get_env(&self) -> Iter<String, String>;
+	fn get_env(&self) -> Iter<'_, String, String>;
 }
 
 fn deref<F, G>(t: (&F, &G)) -> (F, G)
@@ -35,34 +48,203 @@ macro_rules! imple_hook_data_env {
 				}
 			}
 
-			fn get_env(&self) -> Iter<String, String> {
+			fn get_env(&self) -> Iter<'_, String, String> {
 				self.env.iter()
 			}
 		}
 	};
 }
 
+/// Record of a hook execution, kept in the certificate's hook history.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HookExecution {
+	pub timestamp: u64,
+	pub hook: String,
+	pub cmd: String,
+	pub duration_ms: u64,
+	pub exit_code: Option<i32>,
+	pub success: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+/// Reports the executions of the hooks called on behalf of a certificate: each one is emitted as
+/// an event and, if a history is kept, the latest ones are saved in the certificate's state file.
+#[derive(Clone, Debug, Default)]
+pub struct HookRecorder {
+	certificate_id: Option<String>,
+	history: Option<(PathBuf, usize)>,
+	/// Latest executions, loaded from the state file on first use.
+	executions: Arc<tokio::sync::Mutex<Option<Vec<HookExecution>>>>,
+}
+
+impl PartialEq for HookRecorder {
+	fn eq(&self, other: &Self) -> bool {
+		self.certificate_id == other.certificate_id && self.history == other.history
+	}
+}
+
+impl HookRecorder {
+	pub fn new(certificate_id: Option<String>) -> Self {
+		HookRecorder {
+			certificate_id,
+			..Default::default()
+		}
+	}
+
+	/// Keep the given number of executions in the state directory, none being kept if it is zero.
+	pub fn with_history(mut self, state_dir: &str, size: usize) -> Self {
+		self.history = match &self.certificate_id {
+			Some(id) if size > 0 => Some((get_state_file(state_dir, id, "hooks"), size)),
+			_ => None,
+		};
+		self
+	}
+
+	pub fn certificate_id(&self) -> Option<String> {
+		self.certificate_id.to_owned()
+	}
+
+	async fn record(&self, execution: &HookExecution) -> Result<(), Error> {
+		events::emit(
+			"hook_finished",
+			self.certificate_id(),
+			serde_json::to_value(execution)?,
+		);
+		let (state_file, size) = match &self.history {
+			Some(h) => h,
+			None => return Ok(()),
+		};
+		let mut executions = self.executions.lock().await;
+		let history = match executions.as_mut() {
+			Some(h) => h,
+			None => executions.insert(read_history(state_file).await?),
+		};
+		history.push(execution.to_owned());
+		let excess = history.len().saturating_sub(*size);
+		history.drain(..excess);
+		let data = serde_json::to_vec(&history)?;
+		tokio::fs::write(state_file, data)
+			.await
+			.map_err(|e| Error::from(e).prefix(&state_file.display().to_string()))
+	}
+
+	/// Return the latest hook executions, the most recent being the last one.
+	pub async fn get_history(&self) -> Result<Vec<HookExecution>, Error> {
+		let state_file = match &self.history {
+			Some((state_file, _)) => state_file,
+			None => return Ok(vec![]),
+		};
+		let mut executions = self.executions.lock().await;
+		if let Some(history) = executions.as_ref() {
+			return Ok(history.to_owned());
+		}
+		let history = read_history(state_file).await?;
+		Ok(executions.insert(history).to_owned())
+	}
+}
+
+async fn read_history(state_file: &Path) -> Result<Vec<HookExecution>, Error> {
+	let path = state_file.display().to_string();
+	let data = match tokio::fs::read(state_file).await {
+		Ok(data) => data,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => return Err(Error::from(e).prefix(&path)),
+	};
+	serde_json::from_slice(&data).map_err(|e| Error::from(e).prefix(&path))
+}
+
 #[derive(Clone, Serialize)]
 pub struct PostOperationHookData {
+	pub account_contacts: Vec<String>,
+	pub account_name: String,
+	pub account_url: String,
+	pub endpoint_name: String,
 	pub identifiers: Vec<String>,
 	pub key_type: String,
+	pub labels: BTreeMap<String, String>,
 	pub status: String,
 	pub is_success: bool,
+	pub error_source: String,
 	pub certificate_path: PathBuf,
 	pub private_key_path: PathBuf,
+	#[serde(flatten)]
+	pub certificate: CertificateDetails,
 	pub env: HashMap<String, String>,
 }
 
 imple_hook_data_env!(PostOperationHookData);
 
+/// Details of a certificate, which are left empty when it is not available.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CertificateDetails {
+	pub subject_alt_names: Vec<String>,
+	pub serial_number: String,
+	pub not_before: u64,
+	pub not_after: u64,
+	pub fingerprint_sha256: String,
+	pub chain_fingerprints_sha256: Vec<String>,
+	pub tlsa_2_1_1: String,
+	pub tlsa_3_1_1: String,
+}
+
+impl CertificateDetails {
+	/// Read the details of the leaf of a certificate chain, including the data of the DANE-TA
+	/// and DANE-EE TLSA records matching the issuer's and the certificate's public keys.
+	pub fn from_chain(chain: &[X509Certificate]) -> Result<Self, Error> {
+		let leaf = chain
+			.first()
+			.ok_or_else(|| Error::from("empty certificate file"))?;
+		let issuer = chain
+			.get(1)
+			.ok_or_else(|| Error::from("the issuer's certificate is missing from the chain"))?;
+		let mut subject_alt_names: Vec<String> = leaf.subject_alt_names().into_iter().collect();
+		subject_alt_names.sort();
+		Ok(CertificateDetails {
+			subject_alt_names,
+			serial_number: leaf.serial_number()?,
+			not_before: leaf.not_before()?,
+			not_after: leaf.not_after()?,
+			fingerprint_sha256: leaf.fingerprint(HashFunction::Sha256)?,
+			chain_fingerprints_sha256: chain
+				.iter()
+				.skip(1)
+				.map(|c| c.fingerprint(HashFunction::Sha256))
+				.collect::<Result<_, _>>()?,
+			tlsa_2_1_1: issuer.tlsa_rdata(2)?,
+			tlsa_3_1_1: leaf.tlsa_rdata(3)?,
+		})
+	}
+
+	/// Placeholder details used to check the templates of the hooks.
+	pub fn sample(subject_alt_names: Vec<String>) -> Self {
+		CertificateDetails {
+			subject_alt_names,
+			serial_number: "01".to_string(),
+			not_before: now(),
+			not_after: now(),
+			fingerprint_sha256: "fingerprint".to_string(),
+			chain_fingerprints_sha256: vec!["fingerprint".to_string()],
+			tlsa_2_1_1: "tlsa".to_string(),
+			tlsa_3_1_1: "tlsa".to_string(),
+		}
+	}
+}
+
 #[derive(Clone, Serialize)]
 pub struct ChallengeHookData {
+	pub account_contacts: Vec<String>,
+	pub account_name: String,
+	pub account_thumbprint: String,
+	pub account_url: String,
 	pub identifier: String,
 	pub identifier_tls_alpn: String,
 	pub challenge: String,
 	pub file_name: String,
 	pub proof: String,
+	pub record_name: String,
 	pub is_clean_hook: bool,
+	pub labels: BTreeMap<String, String>,
 	pub env: HashMap<String, String>,
 }
 
@@ -74,28 +256,102 @@ pub struct FileStorageHookData {
 	pub file_name: String,
 	pub file_directory: String,
 	pub file_path: PathBuf,
+	pub endpoint_name: String,
+	pub key_type: String,
+	#[serde(flatten)]
+	pub certificate: CertificateDetails,
+	pub labels: BTreeMap<String, String>,
 	pub env: HashMap<String, String>,
 }
 
 imple_hook_data_env!(FileStorageHookData);
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Serialize)]
+pub struct VersionAdvisoryHookData {
+	pub current_version: String,
+	pub latest_version: String,
+	pub security_fix: bool,
+	pub message: String,
+	pub url: String,
+	pub env: HashMap<String, String>,
+}
+
+imple_hook_data_env!(VersionAdvisoryHookData);
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum HookStdin {
 	File(String),
 	Str(String),
 	None,
 }
 
-#[derive(Clone, Debug)]
+/// Signature of the payload sent to a hook's standard input, which allows the receiving end to
+/// authenticate it. The key is read each time a payload is signed, so it may be rotated without
+/// reloading the configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HookSignature {
+	pub algorithm: JwsSignatureAlgorithm,
+	pub key_file: String,
+}
+
+impl HookSignature {
+	/// Check that the key can be read and used with the algorithm, which is done while the
+	/// configuration is loaded.
+	pub fn check(&self) -> Result<(), Error> {
+		let raw_key =
+			std::fs::read(&self.key_file).map_err(|e| Error::from(e).prefix(&self.key_file))?;
+		self.sign_with(&raw_key, b"").map(|_| ())
+	}
+
+	/// Return the base64url-encoded signature of the data.
+	pub async fn sign(&self, data: &[u8]) -> Result<String, Error> {
+		let raw_key = tokio::fs::read(&self.key_file)
+			.await
+			.map_err(|e| Error::from(e).prefix(&self.key_file))?;
+		self.sign_with(&raw_key, data)
+	}
+
+	/// HMAC keys are read as base64url-encoded strings, other keys as PEM-encoded private keys.
+	fn sign_with(&self, raw_key: &[u8], data: &[u8]) -> Result<String, Error> {
+		let hash_func = match self.algorithm {
+			JwsSignatureAlgorithm::Hs256 => Some(HashFunction::Sha256),
+			JwsSignatureAlgorithm::Hs384 => Some(HashFunction::Sha384),
+			JwsSignatureAlgorithm::Hs512 => Some(HashFunction::Sha512),
+			_ => None,
+		};
+		let signature = match hash_func {
+			Some(hash_func) => {
+				let key = String::from_utf8(raw_key.to_vec())?;
+				let key = b64_decode(key.trim()).map_err(|e| e.prefix(&self.key_file))?;
+				hash_func.hmac(&key, data)?
+			}
+			None => KeyPair::from_pem(raw_key)
+				.map_err(|e| e.prefix(&self.key_file))?
+				.sign(&self.algorithm, data)?,
+		};
+		Ok(b64_encode(&signature))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Hook {
 	pub name: String,
 	pub hook_type: HashSet<HookType>,
 	pub cmd: String,
 	pub args: Option<Vec<String>>,
+	pub cwd: Option<String>,
+	pub umask: Option<u32>,
 	pub stdin: HookStdin,
 	pub stdout: Option<String>,
 	pub stderr: Option<String>,
 	pub allow_failure: bool,
+	pub log_output: bool,
+	pub retries: usize,
+	pub retry_delay: Duration,
+	pub signature: Option<HookSignature>,
+	pub timeout: Option<Duration>,
+	pub uid: Option<u32>,
+	pub gid: Option<u32>,
 }
 
 impl fmt::Display for Hook {
@@ -105,20 +361,137 @@ impl fmt::Display for Hook {
 }
 
 macro_rules! get_hook_output {
-	($logger: expr, $out: expr, $data: expr, $hook_name: expr, $out_name: expr) => {{
+	($logger: expr, $out: expr, $data: expr, $hook: expr, $out_name: expr) => {{
 		match $out {
 			Some(path) => {
 				let path = render_template(path, $data)?;
-				$logger.trace(&format!("hook \"{}\": {}: {path}", $hook_name, $out_name));
+				$logger.trace(&format!("hook \"{}\": {}: {path}", $hook.name, $out_name));
 				let file = File::create(&path)?;
 				Stdio::from(file)
 			}
+			None if $hook.log_output => Stdio::piped(),
 			None => Stdio::null(),
 		}
 	}};
 }
 
-async fn call_single<L, T>(logger: &L, data: &T, hook: &Hook) -> Result<(), Error>
+/// Log each line written by the hook on one of its output streams.
+async fn log_output<L, R>(logger: &L, hook: &Hook, out_name: &str, stream: Option<R>)
+where
+	L: HasLogger,
+	R: AsyncRead + Unpin,
+{
+	if let Some(stream) = stream {
+		let mut lines = BufReader::new(stream).lines();
+		while let Some(line) = lines.next().await {
+			match line {
+				Ok(line) => logger.info(&format!("hook \"{}\": {out_name}: {line}", hook.name)),
+				Err(e) => {
+					logger.debug(&format!("hook \"{}\": {out_name}: {e}", hook.name));
+					break;
+				}
+			}
+		}
+	}
+}
+
+/// Wait for the hook's process to exit while logging its output.
+///
+/// The output is drained until the process exits and then for a bounded
+/// amount of time only: a background child inheriting the pipes must not
+/// prevent the hook from completing.
+async fn wait_hook<L, O, E>(
+	logger: &L,
+	hook: &Hook,
+	cmd: &mut async_process::Child,
+	stdout: Option<O>,
+	stderr: Option<E>,
+) -> Result<ExitStatus, Error>
+where
+	L: HasLogger,
+	O: AsyncRead + Unpin,
+	E: AsyncRead + Unpin,
+{
+	let logs = async {
+		futures::join!(
+			log_output(logger, hook, "stdout", stdout),
+			log_output(logger, hook, "stderr", stderr),
+		)
+	};
+	tokio::pin!(logs);
+	let mut logs_done = false;
+	let status = loop {
+		tokio::select! {
+			status = cmd.status() => break status?,
+			_ = &mut logs, if !logs_done => logs_done = true,
+		}
+	};
+	if !logs_done {
+		let drain = Duration::from_secs(crate::DEFAULT_HOOK_OUTPUT_DRAIN_SEC);
+		if tokio::time::timeout(drain, logs).await.is_err() {
+			logger.debug(&format!(
+				"hook \"{}\": output still open after exit, no longer logged",
+				hook.name
+			));
+		}
+	}
+	Ok(status)
+}
+
+async fn run_single<L, T>(
+	logger: &L,
+	recorder: &HookRecorder,
+	data: &T,
+	hook: &Hook,
+) -> Result<ExitStatus, Error>
+where
+	L: HasLogger,
+	T: Clone + HookEnvData + Serialize,
+{
+	let timestamp = acme_common::now();
+	let start = Instant::now();
+	let res = run_hook(logger, data, hook).await;
+	let (exit_code, success, error) = match &res {
+		Ok(status) => (status.code(), status.success(), None),
+		Err(e) => (None, false, Some(e.message.to_owned())),
+	};
+	let execution = HookExecution {
+		timestamp,
+		hook: hook.name.to_owned(),
+		cmd: hook.cmd.to_owned(),
+		duration_ms: start.elapsed().as_millis() as u64,
+		exit_code,
+		success,
+		error,
+	};
+	if let Err(e) = recorder.record(&execution).await {
+		logger.warn(&format!("unable to save the hook history: {e}"));
+	}
+	res
+}
+
+/// Set the umask, the user and the group the hook is run with. Those have no equivalent on the
+/// other platforms, where the permissions are inherited from the parent directory.
+#[cfg(unix)]
+fn set_unix_options(cmd: &mut Command, hook: &Hook) {
+	if let Some(umask) = hook.umask {
+		// Safety: umask is async-signal-safe and cannot fail.
+		unsafe {
+			cmd.pre_exec(move || {
+				libc::umask(umask as libc::mode_t);
+				Ok(())
+			});
+		}
+	}
+	if let Some(gid) = hook.gid {
+		cmd.gid(gid);
+	}
+	if let Some(uid) = hook.uid {
+		cmd.uid(uid);
+	}
+}
+
+async fn run_hook<L, T>(logger: &L, data: &T, hook: &Hook) -> Result<ExitStatus, Error>
 where
 	L: HasLogger,
 	T: Clone + HookEnvData + Serialize,
@@ -137,21 +510,58 @@ where
 	};
 	logger.trace(&format!("hook \"{}\": cmd: {}", hook.name, hook.cmd));
 	logger.trace(&format!("hook \"{}\": args: {args:?}", hook.name));
-	let mut cmd = Command::new(&hook.cmd)
-		.envs(data.get_env())
+	// A signed payload has to be entirely read before being sent.
+	let signed_payload = match &hook.signature {
+		Some(sig) => {
+			let payload = match &hook.stdin {
+				HookStdin::Str(s) => render_template(s, &data)?.into_bytes(),
+				HookStdin::File(f) => {
+					let file_name = render_template(f, &data)?;
+					tokio::fs::read(&file_name)
+						.await
+						.map_err(|e| Error::from(e).prefix(&file_name))?
+				}
+				HookStdin::None => vec![],
+			};
+			let signature = sig.sign(&payload).await?;
+			Some((payload, signature, sig.algorithm.to_string()))
+		}
+		None => None,
+	};
+	let mut cmd = Command::new(&hook.cmd);
+	if let Some(cwd) = &hook.cwd {
+		let cwd = render_template(cwd, &data)?;
+		logger.trace(&format!("hook \"{}\": working directory: {cwd}", hook.name));
+		cmd.current_dir(cwd);
+	}
+	#[cfg(unix)]
+	set_unix_options(&mut cmd, hook);
+	cmd.envs(data.get_env());
+	// Set after the hook's environment so it can neither be overridden nor forged.
+	match &signed_payload {
+		Some((_, signature, algorithm)) => {
+			cmd.env("ACMED_SIGNATURE", signature)
+				.env("ACMED_SIGNATURE_ALGORITHM", algorithm);
+		}
+		None => {
+			cmd.env_remove("ACMED_SIGNATURE")
+				.env_remove("ACMED_SIGNATURE_ALGORITHM");
+		}
+	}
+	let mut cmd = cmd
 		.args(args)
 		.stdout(get_hook_output!(
 			logger,
 			&hook.stdout,
 			&data,
-			&hook.name,
+			hook,
 			"stdout"
 		))
 		.stderr(get_hook_output!(
 			logger,
 			&hook.stderr,
 			&data,
-			&hook.name,
+			hook,
 			"stderr"
 		))
 		.stdin(match &hook.stdin {
@@ -159,7 +569,12 @@ where
 			HookStdin::None => Stdio::null(),
 		})
 		.spawn()?;
+	if let Some((payload, _, _)) = &signed_payload {
+		let stdin = cmd.stdin.as_mut().ok_or("stdin not found")?;
+		stdin.write_all(payload).await?;
+	}
 	match &hook.stdin {
+		_ if signed_payload.is_some() => {}
 		HookStdin::Str(s) => {
 			let data_in = render_template(s, &data)?;
 			logger.trace(&format!("hook \"{}\": string stdin: {data_in}", hook.name));
@@ -170,34 +585,100 @@ where
 			let file_name = render_template(f, &data)?;
 			logger.trace(&format!("hook \"{}\": file stdin: {file_name}", hook.name));
 			let stdin = cmd.stdin.as_mut().ok_or("stdin not found")?;
-			let file = File::open(&file_name).map_err(|e| Error::from(e).prefix(&file_name))?;
-			let buf_reader = BufReader::new(file);
-			for line in buf_reader.lines() {
-				let line = format!("{}\n", line?);
-				stdin.write_all(line.as_bytes()).await?;
-			}
+			let file = tokio::fs::File::open(&file_name)
+				.await
+				.map_err(|e| Error::from(e).prefix(&file_name))?;
+			futures::io::copy(file.compat(), stdin)
+				.await
+				.map_err(|e| Error::from(e).prefix(&file_name))?;
 		}
 		HookStdin::None => {}
 	}
-	// TODO: add a timeout
-	let status = cmd.status().await?;
-	if !status.success() && !hook.allow_failure {
-		let msg = match status.code() {
-			Some(code) => format!("unrecoverable failure: code {code}").into(),
-			None => "unrecoverable failure".into(),
-		};
-		return Err(msg);
-	}
+	let stdout = cmd.stdout.take();
+	let stderr = cmd.stderr.take();
+	let run = wait_hook(logger, hook, &mut cmd, stdout, stderr);
+	let status = match hook.timeout {
+		Some(timeout) => match tokio::time::timeout(timeout, run).await {
+			Ok(status) => status?,
+			Err(_) => {
+				let _ = cmd.kill();
+				let msg = format!("timed out after {}s", timeout.as_secs_f64());
+				return Err(msg.into());
+			}
+		},
+		None => run.await?,
+	};
 	match status.code() {
 		Some(code) => logger.debug(&format!("hook \"{}\": exited: code {code}", hook.name)),
 		None => logger.debug(&format!("hook \"{}\": exited", hook.name)),
 	};
+	Ok(status)
+}
+
+async fn call_single<L, T>(
+	logger: &L,
+	recorder: &HookRecorder,
+	data: &T,
+	hook: &Hook,
+) -> Result<(), Error>
+where
+	L: HasLogger,
+	T: Clone + HookEnvData + Serialize,
+{
+	let mut attempt = 0;
+	let status = loop {
+		let res = run_single(logger, recorder, data, hook).await;
+		let reason = match &res {
+			Ok(status) if status.success() => return Ok(()),
+			Ok(status) => match status.code() {
+				Some(code) => format!("code {code}"),
+				None => "no exit code".to_string(),
+			},
+			// The templates are not rendered differently on the next attempt.
+			Err(e) if e.kind == ErrorKind::Template => break res?,
+			Err(e) => e.message.to_owned(),
+		};
+		// The failures of the hooks which are allowed to fail are ignored, there is no need to
+		// wait for another attempt.
+		if hook.allow_failure || attempt >= hook.retries {
+			break res?;
+		}
+		attempt += 1;
+		let delay = http::get_retry_delay(hook.retry_delay, (attempt - 1) as u32);
+		logger.info(&format!(
+			"hook \"{}\": attempt {attempt}/{} failed: {reason}, retrying in {}ms",
+			hook.name,
+			hook.retries + 1,
+			delay.as_millis()
+		));
+		sleep(delay).await;
+	};
+	if !status.success() {
+		let reason = match status.code() {
+			Some(code) => format!("failed: code {code}"),
+			None => "failed".to_string(),
+		};
+		report_failure(logger, hook, &reason);
+		if !hook.allow_failure {
+			let msg = match status.code() {
+				Some(code) => format!("unrecoverable failure: code {code}").into(),
+				None => "unrecoverable failure".into(),
+			};
+			return Err(msg);
+		}
+	}
 	Ok(())
 }
 
+fn report_failure<L: HasLogger>(logger: &L, hook: &Hook, reason: &str) {
+	let ignored = if hook.allow_failure { ", ignoring" } else { "" };
+	LogEvent::HookFailed.log(|| logger.warn(&format!("hook \"{}\": {reason}{ignored}", hook.name)));
+}
+
 pub async fn call<L, T>(
 	logger: &L,
-	hooks: &[Hook],
+	recorder: &HookRecorder,
+	hooks: &[Arc<Hook>],
 	data: &T,
 	hook_type: HookType,
 ) -> Result<(), Error>
@@ -206,9 +687,514 @@ where
 	T: Clone + HookEnvData + Serialize,
 {
 	for hook in hooks.iter().filter(|h| h.hook_type.contains(&hook_type)) {
-		call_single(logger, data, hook)
+		call_single(logger, recorder, data, hook)
 			.await
-			.map_err(|e| e.prefix(&hook.name))?;
+			.map_err(|e| {
+				// The templates are checked along with the configuration, their errors are not
+				// caused by the hook itself.
+				let e = e.prefix(&hook.name);
+				match e.kind {
+					ErrorKind::Template => e,
+					_ => e.with_kind(ErrorKind::Hook),
+				}
+			})?;
+	}
+	Ok(())
+}
+
+/// Render the templates of the hooks of the given type against the data without calling them, so
+/// unknown variables and invalid operations are reported when the configuration is checked
+/// instead of during a renewal.
+pub fn check<T: Serialize>(
+	hooks: &[Arc<Hook>],
+	data: &T,
+	hook_type: HookType,
+) -> Result<(), Error> {
+	for hook in hooks.iter().filter(|h| h.hook_type.contains(&hook_type)) {
+		let mut templates: Vec<&String> = hook.args.iter().flatten().collect();
+		templates.extend(hook.cwd.iter().chain(&hook.stdout).chain(&hook.stderr));
+		if let HookStdin::Str(s) | HookStdin::File(s) = &hook.stdin {
+			templates.push(s);
+		}
+		for template in templates {
+			check_template(template, data)
+				.map_err(|e| e.prefix(&format!("hook \"{}\" ({hook_type})", hook.name)))?;
+		}
 	}
 	Ok(())
 }
+
+/// Check the command of the hook can be executed by the given user and group, or the current
+/// ones. Commands without any path separator are looked for in the directories of the `PATH`
+/// environment variable, as they are when the hook is called.
+pub fn check_command(hook: &Hook, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+	let cmd = Path::new(&hook.cmd);
+	let prefix = format!("hook \"{}\": {}", hook.name, hook.cmd);
+	if !hook.cmd.contains(std::path::is_separator) {
+		let path = std::env::var_os("PATH").unwrap_or_default();
+		return match std::env::split_paths(&path)
+			.map(|d| d.join(cmd))
+			.find(|p| Current::is_executable(p))
+		{
+			Some(p) => privileges::check_executable(&p, uid, gid)
+				.map_err(|e| e.prefix(&format!("hook \"{}\"", hook.name))),
+			None => Err(format!("{prefix}: command not found").into()),
+		};
+	}
+	// Relative paths depend on the working directory, which may be a template.
+	if cmd.is_relative() && hook.cwd.is_some() {
+		return Ok(());
+	}
+	if !cmd.exists() {
+		return Err(format!("{prefix}: no such file").into());
+	}
+	if !Current::is_executable(cmd) {
+		return Err(format!("{prefix}: not executable").into());
+	}
+	privileges::check_executable(cmd, uid, gid)
+		.map_err(|e| e.prefix(&format!("hook \"{}\"", hook.name)))
+}
+
+/// Call the hooks of the given type until they succeed. Each hook is called again after
+/// `interval` until it exits successfully or `timeout` is elapsed.
+pub async fn call_until_success<L, T>(
+	logger: &L,
+	recorder: &HookRecorder,
+	hooks: &[Arc<Hook>],
+	data: &T,
+	hook_type: HookType,
+	interval: Duration,
+	timeout: Duration,
+) -> Result<(), Error>
+where
+	L: HasLogger,
+	T: Clone + HookEnvData + Serialize,
+{
+	let deadline = Instant::now() + timeout;
+	for hook in hooks.iter().filter(|h| h.hook_type.contains(&hook_type)) {
+		loop {
+			let status = run_single(logger, recorder, data, hook)
+				.await
+				.map_err(|e| e.prefix(&hook.name))?;
+			if status.success() {
+				break;
+			}
+			if Instant::now() + interval > deadline {
+				let reason = format!("still failing after {} seconds", timeout.as_secs());
+				report_failure(logger, hook, &reason);
+				if hook.allow_failure {
+					break;
+				}
+				let msg = format!(
+					"{}: still failing after {} seconds",
+					hook.name,
+					timeout.as_secs()
+				);
+				return Err(msg.into());
+			}
+			sleep(interval).await;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::{
+		call, check, check_command, Hook, HookExecution, HookRecorder, HookSignature, HookStdin,
+		HookType, VersionAdvisoryHookData,
+	};
+	use crate::logs::HasLogger;
+	use crate::storage::TestDir;
+	use acme_common::crypto::{gen_keypair, JwsSignatureAlgorithm, KeyType};
+	use acme_common::error::ErrorKind;
+	use std::collections::{HashMap, HashSet};
+	use std::os::unix::fs::PermissionsExt;
+	use std::path::Path;
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+	use tokio::time::Instant;
+
+	#[derive(Default)]
+	struct TestLogger(Mutex<Vec<String>>);
+
+	impl HasLogger for TestLogger {
+		fn warn(&self, _msg: &str) {}
+		fn info(&self, msg: &str) {
+			self.0.lock().unwrap().push(msg.to_string());
+		}
+		fn debug(&self, _msg: &str) {}
+		fn trace(&self, _msg: &str) {}
+	}
+
+	fn test_data() -> VersionAdvisoryHookData {
+		VersionAdvisoryHookData {
+			current_version: "0.21.0".to_string(),
+			latest_version: "0.22.0".to_string(),
+			security_fix: false,
+			message: String::new(),
+			url: String::new(),
+			env: HashMap::new(),
+		}
+	}
+
+	fn sh_hook(name: &str, script: &str) -> Hook {
+		Hook {
+			name: name.to_string(),
+			hook_type: HashSet::from([HookType::PostOperation]),
+			cmd: "sh".to_string(),
+			args: Some(vec!["-c".to_string(), script.to_string()]),
+			cwd: None,
+			umask: None,
+			stdin: HookStdin::None,
+			stdout: None,
+			stderr: None,
+			allow_failure: false,
+			log_output: false,
+			retries: 0,
+			retry_delay: Duration::from_millis(1),
+			signature: None,
+			timeout: None,
+			uid: None,
+			gid: None,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_log_output() {
+		let hook = Hook {
+			log_output: true,
+			..sh_hook("output", "echo a; echo b >&2")
+		};
+		let logger = TestLogger::default();
+		call(
+			&logger,
+			&HookRecorder::default(),
+			&[Arc::new(hook)],
+			&test_data(),
+			HookType::PostOperation,
+		)
+		.await
+		.unwrap();
+		let lines = logger.0.into_inner().unwrap();
+		assert!(lines.contains(&"hook \"output\": stdout: a".to_string()));
+		assert!(lines.contains(&"hook \"output\": stderr: b".to_string()));
+	}
+
+	/// Hook failing until it has been called the given number of times.
+	fn flaky_hook(counter: &Path, failures: usize, retries: usize) -> Arc<Hook> {
+		let cmd = format!(
+			"echo >> {0}; test $(wc -l < {0}) -gt {failures}",
+			counter.display()
+		);
+		Arc::new(Hook {
+			retries,
+			..sh_hook("flaky", &cmd)
+		})
+	}
+
+	#[tokio::test]
+	async fn test_background_child() {
+		let hook = Hook {
+			log_output: true,
+			..sh_hook("background", "echo a; sleep 30 &")
+		};
+		let start = Instant::now();
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&[Arc::new(hook)],
+			&test_data(),
+			HookType::PostOperation,
+		)
+		.await;
+		assert!(res.is_ok());
+		assert!(start.elapsed() < Duration::from_secs(20));
+	}
+
+	#[tokio::test]
+	async fn test_timeout() {
+		let hook = Hook {
+			timeout: Some(Duration::from_millis(100)),
+			..sh_hook("timeout", "sleep 30")
+		};
+		let start = Instant::now();
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&[Arc::new(hook)],
+			&test_data(),
+			HookType::PostOperation,
+		)
+		.await;
+		assert!(res.is_err());
+		assert!(start.elapsed() < Duration::from_secs(20));
+	}
+
+	#[tokio::test]
+	async fn test_cwd_umask() {
+		let tmp = TestDir::new("hooks-cwd");
+		let dir = tmp.path();
+		let hook = Hook {
+			cwd: Some(dir.display().to_string()),
+			umask: Some(0o077),
+			..sh_hook("cwd", "touch file")
+		};
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&[Arc::new(hook)],
+			&test_data(),
+			HookType::PostOperation,
+		)
+		.await;
+		let mode = std::fs::metadata(dir.join("file")).map(|m| m.permissions().mode());
+		assert!(res.is_ok());
+		assert_eq!(mode.unwrap() & 0o777, 0o600);
+	}
+
+	#[tokio::test]
+	async fn test_file_stdin() {
+		let tmp = TestDir::new("hooks-stdin");
+		let dir = tmp.path();
+		std::fs::write(dir.join("in"), "some input\n").unwrap();
+		let hook = Hook {
+			cwd: Some(dir.display().to_string()),
+			stdin: HookStdin::File(dir.join("in").display().to_string()),
+			..sh_hook("stdin", "cat > out")
+		};
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&[Arc::new(hook)],
+			&test_data(),
+			HookType::PostOperation,
+		)
+		.await;
+		let out = std::fs::read_to_string(dir.join("out"));
+		assert!(res.is_ok());
+		assert_eq!(out.unwrap(), "some input\n");
+	}
+
+	#[tokio::test]
+	async fn test_retries() {
+		let dir = TestDir::new("hooks-retries");
+		let counter = dir.path().join("counter");
+		let data = test_data();
+		let hooks = [flaky_hook(&counter, 2, 2)];
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&hooks,
+			&data,
+			HookType::PostOperation,
+		)
+		.await;
+		let nb_calls = std::fs::read_to_string(&counter).unwrap().lines().count();
+		std::fs::remove_file(&counter).unwrap();
+		assert!(res.is_ok());
+		assert_eq!(nb_calls, 3);
+		let hooks = [flaky_hook(&counter, 2, 1)];
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&hooks,
+			&data,
+			HookType::PostOperation,
+		)
+		.await;
+		let nb_calls = std::fs::read_to_string(&counter).unwrap().lines().count();
+		std::fs::remove_file(&counter).unwrap();
+		assert!(res.is_err());
+		assert_eq!(nb_calls, 2);
+		let hooks = [Arc::new(Hook {
+			allow_failure: true,
+			..(*flaky_hook(&counter, 2, 2)).clone()
+		})];
+		let logger = TestLogger::default();
+		let res = call(
+			&logger,
+			&HookRecorder::default(),
+			&hooks,
+			&data,
+			HookType::PostOperation,
+		)
+		.await;
+		let nb_calls = std::fs::read_to_string(&counter).unwrap().lines().count();
+		std::fs::remove_file(&counter).unwrap();
+		assert!(res.is_ok());
+		assert_eq!(nb_calls, 1);
+		assert!(!logger
+			.0
+			.lock()
+			.unwrap()
+			.iter()
+			.any(|m| m.contains("retrying")));
+		let hooks = [Arc::new(Hook {
+			retries: 2,
+			..sh_hook("template", "echo {{ current_version | unknown_filter }}")
+		})];
+		let logger = TestLogger::default();
+		let res = call(
+			&logger,
+			&HookRecorder::default(),
+			&hooks,
+			&data,
+			HookType::PostOperation,
+		)
+		.await;
+		assert_eq!(res.unwrap_err().kind, ErrorKind::Template);
+		assert!(!logger
+			.0
+			.lock()
+			.unwrap()
+			.iter()
+			.any(|m| m.contains("retrying")));
+	}
+
+	#[tokio::test]
+	async fn test_hook_history() {
+		let dir = TestDir::new("hooks-history");
+		let state_dir = dir.path().display().to_string();
+		let recorder =
+			HookRecorder::new(Some("test_rsa2048".to_string())).with_history(&state_dir, 2);
+		for name in ["a", "b", "c"] {
+			call(
+				&TestLogger::default(),
+				&recorder,
+				&[Arc::new(sh_hook(name, "true"))],
+				&test_data(),
+				HookType::PostOperation,
+			)
+			.await
+			.unwrap();
+		}
+		let names =
+			|h: Vec<HookExecution>| -> Vec<String> { h.into_iter().map(|e| e.hook).collect() };
+		let history = recorder.get_history().await;
+		let reloaded = HookRecorder::new(Some("test_rsa2048".to_string()))
+			.with_history(&state_dir, 2)
+			.get_history()
+			.await;
+		let disabled = HookRecorder::new(Some("test_rsa2048".to_string()))
+			.with_history(&state_dir, 0)
+			.get_history()
+			.await;
+		assert_eq!(names(history.unwrap()), vec!["b", "c"]);
+		assert_eq!(names(reloaded.unwrap()), vec!["b", "c"]);
+		assert!(disabled.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_signature_env() {
+		let tmp = TestDir::new("hooks-sig-env");
+		let dir = tmp.path();
+		std::fs::write(dir.join("key"), "a2V5\n").unwrap();
+		let sig = HookSignature {
+			algorithm: JwsSignatureAlgorithm::Hs256,
+			key_file: dir.join("key").display().to_string(),
+		};
+		let script = format!(
+			"cat > /dev/null; echo \"$ACMED_SIGNATURE\" > {}",
+			dir.join("out").display()
+		);
+		let hook = Hook {
+			stdin: HookStdin::Str("payload".to_string()),
+			signature: Some(sig.clone()),
+			..sh_hook("signed", &script)
+		};
+		let mut data = test_data();
+		data.env
+			.insert("ACMED_SIGNATURE".to_string(), "forged".to_string());
+		let res = call(
+			&TestLogger::default(),
+			&HookRecorder::default(),
+			&[Arc::new(hook)],
+			&data,
+			HookType::PostOperation,
+		)
+		.await;
+		let out = std::fs::read_to_string(dir.join("out"));
+		let expected = sig.sign(b"payload").await;
+		assert!(res.is_ok());
+		assert_eq!(out.unwrap().trim(), expected.unwrap());
+	}
+
+	#[tokio::test]
+	async fn test_hmac_signature() {
+		let dir = TestDir::new("hooks-hmac");
+		let key_file = dir.path().join("key");
+		std::fs::write(&key_file, "a2V5\n").unwrap();
+		let sig = HookSignature {
+			algorithm: JwsSignatureAlgorithm::Hs256,
+			key_file: key_file.display().to_string(),
+		};
+		let res = sig
+			.sign(b"The quick brown fox jumps over the lazy dog")
+			.await;
+		assert_eq!(res.unwrap(), "97yD9DBThCSxMpjmqm-xQ-9NWaFJRhdZl0edvC0aPNg");
+	}
+
+	#[tokio::test]
+	async fn test_key_pair_signature() {
+		let dir = TestDir::new("hooks-sig");
+		let key_file = dir.path().join("key.pem");
+		let kp = gen_keypair(KeyType::EcdsaP256).unwrap();
+		std::fs::write(&key_file, kp.private_key_to_pem().unwrap()).unwrap();
+		let mut sig = HookSignature {
+			algorithm: JwsSignatureAlgorithm::Es256,
+			key_file: key_file.display().to_string(),
+		};
+		let res = sig.sign(b"payload").await;
+		assert!(sig.check().is_ok());
+		sig.algorithm = JwsSignatureAlgorithm::Rs256;
+		let res_invalid = sig.sign(b"payload").await;
+		assert!(res.is_ok());
+		assert!(res_invalid.is_err());
+		assert!(sig.check().is_err());
+	}
+
+	#[test]
+	fn test_check() {
+		let hook = Hook {
+			stdin: HookStdin::Str("{{ current_version }} -> {{ latest_version }}".to_string()),
+			..sh_hook("check", "echo {{ env.PATH }}")
+		};
+		let hooks = [Arc::new(hook)];
+		let mut data = test_data();
+		data.env.insert("PATH".to_string(), "/bin".to_string());
+		assert!(check(&hooks, &data, HookType::PostOperation).is_ok());
+		let hook = Hook {
+			stdout: Some("{{ identifier }}.log".to_string()),
+			..(*hooks[0]).clone()
+		};
+		let hooks = [Arc::new(hook)];
+		let e = check(&hooks, &data, HookType::PostOperation).unwrap_err();
+		assert!(e.message.starts_with("hook \"check\" (post-operation): "));
+		assert!(check(&hooks, &data, HookType::VersionAdvisory).is_ok());
+	}
+
+	#[test]
+	fn test_check_command() {
+		assert!(check_command(&sh_hook("sh", "true"), None, None).is_ok());
+		let hook = Hook {
+			cmd: "acmed-no-such-command".to_string(),
+			..sh_hook("missing", "true")
+		};
+		let e = check_command(&hook, None, None).unwrap_err();
+		assert_eq!(
+			e.message,
+			"hook \"missing\": acmed-no-such-command: command not found"
+		);
+		let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+		let hook = Hook {
+			cmd: manifest.to_string(),
+			..sh_hook("data", "true")
+		};
+		let e = check_command(&hook, None, None).unwrap_err();
+		assert_eq!(
+			e.message,
+			format!("hook \"data\": {manifest}: not executable")
+		);
+	}
+}
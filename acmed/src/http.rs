@@ -1,20 +1,38 @@
 use crate::acme_proto::structs::{AcmeError, HttpApiError};
-use crate::config::NamedAcmeResource;
-use crate::endpoint::Endpoint;
-#[cfg(feature = "crypto_openssl")]
+use crate::config::{IpFamily, NamedAcmeResource};
+use crate::endpoint::{CaRateLimitStatus, Endpoint};
 use acme_common::error::Error;
+#[cfg(feature = "crypto_openssl")]
+use acme_common::now;
+use hyper::client::connect::dns::Name;
+use rand::{thread_rng, Rng};
+use reqwest::dns::{Addrs, Resolve, Resolving};
 use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{header, Client, ClientBuilder, Response};
+use reqwest::{header, Client, ClientBuilder, Proxy, RequestBuilder, Response};
+use std::convert::TryFrom;
 use std::fs::File;
+use std::future::Future;
 #[cfg(feature = "crypto_openssl")]
 use std::io::prelude::*;
-use std::{thread, time};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub const CONTENT_TYPE_JOSE: &str = "application/jose+json";
 pub const CONTENT_TYPE_JSON: &str = "application/json";
 pub const CONTENT_TYPE_PEM: &str = "application/pem-certificate-chain";
 pub const HEADER_NONCE: &str = "Replay-Nonce";
 pub const HEADER_LOCATION: &str = "Location";
+pub const HEADER_LINK: &str = "Link";
+
+tokio::task_local! {
+	static REQUEST_TIMEOUT: Option<Duration>;
+}
+
+/// Run the given future with a specific timeout applied to every HTTP request it makes.
+pub async fn with_timeout<F: Future>(timeout: Option<Duration>, f: F) -> F::Output {
+	REQUEST_TIMEOUT.scope(timeout, f).await
+}
 
 pub struct ValidHttpResponse {
 	headers: HeaderMap,
@@ -24,14 +42,21 @@ pub struct ValidHttpResponse {
 impl ValidHttpResponse {
 	pub fn get_header(&self, name: &str) -> Option<String> {
 		match self.headers.get(name) {
-			Some(r) => match header_to_string(r) {
-				Ok(h) => Some(h),
-				Err(_) => None,
-			},
+			Some(r) => header_to_string(r).ok(),
 			None => None,
 		}
 	}
 
+	/// Return the URLs of the `Link` headers having the given relation type.
+	pub fn get_links(&self, rel: &str) -> Vec<String> {
+		self.headers
+			.get_all(HEADER_LINK)
+			.iter()
+			.filter_map(|h| header_to_string(h).ok())
+			.flat_map(|h| parse_links(&h, rel))
+			.collect()
+	}
+
 	pub fn json<T>(&self) -> Result<T, Error>
 	where
 		T: serde::de::DeserializeOwned,
@@ -57,11 +82,18 @@ pub enum HttpError {
 impl HttpError {
 	pub fn in_err(error: HttpError) -> Error {
 		match error {
-			HttpError::ApiError(e) => e.to_string().into(),
+			HttpError::ApiError(e) => e.into(),
 			HttpError::GenericError(e) => e,
 		}
 	}
 
+	pub fn get_api_error(&self) -> Option<&HttpApiError> {
+		match self {
+			HttpError::ApiError(aerr) => Some(aerr),
+			HttpError::GenericError(_) => None,
+		}
+	}
+
 	pub fn is_acme_err(&self, acme_error: AcmeError) -> bool {
 		match self {
 			HttpError::ApiError(aerr) => aerr.get_acme_type() == acme_error,
@@ -100,6 +132,27 @@ impl From<reqwest::Error> for HttpError {
 	}
 }
 
+fn parse_links(header: &str, rel: &str) -> Vec<String> {
+	header
+		.split(',')
+		.filter_map(|link| {
+			let mut parts = link.split(';').map(|p| p.trim());
+			let url = parts.next()?.strip_prefix('<')?.strip_suffix('>')?;
+			let has_rel = parts.any(|p| match p.split_once('=') {
+				Some((k, v)) => {
+					k.trim() == "rel" && v.trim().trim_matches('"').split(' ').any(|r| r == rel)
+				}
+				None => false,
+			});
+			if has_rel {
+				Some(url.to_string())
+			} else {
+				None
+			}
+		})
+		.collect()
+}
+
 fn is_nonce(data: &str) -> bool {
 	!data.is_empty()
 		&& data
@@ -125,6 +178,122 @@ fn update_nonce(endpoint: &mut Endpoint, response: &Response) -> Result<(), Erro
 	Ok(())
 }
 
+/// Record the rate-limit information sent by the CA, if any.
+fn update_ca_rate_limits(endpoint: &Endpoint, headers: &HeaderMap, detail: Option<String>) {
+	let mut status = parse_rate_limit_headers(headers, now());
+	if detail.is_some() && status.retry_after.is_none() {
+		// Rate limited without any indication of when to retry.
+		status.retry_after = Some(
+			status
+				.reset
+				.unwrap_or_else(|| now() + crate::DEFAULT_RATE_LIMITED_WAIT_SEC),
+		);
+	}
+	status.detail = detail;
+	if status.is_empty() {
+		return;
+	}
+	status.updated = now();
+	endpoint.ca_rate_limits.update(status);
+}
+
+fn get_first_header(headers: &HeaderMap, names: &[&str]) -> Option<String> {
+	names
+		.iter()
+		.find_map(|n| headers.get(*n))
+		.and_then(|h| header_to_string(h).ok())
+}
+
+fn parse_leading_int(value: &str) -> Option<u64> {
+	let value = value.trim();
+	let end = value
+		.find(|c: char| !c.is_ascii_digit())
+		.unwrap_or(value.len());
+	value[..end].parse().ok()
+}
+
+/// Convert a reset value to a timestamp. Some servers send a number of seconds while others
+/// send a timestamp.
+fn reset_to_timestamp(value: u64, now: u64) -> u64 {
+	if value > 1_000_000_000 {
+		value
+	} else {
+		now + value
+	}
+}
+
+fn parse_rate_limit_headers(headers: &HeaderMap, now: u64) -> CaRateLimitStatus {
+	let mut status = CaRateLimitStatus {
+		limit: get_first_header(headers, &["RateLimit-Limit", "X-RateLimit-Limit"])
+			.and_then(|v| parse_leading_int(&v)),
+		remaining: get_first_header(headers, &["RateLimit-Remaining", "X-RateLimit-Remaining"])
+			.and_then(|v| parse_leading_int(&v)),
+		reset: get_first_header(headers, &["RateLimit-Reset", "X-RateLimit-Reset"])
+			.and_then(|v| parse_leading_int(&v))
+			.map(|v| reset_to_timestamp(v, now)),
+		retry_after: get_first_header(headers, &["Retry-After"])
+			.and_then(|v| parse_retry_after(&v, now)),
+		..Default::default()
+	};
+	// Structured form: `RateLimit: limit=10, remaining=5, reset=30`
+	if let Some(value) = get_first_header(headers, &["RateLimit"]) {
+		for param in value.split([',', ';']) {
+			if let Some((k, v)) = param.split_once('=') {
+				let v = parse_leading_int(v);
+				match k.trim() {
+					"limit" => status.limit = status.limit.or(v),
+					"remaining" => status.remaining = status.remaining.or(v),
+					"reset" => status.reset = status.reset.or(v.map(|v| now + v)),
+					_ => {}
+				}
+			}
+		}
+	}
+	status
+}
+
+/// Parse the value of the `Retry-After` header, which is either a number of seconds or an
+/// HTTP date, into a timestamp.
+pub fn parse_retry_after(value: &str, now: u64) -> Option<u64> {
+	let value = value.trim();
+	match value.parse::<u64>() {
+		Ok(nb) => Some(now + nb),
+		Err(_) => parse_http_date(value),
+	}
+}
+
+/// Parse an HTTP date in the IMF-fixdate format, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(value: &str) -> Option<u64> {
+	let parts: Vec<&str> = value.split_whitespace().collect();
+	if parts.len() != 6 || parts[5] != "GMT" {
+		return None;
+	}
+	let day: i64 = parts[1].parse().ok()?;
+	let month = [
+		"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+	]
+	.iter()
+	.position(|m| *m == parts[2])? as i64
+		+ 1;
+	let year: i64 = parts[3].parse().ok()?;
+	let time: Vec<i64> = parts[4]
+		.split(':')
+		.map(|t| t.parse().ok())
+		.collect::<Option<Vec<i64>>>()?;
+	if time.len() != 3 {
+		return None;
+	}
+	// Days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+	let y = if month <= 2 { year - 1 } else { year };
+	let era = if y >= 0 { y } else { y - 399 } / 400;
+	let yoe = y - era * 400;
+	let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = era * 146097 + doe - 719468;
+	let ts = days * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+	u64::try_from(ts).ok()
+}
+
 fn check_status(response: &Response) -> Result<(), Error> {
 	if !response.status().is_success() {
 		let status = response.status();
@@ -145,7 +314,7 @@ fn header_to_string(header_value: &HeaderValue) -> Result<String, Error> {
 	Ok(s.to_string())
 }
 
-fn get_client(root_certs: &[String]) -> Result<Client, Error> {
+fn get_default_headers() -> HeaderMap {
 	let useragent = format!(
 		"{}/{} ({}) {}",
 		crate::APP_NAME,
@@ -154,23 +323,168 @@ fn get_client(root_certs: &[String]) -> Result<Client, Error> {
 		env!("ACMED_HTTP_LIB_AGENT")
 	);
 	// TODO: allow to change the language
-	let mut client_builder = ClientBuilder::new();
 	let mut default_headers = HeaderMap::new();
 	default_headers.append(header::ACCEPT_LANGUAGE, "en-US,en;q=0.5".parse().unwrap());
 	default_headers.append(header::USER_AGENT, useragent.parse().unwrap());
-	client_builder = client_builder.default_headers(default_headers);
-	for crt_file in root_certs.iter() {
+	default_headers
+}
+
+/// Return an HTTP client suitable for the third-party APIs, such as the DNS providers' ones.
+pub fn get_api_client() -> Result<Client, Error> {
+	let client = ClientBuilder::new()
+		.default_headers(get_default_headers())
+		.build()?;
+	Ok(client)
+}
+
+/// Return the endpoint's HTTP client. The client is built once and then shared between every
+/// request sent to the endpoint, which allows connections to be reused.
+pub fn get_client(endpoint: &mut Endpoint) -> Result<Client, Error> {
+	if let Some(client) = &endpoint.client {
+		return Ok(client.clone());
+	}
+	let mut client_builder = ClientBuilder::new().default_headers(get_default_headers());
+	// Unless a proxy is specified, the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables
+	// are used.
+	if let Some(proxy) = &endpoint.proxy {
+		let proxy = Proxy::all(proxy).map_err(|e| Error::from(e).prefix("invalid proxy"))?;
+		client_builder = client_builder.proxy(proxy);
+	}
+	if !endpoint.system_root_certificates {
+		client_builder = client_builder.tls_built_in_root_certs(false);
+	}
+	if endpoint.force_ipv4 || endpoint.dual_stack_preference.is_some() {
+		client_builder = client_builder.dns_resolver(Arc::new(FamilyResolver {
+			preference: endpoint.dual_stack_preference,
+			ipv4_only: endpoint.force_ipv4,
+		}));
+	}
+	for crt_file in endpoint.root_certificates.iter() {
 		#[cfg(feature = "crypto_openssl")]
 		{
-			let mut buff = Vec::new();
-			File::open(crt_file)
-				.map_err(|e| Error::from(e).prefix(crt_file))?
-				.read_to_end(&mut buff)?;
-			let crt = reqwest::Certificate::from_pem(&buff)?;
+			let crt = load_root_certificate(crt_file)?;
 			client_builder = client_builder.add_root_certificate(crt);
 		}
 	}
-	Ok(client_builder.build()?)
+	let client = client_builder.build()?;
+	endpoint.client = Some(client.clone());
+	Ok(client)
+}
+
+/// Resolver filtering or sorting the addresses of the hosts by address family. The connector
+/// tries the family of the first address, then falls back to the other one if the connection
+/// takes too long, hence the preferred family is put first.
+struct FamilyResolver {
+	preference: Option<IpFamily>,
+	ipv4_only: bool,
+}
+
+impl Resolve for FamilyResolver {
+	fn resolve(&self, name: Name) -> Resolving {
+		let preference = self.preference;
+		let ipv4_only = self.ipv4_only;
+		Box::pin(async move {
+			let host = name.as_str();
+			let addrs = tokio::net::lookup_host((host, 0)).await?.collect();
+			let addrs = sort_addresses(addrs, preference, ipv4_only);
+			if addrs.is_empty() {
+				let family = if ipv4_only { " IPv4" } else { "" };
+				let e = Error::from(format!("{host}: no{family} address found"));
+				return Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>);
+			}
+			Ok(Box::new(addrs.into_iter()) as Addrs)
+		})
+	}
+}
+
+fn sort_addresses(
+	mut addrs: Vec<SocketAddr>,
+	preference: Option<IpFamily>,
+	ipv4_only: bool,
+) -> Vec<SocketAddr> {
+	if ipv4_only {
+		addrs.retain(|a| a.is_ipv4());
+	}
+	if let Some(family) = preference {
+		// The sort is stable: within each family, the resolver's order is kept.
+		addrs.sort_by_key(|a| a.is_ipv4() != (family == IpFamily::Ipv4));
+	}
+	addrs
+}
+
+pub fn set_timeout(request: RequestBuilder) -> RequestBuilder {
+	match REQUEST_TIMEOUT.try_with(|t| *t) {
+		Ok(Some(timeout)) => request.timeout(timeout),
+		_ => request,
+	}
+}
+
+#[cfg(feature = "crypto_openssl")]
+pub fn load_root_certificate(crt_file: &str) -> Result<reqwest::Certificate, Error> {
+	let mut buff = Vec::new();
+	File::open(crt_file)
+		.map_err(|e| Error::from(e).prefix(crt_file))?
+		.read_to_end(&mut buff)
+		.map_err(|e| Error::from(e).prefix(crt_file))?;
+	reqwest::Certificate::from_pem(&buff).map_err(|e| Error::from(e).prefix(crt_file))
+}
+
+/// Check that the web server serving the given identifier answers the http-01 challenge with
+/// the expected key authorization. As the CA does, the proxies are not used and the certificates
+/// of the HTTPS servers the requests may be redirected to are not verified.
+pub async fn check_http_01_proof(
+	identifier: &str,
+	token: &str,
+	proof: &str,
+	local_address: Option<IpAddr>,
+) -> Result<(), Error> {
+	let host = if identifier.contains(':') {
+		format!("[{identifier}]")
+	} else {
+		identifier.to_string()
+	};
+	let url = format!("http://{host}/.well-known/acme-challenge/{token}");
+	let mut client_builder = ClientBuilder::new()
+		.no_proxy()
+		.danger_accept_invalid_certs(true)
+		.local_address(local_address);
+	if let Ok(Some(timeout)) = REQUEST_TIMEOUT.try_with(|t| *t) {
+		client_builder = client_builder.timeout(timeout);
+	}
+	let response = client_builder
+		.build()?
+		.get(&url)
+		.send()
+		.await
+		.map_err(|e| Error::from(e).prefix(&url))?;
+	check_status(&response).map_err(|e| e.prefix(&url))?;
+	let body = response.text().await?;
+	if body.trim() != proof {
+		let msg = format!("{url}: unexpected key authorization: {}", body.trim());
+		return Err(msg.into());
+	}
+	Ok(())
+}
+
+/// Delay to wait before the given retry, using a jittered exponential backoff.
+pub fn get_retry_delay(base: Duration, retry: u32) -> Duration {
+	let max = Duration::from_secs(crate::DEFAULT_HTTP_FAIL_MAX_WAIT_SEC);
+	let delay = base
+		.saturating_mul(2_u32.saturating_pow(retry))
+		.min(max.max(base));
+	let half = delay / 2;
+	half + half.mul_f64(thread_rng().gen::<f64>())
+}
+
+async fn wait_before_retry(endpoint: &Endpoint, url: &str, attempt: usize, error: &str) {
+	let delay = get_retry_delay(endpoint.http_retry_delay, (attempt - 1) as u32);
+	log::debug!(
+		"endpoint \"{}\": {url}: attempt {attempt}/{} failed: {error}, retrying in {}ms",
+		endpoint.name,
+		endpoint.http_max_attempts,
+		delay.as_millis()
+	);
+	tokio::time::sleep(delay).await;
 }
 
 pub async fn get(
@@ -178,18 +492,34 @@ pub async fn get(
 	url: &str,
 	resource: Option<NamedAcmeResource>,
 ) -> Result<ValidHttpResponse, HttpError> {
-	let client = get_client(&endpoint.root_certificates)?;
-	rate_limit(endpoint, resource, url).await;
-	let response = client
-		.get(url)
-		.header(header::ACCEPT, CONTENT_TYPE_JSON)
-		.send()
-		.await?;
-	update_nonce(endpoint, &response)?;
-	check_status(&response)?;
-	ValidHttpResponse::from_response(response)
-		.await
-		.map_err(HttpError::from)
+	let client = get_client(endpoint)?;
+	let mut attempt = 0;
+	loop {
+		attempt += 1;
+		rate_limit(endpoint, resource, url).await;
+		let response = set_timeout(client.get(url))
+			.header(header::ACCEPT, CONTENT_TYPE_JSON)
+			.send()
+			.await;
+		let error = match response {
+			Ok(response) => {
+				update_nonce(endpoint, &response)?;
+				update_ca_rate_limits(endpoint, response.headers(), None);
+				if !response.status().is_server_error() || attempt >= endpoint.http_max_attempts {
+					check_status(&response)?;
+					return ValidHttpResponse::from_response(response)
+						.await
+						.map_err(HttpError::from);
+				}
+				check_status(&response).unwrap_err()
+			}
+			Err(e) if attempt >= endpoint.http_max_attempts => {
+				return Err(e.into());
+			}
+			Err(e) => e.into(),
+		};
+		wait_before_retry(endpoint, url, attempt, &error.message).await;
+	}
 }
 
 pub async fn post<F>(
@@ -203,38 +533,79 @@ pub async fn post<F>(
 where
 	F: Fn(&str, &str) -> Result<String, Error>,
 {
-	let client = get_client(&endpoint.root_certificates)?;
-	if endpoint.nonce.is_none() {
-		let _ = new_nonce(endpoint).await;
-	}
-	for _ in 0..crate::DEFAULT_HTTP_FAIL_NB_RETRY {
-		let mut request = client.post(url);
+	let client = get_client(endpoint)?;
+	let mut attempt = 0;
+	loop {
+		attempt += 1;
+		if endpoint.nonce.is_none() {
+			let _ = new_nonce(endpoint).await;
+		}
+		let mut request = set_timeout(client.post(url));
 		request = request.header(header::ACCEPT, accept);
 		request = request.header(header::CONTENT_TYPE, content_type);
-		let nonce = &endpoint.nonce.clone().unwrap_or_default();
-		let body = data_builder(nonce, url)?;
+		// A nonce can only be used once: unless the response provides a new one, a fresh nonce
+		// will be requested.
+		let nonce = endpoint.nonce.take().unwrap_or_default();
+		let body = data_builder(&nonce, url)?;
 		rate_limit(endpoint, resource, url).await;
 		log::trace!("POST request body: {body}");
-		let response = request.body(body).send().await?;
+		let response = match request.body(body).send().await {
+			Ok(r) => r,
+			Err(e) => {
+				if attempt >= endpoint.http_max_attempts {
+					return Err(e.into());
+				}
+				wait_before_retry(endpoint, url, attempt, &e.to_string()).await;
+				continue;
+			}
+		};
 		update_nonce(endpoint, &response)?;
-		match check_status(&response) {
+		let status_err = match check_status(&response) {
 			Ok(_) => {
+				update_ca_rate_limits(endpoint, response.headers(), None);
 				return ValidHttpResponse::from_response(response)
 					.await
 					.map_err(HttpError::from);
 			}
-			Err(_) => {
-				let resp = ValidHttpResponse::from_response(response).await?;
-				let api_err = resp.json::<HttpApiError>()?;
+			Err(e) => e,
+		};
+		let is_server_error = response.status().is_server_error();
+		let resp = ValidHttpResponse::from_response(response).await?;
+		let error: HttpError = match resp.json::<HttpApiError>() {
+			Ok(api_err) => {
 				let acme_err = api_err.get_acme_type();
+				let api_err = if acme_err == AcmeError::UserActionRequired {
+					let tos = resp.get_links("terms-of-service").into_iter().next();
+					api_err.with_terms_of_service(tos)
+				} else {
+					api_err
+				};
+				let detail = if acme_err == AcmeError::RateLimited {
+					Some(api_err.to_string())
+				} else {
+					None
+				};
+				update_ca_rate_limits(endpoint, &resp.headers, detail);
+				if acme_err == AcmeError::RateLimited || resp.get_header("Retry-After").is_some() {
+					// The server asked to wait: retrying now would be pointless.
+					return Err(api_err.into());
+				}
 				if !acme_err.is_recoverable() {
 					return Err(api_err.into());
 				}
+				api_err.into()
 			}
+			Err(_) if is_server_error => status_err.into(),
+			Err(e) => {
+				return Err(e.into());
+			}
+		};
+		if attempt >= endpoint.http_max_attempts {
+			return Err(error);
 		}
-		thread::sleep(time::Duration::from_secs(crate::DEFAULT_HTTP_FAIL_WAIT_SEC));
+		let msg = HttpError::in_err(error).message;
+		wait_before_retry(endpoint, url, attempt, &msg).await;
 	}
-	Err("too much errors, will not retry".into())
 }
 
 pub async fn post_jose<F>(
@@ -259,7 +630,14 @@ where
 
 #[cfg(test)]
 mod tests {
-	use super::is_nonce;
+	use super::{
+		get_retry_delay, is_nonce, parse_http_date, parse_links, parse_rate_limit_headers,
+		sort_addresses,
+	};
+	use crate::config::IpFamily;
+	use reqwest::header::HeaderMap;
+	use std::net::SocketAddr;
+	use std::time::Duration;
 
 	#[test]
 	fn test_nonce_valid() {
@@ -292,4 +670,109 @@ mod tests {
 			assert!(!is_nonce(n));
 		}
 	}
+
+	#[test]
+	fn test_parse_links() {
+		let header = "<https://example.org/dir>;rel=\"index\", <https://example.org/cert/1>; rel=\"alternate\", <https://example.org/cert/2>; rel=alternate";
+		assert_eq!(
+			parse_links(header, "alternate"),
+			vec![
+				"https://example.org/cert/1".to_string(),
+				"https://example.org/cert/2".to_string()
+			]
+		);
+		assert_eq!(
+			parse_links(header, "index"),
+			vec!["https://example.org/dir".to_string()]
+		);
+		assert!(parse_links(header, "up").is_empty());
+	}
+
+	#[test]
+	fn test_parse_http_date() {
+		assert_eq!(
+			parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+			Some(784111777)
+		);
+		assert_eq!(
+			parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT"),
+			Some(1709164800)
+		);
+		assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+		assert_eq!(parse_http_date("120"), None);
+	}
+
+	#[test]
+	fn test_parse_rate_limit_headers() {
+		let now = 1_700_000_000;
+		let mut headers = HeaderMap::new();
+		assert!(parse_rate_limit_headers(&headers, now).is_empty());
+		headers.insert("RateLimit-Limit", "300, 300;w=10800".parse().unwrap());
+		headers.insert("RateLimit-Remaining", "12".parse().unwrap());
+		headers.insert("RateLimit-Reset", "60".parse().unwrap());
+		headers.insert("Retry-After", "120".parse().unwrap());
+		let status = parse_rate_limit_headers(&headers, now);
+		assert_eq!(status.limit, Some(300));
+		assert_eq!(status.remaining, Some(12));
+		assert_eq!(status.reset, Some(now + 60));
+		assert_eq!(status.retry_after, Some(now + 120));
+
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			"RateLimit",
+			"limit=10, remaining=0, reset=30".parse().unwrap(),
+		);
+		let status = parse_rate_limit_headers(&headers, now);
+		assert_eq!(status.limit, Some(10));
+		assert_eq!(status.remaining, Some(0));
+		assert_eq!(status.reset, Some(now + 30));
+		assert_eq!(status.retry_after, None);
+	}
+
+	#[test]
+	fn test_get_retry_delay() {
+		let base = Duration::from_secs(1);
+		for (retry, max) in [(0, 1), (1, 2), (3, 8), (20, 60)] {
+			let delay = get_retry_delay(base, retry);
+			assert!(delay >= Duration::from_secs(max) / 2);
+			assert!(delay <= Duration::from_secs(max));
+		}
+	}
+
+	#[test]
+	fn test_sort_addresses() {
+		let addrs: Vec<SocketAddr> = [
+			"[2001:db8::1]:0",
+			"192.0.2.1:0",
+			"[2001:db8::2]:0",
+			"192.0.2.2:0",
+		]
+		.iter()
+		.map(|a| a.parse().unwrap())
+		.collect();
+		let to_str = |v: Vec<SocketAddr>| v.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+		assert_eq!(
+			to_str(sort_addresses(addrs.clone(), Some(IpFamily::Ipv4), false)),
+			vec![
+				"192.0.2.1:0",
+				"192.0.2.2:0",
+				"[2001:db8::1]:0",
+				"[2001:db8::2]:0"
+			]
+		);
+		assert_eq!(
+			to_str(sort_addresses(addrs.clone(), Some(IpFamily::Ipv6), false)),
+			vec![
+				"[2001:db8::1]:0",
+				"[2001:db8::2]:0",
+				"192.0.2.1:0",
+				"192.0.2.2:0"
+			]
+		);
+		assert_eq!(
+			to_str(sort_addresses(addrs.clone(), None, true)),
+			vec!["192.0.2.1:0", "192.0.2.2:0"]
+		);
+		assert_eq!(sort_addresses(addrs.clone(), None, false), addrs);
+	}
 }
@@ -1,4 +1,5 @@
 use crate::acme_proto::Challenge;
+use crate::solver::NamedSolver;
 use acme_common::error::Error;
 use acme_common::to_idna;
 use serde::{Deserialize, Serialize};
@@ -42,12 +43,13 @@ impl fmt::Display for IdentifierType {
 	}
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Identifier {
 	pub id_type: IdentifierType,
 	pub value: String,
 	pub challenge: Challenge,
 	pub env: HashMap<String, String>,
+	pub solver: Option<NamedSolver>,
 }
 
 impl Identifier {
@@ -72,6 +74,7 @@ impl Identifier {
 			value,
 			challenge,
 			env: env.clone(),
+			solver: None,
 		})
 	}
 
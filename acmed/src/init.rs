@@ -0,0 +1,463 @@
+use crate::config::Config;
+use crate::platform::{Current, Platform};
+use acme_common::error::Error;
+use clap::ArgMatches;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+const DEFAULT_INIT_CHALLENGE: &str = "http-01";
+const DEFAULT_INIT_ENDPOINT_NAME: &str = "Let's Encrypt v2 production";
+const DEFAULT_INIT_ENDPOINT_URL: &str = "https://acme-v02.api.letsencrypt.org/directory";
+const DEFAULT_INIT_WEBROOT: &str = "/var/www/html";
+const DEFAULT_INIT_TACD_PORT: &str = "5001";
+const INIT_ACCOUNT_NAME: &str = "default";
+const INIT_CONFIG_FILE_MODE: u32 = 0o644;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitChallenge {
+	Http01 { webroot: String },
+	Dns01 { set_cmd: String, clean_cmd: String },
+	TlsAlpn01 { port: String },
+}
+
+impl InitChallenge {
+	fn name(&self) -> &'static str {
+		match self {
+			InitChallenge::Http01 { .. } => "http-01",
+			InitChallenge::Dns01 { .. } => "dns-01",
+			InitChallenge::TlsAlpn01 { .. } => "tls-alpn-01",
+		}
+	}
+
+	fn group_name(&self) -> String {
+		format!("init-{}", self.name())
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct InitOptions {
+	pub domains: Vec<String>,
+	pub challenge: InitChallenge,
+	pub email: String,
+	pub endpoint_url: String,
+	pub tos_agreed: bool,
+	pub accounts_directory: String,
+	pub certificates_directory: String,
+}
+
+fn toml_str(s: &str) -> String {
+	toml::Value::String(s.to_string()).to_string()
+}
+
+fn toml_str_array(lst: &[&str]) -> String {
+	let lst: Vec<String> = lst.iter().map(|s| toml_str(s)).collect();
+	format!("[{}]", lst.join(", "))
+}
+
+struct InitHook<'a> {
+	suffix: &'a str,
+	hook_type: &'a str,
+	cmd: &'a str,
+	args: Vec<&'a str>,
+	stdin_str: Option<&'a str>,
+	silent: bool,
+}
+
+impl<'a> InitHook<'a> {
+	fn new(suffix: &'a str, hook_type: &'a str, cmd: &'a str, args: &[&'a str]) -> Self {
+		InitHook {
+			suffix,
+			hook_type,
+			cmd,
+			args: args.to_vec(),
+			stdin_str: None,
+			silent: false,
+		}
+	}
+
+	fn stdin_str(mut self, stdin_str: &'a str) -> Self {
+		self.stdin_str = Some(stdin_str);
+		self
+	}
+
+	fn silent(mut self) -> Self {
+		self.silent = true;
+		self
+	}
+
+	fn write(&self, out: &mut String, name: &str) {
+		out.push_str("[[hook]]\n");
+		out.push_str(&format!("name = {}\n", toml_str(name)));
+		out.push_str(&format!("type = [{}]\n", toml_str(self.hook_type)));
+		out.push_str(&format!("cmd = {}\n", toml_str(self.cmd)));
+		out.push_str(&format!("args = {}\n", toml_str_array(&self.args)));
+		if let Some(s) = self.stdin_str {
+			out.push_str(&format!("stdin_str = {}\n", toml_str(s)));
+		}
+		if self.silent {
+			out.push_str("stdout = \"/dev/null\"\n");
+		}
+		if self.hook_type.ends_with("-clean") {
+			out.push_str("allow_failure = true\n");
+		}
+		out.push('\n');
+	}
+}
+
+fn push_challenge_hooks(out: &mut String, challenge: &InitChallenge) -> Vec<String> {
+	let http_dir;
+	let http_file;
+	let pid_file = "/run/tacd_{{ identifier }}.pid";
+	let proof_args = ["{{ identifier }}", "{{ proof }}"];
+	let hooks = match challenge {
+		InitChallenge::Http01 { webroot } => {
+			http_dir = format!(
+				"{}/.well-known/acme-challenge",
+				webroot.trim_end_matches('/')
+			);
+			http_file = format!("{http_dir}/{{{{ file_name }}}}");
+			vec![
+				InitHook::new(
+					"mkdir",
+					"challenge-http-01",
+					"mkdir",
+					&["-m", "0755", "-p", &http_dir],
+				),
+				InitHook::new("write", "challenge-http-01", "tee", &[&http_file])
+					.stdin_str("{{ proof }}")
+					.silent(),
+				InitHook::new("chmod", "challenge-http-01", "chmod", &["a+r", &http_file]),
+				InitHook::new(
+					"clean",
+					"challenge-http-01-clean",
+					"rm",
+					&["-f", &http_file],
+				),
+			]
+		}
+		InitChallenge::Dns01 { set_cmd, clean_cmd } => vec![
+			InitHook::new("set", "challenge-dns-01", set_cmd, &proof_args),
+			InitHook::new("clean", "challenge-dns-01-clean", clean_cmd, &proof_args),
+		],
+		InitChallenge::TlsAlpn01 { port } => vec![
+			InitHook::new(
+				"start",
+				"challenge-tls-alpn-01",
+				"tacd",
+				&[
+					"--pid-file",
+					pid_file,
+					"--domain",
+					"{{ identifier_tls_alpn }}",
+					"--acme-ext",
+					"{{ proof }}",
+					"--listen",
+					port,
+				],
+			),
+			InitHook::new(
+				"kill",
+				"challenge-tls-alpn-01-clean",
+				"pkill",
+				&["-F", pid_file],
+			),
+			InitHook::new("rm", "challenge-tls-alpn-01-clean", "rm", &["-f", pid_file]),
+		],
+	};
+	let prefix = challenge.group_name();
+	hooks
+		.iter()
+		.map(|hook| {
+			let name = format!("{prefix}-{}", hook.suffix);
+			hook.write(out, &name);
+			name
+		})
+		.collect()
+}
+
+pub fn generate_config(opts: &InitOptions) -> String {
+	let mut out = String::new();
+	out.push_str("# ------------------------------------------------------------------------\n");
+	out.push_str("# Configuration generated by `acmed init`\n");
+	out.push_str("# ------------------------------------------------------------------------\n\n");
+
+	out.push_str("[global]\n");
+	out.push_str(&format!(
+		"accounts_directory = {}\n",
+		toml_str(&opts.accounts_directory)
+	));
+	out.push_str(&format!(
+		"certificates_directory = {}\n\n",
+		toml_str(&opts.certificates_directory)
+	));
+
+	out.push_str("[[endpoint]]\n");
+	out.push_str(&format!(
+		"name = {}\n",
+		toml_str(DEFAULT_INIT_ENDPOINT_NAME)
+	));
+	out.push_str(&format!("url = {}\n", toml_str(&opts.endpoint_url)));
+	out.push_str(&format!("tos_agreed = {}\n\n", opts.tos_agreed));
+
+	let hook_names = push_challenge_hooks(&mut out, &opts.challenge);
+	let hook_names: Vec<&str> = hook_names.iter().map(|e| e.as_str()).collect();
+	let group_name = opts.challenge.group_name();
+	out.push_str("[[group]]\n");
+	out.push_str(&format!("name = {}\n", toml_str(&group_name)));
+	out.push_str(&format!("hooks = {}\n\n", toml_str_array(&hook_names)));
+
+	out.push_str("[[account]]\n");
+	out.push_str(&format!("name = {}\n", toml_str(INIT_ACCOUNT_NAME)));
+	out.push_str(&format!(
+		"contacts = [\n    {{ mailto = {} }},\n]\n\n",
+		toml_str(&opts.email)
+	));
+
+	out.push_str("[[certificate]]\n");
+	out.push_str(&format!("account = {}\n", toml_str(INIT_ACCOUNT_NAME)));
+	out.push_str(&format!(
+		"endpoint = {}\n",
+		toml_str(DEFAULT_INIT_ENDPOINT_NAME)
+	));
+	out.push_str("identifiers = [\n");
+	for domain in opts.domains.iter() {
+		out.push_str(&format!(
+			"    {{ dns = {}, challenge = {} }},\n",
+			toml_str(domain),
+			toml_str(opts.challenge.name())
+		));
+	}
+	out.push_str("]\n");
+	out.push_str(&format!("hooks = {}\n", toml_str_array(&[&group_name])));
+	out
+}
+
+pub fn prompt(question: &str, default: Option<&str>) -> Result<String, Error> {
+	match default {
+		Some(d) => eprint!("{question} [{d}]: "),
+		None => eprint!("{question}: "),
+	};
+	io::stderr().flush()?;
+	let mut input = String::new();
+	io::stdin().read_line(&mut input)?;
+	let input = input.trim();
+	if input.is_empty() {
+		match default {
+			Some(d) => Ok(d.to_string()),
+			None => Err(format!("{question}: a value is required").into()),
+		}
+	} else {
+		Ok(input.to_string())
+	}
+}
+
+fn get_value(
+	matches: &ArgMatches,
+	name: &str,
+	question: &str,
+	default: Option<&str>,
+) -> Result<String, Error> {
+	match matches.get_one::<String>(name) {
+		Some(v) => Ok(v.to_owned()),
+		None => prompt(question, default),
+	}
+}
+
+fn get_options(matches: &ArgMatches) -> Result<InitOptions, Error> {
+	let domains: Vec<String> = match matches.get_many::<String>("domain") {
+		Some(v) => v.map(|e| e.to_owned()).collect(),
+		None => prompt(
+			"Domains to include in the certificate (comma separated)",
+			None,
+		)?
+		.split([',', ' '])
+		.filter(|e| !e.is_empty())
+		.map(|e| e.to_string())
+		.collect(),
+	};
+	if domains.is_empty() {
+		return Err("at least one domain is required".into());
+	}
+	let challenge = get_value(
+		matches,
+		"challenge",
+		"Challenge type (http-01, dns-01, tls-alpn-01)",
+		Some(DEFAULT_INIT_CHALLENGE),
+	)?;
+	let challenge = match challenge.to_lowercase().as_str() {
+		"http-01" => InitChallenge::Http01 {
+			webroot: get_value(
+				matches,
+				"webroot",
+				"Web server document root",
+				Some(DEFAULT_INIT_WEBROOT),
+			)?,
+		},
+		"dns-01" => {
+			let set_cmd = get_value(
+				matches,
+				"dns-hook",
+				"Command creating the TXT record (called with the domain and the proof)",
+				None,
+			)?;
+			let clean_cmd = get_value(
+				matches,
+				"dns-clean-hook",
+				"Command removing the TXT record (called with the domain and the proof)",
+				None,
+			)?;
+			InitChallenge::Dns01 { set_cmd, clean_cmd }
+		}
+		"tls-alpn-01" => InitChallenge::TlsAlpn01 {
+			port: get_value(
+				matches,
+				"tacd-port",
+				"Port tacd should listen on",
+				Some(DEFAULT_INIT_TACD_PORT),
+			)?,
+		},
+		_ => {
+			return Err(format!("{challenge}: unknown challenge").into());
+		}
+	};
+	let email = get_value(matches, "email", "Contact email address", None)?;
+	let endpoint_url = get_value(
+		matches,
+		"endpoint-url",
+		"ACME directory URL",
+		Some(DEFAULT_INIT_ENDPOINT_URL),
+	)?;
+	let tos_agreed = if matches.get_flag("tos-agreed") {
+		true
+	} else {
+		let answer = prompt(
+			"Do you agree to the terms of service of the certificate authority? (yes/no)",
+			Some("no"),
+		)?;
+		matches!(answer.to_lowercase().as_str(), "y" | "yes")
+	};
+	Ok(InitOptions {
+		domains,
+		challenge,
+		email,
+		endpoint_url,
+		tos_agreed,
+		accounts_directory: matches
+			.get_one::<String>("accounts-dir")
+			.map(|e| e.to_owned())
+			.unwrap_or_else(|| crate::DEFAULT_ACCOUNTS_DIR.to_string()),
+		certificates_directory: matches
+			.get_one::<String>("certs-dir")
+			.map(|e| e.to_owned())
+			.unwrap_or_else(|| crate::DEFAULT_CERT_DIR.to_string()),
+	})
+}
+
+fn create_dir(path: &str, mode: u32) -> Result<(), Error> {
+	let p = Path::new(path);
+	if !p.is_dir() {
+		fs::create_dir_all(p).map_err(|e| Error::from(e).prefix(path))?;
+	}
+	Current::set_mode(p, mode).map_err(|e| e.prefix(path))?;
+	Ok(())
+}
+
+fn write_config(path: &str, content: &str, force: bool) -> Result<(), Error> {
+	let p = Path::new(path);
+	if p.exists() && !force {
+		return Err(format!("{path}: file already exists, use --force to overwrite it").into());
+	}
+	if let Some(parent) = p.parent() {
+		if !parent.as_os_str().is_empty() && !parent.is_dir() {
+			fs::create_dir_all(parent)
+				.map_err(|e| Error::from(e).prefix(&parent.display().to_string()))?;
+		}
+	}
+	let mut options = Current::file_options(INIT_CONFIG_FILE_MODE);
+	options.write(true).create(true).truncate(true);
+	let mut file = options.open(p).map_err(|e| Error::from(e).prefix(path))?;
+	file.write_all(content.as_bytes())
+		.map_err(|e| Error::from(e).prefix(path))?;
+	Ok(())
+}
+
+pub fn run(matches: &ArgMatches, config_file: &str) -> Result<(), Error> {
+	let opts = get_options(matches)?;
+	let content = generate_config(&opts);
+	let _: Config = toml::from_str(&content)
+		.map_err(|e| Error::from(e).prefix("generated configuration is invalid"))?;
+	write_config(config_file, &content, matches.get_flag("force"))?;
+	create_dir(&opts.accounts_directory, crate::DEFAULT_ACCOUNT_DIR_MODE)?;
+	create_dir(&opts.certificates_directory, crate::DEFAULT_CERT_DIR_MODE)?;
+	eprintln!("configuration written to {config_file}");
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn get_opts(challenge: InitChallenge) -> InitOptions {
+		InitOptions {
+			domains: vec!["example.org".to_string(), "www.example.org".to_string()],
+			challenge,
+			email: "certs@example.org".to_string(),
+			endpoint_url: DEFAULT_INIT_ENDPOINT_URL.to_string(),
+			tos_agreed: true,
+			accounts_directory: "/var/lib/acmed/accounts".to_string(),
+			certificates_directory: "/var/lib/acmed/certs".to_string(),
+		}
+	}
+
+	fn check_config(opts: &InitOptions) -> Config {
+		let content = generate_config(opts);
+		let cnf: Config = toml::from_str(&content).unwrap();
+		assert_eq!(cnf.endpoint.len(), 1);
+		assert_eq!(cnf.account.len(), 1);
+		assert_eq!(cnf.certificate.len(), 1);
+		let crt = &cnf.certificate[0];
+		assert_eq!(crt.identifiers.len(), 2);
+		assert_eq!(crt.hooks, vec![opts.challenge.group_name()]);
+		assert!(crt.get_hooks(&cnf).is_ok());
+		cnf
+	}
+
+	#[test]
+	fn test_init_http01() {
+		let opts = get_opts(InitChallenge::Http01 {
+			webroot: "/srv/http/".to_string(),
+		});
+		let cnf = check_config(&opts);
+		let hooks = cnf.certificate[0].get_hooks(&cnf).unwrap();
+		assert_eq!(hooks.len(), 4);
+		assert_eq!(
+			hooks[1].args,
+			Some(vec![
+				"/srv/http/.well-known/acme-challenge/{{ file_name }}".to_string()
+			])
+		);
+	}
+
+	#[test]
+	fn test_init_dns01() {
+		let opts = get_opts(InitChallenge::Dns01 {
+			set_cmd: "/usr/local/bin/dns-set".to_string(),
+			clean_cmd: "/usr/local/bin/dns \"clean\"".to_string(),
+		});
+		let cnf = check_config(&opts);
+		let hooks = cnf.certificate[0].get_hooks(&cnf).unwrap();
+		assert_eq!(hooks.len(), 2);
+		assert_eq!(hooks[1].cmd, "/usr/local/bin/dns \"clean\"");
+	}
+
+	#[test]
+	fn test_init_tls_alpn01() {
+		let opts = get_opts(InitChallenge::TlsAlpn01 {
+			port: "5001".to_string(),
+		});
+		let cnf = check_config(&opts);
+		let hooks = cnf.certificate[0].get_hooks(&cnf).unwrap();
+		assert_eq!(hooks.len(), 3);
+	}
+}
@@ -0,0 +1,139 @@
+use acme_common::error::Error;
+use toml::{Table, Value};
+
+/// Top-level tables whose values are left as is. The hooks' arguments are often shell scripts,
+/// in which `${VAR}` is expanded by the shell when the hook is called.
+const SKIPPED_TABLES: &[&str] = &["hook"];
+
+/// Tables left as is at every level. The `env` tables are handed over to the hooks, which
+/// usually expect to expand their own variables.
+const SKIPPED_KEYS: &[&str] = &["env"];
+
+/// Replace the `${VAR}` references to environment variables in every string of the
+/// configuration, so secrets and paths can be kept out of the files. `$${` stands for a literal
+/// `${`.
+pub fn interpolate_env(table: &mut Table) -> Result<(), Error> {
+	for (key, value) in table.iter_mut() {
+		if !SKIPPED_TABLES.contains(&key.as_str()) && !SKIPPED_KEYS.contains(&key.as_str()) {
+			interpolate_value(value, key, &|name| std::env::var(name).ok())?;
+		}
+	}
+	Ok(())
+}
+
+fn interpolate_value<F>(value: &mut Value, path: &str, lookup: &F) -> Result<(), Error>
+where
+	F: Fn(&str) -> Option<String>,
+{
+	match value {
+		Value::String(s) if s.contains('$') => {
+			*s = expand(s, lookup).map_err(|e| e.prefix(path))?;
+		}
+		Value::Array(a) => {
+			for (i, v) in a.iter_mut().enumerate() {
+				interpolate_value(v, &format!("{path}[{i}]"), lookup)?;
+			}
+		}
+		Value::Table(t) => {
+			for (k, v) in t.iter_mut() {
+				if !SKIPPED_KEYS.contains(&k.as_str()) {
+					interpolate_value(v, &format!("{path}.{k}"), lookup)?;
+				}
+			}
+		}
+		_ => {}
+	}
+	Ok(())
+}
+
+fn expand<F>(input: &str, lookup: &F) -> Result<String, Error>
+where
+	F: Fn(&str) -> Option<String>,
+{
+	let mut res = String::with_capacity(input.len());
+	let mut rest = input;
+	while let Some(pos) = rest.find('$') {
+		res += &rest[..pos];
+		rest = &rest[pos..];
+		if let Some(r) = rest.strip_prefix("$${") {
+			res += "${";
+			rest = r;
+		} else if let Some(r) = rest.strip_prefix("${") {
+			let end = r
+				.find('}')
+				.ok_or_else(|| Error::from(format!("{input}: unterminated variable reference")))?;
+			let name = &r[..end];
+			let is_valid = !name.is_empty()
+				&& !name.starts_with(|c: char| c.is_ascii_digit())
+				&& name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+			if !is_valid {
+				return Err(format!("{name}: invalid environment variable name").into());
+			}
+			res += &lookup(name)
+				.ok_or_else(|| Error::from(format!("{name}: environment variable not set")))?;
+			rest = &r[end + 1..];
+		} else {
+			res.push('$');
+			rest = &rest[1..];
+		}
+	}
+	res += rest;
+	Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{expand, interpolate_value};
+	use toml::Value;
+
+	fn lookup(name: &str) -> Option<String> {
+		match name {
+			"TOKEN" => Some("s3cr3t".to_string()),
+			"DIR" => Some("/var/lib/acmed".to_string()),
+			_ => None,
+		}
+	}
+
+	#[test]
+	fn test_expand() {
+		assert_eq!(expand("no variable", &lookup).unwrap(), "no variable");
+		assert_eq!(expand("${TOKEN}", &lookup).unwrap(), "s3cr3t");
+		assert_eq!(
+			expand("${DIR}/certs/${TOKEN}", &lookup).unwrap(),
+			"/var/lib/acmed/certs/s3cr3t"
+		);
+		assert_eq!(
+			expand("$${TOKEN} costs $5", &lookup).unwrap(),
+			"${TOKEN} costs $5"
+		);
+		let e = expand("${UNSET}", &lookup).unwrap_err();
+		assert_eq!(e.message, "UNSET: environment variable not set");
+		assert!(expand("${TOKEN", &lookup).is_err());
+		assert!(expand("${}", &lookup).is_err());
+		assert!(expand("${1A}", &lookup).is_err());
+	}
+
+	#[test]
+	fn test_interpolate_value() {
+		let mut value: Value = toml::from_str(
+			r#"
+			directory = "${DIR}"
+			tokens = ["a", "${TOKEN}"]
+			nested = { missing = "${UNSET}" }
+			env = { HOOK_VAR = "${UNSET}" }
+			"#,
+		)
+		.unwrap();
+		let e = interpolate_value(&mut value, "certificate[0]", &lookup).unwrap_err();
+		assert_eq!(
+			e.message,
+			"certificate[0].nested.missing: UNSET: environment variable not set"
+		);
+		value["nested"]["missing"] = Value::String("$${UNSET}".to_string());
+		interpolate_value(&mut value, "certificate[0]", &lookup).unwrap();
+		assert_eq!(value["directory"].as_str(), Some("/var/lib/acmed"));
+		assert_eq!(value["tokens"][1].as_str(), Some("s3cr3t"));
+		assert_eq!(value["nested"]["missing"].as_str(), Some("${UNSET}"));
+		assert_eq!(value["env"]["HOOK_VAR"].as_str(), Some("${UNSET}"));
+	}
+}
@@ -130,7 +130,7 @@ mod tests {
 		let payload_b64 = "RHVtbXkgcGF5bG9hZCAx";
 		let s = encode_jwk(
 			&key_pair,
-			&key_type.get_default_signature_alg(),
+			&key_type.get_default_signature_alg().unwrap(),
 			payload.as_bytes(),
 			"",
 			Some(String::new()),
@@ -152,7 +152,7 @@ mod tests {
 		let payload_b64_pad = "RHVtbXkgcGF5bG9hZA==";
 		let s = encode_jwk(
 			&key_pair,
-			&key_type.get_default_signature_alg(),
+			&key_type.get_default_signature_alg().unwrap(),
 			payload.as_bytes(),
 			"",
 			Some(String::new()),
@@ -175,7 +175,7 @@ mod tests {
 		let key_id = "0x2a";
 		let s = encode_kid(
 			&key_pair,
-			&key_type.get_default_signature_alg(),
+			&key_type.get_default_signature_alg().unwrap(),
 			key_id,
 			payload.as_bytes(),
 			"",
@@ -0,0 +1,463 @@
+use crate::certificate::Certificate;
+use crate::endpoint::get_state_file;
+use crate::logs::HasLogger;
+use crate::storage;
+use acme_common::crypto::KeyPair;
+use acme_common::error::Error;
+use base64::Engine;
+use reqwest::{header, ClientBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const FIELD_MANAGER: &str = "acmed";
+const REQUEST_TIMEOUT_SEC: u64 = 30;
+
+/// A `kubernetes.io/tls` secret the issued certificate and its private key are written into.
+/// Unless specified, the API server, the credentials and the CA certificate are those of the
+/// kubeconfig file or, without one, those provided to the pods running in the cluster.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KubernetesSecret {
+	pub name: String,
+	pub namespace: Option<String>,
+	pub api_server: Option<String>,
+	pub token_file: Option<String>,
+	pub ca_file: Option<String>,
+	pub kubeconfig: Option<String>,
+}
+
+/// Settings used to connect to the API server.
+#[derive(Debug, Default)]
+struct ApiServer {
+	url: Option<String>,
+	namespace: Option<String>,
+	token: Option<String>,
+	ca_crt: Option<Vec<u8>>,
+	client_crt: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl KubernetesSecret {
+	async fn get_api_server(&self) -> Result<ApiServer, Error> {
+		let mut srv = match &self.kubeconfig {
+			Some(path) => read_kubeconfig(Path::new(path)).await?,
+			None => in_cluster(self.api_server.is_none()).await?,
+		};
+		if let Some(url) = &self.api_server {
+			srv.url = Some(url.to_owned());
+		}
+		if let Some(ns) = &self.namespace {
+			srv.namespace = Some(ns.to_owned());
+		}
+		if let Some(f) = &self.token_file {
+			srv.token = Some(read_file(Path::new(f)).await?);
+		}
+		if let Some(f) = &self.ca_file {
+			srv.ca_crt = Some(read_raw_file(Path::new(f)).await?);
+		}
+		Ok(srv)
+	}
+
+	/// Create or update the secret using a server-side apply, which requires the `patch` verb
+	/// on the secret.
+	pub async fn update(&self, key_pair: &KeyPair, crt: &[u8]) -> Result<(), Error> {
+		let srv = self.get_api_server().await?;
+		let api_server = srv
+			.url
+			.ok_or_else(|| Error::from("not running in a cluster: no API server specified"))?;
+		let namespace = srv
+			.namespace
+			.ok_or_else(|| Error::from("no namespace specified"))?;
+		let url = format!(
+			"{}/api/v1/namespaces/{namespace}/secrets/{}?fieldManager={FIELD_MANAGER}&force=true",
+			api_server.trim_end_matches('/'),
+			self.name
+		);
+		let key = key_pair.private_key_to_pem()?;
+		let body = secret_body(&self.name, &namespace, crt, &key);
+		let mut client_builder = ClientBuilder::new();
+		#[cfg(feature = "crypto_openssl")]
+		{
+			if let Some(ca_crt) = &srv.ca_crt {
+				let crt = reqwest::Certificate::from_pem(ca_crt)?;
+				client_builder = client_builder.add_root_certificate(crt);
+			}
+			if let Some((crt, key)) = &srv.client_crt {
+				// The client key may use any format, while a PKCS#8 one is expected.
+				let key = KeyPair::from_pem(key)?.private_key_to_pem()?;
+				let identity = reqwest::Identity::from_pkcs8_pem(crt, &key)?;
+				client_builder = client_builder.identity(identity);
+			}
+		}
+		#[cfg(not(feature = "crypto_openssl"))]
+		if srv.ca_crt.is_some() || srv.client_crt.is_some() {
+			return Err("CA and client certificates require the crypto_openssl feature".into());
+		}
+		let mut request = client_builder
+			.build()?
+			.patch(&url)
+			.header(header::CONTENT_TYPE, "application/apply-patch+yaml")
+			.timeout(Duration::from_secs(REQUEST_TIMEOUT_SEC))
+			.body(body.to_string());
+		if let Some(token) = &srv.token {
+			request = request.bearer_auth(token);
+		}
+		let response = request.send().await?;
+		let status = response.status();
+		if !status.is_success() {
+			let message = response
+				.text()
+				.await
+				.ok()
+				.and_then(|b| serde_json::from_str::<Value>(&b).ok())
+				.and_then(|v| v["message"].as_str().map(|m| m.to_string()))
+				.unwrap_or_else(|| status.to_string());
+			let msg = format!(
+				"{namespace}/{}: unable to update the secret: {message}",
+				self.name
+			);
+			return Err(msg.into());
+		}
+		Ok(())
+	}
+}
+
+/// Settings provided to the pods running in the cluster. The cluster's CA certificate is only
+/// relevant to the cluster's API server, which is used unless another one is specified.
+async fn in_cluster(use_ca: bool) -> Result<ApiServer, Error> {
+	let dir = Path::new(SERVICE_ACCOUNT_DIR);
+	let url = match env::var("KUBERNETES_SERVICE_HOST") {
+		Ok(host) => {
+			let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".into());
+			Some(in_cluster_url(&host, &port))
+		}
+		Err(_) => None,
+	};
+	// The service account's files are missing if their mounting has been disabled, in which
+	// case they have to be specified.
+	let namespace = read_file(&dir.join("namespace")).await.ok();
+	let token = read_file(&dir.join("token")).await.ok();
+	let ca_crt = match url {
+		Some(_) if use_ca => read_raw_file(&dir.join("ca.crt")).await.ok(),
+		_ => None,
+	};
+	Ok(ApiServer {
+		url,
+		namespace,
+		token,
+		ca_crt,
+		client_crt: None,
+	})
+}
+
+#[derive(Deserialize)]
+struct KubeConfig {
+	#[serde(rename = "current-context")]
+	current_context: Option<String>,
+	#[serde(default)]
+	clusters: Vec<KubeNamedCluster>,
+	#[serde(default)]
+	contexts: Vec<KubeNamedContext>,
+	#[serde(default)]
+	users: Vec<KubeNamedUser>,
+}
+
+#[derive(Deserialize)]
+struct KubeNamedCluster {
+	name: String,
+	cluster: KubeCluster,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct KubeCluster {
+	server: String,
+	certificate_authority: Option<String>,
+	certificate_authority_data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KubeNamedContext {
+	name: String,
+	context: KubeContext,
+}
+
+#[derive(Deserialize)]
+struct KubeContext {
+	cluster: String,
+	user: Option<String>,
+	namespace: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KubeNamedUser {
+	name: String,
+	user: KubeUser,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct KubeUser {
+	token: Option<String>,
+	#[serde(rename = "tokenFile")]
+	token_file: Option<String>,
+	client_certificate: Option<String>,
+	client_certificate_data: Option<String>,
+	client_key: Option<String>,
+	client_key_data: Option<String>,
+	exec: Option<Value>,
+	auth_provider: Option<Value>,
+}
+
+/// Return the settings of the current context of a kubeconfig file. Since the YAML format is
+/// not supported, the file has to use the JSON one, which is output by
+/// `kubectl config view --raw -o json`.
+async fn read_kubeconfig(path: &Path) -> Result<ApiServer, Error> {
+	let prefix = path.display().to_string();
+	let data = read_raw_file(path).await?;
+	let config: KubeConfig = serde_json::from_slice(&data)
+		.map_err(|e| Error::from(format!("invalid kubeconfig file: {e}")).prefix(&prefix))?;
+	let base = path.parent().unwrap_or_else(|| Path::new(""));
+	get_kubeconfig_server(&config, base)
+		.await
+		.map_err(|e| e.prefix(&prefix))
+}
+
+async fn get_kubeconfig_server(config: &KubeConfig, base: &Path) -> Result<ApiServer, Error> {
+	let ctx_name = config
+		.current_context
+		.as_ref()
+		.ok_or_else(|| Error::from("no current context"))?;
+	let ctx = &config
+		.contexts
+		.iter()
+		.find(|c| &c.name == ctx_name)
+		.ok_or_else(|| Error::from(format!("{ctx_name}: context not found")))?
+		.context;
+	let cluster = &config
+		.clusters
+		.iter()
+		.find(|c| c.name == ctx.cluster)
+		.ok_or_else(|| Error::from(format!("{}: cluster not found", ctx.cluster)))?
+		.cluster;
+	let mut srv = ApiServer {
+		url: Some(cluster.server.to_owned()),
+		namespace: Some(ctx.namespace.clone().unwrap_or_else(|| "default".into())),
+		ca_crt: get_kubeconfig_data(
+			&cluster.certificate_authority_data,
+			&cluster.certificate_authority,
+			base,
+		)
+		.await?,
+		..Default::default()
+	};
+	let user_name = match &ctx.user {
+		Some(u) => u,
+		None => return Ok(srv),
+	};
+	let user = &config
+		.users
+		.iter()
+		.find(|u| &u.name == user_name)
+		.ok_or_else(|| Error::from(format!("{user_name}: user not found")))?
+		.user;
+	if user.exec.is_some() || user.auth_provider.is_some() {
+		let msg = format!(
+			"{user_name}: only the token and client certificate authentications are supported"
+		);
+		return Err(msg.into());
+	}
+	srv.token = match (&user.token, &user.token_file) {
+		(Some(t), _) => Some(t.to_owned()),
+		(None, Some(f)) => Some(read_file(&base.join(f)).await?),
+		(None, None) => None,
+	};
+	let crt = get_kubeconfig_data(
+		&user.client_certificate_data,
+		&user.client_certificate,
+		base,
+	)
+	.await?;
+	let key = get_kubeconfig_data(&user.client_key_data, &user.client_key, base).await?;
+	srv.client_crt = match (crt, key) {
+		(Some(crt), Some(key)) => Some((crt, key)),
+		(None, None) => None,
+		_ => {
+			let msg = format!("{user_name}: the client certificate and key must both be specified");
+			return Err(msg.into());
+		}
+	};
+	Ok(srv)
+}
+
+/// Return the base64-encoded data or the content of the file, whose path is relative to the
+/// kubeconfig file.
+async fn get_kubeconfig_data(
+	data: &Option<String>,
+	file: &Option<String>,
+	base: &Path,
+) -> Result<Option<Vec<u8>>, Error> {
+	match (data, file) {
+		(Some(d), _) => {
+			let engine = base64::engine::general_purpose::STANDARD;
+			let data = engine
+				.decode(d.trim())
+				.map_err(|e| Error::from(format!("invalid base64 data: {e}")))?;
+			Ok(Some(data))
+		}
+		(None, Some(f)) => Ok(Some(read_raw_file(&base.join(f)).await?)),
+		(None, None) => Ok(None),
+	}
+}
+
+async fn read_raw_file(path: &Path) -> Result<Vec<u8>, Error> {
+	fs::read(path)
+		.await
+		.map_err(|e| Error::from(e).prefix(&path.display().to_string()))
+}
+
+async fn read_file(path: &Path) -> Result<String, Error> {
+	let content = fs::read_to_string(path)
+		.await
+		.map_err(|e| Error::from(e).prefix(&path.display().to_string()))?;
+	Ok(content.trim().to_string())
+}
+
+fn get_pending_file(cert: &Certificate) -> PathBuf {
+	get_state_file(&cert.state_dir, &cert.get_id(), "kubernetes")
+}
+
+/// Return whether or not the latest certificate has not been written into the secret yet.
+pub fn is_pending(cert: &Certificate) -> bool {
+	cert.kubernetes_secret.is_some() && get_pending_file(cert).is_file()
+}
+
+/// Write the certificate into the secret. Since the certificate is valid even if the secret
+/// cannot be updated, a failure is recorded so the update is attempted again later on, even
+/// across restarts.
+pub async fn update_secret(cert: &Certificate, key_pair: &KeyPair, crt: &[u8]) {
+	let secret = match &cert.kubernetes_secret {
+		Some(s) => s,
+		None => return,
+	};
+	let pending_file = get_pending_file(cert);
+	match secret.update(key_pair, crt).await {
+		Ok(_) => {
+			if pending_file.is_file() {
+				if let Err(e) = fs::remove_file(&pending_file).await {
+					cert.warn(&format!("{}: {e}", pending_file.display()));
+				}
+			}
+			cert.debug(&format!("kubernetes secret \"{}\" updated", secret.name));
+		}
+		Err(e) => {
+			let e = e.prefix(&format!("kubernetes secret \"{}\"", secret.name));
+			cert.warn_error("kubernetes", &e);
+			let data = json!({ "secret": secret.name, "error": e.message }).to_string();
+			if let Err(e) = fs::write(&pending_file, data).await {
+				cert.warn(&format!("{}: {e}", pending_file.display()));
+			}
+		}
+	}
+}
+
+/// Attempt again to write the stored certificate into the secret.
+pub async fn retry_pending(cert: &Certificate) {
+	let res = async {
+		let key_pair = storage::get_keypair(&cert.file_manager).await?;
+		let crt = storage::get_certificate_data(&cert.file_manager).await?;
+		Ok::<_, Error>((key_pair, crt))
+	}
+	.await;
+	match res {
+		Ok((key_pair, crt)) => update_secret(cert, &key_pair, &crt).await,
+		Err(e) => cert.warn_error("kubernetes", &e.prefix("kubernetes secret")),
+	}
+}
+
+fn in_cluster_url(host: &str, port: &str) -> String {
+	if host.contains(':') {
+		format!("https://[{host}]:{port}")
+	} else {
+		format!("https://{host}:{port}")
+	}
+}
+
+fn secret_body(name: &str, namespace: &str, crt: &[u8], key: &[u8]) -> Value {
+	let engine = base64::engine::general_purpose::STANDARD;
+	json!({
+		"apiVersion": "v1",
+		"kind": "Secret",
+		"metadata": {
+			"name": name,
+			"namespace": namespace,
+		},
+		"type": "kubernetes.io/tls",
+		"data": {
+			"tls.crt": engine.encode(crt),
+			"tls.key": engine.encode(key),
+		},
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_kubeconfig_server, in_cluster_url, secret_body, KubeConfig};
+	use std::path::Path;
+
+	#[test]
+	fn test_in_cluster_url() {
+		assert_eq!(in_cluster_url("10.0.0.1", "443"), "https://10.0.0.1:443");
+		assert_eq!(in_cluster_url("fd00::1", "6443"), "https://[fd00::1]:6443");
+	}
+
+	#[test]
+	fn test_secret_body() {
+		let body = secret_body("example-tls", "default", b"crt", b"key");
+		assert_eq!(body["type"], "kubernetes.io/tls");
+		assert_eq!(body["metadata"]["name"], "example-tls");
+		assert_eq!(body["metadata"]["namespace"], "default");
+		assert_eq!(body["data"]["tls.crt"], "Y3J0");
+		assert_eq!(body["data"]["tls.key"], "a2V5");
+	}
+
+	#[tokio::test]
+	async fn test_kubeconfig() {
+		let config: KubeConfig = serde_json::from_str(
+			r#"{
+				"current-context": "prod",
+				"clusters": [
+					{"name": "dev", "cluster": {"server": "https://dev:6443"}},
+					{"name": "prod", "cluster": {"server": "https://prod:6443", "certificate-authority-data": "Y2E="}}
+				],
+				"contexts": [
+					{"name": "dev", "context": {"cluster": "dev", "user": "dev"}},
+					{"name": "prod", "context": {"cluster": "prod", "user": "admin", "namespace": "tls"}}
+				],
+				"users": [
+					{"name": "dev", "user": {"exec": {"command": "login"}}},
+					{"name": "admin", "user": {"token": "t0k3n"}}
+				]
+			}"#,
+		)
+		.unwrap();
+		let srv = get_kubeconfig_server(&config, Path::new("/"))
+			.await
+			.unwrap();
+		assert_eq!(srv.url.as_deref(), Some("https://prod:6443"));
+		assert_eq!(srv.namespace.as_deref(), Some("tls"));
+		assert_eq!(srv.token.as_deref(), Some("t0k3n"));
+		assert_eq!(srv.ca_crt.as_deref(), Some(b"ca".as_slice()));
+		assert!(srv.client_crt.is_none());
+
+		let config = KubeConfig {
+			current_context: Some("dev".to_string()),
+			..config
+		};
+		let res = get_kubeconfig_server(&config, Path::new("/")).await;
+		assert!(res.is_err());
+	}
+}
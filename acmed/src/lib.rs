@@ -0,0 +1,132 @@
+use acme_common::crypto::{
+	HashFunction, JwsSignatureAlgorithm, KeyEncoding, KeyType, PrivateKeyFormat,
+};
+use async_lock::RwLock;
+use std::sync::Arc;
+
+mod account;
+mod acme_dns;
+mod acme_proto;
+mod advisory;
+mod caa;
+mod certificate;
+pub mod config;
+pub mod control;
+mod dns;
+mod dns_propagation;
+mod dns_provider;
+mod dns_update;
+pub mod duration;
+mod email;
+mod endpoint;
+mod events;
+mod hooks;
+mod http;
+mod identifier;
+pub mod init;
+mod interpolation;
+mod jws;
+mod kubernetes;
+mod logs;
+pub mod main_event_loop;
+mod ocsp;
+pub mod platform;
+pub mod privileges;
+pub mod renewal_window;
+mod solver;
+mod storage;
+pub mod template;
+pub mod terminal;
+mod watched;
+mod web_ui;
+mod webhooks;
+
+pub use acme_proto::RevocationReason;
+
+pub const APP_NAME: &str = "ACMEd";
+pub const APP_THREAD_NAME: &str = "acmed-runtime";
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const DEFAULT_ACCOUNTS_DIR: &str = env!("ACMED_DEFAULT_ACCOUNTS_DIR");
+pub const DEFAULT_CERT_DIR: &str = env!("ACMED_DEFAULT_CERT_DIR");
+pub const DEFAULT_CERT_FORMAT: &str = env!("ACMED_DEFAULT_CERT_FORMAT");
+pub const DEFAULT_CONFIG_FILE: &str = env!("ACMED_DEFAULT_CONFIG_FILE");
+pub const DEFAULT_PID_FILE: &str = env!("ACMED_DEFAULT_PID_FILE");
+pub const DEFAULT_STATE_DIR: &str = env!("ACMED_DEFAULT_STATE_DIR");
+pub const DEFAULT_POOL_TIME: u64 = 5000;
+pub const DEFAULT_CSR_DIGEST: HashFunction = HashFunction::Sha256;
+pub const DEFAULT_CERT_KEY_TYPE: KeyType = KeyType::Rsa2048;
+pub const DEFAULT_CERT_FILE_MODE: u32 = 0o644;
+pub const DEFAULT_CERT_DIR_MODE: u32 = 0o755;
+pub const DEFAULT_CERT_RANDOM_EARLY_RENEW: u64 = 0; // default to not renewing early
+pub const DEFAULT_CERT_RENEW_DELAY: u64 = 30 * 24 * 60 * 60; // 30 days
+pub const DEFAULT_CERT_RENEW_JITTER: u64 = 0;
+pub const DEFAULT_CERT_RENEW_ADAPTIVE_FACTOR: f64 = 0.0;
+pub const DEFAULT_ISSUANCE_HISTORY_SIZE: usize = 10;
+pub const DEFAULT_RENEW_WINDOW_CRITICAL_SEC: u64 = 7 * 24 * 60 * 60;
+pub const DEFAULT_PK_FILE_MODE: u32 = 0o600;
+pub const DEFAULT_ACCOUNT_FILE_MODE: u32 = 0o600;
+pub const DEFAULT_ACCOUNT_DIR_MODE: u32 = 0o700;
+pub const DEFAULT_STATE_DIR_MODE: u32 = 0o700;
+pub const DEFAULT_CONTROL_SOCKET_MODE: u32 = 0o600;
+pub const DEFAULT_WEBROOT_FILE_MODE: u32 = 0o644;
+pub const DEFAULT_WEBROOT_DIR_MODE: u32 = 0o755;
+pub const DEFAULT_WEB_UI_USER: &str = "acmed";
+pub const DEFAULT_KP_REUSE: bool = false;
+pub const DEFAULT_PK_FORMAT: PrivateKeyFormat = PrivateKeyFormat::Pkcs8;
+pub const DEFAULT_PK_ENCODING: KeyEncoding = KeyEncoding::Pem;
+pub const DEFAULT_DUPLICATE_IDENTIFIERS: config::DuplicateIdentifiers =
+	config::DuplicateIdentifiers::Warn;
+pub const DEFAULT_ACCOUNT_KEY_TYPE: KeyType = KeyType::EcdsaP256;
+pub const DEFAULT_EXTERNAL_ACCOUNT_JWA: JwsSignatureAlgorithm = JwsSignatureAlgorithm::Hs256;
+pub const DEFAULT_POLL_INTERVAL_SEC: u64 = 5;
+pub const DEFAULT_AUTHORIZATION_TIMEOUT_SEC: u64 = 100;
+pub const DEFAULT_FINALIZATION_TIMEOUT_SEC: u64 = 100;
+pub const DEFAULT_STUCK_ORDER_MAX_RECREATIONS: usize = 1;
+pub const DEFAULT_HTTP_FAIL_NB_RETRY: usize = 10;
+pub const DEFAULT_HTTP_FAIL_WAIT_SEC: u64 = 1;
+pub const DEFAULT_HTTP_FAIL_MAX_WAIT_SEC: u64 = 60;
+pub const DEFAULT_RATE_LIMITED_WAIT_SEC: u64 = 60 * 60;
+pub const DEFAULT_CA_RATE_LIMITS_WRITE_DELAY_SEC: u64 = 1;
+pub const DEFAULT_HOOK_ALLOW_FAILURE: bool = false;
+pub const DEFAULT_HOOK_HISTORY_SIZE: usize = 20;
+pub const DEFAULT_HOOK_LOG_OUTPUT: bool = false;
+pub const DEFAULT_HOOK_OUTPUT_DRAIN_SEC: u64 = 5;
+pub const DEFAULT_HOOK_RETRIES: usize = 0;
+pub const DEFAULT_HOOK_RETRY_DELAY_SEC: u64 = 5;
+pub const DEFAULT_MAX_PARALLEL_RENEWALS: usize = 4;
+pub const DEFAULT_SHUTDOWN_TIMEOUT_SEC: u64 = 60;
+pub const DEFAULT_CHALLENGE_READY_INTERVAL_SEC: u64 = 5;
+pub const DEFAULT_CHALLENGE_READY_TIMEOUT_SEC: u64 = 5 * 60;
+pub const DEFAULT_DNS_CLEANUP_DELAY_SEC: u64 = 0;
+pub const DEFAULT_DNS_PROPAGATION_CHECK: bool = false;
+pub const DEFAULT_DNS_PROPAGATION_TIMEOUT_SEC: u64 = 5 * 60;
+pub const DEFAULT_VERSION_ADVISORY_INTERVAL_SEC: u64 = 24 * 60 * 60;
+pub const DEFAULT_WEBHOOK_TIMEOUT_SEC: u64 = 30;
+pub const DEFAULT_WEBHOOK_MAX_ATTEMPTS: usize = 5;
+pub const DEFAULT_WEBHOOK_RETRY_DELAY_SEC: u64 = 2;
+pub const DEFAULT_DEPLOY_CHECK_DELAY_SEC: u64 = 5;
+pub const DEFAULT_DEPLOY_CHECK_NB_TRIES: usize = 3;
+pub const DEFAULT_WATCH_INTERVAL_SEC: u64 = 12 * 60 * 60;
+pub const DEFAULT_TLS_CHECK_TIMEOUT_SEC: u64 = 30;
+pub const TIME_OFFSET_ENV_VAR: &str = "ACMED_TIME_OFFSET";
+pub const DEFAULT_TSIG_ALGORITHM: &str = "hmac-sha256";
+pub const DEFAULT_HTTP_SELF_CHECK: bool = false;
+pub const DEFAULT_OCSP_STAPLE: bool = false;
+pub const DEFAULT_OCSP_RETRY_SEC: u64 = 60 * 60;
+pub const DEFAULT_KUBERNETES_RETRY_SEC: u64 = 10 * 60;
+pub const DEFAULT_STAR_FETCH_RETRY_SEC: u64 = 60 * 60;
+pub const DEFAULT_FAILOVER_ATTEMPTS: usize = 3;
+pub const DEFAULT_RENEWAL_RETRY_BACKOFF_SEC: [u64; 4] = [60, 10 * 60, 100 * 60, 24 * 60 * 60];
+pub const DEFAULT_CAA_CHECK: config::CaaCheck = config::CaaCheck::Off;
+pub const DEFAULT_READ_ONLY_STORAGE_RETRY_SEC: u64 = 10 * 60;
+pub const DEFAULT_AUTO_REGISTER: bool = true;
+pub const DEFAULT_CERTIFICATE_EXPIRING_SEC: u64 = 7 * 24 * 60 * 60;
+pub const DEFAULT_EMAIL_EXPIRATION_MARGIN_SEC: u64 = 7 * 24 * 60 * 60;
+pub const DEFAULT_EMAIL_FAILURES: usize = 3;
+pub const DEFAULT_EMAIL_SMTP_TLS: config::SmtpTls = config::SmtpTls::StartTls;
+pub const DEFAULT_EMAIL_TIMEOUT_SEC: u64 = 60;
+pub const MAX_RATE_LIMIT_SLEEP_MILISEC: u64 = 3_600_000;
+pub const MIN_RATE_LIMIT_SLEEP_MILISEC: u64 = 100;
+
+pub type AccountSync = Arc<RwLock<account::Account>>;
+pub type EndpointSync = Arc<RwLock<endpoint::Endpoint>>;
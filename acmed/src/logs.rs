@@ -1,3 +1,33 @@
+use acme_common::logs::with_event_id;
+
+/// Significant log messages, whose identifier is stable and documented so the messages can be
+/// matched regardless of their wording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogEvent {
+	CertificateIssued,
+	CertificateRenewed,
+	RenewalFailed,
+	HookFailed,
+	StorageReadOnly,
+}
+
+impl LogEvent {
+	pub fn id(self) -> &'static str {
+		match self {
+			LogEvent::CertificateIssued => "certificate_issued",
+			LogEvent::CertificateRenewed => "certificate_renewed",
+			LogEvent::RenewalFailed => "renewal_failed",
+			LogEvent::HookFailed => "hook_failed",
+			LogEvent::StorageReadOnly => "storage_read_only",
+		}
+	}
+
+	/// Attach the event's identifier to the messages logged by the given function.
+	pub fn log<F: FnOnce()>(self, f: F) {
+		with_event_id(self.id(), f);
+	}
+}
+
 pub trait HasLogger {
 	fn warn(&self, msg: &str);
 	fn info(&self, msg: &str);
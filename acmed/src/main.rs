@@ -1,70 +1,112 @@
-use crate::main_event_loop::MainEventLoop;
 use acme_common::crypto::{
-	get_lib_name, get_lib_version, HashFunction, JwsSignatureAlgorithm, KeyType,
+	convert_certificate, convert_private_key, get_lib_name, get_lib_version, KeyEncoding,
+	PrivateKeyFormat,
 };
-use acme_common::logs::{set_log_system, DEFAULT_LOG_LEVEL};
+use acme_common::error::{Error, ErrorKind};
+use acme_common::logs::{set_log_system, with_log_context, DEFAULT_LOG_LEVEL};
 use acme_common::{clean_pid_file, init_server};
-use async_lock::RwLock;
-use clap::{Arg, ArgAction, Command};
+use acmed::main_event_loop::MainEventLoop;
+use acmed::platform::{Current, Platform};
+use acmed::{config, control, init, privileges, terminal, RevocationReason};
+use acmed::{
+	APP_NAME, APP_THREAD_NAME, APP_VERSION, DEFAULT_CERT_FILE_MODE, DEFAULT_CONFIG_FILE,
+	DEFAULT_PID_FILE, DEFAULT_PK_FILE_MODE,
+};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap_complete::{generate, Shell};
 use log::error;
-use std::sync::Arc;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use tokio::runtime::Builder;
 
-mod account;
-mod acme_proto;
-mod certificate;
-mod config;
-mod duration;
-mod endpoint;
-mod hooks;
-mod http;
-mod identifier;
-mod jws;
-mod logs;
-mod main_event_loop;
-mod storage;
-mod template;
-
-pub const APP_NAME: &str = "ACMEd";
-pub const APP_THREAD_NAME: &str = "acmed-runtime";
-pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
-pub const DEFAULT_ACCOUNTS_DIR: &str = env!("ACMED_DEFAULT_ACCOUNTS_DIR");
-pub const DEFAULT_CERT_DIR: &str = env!("ACMED_DEFAULT_CERT_DIR");
-pub const DEFAULT_CERT_FORMAT: &str = env!("ACMED_DEFAULT_CERT_FORMAT");
-pub const DEFAULT_CONFIG_FILE: &str = env!("ACMED_DEFAULT_CONFIG_FILE");
-pub const DEFAULT_PID_FILE: &str = env!("ACMED_DEFAULT_PID_FILE");
-pub const DEFAULT_POOL_TIME: u64 = 5000;
-pub const DEFAULT_CSR_DIGEST: HashFunction = HashFunction::Sha256;
-pub const DEFAULT_CERT_KEY_TYPE: KeyType = KeyType::Rsa2048;
-pub const DEFAULT_CERT_FILE_MODE: u32 = 0o644;
-pub const DEFAULT_CERT_RANDOM_EARLY_RENEW: u64 = 0; // default to not renewing early
-pub const DEFAULT_CERT_RENEW_DELAY: u64 = 30 * 24 * 60 * 60; // 30 days
-pub const DEFAULT_PK_FILE_MODE: u32 = 0o600;
-pub const DEFAULT_ACCOUNT_FILE_MODE: u32 = 0o600;
-pub const DEFAULT_KP_REUSE: bool = false;
-pub const DEFAULT_ACCOUNT_KEY_TYPE: KeyType = KeyType::EcdsaP256;
-pub const DEFAULT_EXTERNAL_ACCOUNT_JWA: JwsSignatureAlgorithm = JwsSignatureAlgorithm::Hs256;
-pub const DEFAULT_POOL_NB_TRIES: usize = 20;
-pub const DEFAULT_POOL_WAIT_SEC: u64 = 5;
-pub const DEFAULT_HTTP_FAIL_NB_RETRY: usize = 10;
-pub const DEFAULT_HTTP_FAIL_WAIT_SEC: u64 = 1;
-pub const DEFAULT_HOOK_ALLOW_FAILURE: bool = false;
-pub const MAX_RATE_LIMIT_SLEEP_MILISEC: u64 = 3_600_000;
-pub const MIN_RATE_LIMIT_SLEEP_MILISEC: u64 = 100;
-
-type AccountSync = Arc<RwLock<account::Account>>;
-type EndpointSync = Arc<RwLock<endpoint::Endpoint>>;
-
-fn main() {
+fn block_on<F: Future>(future: F) -> F::Output {
 	Builder::new_multi_thread()
 		.enable_all()
 		.thread_name(APP_THREAD_NAME)
 		.build()
 		.unwrap()
-		.block_on(inner_main());
+		.block_on(future)
 }
 
-async fn inner_main() {
+/// Load the configuration, then drop the privileges if a user or a group has been specified.
+/// Since capabilities are per-thread, the configuration is loaded using a single-threaded
+/// runtime, which is shut down before the privileges are dropped.
+fn init_event_loop(
+	config_file: &str,
+	root_certs: &[&str],
+	daemon_matches: &ArgMatches,
+	is_daemon: bool,
+) -> Result<MainEventLoop, Error> {
+	let runtime = Builder::new_current_thread().enable_all().build()?;
+	let mut srv = runtime.block_on(MainEventLoop::new(config_file, root_certs))?;
+	drop(runtime);
+	if is_daemon {
+		srv.bind_control_socket();
+		srv.bind_web_ui();
+	}
+	let user = daemon_matches.get_one::<String>("user").map(|e| e.as_str());
+	let group = daemon_matches
+		.get_one::<String>("group")
+		.map(|e| e.as_str());
+	srv.drop_privileges(user, group)
+		.map_err(|e| e.prefix("unable to drop the privileges"))?;
+	Ok(srv)
+}
+
+fn get_daemon_args() -> Vec<Arg> {
+	vec![
+		Arg::new("foreground")
+			.short('f')
+			.long("foreground")
+			.help("Runs in the foreground")
+			.action(ArgAction::SetTrue),
+		Arg::new("pid-file")
+			.long("pid-file")
+			.help("Path to the PID file")
+			.num_args(1)
+			.value_name("FILE")
+			.default_value(DEFAULT_PID_FILE)
+			.default_value_if("no-pid-file", clap::builder::ArgPredicate::IsPresent, None)
+			.conflicts_with("no-pid-file"),
+		Arg::new("no-pid-file")
+			.long("no-pid-file")
+			.help("Do not create any PID file")
+			.conflicts_with("pid-file")
+			.action(ArgAction::SetTrue),
+		Arg::new("user")
+			.long("user")
+			.help("Run as this user once initialized")
+			.num_args(1)
+			.value_name("USER"),
+		Arg::new("group")
+			.long("group")
+			.help("Run as this group once initialized")
+			.num_args(1)
+			.value_name("GROUP"),
+		Arg::new("once")
+			.long("once")
+			.help("Renew the certificates which are due for renewal, then exit")
+			.conflicts_with_all(["foreground", "pid-file", "no-pid-file"])
+			.action(ArgAction::SetTrue),
+		Arg::new("check-config")
+			.long("check-config")
+			.help("Check the configuration, as the check command does, then exit")
+			.conflicts_with_all(["foreground", "pid-file", "no-pid-file", "once"])
+			.action(ArgAction::SetTrue),
+	]
+}
+
+fn get_certificate_arg() -> Arg {
+	Arg::new("certificate")
+		.help("Name or id of the certificate (can be set multiple times)")
+		.num_args(1)
+		.action(ArgAction::Append)
+		.value_name("CERTIFICATE")
+}
+
+fn get_cli() -> Command {
 	let full_version = format!(
 		"{APP_VERSION} built for {}\n\nCryptographic library:\n - {} {}\nHTTP client library:\n - {} {}",
 		env!("ACMED_TARGET"),
@@ -74,7 +116,7 @@ async fn inner_main() {
 		env!("ACMED_HTTP_LIB_VERSION")
 	);
 	let default_log_level = DEFAULT_LOG_LEVEL.to_string().to_lowercase();
-	let matches = Command::new(APP_NAME)
+	Command::new(APP_NAME)
 		.version(APP_VERSION)
 		.long_version(full_version)
 		.arg(
@@ -84,7 +126,8 @@ async fn inner_main() {
 				.help("Path to the main configuration file")
 				.num_args(1)
 				.value_name("FILE")
-				.default_value(DEFAULT_CONFIG_FILE),
+				.default_value(DEFAULT_CONFIG_FILE)
+				.global(true),
 		)
 		.arg(
 			Arg::new("log-level")
@@ -93,60 +136,524 @@ async fn inner_main() {
 				.num_args(1)
 				.value_name("LEVEL")
 				.value_parser(["error", "warn", "info", "debug", "trace"])
-				.default_value(default_log_level),
+				.default_value(default_log_level)
+				.global(true),
 		)
 		.arg(
 			Arg::new("to-syslog")
 				.long("log-syslog")
 				.help("Sends log messages via syslog")
-				.conflicts_with("to-stderr")
-				.action(ArgAction::SetTrue),
+				.action(ArgAction::SetTrue)
+				.global(true),
 		)
 		.arg(
 			Arg::new("to-stderr")
 				.long("log-stderr")
 				.help("Prints log messages to the standard error output")
-				.conflicts_with("to-syslog")
-				.action(ArgAction::SetTrue),
-		)
-		.arg(
-			Arg::new("foreground")
-				.short('f')
-				.long("foreground")
-				.help("Runs in the foreground")
-				.action(ArgAction::SetTrue),
+				.action(ArgAction::SetTrue)
+				.global(true),
 		)
 		.arg(
-			Arg::new("pid-file")
-				.long("pid-file")
-				.help("Path to the PID file")
+			Arg::new("color")
+				.long("color")
+				.help("Color the output of the commands")
 				.num_args(1)
-				.value_name("FILE")
-				.default_value(DEFAULT_PID_FILE)
-				.default_value_if("no-pid-file", clap::builder::ArgPredicate::IsPresent, None)
-				.conflicts_with("no-pid-file"),
-		)
-		.arg(
-			Arg::new("no-pid-file")
-				.long("no-pid-file")
-				.help("Do not create any PID file")
-				.conflicts_with("pid-file")
-				.action(ArgAction::SetTrue),
+				.value_name("WHEN")
+				.value_parser(["auto", "always", "never"])
+				.default_value("auto")
+				.global(true),
 		)
+		.args(get_daemon_args())
 		.arg(
 			Arg::new("root-cert")
 				.long("root-cert")
 				.help("Add a root certificate to the trust store (can be set multiple times)")
 				.num_args(1)
 				.action(ArgAction::Append)
-				.value_name("FILE"),
+				.value_name("FILE")
+				.global(true),
+		)
+		.subcommand(
+			Command::new("run")
+				.about("Run the daemon (default)")
+				.args(get_daemon_args()),
+		)
+		.subcommand(
+			Command::new("status")
+				.about("Display the state of the certificates")
+				.arg(get_certificate_arg())
+				.arg(
+					Arg::new("output")
+						.long("output")
+						.help("Output format")
+						.num_args(1)
+						.value_name("FORMAT")
+						.value_parser(["table", "json"])
+						.default_value("table"),
+				),
+		)
+		.subcommand(
+			Command::new("plan")
+				.about("Display when the certificates will be renewed")
+				.arg(get_certificate_arg()),
+		)
+		.subcommand(
+			Command::new("renew")
+				.about("Renew the certificates which are due for renewal, then exit")
+				.arg(get_certificate_arg())
+				.arg(
+					Arg::new("force")
+						.long("force")
+						.help("Renew the certificates even if they are not due for renewal")
+						.action(ArgAction::SetTrue),
+				),
+		)
+		.subcommand(
+			Command::new("revoke")
+				.about("Revoke a certificate")
+				.arg(
+					get_certificate_arg()
+						.help("Name or id of the certificate")
+						.required(true)
+						.action(ArgAction::Set),
+				)
+				.arg(
+					Arg::new("reason")
+						.long("reason")
+						.help("Reason of the revocation")
+						.num_args(1)
+						.value_name("REASON")
+						.value_parser([
+							"unspecified",
+							"key-compromise",
+							"affiliation-changed",
+							"superseded",
+							"cessation-of-operation",
+						])
+						.default_value("unspecified"),
+				),
+		)
+		.subcommand(Command::new("check").about("Check the configuration, then exit"))
+		.subcommand(
+			Command::new("ctl")
+				.about("Send a command to the running daemon using the control socket")
+				.arg(
+					Arg::new("socket")
+						.long("socket")
+						.help("Path to the control socket (default to the configured one)")
+						.num_args(1)
+						.value_name("FILE"),
+				)
+				.subcommand_required(true)
+				.subcommand(
+					Command::new("hello")
+						.about("Display the protocol version and capabilities of the daemon"),
+				)
+				.subcommand(
+					Command::new("renew")
+						.about("Renew the certificates now")
+						.arg(get_certificate_arg()),
+				)
+				.subcommand(
+					Command::new("deploy")
+						.about("Call the post-operation hooks of the certificates")
+						.arg(get_certificate_arg()),
+				)
+				.subcommand(
+					Command::new("status")
+						.about("Display the state of the daemon")
+						.arg(get_certificate_arg()),
+				)
+				.subcommand(
+					Command::new("hooks")
+						.about("Display the latest hook executions of the certificates")
+						.arg(get_certificate_arg()),
+				)
+				.subcommand(
+					Command::new("watch")
+						.about("Display the events of the certificates as they happen")
+						.arg(get_certificate_arg()),
+				),
+		)
+		.subcommand(
+			Command::new("account")
+				.about("Manage the accounts")
+				.subcommand_required(true)
+				.subcommand(
+					Command::new("register")
+						.about("Register the accounts used with an endpoint")
+						.arg(
+							Arg::new("endpoint")
+								.help("Name of the endpoint")
+								.required(true)
+								.value_name("ENDPOINT"),
+						)
+						.arg(
+							Arg::new("account")
+								.long("account")
+								.help("Name of the account (default to every account used with the endpoint, can be set multiple times)")
+								.num_args(1)
+								.action(ArgAction::Append)
+								.value_name("ACCOUNT"),
+						)
+						.arg(
+							Arg::new("yes")
+								.long("yes")
+								.help("Do not ask for a confirmation")
+								.action(ArgAction::SetTrue),
+						),
+				)
+				.subcommand(
+					Command::new("agree-tos")
+						.about("Agree to the current terms of service of an endpoint")
+						.arg(
+							Arg::new("endpoint")
+								.help("Name of the endpoint")
+								.required(true)
+								.value_name("ENDPOINT"),
+						)
+						.arg(
+							Arg::new("account")
+								.long("account")
+								.help("Name of the account (default to every account used with the endpoint, can be set multiple times)")
+								.num_args(1)
+								.action(ArgAction::Append)
+								.value_name("ACCOUNT"),
+						)
+						.arg(
+							Arg::new("yes")
+								.long("yes")
+								.help("Do not ask for a confirmation")
+								.action(ArgAction::SetTrue),
+						),
+				),
 		)
-		.get_matches();
+		.subcommand(
+			Command::new("thumbprint")
+				.about("Print the thumbprint of the accounts' keys")
+				.arg(
+					Arg::new("account")
+						.help("Name of the account (default to every account)")
+						.num_args(0..)
+						.action(ArgAction::Append)
+						.value_name("ACCOUNT"),
+				),
+		)
+		.subcommand(
+			Command::new("convert")
+				.about("Convert an already issued certificate or private key to another format")
+				.arg(
+					Arg::new("in")
+						.long("in")
+						.help("File to convert, either in PEM or DER")
+						.required(true)
+						.num_args(1)
+						.value_name("FILE"),
+				)
+				.arg(
+					Arg::new("out")
+						.long("out")
+						.help("File to write")
+						.required(true)
+						.num_args(1)
+						.value_name("FILE"),
+				)
+				.arg(
+					Arg::new("kind")
+						.long("kind")
+						.help("Kind of the file to convert")
+						.required(true)
+						.num_args(1)
+						.value_parser(["certificate", "private-key"])
+						.value_name("KIND"),
+				)
+				.arg(
+					Arg::new("encoding")
+						.long("encoding")
+						.help("Encoding of the output file (default to der if its extension is .der, pem otherwise)")
+						.num_args(1)
+						.value_parser(["pem", "der"])
+						.value_name("ENCODING"),
+				)
+				.arg(
+					Arg::new("format")
+						.long("format")
+						.help("Format of the output private key")
+						.num_args(1)
+						.value_parser(["pkcs1", "pkcs8", "sec1"])
+						.default_value("pkcs8")
+						.value_name("FORMAT"),
+				),
+		)
+		.subcommand(
+			Command::new("completions")
+				.about("Generate the shell completion script")
+				.arg(
+					Arg::new("shell")
+						.help("Shell to generate the completion script for")
+						.required(true)
+						.value_parser(value_parser!(Shell)),
+				),
+		)
+		.subcommand(
+			Command::new("init")
+				.about("Interactively generate a starter configuration file")
+				.arg(
+					Arg::new("domain")
+						.long("domain")
+						.help("Domain to include in the certificate (can be set multiple times)")
+						.num_args(1)
+						.action(ArgAction::Append)
+						.value_name("DOMAIN"),
+				)
+				.arg(
+					Arg::new("challenge")
+						.long("challenge")
+						.help("Challenge used to validate the domains")
+						.num_args(1)
+						.value_parser(["http-01", "dns-01", "tls-alpn-01"])
+						.value_name("CHALLENGE"),
+				)
+				.arg(
+					Arg::new("webroot")
+						.long("webroot")
+						.help("Document root of the web server (http-01 only)")
+						.num_args(1)
+						.value_name("DIR"),
+				)
+				.arg(
+					Arg::new("dns-hook")
+						.long("dns-hook")
+						.help("Command creating the TXT record (dns-01 only)")
+						.num_args(1)
+						.value_name("CMD"),
+				)
+				.arg(
+					Arg::new("dns-clean-hook")
+						.long("dns-clean-hook")
+						.help("Command removing the TXT record (dns-01 only)")
+						.num_args(1)
+						.value_name("CMD"),
+				)
+				.arg(
+					Arg::new("tacd-port")
+						.long("tacd-port")
+						.help("Port tacd should listen on (tls-alpn-01 only)")
+						.num_args(1)
+						.value_name("PORT"),
+				)
+				.arg(
+					Arg::new("email")
+						.long("email")
+						.help("Contact email address of the account")
+						.num_args(1)
+						.value_name("EMAIL"),
+				)
+				.arg(
+					Arg::new("endpoint-url")
+						.long("endpoint-url")
+						.help("URL of the ACME directory")
+						.num_args(1)
+						.value_name("URL"),
+				)
+				.arg(
+					Arg::new("tos-agreed")
+						.long("tos-agreed")
+						.help("Agree to the terms of service of the certificate authority")
+						.action(ArgAction::SetTrue),
+				)
+				.arg(
+					Arg::new("accounts-dir")
+						.long("accounts-dir")
+						.help("Directory where the accounts are stored")
+						.num_args(1)
+						.value_name("DIR"),
+				)
+				.arg(
+					Arg::new("certs-dir")
+						.long("certs-dir")
+						.help("Directory where the certificates are stored")
+						.num_args(1)
+						.value_name("DIR"),
+				)
+				.arg(
+					Arg::new("force")
+						.long("force")
+						.help("Overwrite the configuration file if it already exists")
+						.action(ArgAction::SetTrue),
+				),
+		)
+}
+
+/// Return the exit status of a command which failed because of the given error.
+fn get_exit_code(e: &Error) -> i32 {
+	match e.kind {
+		ErrorKind::Config => 3,
+		_ => 1,
+	}
+}
+
+fn exit_on_error<T>(res: Result<T, Error>) -> T {
+	match res {
+		Ok(v) => v,
+		Err(e) => {
+			with_log_context(e.get_log_context(), || error!("{e}"));
+			std::process::exit(get_exit_code(&e));
+		}
+	}
+}
 
+/// Send a command to the daemon and print its response. Return whether or not the command
+/// succeeded.
+async fn run_ctl(matches: &ArgMatches, config_file: &str) -> Result<bool, Error> {
+	let socket = match matches.get_one::<String>("socket") {
+		Some(s) => s.to_owned(),
+		None => config::from_file(config_file)?
+			.get_control_socket()
+			.ok_or_else(|| Error::from("no control socket has been configured"))?,
+	};
+	let (name, sub_matches) = matches.subcommand().unwrap();
+	let certificates: Vec<String> = match sub_matches.try_get_many::<String>("certificate") {
+		Ok(Some(v)) => v.map(|e| e.to_owned()).collect(),
+		_ => vec![],
+	};
+	let request = match name {
+		"hello" => control::Request::Hello,
+		"renew" => control::Request::Renew { certificates },
+		"deploy" => control::Request::Deploy { certificates },
+		"status" => control::Request::Status { certificates },
+		"hooks" => control::Request::Hooks { certificates },
+		"watch" => {
+			let request = control::Request::Watch { certificates };
+			let response = control::watch(&socket, &request, |event| println!("{event}")).await?;
+			if !response.success {
+				eprintln!("Error: {}", response.message);
+			}
+			return Ok(response.success);
+		}
+		_ => unreachable!(),
+	};
+	let response = control::send(&socket, &request).await?;
+	if response.success {
+		println!("{}", response.message);
+	} else {
+		eprintln!("Error: {}", response.message);
+	}
+	if let Some(data) = response.data {
+		println!("{}", serde_json::to_string_pretty(&data)?);
+	}
+	Ok(response.success)
+}
+
+/// Convert a certificate or a private key, so alternate formats can be produced without renewing
+/// the certificate.
+fn run_convert(matches: &ArgMatches) -> Result<(), Error> {
+	let input = matches.get_one::<String>("in").unwrap();
+	let output = matches.get_one::<String>("out").unwrap();
+	let encoding = match matches.get_one::<String>("encoding") {
+		Some(e) => e.parse()?,
+		None if Path::new(output).extension() == Some(OsStr::new("der")) => KeyEncoding::Der,
+		None => KeyEncoding::Pem,
+	};
+	let data = std::fs::read(input).map_err(|e| Error::from(e).prefix(input))?;
+	let key = match matches.get_one::<String>("kind").map(|e| e.as_str()) {
+		Some("private-key") => {
+			let format: PrivateKeyFormat = matches.get_one::<String>("format").unwrap().parse()?;
+			let key = convert_private_key(&data, format, encoding).map_err(|e| e.prefix(input))?;
+			Some(key)
+		}
+		_ => None,
+	};
+	let mode = match key {
+		Some(_) => DEFAULT_PK_FILE_MODE,
+		None => DEFAULT_CERT_FILE_MODE,
+	};
+	let file = Current::file_options(mode)
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(output)
+		.map_err(|e| Error::from(e).prefix(output))?;
+	let mut file = BufWriter::new(file);
+	match key {
+		Some(key) => file
+			.write_all(&key)
+			.map_err(|e| Error::from(e).prefix(output))?,
+		None => {
+			// The certificates are written as soon as they are converted, hence a partial output
+			// has to be removed on failure.
+			if let Err(e) = convert_certificate(&data, encoding, &mut file) {
+				drop(file);
+				let _ = std::fs::remove_file(output);
+				return Err(e.prefix(input));
+			}
+		}
+	}
+	file.flush().map_err(|e| Error::from(e).prefix(output))?;
+	Ok(())
+}
+
+fn main() {
+	let matches = get_cli().get_matches();
+
+	let config_file = matches
+		.get_one::<String>("config")
+		.map(|e| e.as_str())
+		.unwrap_or(DEFAULT_CONFIG_FILE);
+	terminal::set_color(
+		matches
+			.get_one::<String>("color")
+			.map(|e| e.as_str())
+			.unwrap_or("auto"),
+	);
+
+	match matches.subcommand() {
+		Some(("init", init_matches)) => match init::run(init_matches, config_file) {
+			Ok(_) => std::process::exit(0),
+			Err(e) => {
+				eprintln!("Error: {e}");
+				std::process::exit(1);
+			}
+		},
+		Some(("ctl", ctl_matches)) => match block_on(run_ctl(ctl_matches, config_file)) {
+			Ok(true) => std::process::exit(0),
+			Ok(false) => std::process::exit(1),
+			Err(e) => {
+				eprintln!("Error: {e}");
+				std::process::exit(1);
+			}
+		},
+		Some(("convert", sub_matches)) => match run_convert(sub_matches) {
+			Ok(_) => std::process::exit(0),
+			Err(e) => {
+				eprintln!("Error: {e}");
+				std::process::exit(1);
+			}
+		},
+		Some(("completions", sub_matches)) => {
+			let shell = *sub_matches.get_one::<Shell>("shell").unwrap();
+			generate(shell, &mut get_cli(), "acmed", &mut io::stdout());
+			std::process::exit(0);
+		}
+		_ => {}
+	};
+
+	let daemon_matches = match matches.subcommand() {
+		Some(("run", sub_matches)) => sub_matches,
+		_ => &matches,
+	};
+	let is_once = daemon_matches.get_flag("once");
+	let is_check = daemon_matches.get_flag("check-config");
+
+	// Commands other than the daemon log to the standard error output by default.
+	let is_daemon =
+		matches!(matches.subcommand(), None | Some(("run", _))) && !is_once && !is_check;
+	let to_syslog = matches.get_flag("to-syslog");
+	let to_stderr = matches.get_flag("to-stderr") || (!is_daemon && !to_syslog);
 	match set_log_system(
 		matches.get_one::<String>("log-level").map(|e| e.as_str()),
-		matches.get_flag("to-syslog"),
-		matches.get_flag("to-stderr"),
+		to_syslog,
+		to_stderr,
 	) {
 		Ok(_) => {}
 		Err(e) => {
@@ -160,21 +667,153 @@ async fn inner_main() {
 		None => vec![],
 	};
 
-	let config_file = matches
-		.get_one::<String>("config")
-		.map(|e| e.as_str())
-		.unwrap_or(DEFAULT_CONFIG_FILE);
-	let pid_file = matches.get_one::<String>("pid-file").map(|e| e.as_str());
+	if is_once {
+		// A single pass, meant to be run by cron or a systemd timer.
+		let mut srv = exit_on_error(init_event_loop(
+			config_file,
+			&root_certs,
+			daemon_matches,
+			false,
+		));
+		if !exit_on_error(block_on(srv.renew(&[], false))) {
+			std::process::exit(1);
+		}
+		return;
+	}
+
+	if is_check {
+		let user = daemon_matches.get_one::<String>("user").map(|e| e.as_str());
+		let group = daemon_matches
+			.get_one::<String>("group")
+			.map(|e| e.as_str());
+		block_on(check_config(config_file, &root_certs, user, group));
+		return;
+	}
 
-	init_server(matches.get_flag("foreground"), pid_file);
+	if !is_daemon {
+		block_on(run_command(&matches, config_file, &root_certs));
+		return;
+	}
 
-	let mut srv = match MainEventLoop::new(config_file, &root_certs).await {
+	let pid_file = daemon_matches
+		.get_one::<String>("pid-file")
+		.map(|e| e.as_str());
+
+	init_server(daemon_matches.get_flag("foreground"), pid_file);
+
+	let mut srv = match init_event_loop(config_file, &root_certs, daemon_matches, true) {
 		Ok(s) => s,
 		Err(e) => {
-			error!("{e}");
+			with_log_context(e.get_log_context(), || error!("{e}"));
 			let _ = clean_pid_file(pid_file);
-			std::process::exit(1);
+			std::process::exit(get_exit_code(&e));
 		}
 	};
-	srv.run().await;
+	block_on(srv.run());
+	let _ = clean_pid_file(pid_file);
+}
+
+/// Check the configuration without contacting any endpoint nor writing any file, the access
+/// rights being checked for the given user and group. The exit status is 3 if it is invalid.
+async fn check_config(
+	config_file: &str,
+	root_certs: &[&str],
+	user: Option<&str>,
+	group: Option<&str>,
+) {
+	let srv = exit_on_error(
+		MainEventLoop::new_check(config_file, root_certs)
+			.await
+			.map_err(|e| e.or_kind(ErrorKind::Config)),
+	);
+	let (uid, gid) =
+		exit_on_error(privileges::get_ids(user, group).map_err(|e| e.or_kind(ErrorKind::Config)));
+	exit_on_error(
+		srv.check(uid, gid)
+			.await
+			.map_err(|e| e.or_kind(ErrorKind::Config)),
+	);
+}
+
+/// Run a command other than the daemon.
+async fn run_command(matches: &ArgMatches, config_file: &str, root_certs: &[&str]) {
+	if matches.subcommand_name() == Some("check") {
+		check_config(config_file, root_certs, None, None).await;
+		return;
+	}
+	let mut srv = exit_on_error(MainEventLoop::new(config_file, root_certs).await);
+	let (name, sub_matches) = matches.subcommand().unwrap();
+	let crt_names: Vec<&str> = match sub_matches
+		.try_get_many::<String>("certificate")
+		.ok()
+		.flatten()
+	{
+		Some(v) => v.map(|e| e.as_str()).collect(),
+		None => vec![],
+	};
+	match name {
+		"status" => {
+			let as_json = sub_matches
+				.get_one::<String>("output")
+				.map(|e| e == "json")
+				.unwrap_or(false);
+			exit_on_error(srv.print_status(&crt_names, as_json).await)
+		}
+		"plan" => exit_on_error(srv.print_plan(&crt_names).await),
+		"account" => {
+			let (action, account_matches) = sub_matches.subcommand().unwrap();
+			let endpoint = account_matches.get_one::<String>("endpoint").unwrap();
+			let acc_names: Vec<&str> = match account_matches.get_many::<String>("account") {
+				Some(v) => v.map(|e| e.as_str()).collect(),
+				None => vec![],
+			};
+			let assume_yes = account_matches.get_flag("yes");
+			let res = match action {
+				"agree-tos" => {
+					srv.agree_to_terms_of_service(endpoint, &acc_names, assume_yes)
+						.await
+				}
+				_ => {
+					srv.register_accounts(endpoint, &acc_names, assume_yes)
+						.await
+				}
+			};
+			srv.flush_ca_rate_limits().await;
+			exit_on_error(res);
+		}
+		"thumbprint" => {
+			let acc_names: Vec<&str> = match sub_matches.get_many::<String>("account") {
+				Some(v) => v.map(|e| e.as_str()).collect(),
+				None => vec![],
+			};
+			exit_on_error(srv.print_thumbprints(&acc_names).await);
+		}
+		"renew" => {
+			let force = sub_matches.get_flag("force");
+			if !exit_on_error(srv.renew(&crt_names, force).await) {
+				std::process::exit(1);
+			}
+		}
+		"revoke" => {
+			let reason = sub_matches
+				.get_one::<String>("reason")
+				.map(|e| e.as_str())
+				.unwrap_or("unspecified");
+			let reason = exit_on_error(reason.parse::<RevocationReason>());
+			let res = srv.revoke(crt_names[0], reason).await;
+			srv.flush_ca_rate_limits().await;
+			exit_on_error(res);
+		}
+		_ => unreachable!(),
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::get_cli;
+
+	#[test]
+	fn test_cli() {
+		get_cli().debug_assert();
+	}
 }
@@ -1,30 +1,147 @@
 use crate::account::Account;
-use crate::acme_proto::request_certificate;
+use crate::acme_proto::{
+	get_terms_of_service, request_certificate, revoke_certificate, RevocationReason,
+};
+use crate::advisory::{self, VersionAdvisory};
 use crate::certificate::Certificate;
-use crate::config;
-use crate::endpoint::Endpoint;
-use crate::hooks::HookType;
-use crate::logs::HasLogger;
-use crate::storage::FileManager;
+use crate::config::{self, DuplicateIdentifiers};
+use crate::control::{self, ControlRequest, Request, Response};
+use crate::email::{EmailNotifier, FailureTracker};
+use crate::endpoint::{format_timestamp, CaRateLimits, Endpoint};
+use crate::events;
+use crate::hooks::{self, Hook, HookRecorder, HookType};
+use crate::http::with_timeout;
+use crate::init::prompt;
+use crate::kubernetes;
+use crate::logs::{HasLogger, LogEvent};
+use crate::ocsp;
+use crate::privileges;
+use crate::solver;
+use crate::storage::{
+	self, certificate_files_exists, check_manifest, check_writable, get_certificate, is_read_only,
+	FileManager,
+};
+use crate::template::{self, Templates};
+use crate::terminal::{self, paint, print_table, Cell, Progress, Style};
+use crate::watched::{AlertedCertificates, WatchedCertificate};
+use crate::web_ui::{self, WebUi};
+use crate::webhooks::{self, Webhook};
 use crate::{AccountSync, EndpointSync};
-use acme_common::error::Error;
+use acme_common::error::{Error, ErrorKind};
+use acme_common::logs::{set_log_format, set_syslog};
+use acme_common::now;
 use async_lock::RwLock;
-use futures::stream::FuturesUnordered;
-use futures::StreamExt;
-use std::collections::HashMap;
+use futures::future;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::sync::{mpsc, oneshot, watch, Notify, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+
+struct RenewalTask {
+	stop: watch::Sender<bool>,
+	force: Arc<Notify>,
+	handle: JoinHandle<()>,
+}
+
+/// Certificate as it is requested from one of its endpoints, using the account associated with
+/// this endpoint.
+#[derive(Clone)]
+struct RenewalTarget {
+	certificate: Arc<Certificate>,
+	account_s: AccountSync,
+	endpoint_s: EndpointSync,
+}
 
 pub struct MainEventLoop {
-	certificates: HashMap<String, Certificate>,
+	config_file: String,
+	config_files: Vec<PathBuf>,
+	root_certs: Vec<String>,
+	certificates: HashMap<String, Arc<Certificate>>,
 	accounts: HashMap<String, AccountSync>,
 	endpoints: HashMap<String, EndpointSync>,
+	/// Latest rate-limit information sent by the CA of each endpoint, which is shared with the
+	/// endpoint so it can be read without waiting for the endpoint's lock.
+	ca_rate_limits: HashMap<String, CaRateLimits>,
+	renewals: HashMap<String, RenewalTask>,
+	max_parallel_renewals: usize,
+	renewal_permits: Arc<Semaphore>,
+	shutdown_timeout: Duration,
+	control_socket: Option<(String, u32)>,
+	control_listener: Option<control::Listener>,
+	state_dir: String,
+	has_file_owners: bool,
+	/// Names of the hooks which run as another user or group, which requires the daemon to keep
+	/// its privileges.
+	hook_owners: Vec<String>,
+	version_advisory: Option<VersionAdvisory>,
+	version_advisory_task: Option<JoinHandle<()>>,
+	watched: Vec<WatchedCertificate>,
+	watched_alerted: AlertedCertificates,
+	watched_tasks: Vec<JoinHandle<()>>,
+	web_ui: Option<WebUi>,
+	web_ui_listener: Option<TcpListener>,
+	webhooks: Vec<Webhook>,
+	webhooks_task: Option<JoinHandle<()>>,
+	/// Compiled templates of the configuration, until they replace the shared registry.
+	templates: Option<Templates>,
 }
 
 impl MainEventLoop {
 	pub async fn new(config_file: &str, root_certs: &[&str]) -> Result<Self, Error> {
-		let cnf = config::from_file(config_file)?;
+		let mut srv = Self::load(config_file, root_certs, false).await?;
+		if let Some(templates) = srv.templates.take() {
+			templates.apply();
+		}
+		Ok(srv)
+	}
+
+	/// Load the configuration without any side effect, such as setting up the logs or rotating
+	/// the account keys, so it can be checked.
+	pub async fn new_check(config_file: &str, root_certs: &[&str]) -> Result<Self, Error> {
+		Self::load(config_file, root_certs, true).await
+	}
+
+	async fn load(config_file: &str, root_certs: &[&str], is_check: bool) -> Result<Self, Error> {
+		template::start_loading();
+		let cnf = config::from_file(config_file).map_err(|e| e.or_kind(ErrorKind::Config))?;
+		let max_parallel_renewals = cnf
+			.get_max_parallel_renewals()
+			.map_err(|e| e.or_kind(ErrorKind::Config))?;
+		let control_socket = cnf
+			.get_control_socket()
+			.map(|p| (p, cnf.get_control_socket_mode()));
+		let has_file_owners = [
+			cnf.get_account_dir_user(),
+			cnf.get_account_dir_group(),
+			cnf.get_cert_dir_user(),
+			cnf.get_cert_dir_group(),
+			cnf.get_cert_file_user(),
+			cnf.get_cert_file_group(),
+			cnf.get_pk_file_user(),
+			cnf.get_pk_file_group(),
+		]
+		.iter()
+		.any(|e| e.is_some());
+		let log_format = cnf
+			.get_log_format()
+			.map_err(|e| e.or_kind(ErrorKind::Config))?;
+		let time_offset = cnf
+			.get_time_offset()
+			.map_err(|e| e.or_kind(ErrorKind::Config))?;
+		if !is_check {
+			set_log_format(log_format);
+			set_syslog(
+				cnf.get_log_syslog(),
+				cnf.get_syslog_facility().as_deref(),
+				cnf.get_syslog_tag().as_deref(),
+			)?;
+			acme_common::set_time_offset(time_offset)?;
+		}
 		let file_hooks = vec![
 			HookType::FilePreCreate,
 			HookType::FilePostCreate,
@@ -40,6 +157,7 @@ impl MainEventLoop {
 			HookType::ChallengeDns01Clean,
 			HookType::ChallengeTlsAlpn01,
 			HookType::ChallengeTlsAlpn01Clean,
+			HookType::ChallengeReady,
 			HookType::PostOperation,
 		]
 		.into_iter()
@@ -50,6 +168,7 @@ impl MainEventLoop {
 			let fm = FileManager {
 				account_directory: cnf.get_account_dir(),
 				account_name: acc.name.clone(),
+				endpoint_name: String::new(),
 				crt_name: String::new(),
 				crt_name_format: String::new(),
 				crt_directory: String::new(),
@@ -60,162 +179,2349 @@ impl MainEventLoop {
 				pk_file_mode: cnf.get_pk_file_mode(),
 				pk_file_owner: cnf.get_pk_file_user(),
 				pk_file_group: cnf.get_pk_file_group(),
+				pk_encryption: None,
+				pk_format: crate::DEFAULT_PK_FORMAT,
+				pk_encoding: crate::DEFAULT_PK_ENCODING,
 				hooks: acc
-					.get_hooks(&cnf)?
+					.get_hooks(&cnf)
+					.map_err(|e| e.or_kind(ErrorKind::Config))?
 					.iter()
 					.filter(|h| !h.hook_type.is_disjoint(&file_hooks))
 					.map(|e| e.to_owned())
 					.collect(),
 				env: acc.env.clone(),
+				labels: BTreeMap::new(),
 			};
-			let account = acc.to_generic(&fm).await?;
+			let account = acc.to_generic(&cnf, &fm, !is_check).await?;
 			let name = acc.name.clone();
 			accounts.insert(name, account);
 		}
 
+		let email = cnf
+			.get_email_notifier()
+			.map_err(|e| e.or_kind(ErrorKind::Config))?
+			.map(Arc::new);
 		let mut endpoints: HashMap<String, Endpoint> = HashMap::new();
-		let mut certificates: HashMap<String, Certificate> = HashMap::new();
+		let mut certificates: HashMap<String, Arc<Certificate>> = HashMap::new();
+		let mut seen_identifiers: HashMap<(String, String), String> = HashMap::new();
 		for crt in cnf.certificate.iter() {
-			let endpoint = crt.get_endpoint(&cnf, root_certs)?;
-			let endpoint_name = endpoint.name.clone();
-			let crt_name = crt.get_crt_name()?;
-			let key_type = crt.get_key_type()?;
-			let hooks = crt.get_hooks(&cnf)?;
-			let fm = FileManager {
-				account_directory: cnf.get_account_dir(),
-				account_name: crt.account.clone(),
-				crt_name: crt_name.clone(),
-				crt_name_format: crt.get_crt_name_format(&cnf)?,
-				crt_directory: crt.get_crt_dir(&cnf),
-				crt_key_type: key_type.to_string(),
-				cert_file_mode: cnf.get_cert_file_mode(),
-				cert_file_owner: cnf.get_cert_file_user(),
-				cert_file_group: cnf.get_cert_file_group(),
-				pk_file_mode: cnf.get_pk_file_mode(),
-				pk_file_owner: cnf.get_pk_file_user(),
-				pk_file_group: cnf.get_pk_file_group(),
-				hooks: hooks
-					.iter()
-					.filter(|h| !h.hook_type.is_disjoint(&file_hooks))
-					.map(|e| e.to_owned())
-					.collect(),
-				env: crt.env.clone(),
-			};
-			let cert = Certificate {
-				account_name: crt.account.clone(),
-				identifiers: crt.get_identifiers()?,
-				subject_attributes: crt.subject_attributes.to_generic(),
-				key_type,
-				csr_digest: crt.get_csr_digest()?,
-				kp_reuse: crt.get_kp_reuse(),
-				endpoint_name: endpoint_name.clone(),
-				hooks: hooks
-					.iter()
-					.filter(|h| !h.hook_type.is_disjoint(&cert_hooks))
-					.map(|e| e.to_owned())
-					.collect(),
-				crt_name,
-				env: crt.env.to_owned(),
-				random_early_renew: crt.get_random_early_renew(&cnf)?,
-				renew_delay: crt.get_renew_delay(&cnf)?,
-				file_manager: fm,
-			};
+			let (mut cert, endpoints_accounts) =
+				load_certificate(&cnf, crt, root_certs, &email, &file_hooks, &cert_hooks)
+					.map_err(|e| e.or_kind(ErrorKind::Config))?;
 			let crt_id = cert.get_id();
 			if certificates.contains_key(&crt_id) {
 				let msg = format!("{crt_id}: duplicate certificate id");
-				return Err(msg.into());
+				return Err(Error::new(ErrorKind::Config, &msg));
 			}
-			match accounts.get_mut(&crt.account) {
-				Some(acc) => acc.add_endpoint_name(&endpoint_name),
-				None => {
-					let msg = format!("{}: account not found", &crt.account);
-					return Err(msg.into());
+			check_duplicate_identifiers(
+				&mut cert,
+				crt.get_duplicate_identifiers(&cnf),
+				&mut seen_identifiers,
+			)?;
+			for (endpoint, account_name) in endpoints_accounts {
+				accounts
+					.get_mut(&account_name)
+					.ok_or_else(|| {
+						let msg = format!("{account_name}: account not found");
+						Error::new(ErrorKind::Config, &msg)
+					})?
+					.add_endpoint_name(&endpoint.name);
+				if !endpoints.contains_key(&endpoint.name) {
+					endpoints.insert(endpoint.name.clone(), endpoint);
 				}
-			};
-			if !endpoints.contains_key(&endpoint.name) {
-				endpoints.insert(endpoint.name.clone(), endpoint);
 			}
-			certificates.insert(crt_id, cert);
+			certificates.insert(crt_id, Arc::new(cert));
+		}
+		for acc in &cnf.account {
+			if acc.order_limits.is_empty() {
+				continue;
+			}
+			let prefix = format!("account \"{}\"", acc.name);
+			for endpoint_name in accounts[&acc.name].endpoints.keys() {
+				if let Some(endpoint) = endpoints.get_mut(endpoint_name) {
+					endpoint
+						.set_account_order_limits(
+							&acc.name,
+							&acc.order_limits,
+							&cnf.get_state_dir(),
+						)
+						.map_err(|e| e.prefix(&prefix).or_kind(ErrorKind::Config))?;
+				}
+			}
+		}
+		let watched = cnf
+			.get_watched_certificates()
+			.map_err(|e| e.or_kind(ErrorKind::Config))?;
+		for w in watched.iter() {
+			if certificates.contains_key(&w.name) {
+				let msg = format!(
+					"{}: watched certificate name already used as a certificate id",
+					w.name
+				);
+				return Err(Error::new(ErrorKind::Config, &msg));
+			}
+		}
+
+		let web_ui = cnf.get_web_ui().map_err(|e| e.or_kind(ErrorKind::Config))?;
+		if let Some(id) = web_ui.as_ref().and_then(|ui| ui.certificate.as_ref()) {
+			if !certificates.contains_key(id) {
+				let msg = format!("{id}: web UI certificate not found");
+				return Err(Error::new(ErrorKind::Config, &msg));
+			}
+		}
+
+		let version_advisory = cnf
+			.get_version_advisory()
+			.map_err(|e| e.or_kind(ErrorKind::Config))?;
+		let mut hook_owners: Vec<String> = certificates
+			.values()
+			.flat_map(|c| c.hooks.iter().chain(c.file_manager.hooks.iter()))
+			.chain(accounts.values().flat_map(|a| a.file_manager.hooks.iter()))
+			.chain(version_advisory.iter().flat_map(|a| a.hooks.iter()))
+			.filter(|h| h.uid.is_some() || h.gid.is_some())
+			.map(|h| h.name.to_owned())
+			.collect();
+		hook_owners.sort_unstable();
+		hook_owners.dedup();
+		if privileges::are_dropped() && !hook_owners.is_empty() {
+			let msg = format!(
+				"hook \"{}\": the user and group cannot be changed once the privileges have been dropped",
+				hook_owners[0]
+			);
+			return Err(Error::new(ErrorKind::Config, &msg));
 		}
 
 		Ok(MainEventLoop {
+			config_file: config_file.to_string(),
+			config_files: cnf.loaded_files.clone(),
+			root_certs: root_certs.iter().map(|e| e.to_string()).collect(),
 			certificates,
 			accounts: accounts
 				.into_iter()
 				.map(|(k, v)| (k, Arc::new(RwLock::new(v))))
 				.collect(),
+			ca_rate_limits: endpoints
+				.iter()
+				.map(|(k, v)| (k.to_owned(), v.ca_rate_limits.clone()))
+				.collect(),
 			endpoints: endpoints
 				.into_iter()
 				.map(|(k, v)| (k, Arc::new(RwLock::new(v))))
 				.collect(),
+			renewals: HashMap::new(),
+			max_parallel_renewals,
+			renewal_permits: Arc::new(Semaphore::new(max_parallel_renewals)),
+			shutdown_timeout: cnf
+				.get_shutdown_timeout()
+				.map_err(|e| e.or_kind(ErrorKind::Config))?,
+			control_socket,
+			control_listener: None,
+			state_dir: cnf.get_state_dir(),
+			has_file_owners,
+			hook_owners,
+			version_advisory,
+			version_advisory_task: None,
+			watched,
+			watched_alerted: AlertedCertificates::default(),
+			watched_tasks: vec![],
+			web_ui,
+			web_ui_listener: None,
+			webhooks: cnf
+				.get_webhooks()
+				.map_err(|e| e.or_kind(ErrorKind::Config))?,
+			webhooks_task: None,
+			templates: Some(template::finish_loading()),
 		})
 	}
 
+	/// Apply the rate limits of the solvers used by the certificates.
+	fn set_solver_rate_limits(&self) {
+		let solvers = self
+			.certificates
+			.values()
+			.flat_map(|c| c.identifiers.iter())
+			.filter_map(|i| i.solver.as_ref());
+		if let Err(e) = solver::set_rate_limits(solvers) {
+			log::warn!("{e}");
+		}
+	}
+
 	pub async fn run(&mut self) {
-		let mut renewals = FuturesUnordered::new();
-		for (_, crt) in self.certificates.iter_mut() {
-			log::trace!("Adding certificate: {}", crt.get_id());
-			if let Some(acc) = self.accounts.get(&crt.account_name) {
-				if let Some(ept) = self.endpoints.get(&crt.endpoint_name) {
-					renewals.push(renew_certificate(crt, acc.clone(), ept.clone()));
-				} else {
+		if self.certificates.is_empty() && self.watched.is_empty() {
+			log::error!("No certificate found.");
+			return;
+		}
+		self.set_solver_rate_limits();
+		let mut directories: Vec<&str> = self
+			.certificates
+			.values()
+			.flat_map(|c| c.file_manager.directories())
+			.collect();
+		directories.sort_unstable();
+		directories.dedup();
+		check_writable(&directories).await;
+		self.start_webhooks();
+		let ids: Vec<String> = self.certificates.keys().map(|e| e.to_owned()).collect();
+		for id in ids {
+			self.start_renewal(&id, None);
+		}
+		self.start_version_advisory();
+		self.start_watched();
+		let control_path = match &self.control_listener {
+			Some(_) => self
+				.control_socket
+				.as_ref()
+				.map(|(path, _)| path.to_owned()),
+			None => None,
+		};
+		let mut control = match self.control_listener.take() {
+			Some(listener) => match control::listen(listener) {
+				Ok(rx) => Some(rx),
+				Err(e) => {
+					log::error!("unable to listen on the control socket: {e}");
+					None
 				}
-			} else {
+			},
+			None => None,
+		};
+		let mut web_ui = match self.web_ui_listener.take() {
+			Some(listener) => match self.listen_web_ui(listener).await {
+				Ok(rx) => Some(rx),
+				Err(e) => {
+					log::error!("unable to serve the web UI: {e}");
+					None
+				}
+			},
+			None => None,
+		};
+		let mut sighup = listen_signal(SignalType::Hangup, "SIGHUP");
+		let mut sigint = listen_signal(SignalType::Interrupt, "SIGINT");
+		let mut sigterm = listen_signal(SignalType::Terminate, "SIGTERM");
+		let signal_name = loop {
+			tokio::select! {
+				Some(_) = recv_signal(&mut sigint) => break "SIGINT",
+				Some(_) = recv_signal(&mut sigterm) => break "SIGTERM",
+				Some(_) = recv_signal(&mut sighup) => {
+					log::info!("SIGHUP received: reloading the configuration");
+					if let Err(e) = self.reload().await {
+						log::error!("unable to reload the configuration: {e}");
+					}
+				}
+				Some((request, reply)) = recv_control(&mut control) => {
+					self.handle_control_request(request, reply);
+				}
+				Some((request, reply)) = recv_control(&mut web_ui) => {
+					self.handle_control_request(request, reply);
+				}
+				else => return future::pending().await,
 			}
+		};
+		log::info!("{signal_name} received: shutting down");
+		if let Some(path) = &control_path {
+			control::remove(path);
 		}
-		loop {
-			if renewals.is_empty() {
-				log::error!("No certificate found.");
-				return;
+		tokio::select! {
+			_ = self.shutdown() => {}
+			Some(_) = recv_signal(&mut sigint) => log::warn!("SIGINT received: exiting immediately"),
+			Some(_) = recv_signal(&mut sigterm) => log::warn!("SIGTERM received: exiting immediately"),
+		}
+	}
+
+	/// Stop scheduling renewals and wait for the in-flight ones to finish, so the pending orders
+	/// are not left behind. Renewals which are still running after the shutdown timeout are
+	/// interrupted.
+	async fn shutdown(&mut self) {
+		if let Some(task) = self.version_advisory_task.take() {
+			task.abort();
+		}
+		for task in self.watched_tasks.drain(..) {
+			task.abort();
+		}
+		let ids: Vec<String> = self.renewals.keys().map(|e| e.to_owned()).collect();
+		let handles: Vec<JoinHandle<()>> =
+			ids.iter().filter_map(|id| self.stop_renewal(id)).collect();
+		let deadline = tokio::time::Instant::now() + self.shutdown_timeout;
+		let mut nb_interrupted = 0;
+		for mut handle in handles {
+			if tokio::time::timeout_at(deadline, &mut handle)
+				.await
+				.is_err()
+			{
+				handle.abort();
+				nb_interrupted += 1;
 			}
-			if let Some((crt, acc, ept)) = renewals.next().await {
-				renewals.push(renew_certificate(crt, acc, ept));
+		}
+		if nb_interrupted != 0 {
+			log::warn!(
+				"{nb_interrupted} renewal(s) interrupted after the {} seconds shutdown timeout",
+				self.shutdown_timeout.as_secs()
+			);
+		}
+		self.flush_ca_rate_limits().await;
+	}
+
+	/// Start the periodic version advisory check, replacing the previous one.
+	fn start_version_advisory(&mut self) {
+		if let Some(task) = self.version_advisory_task.take() {
+			task.abort();
+		}
+		if let Some(advisory) = &self.version_advisory {
+			self.version_advisory_task = Some(tokio::spawn(advisory.clone().run()));
+		}
+	}
+
+	/// Start monitoring the watched certificates, replacing the previous tasks. The notified
+	/// expirations of the certificates which are still watched are kept.
+	fn start_watched(&mut self) {
+		for task in self.watched_tasks.drain(..) {
+			task.abort();
+		}
+		self.watched_alerted
+			.lock()
+			.unwrap_or_else(|e| e.into_inner())
+			.retain(|name| self.watched.iter().any(|w| &w.name == name));
+		for w in self.watched.iter() {
+			let task = w.clone().run(self.watched_alerted.clone());
+			self.watched_tasks.push(tokio::spawn(task));
+		}
+	}
+
+	/// Attach the labels to the certificates' events and send the webhooks on those events,
+	/// replacing the previous ones.
+	fn start_webhooks(&mut self) {
+		if let Some(task) = self.webhooks_task.take() {
+			task.abort();
+		}
+		let labels = self
+			.certificates
+			.iter()
+			.map(|(id, crt)| (id.to_owned(), crt.labels.clone()))
+			.chain(
+				self.watched
+					.iter()
+					.map(|w| (w.name.to_owned(), w.labels.clone())),
+			)
+			.collect();
+		events::set_labels(labels);
+		if !self.webhooks.is_empty() {
+			self.webhooks_task = Some(webhooks::start(self.webhooks.clone()));
+		}
+	}
+
+	/// Create the control socket, if any, so it can be used once the daemon is running.
+	pub fn bind_control_socket(&mut self) {
+		if let Some((path, mode)) = &self.control_socket {
+			match control::bind(path, *mode) {
+				Ok(listener) => {
+					log::debug!("listening on the control socket: {path}");
+					self.control_listener = Some(listener);
+				}
+				Err(e) => log::error!("unable to create the control socket: {e}"),
 			}
 		}
 	}
-}
 
-async fn renew_certificate(
-	certificate: &mut Certificate,
-	account_s: AccountSync,
-	endpoint_s: EndpointSync,
-) -> (&mut Certificate, AccountSync, EndpointSync) {
-	let backoff = [60, 10 * 60, 100 * 60, 24 * 60 * 60];
-	let mut scheduling_retries = 0;
-	loop {
-		match certificate.schedule_renewal().await {
-			Ok(duration) => {
-				sleep(duration).await;
-				break;
+	/// Serve the web UI on the listening socket, using its certificate if any.
+	async fn listen_web_ui(
+		&self,
+		listener: TcpListener,
+	) -> Result<mpsc::Receiver<ControlRequest>, Error> {
+		let ui = self
+			.web_ui
+			.clone()
+			.ok_or_else(|| Error::from("the web UI is not configured"))?;
+		let tls = match &ui.certificate {
+			Some(id) => {
+				let crt = self
+					.certificates
+					.get(id)
+					.ok_or_else(|| Error::from(format!("{id}: web UI certificate not found")))?;
+				Some(web_ui::Tls::new(crt).await?)
 			}
+			None => None,
+		};
+		web_ui::listen(listener, ui, tls)
+	}
+
+	/// Create the web UI's listening socket, if any, so a privileged port may be used.
+	pub fn bind_web_ui(&mut self) {
+		if let Some(ui) = &self.web_ui {
+			match web_ui::bind(&ui.listen) {
+				Ok(listener) => {
+					log::info!("serving the web UI on {}", ui.get_url());
+					self.web_ui_listener = Some(listener);
+				}
+				Err(e) => log::error!("unable to serve the web UI: {e}"),
+			}
+		}
+	}
+
+	/// Switch to the given user and group. The state directory, as well as the accounts and
+	/// certificates directories, are handed over to the new user since the daemon has to keep
+	/// writing into them, and the configuration files have to remain readable so it can be
+	/// reloaded. If any file or directory owner has been configured, the ability to change the
+	/// owner of files is retained.
+	pub fn drop_privileges(&self, user: Option<&str>, group: Option<&str>) -> Result<(), Error> {
+		if user.is_none() && group.is_none() {
+			return Ok(());
+		}
+		if let Some(name) = self.hook_owners.first() {
+			let msg = format!(
+				"hook \"{name}\": the user and group of the hooks cannot be changed when the daemon runs as another user or group"
+			);
+			return Err(Error::new(ErrorKind::Config, &msg));
+		}
+		let (uid, gid) = privileges::get_ids(user, group)?;
+		let mut directories: Vec<&Path> = self
+			.certificates
+			.values()
+			.flat_map(|c| c.file_manager.directories())
+			.map(Path::new)
+			.collect();
+		directories.push(Path::new(&self.state_dir));
+		directories.sort_unstable();
+		directories.dedup();
+		privileges::hand_over(&directories, &self.config_files, uid, gid)?;
+		privileges::drop_privileges(user, group, self.has_file_owners)
+	}
+
+	/// Handle a request received on the control socket. Operations which may take some time are
+	/// run in a dedicated task so the main loop is never blocked.
+	fn handle_control_request(&mut self, request: Request, reply: oneshot::Sender<Response>) {
+		let names = match &request {
+			Request::Hello => &[],
+			Request::Renew { certificates }
+			| Request::Deploy { certificates }
+			| Request::Status { certificates }
+			| Request::Hooks { certificates }
+			| Request::Watch { certificates } => certificates.as_slice(),
+		};
+		let names: Vec<&str> = names.iter().map(|e| e.as_str()).collect();
+		let ids = match self.select_certificates(&names) {
+			Ok(ids) => ids,
 			Err(e) => {
-				certificate.warn(&e.message);
-				sleep(Duration::from_secs(
-					backoff[scheduling_retries.min(backoff.len() - 1)],
-				))
-				.await;
-				scheduling_retries += 1;
+				let _ = reply.send(Response::error(&e));
+				return;
+			}
+		};
+		match request {
+			Request::Hello => {
+				let _ = reply.send(Response::hello(control::PROTOCOL_VERSION));
+			}
+			Request::Renew { .. } => {
+				let mut lines = vec![];
+				for id in ids {
+					match self.renewals.get(&id) {
+						Some(task) => {
+							task.force.notify_one();
+							self.certificates[&id].info("renewal requested on the control socket");
+							lines.push(format!("{id}: renewal requested"));
+						}
+						None => lines.push(format!("{id}: not scheduled for renewal")),
+					}
+				}
+				let _ = reply.send(Response::new(true, &lines.join("\n")));
+			}
+			Request::Deploy { .. } => {
+				let certificates: Vec<(Arc<Certificate>, AccountSync)> = ids
+					.iter()
+					.map(|id| {
+						let crt = self.certificates[id].as_issued();
+						let account_s = self.accounts[&crt.account_name].clone();
+						(crt, account_s)
+					})
+					.collect();
+				tokio::spawn(async move {
+					let mut success = true;
+					let mut lines = vec![];
+					for (crt, account_s) in certificates {
+						let id = crt.get_id();
+						let account = account_s.read().await.get_identity(&crt.endpoint_name);
+						match crt
+							.call_post_operation_hooks(&account, "deploy", None)
+							.await
+						{
+							Ok(_) => match crt.check_deployment().await {
+								Ok(_) => lines.push(format!("{id}: post-operation hooks called")),
+								Err(e) => {
+									lines.push(format!("{id}: {e}"));
+									success = false;
+								}
+							},
+							Err(e) => {
+								let e = e.prefix("post-operation hook error");
+								crt.warn_error("post-operation-hook", &e);
+								lines.push(format!("{id}: {e}"));
+								success = false;
+							}
+						}
+					}
+					let _ = reply.send(Response::new(success, &lines.join("\n")));
+				});
+			}
+			Request::Watch { .. } => {
+				let mut resp = Response::new(
+					true,
+					&format!("watching the events of {} certificate(s)", ids.len()),
+				);
+				resp.data = Some(json!(ids));
+				let _ = reply.send(resp);
+			}
+			Request::Hooks { .. } => {
+				let certificates: Vec<Arc<Certificate>> =
+					ids.iter().map(|id| self.certificates[id].clone()).collect();
+				tokio::spawn(async move {
+					let mut count = 0;
+					let mut history = vec![];
+					for crt in certificates {
+						let executions = match crt.hook_recorder.get_history().await {
+							Ok(h) => h,
+							Err(e) => {
+								let _ = reply.send(Response::error(&e));
+								return;
+							}
+						};
+						count += executions.len();
+						history.push(json!({
+							"id": crt.get_id(),
+							"hooks": executions.iter().map(|e| json!({
+								"date": format_timestamp(e.timestamp),
+								"hook": e.hook,
+								"cmd": e.cmd,
+								"duration_ms": e.duration_ms,
+								"exit_code": e.exit_code,
+								"success": e.success,
+								"error": e.error,
+							})).collect::<Vec<_>>(),
+						}));
+					}
+					let mut resp = Response::new(true, &format!("{count} hook execution(s)"));
+					resp.data = Some(json!({ "certificates": history }));
+					let _ = reply.send(resp);
+				});
+			}
+			Request::Status { .. } => {
+				let certificates: Vec<(Arc<Certificate>, EndpointSync)> = ids
+					.iter()
+					.map(|id| {
+						let crt = self.certificates[id].clone();
+						let ept = self.endpoints[&crt.endpoint_name].clone();
+						(crt, ept)
+					})
+					.collect();
+				let watched: Vec<WatchedCertificate> =
+					self.select_watched(&names).into_iter().cloned().collect();
+				let mut ca_rate_limits: Vec<(String, CaRateLimits)> = certificates
+					.iter()
+					.filter_map(|(crt, _)| {
+						self.ca_rate_limits
+							.get(&crt.endpoint_name)
+							.map(|l| (crt.endpoint_name.to_owned(), l.clone()))
+					})
+					.collect();
+				ca_rate_limits.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+				ca_rate_limits.dedup_by(|a, b| a.0 == b.0);
+				let renewals_in_progress =
+					self.max_parallel_renewals - self.renewal_permits.available_permits();
+				tokio::spawn(async move {
+					let mut status = vec![];
+					for (crt, ept) in certificates {
+						let next_renewal = match crt.schedule_renewal().await {
+							Ok(d) => {
+								// The endpoint is locked while one of its certificates is renewed,
+								// in which case its limits are ignored instead of waiting.
+								let wait = match ept.try_read() {
+									Some(e) => endpoint_wait(&e, &crt.account_name).await,
+									None => None,
+								};
+								let d = match wait {
+									Some((w, _)) if w > d => w,
+									_ => d,
+								};
+								Some(format_timestamp(now() + d.as_secs()))
+							}
+							Err(_) => None,
+						};
+						let not_after = if certificate_files_exists(&crt.file_manager) {
+							get_certificate(&crt.file_manager)
+								.await
+								.and_then(|c| c.not_after())
+								.ok()
+						} else {
+							None
+						};
+						let last_renewal = crt.get_renewal_status().ok().flatten();
+						status.push(json!({
+							"id": crt.get_id(),
+							"name": crt.crt_name,
+							"endpoint": crt.endpoint_name,
+							"labels": crt.labels,
+							"expires": not_after.map(format_timestamp),
+							"expires_timestamp": not_after,
+							"expires_in_days": not_after.map(|t| (t as i64 - now() as i64).div_euclid(86_400)),
+							"next_renewal": next_renewal,
+							"last_renewal": last_renewal.map(|r| json!({
+								"date": format_timestamp(r.timestamp),
+								"success": r.success,
+								"status": r.status,
+								"error_source": r.error_source,
+							})),
+						}));
+					}
+					let mut watched_status = vec![];
+					for w in watched {
+						let not_after = w.get_certificate().await.and_then(|c| c.not_after()).ok();
+						watched_status.push(json!({
+							"name": w.name,
+							"source": w.source_name(),
+							"labels": w.labels,
+							"expires": not_after.map(format_timestamp),
+							"expires_timestamp": not_after,
+							"expires_in_days": not_after.map(|t| (t as i64 - now() as i64).div_euclid(86_400)),
+						}));
+					}
+					let mut endpoints = vec![];
+					for (name, limits) in ca_rate_limits {
+						match limits.load() {
+							Ok(Some(status)) => {
+								endpoints.push(json!({"name": name, "rate_limits": status}));
+							}
+							Ok(None) => {}
+							Err(e) => log::warn!("endpoint \"{name}\": {e}"),
+						}
+					}
+					let mut msg = format!(
+						"{} certificate(s), {renewals_in_progress} renewal(s) in progress",
+						status.len()
+					);
+					if !watched_status.is_empty() {
+						msg += &format!(", {} watched certificate(s)", watched_status.len());
+					}
+					if let Some(advisory) = advisory::get_latest() {
+						msg += &format!("\n{}", advisory.summary());
+					}
+					let mut resp = Response::new(true, &msg);
+					resp.data = Some(json!({
+						"pid": std::process::id(),
+						"version": crate::APP_VERSION,
+						"renewals_in_progress": renewals_in_progress,
+						"read_only_storage": is_read_only(),
+						"version_advisory": advisory::get_latest(),
+						"certificates": status,
+						"watched_certificates": watched_status,
+						"endpoints": endpoints,
+					}));
+					let _ = reply.send(resp);
+				});
 			}
 		}
 	}
-	let (status, is_success) =
-		match request_certificate(certificate, account_s.clone(), endpoint_s.clone()).await {
-			Ok(_) => ("success".to_string(), true),
+
+	/// Write the rate-limit information which has not been written yet.
+	pub async fn flush_ca_rate_limits(&self) {
+		for limits in self.ca_rate_limits.values() {
+			limits.flush().await;
+		}
+	}
+
+	fn start_renewal(&mut self, id: &str, previous: Option<JoinHandle<()>>) {
+		let crt = match self.certificates.get(id) {
+			Some(c) => c.clone(),
+			None => return,
+		};
+		log::trace!("Adding certificate: {id}");
+		let targets = match self.get_renewal_targets(&crt) {
+			Ok(v) => v,
 			Err(e) => {
-				let e = e.prefix("unable to renew the certificate");
-				certificate.warn(&e.message);
-				(e.message, false)
+				crt.warn(&e.message);
+				return;
+			}
+		};
+		let (stop, stop_rx) = watch::channel(false);
+		let force = Arc::new(Notify::new());
+		let permits = self.renewal_permits.clone();
+		let handle = tokio::spawn(renew_certificate(
+			crt,
+			targets,
+			permits,
+			stop_rx,
+			force.clone(),
+			previous,
+		));
+		self.renewals.insert(
+			id.to_string(),
+			RenewalTask {
+				stop,
+				force,
+				handle,
+			},
+		);
+	}
+
+	/// Ask the renewal task of a certificate to stop. An in-flight renewal is not interrupted.
+	fn stop_renewal(&mut self, id: &str) -> Option<JoinHandle<()>> {
+		self.renewals.remove(id).map(|task| {
+			let _ = task.stop.send(true);
+			task.handle
+		})
+	}
+
+	async fn reload(&mut self) -> Result<(), Error> {
+		let root_certs: Vec<&str> = self.root_certs.iter().map(|e| e.as_str()).collect();
+		let new_loop = MainEventLoop::load(&self.config_file, &root_certs, false).await?;
+		let errors = new_loop.check_templates().await;
+		if !errors.is_empty() {
+			let errors: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+			return Err(errors.join(", ").into());
+		}
+		let MainEventLoop {
+			certificates: new_certificates,
+			accounts: new_accounts,
+			endpoints: new_endpoints,
+			max_parallel_renewals,
+			shutdown_timeout,
+			control_socket,
+			version_advisory,
+			watched,
+			web_ui,
+			webhooks,
+			templates,
+			..
+		} = new_loop;
+		// Everything which may fail is done before the running state is modified, so a failed
+		// reload leaves the daemon as it was.
+		let mut new_endpoints = unwrap_all(new_endpoints, "endpoint")?;
+		let new_accounts = unwrap_all(new_accounts, "account")?;
+		if let Some(templates) = templates {
+			templates.apply();
+		}
+		self.version_advisory = version_advisory;
+		self.start_version_advisory();
+		self.watched = watched;
+		self.start_watched();
+		self.shutdown_timeout = shutdown_timeout;
+		if control_socket != self.control_socket {
+			log::warn!("the control socket cannot be changed without restarting the daemon");
+		}
+		if web_ui != self.web_ui {
+			log::warn!("the web UI cannot be changed without restarting the daemon");
+		}
+
+		// The running renewals share the same semaphore, hence its permits are adjusted.
+		if max_parallel_renewals > self.max_parallel_renewals {
+			self.renewal_permits
+				.add_permits(max_parallel_renewals - self.max_parallel_renewals);
+		} else if max_parallel_renewals < self.max_parallel_renewals {
+			let nb = (self.max_parallel_renewals - max_parallel_renewals) as u32;
+			let permits = self.renewal_permits.clone();
+			tokio::spawn(async move {
+				if let Ok(p) = permits.acquire_many_owned(nb).await {
+					p.forget();
+				}
+			});
+		}
+		self.max_parallel_renewals = max_parallel_renewals;
+
+		// Accounts and endpoints are updated in place so the running renewals use the new values.
+		// The previous ones are kept until the removed certificates have abandoned their orders.
+		let old_accounts = self.accounts.clone();
+		let old_endpoints = self.endpoints.clone();
+		// The in-memory state of the endpoints, such as the nonce and the rate limits, is kept.
+		self.endpoints.retain(|k, _| new_endpoints.contains_key(k));
+		self.ca_rate_limits.clear();
+		for (name, mut ept) in new_endpoints.drain() {
+			match self.endpoints.get(&name) {
+				Some(old) => {
+					let mut old = old.write().await;
+					ept.take_state(&mut old);
+					self.ca_rate_limits.insert(name, ept.ca_rate_limits.clone());
+					*old = ept;
+				}
+				None => {
+					self.ca_rate_limits
+						.insert(name.clone(), ept.ca_rate_limits.clone());
+					self.endpoints.insert(name, Arc::new(RwLock::new(ept)));
+				}
+			}
+		}
+		self.accounts.retain(|k, _| new_accounts.contains_key(k));
+		for (name, acc) in new_accounts {
+			match self.accounts.get(&name) {
+				Some(old) => *old.write().await = acc,
+				None => {
+					self.accounts.insert(name, Arc::new(RwLock::new(acc)));
+				}
+			}
+		}
+
+		// Certificates are diffed so only the added, removed or changed ones are rescheduled.
+		let old_certificates = std::mem::replace(&mut self.certificates, new_certificates);
+		self.set_solver_rate_limits();
+		let mut ids: Vec<String> = old_certificates.keys().map(|e| e.to_owned()).collect();
+		ids.extend(self.certificates.keys().map(|e| e.to_owned()));
+		ids.sort();
+		ids.dedup();
+		let (mut nb_added, mut nb_removed, mut nb_changed) = (0, 0, 0);
+		for id in ids {
+			match (old_certificates.get(&id), self.certificates.get(&id)) {
+				(Some(old), Some(new)) if old == new => {}
+				(Some(_), Some(_)) => {
+					log::debug!("{id}: certificate changed");
+					let previous = self.stop_renewal(&id);
+					self.start_renewal(&id, previous);
+					nb_changed += 1;
+				}
+				(Some(old), None) => {
+					log::debug!("{id}: certificate removed");
+					let previous = self.stop_renewal(&id);
+					abandon_order(old.clone(), &old_accounts, &old_endpoints, previous);
+					nb_removed += 1;
+				}
+				(None, Some(_)) => {
+					log::debug!("{id}: certificate added");
+					self.start_renewal(&id, None);
+					nb_added += 1;
+				}
+				(None, None) => {}
+			}
+		}
+		// The webhooks are restarted since they use the labels of the certificates.
+		self.webhooks = webhooks;
+		self.start_webhooks();
+		log::info!(
+			"configuration reloaded: {nb_added} certificate(s) added, {nb_removed} removed and {nb_changed} changed"
+		);
+		if self.certificates.is_empty() && self.watched.is_empty() {
+			log::warn!("No certificate found.");
+		}
+		Ok(())
+	}
+
+	/// Return the id of the certificates matching the given names, which can be either the
+	/// certificate's id, its name or a `key=value` label. If no name is given, every certificate
+	/// is returned. Names only matching watched certificates are not an error.
+	fn select_certificates(&self, names: &[&str]) -> Result<Vec<String>, Error> {
+		let mut ids: Vec<String> = if names.is_empty() {
+			self.certificates.keys().map(|k| k.to_owned()).collect()
+		} else {
+			let mut ids = vec![];
+			for name in names {
+				let label = name.split_once('=');
+				let mut found = false;
+				for (id, crt) in self.certificates.iter() {
+					let is_selected = match label {
+						Some((k, v)) => crt.labels.get(k).map(|l| l == v).unwrap_or(false),
+						None => id == name || crt.crt_name == *name,
+					};
+					if is_selected {
+						ids.push(id.to_owned());
+						found = true;
+					}
+				}
+				if !found && self.select_watched(&[name]).is_empty() {
+					return Err(format!("{name}: certificate not found").into());
+				}
+			}
+			ids
+		};
+		ids.sort();
+		ids.dedup();
+		Ok(ids)
+	}
+
+	/// Return the watched certificates matching the given names or `key=value` labels. If no
+	/// name is given, every watched certificate is returned.
+	fn select_watched(&self, names: &[&str]) -> Vec<&WatchedCertificate> {
+		self.watched
+			.iter()
+			.filter(|w| {
+				names.is_empty()
+					|| names.iter().any(|name| match name.split_once('=') {
+						Some((k, v)) => w.labels.get(k).map(|l| l == v).unwrap_or(false),
+						None => w.name == *name,
+					})
+			})
+			.collect()
+	}
+
+	fn get_certificate_sync(
+		&self,
+		crt: &Certificate,
+	) -> Result<(AccountSync, EndpointSync), Error> {
+		let acc = self
+			.accounts
+			.get(&crt.account_name)
+			.ok_or_else(|| Error::from(format!("{}: account not found", crt.account_name)))?;
+		Ok((acc.clone(), self.get_endpoint_sync(&crt.endpoint_name)?))
+	}
+
+	/// Return the certificate as it is requested from its main endpoint, followed by its fallback
+	/// endpoints in priority order.
+	fn get_renewal_targets(&self, crt: &Arc<Certificate>) -> Result<Vec<RenewalTarget>, Error> {
+		let fallbacks = crt
+			.fallback_endpoints
+			.iter()
+			.map(|fallback| Arc::new(crt.with_fallback(fallback)));
+		std::iter::once(crt.clone())
+			.chain(fallbacks)
+			.map(|certificate| {
+				let (account_s, endpoint_s) = self.get_certificate_sync(&certificate)?;
+				Ok(RenewalTarget {
+					certificate,
+					account_s,
+					endpoint_s,
+				})
+			})
+			.collect()
+	}
+
+	fn get_endpoint_sync(&self, name: &str) -> Result<EndpointSync, Error> {
+		self.endpoints
+			.get(name)
+			.cloned()
+			.ok_or_else(|| Error::from(format!("{name}: endpoint not found")))
+	}
+
+	/// Check the templates of every hook against sample data.
+	async fn check_templates(&self) -> Vec<Error> {
+		let mut errors = vec![];
+		let mut ids: Vec<&String> = self.certificates.keys().collect();
+		ids.sort();
+		for id in ids {
+			if let Err(e) = self.certificates[id].check_hooks().await {
+				errors.push(e.prefix(&format!("certificate \"{id}\"")));
+			}
+		}
+		let mut names: Vec<&String> = self.accounts.keys().collect();
+		names.sort();
+		for name in names {
+			let account = self.accounts[name].read().await;
+			if let Err(e) = storage::check_hooks(&account.file_manager) {
+				errors.push(e.prefix(&format!("account \"{name}\"")));
+			}
+		}
+		if let Some(advisory) = &self.version_advisory {
+			if let Err(e) = advisory.check_hooks() {
+				errors.push(e.prefix("version advisory"));
+			}
+		}
+		errors
+	}
+
+	/// Check the templates and the commands of every hook, as well as the directories and the
+	/// configuration files, then display a summary of the configuration. The access rights are
+	/// checked for the user and group the daemon would switch to, if any.
+	pub async fn check(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+		let mut errors = self.check_templates().await;
+		let mut hooks: Vec<&Arc<Hook>> = vec![];
+		let mut directories = vec![self.state_dir.as_str()];
+		for crt in self.certificates.values() {
+			hooks.extend(crt.hooks.iter().chain(crt.file_manager.hooks.iter()));
+			directories.extend(crt.file_manager.directories());
+		}
+		let accounts: Vec<_> = future::join_all(self.accounts.values().map(|a| a.read())).await;
+		for account in accounts.iter() {
+			hooks.extend(account.file_manager.hooks.iter());
+			directories.extend(account.file_manager.directories());
+		}
+		if let Some(advisory) = &self.version_advisory {
+			hooks.extend(advisory.hooks.iter());
+		}
+		hooks.sort_by(|a, b| a.name.cmp(&b.name));
+		hooks.dedup_by(|a, b| a.name == b.name);
+		for hook in hooks {
+			if let Err(e) = hooks::check_command(hook, uid, gid) {
+				errors.push(e);
+			}
+			let is_dropping = uid.is_some() || gid.is_some();
+			if is_dropping && (hook.uid.is_some() || hook.gid.is_some()) {
+				let msg = format!(
+					"hook \"{}\": the user and group of the hooks cannot be changed when the daemon runs as another user or group",
+					hook.name
+				);
+				errors.push(msg.into());
+			}
+		}
+		directories.sort_unstable();
+		directories.dedup();
+		for dir in directories {
+			if let Err(e) = storage::check_directory(dir, uid, gid) {
+				errors.push(e);
+			}
+		}
+		for file in self.config_files.iter() {
+			if let Err(e) = privileges::check_readable(file, uid, gid) {
+				errors.push(e);
+			}
+		}
+		if !errors.is_empty() {
+			for e in errors.iter() {
+				eprintln!("{e}");
+			}
+			let msg = format!("{} configuration error(s)", errors.len());
+			return Err(msg.into());
+		}
+		println!(
+			"configuration is valid: {} certificate(s), {} account(s), {} endpoint(s)",
+			self.certificates.len(),
+			self.accounts.len(),
+			self.endpoints.len()
+		);
+		Ok(())
+	}
+
+	pub async fn print_status(&self, names: &[&str], as_json: bool) -> Result<(), Error> {
+		let mut endpoint_names = vec![];
+		let mut endpoint_accounts = vec![];
+		let mut directories = vec![];
+		let mut certificates = vec![];
+		let mut rows = vec![];
+		for id in self.select_certificates(names)? {
+			let crt = &self.certificates[&id];
+			directories.extend(crt.file_manager.directories());
+			let (sans, not_after, stored_key_type, error) =
+				if certificate_files_exists(&crt.file_manager) {
+					match get_certificate(&crt.file_manager).await {
+						Ok(c) => {
+							let mut sans: Vec<String> = c.subject_alt_names().into_iter().collect();
+							sans.sort();
+							// The certificate's public key is the one of the stored key pair.
+							let key_type = c.key_type().ok().map(|k| k.to_string());
+							(sans, Some(c.not_after()?), key_type, None)
+						}
+						Err(e) => (
+							vec![],
+							None,
+							None,
+							Some(format!("unreadable certificate ({e})")),
+						),
+					}
+				} else {
+					(vec![], None, None, Some("no certificate".to_string()))
+				};
+			let days = not_after.map(|ts| ts.saturating_sub(now()) / 86400);
+			let last_renewal = crt.get_renewal_status()?;
+			if as_json {
+				certificates.push(json!({
+					"id": id,
+					"name": crt.crt_name,
+					"key_type": crt.key_type.to_string(),
+					"stored_key_type": stored_key_type,
+					"endpoint": crt.endpoint_name,
+					"account": crt.account_name,
+					"labels": crt.labels,
+					"identifiers": crt.identifiers.iter().map(|i| &i.value).collect::<Vec<_>>(),
+					"subject_alt_names": sans,
+					"not_after": not_after.map(format_timestamp),
+					"days_remaining": days,
+					"error": error,
+					"last_renewal": last_renewal.map(|r| json!({
+						"date": format_timestamp(r.timestamp),
+						"success": r.success,
+						"status": r.status,
+						"error_source": r.error_source,
+					})),
+				}));
+			} else {
+				let expiring_days = crt.expiring_margin.as_secs() / 86400;
+				let days_style = match days {
+					Some(d) if d <= expiring_days => Style::Red,
+					Some(_) if matches!(crt.schedule_renewal().await, Ok(d) if d.is_zero()) => {
+						Style::Yellow
+					}
+					Some(_) => Style::Green,
+					None => Style::Red,
+				};
+				rows.push([
+					id.to_owned().into(),
+					if sans.is_empty() {
+						crt.identifier_list()
+					} else {
+						sans.join(",")
+					}
+					.into(),
+					stored_key_type.unwrap_or_else(|| "-".to_string()).into(),
+					match (not_after, &error) {
+						(Some(ts), _) => format_timestamp(ts).into(),
+						(None, Some(e)) => Cell::new(e, Style::Red),
+						(None, None) => "-".to_string().into(),
+					},
+					match days {
+						Some(d) => Cell::new(&d.to_string(), days_style),
+						None => "-".to_string().into(),
+					},
+					match last_renewal {
+						Some(r) if r.success => Cell::new(
+							&format!("success ({})", format_timestamp(r.timestamp)),
+							Style::Green,
+						),
+						Some(r) => Cell::new(
+							&format!("failure ({})", format_timestamp(r.timestamp)),
+							Style::Red,
+						),
+						None => "-".to_string().into(),
+					},
+				]);
+			}
+			if !endpoint_names.contains(&crt.endpoint_name) {
+				endpoint_names.push(crt.endpoint_name.clone());
+			}
+			let endpoint_account = (&crt.endpoint_name, &crt.account_name);
+			if !endpoint_accounts.contains(&endpoint_account) {
+				endpoint_accounts.push(endpoint_account);
+			}
+		}
+		endpoint_accounts.sort_unstable();
+		let mut watched = vec![];
+		for w in self.select_watched(names) {
+			let (sans, not_after, error) = match w.get_certificate().await {
+				Ok(c) => {
+					let mut sans: Vec<String> = c.subject_alt_names().into_iter().collect();
+					sans.sort();
+					(sans, Some(c.not_after()?), None)
+				}
+				Err(e) => (vec![], None, Some(format!("unreadable certificate ({e})"))),
+			};
+			let days = not_after.map(|ts| ts.saturating_sub(now()) / 86400);
+			if as_json {
+				watched.push(json!({
+					"name": w.name,
+					"source": w.source_name(),
+					"labels": w.labels,
+					"subject_alt_names": sans,
+					"not_after": not_after.map(format_timestamp),
+					"days_remaining": days,
+					"error": error,
+				}));
+			} else {
+				let days_style = match days {
+					Some(d) if d <= w.expiration_margin.as_secs() / 86400 => Style::Red,
+					Some(_) => Style::Green,
+					None => Style::Red,
+				};
+				rows.push([
+					w.name.to_owned().into(),
+					if sans.is_empty() {
+						w.source_name()
+					} else {
+						sans.join(",")
+					}
+					.into(),
+					"-".to_string().into(),
+					match (not_after, &error) {
+						(Some(ts), _) => format_timestamp(ts).into(),
+						(None, Some(e)) => Cell::new(e, Style::Red),
+						(None, None) => "-".to_string().into(),
+					},
+					match days {
+						Some(d) => Cell::new(&d.to_string(), days_style),
+						None => "-".to_string().into(),
+					},
+					"watch only".to_string().into(),
+				]);
+			}
+		}
+		let mut endpoints = vec![];
+		let mut endpoint_lines = vec![];
+		for name in endpoint_names.iter() {
+			let endpoint = self.endpoints[name].read().await;
+			match endpoint.ca_rate_limits.load() {
+				Ok(Some(status)) if as_json => {
+					endpoints.push(json!({"name": name, "rate_limits": status}));
+				}
+				Ok(Some(status)) => endpoint_lines.push(format!("endpoint \"{name}\": {status}")),
+				Ok(None) => {}
+				Err(e) => endpoint_lines.push(format!(
+					"endpoint \"{name}\": unreadable rate-limit state ({e})"
+				)),
+			}
+		}
+		let mut tos_changes = vec![];
+		for (endpoint_name, account_name) in endpoint_accounts {
+			let endpoint = self.endpoints[endpoint_name].read().await;
+			match endpoint.tos_changes.load(account_name) {
+				Ok(Some(change)) if as_json => tos_changes.push(json!({
+					"endpoint": endpoint_name,
+					"account": account_name,
+					"url": change.url,
+					"instance": change.instance,
+					"detected": format_timestamp(change.detected),
+				})),
+				Ok(Some(change)) => endpoint_lines.push(paint(
+					&format!(
+						"endpoint \"{endpoint_name}\": account \"{account_name}\": {change}, run `acmed account agree-tos {endpoint_name}` to agree to them"
+					),
+					Style::Yellow,
+				)),
+				Ok(None) => {}
+				Err(e) => endpoint_lines.push(format!(
+					"endpoint \"{endpoint_name}\": account \"{account_name}\": unreadable terms of service state ({e})"
+				)),
 			}
+		}
+		directories.sort_unstable();
+		directories.dedup();
+		let read_only = !check_writable(&directories).await;
+		let newer_version = match &self.version_advisory {
+			Some(va) => advisory::fetch(&va.url).await.unwrap_or_else(|e| {
+				log::warn!("version advisory: {e}");
+				None
+			}),
+			None => None,
 		};
+		if as_json {
+			let status = json!({
+				"certificates": certificates,
+				"watched_certificates": watched,
+				"endpoints": endpoints,
+				"terms_of_service_changes": tos_changes,
+				"read_only_storage": read_only,
+				"version_advisory": newer_version,
+			});
+			println!("{}", serde_json::to_string_pretty(&status)?);
+			return Ok(());
+		}
+		print_table(
+			[
+				"CERTIFICATE",
+				"IDENTIFIERS",
+				"KEY",
+				"NOT AFTER",
+				"DAYS",
+				"LAST RENEWAL",
+			],
+			&rows,
+		);
+		for line in endpoint_lines.iter() {
+			println!("{line}");
+		}
+		if read_only {
+			println!(
+				"{}",
+				paint(
+					"storage: read-only file system, no certificate can be renewed",
+					Style::Red
+				)
+			);
+		}
+		if let Some(advisory) = newer_version {
+			println!(
+				"{}",
+				paint(&format!("version: {}", advisory.summary()), Style::Yellow)
+			);
+		}
+		Ok(())
+	}
+
+	pub async fn print_thumbprints(&self, names: &[&str]) -> Result<(), Error> {
+		let mut account_names: Vec<&String> = self.accounts.keys().collect();
+		account_names.sort();
+		for name in names.iter() {
+			if !self.accounts.contains_key(*name) {
+				return Err(format!("{name}: account not found").into());
+			}
+		}
+		for name in account_names {
+			if !names.is_empty() && !names.contains(&name.as_str()) {
+				continue;
+			}
+			let thumbprint = self.accounts[name]
+				.read()
+				.await
+				.current_key
+				.key
+				.jwk_thumbprint()?;
+			println!("{name}: {thumbprint}");
+		}
+		Ok(())
+	}
+
+	/// Return the endpoint and the names of the accounts used with it, restricted to the given
+	/// ones unless empty.
+	async fn select_endpoint_accounts(
+		&self,
+		endpoint_name: &str,
+		names: &[&str],
+	) -> Result<(&EndpointSync, Vec<&str>), Error> {
+		let endpoint_s = self
+			.endpoints
+			.get(endpoint_name)
+			.ok_or_else(|| Error::from(format!("{endpoint_name}: endpoint not found")))?;
+		let mut account_names = vec![];
+		for (name, account_s) in self.accounts.iter() {
+			if account_s.read().await.endpoints.contains_key(endpoint_name) {
+				account_names.push(name.as_str());
+			}
+		}
+		account_names.sort_unstable();
+		for name in names.iter() {
+			if !self.accounts.contains_key(*name) {
+				return Err(format!("{name}: account not found").into());
+			}
+			if !account_names.contains(name) {
+				let msg = format!("{name}: account not used with endpoint \"{endpoint_name}\"");
+				return Err(msg.into());
+			}
+		}
+		account_names.retain(|n| names.is_empty() || names.contains(n));
+		Ok((endpoint_s, account_names))
+	}
+
+	/// Register the accounts used with the given endpoint. Unless `assume_yes` is set, a
+	/// confirmation is asked for each account.
+	pub async fn register_accounts(
+		&self,
+		endpoint_name: &str,
+		names: &[&str],
+		assume_yes: bool,
+	) -> Result<(), Error> {
+		let (endpoint_s, account_names) =
+			self.select_endpoint_accounts(endpoint_name, names).await?;
+		let mut endpoint = endpoint_s.write().await;
+		if let Some(tos) = get_terms_of_service(&mut endpoint).await? {
+			println!("Terms of service of endpoint \"{endpoint_name}\": {tos}");
+		}
+		for name in account_names {
+			let mut account = self.accounts[name].write().await;
+			if !account.get_endpoint(endpoint_name)?.account_url.is_empty() {
+				println!("{name}: already registered on endpoint \"{endpoint_name}\"");
+				continue;
+			}
+			if !assume_yes {
+				let question =
+					format!("Register account \"{name}\" on endpoint \"{endpoint_name}\"?");
+				let answer = prompt(&question, Some("no"))?.to_lowercase();
+				if answer != "y" && answer != "yes" {
+					println!("{name}: skipped");
+					continue;
+				}
+			}
+			account.register(&mut endpoint).await?;
+			println!("{name}: registered on endpoint \"{endpoint_name}\"");
+		}
+		Ok(())
+	}
+
+	/// Agree to the current terms of service of the given endpoint with the accounts registered
+	/// on it. Unless `assume_yes` is set, a confirmation is asked for each account.
+	pub async fn agree_to_terms_of_service(
+		&self,
+		endpoint_name: &str,
+		names: &[&str],
+		assume_yes: bool,
+	) -> Result<(), Error> {
+		let (endpoint_s, account_names) =
+			self.select_endpoint_accounts(endpoint_name, names).await?;
+		let mut endpoint = endpoint_s.write().await;
+		match get_terms_of_service(&mut endpoint).await? {
+			Some(tos) => println!("Terms of service of endpoint \"{endpoint_name}\": {tos}"),
+			None => {
+				println!("Endpoint \"{endpoint_name}\" does not advertise any terms of service")
+			}
+		}
+		for name in account_names {
+			let mut account = self.accounts[name].write().await;
+			if account.get_endpoint(endpoint_name)?.account_url.is_empty() {
+				println!("{name}: not registered on endpoint \"{endpoint_name}\"");
+				continue;
+			}
+			if let Some(change) = endpoint.tos_changes.load(name)? {
+				println!("{name}: {change}");
+			}
+			if !assume_yes {
+				let question = format!(
+					"Agree to the terms of service of endpoint \"{endpoint_name}\" with account \"{name}\"?"
+				);
+				let answer = prompt(&question, Some("no"))?.to_lowercase();
+				if answer != "y" && answer != "yes" {
+					println!("{name}: skipped");
+					continue;
+				}
+			}
+			account.agree_to_terms_of_service(&mut endpoint).await?;
+			println!("{name}: terms of service agreed on endpoint \"{endpoint_name}\"");
+		}
+		Ok(())
+	}
+
+	pub async fn print_plan(&self, names: &[&str]) -> Result<(), Error> {
+		for id in self.select_certificates(names)? {
+			let crt = &self.certificates[&id];
+			let ept = &self.endpoints[&crt.endpoint_name];
+			let wait = get_endpoint_wait(ept, &crt.account_name).await;
+			let (action, style) = match (crt.schedule_renewal().await, wait) {
+				(Ok(d), Some((w, reason))) if d <= w => (
+					format!("renewal in {} seconds ({reason})", w.as_secs()),
+					Style::Yellow,
+				),
+				(Ok(d), _) if d.is_zero() => ("renewal now".to_string(), Style::Yellow),
+				(Ok(d), _) => (
+					format!("renewal in {} days", d.as_secs() / 86400),
+					Style::Green,
+				),
+				(Err(e), _) => (format!("unable to schedule the renewal ({e})"), Style::Red),
+			};
+			println!("{id}: {}", paint(&action, style));
+		}
+		Ok(())
+	}
+
+	/// Renew the selected certificates once and return whether or not every renewal succeeded.
+	/// Unless `force` is set, only the certificates that are due for renewal are renewed.
+	pub async fn renew(&mut self, names: &[&str], force: bool) -> Result<bool, Error> {
+		self.set_solver_rate_limits();
+		let ids = self.select_certificates(names)?;
+		let progress = Progress::new("renewing", ids.len());
+		let progress = &progress;
+		let mut renewals = vec![];
+		for id in ids {
+			let crt = &self.certificates[&id];
+			let (acc, ept) = self.get_certificate_sync(crt)?;
+			let permits = self.renewal_permits.clone();
+			let renewal = async move {
+				check_manifest(&crt.file_manager).await?;
+				let endpoints = [(acc.clone(), ept.clone())];
+				deactivate_stale_authorizations(crt, &endpoints, false).await;
+				if !force && !crt.schedule_renewal().await?.is_zero() {
+					crt.info("renewal not due yet");
+					return Ok((true, "not due".to_string().into()));
+				}
+				let permit = permits.acquire().await;
+				if let Some((wait, reason)) = get_endpoint_wait(&ept, &crt.account_name).await {
+					crt.warn(&format!(
+						"{reason}, the renewal is possible in {} seconds",
+						wait.as_secs()
+					));
+					let msg = format!("postponed ({reason})");
+					return Ok((false, Cell::new(&msg, Style::Yellow)));
+				}
+				let res = renew_now(crt, acc, ept).await;
+				drop(permit);
+				match res {
+					Ok(is_new) => {
+						if is_new {
+							check_new_deployment(crt).await;
+						}
+						Ok((true, Cell::new("renewed", Style::Green)))
+					}
+					Err(e) => Ok((false, Cell::new(&format!("failed ({e})"), Style::Red))),
+				}
+			};
+			renewals.push(async move {
+				let res = renewal.await;
+				progress.inc();
+				(id, res)
+			});
+		}
+		let mut all_ok = true;
+		let mut rows = vec![];
+		let mut error = None;
+		for (id, res) in future::join_all(renewals).await {
+			match res {
+				Ok((is_ok, result)) => {
+					all_ok &= is_ok;
+					rows.push([id.into(), result]);
+				}
+				Err(e) => {
+					rows.push([id.into(), Cell::new(&format!("error ({e})"), Style::Red)]);
+					error.get_or_insert(e);
+				}
+			}
+		}
+		progress.finish();
+		self.flush_ca_rate_limits().await;
+		// The summary is only displayed to humans, the logs being enough for the scheduled runs.
+		if terminal::is_interactive() && !rows.is_empty() {
+			print_table(["CERTIFICATE", "RESULT"], &rows);
+		}
+		match error {
+			Some(e) => Err(e),
+			None => Ok(all_ok),
+		}
+	}
+
+	pub async fn revoke(&mut self, name: &str, reason: RevocationReason) -> Result<(), Error> {
+		if name.contains('=') {
+			return Err(format!("{name}: certificates cannot be revoked by label").into());
+		}
+		for id in self.select_certificates(&[name])? {
+			let crt = self.certificates[&id].as_issued();
+			let (acc, ept) = self.get_certificate_sync(&crt)?;
+			with_timeout(crt.timeout, revoke_certificate(&crt, acc, ept, reason)).await?;
+		}
+		Ok(())
+	}
+}
+
+/// Take the values out of their locks, which are not shared yet.
+fn unwrap_all<T>(
+	values: HashMap<String, Arc<RwLock<T>>>,
+	kind: &str,
+) -> Result<HashMap<String, T>, Error> {
+	values
+		.into_iter()
+		.map(|(name, value)| match Arc::try_unwrap(value) {
+			Ok(value) => Ok((name, value.into_inner())),
+			Err(_) => Err(format!("{name}: unable to update the {kind}").into()),
+		})
+		.collect()
+}
+
+/// Load a certificate from the configuration, along with its endpoints and the account to use on
+/// each of them, the main endpoint being the first one.
+fn load_certificate(
+	cnf: &config::Config,
+	crt: &config::Certificate,
+	root_certs: &[&str],
+	email: &Option<Arc<EmailNotifier>>,
+	file_hooks: &HashSet<HookType>,
+	cert_hooks: &HashSet<HookType>,
+) -> Result<(Certificate, Vec<(Endpoint, String)>), Error> {
+	let endpoint = crt.get_endpoint(cnf, root_certs)?;
+	let endpoint_name = endpoint.name.clone();
+	let fallback_endpoints = crt.get_fallback_endpoints(cnf, root_certs)?;
+	let crt_name = crt.get_crt_name()?;
+	let key_type = crt.get_key_type()?;
+	let hooks = crt.get_hooks(cnf)?;
+	let labels = crt.get_labels()?;
+	let fm = FileManager {
+		account_directory: cnf.get_account_dir(),
+		account_name: crt.account.clone(),
+		endpoint_name: endpoint_name.clone(),
+		crt_name: crt_name.clone(),
+		crt_name_format: crt.get_crt_name_format(cnf)?,
+		crt_directory: crt.get_crt_dir(cnf),
+		crt_key_type: key_type.to_string(),
+		cert_file_mode: cnf.get_cert_file_mode(),
+		cert_file_owner: cnf.get_cert_file_user(),
+		cert_file_group: cnf.get_cert_file_group(),
+		pk_file_mode: cnf.get_pk_file_mode(),
+		pk_file_owner: cnf.get_pk_file_user(),
+		pk_file_group: cnf.get_pk_file_group(),
+		pk_encryption: match &crt.pk_encryption {
+			Some(e) => Some(e.to_generic()?),
+			None => None,
+		},
+		pk_format: crt.get_pk_format()?,
+		pk_encoding: crt.get_pk_encoding()?,
+		hooks: hooks
+			.iter()
+			.filter(|h| !h.hook_type.is_disjoint(file_hooks))
+			.map(|e| e.to_owned())
+			.collect(),
+		env: crt.env.clone(),
+		labels: labels.clone(),
+	};
+	let cert = Certificate {
+		account_name: crt.account.clone(),
+		identifiers: crt.get_identifiers(cnf)?,
+		subject_attributes: crt.subject_attributes.to_generic(),
+		key_type,
+		csr_digest: crt.get_csr_digest()?,
+		deploy_check: crt.get_deploy_check()?,
+		kp_reuse: crt.get_kp_reuse(),
+		kubernetes_secret: match &crt.kubernetes_secret {
+			Some(s) => Some(s.to_generic()?),
+			None => None,
+		},
+		ocsp_staple: crt.get_ocsp_staple(),
+		star: match &crt.star {
+			Some(s) => Some(s.to_generic()?),
+			None => None,
+		},
+		caa_check: crt.get_caa_check(),
+		endpoint_name: endpoint_name.clone(),
+		fallback_endpoints: fallback_endpoints.iter().map(|e| e.1.clone()).collect(),
+		failover_attempts: crt.get_failover_attempts()?,
+		hooks: hooks
+			.iter()
+			.filter(|h| !h.hook_type.is_disjoint(cert_hooks))
+			.map(|e| e.to_owned())
+			.collect(),
+		email: email.clone(),
+		crt_name,
+		env: crt.env.to_owned(),
+		labels,
+		random_early_renew: crt.get_random_early_renew(cnf)?,
+		renew_delay: crt.get_renew_delay(cnf)?,
+		renew_adaptive_factor: crt.get_renew_adaptive_factor(cnf)?,
+		renew_at: crt.get_renew_at(cnf)?,
+		renew_jitter: crt.get_renew_jitter(cnf)?,
+		renew_windows: crt.get_renew_windows(cnf)?,
+		renew_window_critical: crt.get_renew_window_critical(cnf)?,
+		challenge_ready_interval: crt.get_challenge_ready_interval()?,
+		challenge_ready_timeout: crt.get_challenge_ready_timeout()?,
+		dns_cleanup_delay: crt.get_dns_cleanup_delay()?,
+		dns_propagation_check: crt.get_dns_propagation_check(),
+		dns_propagation_resolvers: crt.get_dns_propagation_resolvers()?,
+		dns_propagation_timeout: crt.get_dns_propagation_timeout()?,
+		http_self_check: crt.get_http_self_check(),
+		http_self_check_address: crt.get_http_self_check_address()?,
+		stateless_http_01: crt.stateless_http_01.unwrap_or(false),
+		timeout: crt.get_timeout(cnf)?,
+		preferred_chain: crt.get_preferred_chain(cnf)?,
+		profile: crt.get_profile(cnf)?,
+		state_dir: cnf.get_state_dir(),
+		hook_recorder: HookRecorder::new(fm.get_certificate_id())
+			.with_history(&cnf.get_state_dir(), cnf.get_hook_history_size()),
+		expiring_margin: cnf.get_expiring_margin()?,
+		file_manager: fm,
+	};
+	let fallback_endpoints = fallback_endpoints
+		.into_iter()
+		.map(|(endpoint, fallback)| (endpoint, fallback.account_name));
+	let endpoints = std::iter::once((endpoint, crt.account.clone()))
+		.chain(fallback_endpoints)
+		.collect();
+	Ok((cert, endpoints))
+}
+
+/// Check whether the certificate's identifiers are already included in another certificate
+/// requested to the same endpoint, which may trigger the CA's duplicate certificate rate limit.
+/// Certificates sharing the same name, hence only differing by their key type, are not
+/// considered duplicates.
+fn check_duplicate_identifiers(
+	cert: &mut Certificate,
+	mode: DuplicateIdentifiers,
+	seen: &mut HashMap<(String, String), String>,
+) -> Result<(), Error> {
+	let mut identifiers = Vec::with_capacity(cert.identifiers.len());
+	for id in std::mem::take(&mut cert.identifiers) {
+		let key = (cert.endpoint_name.clone(), id.value.clone());
+		match seen.get(&key) {
+			Some(other) if *other != cert.crt_name => match mode {
+				DuplicateIdentifiers::Allow => {}
+				DuplicateIdentifiers::Warn => cert.warn(&format!(
+					"{}: also included in certificate \"{other}\" on the same endpoint",
+					id.value
+				)),
+				DuplicateIdentifiers::Skip => {
+					cert.info(&format!(
+						"{}: already included in certificate \"{other}\" on the same endpoint, skipping",
+						id.value
+					));
+					continue;
+				}
+			},
+			Some(_) => {}
+			None => {
+				seen.insert(key, cert.crt_name.clone());
+			}
+		}
+		identifiers.push(id);
+	}
+	if identifiers.is_empty() {
+		let msg = format!(
+			"{}: every identifier is already included in another certificate",
+			cert.get_id()
+		);
+		return Err(Error::new(ErrorKind::Config, &msg));
+	}
+	cert.identifiers = identifiers;
+	Ok(())
+}
+
+/// Request the certificate and call the post-operation hooks. Return whether or not a new
+/// certificate has been installed, or the error if the certificate has not been renewed.
+async fn renew_now(
+	certificate: &Certificate,
+	account_s: AccountSync,
+	endpoint_s: EndpointSync,
+) -> Result<bool, Error> {
+	let endpoint_name = endpoint_s.read().await.name.to_owned();
+	let start = Instant::now();
+	events::emit("renewal_started", Some(certificate.get_id()), json!(null));
+	let res = with_timeout(
+		certificate.timeout,
+		request_certificate(certificate, account_s.clone(), endpoint_s),
+	)
+	.await
+	.map_err(|e| {
+		certificate.add_error_context("renewal", e.prefix("unable to renew the certificate"))
+	});
+	if let Ok(false) = res {
+		// The STAR certificate has not been replaced yet, the services do not need to be
+		// reloaded.
+		events::emit(
+			"renewal_finished",
+			Some(certificate.get_id()),
+			json!({"success": true, "status": "unchanged"}),
+		);
+		return Ok(false);
+	}
+	let res = res.map(|_| ());
+	let (status, error_source, error_transient) = match &res {
+		Ok(_) => ("success".to_string(), None, None),
+		Err(e) => {
+			LogEvent::RenewalFailed.log(|| certificate.warn_error("renewal", e));
+			(e.message.to_owned(), Some(e.kind), Some(e.is_transient()))
+		}
+	};
+	let is_success = error_source.is_none();
+	let account = account_s.read().await.get_identity(&endpoint_name);
+	events::emit(
+		"renewal_finished",
+		Some(certificate.get_id()),
+		json!({
+			"success": is_success,
+			"status": status,
+			"error_source": error_source.map(|e| e.to_string()),
+			"error_transient": error_transient,
+			"account": account.name,
+			"account_url": account.url,
+			"account_contacts": account.contacts,
+		}),
+	);
+	if let Err(e) = certificate.save_renewal_status(&status, error_source) {
+		certificate.warn(&e.prefix("unable to save the renewal status").message);
+	}
+	if is_success {
+		if let Err(e) = certificate.save_issuer() {
+			certificate.warn(&e.prefix("unable to save the issuer").message);
+		}
+	}
 	match certificate
-		.call_post_operation_hooks(&status, is_success)
+		.call_post_operation_hooks(&account, &status, error_source)
 		.await
 	{
 		Ok(_) => {}
 		Err(e) => {
 			let e = e.prefix("post-operation hook error");
-			certificate.warn(&e.message);
+			certificate.warn_error("post-operation-hook", &e);
 		}
 	};
-	(certificate, account_s.clone(), endpoint_s.clone())
+	if is_success {
+		if let Err(e) = certificate.save_issuance_duration(start.elapsed()).await {
+			certificate.warn(&e.prefix("unable to save the issuance duration").message);
+		}
+	}
+	res.map(|_| true)
+}
+
+/// Check that the renewed certificate is served. Since this may take some time, it should be
+/// done once the renewal permit has been released.
+async fn check_new_deployment(certificate: &Certificate) {
+	if let Err(e) = certificate.check_deployment().await {
+		notify_deployment_failure(certificate, &e);
+	}
+}
+
+/// Notify by email that the renewed certificate is not served, which usually means the
+/// post-operation hooks failed to reload the services.
+fn notify_deployment_failure(certificate: &Certificate, error: &Error) {
+	let notifier = match &certificate.email {
+		Some(n) => n.clone(),
+		None => return,
+	};
+	let id = certificate.get_id();
+	let subject = format!("[acmed] certificate {id} is not deployed");
+	let body = format!(
+		"The following certificate has been renewed, but the previous one is still served.\n\ncertificate: {id}\nerror: {}\n",
+		error.message,
+	);
+	let certificate = certificate.clone();
+	tokio::spawn(async move {
+		match notifier.send(&subject, &body).await {
+			Ok(_) => certificate.info("deployment failure notified by email"),
+			Err(e) => certificate.warn(&e.prefix("unable to send the email notification").message),
+		}
+	});
+}
+
+/// Return the expiration timestamp of the current certificate and the time left until then.
+async fn get_expiration(certificate: &Certificate) -> (Option<u64>, Option<Duration>) {
+	let not_after = if certificate_files_exists(&certificate.file_manager) {
+		get_certificate(&certificate.file_manager)
+			.await
+			.and_then(|c| c.not_after())
+			.ok()
+	} else {
+		None
+	};
+	let expires_in = not_after.map(|t| Duration::from_secs(t.saturating_sub(now())));
+	(not_after, expires_in)
+}
+
+fn emit_expiring(certificate: &Certificate, not_after: Option<u64>, expires_in: Option<Duration>) {
+	certificate.warn(&format!(
+		"the certificate expires in {} days",
+		expires_in.map(|d| d.as_secs() / 86_400).unwrap_or_default()
+	));
+	events::emit(
+		"certificate_expiring",
+		Some(certificate.get_id()),
+		json!({
+			"not_after": not_after.map(format_timestamp),
+			"expires_in_days": expires_in.map(|d| d.as_secs() / 86_400),
+		}),
+	);
+}
+
+/// Notify the renewal failure by email, if required.
+async fn notify_failure(
+	certificate: &Arc<Certificate>,
+	failures: &mut FailureTracker,
+	endpoint_s: &EndpointSync,
+	error: &Error,
+) {
+	let (not_after, expires_in) = get_expiration(certificate).await;
+	let notifier = match &certificate.email {
+		Some(n) => n.clone(),
+		None => return,
+	};
+	if !failures.failure(&notifier, expires_in, error.is_transient()) {
+		return;
+	}
+	let expiration = match (not_after, expires_in) {
+		(Some(t), Some(d)) => format!("{} (in {} days)", format_timestamp(t), d.as_secs() / 86_400),
+		_ => "no certificate".to_string(),
+	};
+	let subject = format!(
+		"[acmed] unable to renew certificate {}",
+		certificate.get_id()
+	);
+	let body = format!(
+		"ACMEd has been unable to renew the following certificate.\n\ncertificate: {}\nidentifiers: {}\nendpoint: {}\nconsecutive failures: {}\nexpiration: {expiration}\nerror: {}\n",
+		certificate.get_id(),
+		certificate
+			.identifiers
+			.iter()
+			.map(|i| i.value.to_owned())
+			.collect::<Vec<String>>()
+			.join(", "),
+		endpoint_s.read().await.name,
+		failures.nb_failures,
+		error.message,
+	);
+	let certificate = certificate.clone();
+	tokio::spawn(async move {
+		match notifier.send(&subject, &body).await {
+			Ok(_) => certificate.info("renewal failure notified by email"),
+			Err(e) => certificate.warn(&e.prefix("unable to send the email notification").message),
+		}
+	});
+}
+
+async fn deactivate_stale_authorizations(
+	certificate: &Certificate,
+	endpoints: &[(AccountSync, EndpointSync)],
+	is_abandoned: bool,
+) {
+	if let Err(e) =
+		crate::acme_proto::deactivate_stale_authorizations(certificate, endpoints, is_abandoned)
+			.await
+	{
+		certificate.warn(
+			&e.prefix("unable to deactivate the stale authorizations")
+				.message,
+		);
+	}
+}
+
+/// Once its in-flight renewal is over, deactivate the pending authorizations of the order left
+/// by a certificate which has been removed from the configuration, if any.
+fn abandon_order(
+	certificate: Arc<Certificate>,
+	accounts: &HashMap<String, AccountSync>,
+	endpoints: &HashMap<String, EndpointSync>,
+	previous: Option<JoinHandle<()>>,
+) {
+	let fallbacks = certificate
+		.fallback_endpoints
+		.iter()
+		.map(|f| (&f.account_name, &f.endpoint_name));
+	let endpoints: Vec<(AccountSync, EndpointSync)> =
+		std::iter::once((&certificate.account_name, &certificate.endpoint_name))
+			.chain(fallbacks)
+			.filter_map(|(acc, ept)| {
+				Some((accounts.get(acc)?.clone(), endpoints.get(ept)?.clone()))
+			})
+			.collect();
+	tokio::spawn(async move {
+		if let Some(handle) = previous {
+			let _ = handle.await;
+		}
+		deactivate_stale_authorizations(&certificate, &endpoints, true).await;
+	});
+}
+
+/// Return the index of the first endpoint, other than the current one, on which a new order can
+/// be created right away.
+async fn get_available_endpoint(targets: &[RenewalTarget], current: usize) -> Option<usize> {
+	for (idx, target) in targets.iter().enumerate() {
+		if idx != current
+			&& get_endpoint_wait(&target.endpoint_s, &target.certificate.account_name)
+				.await
+				.is_none()
+		{
+			return Some(idx);
+		}
+	}
+	None
+}
+
+/// Return how long to wait before creating a new order on the endpoint, along with the reason,
+/// either because of the endpoint's or the account's order limits or because the CA asked to.
+async fn get_endpoint_wait(
+	endpoint_s: &EndpointSync,
+	account_name: &str,
+) -> Option<(Duration, &'static str)> {
+	endpoint_wait(&*endpoint_s.read().await, account_name).await
+}
+
+async fn endpoint_wait(
+	endpoint: &Endpoint,
+	account_name: &str,
+) -> Option<(Duration, &'static str)> {
+	let waits = [
+		(
+			endpoint.order_limits.time_until_allowed().await,
+			"order limit reached",
+		),
+		(
+			endpoint.account_time_until_allowed(account_name).await,
+			"account order limit reached",
+		),
+		(endpoint.ca_rate_limits.retry_in(), "rate limited by the CA"),
+	];
+	let mut ret: Option<(Duration, &'static str)> = None;
+	for (wait, reason) in waits {
+		match wait {
+			Ok(d) if !d.is_zero() && ret.map(|(r, _)| d > r).unwrap_or(true) => {
+				ret = Some((d, reason));
+			}
+			Ok(_) => {}
+			Err(e) => log::warn!("endpoint \"{}\": {e}", endpoint.name),
+		}
+	}
+	ret
+}
+
+/// Signals the daemon reacts to.
+enum SignalType {
+	Hangup,
+	Interrupt,
+	Terminate,
+}
+
+#[cfg(unix)]
+type Signal = tokio::signal::unix::Signal;
+
+#[cfg(unix)]
+fn listen_signal(signal_type: SignalType, name: &str) -> Option<Signal> {
+	use tokio::signal::unix::SignalKind;
+	let kind = match signal_type {
+		SignalType::Hangup => SignalKind::hangup(),
+		SignalType::Interrupt => SignalKind::interrupt(),
+		SignalType::Terminate => SignalKind::terminate(),
+	};
+	match tokio::signal::unix::signal(kind) {
+		Ok(s) => Some(s),
+		Err(e) => {
+			log::error!("unable to listen to the {name} signal: {e}");
+			None
+		}
+	}
+}
+
+/// Only the interruption, using Ctrl-C, is available on the other platforms.
+#[cfg(not(unix))]
+struct Signal;
+
+#[cfg(not(unix))]
+impl Signal {
+	async fn recv(&mut self) -> Option<()> {
+		tokio::signal::ctrl_c().await.ok()
+	}
+}
+
+#[cfg(not(unix))]
+fn listen_signal(signal_type: SignalType, _name: &str) -> Option<Signal> {
+	match signal_type {
+		SignalType::Interrupt => Some(Signal),
+		SignalType::Hangup | SignalType::Terminate => None,
+	}
+}
+
+async fn recv_signal(signal: &mut Option<Signal>) -> Option<()> {
+	match signal {
+		Some(s) => s.recv().await,
+		None => None,
+	}
+}
+
+async fn recv_control(rx: &mut Option<mpsc::Receiver<ControlRequest>>) -> Option<ControlRequest> {
+	match rx {
+		Some(r) => r.recv().await,
+		None => None,
+	}
+}
+
+/// Sleep for the given duration. Return whether or not the task has been asked to stop.
+async fn sleep_or_stop(duration: Duration, stop: &mut watch::Receiver<bool>) -> bool {
+	tokio::select! {
+		_ = sleep(duration) => *stop.borrow(),
+		_ = stop.changed() => true,
+	}
+}
+
+/// Task a sleeping renewal task wakes up for.
+enum Wakeup {
+	Renewal,
+	Staple,
+	Secret,
+	Expiring,
+}
+
+/// Renew the certificate whenever required. The endpoints are listed in priority order: the
+/// renewals are requested to the first one, and fall back to the next one after several
+/// consecutive failures or while the current one is rate limiting. Once a renewal succeeds, the
+/// next one is requested to the first endpoint again.
+async fn renew_certificate(
+	certificate: Arc<Certificate>,
+	targets: Vec<RenewalTarget>,
+	permits: Arc<Semaphore>,
+	mut stop: watch::Receiver<bool>,
+	force: Arc<Notify>,
+	previous: Option<JoinHandle<()>>,
+) {
+	// Wait for the in-flight renewal of the previous version of this certificate.
+	if let Some(handle) = previous {
+		let _ = handle.await;
+	}
+	if let Err(e) = check_manifest(&certificate.file_manager).await {
+		certificate.warn_error("storage", &e.prefix("the certificate will not be renewed"));
+		return;
+	}
+	let endpoints: Vec<(AccountSync, EndpointSync)> = targets
+		.iter()
+		.map(|t| (t.account_s.clone(), t.endpoint_s.clone()))
+		.collect();
+	deactivate_stale_authorizations(&certificate, &endpoints, false).await;
+	let backoff = &crate::DEFAULT_RENEWAL_RETRY_BACKOFF_SEC;
+	let mut staple_failed = false;
+	let mut no_responder_warned = false;
+	let mut current = 0;
+	let mut nb_failures = 0;
+	let mut nb_fallbacks = 0;
+	let mut nb_retries = 0;
+	let mut failures = FailureTracker::default();
+	loop {
+		let mut scheduling_retries = 0;
+		loop {
+			match certificate.schedule_renewal().await {
+				Ok(duration) => {
+					let (not_after, expires_in) = get_expiration(&certificate).await;
+					if failures.is_expiring(expires_in, certificate.expiring_margin) {
+						emit_expiring(&certificate, not_after, expires_in);
+					}
+					let mut wakeup = (duration, Wakeup::Renewal);
+					// The OCSP response is refreshed in between the renewals.
+					if certificate.ocsp_staple {
+						match ocsp::get_refresh_delay(&certificate).await {
+							Some(d) => {
+								let d = if staple_failed {
+									Duration::from_secs(crate::DEFAULT_OCSP_RETRY_SEC)
+								} else {
+									d
+								};
+								if d < wakeup.0 {
+									wakeup = (d, Wakeup::Staple);
+								}
+							}
+							None if !no_responder_warned => {
+								certificate.warn(
+									"the certificate does not specify any OCSP responder, no OCSP response will be stapled",
+								);
+								no_responder_warned = true;
+							}
+							None => {}
+						}
+					}
+					// So is a Kubernetes secret which could not be updated.
+					let secret_delay = Duration::from_secs(crate::DEFAULT_KUBERNETES_RETRY_SEC);
+					if kubernetes::is_pending(&certificate) && secret_delay < wakeup.0 {
+						wakeup = (secret_delay, Wakeup::Secret);
+					}
+					// The `certificate_expiring` event is emitted as soon as the certificate
+					// enters the margin, even if it is not being renewed.
+					let expiring_delay = expires_in
+						.and_then(|d| d.checked_sub(certificate.expiring_margin))
+						.filter(|d| !d.is_zero());
+					if let Some(d) = expiring_delay {
+						if d < wakeup.0 {
+							wakeup = (d, Wakeup::Expiring);
+						}
+					}
+					tokio::select! {
+						_ = sleep(wakeup.0) => {
+							if *stop.borrow() {
+								return;
+							}
+							match wakeup.1 {
+								Wakeup::Renewal => {}
+								Wakeup::Staple => {
+									let endpoint_s = &targets[current].endpoint_s;
+									if let Err(e) = ocsp::update_staple(&certificate, endpoint_s).await {
+										certificate.warn_error("ocsp", &e.prefix("OCSP response"));
+										staple_failed = true;
+									} else {
+										staple_failed = false;
+									}
+									continue;
+								}
+								Wakeup::Secret => {
+									kubernetes::retry_pending(&certificate).await;
+									continue;
+								}
+								Wakeup::Expiring => continue,
+							}
+						}
+						_ = stop.changed() => return,
+						_ = force.notified() => certificate.info("starting the requested renewal"),
+					}
+					break;
+				}
+				Err(e) => {
+					certificate.warn_error("scheduling", &e);
+					let duration =
+						Duration::from_secs(backoff[scheduling_retries.min(backoff.len() - 1)]);
+					if sleep_or_stop(duration, &mut stop).await {
+						return;
+					}
+					scheduling_retries += 1;
+				}
+			}
+		}
+		// Limit the number of concurrent renewals. The endpoint is checked once the permit is
+		// obtained since another renewal may have reached its limits in the meantime.
+		let permit = tokio::select! {
+			p = permits.acquire() => p,
+			_ = stop.changed() => return,
+		};
+		let target = &targets[current];
+		let wait = get_endpoint_wait(&target.endpoint_s, &target.certificate.account_name).await;
+		if let Some((duration, reason)) = wait {
+			match get_available_endpoint(&targets, current).await {
+				Some(idx) => {
+					certificate.info(&format!(
+						"{reason} on endpoint \"{}\", falling back to endpoint \"{}\"",
+						target.certificate.endpoint_name, targets[idx].certificate.endpoint_name
+					));
+					current = idx;
+					nb_failures = 0;
+				}
+				None => {
+					drop(permit);
+					certificate.info(&format!(
+						"{reason}, delaying the renewal by {} seconds",
+						duration.as_secs()
+					));
+					if sleep_or_stop(duration, &mut stop).await {
+						return;
+					}
+					continue;
+				}
+			}
+		}
+		// Renewing on a read-only storage would fail after the certificate has been issued, hence
+		// the renewal is postponed until the storage is writable.
+		if !check_writable(&certificate.file_manager.directories()).await {
+			drop(permit);
+			let duration = Duration::from_secs(crate::DEFAULT_READ_ONLY_STORAGE_RETRY_SEC);
+			certificate.warn(&format!(
+				"read-only storage, delaying the renewal by {} seconds",
+				duration.as_secs()
+			));
+			if sleep_or_stop(duration, &mut stop).await {
+				return;
+			}
+			continue;
+		}
+		let target = targets[current].clone();
+		let endpoint_s = target.endpoint_s;
+		let res = renew_now(&target.certificate, target.account_s, endpoint_s.clone()).await;
+		drop(permit);
+		let error = match res {
+			Ok(is_new) => {
+				if is_new {
+					check_new_deployment(&certificate).await;
+				}
+				no_responder_warned = false;
+				failures.success();
+				current = 0;
+				nb_failures = 0;
+				nb_fallbacks = 0;
+				nb_retries = 0;
+				if *stop.borrow() {
+					return;
+				}
+				continue;
+			}
+			Err(e) => e,
+		};
+		notify_failure(&certificate, &mut failures, &endpoint_s, &error).await;
+		nb_failures += 1;
+		// Retrying a permanent error returned by the CA on the same endpoint would fail again,
+		// while another CA may accept the request. A local error, such as a configuration or a
+		// storage one, would fail on any endpoint.
+		let reason = if error.is_transient() {
+			if nb_failures >= certificate.failover_attempts {
+				Some(format!("{nb_failures} consecutive failures"))
+			} else {
+				None
+			}
+		} else if error.kind == ErrorKind::Acme {
+			Some("permanent failure".to_string())
+		} else {
+			None
+		};
+		let is_delayed = match reason {
+			Some(reason) if targets.len() > 1 => {
+				let next = (current + 1) % targets.len();
+				certificate.warn(&format!(
+					"{reason} on endpoint \"{}\", falling back to endpoint \"{}\"",
+					target.certificate.endpoint_name, targets[next].certificate.endpoint_name
+				));
+				current = next;
+				nb_failures = 0;
+				nb_fallbacks += 1;
+				// Once every endpoint has failed, the renewal is delayed as if there were a
+				// single one, so an outage does not make it cycle through the CAs forever.
+				nb_fallbacks % targets.len() == 0
+			}
+			_ => true,
+		};
+		if is_delayed {
+			let duration = get_renewal_retry_delay(&error, nb_retries);
+			nb_retries += 1;
+			certificate.info(&format!(
+				"retrying the renewal in {} seconds",
+				duration.as_secs()
+			));
+			tokio::select! {
+				_ = sleep(duration) => {}
+				_ = stop.changed() => return,
+				_ = force.notified() => certificate.info("starting the requested renewal"),
+			}
+		}
+		if *stop.borrow() {
+			return;
+		}
+	}
+}
+
+/// Return the delay before retrying a failed renewal, which increases with the number of
+/// previous retries. A permanent error is only retried once a day, since it usually requires
+/// an intervention.
+fn get_renewal_retry_delay(error: &Error, retries: usize) -> Duration {
+	let backoff = &crate::DEFAULT_RENEWAL_RETRY_BACKOFF_SEC;
+	let delay = if error.is_transient() {
+		backoff[retries.min(backoff.len() - 1)]
+	} else {
+		backoff[backoff.len() - 1]
+	};
+	Duration::from_secs(delay)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{check_duplicate_identifiers, load_certificate};
+	use crate::config::{Config, DuplicateIdentifiers};
+	use acme_common::error::ErrorKind;
+	use std::collections::{HashMap, HashSet};
+
+	const CONFIG: &str = r#"
+[[endpoint]]
+name = "ep"
+url = "https://acme.example.org/directory"
+tos_agreed = true
+
+[[hook]]
+name = "http"
+type = ["challenge-http-01"]
+cmd = "true"
+
+[[account]]
+name = "acc"
+contacts = [{ mailto = "certs@example.org" }]
+
+[[certificate]]
+name = "a"
+account = "acc"
+endpoint = "ep"
+identifiers = [
+	{ dns = "a.example.org", challenge = "http-01" },
+	{ dns = "b.example.org", challenge = "http-01" },
+]
+hooks = ["http"]
+
+[[certificate]]
+name = "a"
+account = "acc"
+endpoint = "ep"
+key_type = "ecdsa_p256"
+identifiers = [
+	{ dns = "a.example.org", challenge = "http-01" },
+	{ dns = "b.example.org", challenge = "http-01" },
+]
+hooks = ["http"]
+
+[[certificate]]
+name = "b"
+account = "acc"
+endpoint = "ep"
+identifiers = [
+	{ dns = "b.example.org", challenge = "http-01" },
+	{ dns = "c.example.org", challenge = "http-01" },
+]
+hooks = ["http"]
+
+[[certificate]]
+name = "c"
+account = "acc"
+endpoint = "ep"
+identifiers = [{ dns = "a.example.org", challenge = "http-01" }]
+hooks = ["http"]
+"#;
+
+	fn check_all(mode: DuplicateIdentifiers) -> Vec<Result<Vec<String>, ErrorKind>> {
+		let cnf: Config = toml::from_str(CONFIG).unwrap();
+		let mut seen = HashMap::new();
+		cnf.certificate
+			.iter()
+			.map(|crt| {
+				let (mut cert, _) =
+					load_certificate(&cnf, crt, &[], &None, &HashSet::new(), &HashSet::new())
+						.unwrap();
+				check_duplicate_identifiers(&mut cert, mode, &mut seen)
+					.map(|_| cert.identifiers.iter().map(|i| i.value.clone()).collect())
+					.map_err(|e| e.kind)
+			})
+			.collect()
+	}
+
+	fn ids(values: &[&str]) -> Result<Vec<String>, ErrorKind> {
+		Ok(values.iter().map(|v| v.to_string()).collect())
+	}
+
+	#[test]
+	fn test_duplicate_identifiers_warn() {
+		for mode in [DuplicateIdentifiers::Allow, DuplicateIdentifiers::Warn] {
+			let res = check_all(mode);
+			assert_eq!(res[0], ids(&["a.example.org", "b.example.org"]));
+			assert_eq!(res[1], ids(&["a.example.org", "b.example.org"]));
+			assert_eq!(res[2], ids(&["b.example.org", "c.example.org"]));
+			assert_eq!(res[3], ids(&["a.example.org"]));
+		}
+	}
+
+	#[test]
+	fn test_duplicate_identifiers_skip() {
+		let res = check_all(DuplicateIdentifiers::Skip);
+		assert_eq!(res[0], ids(&["a.example.org", "b.example.org"]));
+		assert_eq!(res[1], ids(&["a.example.org", "b.example.org"]));
+		assert_eq!(res[2], ids(&["c.example.org"]));
+		assert_eq!(res[3], Err(ErrorKind::Config));
+	}
 }
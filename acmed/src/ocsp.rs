@@ -0,0 +1,96 @@
+use crate::certificate::Certificate;
+use crate::endpoint::format_timestamp;
+use crate::http;
+use crate::logs::HasLogger;
+use crate::storage;
+use crate::EndpointSync;
+use acme_common::crypto::{OcspValidity, X509Certificate};
+use acme_common::error::Error;
+use acme_common::now;
+use reqwest::header;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT_SEC: u64 = 30;
+
+async fn get_leaf_and_issuer(
+	cert: &Certificate,
+) -> Result<(X509Certificate, X509Certificate), Error> {
+	let mut chain = storage::get_certificate_chain(&cert.file_manager)
+		.await?
+		.into_iter();
+	match (chain.next(), chain.next()) {
+		(Some(leaf), Some(issuer)) => Ok((leaf, issuer)),
+		_ => Err("the issuer's certificate is missing from the certificate chain".into()),
+	}
+}
+
+/// Fetch a fresh OCSP response from the CA's responder and write it next to the certificate. The
+/// request is sent using the endpoint's HTTP client, hence its proxy and root certificates. If
+/// the certificate does not specify any OCSP responder, there is nothing to fetch.
+pub async fn update_staple(
+	cert: &Certificate,
+	endpoint_s: &EndpointSync,
+) -> Result<Option<OcspValidity>, Error> {
+	let (leaf, issuer) = get_leaf_and_issuer(cert).await?;
+	let url = match leaf.ocsp_responder() {
+		Some(url) => url,
+		None => return Ok(None),
+	};
+	cert.debug(&format!("requesting the OCSP response from {url}"));
+	let client = http::get_client(&mut *(endpoint_s.write().await))?;
+	let response = client
+		.post(&url)
+		.header(header::CONTENT_TYPE, "application/ocsp-request")
+		.header(header::ACCEPT, "application/ocsp-response")
+		.timeout(Duration::from_secs(REQUEST_TIMEOUT_SEC))
+		.body(leaf.ocsp_request(&issuer)?)
+		.send()
+		.await?;
+	let status = response.status();
+	if !status.is_success() {
+		let msg = format!("{url}: OCSP responder returned {status}");
+		return Err(msg.into());
+	}
+	let data = response.bytes().await?;
+	let validity = leaf
+		.check_ocsp_response(&issuer, &data)
+		.map_err(|e| e.prefix(&url))?;
+	storage::write_ocsp_response(&cert.file_manager, &data).await?;
+	cert.info(&format!(
+		"OCSP response updated, valid until {}",
+		format_timestamp(validity.next_update)
+	));
+	Ok(Some(validity))
+}
+
+/// Return the period of time after which the current OCSP response should be replaced, which is
+/// half of its validity period. A missing, invalid or outdated response has to be replaced
+/// immediately. If the certificate does not specify any OCSP responder, the response is never
+/// replaced.
+pub async fn get_refresh_delay(cert: &Certificate) -> Option<Duration> {
+	match get_current_validity(cert).await {
+		Ok(Some(validity)) => {
+			let refresh_at = validity.this_update
+				+ validity.next_update.saturating_sub(validity.this_update) / 2;
+			Some(Duration::from_secs(refresh_at.saturating_sub(now())))
+		}
+		Ok(None) => match get_leaf_and_issuer(cert).await {
+			Ok((leaf, _)) if leaf.ocsp_responder().is_none() => None,
+			_ => Some(Duration::ZERO),
+		},
+		Err(e) => {
+			cert.debug(&format!("current OCSP response: {e}"));
+			Some(Duration::ZERO)
+		}
+	}
+}
+
+async fn get_current_validity(cert: &Certificate) -> Result<Option<OcspValidity>, Error> {
+	let data = match storage::get_ocsp_response(&cert.file_manager).await? {
+		Some(d) => d,
+		None => return Ok(None),
+	};
+	let (leaf, issuer) = get_leaf_and_issuer(cert).await?;
+	let validity = leaf.check_ocsp_response(&issuer, &data)?;
+	Ok(Some(validity))
+}
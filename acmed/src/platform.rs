@@ -0,0 +1,294 @@
+use acme_common::error::Error;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+/// Operations whose semantics depend on the operating system.
+pub trait Platform {
+	/// Return the options used to open a file for writing. The mode is applied when the file is
+	/// created.
+	fn file_options(mode: u32) -> OpenOptions;
+
+	/// Set the mode of an existing file or directory.
+	fn set_mode(path: &Path, mode: u32) -> Result<(), Error>;
+
+	/// Change the owner and the group of a file or directory. Both of them may either be a name or
+	/// a numerical id, and are left unchanged if not specified.
+	fn set_owner(path: &Path, user: Option<&str>, group: Option<&str>) -> Result<(), Error>;
+
+	/// Return the offset, in seconds, of the local time from UTC at the given Unix timestamp, or
+	/// `None` if the local time zone cannot be determined.
+	fn utc_offset(ts: u64) -> Option<i64>;
+
+	/// Return whether or not the standard output is a terminal able to display colors.
+	fn stdout_is_terminal() -> bool;
+
+	/// Return whether or not the file exists and can be executed.
+	fn is_executable(path: &Path) -> bool;
+}
+
+#[cfg(unix)]
+pub type Current = Unix;
+#[cfg(windows)]
+pub type Current = Windows;
+#[cfg(not(any(unix, windows)))]
+pub type Current = Fallback;
+
+#[cfg(unix)]
+pub struct Unix;
+
+#[cfg(unix)]
+impl Platform for Unix {
+	fn file_options(mode: u32) -> OpenOptions {
+		use std::os::unix::fs::OpenOptionsExt;
+		let mut options = OpenOptions::new();
+		options.mode(mode);
+		options
+	}
+
+	fn set_mode(path: &Path, mode: u32) -> Result<(), Error> {
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+		Ok(())
+	}
+
+	fn set_owner(path: &Path, user: Option<&str>, group: Option<&str>) -> Result<(), Error> {
+		let uid = match user {
+			Some(u) => get_uid(u)?,
+			None => None,
+		};
+		let gid = match group {
+			Some(g) => get_gid(g)?,
+			None => None,
+		};
+		if uid.is_none() && gid.is_none() {
+			return Ok(());
+		}
+		nix::unistd::chown(path, uid, gid).map_err(|e| format!("{e}").into())
+	}
+
+	fn utc_offset(ts: u64) -> Option<i64> {
+		let t = ts as libc::time_t;
+		let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+		// localtime_r, unlike localtime, is thread-safe.
+		if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+			return None;
+		}
+		Some(tm.tm_gmtoff as i64)
+	}
+
+	fn stdout_is_terminal() -> bool {
+		unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+	}
+
+	fn is_executable(path: &Path) -> bool {
+		use std::os::unix::fs::PermissionsExt;
+		match std::fs::metadata(path) {
+			Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+			Err(_) => false,
+		}
+	}
+}
+
+#[cfg(unix)]
+pub fn get_uid(user: &str) -> Result<Option<nix::unistd::Uid>, Error> {
+	if user.bytes().all(|b| b.is_ascii_digit()) {
+		let raw_uid = user
+			.parse::<u32>()
+			.map_err(|_| Error::from("unable to parse the UID"))?;
+		Ok(Some(nix::unistd::Uid::from_raw(raw_uid)))
+	} else {
+		let user = nix::unistd::User::from_name(user)?;
+		Ok(user.map(|u| u.uid))
+	}
+}
+
+#[cfg(unix)]
+pub fn get_gid(group: &str) -> Result<Option<nix::unistd::Gid>, Error> {
+	if group.bytes().all(|b| b.is_ascii_digit()) {
+		let raw_gid = group
+			.parse::<u32>()
+			.map_err(|_| Error::from("unable to parse the GID"))?;
+		Ok(Some(nix::unistd::Gid::from_raw(raw_gid)))
+	} else {
+		let grp = nix::unistd::Group::from_name(group)?;
+		Ok(grp.map(|g| g.gid))
+	}
+}
+
+/// The access to files is governed by the ACLs inherited from their directory, hence the modes
+/// are ignored. Since the owner cannot be changed, specifying one is an error rather than a
+/// silently ignored security setting.
+#[cfg(windows)]
+pub struct Windows;
+
+#[cfg(windows)]
+impl Platform for Windows {
+	fn file_options(_mode: u32) -> OpenOptions {
+		OpenOptions::new()
+	}
+
+	fn set_mode(_path: &Path, _mode: u32) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn set_owner(_path: &Path, user: Option<&str>, group: Option<&str>) -> Result<(), Error> {
+		if user.is_some() || group.is_some() {
+			return Err("changing the owner of a file is not supported on Windows".into());
+		}
+		Ok(())
+	}
+
+	/// The timestamp is converted to the local time using the time zone rules, daylight saving
+	/// time included, which apply at that time.
+	fn utc_offset(ts: u64) -> Option<i64> {
+		let utc_ft = windows::to_file_time(ts);
+		let mut utc_st = windows::SystemTime::default();
+		let mut local_st = windows::SystemTime::default();
+		let mut local_ft = windows::FileTime::default();
+		let is_ok = unsafe {
+			windows::FileTimeToSystemTime(&utc_ft, &mut utc_st) != 0
+				&& windows::SystemTimeToTzSpecificLocalTime(
+					std::ptr::null(),
+					&utc_st,
+					&mut local_st,
+				) != 0 && windows::SystemTimeToFileTime(&local_st, &mut local_ft) != 0
+		};
+		if !is_ok {
+			return None;
+		}
+		let diff = windows::from_file_time(&local_ft) - windows::from_file_time(&utc_ft);
+		Some(diff / windows::FILE_TIME_TICKS_PER_SEC)
+	}
+
+	fn stdout_is_terminal() -> bool {
+		false
+	}
+
+	/// The programs are usually referred to without their extension.
+	fn is_executable(path: &Path) -> bool {
+		path.is_file() || path.with_extension("exe").is_file()
+	}
+}
+
+/// Bindings to the time functions of the Windows API, see the `minwinbase.h` and `timezoneapi.h`
+/// headers.
+#[cfg(windows)]
+mod windows {
+	/// Number of 100-nanosecond intervals in a second.
+	pub const FILE_TIME_TICKS_PER_SEC: i64 = 10_000_000;
+	/// Number of seconds between the Windows epoch, January 1, 1601, and the Unix one.
+	const UNIX_EPOCH_OFFSET_SEC: u64 = 11_644_473_600;
+
+	#[repr(C)]
+	#[derive(Default)]
+	pub struct FileTime {
+		low_date_time: u32,
+		high_date_time: u32,
+	}
+
+	#[repr(C)]
+	#[derive(Default)]
+	pub struct SystemTime {
+		year: u16,
+		month: u16,
+		day_of_week: u16,
+		day: u16,
+		hour: u16,
+		minute: u16,
+		second: u16,
+		milliseconds: u16,
+	}
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		pub fn FileTimeToSystemTime(
+			file_time: *const FileTime,
+			system_time: *mut SystemTime,
+		) -> i32;
+		pub fn SystemTimeToFileTime(
+			system_time: *const SystemTime,
+			file_time: *mut FileTime,
+		) -> i32;
+		pub fn SystemTimeToTzSpecificLocalTime(
+			time_zone: *const std::ffi::c_void,
+			universal_time: *const SystemTime,
+			local_time: *mut SystemTime,
+		) -> i32;
+	}
+
+	pub fn to_file_time(ts: u64) -> FileTime {
+		let ticks = (ts + UNIX_EPOCH_OFFSET_SEC) * FILE_TIME_TICKS_PER_SEC as u64;
+		FileTime {
+			low_date_time: ticks as u32,
+			high_date_time: (ticks >> 32) as u32,
+		}
+	}
+
+	pub fn from_file_time(ft: &FileTime) -> i64 {
+		(((ft.high_date_time as u64) << 32) | ft.low_date_time as u64) as i64
+	}
+}
+
+/// Platforms without any notion of file modes, owners or time zones.
+#[cfg(not(any(unix, windows)))]
+pub struct Fallback;
+
+#[cfg(not(any(unix, windows)))]
+impl Platform for Fallback {
+	fn file_options(_mode: u32) -> OpenOptions {
+		OpenOptions::new()
+	}
+
+	fn set_mode(_path: &Path, _mode: u32) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn set_owner(_path: &Path, _user: Option<&str>, _group: Option<&str>) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn utc_offset(_ts: u64) -> Option<i64> {
+		None
+	}
+
+	fn stdout_is_terminal() -> bool {
+		false
+	}
+
+	fn is_executable(path: &Path) -> bool {
+		path.is_file()
+	}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::{get_uid, Current, Platform};
+	use crate::storage::TestDir;
+	use std::os::unix::fs::PermissionsExt;
+
+	#[test]
+	fn test_unix_mode() {
+		let dir = TestDir::new("platform");
+		let path = dir.path().join("file");
+		Current::file_options(0o600)
+			.write(true)
+			.create_new(true)
+			.open(&path)
+			.unwrap();
+		let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+		Current::set_mode(&path, 0o640).unwrap();
+		let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o640);
+		assert!(!Current::is_executable(&path));
+		Current::set_mode(&path, 0o750).unwrap();
+		assert!(Current::is_executable(&path));
+		Current::set_owner(&path, None, None).unwrap();
+	}
+
+	#[test]
+	fn test_get_uid() {
+		assert_eq!(get_uid("0").unwrap().map(|u| u.as_raw()), Some(0));
+		assert_eq!(get_uid("root").unwrap().map(|u| u.as_raw()), Some(0));
+	}
+}
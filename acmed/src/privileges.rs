@@ -0,0 +1,281 @@
+#[cfg(unix)]
+use crate::platform::{get_gid, get_uid};
+use acme_common::error::Error;
+#[cfg(unix)]
+use nix::unistd::{self, Gid, Uid, User};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether or not the daemon has switched to another user or group.
+static DROPPED: AtomicBool = AtomicBool::new(false);
+
+/// Permission bits required to read a file.
+#[cfg(unix)]
+const ACCESS_READ: u32 = 0o4;
+/// Permission bits required to create, replace and remove the entries of a directory.
+#[cfg(unix)]
+const ACCESS_WRITE_DIR: u32 = 0o3;
+/// Permission bits required to execute a file.
+#[cfg(unix)]
+const ACCESS_EXECUTE: u32 = 0o1;
+
+/// Switch to the given user and group. If no group is given, the user's primary group is used.
+/// When `keep_chown` is set, the `CAP_CHOWN` capability is retained on Linux so the owner of the
+/// created files can still be set.
+///
+/// Capabilities are per-thread, hence this must be called before any other thread is started.
+#[cfg(unix)]
+pub fn drop_privileges(
+	user: Option<&str>,
+	group: Option<&str>,
+	keep_chown: bool,
+) -> Result<(), Error> {
+	let (uid, gid) = get_ids(user, group)?;
+	let uid = uid.map(Uid::from_raw);
+	let gid = gid.map(Gid::from_raw);
+	if uid.is_none() && gid.is_none() {
+		return Ok(());
+	}
+	if !Uid::effective().is_root() {
+		return Err("unable to drop the privileges: not running as root".into());
+	}
+	let keep_chown = keep_chown && uid.map(|u| !u.is_root()).unwrap_or(false);
+	if keep_chown {
+		keep_capabilities()?;
+	}
+	if let Some(gid) = gid {
+		set_groups(gid)?;
+		unistd::setgid(gid).map_err(|e| Error::from(format!("setgid: {e}")))?;
+	}
+	if let Some(uid) = uid {
+		unistd::setuid(uid).map_err(|e| Error::from(format!("setuid: {e}")))?;
+	}
+	DROPPED.store(true, Ordering::Relaxed);
+	if keep_chown {
+		set_chown_capability()?;
+	}
+	log::debug!(
+		"privileges dropped: running with uid {} and gid {}",
+		Uid::current(),
+		Gid::current()
+	);
+	Ok(())
+}
+
+/// Return whether or not the privileges have been dropped, in which case the hooks can no longer
+/// be run as another user or group.
+pub fn are_dropped() -> bool {
+	DROPPED.load(Ordering::Relaxed)
+}
+
+/// Hand the given directories over to the user and group the daemon is about to switch to, along
+/// with the entries they contain which are owned by the current user, such as the account files
+/// and the certificates previously written as root. The directories whose owner has been
+/// configured are left as is, but they, as well as the configuration files, are then checked to
+/// be accessible once the privileges are dropped, so the daemon does not fail on its first write
+/// or reload.
+#[cfg(unix)]
+pub fn hand_over(
+	directories: &[&Path],
+	files: &[PathBuf],
+	uid: Option<u32>,
+	gid: Option<u32>,
+) -> Result<(), Error> {
+	let owner = Uid::effective().as_raw();
+	for dir in directories.iter().filter(|d| d.is_dir()) {
+		let mut paths = vec![dir.to_path_buf()];
+		for entry in std::fs::read_dir(dir).map_err(|e| path_error(e, dir))? {
+			paths.push(entry.map_err(|e| path_error(e, dir))?.path());
+		}
+		for path in paths.iter() {
+			let metadata = std::fs::symlink_metadata(path).map_err(|e| path_error(e, path))?;
+			if metadata.uid() == owner && !metadata.file_type().is_symlink() {
+				unistd::chown(path, uid.map(Uid::from_raw), gid.map(Gid::from_raw))
+					.map_err(|e| Error::from(format!("{}: {e}", path.display())))?;
+			}
+		}
+		check_access(dir, uid, gid, ACCESS_WRITE_DIR)?;
+	}
+	for file in files.iter() {
+		check_access(file, uid, gid, ACCESS_READ)?;
+	}
+	Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn hand_over(
+	_directories: &[&Path],
+	_files: &[PathBuf],
+	_uid: Option<u32>,
+	_gid: Option<u32>,
+) -> Result<(), Error> {
+	Ok(())
+}
+
+/// Check that the given user and group are granted the given access to the path, using the
+/// permission bits of its mode. Since the supplementary groups are dropped along with the
+/// privileges, only the given group is taken into account. ACLs are not.
+#[cfg(unix)]
+pub fn check_access(
+	path: &Path,
+	uid: Option<u32>,
+	gid: Option<u32>,
+	access: u32,
+) -> Result<(), Error> {
+	let uid = uid.unwrap_or_else(|| Uid::effective().as_raw());
+	let gid = gid.unwrap_or_else(|| Gid::effective().as_raw());
+	if uid == 0 {
+		return Ok(());
+	}
+	let metadata = std::fs::metadata(path).map_err(|e| path_error(e, path))?;
+	let mode = metadata.mode();
+	let granted = if metadata.uid() == uid {
+		mode >> 6
+	} else if metadata.gid() == gid {
+		mode >> 3
+	} else {
+		mode
+	};
+	if granted & access != access {
+		let msg = format!(
+			"{}: permission denied to uid {uid} and gid {gid}",
+			path.display()
+		);
+		return Err(msg.into());
+	}
+	Ok(())
+}
+
+/// Check that the given user and group, or the current ones, can create files in the directory.
+/// The directories owned by the current user are handed over when the privileges are dropped,
+/// hence they are considered writable.
+#[cfg(unix)]
+pub fn check_writable_dir(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+	if uid.is_some() || gid.is_some() {
+		let metadata = std::fs::metadata(path).map_err(|e| path_error(e, path))?;
+		if metadata.uid() == Uid::effective().as_raw() {
+			return Ok(());
+		}
+	}
+	check_access(path, uid, gid, ACCESS_WRITE_DIR)
+}
+
+/// Check that the given user and group, or the current ones, can read the file.
+#[cfg(unix)]
+pub fn check_readable(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+	check_access(path, uid, gid, ACCESS_READ)
+}
+
+/// Check that the given user and group, or the current ones, can execute the file.
+#[cfg(unix)]
+pub fn check_executable(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+	check_access(path, uid, gid, ACCESS_EXECUTE)
+}
+
+#[cfg(not(unix))]
+pub fn check_writable_dir(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> Result<(), Error> {
+	Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_readable(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> Result<(), Error> {
+	Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_executable(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> Result<(), Error> {
+	Ok(())
+}
+
+#[cfg(unix)]
+fn path_error(e: std::io::Error, path: &Path) -> Error {
+	Error::from(e).prefix(&path.display().to_string())
+}
+
+/// Return the ids of the given user and group. If no group is given, the user's primary group is
+/// used.
+#[cfg(unix)]
+pub fn get_ids(
+	user: Option<&str>,
+	group: Option<&str>,
+) -> Result<(Option<u32>, Option<u32>), Error> {
+	let uid = match user {
+		Some(u) => Some(get_uid(u)?.ok_or_else(|| Error::from(format!("{u}: user not found")))?),
+		None => None,
+	};
+	let gid = match group {
+		Some(g) => Some(get_gid(g)?.ok_or_else(|| Error::from(format!("{g}: group not found")))?),
+		None => match uid {
+			Some(u) => User::from_uid(u)?.map(|u| u.gid),
+			None => None,
+		},
+	};
+	Ok((uid.map(|u| u.as_raw()), gid.map(|g| g.as_raw())))
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(
+	user: Option<&str>,
+	group: Option<&str>,
+	_keep_chown: bool,
+) -> Result<(), Error> {
+	get_ids(user, group).map(|_| ())
+}
+
+/// Users and groups cannot be switched to on this platform, hence specifying one is an error
+/// rather than a silently ignored security setting.
+#[cfg(not(unix))]
+pub fn get_ids(
+	user: Option<&str>,
+	group: Option<&str>,
+) -> Result<(Option<u32>, Option<u32>), Error> {
+	if user.is_some() || group.is_some() {
+		return Err("changing the user or the group is not supported on this platform".into());
+	}
+	Ok((None, None))
+}
+
+#[cfg(all(
+	unix,
+	not(any(target_os = "ios", target_os = "macos", target_os = "redox"))
+))]
+fn set_groups(gid: Gid) -> Result<(), Error> {
+	unistd::setgroups(&[gid]).map_err(|e| Error::from(format!("setgroups: {e}")))
+}
+
+#[cfg(any(target_os = "ios", target_os = "macos", target_os = "redox"))]
+fn set_groups(_gid: Gid) -> Result<(), Error> {
+	Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn keep_capabilities() -> Result<(), Error> {
+	rustix::thread::set_keep_capabilities(true)
+		.map_err(|e| format!("unable to keep the capabilities: {e}").into())
+}
+
+/// Once the user has been switched to, restrict the capabilities to `CAP_CHOWN`.
+#[cfg(target_os = "linux")]
+fn set_chown_capability() -> Result<(), Error> {
+	use rustix::thread::{set_capabilities, CapabilityFlags, CapabilitySets};
+	let sets = CapabilitySets {
+		effective: CapabilityFlags::CHOWN,
+		permitted: CapabilityFlags::CHOWN,
+		inheritable: CapabilityFlags::empty(),
+	};
+	set_capabilities(None, sets)
+		.map_err(|e| format!("unable to retain the CAP_CHOWN capability: {e}").into())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn keep_capabilities() -> Result<(), Error> {
+	log::warn!("the owner of the created files cannot be set once the privileges are dropped");
+	Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn set_chown_capability() -> Result<(), Error> {
+	Ok(())
+}
@@ -0,0 +1,200 @@
+use acme_common::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Period of the week, in local time, during which a certificate may be renewed, for example
+/// `mon-fri 02:00-05:00`. A window ending before it starts goes past midnight, in which case the
+/// days are those on which it starts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenewalWindow {
+	days: [bool; 7],
+	start: u32,
+	end: u32,
+}
+
+impl RenewalWindow {
+	fn len(&self) -> u32 {
+		if self.end > self.start {
+			self.end - self.start
+		} else {
+			self.end + MINUTES_PER_DAY - self.start
+		}
+	}
+}
+
+fn parse_day(day: &str) -> Result<usize, Error> {
+	let day = day.to_lowercase();
+	DAY_NAMES
+		.iter()
+		.position(|d| *d == day)
+		.ok_or_else(|| format!("{day}: invalid day").into())
+}
+
+fn parse_days(days: &str) -> Result<[bool; 7], Error> {
+	let mut ret = [false; 7];
+	for part in days.split(',') {
+		match part.split_once('-') {
+			Some((first, last)) => {
+				let (first, last) = (parse_day(first)?, parse_day(last)?);
+				let mut day = first;
+				loop {
+					ret[day] = true;
+					if day == last {
+						break;
+					}
+					day = (day + 1) % 7;
+				}
+			}
+			None => ret[parse_day(part)?] = true,
+		}
+	}
+	Ok(ret)
+}
+
+fn parse_time(time: &str, allow_end_of_day: bool) -> Result<u32, Error> {
+	let err = || Error::from(format!("{time}: invalid time"));
+	let (h, m) = time.split_once(':').ok_or_else(err)?;
+	if h.len() != 2 || m.len() != 2 {
+		return Err(err());
+	}
+	let h: u32 = h.parse().map_err(|_| err())?;
+	let m: u32 = m.parse().map_err(|_| err())?;
+	let minutes = h * 60 + m;
+	if m >= 60 || minutes > MINUTES_PER_DAY || (minutes == MINUTES_PER_DAY && !allow_end_of_day) {
+		return Err(err());
+	}
+	Ok(minutes)
+}
+
+impl FromStr for RenewalWindow {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let prefix = format!("{s}: invalid renewal window");
+		let (days, times) = match s.trim().rsplit_once(' ') {
+			Some((d, t)) => (parse_days(d.trim()).map_err(|e| e.prefix(&prefix))?, t),
+			None => ([true; 7], s.trim()),
+		};
+		let (start, end) = times
+			.split_once('-')
+			.ok_or_else(|| Error::from(format!("{prefix}: missing end time")))?;
+		let start = parse_time(start, false).map_err(|e| e.prefix(&prefix))?;
+		let end = parse_time(end, true).map_err(|e| e.prefix(&prefix))?;
+		if start == end {
+			return Err(format!("{prefix}: empty window").into());
+		}
+		Ok(RenewalWindow { days, start, end })
+	}
+}
+
+impl fmt::Display for RenewalWindow {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let days: Vec<&str> = DAY_NAMES
+			.iter()
+			.zip(self.days.iter())
+			.filter(|(_, d)| **d)
+			.map(|(n, _)| *n)
+			.collect();
+		write!(
+			f,
+			"{} {:02}:{:02}-{:02}:{:02}",
+			days.join(","),
+			self.start / 60,
+			self.start % 60,
+			self.end / 60,
+			self.end % 60
+		)
+	}
+}
+
+/// Return the number of seconds until one of the windows is open, which is zero if one of them
+/// already is. The time is a Unix timestamp shifted by the offset of the local time.
+pub fn wait_for_window(windows: &[RenewalWindow], local_time: i64) -> u64 {
+	let day_secs = i64::from(MINUTES_PER_DAY) * 60;
+	let today = local_time.div_euclid(day_secs);
+	let mut wait = None;
+	for window in windows {
+		// The previous day is included since its window may go past midnight.
+		for day in (today - 1)..=(today + 7) {
+			// The 1st of January 1970 was a Thursday.
+			let weekday = (day + 3).rem_euclid(7) as usize;
+			if !window.days[weekday] {
+				continue;
+			}
+			let start = day * day_secs + i64::from(window.start) * 60;
+			let end = start + i64::from(window.len()) * 60;
+			if start <= local_time && local_time < end {
+				return 0;
+			}
+			if start > local_time {
+				let w = (start - local_time) as u64;
+				wait = Some(wait.map_or(w, |v: u64| v.min(w)));
+			}
+		}
+	}
+	wait.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{wait_for_window, RenewalWindow};
+
+	// Monday the 2nd of March 2020 at 00:00.
+	const MONDAY: i64 = 1583107200;
+	const HOUR: i64 = 60 * 60;
+
+	#[test]
+	fn test_parse() {
+		let w: RenewalWindow = "mon-fri 02:00-05:00".parse().unwrap();
+		assert_eq!(w.to_string(), "mon,tue,wed,thu,fri 02:00-05:00");
+		let w: RenewalWindow = "Sat,sun 22:30-24:00".parse().unwrap();
+		assert_eq!(w.to_string(), "sat,sun 22:30-24:00");
+		let w: RenewalWindow = "fri-mon 23:00-01:00".parse().unwrap();
+		assert_eq!(w.to_string(), "mon,fri,sat,sun 23:00-01:00");
+		let w: RenewalWindow = "08:00-18:00".parse().unwrap();
+		assert_eq!(w.to_string(), "mon,tue,wed,thu,fri,sat,sun 08:00-18:00");
+		for invalid in [
+			"",
+			"mon",
+			"mon 02:00",
+			"mon 2:00-05:00",
+			"mon 02:00-02:00",
+			"mon 24:00-02:00",
+			"mon 02:60-05:00",
+			"monday 02:00-05:00",
+			"mon-fri 02:00-25:00",
+		] {
+			assert!(invalid.parse::<RenewalWindow>().is_err(), "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn test_wait_for_window() {
+		let weekdays: RenewalWindow = "mon-fri 02:00-05:00".parse().unwrap();
+		let windows = vec![weekdays];
+		assert_eq!(wait_for_window(&windows, MONDAY), 2 * HOUR as u64);
+		assert_eq!(wait_for_window(&windows, MONDAY + 3 * HOUR), 0);
+		assert_eq!(
+			wait_for_window(&windows, MONDAY + 5 * HOUR),
+			21 * HOUR as u64
+		);
+		// Friday at 06:00, the next window is on Monday.
+		let friday = MONDAY + 4 * 24 * HOUR;
+		assert_eq!(
+			wait_for_window(&windows, friday + 6 * HOUR),
+			(3 * 24 - 4) as u64 * HOUR as u64
+		);
+
+		let overnight: RenewalWindow = "sun 23:00-01:00".parse().unwrap();
+		let windows = vec![overnight];
+		assert_eq!(wait_for_window(&windows, MONDAY), 0);
+		assert_eq!(
+			wait_for_window(&windows, MONDAY + HOUR),
+			(6 * 24 + 22) as u64 * HOUR as u64
+		);
+		assert_eq!(wait_for_window(&[], MONDAY), 0);
+	}
+}
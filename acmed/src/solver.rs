@@ -0,0 +1,928 @@
+use crate::acme_dns::AcmeDns;
+use crate::acme_proto::Challenge;
+use crate::certificate::Certificate;
+use crate::config;
+use crate::dns_provider::{DnsProvider, ProviderType, Secret};
+use crate::dns_update::{DnsUpdate, TsigAlgorithm, TsigKey};
+use crate::endpoint::RateLimits;
+use crate::hooks::{self, ChallengeHookData, HookType};
+use crate::logs::HasLogger;
+use crate::platform::{Current, Platform};
+use acme_common::error::{Error, ErrorKind};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+type SolverRateLimits = HashMap<String, (Vec<config::RateLimit>, Arc<RateLimits>)>;
+
+/// Rate limits of the named solvers. They are shared by every certificate using the solver and
+/// are kept across configuration reloads unless they are modified.
+static RATE_LIMITS: Mutex<Option<SolverRateLimits>> = Mutex::new(None);
+
+/// Replace the rate limits by those of the given solvers, the state of the unchanged ones being
+/// kept. The rate limits of the solvers which are no longer used are dropped.
+pub fn set_rate_limits<'a>(solvers: impl Iterator<Item = &'a NamedSolver>) -> Result<(), Error> {
+	let mut registry = RATE_LIMITS.lock().unwrap_or_else(|e| e.into_inner());
+	let mut current = HashMap::new();
+	for solver in solvers {
+		if solver.rate_limits.is_empty() || current.contains_key(&solver.name) {
+			continue;
+		}
+		let previous = registry.as_ref().and_then(|r| r.get(&solver.name));
+		let rl = match previous {
+			Some((limits, rl)) if *limits == solver.rate_limits => rl.clone(),
+			_ => Arc::new(RateLimits::new(&solver.rate_limits)?),
+		};
+		current.insert(solver.name.to_owned(), (solver.rate_limits.clone(), rl));
+	}
+	*registry = Some(current);
+	Ok(())
+}
+
+fn get_rate_limits(solver_name: &str) -> Option<Arc<RateLimits>> {
+	RATE_LIMITS
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.as_ref()
+		.and_then(|r| r.get(solver_name))
+		.map(|(_, rl)| rl.clone())
+}
+
+/// A solver declared in the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedSolver {
+	pub name: String,
+	pub solver_type: SolverType,
+	pub rate_limits: Vec<config::RateLimit>,
+}
+
+impl NamedSolver {
+	pub fn get_solver(&self) -> Box<dyn Solver> {
+		let solver = self.solver_type.get_solver();
+		match get_rate_limits(&self.name) {
+			Some(limits) => Box::new(RateLimitedSolver {
+				name: self.name.to_owned(),
+				limits,
+				inner: solver,
+			}),
+			None => solver,
+		}
+	}
+}
+
+impl fmt::Display for NamedSolver {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} ({})", self.name, self.solver_type)
+	}
+}
+
+/// Built-in challenge solvers, as declared in the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SolverType {
+	AcmeDns(AcmeDns),
+	DnsProvider(DnsProvider),
+	Hooks,
+	Rfc2136(DnsUpdate),
+	StatelessHttp01,
+	Webroot(Webroot),
+}
+
+impl SolverType {
+	pub fn from_config(solver: &config::Solver) -> Result<Self, Error> {
+		let has_file_opts =
+			solver.file_group.is_some() || solver.file_mode.is_some() || solver.file_user.is_some();
+		if has_file_opts && solver.solver_type != "webroot" {
+			let msg = format!(
+				"{}: the `file_*` fields are only supported by the webroot solver",
+				solver.solver_type
+			);
+			return Err(msg.into());
+		}
+		if let Some(provider_type) = ProviderType::from_name(&solver.solver_type) {
+			let api_token = Secret::from_config(
+				"api_token",
+				solver.api_token_file.as_deref(),
+				solver.api_token_env.as_deref(),
+			)?;
+			let provider = DnsProvider::new(
+				provider_type,
+				api_token,
+				solver.access_key_id.as_deref(),
+				solver.zone.as_deref(),
+			)?;
+			return Ok(SolverType::DnsProvider(provider));
+		}
+		match solver.solver_type.as_str() {
+			"acme-dns" => match &solver.url {
+				Some(url) => Ok(SolverType::AcmeDns(AcmeDns::new(url, &solver.allow_from)?)),
+				None => Err("the acme-dns solver requires a url".into()),
+			},
+			"hooks" => Ok(SolverType::Hooks),
+			"rfc2136" => {
+				let nameserver = solver
+					.nameserver
+					.as_deref()
+					.ok_or_else(|| Error::from("the rfc2136 solver requires a nameserver"))?;
+				let key = match &solver.tsig_key_name {
+					Some(name) => {
+						let algorithm = solver
+							.tsig_algorithm
+							.as_deref()
+							.unwrap_or(crate::DEFAULT_TSIG_ALGORITHM);
+						let secret = Secret::from_config(
+							"tsig_secret",
+							solver.tsig_secret_file.as_deref(),
+							solver.tsig_secret_env.as_deref(),
+						)?;
+						Some(TsigKey {
+							name: name.to_owned(),
+							algorithm: TsigAlgorithm::from_name(algorithm)?,
+							secret,
+						})
+					}
+					None => {
+						if solver.tsig_secret_file.is_some() || solver.tsig_secret_env.is_some() {
+							return Err("the TSIG secret requires a tsig_key_name".into());
+						}
+						None
+					}
+				};
+				let update = DnsUpdate::new(nameserver, solver.zone.as_deref(), key)?;
+				Ok(SolverType::Rfc2136(update))
+			}
+			"stateless-http-01" => Ok(SolverType::StatelessHttp01),
+			"webroot" => match &solver.path {
+				Some(p) => Ok(SolverType::Webroot(Webroot::new(
+					p,
+					solver.file_mode,
+					solver.file_user.as_deref(),
+					solver.file_group.as_deref(),
+				))),
+				None => Err("the webroot solver requires a path".into()),
+			},
+			t => Err(format!("{t}: unknown solver type").into()),
+		}
+	}
+
+	pub fn supported_challenges(&self) -> Vec<Challenge> {
+		match self {
+			SolverType::AcmeDns(_) | SolverType::DnsProvider(_) | SolverType::Rfc2136(_) => {
+				vec![Challenge::Dns01]
+			}
+			SolverType::Hooks => vec![Challenge::Http01, Challenge::Dns01, Challenge::TlsAlpn01],
+			SolverType::StatelessHttp01 | SolverType::Webroot(_) => vec![Challenge::Http01],
+		}
+	}
+
+	pub fn get_solver(&self) -> Box<dyn Solver> {
+		match self {
+			SolverType::AcmeDns(acme_dns) => Box::new(AcmeDnsSolver {
+				acme_dns: acme_dns.clone(),
+			}),
+			SolverType::DnsProvider(provider) => Box::new(DnsProviderSolver {
+				provider: provider.clone(),
+			}),
+			SolverType::Hooks => Box::new(HookSolver),
+			SolverType::Rfc2136(update) => Box::new(DnsUpdateSolver {
+				update: update.clone(),
+			}),
+			SolverType::StatelessHttp01 => Box::new(StatelessHttp01Solver),
+			SolverType::Webroot(webroot) => Box::new(WebrootSolver {
+				webroot: webroot.clone(),
+			}),
+		}
+	}
+}
+
+impl fmt::Display for SolverType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SolverType::AcmeDns(_) => write!(f, "acme-dns"),
+			SolverType::DnsProvider(provider) => write!(f, "{}", provider.provider_type),
+			SolverType::Hooks => write!(f, "hooks"),
+			SolverType::Rfc2136(_) => write!(f, "rfc2136"),
+			SolverType::StatelessHttp01 => write!(f, "stateless-http-01"),
+			SolverType::Webroot(_) => write!(f, "webroot"),
+		}
+	}
+}
+
+/// A challenge solver makes the proof available to the CA.
+pub trait Solver: Send + Sync {
+	/// Make the proof available.
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>>;
+
+	/// Wait until the proof is actually available to the CA.
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>>;
+
+	/// Remove the proof once it is no longer required.
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>>;
+
+	/// Whether the proofs of several challenges are handled at once by `prepare_all` and
+	/// `cleanup_all`, instead of one after the other.
+	fn is_batching(&self) -> bool {
+		false
+	}
+
+	/// Make the proofs of several challenges available.
+	fn prepare_all<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a [ChallengeHookData],
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(prepare_each(self, cert, data))
+	}
+
+	/// Remove the proofs of several challenges, which may only have been partially prepared.
+	fn cleanup_all<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a [ChallengeHookData],
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(cleanup_each(self, cert, data))
+	}
+}
+
+async fn prepare_each<S: Solver + ?Sized>(
+	solver: &S,
+	cert: &Certificate,
+	data: &[ChallengeHookData],
+) -> Result<(), Error> {
+	for d in data {
+		solver.prepare(cert, d).await?;
+	}
+	Ok(())
+}
+
+/// Remove every proof, even if the removal of one of them fails.
+async fn cleanup_each<S: Solver + ?Sized>(
+	solver: &S,
+	cert: &Certificate,
+	data: &[ChallengeHookData],
+) -> Result<(), Error> {
+	let mut res = Ok(());
+	for d in data {
+		if let Err(e) = solver.cleanup(cert, d).await {
+			let e = e.prefix(&d.identifier);
+			if res.is_ok() {
+				res = Err(e);
+			} else {
+				cert.warn(&e.message);
+			}
+		}
+	}
+	res
+}
+
+pub fn get_hook_types(challenge: &str) -> Result<(HookType, HookType), Error> {
+	match Challenge::from_str(challenge)? {
+		Challenge::Http01 => Ok((HookType::ChallengeHttp01, HookType::ChallengeHttp01Clean)),
+		Challenge::Dns01 => Ok((HookType::ChallengeDns01, HookType::ChallengeDns01Clean)),
+		Challenge::TlsAlpn01 => Ok((
+			HookType::ChallengeTlsAlpn01,
+			HookType::ChallengeTlsAlpn01Clean,
+		)),
+	}
+}
+
+/// Wrap a solver so the calls which may reach a provider's API, which are the preparation and
+/// the cleanup, are rate limited.
+struct RateLimitedSolver {
+	name: String,
+	limits: Arc<RateLimits>,
+	inner: Box<dyn Solver>,
+}
+
+impl RateLimitedSolver {
+	async fn throttle(&self, cert: &Certificate) {
+		let start = Instant::now();
+		self.limits.block_until_ready().await;
+		let waited = start.elapsed();
+		if waited.as_millis() >= u128::from(crate::MIN_RATE_LIMIT_SLEEP_MILISEC) {
+			cert.info(&format!(
+				"solver \"{}\": rate limit reached, waited {}ms",
+				self.name,
+				waited.as_millis()
+			));
+		}
+	}
+}
+
+impl Solver for RateLimitedSolver {
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.throttle(cert).await;
+			self.inner.prepare(cert, data).await
+		})
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		self.inner.wait(cert, data)
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.throttle(cert).await;
+			self.inner.cleanup(cert, data).await
+		})
+	}
+
+	fn is_batching(&self) -> bool {
+		self.inner.is_batching()
+	}
+
+	fn prepare_all<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a [ChallengeHookData],
+	) -> BoxFuture<'a, Result<(), Error>> {
+		if !self.is_batching() {
+			return Box::pin(prepare_each(self, cert, data));
+		}
+		Box::pin(async move {
+			self.throttle(cert).await;
+			self.inner.prepare_all(cert, data).await
+		})
+	}
+
+	fn cleanup_all<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a [ChallengeHookData],
+	) -> BoxFuture<'a, Result<(), Error>> {
+		if !self.is_batching() {
+			return Box::pin(cleanup_each(self, cert, data));
+		}
+		Box::pin(async move {
+			self.throttle(cert).await;
+			self.inner.cleanup_all(cert, data).await
+		})
+	}
+}
+
+/// Publish the dns-01 proofs on an acme-dns server, then wait for the `challenge-ready` hooks to
+/// succeed, if any.
+struct AcmeDnsSolver {
+	acme_dns: AcmeDns,
+}
+
+impl Solver for AcmeDnsSolver {
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.acme_dns
+				.add_record(cert, &data.identifier, &data.proof)
+				.await
+		})
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move { cert.call_challenge_ready_hooks(data).await })
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.acme_dns.remove_record(cert, &data.identifier);
+			Ok(())
+		})
+	}
+}
+
+/// Publish the dns-01 proofs using a DNS provider's API, then wait for the
+/// `challenge-ready` hooks to succeed, if any.
+struct DnsProviderSolver {
+	provider: DnsProvider,
+}
+
+impl Solver for DnsProviderSolver {
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.provider
+				.add_records(cert, &[(data.identifier.as_str(), data.proof.as_str())])
+				.await
+		})
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move { cert.call_challenge_ready_hooks(data).await })
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.provider
+				.remove_records(cert, &[(data.identifier.as_str(), data.proof.as_str())])
+				.await
+		})
+	}
+
+	fn is_batching(&self) -> bool {
+		true
+	}
+
+	fn prepare_all<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a [ChallengeHookData],
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let records: Vec<(&str, &str)> = data
+				.iter()
+				.map(|d| (d.identifier.as_str(), d.proof.as_str()))
+				.collect();
+			self.provider.add_records(cert, &records).await
+		})
+	}
+
+	fn cleanup_all<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a [ChallengeHookData],
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let records: Vec<(&str, &str)> = data
+				.iter()
+				.map(|d| (d.identifier.as_str(), d.proof.as_str()))
+				.collect();
+			self.provider.remove_records(cert, &records).await
+		})
+	}
+}
+
+/// Publish the dns-01 proofs using dynamic updates sent to the primary nameserver, then wait for
+/// the `challenge-ready` hooks to succeed, if any.
+struct DnsUpdateSolver {
+	update: DnsUpdate,
+}
+
+impl Solver for DnsUpdateSolver {
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.update
+				.add_record(cert, &data.identifier, &data.proof)
+				.await
+		})
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move { cert.call_challenge_ready_hooks(data).await })
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			self.update
+				.remove_record(cert, &data.identifier, &data.proof)
+				.await
+		})
+	}
+}
+
+/// Solve the challenges using the certificate's hooks.
+struct HookSolver;
+
+impl Solver for HookSolver {
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let (hook_type, _) = get_hook_types(&data.challenge)?;
+			hooks::call(cert, &cert.hook_recorder, &cert.hooks, data, hook_type).await
+		})
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move { cert.call_challenge_ready_hooks(data).await })
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let (_, hook_type) = get_hook_types(&data.challenge)?;
+			let mut data = data.clone();
+			data.is_clean_hook = true;
+			hooks::call(cert, &cert.hook_recorder, &cert.hooks, &data, hook_type).await
+		})
+	}
+}
+
+/// Rely on a web server answering the http-01 challenges by itself using the account
+/// thumbprint.
+struct StatelessHttp01Solver;
+
+impl Solver for StatelessHttp01Solver {
+	fn prepare<'a>(
+		&'a self,
+		_cert: &'a Certificate,
+		_data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async { Ok(()) })
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			crate::http::check_http_01_proof(&data.identifier, &data.file_name, &data.proof, None)
+				.await
+				.map_err(|e| e.prefix(&format!("{}: stateless http-01", data.identifier)))?;
+			cert.debug(&format!(
+				"{}: stateless http-01 self-check passed",
+				data.identifier
+			));
+			Ok(())
+		})
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		_cert: &'a Certificate,
+		_data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async { Ok(()) })
+	}
+}
+
+/// Web server's document root in which the http-01 proofs are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Webroot {
+	pub path: PathBuf,
+	pub file_mode: u32,
+	pub file_user: Option<String>,
+	pub file_group: Option<String>,
+}
+
+impl Webroot {
+	pub fn new(
+		path: &str,
+		file_mode: Option<u32>,
+		file_user: Option<&str>,
+		file_group: Option<&str>,
+	) -> Self {
+		Webroot {
+			path: PathBuf::from(path),
+			file_mode: file_mode.unwrap_or(crate::DEFAULT_WEBROOT_FILE_MODE),
+			file_user: file_user.map(|u| u.to_string()),
+			file_group: file_group.map(|g| g.to_string()),
+		}
+	}
+
+	fn get_path(&self, data: &ChallengeHookData) -> PathBuf {
+		let mut path = self.get_challenge_dir();
+		path.push(&data.file_name);
+		path
+	}
+
+	fn get_challenge_dir(&self) -> PathBuf {
+		self.path.join(".well-known").join("acme-challenge")
+	}
+
+	/// Create the `.well-known` and `acme-challenge` directories if they do not exist, with the
+	/// same owner as the proofs so the web server is able to read them.
+	async fn create_dirs(&self, cert: &Certificate) -> Result<(), Error> {
+		let dirs = [self.path.join(".well-known"), self.get_challenge_dir()];
+		for dir in dirs.iter() {
+			if is_dir(dir).await {
+				continue;
+			}
+			let dir_str = dir.display().to_string();
+			cert.trace(&format!("creating directory {dir_str}"));
+			tokio::fs::create_dir(dir)
+				.await
+				.map_err(|e| Error::from(e).prefix(&dir_str))?;
+			Current::set_mode(dir, crate::DEFAULT_WEBROOT_DIR_MODE)
+				.and_then(|_| {
+					Current::set_owner(dir, self.file_user.as_deref(), self.file_group.as_deref())
+				})
+				.map_err(|e| e.prefix(&dir_str))?;
+		}
+		Ok(())
+	}
+
+	async fn write_proof(&self, cert: &Certificate, data: &ChallengeHookData) -> Result<(), Error> {
+		if !is_dir(&self.path).await {
+			let msg = format!("{}: webroot directory not found", self.path.display());
+			return Err(Error::from(msg).with_kind(ErrorKind::Config));
+		}
+		self.create_dirs(cert).await?;
+		let path = self.get_path(data);
+		let path_str = path.display().to_string();
+		cert.trace(&format!("writing file {path_str}"));
+		let mut file = OpenOptions::from(Current::file_options(self.file_mode))
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.open(&path)
+			.await
+			.map_err(|e| Error::from(e).prefix(&path_str))?;
+		file.write_all(data.proof.as_bytes())
+			.await
+			.map_err(|e| Error::from(e).prefix(&path_str))?;
+		// The mode given when opening the file is restricted by the umask.
+		Current::set_mode(&path, self.file_mode)
+			.and_then(|_| {
+				Current::set_owner(&path, self.file_user.as_deref(), self.file_group.as_deref())
+			})
+			.map_err(|e| e.prefix(&path_str))
+	}
+}
+
+async fn is_dir(path: &Path) -> bool {
+	tokio::fs::metadata(path)
+		.await
+		.map(|m| m.is_dir())
+		.unwrap_or(false)
+}
+
+/// Write the http-01 proofs in the `.well-known/acme-challenge` directory of a web server's
+/// document root.
+struct WebrootSolver {
+	webroot: Webroot,
+}
+
+impl Solver for WebrootSolver {
+	fn prepare<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move { self.webroot.write_proof(cert, data).await })
+	}
+
+	fn wait<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move { cert.call_challenge_ready_hooks(data).await })
+	}
+
+	fn cleanup<'a>(
+		&'a self,
+		cert: &'a Certificate,
+		data: &'a ChallengeHookData,
+	) -> BoxFuture<'a, Result<(), Error>> {
+		Box::pin(async move {
+			let path = self.webroot.get_path(data);
+			let path_str = path.display().to_string();
+			cert.trace(&format!("removing file {path_str}"));
+			tokio::fs::remove_file(&path)
+				.await
+				.map_err(|e| Error::from(e).prefix(&path_str))
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_rate_limits, set_rate_limits, NamedSolver, SolverType, Webroot};
+	use crate::acme_proto::Challenge;
+	use crate::config::{RateLimit, Solver};
+	use crate::dns_provider::ProviderType;
+	use crate::dns_update::TsigAlgorithm;
+	use std::num::NonZeroU32;
+	use std::path::PathBuf;
+	use std::sync::Arc;
+
+	fn solver(solver_type: &str) -> Solver {
+		Solver {
+			name: "test".to_string(),
+			solver_type: solver_type.to_string(),
+			access_key_id: None,
+			allow_from: vec![],
+			api_token_env: None,
+			api_token_file: None,
+			file_group: None,
+			file_mode: None,
+			file_user: None,
+			nameserver: None,
+			path: None,
+			rate_limits: vec![],
+			tsig_algorithm: None,
+			tsig_key_name: None,
+			tsig_secret_env: None,
+			tsig_secret_file: None,
+			url: None,
+			zone: None,
+		}
+	}
+
+	#[test]
+	fn test_solver_type() {
+		assert_eq!(
+			SolverType::from_config(&solver("hooks")).unwrap(),
+			SolverType::Hooks
+		);
+		let mut webroot = solver("webroot");
+		assert!(SolverType::from_config(&webroot).is_err());
+		webroot.path = Some("/var/www".to_string());
+		assert_eq!(
+			SolverType::from_config(&webroot).unwrap(),
+			SolverType::Webroot(Webroot {
+				path: PathBuf::from("/var/www"),
+				file_mode: crate::DEFAULT_WEBROOT_FILE_MODE,
+				file_user: None,
+				file_group: None,
+			})
+		);
+		webroot.file_mode = Some(0o640);
+		webroot.file_group = Some("www-data".to_string());
+		match SolverType::from_config(&webroot).unwrap() {
+			SolverType::Webroot(w) => {
+				assert_eq!(w.file_mode, 0o640);
+				assert_eq!(w.file_group.as_deref(), Some("www-data"));
+			}
+			t => panic!("unexpected solver type: {}", t),
+		}
+		let mut hooks = solver("hooks");
+		hooks.file_mode = Some(0o640);
+		assert!(SolverType::from_config(&hooks).is_err());
+		assert!(SolverType::from_config(&solver("unknown")).is_err());
+		assert!(!SolverType::StatelessHttp01
+			.supported_challenges()
+			.contains(&Challenge::Dns01));
+	}
+
+	#[test]
+	fn test_dns_provider_solver_type() {
+		let mut cloudflare = solver("cloudflare");
+		assert!(SolverType::from_config(&cloudflare).is_err());
+		cloudflare.api_token_env = Some("CF_API_TOKEN".to_string());
+		cloudflare.zone = Some("Example.org.".to_string());
+		match SolverType::from_config(&cloudflare).unwrap() {
+			SolverType::DnsProvider(p) => {
+				assert_eq!(p.provider_type, ProviderType::Cloudflare);
+				assert_eq!(p.zone.as_deref(), Some("example.org"));
+			}
+			t => panic!("unexpected solver type: {}", t),
+		}
+		cloudflare.api_token_file = Some("/etc/acmed/cf_token".to_string());
+		assert!(SolverType::from_config(&cloudflare).is_err());
+		let mut route53 = solver("route53");
+		route53.api_token_env = Some("AWS_SECRET_ACCESS_KEY".to_string());
+		assert!(SolverType::from_config(&route53).is_err());
+		route53.access_key_id = Some("AKIDEXAMPLE".to_string());
+		route53.zone = Some("Z1D633PJN98FT9".to_string());
+		let route53 = SolverType::from_config(&route53).unwrap();
+		assert_eq!(route53.supported_challenges(), vec![Challenge::Dns01]);
+		assert_eq!(route53.to_string(), "route53");
+	}
+
+	#[test]
+	fn test_acme_dns_solver_type() {
+		let mut acme_dns = solver("acme-dns");
+		assert!(SolverType::from_config(&acme_dns).is_err());
+		acme_dns.url = Some("https://auth.example.org".to_string());
+		acme_dns.allow_from = vec!["192.0.2.0/24".to_string()];
+		match SolverType::from_config(&acme_dns).unwrap() {
+			SolverType::AcmeDns(a) => assert_eq!(a.allow_from, vec!["192.0.2.0/24"]),
+			t => panic!("unexpected solver type: {}", t),
+		}
+		acme_dns.url = Some("auth.example.org".to_string());
+		assert!(SolverType::from_config(&acme_dns).is_err());
+	}
+
+	#[test]
+	fn test_rfc2136_solver_type() {
+		let mut rfc2136 = solver("rfc2136");
+		assert!(SolverType::from_config(&rfc2136).is_err());
+		rfc2136.nameserver = Some("192.0.2.53".to_string());
+		match SolverType::from_config(&rfc2136).unwrap() {
+			SolverType::Rfc2136(u) => {
+				assert_eq!(u.nameserver.to_string(), "192.0.2.53:53");
+				assert!(u.key.is_none());
+			}
+			t => panic!("unexpected solver type: {}", t),
+		}
+		rfc2136.nameserver = Some("[2001:db8::53]:5353".to_string());
+		rfc2136.tsig_key_name = Some("acmed".to_string());
+		assert!(SolverType::from_config(&rfc2136).is_err());
+		rfc2136.tsig_secret_file = Some("/etc/acmed/tsig.key".to_string());
+		rfc2136.tsig_algorithm = Some("hmac-sha512".to_string());
+		match SolverType::from_config(&rfc2136).unwrap() {
+			SolverType::Rfc2136(u) => {
+				assert_eq!(u.nameserver.to_string(), "[2001:db8::53]:5353");
+				assert_eq!(u.key.unwrap().algorithm, TsigAlgorithm::HmacSha512);
+			}
+			t => panic!("unexpected solver type: {}", t),
+		}
+		rfc2136.tsig_algorithm = Some("hmac-md5".to_string());
+		assert!(SolverType::from_config(&rfc2136).is_err());
+		rfc2136.tsig_algorithm = None;
+		rfc2136.tsig_key_name = None;
+		assert!(SolverType::from_config(&rfc2136).is_err());
+	}
+
+	#[test]
+	fn test_rate_limits_reload() {
+		let mut limit = RateLimit {
+			name: "dns-api".to_string(),
+			number: NonZeroU32::new(10).unwrap(),
+			period: "1m".to_string(),
+			acme_resources: vec![],
+			path: None,
+		};
+		let named = |name: &str, rate_limits: &[RateLimit]| NamedSolver {
+			name: name.to_string(),
+			solver_type: SolverType::Hooks,
+			rate_limits: rate_limits.to_vec(),
+		};
+		let unlimited = named("test-unlimited", &[]);
+		let other = named("test-other", &[limit.clone()]);
+		set_rate_limits([named("test-reload", &[limit.clone()]), other.clone()].iter()).unwrap();
+		let first = get_rate_limits("test-reload").unwrap();
+		let first_other = get_rate_limits("test-other").unwrap();
+		assert!(!Arc::ptr_eq(&first, &first_other));
+		assert!(get_rate_limits("test-unlimited").is_none());
+		limit.number = NonZeroU32::new(5).unwrap();
+		set_rate_limits([named("test-reload", &[limit]), other.clone(), unlimited].iter()).unwrap();
+		assert!(!Arc::ptr_eq(
+			&first,
+			&get_rate_limits("test-reload").unwrap()
+		));
+		assert!(Arc::ptr_eq(
+			&first_other,
+			&get_rate_limits("test-other").unwrap()
+		));
+		assert!(get_rate_limits("test-unlimited").is_none());
+		set_rate_limits([other].iter()).unwrap();
+		assert!(get_rate_limits("test-reload").is_none());
+		assert!(Arc::ptr_eq(
+			&first_other,
+			&get_rate_limits("test-other").unwrap()
+		));
+	}
+}
@@ -1,20 +1,166 @@
-use crate::hooks::{self, FileStorageHookData, Hook, HookEnvData, HookType};
-use crate::logs::HasLogger;
+use crate::events;
+use crate::hooks::{
+	self, CertificateDetails, FileStorageHookData, Hook, HookEnvData, HookRecorder, HookType,
+};
+use crate::logs::{HasLogger, LogEvent};
+use crate::platform::{Current, Platform};
+use crate::privileges;
 use crate::template::render_template;
 use acme_common::b64_encode;
-use acme_common::crypto::{KeyPair, X509Certificate};
+use acme_common::crypto::{
+	convert_private_key, KeyEncoding, KeyPair, PrivateKeyFormat, X509Certificate,
+};
 use acme_common::error::Error;
-use serde::Serialize;
-use std::collections::HashMap;
+use acme_common::now;
+use async_lock::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-#[derive(Clone, Debug)]
+/// Version of the layout of the certificates directory, which is recorded in its manifest. Any
+/// change to the layout must increment it and explicitly migrate the files described by the
+/// manifests of the previous versions.
+const MANIFEST_VERSION: u32 = 1;
+const MANIFEST_FILE_NAME: &str = "acmed-manifest.json";
+
+/// The certificates sharing a directory also share its manifest.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Whether or not the storage has been found to be on a read-only file system, in which case the
+/// daemon runs in a degraded mode where no certificate is renewed.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn is_read_only() -> bool {
+	READ_ONLY.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+fn is_read_only_error(e: &std::io::Error) -> bool {
+	e.raw_os_error() == Some(libc::EROFS)
+}
+
+/// ERROR_WRITE_PROTECT, returned when the media is write-protected.
+#[cfg(windows)]
+fn is_read_only_error(e: &std::io::Error) -> bool {
+	e.raw_os_error() == Some(19)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_read_only_error(_e: &std::io::Error) -> bool {
+	false
+}
+
+/// Enter the degraded mode, which is reported by the logs, the events and the metrics.
+fn set_read_only(path: &Path) {
+	if !READ_ONLY.swap(true, Ordering::Relaxed) {
+		LogEvent::StorageReadOnly.log(|| {
+			log::error!(
+				"{}: read-only file system, entering degraded mode: no certificate will be renewed until the storage is writable",
+				path.display()
+			)
+		});
+		events::emit("storage_read_only", None, json!({ "path": path }));
+	}
+}
+
+fn io_error(e: std::io::Error, path: &Path) -> Error {
+	if is_read_only_error(&e) {
+		set_read_only(path);
+	}
+	Error::from(e).prefix(&path.display().to_string())
+}
+
+/// Check whether or not the directories are on a writable file system and update the degraded
+/// mode accordingly. Errors other than a read-only file system are left to be reported when the
+/// files are actually written.
+pub async fn check_writable(directories: &[&str]) -> bool {
+	for dir in directories.iter() {
+		let probe = Path::new(dir).join(format!(".acmed-probe-{}", std::process::id()));
+		match OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(&probe)
+			.await
+		{
+			Ok(_) => {
+				let _ = tokio::fs::remove_file(&probe).await;
+			}
+			Err(e) if is_read_only_error(&e) => {
+				set_read_only(Path::new(dir));
+				return false;
+			}
+			Err(_) => {}
+		}
+	}
+	if READ_ONLY.swap(false, Ordering::Relaxed) {
+		log::info!("the storage is writable again, leaving degraded mode");
+		events::emit("storage_writable", None, json!(null));
+	}
+	true
+}
+
+/// Check the directory exists and files can be created in it by the given user and group, or
+/// the current ones.
+pub fn check_directory(dir: &str, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+	let path = Path::new(dir);
+	if !path.is_dir() {
+		return Err(format!("{dir}: not a directory").into());
+	}
+	privileges::check_writable_dir(path, uid, gid)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Passphrase {
+	File(String),
+	Env(String),
+}
+
+impl Passphrase {
+	async fn get(&self) -> Result<Vec<u8>, Error> {
+		let passphrase = match self {
+			Passphrase::File(path) => {
+				let mut pass = tokio::fs::read(path)
+					.await
+					.map_err(|e| Error::from(e).prefix(path))?;
+				while matches!(pass.last(), Some(b'\n') | Some(b'\r')) {
+					pass.pop();
+				}
+				pass
+			}
+			Passphrase::Env(name) => std::env::var_os(name)
+				.map(|v| v.to_string_lossy().as_bytes().to_vec())
+				.unwrap_or_default(),
+		};
+		if passphrase.is_empty() {
+			let src = match self {
+				Passphrase::File(path) => path,
+				Passphrase::Env(name) => name,
+			};
+			return Err(format!("{src}: empty private key passphrase").into());
+		}
+		Ok(passphrase)
+	}
+}
+
+/// Encryption of the certificate's private key. Unless the unencrypted copy is kept, the
+/// encrypted file is the only one to be written and it is decrypted when the key is reused.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PkEncryption {
+	pub passphrase: Passphrase,
+	pub keep_unencrypted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct FileManager {
 	pub account_name: String,
 	pub account_directory: String,
+	pub endpoint_name: String,
 	pub crt_name: String,
 	pub crt_name_format: String,
 	pub crt_directory: String,
@@ -25,8 +171,31 @@ pub struct FileManager {
 	pub pk_file_mode: u32,
 	pub pk_file_owner: Option<String>,
 	pub pk_file_group: Option<String>,
-	pub hooks: Vec<Hook>,
+	pub pk_encryption: Option<PkEncryption>,
+	pub pk_format: PrivateKeyFormat,
+	pub pk_encoding: KeyEncoding,
+	pub hooks: Vec<Arc<Hook>>,
 	pub env: HashMap<String, String>,
+	pub labels: BTreeMap<String, String>,
+}
+
+impl FileManager {
+	/// Id of the certificate the files belong to, if any.
+	pub fn get_certificate_id(&self) -> Option<String> {
+		if self.crt_name.is_empty() {
+			None
+		} else {
+			Some(format!("{}_{}", self.crt_name, self.crt_key_type))
+		}
+	}
+
+	pub fn directories(&self) -> Vec<&str> {
+		[&self.account_directory, &self.crt_directory]
+			.iter()
+			.filter(|d| !d.is_empty())
+			.map(|d| d.as_str())
+			.collect()
+	}
 }
 
 impl HasLogger for FileManager {
@@ -62,7 +231,9 @@ impl fmt::Display for FileManager {
 enum FileType {
 	Account,
 	PrivateKey,
+	EncryptedPrivateKey,
 	Certificate,
+	OcspResponse,
 }
 
 impl fmt::Display for FileType {
@@ -70,7 +241,9 @@ impl fmt::Display for FileType {
 		let s = match self {
 			FileType::Account => "account",
 			FileType::PrivateKey => "pk",
+			FileType::EncryptedPrivateKey => "pk-enc",
 			FileType::Certificate => "crt",
+			FileType::OcspResponse => "ocsp",
 		};
 		write!(f, "{s}")
 	}
@@ -91,7 +264,9 @@ fn get_file_full_path(
 	let base_path = match file_type {
 		FileType::Account => &fm.account_directory,
 		FileType::PrivateKey => &fm.crt_directory,
+		FileType::EncryptedPrivateKey => &fm.crt_directory,
 		FileType::Certificate => &fm.crt_directory,
+		FileType::OcspResponse => &fm.crt_directory,
 	};
 	let file_name = match file_type {
 		FileType::Account => format!(
@@ -100,10 +275,18 @@ fn get_file_full_path(
 			file_type = file_type,
 			ext = "bin"
 		),
-		FileType::PrivateKey | FileType::Certificate => {
+		FileType::PrivateKey
+		| FileType::EncryptedPrivateKey
+		| FileType::Certificate
+		| FileType::OcspResponse => {
+			let ext = match file_type {
+				FileType::OcspResponse => KeyEncoding::Der.to_string(),
+				FileType::PrivateKey => fm.pk_encoding.to_string(),
+				_ => KeyEncoding::Pem.to_string(),
+			};
 			let fmt_data = CertFileFormat {
 				key_type: fm.crt_key_type.to_string(),
-				ext: "pem".into(),
+				ext,
 				file_type: file_type.to_string(),
 				name: fm.crt_name.to_owned(),
 			};
@@ -130,107 +313,328 @@ async fn read_file(fm: &FileManager, path: &Path) -> Result<Vec<u8>, Error> {
 	Ok(contents)
 }
 
-#[cfg(unix)]
 fn set_owner(fm: &FileManager, path: &Path, file_type: FileType) -> Result<(), Error> {
-	let (uid, gid) = match file_type {
-		FileType::Certificate => (fm.cert_file_owner.to_owned(), fm.cert_file_group.to_owned()),
-		FileType::PrivateKey => (fm.pk_file_owner.to_owned(), fm.pk_file_group.to_owned()),
+	let (user, group) = match file_type {
+		FileType::Certificate | FileType::OcspResponse => {
+			(&fm.cert_file_owner, &fm.cert_file_group)
+		}
+		FileType::PrivateKey | FileType::EncryptedPrivateKey => {
+			(&fm.pk_file_owner, &fm.pk_file_group)
+		}
 		FileType::Account => {
 			// The account file does not need to be accessible to users other different from the current one.
 			return Ok(());
 		}
 	};
-	let uid = match uid {
-		Some(u) => {
-			if u.bytes().all(|b| b.is_ascii_digit()) {
-				let raw_uid = u
-					.parse::<u32>()
-					.map_err(|_| Error::from("unable to parse the UID"))?;
-				let nix_uid = nix::unistd::Uid::from_raw(raw_uid);
-				Some(nix_uid)
-			} else {
-				let user = nix::unistd::User::from_name(&u)?;
-				user.map(|u| u.uid)
-			}
-		}
-		None => None,
+	match user {
+		Some(u) => fm.trace(&format!("{path:?}: setting the user to {u}")),
+		None => fm.trace(&format!("{path:?}: user unchanged")),
 	};
-	let gid = match gid {
-		Some(g) => {
-			if g.bytes().all(|b| b.is_ascii_digit()) {
-				let raw_gid = g
-					.parse::<u32>()
-					.map_err(|_| Error::from("unable to parse the GID"))?;
-				let nix_gid = nix::unistd::Gid::from_raw(raw_gid);
-				Some(nix_gid)
-			} else {
-				let grp = nix::unistd::Group::from_name(&g)?;
-				grp.map(|g| g.gid)
-			}
-		}
-		None => None,
-	};
-	match uid {
-		Some(u) => fm.trace(&format!("{path:?}: setting the uid to {}", u.as_raw())),
-		None => fm.trace(&format!("{path:?}: uid unchanged")),
-	};
-	match gid {
-		Some(g) => fm.trace(&format!("{path:?}: setting the gid to {}", g.as_raw())),
-		None => fm.trace(&format!("{path:?}: gid unchanged")),
+	match group {
+		Some(g) => fm.trace(&format!("{path:?}: setting the group to {g}")),
+		None => fm.trace(&format!("{path:?}: group unchanged")),
 	};
-	match nix::unistd::chown(path, uid, gid) {
-		Ok(_) => Ok(()),
-		Err(e) => Err(format!("{e}").into()),
-	}
+	Current::set_owner(path, user.as_deref(), group.as_deref())
 }
 
 async fn write_file(fm: &FileManager, file_type: FileType, data: &[u8]) -> Result<(), Error> {
 	let (file_directory, file_name, path) = get_file_full_path(fm, file_type.clone())?;
+	let certificate = match file_type {
+		FileType::Certificate => {
+			match X509Certificate::from_pem_chain(data)
+				.and_then(|chain| CertificateDetails::from_chain(&chain))
+			{
+				Ok(details) => details,
+				Err(e) => {
+					fm.warn(&e.prefix("unable to read the certificate's details").message);
+					CertificateDetails::default()
+				}
+			}
+		}
+		_ => CertificateDetails::default(),
+	};
 	let mut hook_data = FileStorageHookData {
 		file_name,
 		file_directory,
 		file_path: path.to_owned(),
+		endpoint_name: fm.endpoint_name.to_owned(),
+		key_type: fm.crt_key_type.to_owned(),
+		certificate,
+		labels: fm.labels.clone(),
 		env: HashMap::new(),
 	};
 	hook_data.set_env(&fm.env);
 	let is_new = !path.is_file();
+	let recorder = HookRecorder::new(fm.get_certificate_id());
 
 	if is_new {
-		hooks::call(fm, &fm.hooks, &hook_data, HookType::FilePreCreate).await?;
+		hooks::call(
+			fm,
+			&recorder,
+			&fm.hooks,
+			&hook_data,
+			HookType::FilePreCreate,
+		)
+		.await?;
 	} else {
-		hooks::call(fm, &fm.hooks, &hook_data, HookType::FilePreEdit).await?;
+		hooks::call(fm, &recorder, &fm.hooks, &hook_data, HookType::FilePreEdit).await?;
 	}
 
 	fm.trace(&format!("writing file {path:?}"));
-	let mut file = if cfg!(unix) {
-		let mut options = OpenOptions::new();
-		options.mode(match &file_type {
-			FileType::Certificate => fm.cert_file_mode,
-			FileType::PrivateKey => fm.pk_file_mode,
-			FileType::Account => crate::DEFAULT_ACCOUNT_FILE_MODE,
-		});
-		options
-			.write(true)
-			.create(true)
-			.open(&path)
-			.await
-			.map_err(|e| Error::from(e).prefix(&path.display().to_string()))?
-	} else {
-		File::create(&path)
-			.await
-			.map_err(|e| Error::from(e).prefix(&path.display().to_string()))?
+	let mode = match &file_type {
+		FileType::Certificate | FileType::OcspResponse => fm.cert_file_mode,
+		FileType::PrivateKey | FileType::EncryptedPrivateKey => fm.pk_file_mode,
+		FileType::Account => crate::DEFAULT_ACCOUNT_FILE_MODE,
 	};
-	file.write_all(data)
+	let mut options = OpenOptions::from(Current::file_options(mode));
+	let mut file = options
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(&path)
 		.await
+		.map_err(|e| io_error(e, &path))?;
+	file.write_all(data).await.map_err(|e| io_error(e, &path))?;
+	let file_type_name = file_type.to_string();
+	set_owner(fm, &path, file_type).map_err(|e| e.prefix(&path.display().to_string()))?;
+	events::emit(
+		"file_written",
+		fm.get_certificate_id(),
+		json!({
+			"file_type": file_type_name,
+			"path": path,
+		}),
+	);
+
+	if is_new {
+		hooks::call(
+			fm,
+			&recorder,
+			&fm.hooks,
+			&hook_data,
+			HookType::FilePostCreate,
+		)
+		.await?;
+	} else {
+		hooks::call(fm, &recorder, &fm.hooks, &hook_data, HookType::FilePostEdit).await?;
+	}
+	Ok(())
+}
+
+/// Description of the files stored in a certificates directory.
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+	version: u32,
+	#[serde(default)]
+	certificates: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct ManifestEntry {
+	/// Names of the files, relative to the directory, indexed by their type.
+	files: BTreeMap<String, String>,
+	key_type: String,
+	pk_encrypted: bool,
+	pk_format: String,
+	serial_number: Option<String>,
+	not_after: Option<u64>,
+	updated: u64,
+}
+
+fn get_manifest_path(fm: &FileManager) -> PathBuf {
+	Path::new(&fm.crt_directory).join(MANIFEST_FILE_NAME)
+}
+
+/// Return the types of the files of a certificate, which are described by the manifest.
+fn get_certificate_file_types(fm: &FileManager) -> [FileType; 3] {
+	[
+		get_keypair_file_type(fm),
+		FileType::Certificate,
+		FileType::OcspResponse,
+	]
+}
+
+/// Check the templates of the file hooks against the data of each file managed by the file
+/// manager.
+pub fn check_hooks(fm: &FileManager) -> Result<(), Error> {
+	let file_types = if fm.crt_name.is_empty() {
+		vec![FileType::Account]
+	} else {
+		get_certificate_file_types(fm).to_vec()
+	};
+	for file_type in file_types {
+		let certificate = match file_type {
+			FileType::Certificate => CertificateDetails::sample(vec![fm.crt_name.to_owned()]),
+			_ => CertificateDetails::default(),
+		};
+		let (file_directory, file_name, path) = get_file_full_path(fm, file_type)?;
+		let mut hook_data = FileStorageHookData {
+			file_name,
+			file_directory,
+			file_path: path,
+			endpoint_name: fm.endpoint_name.to_owned(),
+			key_type: fm.crt_key_type.to_owned(),
+			labels: fm.labels.clone(),
+			certificate,
+			env: HashMap::new(),
+		};
+		hook_data.set_env(&fm.env);
+		for hook_type in [
+			HookType::FilePreCreate,
+			HookType::FilePostCreate,
+			HookType::FilePreEdit,
+			HookType::FilePostEdit,
+		] {
+			hooks::check(&fm.hooks, &hook_data, hook_type)?;
+		}
+	}
+	Ok(())
+}
+
+async fn read_manifest(fm: &FileManager) -> Result<Option<Manifest>, Error> {
+	let path = get_manifest_path(fm);
+	if !path.is_file() {
+		return Ok(None);
+	}
+	let data = read_file(fm, &path).await?;
+	let manifest: Manifest = serde_json::from_slice(&data)
 		.map_err(|e| Error::from(e).prefix(&path.display().to_string()))?;
-	if cfg!(unix) {
-		set_owner(fm, &path, file_type).map_err(|e| e.prefix(&path.display().to_string()))?;
+	if manifest.version > MANIFEST_VERSION {
+		let msg = format!(
+			"{}: the storage layout version {} is not supported, the latest supported version being {MANIFEST_VERSION}",
+			path.display(),
+			manifest.version
+		);
+		return Err(msg.into());
 	}
+	Ok(Some(manifest))
+}
 
-	if is_new {
-		hooks::call(fm, &fm.hooks, &hook_data, HookType::FilePostCreate).await?;
+/// Replace the manifest at once, so it is never read partially written.
+async fn write_manifest(fm: &FileManager, manifest: &Manifest) -> Result<(), Error> {
+	let path = get_manifest_path(fm);
+	let tmp_path = path.with_extension("json.tmp");
+	let data = serde_json::to_vec_pretty(manifest)?;
+	fm.trace(&format!("writing file {path:?}"));
+	let mut options = OpenOptions::from(Current::file_options(fm.cert_file_mode));
+	let mut file = options
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(&tmp_path)
+		.await
+		.map_err(|e| io_error(e, &tmp_path))?;
+	file.write_all(&data)
+		.await
+		.map_err(|e| io_error(e, &tmp_path))?;
+	set_owner(fm, &tmp_path, FileType::Certificate)
+		.map_err(|e| e.prefix(&tmp_path.display().to_string()))?;
+	tokio::fs::rename(&tmp_path, &path)
+		.await
+		.map_err(|e| io_error(e, &path))
+}
+
+async fn get_manifest_entry(fm: &FileManager) -> Result<ManifestEntry, Error> {
+	let mut files = BTreeMap::new();
+	for file_type in get_certificate_file_types(fm) {
+		let (_, file_name, path) = get_file_full_path(fm, file_type.clone())?;
+		if path.is_file() {
+			files.insert(file_type.to_string(), file_name);
+		}
+	}
+	let crt = if files.contains_key(&FileType::Certificate.to_string()) {
+		get_certificate(fm).await.ok()
 	} else {
-		hooks::call(fm, &fm.hooks, &hook_data, HookType::FilePostEdit).await?;
+		None
+	};
+	Ok(ManifestEntry {
+		files,
+		key_type: fm.crt_key_type.to_owned(),
+		pk_encrypted: fm.pk_encryption.is_some(),
+		pk_format: fm.pk_format.to_string(),
+		serial_number: crt.as_ref().and_then(|c| c.serial_number().ok()),
+		not_after: crt.as_ref().and_then(|c| c.not_after().ok()),
+		updated: now(),
+	})
+}
+
+/// Record the certificate's files in the manifest of its directory.
+async fn update_manifest(fm: &FileManager) -> Result<(), Error> {
+	let id = match fm.get_certificate_id() {
+		Some(id) => id,
+		None => return Ok(()),
+	};
+	let _lock = MANIFEST_LOCK.lock().await;
+	let mut manifest = read_manifest(fm).await?.unwrap_or(Manifest {
+		version: MANIFEST_VERSION,
+		certificates: BTreeMap::new(),
+	});
+	manifest
+		.certificates
+		.insert(id, get_manifest_entry(fm).await?);
+	write_manifest(fm, &manifest).await
+}
+
+/// Check the certificate's files against the manifest of its directory. The files whose name
+/// changed, for example because of a new `file_name_format`, are renamed, and the private key is
+/// converted in case its encoding changed. A layout written by a
+/// newer version is an error, since the files could not be found or would be overwritten.
+pub async fn check_manifest(fm: &FileManager) -> Result<(), Error> {
+	let id = match fm.get_certificate_id() {
+		Some(id) => id,
+		None => return Ok(()),
+	};
+	let _lock = MANIFEST_LOCK.lock().await;
+	let mut manifest = match read_manifest(fm).await? {
+		Some(m) => m,
+		None => Manifest {
+			version: MANIFEST_VERSION,
+			certificates: BTreeMap::new(),
+		},
+	};
+	if let Some(entry) = manifest.certificates.get(&id) {
+		for file_type in get_certificate_file_types(fm) {
+			let old_name = match entry.files.get(&file_type.to_string()) {
+				Some(n) => n,
+				None => continue,
+			};
+			let is_key = matches!(file_type, FileType::PrivateKey);
+			let (_, new_name, new_path) = get_file_full_path(fm, file_type)?;
+			let old_path = Path::new(&fm.crt_directory).join(old_name);
+			if *old_name == new_name || !old_path.is_file() || new_path.exists() {
+				continue;
+			}
+			if let Some(parent) = new_path.parent() {
+				tokio::fs::create_dir_all(parent)
+					.await
+					.map_err(|e| io_error(e, parent))?;
+			}
+			if is_key {
+				// The key's encoding may have changed along with its extension.
+				fm.info(&format!("converting {old_path:?} to {new_path:?}"));
+				let data = read_file(fm, &old_path).await?;
+				let data = convert_private_key(&data, fm.pk_format, fm.pk_encoding)
+					.map_err(|e| e.prefix(&old_path.display().to_string()))?;
+				write_file(fm, FileType::PrivateKey, &data).await?;
+				tokio::fs::remove_file(&old_path)
+					.await
+					.map_err(|e| io_error(e, &old_path))?;
+				continue;
+			}
+			fm.info(&format!("renaming {old_path:?} to {new_path:?}"));
+			tokio::fs::rename(&old_path, &new_path)
+				.await
+				.map_err(|e| io_error(e, &new_path))?;
+		}
+	} else if !certificate_files_exists(fm) {
+		return Ok(());
+	}
+	let entry = get_manifest_entry(fm).await?;
+	if manifest.certificates.get(&id).map(|e| &e.files) != Some(&entry.files) {
+		manifest.certificates.insert(id, entry);
+		if let Err(e) = write_manifest(fm, &manifest).await {
+			fm.warn(&e.prefix("unable to update the manifest").message);
+		}
 	}
 	Ok(())
 }
@@ -244,19 +648,52 @@ pub async fn set_account_data(fm: &FileManager, data: &[u8]) -> Result<(), Error
 	write_file(fm, FileType::Account, data).await
 }
 
+/// Return the type of the file the private key is read from.
+fn get_keypair_file_type(fm: &FileManager) -> FileType {
+	match &fm.pk_encryption {
+		Some(enc) if !enc.keep_unencrypted => FileType::EncryptedPrivateKey,
+		_ => FileType::PrivateKey,
+	}
+}
+
 pub async fn get_keypair_path(fm: &FileManager) -> Result<PathBuf, Error> {
-	get_file_path(fm, FileType::PrivateKey)
+	get_file_path(fm, get_keypair_file_type(fm))
 }
 
 pub async fn get_keypair(fm: &FileManager) -> Result<KeyPair, Error> {
 	let path = get_keypair_path(fm).await?;
 	let raw_key = read_file(fm, &path).await?;
-	let key = KeyPair::from_pem(&raw_key)?;
+	let key = match &fm.pk_encryption {
+		Some(enc) if !enc.keep_unencrypted => {
+			KeyPair::from_encrypted_pem(&raw_key, &enc.passphrase.get().await?)
+				.map_err(|e| e.prefix(&path.display().to_string()))?
+		}
+		_ => match fm.pk_encoding {
+			KeyEncoding::Pem => KeyPair::from_pem(&raw_key)?,
+			KeyEncoding::Der => KeyPair::from_der(&raw_key)?,
+		},
+	};
 	Ok(key)
 }
 
 pub async fn set_keypair(fm: &FileManager, key_pair: &KeyPair) -> Result<(), Error> {
-	let data = key_pair.private_key_to_pem()?;
+	if let Some(enc) = &fm.pk_encryption {
+		let passphrase = enc.passphrase.get().await?;
+		let data = key_pair.private_key_to_encrypted_pem(fm.pk_format, &passphrase)?;
+		write_file(fm, FileType::EncryptedPrivateKey, &data).await?;
+		if !enc.keep_unencrypted {
+			// Do not leave behind an unencrypted key written before the encryption was enabled.
+			let path = get_file_path(fm, FileType::PrivateKey)?;
+			if path.is_file() {
+				fm.debug(&format!("removing the unencrypted private key {path:?}"));
+				tokio::fs::remove_file(&path)
+					.await
+					.map_err(|e| io_error(e, &path))?;
+			}
+			return Ok(());
+		}
+	}
+	let data = key_pair.private_key_to_format(fm.pk_format, fm.pk_encoding)?;
 	write_file(fm, FileType::PrivateKey, &data).await
 }
 
@@ -271,8 +708,39 @@ pub async fn get_certificate(fm: &FileManager) -> Result<X509Certificate, Error>
 	Ok(crt)
 }
 
+pub async fn get_certificate_chain(fm: &FileManager) -> Result<Vec<X509Certificate>, Error> {
+	let raw_crt = get_certificate_data(fm).await?;
+	X509Certificate::from_pem_chain(&raw_crt)
+}
+
+/// Return the PEM-encoded certificate chain as stored.
+pub async fn get_certificate_data(fm: &FileManager) -> Result<Vec<u8>, Error> {
+	let path = get_certificate_path(fm).await?;
+	read_file(fm, &path).await
+}
+
 pub async fn write_certificate(fm: &FileManager, data: &[u8]) -> Result<(), Error> {
-	write_file(fm, FileType::Certificate, data).await
+	write_file(fm, FileType::Certificate, data).await?;
+	if let Err(e) = update_manifest(fm).await {
+		fm.warn(&e.prefix("unable to update the manifest").message);
+	}
+	Ok(())
+}
+
+pub async fn get_ocsp_response(fm: &FileManager) -> Result<Option<Vec<u8>>, Error> {
+	let path = get_file_path(fm, FileType::OcspResponse)?;
+	if !path.is_file() {
+		return Ok(None);
+	}
+	Ok(Some(read_file(fm, &path).await?))
+}
+
+pub async fn write_ocsp_response(fm: &FileManager, data: &[u8]) -> Result<(), Error> {
+	write_file(fm, FileType::OcspResponse, data).await?;
+	if let Err(e) = update_manifest(fm).await {
+		fm.warn(&e.prefix("unable to update the manifest").message);
+	}
+	Ok(())
 }
 
 fn check_files(fm: &FileManager, file_types: &[FileType]) -> bool {
@@ -300,6 +768,307 @@ pub fn account_files_exists(fm: &FileManager) -> bool {
 }
 
 pub fn certificate_files_exists(fm: &FileManager) -> bool {
-	let file_types = vec![FileType::PrivateKey, FileType::Certificate];
+	let file_types = vec![get_keypair_file_type(fm), FileType::Certificate];
 	check_files(fm, &file_types)
 }
+
+/// Temporary directory removed once the test is over, even on failure.
+#[cfg(test)]
+pub struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+	pub fn new(name: &str) -> Self {
+		let path = std::env::temp_dir().join(format!("acmed-{name}-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		std::fs::create_dir_all(&path).unwrap();
+		TestDir(path)
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.0
+	}
+
+	/// File manager of the example.org certificate, whose files are stored in this directory.
+	pub fn file_manager(&self, hooks: Vec<Arc<Hook>>) -> FileManager {
+		FileManager {
+			account_name: "test account".to_string(),
+			account_directory: self.0.join("accounts").display().to_string(),
+			endpoint_name: "test endpoint".to_string(),
+			crt_name: "example.org".to_string(),
+			crt_name_format: crate::DEFAULT_CERT_FORMAT.to_string(),
+			crt_directory: self.0.join("certs").display().to_string(),
+			crt_key_type: acme_common::crypto::KeyType::EcdsaP256.to_string(),
+			cert_file_mode: crate::DEFAULT_CERT_FILE_MODE,
+			cert_file_owner: None,
+			cert_file_group: None,
+			pk_file_mode: crate::DEFAULT_PK_FILE_MODE,
+			pk_file_owner: None,
+			pk_file_group: None,
+			pk_encryption: None,
+			pk_format: PrivateKeyFormat::Pkcs8,
+			pk_encoding: KeyEncoding::Pem,
+			hooks,
+			env: HashMap::new(),
+			labels: BTreeMap::new(),
+		}
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+	use super::{
+		check_manifest, get_certificate, get_file_path, get_keypair, get_manifest_path,
+		get_ocsp_response, read_manifest, set_account_data, set_keypair, write_certificate,
+		write_ocsp_response, FileManager, FileType, TestDir,
+	};
+	use crate::hooks::{Hook, HookStdin, HookType};
+	use acme_common::crypto::{gen_keypair, KeyEncoding, KeyType};
+	use std::collections::HashSet;
+	use std::os::unix::fs::{MetadataExt, PermissionsExt};
+	use std::path::Path;
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	const CERT_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBgDCCASegAwIBAgIUXw2WpRDp60SP2pzTuvMU1Ftl6ekwCgYIKoZIzj0EAwIw
+FjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcwHhcNMjYxMDE0MTU1NTEwWhcNMzYxMDEx
+MTU1NTEwWjAWMRQwEgYDVQQDDAtleGFtcGxlLm9yZzBZMBMGByqGSM49AgEGCCqG
+SM49AwEHA0IABNaRwR+J5kOVj0yI4Pofu95UnjT6VY9j93zH/Z4GF5aNlfcB4lyX
+SbNcgdq9ZSd1hZEoC/da/rBZr36TXvoU1COjUzBRMB0GA1UdDgQWBBQn5mAhDg6W
+S66P6gTf/ESt+5pZ3TAfBgNVHSMEGDAWgBQn5mAhDg6WS66P6gTf/ESt+5pZ3TAP
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0cAMEQCIHD3H73bvWA5U9XiQtMs
+ifwJx3wa8OBXlduVsWSx6KHXAiAX5aUzRA7kRY/c4OPyZP4qfcxcaBGB2Hp4P6mH
+qc8Uaw==
+-----END CERTIFICATE-----
+";
+
+	fn log_hook(name: &str, hook_type: HookType, log: &Path) -> Arc<Hook> {
+		let cmd = format!("echo '{name} {{{{ file_name }}}}' >> {}", log.display());
+		Arc::new(Hook {
+			name: name.to_string(),
+			hook_type: HashSet::from([hook_type]),
+			cmd: "sh".to_string(),
+			args: Some(vec!["-c".to_string(), cmd]),
+			cwd: None,
+			umask: None,
+			stdin: HookStdin::None,
+			stdout: None,
+			stderr: None,
+			allow_failure: false,
+			log_output: false,
+			retries: 0,
+			retry_delay: Duration::from_secs(crate::DEFAULT_HOOK_RETRY_DELAY_SEC),
+			signature: None,
+			timeout: None,
+			uid: None,
+			gid: None,
+		})
+	}
+
+	fn file_name(fm: &FileManager, file_type: FileType) -> String {
+		let path = get_file_path(fm, file_type).unwrap();
+		path.file_name().unwrap().to_string_lossy().to_string()
+	}
+
+	fn mode(path: &Path) -> u32 {
+		std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+	}
+
+	#[test]
+	fn test_file_names() {
+		let dir = TestDir::new("storage-names");
+		let mut fm = dir.file_manager(vec![]);
+		// Changing these names would orphan the files of existing deployments.
+		assert_eq!(
+			file_name(&fm, FileType::Certificate),
+			"example.org_ecdsa-p256.crt.pem"
+		);
+		assert_eq!(
+			file_name(&fm, FileType::PrivateKey),
+			"example.org_ecdsa-p256.pk.pem"
+		);
+		assert_eq!(
+			file_name(&fm, FileType::EncryptedPrivateKey),
+			"example.org_ecdsa-p256.pk-enc.pem"
+		);
+		assert_eq!(
+			file_name(&fm, FileType::OcspResponse),
+			"example.org_ecdsa-p256.ocsp.der"
+		);
+		assert_eq!(
+			file_name(&fm, FileType::Account),
+			"dGVzdCBhY2NvdW50.account.bin"
+		);
+		assert_eq!(
+			get_file_path(&fm, FileType::Account).unwrap().parent(),
+			Some(dir.path().join("accounts").as_path())
+		);
+		assert_eq!(
+			get_file_path(&fm, FileType::Certificate).unwrap().parent(),
+			Some(dir.path().join("certs").as_path())
+		);
+		fm.crt_name_format = "{{ file_type }}/{{ name }}.{{ ext }}".to_string();
+		assert_eq!(
+			get_file_path(&fm, FileType::OcspResponse).unwrap(),
+			dir.path()
+				.join("certs")
+				.join("ocsp")
+				.join("example.org.der")
+		);
+	}
+
+	#[tokio::test]
+	async fn test_write_files() {
+		let dir = TestDir::new("storage-write");
+		let log = dir.path().join("hooks.log");
+		let hooks = vec![
+			log_hook("pre-create", HookType::FilePreCreate, &log),
+			log_hook("post-create", HookType::FilePostCreate, &log),
+			log_hook("pre-edit", HookType::FilePreEdit, &log),
+			log_hook("post-edit", HookType::FilePostEdit, &log),
+		];
+		let mut fm = dir.file_manager(hooks);
+		fm.cert_file_mode = 0o640;
+		fm.cert_file_owner = Some(nix::unistd::getuid().to_string());
+		std::fs::create_dir(&fm.account_directory).unwrap();
+		std::fs::create_dir(&fm.crt_directory).unwrap();
+
+		let key_pair = gen_keypair(KeyType::EcdsaP256).unwrap();
+		set_keypair(&fm, &key_pair).await.unwrap();
+		write_certificate(&fm, CERT_PEM.as_bytes()).await.unwrap();
+		write_ocsp_response(&fm, b"response").await.unwrap();
+		set_account_data(&fm, b"account").await.unwrap();
+		// Rewriting a file with shorter data must not leave any of the previous data behind.
+		write_ocsp_response(&fm, b"new").await.unwrap();
+
+		let pk_path = get_file_path(&fm, FileType::PrivateKey).unwrap();
+		let crt_path = get_file_path(&fm, FileType::Certificate).unwrap();
+		let ocsp_path = get_file_path(&fm, FileType::OcspResponse).unwrap();
+		let account_path = get_file_path(&fm, FileType::Account).unwrap();
+		assert_eq!(mode(&pk_path), 0o600);
+		assert_eq!(mode(&crt_path), 0o640);
+		assert_eq!(mode(&ocsp_path), 0o640);
+		assert_eq!(mode(&account_path), crate::DEFAULT_ACCOUNT_FILE_MODE);
+		let uid = nix::unistd::getuid().as_raw();
+		assert_eq!(std::fs::metadata(&crt_path).unwrap().uid(), uid);
+		assert_eq!(std::fs::metadata(&pk_path).unwrap().uid(), uid);
+
+		let key = get_keypair(&fm).await.unwrap();
+		assert_eq!(
+			key.public_key_to_pem().unwrap(),
+			key_pair.public_key_to_pem().unwrap()
+		);
+		assert!(get_certificate(&fm).await.is_ok());
+		assert_eq!(get_ocsp_response(&fm).await.unwrap(), Some(b"new".to_vec()));
+
+		let expected = "pre-create example.org_ecdsa-p256.pk.pem
+post-create example.org_ecdsa-p256.pk.pem
+pre-create example.org_ecdsa-p256.crt.pem
+post-create example.org_ecdsa-p256.crt.pem
+pre-create example.org_ecdsa-p256.ocsp.der
+post-create example.org_ecdsa-p256.ocsp.der
+pre-create dGVzdCBhY2NvdW50.account.bin
+post-create dGVzdCBhY2NvdW50.account.bin
+pre-edit example.org_ecdsa-p256.ocsp.der
+post-edit example.org_ecdsa-p256.ocsp.der
+";
+		assert_eq!(std::fs::read_to_string(&log).unwrap(), expected);
+	}
+
+	#[tokio::test]
+	async fn test_missing_ocsp_response() {
+		let dir = TestDir::new("storage-ocsp");
+		let fm = dir.file_manager(vec![]);
+		assert_eq!(get_ocsp_response(&fm).await.unwrap(), None);
+		assert!(get_certificate(&fm).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_manifest() {
+		let dir = TestDir::new("storage-manifest");
+		let mut fm = dir.file_manager(vec![]);
+		std::fs::create_dir(&fm.crt_directory).unwrap();
+		let key_pair = gen_keypair(KeyType::EcdsaP256).unwrap();
+		set_keypair(&fm, &key_pair).await.unwrap();
+		write_certificate(&fm, CERT_PEM.as_bytes()).await.unwrap();
+
+		let manifest = read_manifest(&fm).await.unwrap().unwrap();
+		assert_eq!(manifest.version, 1);
+		let entry = &manifest.certificates["example.org_ecdsa-p256"];
+		assert_eq!(entry.files["pk"], "example.org_ecdsa-p256.pk.pem");
+		assert_eq!(entry.files["crt"], "example.org_ecdsa-p256.crt.pem");
+		assert!(!entry.files.contains_key("ocsp"));
+		assert_eq!(entry.pk_format, "pkcs8");
+		assert_eq!(
+			entry.serial_number.as_deref(),
+			Some("5f0d96a510e9eb448fda9cd3baf314d45b65e9e9")
+		);
+		assert_eq!(entry.not_after, Some(2107353310));
+
+		// The files are renamed according to the new file name format.
+		fm.crt_name_format = "{{ name }}/{{ file_type }}.{{ ext }}".to_string();
+		check_manifest(&fm).await.unwrap();
+		let crt_path = get_file_path(&fm, FileType::Certificate).unwrap();
+		assert_eq!(
+			crt_path,
+			dir.path().join("certs").join("example.org").join("crt.pem")
+		);
+		assert!(get_certificate(&fm).await.is_ok());
+		assert!(get_keypair(&fm).await.is_ok());
+		assert!(!dir
+			.path()
+			.join("certs")
+			.join("example.org_ecdsa-p256.crt.pem")
+			.exists());
+		let manifest = read_manifest(&fm).await.unwrap().unwrap();
+		let entry = &manifest.certificates["example.org_ecdsa-p256"];
+		assert_eq!(entry.files["crt"], "example.org/crt.pem");
+
+		// The key is converted to its new encoding.
+		fm.pk_encoding = KeyEncoding::Der;
+		check_manifest(&fm).await.unwrap();
+		let pk_path = get_file_path(&fm, FileType::PrivateKey).unwrap();
+		assert_eq!(
+			pk_path,
+			dir.path().join("certs").join("example.org").join("pk.der")
+		);
+		assert!(!std::fs::read(&pk_path).unwrap().starts_with(b"-----"));
+		assert_eq!(
+			get_keypair(&fm)
+				.await
+				.unwrap()
+				.private_key_to_pem()
+				.unwrap(),
+			key_pair.private_key_to_pem().unwrap()
+		);
+		assert!(!dir
+			.path()
+			.join("certs")
+			.join("example.org")
+			.join("pk.pem")
+			.exists());
+		let manifest = read_manifest(&fm).await.unwrap().unwrap();
+		let entry = &manifest.certificates["example.org_ecdsa-p256"];
+		assert_eq!(entry.files["pk"], "example.org/pk.der");
+		fm.pk_encoding = KeyEncoding::Pem;
+
+		// A layout written by a newer version is left untouched.
+		let path = get_manifest_path(&fm);
+		let data = std::fs::read_to_string(&path)
+			.unwrap()
+			.replace("\"version\": 1", "\"version\": 2");
+		std::fs::write(&path, &data).unwrap();
+		fm.crt_name_format = crate::DEFAULT_CERT_FORMAT.to_string();
+		assert!(check_manifest(&fm).await.is_err());
+		assert!(write_certificate(&fm, CERT_PEM.as_bytes()).await.is_ok());
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), data);
+	}
+}
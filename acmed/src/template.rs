@@ -1,6 +1,27 @@
 use acme_common::error::Error;
-use minijinja::{value::Value, Environment};
+use minijinja::{value::Value, Environment, UndefinedBehavior};
 use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Registry of the compiled templates of the running configuration, shared by every hook,
+/// webhook and certificate. Templates are registered under their own source, hence each
+/// distinct template is compiled only once.
+static REGISTRY: RwLock<Option<Arc<Environment<'static>>>> = RwLock::new(None);
+
+/// Templates compiled while a configuration is being loaded. They replace the registry only
+/// once this configuration is applied, so a failed reload or a check leaves it untouched.
+static PENDING: Mutex<Option<Environment<'static>>> = Mutex::new(None);
+
+/// Compiled templates of a loaded configuration.
+pub struct Templates(Environment<'static>);
+
+impl Templates {
+	/// Replace the shared registry, hence the templates of the previous configuration are
+	/// dropped once the renderings using them are done.
+	pub fn apply(self) {
+		*REGISTRY.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(self.0));
+	}
+}
 
 fn formatter_rev_labels(value: Value) -> Result<Value, minijinja::Error> {
 	if let Some(value) = value.as_str() {
@@ -10,20 +31,73 @@ fn formatter_rev_labels(value: Value) -> Result<Value, minijinja::Error> {
 	}
 }
 
+fn new_environment() -> Environment<'static> {
+	let mut environment = Environment::new();
+	environment.add_filter("rev_labels", formatter_rev_labels);
+	environment
+}
+
+/// Start loading a configuration, discarding the templates left by a previous load which failed.
+pub fn start_loading() {
+	*PENDING.lock().unwrap_or_else(|e| e.into_inner()) = Some(new_environment());
+}
+
+/// Return the templates compiled since the configuration started loading.
+pub fn finish_loading() -> Templates {
+	let env = PENDING.lock().unwrap_or_else(|e| e.into_inner()).take();
+	Templates(env.unwrap_or_else(new_environment))
+}
+
+/// Compile the template and add it to the templates of the configuration being loaded, unless it
+/// already is. This allows syntax errors to be reported when the configuration is loaded.
+pub fn compile_template(template: &str) -> Result<(), Error> {
+	let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+	let env = pending.get_or_insert_with(new_environment);
+	if env.get_template(template).is_ok() {
+		return Ok(());
+	}
+	env.add_template_owned(template.to_owned(), template.to_owned())
+		.map_err(|e| Error::from(e).prefix(&format!("invalid template: {template}")))
+}
+
+/// Render the template using its compiled version from the registry. Templates which are not
+/// part of the running configuration are compiled on the fly.
 pub fn render_template<T>(template: &str, data: &T) -> Result<String, Error>
 where
 	T: Serialize,
 {
-	let mut environment = Environment::new();
-	environment.add_filter("rev_labels", formatter_rev_labels);
-	environment.add_template("template", template)?;
-	let template = environment.get_template("template")?;
-	Ok(template.render(data)?)
+	let registry = REGISTRY
+		.read()
+		.unwrap_or_else(|e| e.into_inner())
+		.as_ref()
+		.map(Arc::clone);
+	if let Some(env) = registry {
+		if let Ok(tpl) = env.get_template(template) {
+			return Ok(tpl.render(data)?);
+		}
+	}
+	Ok(new_environment().render_str(template, data)?)
+}
+
+/// Render the template without keeping the result, failing on any undefined variable instead of
+/// replacing it with an empty string. Optional variables may still be used with the `is defined`
+/// test.
+pub fn check_template<T>(template: &str, data: &T) -> Result<(), Error>
+where
+	T: Serialize,
+{
+	let mut env = new_environment();
+	env.set_undefined_behavior(UndefinedBehavior::Strict);
+	env.render_str(template, data)
+		.map_err(|e| Error::from(e).prefix(&format!("template \"{template}\"")))?;
+	Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-	use super::render_template;
+	use super::{
+		check_template, compile_template, finish_loading, render_template, start_loading, REGISTRY,
+	};
 	use serde::Serialize;
 
 	#[derive(Serialize)]
@@ -57,4 +131,53 @@ mod tests {
 		let rendered = rendered.unwrap();
 		assert_eq!(rendered, "mx1.example.org - org.example.mx1");
 	}
+
+	#[test]
+	fn test_invalid_template() {
+		assert!(compile_template("{{ foo }").is_err());
+		assert!(compile_template("{{ foo }}").is_ok());
+	}
+
+	#[test]
+	fn test_registry() {
+		let c = TplTest {
+			foo: String::from("test"),
+			bar: 42,
+		};
+		let tpl = "registered {{ foo }}";
+		let registered = |tpl: &str| {
+			REGISTRY
+				.read()
+				.unwrap()
+				.as_ref()
+				.map(|env| env.get_template(tpl).is_ok())
+				.unwrap_or(false)
+		};
+		start_loading();
+		compile_template(tpl).unwrap();
+		compile_template(tpl).unwrap();
+		assert!(!registered(tpl));
+		let templates = finish_loading();
+		assert!(templates.0.get_template(tpl).is_ok());
+		templates.apply();
+		assert!(registered(tpl));
+		assert_eq!(render_template(tpl, &c).unwrap(), "registered test");
+		assert_eq!(render_template("{{ bar }}", &c).unwrap(), "42");
+	}
+
+	#[test]
+	fn test_check_template() {
+		let c = TplTest {
+			foo: String::from("test"),
+			bar: 42,
+		};
+		assert!(check_template("{{ foo }} {{ bar + 1 }}", &c).is_ok());
+		assert!(check_template("{% if baz is defined %}{{ baz }}{% endif %}", &c).is_ok());
+		assert!(check_template("{{ baz }}", &c).is_err());
+		assert!(check_template("{{ foo.baz }}", &c).is_err());
+		assert!(check_template("{% for x in bar %}{{ x }}{% endfor %}", &c).is_err());
+		assert!(check_template("{{ foo }", &c).is_err());
+		// The lenient rendering is unaffected.
+		assert_eq!(render_template("{{ baz }}", &c).unwrap(), "");
+	}
 }
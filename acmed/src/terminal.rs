@@ -0,0 +1,229 @@
+use crate::platform::{Current, Platform};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Whether or not the output of the commands is colored.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+	Bold,
+	Green,
+	Yellow,
+	Red,
+}
+
+impl Style {
+	fn code(&self) -> &'static str {
+		match self {
+			Style::Bold => "1",
+			Style::Green => "32",
+			Style::Yellow => "33",
+			Style::Red => "31",
+		}
+	}
+}
+
+/// Cell of a table, whose style only applies when the output is colored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+	pub text: String,
+	pub style: Option<Style>,
+}
+
+impl Cell {
+	pub fn new(text: &str, style: Style) -> Self {
+		Cell {
+			text: text.to_string(),
+			style: Some(style),
+		}
+	}
+}
+
+impl From<String> for Cell {
+	fn from(text: String) -> Self {
+		Cell { text, style: None }
+	}
+}
+
+/// Return whether or not the standard output is interactive.
+pub fn is_interactive() -> bool {
+	Current::stdout_is_terminal()
+}
+
+/// Set whether or not the output is colored, which is either `always`, `never` or `auto`. In
+/// the latter case, the output is colored when it is a terminal, unless the `NO_COLOR`
+/// environment variable is set.
+pub fn set_color(when: &str) {
+	let color = match when {
+		"always" => true,
+		"never" => false,
+		_ => is_interactive() && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty()),
+	};
+	COLOR.store(color, Ordering::Relaxed);
+}
+
+pub fn paint(text: &str, style: Style) -> String {
+	paint_if(text, style, COLOR.load(Ordering::Relaxed))
+}
+
+fn paint_if(text: &str, style: Style, color: bool) -> String {
+	if color {
+		format!("\x1b[{}m{text}\x1b[0m", style.code())
+	} else {
+		text.to_string()
+	}
+}
+
+fn format_table<const N: usize>(header: [&str; N], rows: &[[Cell; N]], color: bool) -> Vec<String> {
+	let header = header.map(|h| Cell::new(h, Style::Bold));
+	let mut widths = [0; N];
+	for row in std::iter::once(&header).chain(rows.iter()) {
+		for (w, cell) in widths.iter_mut().zip(row.iter()) {
+			*w = (*w).max(cell.text.chars().count());
+		}
+	}
+	std::iter::once(&header)
+		.chain(rows.iter())
+		.map(|row| {
+			let line: Vec<String> = row
+				.iter()
+				.zip(widths.iter())
+				.enumerate()
+				.map(|(i, (cell, w))| {
+					// The padding is added outside of the escape sequences, and the last cell is
+					// not padded so the lines have no trailing spaces.
+					let padding = if i + 1 == N {
+						0
+					} else {
+						w - cell.text.chars().count()
+					};
+					let text = match cell.style {
+						Some(s) => paint_if(&cell.text, s, color),
+						None => cell.text.to_owned(),
+					};
+					format!("{text}{}", " ".repeat(padding))
+				})
+				.collect();
+			line.join("  ")
+		})
+		.collect()
+}
+
+/// Print the rows as a table whose columns are aligned, below the header.
+pub fn print_table<const N: usize>(header: [&str; N], rows: &[[Cell; N]]) {
+	for line in format_table(header, rows, COLOR.load(Ordering::Relaxed)) {
+		println!("{line}");
+	}
+}
+
+/// Progress of a set of tasks, which is displayed on a single line of the standard error and
+/// updated as the tasks are completed. Nothing is displayed unless the session is interactive.
+pub struct Progress {
+	label: String,
+	total: usize,
+	done: AtomicUsize,
+	is_visible: bool,
+}
+
+impl Progress {
+	pub fn new(label: &str, total: usize) -> Self {
+		let progress = Progress {
+			label: label.to_string(),
+			total,
+			done: AtomicUsize::new(0),
+			is_visible: is_interactive() && total > 1,
+		};
+		progress.display(0);
+		progress
+	}
+
+	/// Record that one more task has been completed.
+	pub fn inc(&self) {
+		let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+		self.display(done);
+	}
+
+	/// Clear the progress line, so the summary of the tasks can be displayed instead.
+	pub fn finish(&self) {
+		if self.is_visible {
+			eprint!("\r\x1b[2K");
+			let _ = std::io::stderr().flush();
+		}
+	}
+
+	fn display(&self, done: usize) {
+		if self.is_visible {
+			eprint!(
+				"\r\x1b[2K{}",
+				format_progress(&self.label, done, self.total)
+			);
+			let _ = std::io::stderr().flush();
+		}
+	}
+}
+
+fn format_progress(label: &str, done: usize, total: usize) -> String {
+	const WIDTH: usize = 20;
+	let filled = (done * WIDTH)
+		.checked_div(total)
+		.unwrap_or(WIDTH)
+		.min(WIDTH);
+	format!(
+		"{label} [{}{}] {done}/{total}",
+		"#".repeat(filled),
+		" ".repeat(WIDTH - filled)
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{format_progress, format_table, Cell, Style};
+
+	#[test]
+	fn test_format_table() {
+		let rows = [
+			[
+				Cell::from("example.org_rsa2048".to_string()),
+				Cell::new("3", Style::Red),
+			],
+			[Cell::from("é".to_string()), Cell::from("-".to_string())],
+		];
+		assert_eq!(
+			format_table(["CERTIFICATE", "DAYS"], &rows, false),
+			vec![
+				"CERTIFICATE          DAYS",
+				"example.org_rsa2048  3",
+				"é                    -",
+			]
+		);
+		assert_eq!(
+			format_table(["CERTIFICATE", "DAYS"], &rows, true)[1],
+			"example.org_rsa2048  \x1b[31m3\x1b[0m"
+		);
+		assert_eq!(
+			format_table(["A", "B"], &[], true)[0],
+			"\x1b[1mA\x1b[0m  \x1b[1mB\x1b[0m"
+		);
+	}
+
+	#[test]
+	fn test_format_progress() {
+		assert_eq!(
+			format_progress("renewing", 0, 4),
+			"renewing [                    ] 0/4"
+		);
+		assert_eq!(
+			format_progress("renewing", 1, 4),
+			"renewing [#####               ] 1/4"
+		);
+		assert_eq!(
+			format_progress("renewing", 4, 4),
+			"renewing [####################] 4/4"
+		);
+		assert_eq!(
+			format_progress("renewing", 0, 0),
+			"renewing [####################] 0/0"
+		);
+	}
+}
@@ -0,0 +1,210 @@
+use crate::email::EmailNotifier;
+use crate::endpoint::format_timestamp;
+use crate::events;
+use crate::logs::HasLogger;
+use acme_common::crypto::X509Certificate;
+use acme_common::error::Error;
+use acme_common::now;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "crypto_openssl")]
+use tokio::net::TcpStream;
+
+/// Names of the watched certificates whose expiration has already been notified, which is shared
+/// by the monitoring tasks and kept across the reloads so the notifications are not sent again.
+pub type AlertedCertificates = Arc<Mutex<HashSet<String>>>;
+
+/// Location of a certificate which is managed by another tool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchSource {
+	File(String),
+	Tls {
+		address: String,
+		server_name: String,
+	},
+}
+
+/// Certificate which is not renewed by ACMEd but whose expiration is monitored, so it is
+/// included in the status and the expiration notifications.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchedCertificate {
+	pub name: String,
+	pub source: WatchSource,
+	pub labels: BTreeMap<String, String>,
+	pub interval: Duration,
+	pub expiration_margin: Duration,
+	pub email: Option<Arc<EmailNotifier>>,
+}
+
+impl HasLogger for WatchedCertificate {
+	fn warn(&self, msg: &str) {
+		log::warn!("watched certificate \"{}\": {msg}", self.name);
+	}
+
+	fn info(&self, msg: &str) {
+		log::info!("watched certificate \"{}\": {msg}", self.name);
+	}
+
+	fn debug(&self, msg: &str) {
+		log::debug!("watched certificate \"{}\": {msg}", self.name);
+	}
+
+	fn trace(&self, msg: &str) {
+		log::trace!("watched certificate \"{}\": {msg}", self.name);
+	}
+}
+
+impl WatchedCertificate {
+	/// Return the source as displayed in the status.
+	pub fn source_name(&self) -> String {
+		match &self.source {
+			WatchSource::File(path) => path.to_owned(),
+			WatchSource::Tls { address, .. } => format!("tls://{address}"),
+		}
+	}
+
+	/// Read the certificate from its file or retrieve the one presented by the TLS endpoint. In
+	/// the latter case, the certificate is not verified since an invalid one should be reported
+	/// instead of being an error.
+	pub async fn get_certificate(&self) -> Result<X509Certificate, Error> {
+		match &self.source {
+			WatchSource::File(path) => {
+				let raw = tokio::fs::read(path)
+					.await
+					.map_err(|e| Error::from(e).prefix(path))?;
+				X509Certificate::from_pem(&raw).map_err(|e| e.prefix(path))
+			}
+			WatchSource::Tls {
+				address,
+				server_name,
+			} => fetch_certificate(address, server_name).await,
+		}
+	}
+
+	/// Periodically check the certificate. Once it enters the expiration margin, the
+	/// `certificate_expiring` event is emitted and an email is sent, which is done again only
+	/// after the certificate has been replaced.
+	pub async fn run(self, alerted: AlertedCertificates) {
+		loop {
+			if let Err(e) = self.check(&alerted).await {
+				self.warn(&e.message);
+			}
+			tokio::time::sleep(self.interval).await;
+		}
+	}
+
+	async fn check(&self, alerted: &AlertedCertificates) -> Result<(), Error> {
+		let not_after = self.get_certificate().await?.not_after()?;
+		let expires_in = Duration::from_secs(not_after.saturating_sub(now()));
+		self.debug(&format!(
+			"certificate expires on {}",
+			format_timestamp(not_after)
+		));
+		let is_new_alert = {
+			let mut alerted = alerted.lock().unwrap_or_else(|e| e.into_inner());
+			let mut is_alerted = alerted.contains(&self.name);
+			let res = should_alert(&mut is_alerted, expires_in, self.expiration_margin);
+			if is_alerted {
+				alerted.insert(self.name.to_owned());
+			} else {
+				alerted.remove(&self.name);
+			}
+			res
+		};
+		if !is_new_alert {
+			return Ok(());
+		}
+		let days = expires_in.as_secs() / 86_400;
+		self.warn(&format!("certificate expires in {days} days"));
+		events::emit(
+			"certificate_expiring",
+			Some(self.name.to_owned()),
+			json!({
+				"not_after": format_timestamp(not_after),
+				"expires_in_days": days,
+				"watch_only": true,
+			}),
+		);
+		if let Some(notifier) = &self.email {
+			let subject = format!("[acmed] certificate {} is about to expire", self.name);
+			let body = format!(
+				"The following certificate, which is not renewed by ACMEd, is about to expire.\n\ncertificate: {}\nsource: {}\nexpiration: {} (in {days} days)\n",
+				self.name,
+				self.source_name(),
+				format_timestamp(not_after),
+			);
+			match notifier.send(&subject, &body).await {
+				Ok(_) => self.info("expiration notified by email"),
+				Err(e) => self.warn(&e.prefix("unable to send the email notification").message),
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Retrieve the certificate presented by a TLS server, without verifying it.
+pub async fn fetch_certificate(address: &str, server_name: &str) -> Result<X509Certificate, Error> {
+	let timeout = Duration::from_secs(crate::DEFAULT_TLS_CHECK_TIMEOUT_SEC);
+	tokio::time::timeout(timeout, do_fetch_certificate(address, server_name))
+		.await
+		.map_err(|_| Error::from("connection timeout"))
+		.and_then(|r| r)
+		.map_err(|e| e.prefix(address))
+}
+
+#[cfg(not(feature = "crypto_openssl"))]
+async fn do_fetch_certificate(
+	_address: &str,
+	_server_name: &str,
+) -> Result<X509Certificate, Error> {
+	Err("fetching a certificate requires the crypto_openssl feature".into())
+}
+
+#[cfg(feature = "crypto_openssl")]
+async fn do_fetch_certificate(address: &str, server_name: &str) -> Result<X509Certificate, Error> {
+	let stream = TcpStream::connect(address).await?;
+	let connector = native_tls::TlsConnector::builder()
+		.danger_accept_invalid_certs(true)
+		.danger_accept_invalid_hostnames(true)
+		.build()?;
+	let connector = tokio_native_tls::TlsConnector::from(connector);
+	let stream = connector.connect(server_name, stream).await?;
+	let crt = stream
+		.get_ref()
+		.peer_certificate()?
+		.ok_or_else(|| Error::from("no certificate presented"))?;
+	X509Certificate::from_der(&crt.to_der()?)
+}
+
+/// Return whether or not the expiration should be notified, which is the case once the
+/// certificate enters the margin. A certificate leaving it has been replaced, hence the next
+/// expiration will be notified again.
+fn should_alert(alerted: &mut bool, expires_in: Duration, margin: Duration) -> bool {
+	if expires_in > margin {
+		*alerted = false;
+		return false;
+	}
+	!std::mem::replace(alerted, true)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::should_alert;
+	use std::time::Duration;
+
+	#[test]
+	fn test_should_alert() {
+		let margin = Duration::from_secs(7 * 24 * 60 * 60);
+		let long = Duration::from_secs(30 * 24 * 60 * 60);
+		let short = Duration::from_secs(24 * 60 * 60);
+		let mut alerted = false;
+		assert!(!should_alert(&mut alerted, long, margin));
+		assert!(should_alert(&mut alerted, short, margin));
+		assert!(!should_alert(&mut alerted, short, margin));
+		assert!(!should_alert(&mut alerted, Duration::ZERO, margin));
+		assert!(!should_alert(&mut alerted, long, margin));
+		assert!(should_alert(&mut alerted, short, margin));
+	}
+}
@@ -0,0 +1,814 @@
+use crate::certificate::Certificate;
+use crate::control::{AcceptBackoff, ControlRequest, Request, MAX_CONNECTIONS};
+use acme_common::error::Error;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use minijinja::Environment;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+#[cfg(feature = "crypto_openssl")]
+use crate::events;
+#[cfg(feature = "crypto_openssl")]
+use crate::storage::{self, FileManager};
+#[cfg(feature = "crypto_openssl")]
+use std::sync::RwLock;
+#[cfg(feature = "crypto_openssl")]
+use tokio::sync::broadcast::error::RecvError;
+#[cfg(feature = "crypto_openssl")]
+use tokio_native_tls::TlsAcceptor;
+
+/// Maximal size of a request's head, in bytes.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const REQUEST_TIMEOUT_SEC: u64 = 10;
+/// Number of days displayed on the expiration timeline.
+const TIMELINE_DAYS: i64 = 90;
+/// Number of hook executions displayed for each certificate.
+const MAX_HOOKS: usize = 10;
+const INDEX_TEMPLATE: &str = include_str!("web_ui.html");
+
+/// Read-only web interface displaying the daemon's status. Unless it is served over HTTPS using
+/// one of the managed certificates, it only listens on a loopback address, hence it may be
+/// exposed through a reverse proxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebUi {
+	pub listen: SocketAddr,
+	pub user: String,
+	pub password: String,
+	/// Id of the certificate the web interface is served with over HTTPS.
+	pub certificate: Option<String>,
+}
+
+impl WebUi {
+	pub fn get_url(&self) -> String {
+		let scheme = match self.certificate {
+			Some(_) => "https",
+			None => "http",
+		};
+		format!("{scheme}://{}", self.listen)
+	}
+}
+
+/// TLS acceptor of the web interface, which uses one of the managed certificates. It is rebuilt
+/// each time this certificate is renewed, so the new one is served without restarting the daemon.
+#[cfg(feature = "crypto_openssl")]
+#[derive(Clone)]
+pub struct Tls {
+	acceptor: Arc<RwLock<Option<Arc<TlsAcceptor>>>>,
+}
+
+#[cfg(feature = "crypto_openssl")]
+impl Tls {
+	/// Load the certificate and watch its renewals. If it has not been issued yet, the
+	/// connections are refused until it is.
+	pub async fn new(certificate: &Certificate) -> Result<Self, Error> {
+		let fm = certificate.file_manager.clone();
+		let acceptor = match build_acceptor(&fm).await {
+			Ok(a) => Some(Arc::new(a)),
+			Err(e) => {
+				log::error!("web UI: unable to load the TLS certificate: {e}");
+				None
+			}
+		};
+		let tls = Tls {
+			acceptor: Arc::new(RwLock::new(acceptor)),
+		};
+		tls.watch(certificate.get_id(), fm);
+		Ok(tls)
+	}
+
+	/// Reload the certificate once it has been renewed.
+	fn watch(&self, id: String, fm: FileManager) {
+		let acceptor = self.acceptor.clone();
+		let mut events = events::subscribe();
+		tokio::spawn(async move {
+			loop {
+				match events.recv().await {
+					Ok(event) => {
+						let is_renewed = event.kind == "renewal_finished"
+							&& event.certificate.as_ref() == Some(&id)
+							&& event.details["status"] == "success";
+						if !is_renewed {
+							continue;
+						}
+						match build_acceptor(&fm).await {
+							Ok(a) => {
+								*acceptor.write().unwrap_or_else(|e| e.into_inner()) =
+									Some(Arc::new(a));
+								log::info!("web UI: TLS certificate reloaded");
+							}
+							Err(e) => {
+								log::error!("web UI: unable to reload the TLS certificate: {e}")
+							}
+						}
+					}
+					Err(RecvError::Lagged(_)) => {}
+					Err(RecvError::Closed) => return,
+				}
+			}
+		});
+	}
+
+	async fn serve(
+		&self,
+		stream: TcpStream,
+		web_ui: &WebUi,
+		tx: mpsc::Sender<ControlRequest>,
+	) -> Result<(), Error> {
+		let acceptor = self
+			.acceptor
+			.read()
+			.unwrap_or_else(|e| e.into_inner())
+			.clone()
+			.ok_or_else(|| Error::from("no TLS certificate available"))?;
+		let timeout = Duration::from_secs(REQUEST_TIMEOUT_SEC);
+		let stream = tokio::time::timeout(timeout, acceptor.accept(stream))
+			.await
+			.map_err(|_| Error::from("timeout during the TLS handshake"))??;
+		handle_connection(stream, web_ui, tx).await
+	}
+}
+
+#[cfg(feature = "crypto_openssl")]
+async fn build_acceptor(fm: &FileManager) -> Result<TlsAcceptor, Error> {
+	let chain = storage::get_certificate_data(fm).await?;
+	let key = storage::get_keypair(fm).await?.private_key_to_pem()?;
+	let identity = native_tls::Identity::from_pkcs8(&chain, &key)?;
+	Ok(TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?))
+}
+
+#[cfg(not(feature = "crypto_openssl"))]
+#[derive(Clone)]
+pub enum Tls {}
+
+#[cfg(not(feature = "crypto_openssl"))]
+impl Tls {
+	pub async fn new(_certificate: &Certificate) -> Result<Self, Error> {
+		Err("the web_ui_certificate option requires the crypto_openssl feature".into())
+	}
+
+	async fn serve(
+		&self,
+		_stream: TcpStream,
+		_web_ui: &WebUi,
+		_tx: mpsc::Sender<ControlRequest>,
+	) -> Result<(), Error> {
+		match *self {}
+	}
+}
+
+struct HttpRequest {
+	method: String,
+	path: String,
+	authorization: Option<String>,
+}
+
+struct HttpResponse {
+	status: u16,
+	content_type: &'static str,
+	headers: Vec<(&'static str, &'static str)>,
+	body: String,
+}
+
+impl HttpResponse {
+	fn new(status: u16, content_type: &'static str, body: &str) -> Self {
+		HttpResponse {
+			status,
+			content_type,
+			headers: vec![],
+			body: body.to_string(),
+		}
+	}
+
+	fn text(status: u16, body: &str) -> Self {
+		Self::new(status, "text/plain; charset=utf-8", &format!("{body}\n"))
+	}
+
+	fn to_bytes(&self, with_body: bool) -> Vec<u8> {
+		let reason = match self.status {
+			200 => "OK",
+			400 => "Bad Request",
+			401 => "Unauthorized",
+			404 => "Not Found",
+			405 => "Method Not Allowed",
+			_ => "Internal Server Error",
+		};
+		let mut head = format!(
+			"HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\nCache-Control: no-store\r\nContent-Security-Policy: default-src 'none'; style-src 'unsafe-inline'\r\nX-Content-Type-Options: nosniff\r\nX-Frame-Options: DENY\r\n",
+			self.status,
+			self.content_type,
+			self.body.len()
+		);
+		for (name, value) in self.headers.iter() {
+			head += &format!("{name}: {value}\r\n");
+		}
+		head += "\r\n";
+		let mut bytes = head.into_bytes();
+		if with_body {
+			bytes.extend_from_slice(self.body.as_bytes());
+		}
+		bytes
+	}
+}
+
+/// Create the listening socket. This does not require any runtime, so it may be done before the
+/// privileges are dropped.
+pub fn bind(addr: &SocketAddr) -> Result<StdTcpListener, Error> {
+	let listener =
+		StdTcpListener::bind(addr).map_err(|e| Error::from(e).prefix(&addr.to_string()))?;
+	listener.set_nonblocking(true)?;
+	Ok(listener)
+}
+
+/// Serve the web interface, over HTTPS if a TLS acceptor is given. The status requests are
+/// forwarded to the returned channel, just like the ones received on the control socket.
+pub fn listen(
+	listener: StdTcpListener,
+	web_ui: WebUi,
+	tls: Option<Tls>,
+) -> Result<mpsc::Receiver<ControlRequest>, Error> {
+	let listener = TcpListener::from_std(listener)?;
+	let (tx, rx) = mpsc::channel(16);
+	let web_ui = Arc::new(web_ui);
+	let permits = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+	tokio::spawn(async move {
+		let mut backoff = AcceptBackoff::default();
+		loop {
+			let permit = match permits.clone().acquire_owned().await {
+				Ok(p) => p,
+				Err(_) => return,
+			};
+			match listener.accept().await {
+				Ok((stream, _)) => {
+					backoff.reset();
+					let tx = tx.clone();
+					let web_ui = web_ui.clone();
+					let tls = tls.clone();
+					tokio::spawn(async move {
+						let res = match &tls {
+							Some(tls) => tls.serve(stream, &web_ui, tx).await,
+							None => handle_connection(stream, &web_ui, tx).await,
+						};
+						if let Err(e) = res {
+							log::debug!("web UI: {e}");
+						}
+						drop(permit);
+					});
+				}
+				Err(e) => {
+					log::warn!("web UI: unable to accept a connection: {e}");
+					backoff.wait().await;
+				}
+			}
+		}
+	});
+	Ok(rx)
+}
+
+async fn handle_connection<S>(
+	mut stream: S,
+	web_ui: &WebUi,
+	tx: mpsc::Sender<ControlRequest>,
+) -> Result<(), Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let timeout = Duration::from_secs(REQUEST_TIMEOUT_SEC);
+	let head = tokio::time::timeout(timeout, read_head(&mut stream))
+		.await
+		.map_err(|_| Error::from("timeout while reading the request"))??;
+	let response = match parse_request(&head) {
+		Some(request) => {
+			let with_body = request.method != "HEAD";
+			let response = handle_request(&request, web_ui, &tx).await;
+			log::debug!(
+				"web UI: {} {}: {}",
+				request.method,
+				request.path,
+				response.status
+			);
+			response.to_bytes(with_body)
+		}
+		None => HttpResponse::text(400, "invalid request").to_bytes(true),
+	};
+	stream.write_all(&response).await?;
+	stream.shutdown().await?;
+	Ok(())
+}
+
+/// Read the request until the end of its headers. Since only GET and HEAD requests are
+/// supported, the body is ignored.
+async fn read_head<S>(stream: &mut S) -> Result<String, Error>
+where
+	S: AsyncRead + Unpin,
+{
+	let mut head = vec![];
+	let mut buf = [0; 1024];
+	while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+		let nb = stream.read(&mut buf).await?;
+		if nb == 0 {
+			break;
+		}
+		head.extend_from_slice(&buf[..nb]);
+		if head.len() > MAX_REQUEST_SIZE {
+			return Err("request too large".into());
+		}
+	}
+	Ok(String::from_utf8_lossy(&head).to_string())
+}
+
+fn parse_request(head: &str) -> Option<HttpRequest> {
+	let mut lines = head.split("\r\n");
+	let mut request_line = lines.next()?.split(' ');
+	let method = request_line.next()?.to_string();
+	let target = request_line.next()?;
+	if !request_line.next()?.starts_with("HTTP/1.") {
+		return None;
+	}
+	let path = target.split('?').next().unwrap_or_default().to_string();
+	let authorization = lines
+		.take_while(|l| !l.is_empty())
+		.filter_map(|l| l.split_once(':'))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+		.map(|(_, value)| value.trim().to_string());
+	Some(HttpRequest {
+		method,
+		path,
+		authorization,
+	})
+}
+
+async fn handle_request(
+	request: &HttpRequest,
+	web_ui: &WebUi,
+	tx: &mpsc::Sender<ControlRequest>,
+) -> HttpResponse {
+	if request.method != "GET" && request.method != "HEAD" {
+		let mut response = HttpResponse::text(405, "method not allowed");
+		response.headers.push(("Allow", "GET, HEAD"));
+		return response;
+	}
+	if !is_authorized(request.authorization.as_deref(), web_ui) {
+		let mut response = HttpResponse::text(401, "unauthorized");
+		response.headers.push((
+			"WWW-Authenticate",
+			"Basic realm=\"acmed\", charset=\"UTF-8\"",
+		));
+		return response;
+	}
+	let res = match request.path.as_str() {
+		"/" => render_index(tx)
+			.await
+			.map(|b| (b, "text/html; charset=utf-8")),
+		"/api/status" => query(tx, status_request())
+			.await
+			.map(|d| (d.to_string(), "application/json")),
+		"/api/hooks" => query(tx, hooks_request())
+			.await
+			.map(|d| (d.to_string(), "application/json")),
+		"/metrics" => query(tx, status_request())
+			.await
+			.map(|d| (get_metrics(&d), "text/plain; version=0.0.4; charset=utf-8")),
+		_ => return HttpResponse::text(404, "not found"),
+	};
+	match res {
+		Ok((body, content_type)) => HttpResponse::new(200, content_type, &body),
+		Err(e) => {
+			log::warn!("web UI: {}: {e}", request.path);
+			HttpResponse::text(500, &e.message)
+		}
+	}
+}
+
+/// Check the credentials sent using the basic authentication scheme. The comparison does not
+/// stop at the first difference so its duration does not disclose the expected credentials.
+fn is_authorized(authorization: Option<&str>, web_ui: &WebUi) -> bool {
+	let credentials = match authorization.and_then(|a| a.strip_prefix("Basic ")) {
+		Some(c) => c.trim(),
+		None => return false,
+	};
+	let credentials = match STANDARD.decode(credentials) {
+		Ok(c) => c,
+		Err(_) => return false,
+	};
+	let expected = format!("{}:{}", web_ui.user, web_ui.password);
+	let expected = expected.as_bytes();
+	let mut diff = credentials.len() ^ expected.len();
+	for (i, b) in expected.iter().enumerate() {
+		diff |= usize::from(b ^ credentials.get(i).copied().unwrap_or(!b));
+	}
+	diff == 0
+}
+
+fn status_request() -> Request {
+	Request::Status {
+		certificates: vec![],
+	}
+}
+
+fn hooks_request() -> Request {
+	Request::Hooks {
+		certificates: vec![],
+	}
+}
+
+async fn query(tx: &mpsc::Sender<ControlRequest>, request: Request) -> Result<Value, Error> {
+	let (resp_tx, resp_rx) = oneshot::channel();
+	tx.send((request, resp_tx))
+		.await
+		.map_err(|_| Error::from("the daemon is shutting down"))?;
+	let response = resp_rx
+		.await
+		.map_err(|_| Error::from("the request has been dropped"))?;
+	if !response.success {
+		return Err(response.message.into());
+	}
+	Ok(response.data.unwrap_or_default())
+}
+
+async fn render_index(tx: &mpsc::Sender<ControlRequest>) -> Result<String, Error> {
+	let status = query(tx, status_request()).await?;
+	let hooks = query(tx, hooks_request()).await?;
+	let mut env = Environment::new();
+	// The name's extension enables the escaping of the HTML special characters.
+	env.add_template("index.html", INDEX_TEMPLATE)?;
+	let template = env.get_template("index.html")?;
+	Ok(template.render(get_context(&status, &hooks))?)
+}
+
+/// Build the data displayed by the web interface from the responses to the status and hooks
+/// requests.
+fn get_context(status: &Value, hooks: &Value) -> Value {
+	let mut history: HashMap<&str, Vec<Value>> = HashMap::new();
+	for crt in hooks["certificates"].as_array().into_iter().flatten() {
+		let mut executions: Vec<Value> = crt["hooks"].as_array().cloned().unwrap_or_default();
+		executions.reverse();
+		history.insert(crt["id"].as_str().unwrap_or_default(), executions);
+	}
+	let mut certificates = vec![];
+	let mut failures = vec![];
+	for crt in status["certificates"].as_array().into_iter().flatten() {
+		let name = crt["name"].as_str().unwrap_or_default();
+		let mut crt = crt.clone();
+		let executions = history
+			.remove(crt["id"].as_str().unwrap_or_default())
+			.unwrap_or_default();
+		if crt["last_renewal"]["success"] == json!(false) {
+			failures.push(json!({
+				"date": crt["last_renewal"]["date"],
+				"certificate": name,
+				"source": "renewal",
+				"error": crt["last_renewal"]["error_source"],
+			}));
+		}
+		for exec in executions.iter().filter(|e| e["success"] == json!(false)) {
+			failures.push(json!({
+				"date": exec["date"],
+				"certificate": name,
+				"source": format!("hook \"{}\"", exec["hook"].as_str().unwrap_or_default()),
+				"error": match exec["exit_code"].as_i64() {
+					Some(code) => json!(format!("code {code}")),
+					None => exec["error"].clone(),
+				},
+			}));
+		}
+		if let Some(days) = crt["expires_in_days"].as_i64() {
+			let width = days.clamp(0, TIMELINE_DAYS) * 100 / TIMELINE_DAYS;
+			let level = match days {
+				d if d < 7 => "critical",
+				d if d < 30 => "warning",
+				_ => "ok",
+			};
+			crt["timeline"] = json!({ "width": width, "level": level });
+		}
+		crt["hooks"] = json!(executions.into_iter().take(MAX_HOOKS).collect::<Vec<_>>());
+		certificates.push(crt);
+	}
+	// The dates are formatted the same way, hence they are sorted chronologically.
+	failures.sort_by(|a, b| b["date"].as_str().cmp(&a["date"].as_str()));
+	json!({
+		"version": status["version"],
+		"pid": status["pid"],
+		"renewals_in_progress": status["renewals_in_progress"],
+		"read_only_storage": status["read_only_storage"],
+		"version_advisory": status["version_advisory"],
+		"timeline_days": TIMELINE_DAYS,
+		"certificates": certificates,
+		"failures": failures,
+	})
+}
+
+/// Escape a label value of the Prometheus text format.
+fn escape_label_value(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Name, help and field of the status holding the certificates of a metric, along with the
+/// function returning the value of each certificate, if any.
+type CertificateMetric = (
+	&'static str,
+	&'static str,
+	&'static str,
+	fn(&Value) -> Option<u64>,
+);
+
+/// Format the labels of a sample, the certificate's own labels being prefixed by `label_` so
+/// they cannot override the other ones.
+fn format_labels(names: &[(&str, &str)], crt_labels: &Value) -> String {
+	let labels: Vec<String> = names
+		.iter()
+		.map(|(k, v)| (k.to_string(), *v))
+		.chain(
+			crt_labels
+				.as_object()
+				.into_iter()
+				.flatten()
+				.map(|(k, v)| (format!("label_{k}"), v.as_str().unwrap_or_default())),
+		)
+		.map(|(k, v)| format!("{k}=\"{}\"", escape_label_value(v)))
+		.collect();
+	labels.join(",")
+}
+
+/// Build the metrics, using the Prometheus text format, from the response to the status
+/// request.
+fn get_metrics(status: &Value) -> String {
+	let rate_limit_metrics = [
+		(
+			"acmed_ca_rate_limit_limit",
+			"limit",
+			"Number of requests allowed by the CA during the current period.",
+		),
+		(
+			"acmed_ca_rate_limit_remaining",
+			"remaining",
+			"Number of requests the CA still allows during the current period.",
+		),
+		(
+			"acmed_ca_rate_limit_reset_timestamp_seconds",
+			"reset",
+			"Date at which the CA resets its limit, as a Unix timestamp.",
+		),
+		(
+			"acmed_ca_rate_limit_retry_after_timestamp_seconds",
+			"retry_after",
+			"Date before which the CA asked not to send new requests, as a Unix timestamp.",
+		),
+	];
+	let mut metrics = String::new();
+	for (name, field, help) in rate_limit_metrics {
+		let mut samples = vec![];
+		for endpoint in status["endpoints"].as_array().into_iter().flatten() {
+			if let Some(value) = endpoint["rate_limits"][field].as_u64() {
+				let endpoint_name =
+					escape_label_value(endpoint["name"].as_str().unwrap_or_default());
+				samples.push(format!("{name}{{endpoint=\"{endpoint_name}\"}} {value}\n"));
+			}
+		}
+		if !samples.is_empty() {
+			metrics += &format!("# HELP {name} {help}\n# TYPE {name} gauge\n");
+			metrics += &samples.concat();
+		}
+	}
+	let certificate_metrics: [CertificateMetric; 3] = [
+		(
+			"acmed_certificate_expiry_timestamp_seconds",
+			"Expiration date of the certificate, as a Unix timestamp.",
+			"certificates",
+			|crt| crt["expires_timestamp"].as_u64(),
+		),
+		(
+			"acmed_certificate_last_renewal_success",
+			"Whether the latest renewal of the certificate succeeded.",
+			"certificates",
+			|crt| crt["last_renewal"]["success"].as_bool().map(u64::from),
+		),
+		(
+			"acmed_watched_certificate_expiry_timestamp_seconds",
+			"Expiration date of the watched certificate, as a Unix timestamp.",
+			"watched_certificates",
+			|crt| crt["expires_timestamp"].as_u64(),
+		),
+	];
+	for (name, help, field, get_value) in certificate_metrics {
+		let mut samples = vec![];
+		for crt in status[field].as_array().into_iter().flatten() {
+			if let Some(value) = get_value(crt) {
+				let id = crt["id"].as_str().or(crt["name"].as_str());
+				let mut names = vec![("certificate", id.unwrap_or_default())];
+				if let Some(endpoint) = crt["endpoint"].as_str() {
+					names.push(("endpoint", endpoint));
+				}
+				let labels = format_labels(&names, &crt["labels"]);
+				samples.push(format!("{name}{{{labels}}} {value}\n"));
+			}
+		}
+		if !samples.is_empty() {
+			metrics += &format!("# HELP {name} {help}\n# TYPE {name} gauge\n");
+			metrics += &samples.concat();
+		}
+	}
+	if let Some(read_only) = status["read_only_storage"].as_bool() {
+		let name = "acmed_storage_read_only";
+		metrics += &format!(
+			"# HELP {name} Whether the storage is on a read-only file system, no certificate being renewed meanwhile.\n# TYPE {name} gauge\n{name} {}\n",
+			u8::from(read_only)
+		);
+	}
+	metrics
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{get_context, get_metrics, is_authorized, parse_request, WebUi, INDEX_TEMPLATE};
+	use minijinja::Environment;
+	use serde_json::json;
+
+	#[test]
+	fn test_parse_request() {
+		let req = parse_request(
+			"GET /api/status?x=1 HTTP/1.1\r\nHost: localhost\r\nauthorization: Basic YTpi\r\n\r\n",
+		)
+		.unwrap();
+		assert_eq!(req.method, "GET");
+		assert_eq!(req.path, "/api/status");
+		assert_eq!(req.authorization.as_deref(), Some("Basic YTpi"));
+		assert!(parse_request("GET /\r\n\r\n").is_none());
+		assert!(parse_request("").is_none());
+	}
+
+	#[test]
+	fn test_is_authorized() {
+		let web_ui = WebUi {
+			listen: "127.0.0.1:8080".parse().unwrap(),
+			user: "acmed".to_string(),
+			password: "secret".to_string(),
+			certificate: None,
+		};
+		// acmed:secret
+		assert!(is_authorized(Some("Basic YWNtZWQ6c2VjcmV0"), &web_ui));
+		// acmed:secre
+		assert!(!is_authorized(Some("Basic YWNtZWQ6c2VjcmU="), &web_ui));
+		// acmed:secrets
+		assert!(!is_authorized(Some("Basic YWNtZWQ6c2VjcmV0cw=="), &web_ui));
+		assert!(!is_authorized(Some("Bearer YWNtZWQ6c2VjcmV0"), &web_ui));
+		assert!(!is_authorized(None, &web_ui));
+	}
+
+	#[cfg(feature = "crypto_openssl")]
+	#[tokio::test]
+	async fn test_tls() {
+		use super::{bind, build_acceptor, listen, Tls};
+		use crate::storage::{self, TestDir};
+		use acme_common::b64_decode;
+		use acme_common::crypto::{
+			convert_certificate, HashFunction, KeyEncoding, KeyType, X509Certificate,
+		};
+		use std::sync::{Arc, RwLock};
+		use tokio::io::{AsyncReadExt, AsyncWriteExt};
+		use tokio::net::TcpStream;
+
+		let dir = TestDir::new("web-ui-tls");
+		let fm = dir.file_manager(vec![]);
+		std::fs::create_dir_all(dir.path().join("certs")).unwrap();
+		let (key, crt) = X509Certificate::from_acme_ext(
+			"localhost",
+			"",
+			KeyType::EcdsaP256,
+			HashFunction::Sha256,
+		)
+		.unwrap();
+		let der = b64_decode(&crt.to_der_base64().unwrap()).unwrap();
+		let mut pem = vec![];
+		convert_certificate(&der, KeyEncoding::Pem, &mut pem).unwrap();
+		storage::set_keypair(&fm, &key).await.unwrap();
+		storage::write_certificate(&fm, &pem).await.unwrap();
+
+		let acceptor = build_acceptor(&fm).await.unwrap();
+		let tls = Tls {
+			acceptor: Arc::new(RwLock::new(Some(Arc::new(acceptor)))),
+		};
+		let web_ui = WebUi {
+			listen: "127.0.0.1:0".parse().unwrap(),
+			user: "acmed".to_string(),
+			password: "secret".to_string(),
+			certificate: Some("example.org_ecdsa-p256".to_string()),
+		};
+		let listener = bind(&web_ui.listen).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let _rx = listen(listener, web_ui, Some(tls)).unwrap();
+
+		let connector = native_tls::TlsConnector::builder()
+			.danger_accept_invalid_certs(true)
+			.build()
+			.unwrap();
+		let connector = tokio_native_tls::TlsConnector::from(connector);
+		let stream = TcpStream::connect(addr).await.unwrap();
+		let mut stream = connector.connect("localhost", stream).await.unwrap();
+		stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+		let mut response = String::new();
+		stream.read_to_string(&mut response).await.unwrap();
+		assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+	}
+
+	#[test]
+	fn test_get_context() {
+		let status = json!({
+			"version": "0.21.0",
+			"certificates": [
+				{
+					"id": "example.org_ecdsa_p256",
+					"name": "example.org",
+					"expires_in_days": 12,
+					"last_renewal": {"date": "2026-10-01T00:00:00Z", "success": false, "error_source": "order"},
+				},
+				{"id": "example.net_rsa2048", "name": "example.net", "expires_in_days": 120},
+			],
+		});
+		let hooks = json!({
+			"certificates": [
+				{"id": "example.org_ecdsa_p256", "hooks": [
+					{"date": "2026-10-02T00:00:00Z", "hook": "deploy", "success": false, "exit_code": 2},
+					{"date": "2026-10-03T00:00:00Z", "hook": "deploy", "success": true, "exit_code": 0},
+				]},
+			],
+		});
+		let ctx = get_context(&status, &hooks);
+		let crt = &ctx["certificates"][0];
+		assert_eq!(crt["timeline"], json!({"width": 13, "level": "warning"}));
+		assert_eq!(crt["hooks"][0]["date"], "2026-10-03T00:00:00Z");
+		assert_eq!(ctx["certificates"][1]["timeline"]["width"], 100);
+		assert_eq!(ctx["failures"].as_array().unwrap().len(), 2);
+		assert_eq!(ctx["failures"][0]["source"], "hook \"deploy\"");
+		assert_eq!(ctx["failures"][0]["error"], "code 2");
+		assert_eq!(ctx["failures"][1]["source"], "renewal");
+		let mut env = Environment::new();
+		env.add_template("index.html", INDEX_TEMPLATE).unwrap();
+		let mut ctx = ctx;
+		ctx["certificates"][1]["name"] = json!("<script>");
+		let page = env.get_template("index.html").unwrap().render(ctx).unwrap();
+		assert!(page.contains("example.org"));
+		assert!(page.contains("code 2"));
+		assert!(page.contains("&lt;script&gt;"));
+		assert!(!page.contains("<script>"));
+	}
+
+	#[test]
+	fn test_get_metrics() {
+		let status = json!({
+			"endpoints": [
+				{"name": "Let's \"Encrypt\"", "rate_limits": {"limit": 300, "remaining": 12, "reset": null, "retry_after": 1790000000}},
+				{"name": "other", "rate_limits": {"limit": null, "remaining": 3}},
+			],
+			"read_only_storage": true,
+			"certificates": [
+				{
+					"id": "example.org_rsa2048",
+					"endpoint": "other",
+					"labels": {"team": "pay\"ments", "endpoint": "prod"},
+					"expires_timestamp": 1790000000,
+					"last_renewal": {"success": false},
+				},
+				{"id": "example.net_rsa2048", "endpoint": "other", "labels": {}},
+			],
+			"watched_certificates": [
+				{"name": "legacy", "labels": {"team": "ops"}, "expires_timestamp": 1780000000},
+			],
+		});
+		let metrics = get_metrics(&status);
+		assert!(metrics.contains(
+			"acmed_certificate_expiry_timestamp_seconds{certificate=\"example.org_rsa2048\",endpoint=\"other\",label_endpoint=\"prod\",label_team=\"pay\\\"ments\"} 1790000000\n"
+		));
+		assert!(metrics.contains(
+			"acmed_certificate_last_renewal_success{certificate=\"example.org_rsa2048\",endpoint=\"other\",label_endpoint=\"prod\",label_team=\"pay\\\"ments\"} 0\n"
+		));
+		assert!(!metrics.contains("example.net_rsa2048"));
+		assert!(metrics.contains(
+			"acmed_watched_certificate_expiry_timestamp_seconds{certificate=\"legacy\",label_team=\"ops\"} 1780000000\n"
+		));
+		assert!(metrics.contains("# TYPE acmed_ca_rate_limit_limit gauge\n"));
+		assert!(
+			metrics.contains("acmed_ca_rate_limit_limit{endpoint=\"Let's \\\"Encrypt\\\"\"} 300\n")
+		);
+		assert!(metrics.contains("acmed_ca_rate_limit_remaining{endpoint=\"other\"} 3\n"));
+		assert!(metrics.contains(
+			"acmed_ca_rate_limit_retry_after_timestamp_seconds{endpoint=\"Let's \\\"Encrypt\\\"\"} 1790000000\n"
+		));
+		assert!(!metrics.contains("acmed_ca_rate_limit_reset_timestamp_seconds"));
+		assert!(
+			metrics.contains("# TYPE acmed_storage_read_only gauge\nacmed_storage_read_only 1\n")
+		);
+		let metrics = get_metrics(&json!({"read_only_storage": false}));
+		assert!(metrics.ends_with("\nacmed_storage_read_only 0\n"));
+		assert_eq!(get_metrics(&json!({})), "");
+	}
+}
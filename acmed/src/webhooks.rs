@@ -0,0 +1,325 @@
+use crate::endpoint::format_timestamp;
+use crate::events::{self, Event};
+use crate::hooks::HookSignature;
+use crate::http;
+use crate::logs::HasLogger;
+use crate::template::render_template;
+use acme_common::error::{Error, ErrorKind};
+use reqwest::{header, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+/// Events a webhook may be sent on. Besides the events of the certificates, the
+/// `renewal_succeeded` and `renewal_failed` names only match the corresponding
+/// `renewal_finished` events.
+pub const EVENTS: &[&str] = &[
+	"authorization_valid",
+	"certificate_expiring",
+	"deployment_checked",
+	"file_written",
+	"hook_finished",
+	"order_created",
+	"renewal_failed",
+	"renewal_finished",
+	"renewal_started",
+	"renewal_succeeded",
+	"storage_read_only",
+	"storage_writable",
+	"terms_of_service_changed",
+];
+
+/// Headers holding the signature of the body and the name of its algorithm.
+pub const SIGNATURE_HEADERS: &[&str] = &["x-acmed-signature", "x-acmed-signature-algorithm"];
+
+/// HTTP POST request sent on the lifecycle events of the certificates, so chat and alerting
+/// services can be notified without any wrapper script.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Webhook {
+	pub name: String,
+	pub url: String,
+	pub events: Vec<String>,
+	pub headers: BTreeMap<String, String>,
+	pub body: Option<String>,
+	pub signature: Option<HookSignature>,
+	pub env: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct WebhookData<'a> {
+	name: &'a str,
+	kind: &'a str,
+	certificate: &'a str,
+	date: String,
+	timestamp: u64,
+	details: &'a Value,
+	labels: &'a BTreeMap<String, String>,
+	env: &'a HashMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct WebhookRequest {
+	url: String,
+	headers: Vec<(String, String)>,
+	body: String,
+}
+
+impl HasLogger for Webhook {
+	fn warn(&self, msg: &str) {
+		log::warn!("webhook \"{}\": {msg}", self.name);
+	}
+
+	fn info(&self, msg: &str) {
+		log::info!("webhook \"{}\": {msg}", self.name);
+	}
+
+	fn debug(&self, msg: &str) {
+		log::debug!("webhook \"{}\": {msg}", self.name);
+	}
+
+	fn trace(&self, msg: &str) {
+		log::trace!("webhook \"{}\": {msg}", self.name);
+	}
+}
+
+impl Webhook {
+	fn matches(&self, event: &Event) -> bool {
+		self.events.iter().any(|e| match e.as_str() {
+			"renewal_succeeded" => {
+				event.kind == "renewal_finished" && event.details["success"] == Value::Bool(true)
+			}
+			"renewal_failed" => {
+				event.kind == "renewal_finished" && event.details["success"] == Value::Bool(false)
+			}
+			kind => kind == event.kind,
+		})
+	}
+
+	/// Return the request sent on the given event. Unless a body template is specified, the body
+	/// is the event itself, as displayed by `ctl watch`.
+	fn render(&self, event: &Event) -> Result<WebhookRequest, Error> {
+		let data = WebhookData {
+			name: &self.name,
+			kind: &event.kind,
+			certificate: event.certificate.as_deref().unwrap_or_default(),
+			date: format_timestamp(event.timestamp),
+			timestamp: event.timestamp,
+			details: &event.details,
+			labels: &event.labels,
+			env: &self.env,
+		};
+		let url = render_template(&self.url, &data)?;
+		let mut headers = vec![];
+		for (name, value) in self.headers.iter() {
+			headers.push((name.to_owned(), render_template(value, &data)?));
+		}
+		let body = match &self.body {
+			Some(b) => render_template(b, &data)?,
+			None => serde_json::to_string(event)?,
+		};
+		Ok(WebhookRequest { url, headers, body })
+	}
+
+	/// Add the signature of the body, which allows the receiver to authenticate the request.
+	async fn sign(&self, req: &mut WebhookRequest) -> Result<(), Error> {
+		if let Some(sig) = &self.signature {
+			let signature = sig.sign(req.body.as_bytes()).await?;
+			let values = [signature, sig.algorithm.to_string()];
+			for (name, value) in SIGNATURE_HEADERS.iter().zip(values) {
+				req.headers.push((name.to_string(), value));
+			}
+		}
+		Ok(())
+	}
+
+	/// Send the webhook, retrying on network errors, server errors and rate limiting.
+	async fn send(&self, event: &Event) -> Result<(), Error> {
+		let mut req = self.render(event)?;
+		self.sign(&mut req).await?;
+		let client = http::get_api_client()?;
+		let mut attempt = 1;
+		loop {
+			self.debug(&format!("sending the {} event to {}", event.kind, req.url));
+			let mut request = client
+				.post(&req.url)
+				.timeout(Duration::from_secs(crate::DEFAULT_WEBHOOK_TIMEOUT_SEC))
+				.header(header::CONTENT_TYPE, http::CONTENT_TYPE_JSON);
+			for (name, value) in req.headers.iter() {
+				request = request.header(name, value);
+			}
+			let (error, is_retryable) = match request.body(req.body.clone()).send().await {
+				Ok(response) if response.status().is_success() => return Ok(()),
+				Ok(response) => {
+					let status = response.status();
+					let body = response.text().await.unwrap_or_default();
+					let msg = format!("HTTP error: {}: {}", status.as_u16(), body.trim());
+					let is_retryable =
+						status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+					(Error::new(ErrorKind::Http, &msg), is_retryable)
+				}
+				Err(e) => (Error::from(e), true),
+			};
+			if !is_retryable || attempt >= crate::DEFAULT_WEBHOOK_MAX_ATTEMPTS {
+				return Err(error);
+			}
+			let base = Duration::from_secs(crate::DEFAULT_WEBHOOK_RETRY_DELAY_SEC);
+			let delay = http::get_retry_delay(base, (attempt - 1) as u32);
+			self.debug(&format!(
+				"attempt {attempt}/{} failed: {}, retrying in {}ms",
+				crate::DEFAULT_WEBHOOK_MAX_ATTEMPTS,
+				error.message,
+				delay.as_millis()
+			));
+			tokio::time::sleep(delay).await;
+			attempt += 1;
+		}
+	}
+}
+
+/// Send the webhooks matching each event. The events are subscribed to before this function
+/// returns, so none of those emitted afterwards is missed.
+pub fn start(webhooks: Vec<Webhook>) -> JoinHandle<()> {
+	let mut rx = events::subscribe();
+	tokio::spawn(async move {
+		loop {
+			match rx.recv().await {
+				Ok(event) => {
+					for webhook in webhooks.iter().filter(|w| w.matches(&event)) {
+						let webhook = webhook.clone();
+						let event = event.clone();
+						tokio::spawn(async move {
+							if let Err(e) = webhook.send(&event).await {
+								webhook.warn(&e.prefix(&event.kind).message);
+							}
+						});
+					}
+				}
+				Err(RecvError::Lagged(nb)) => {
+					log::warn!("webhooks: {nb} event(s) lost");
+				}
+				Err(RecvError::Closed) => return,
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Webhook;
+	use crate::events::Event;
+	use crate::hooks::HookSignature;
+	use crate::storage::TestDir;
+	use acme_common::crypto::JwsSignatureAlgorithm;
+	use serde_json::json;
+	use std::collections::{BTreeMap, HashMap};
+
+	fn event(kind: &str, details: serde_json::Value) -> Event {
+		Event {
+			timestamp: 1582915316,
+			kind: kind.to_string(),
+			certificate: Some("example.org_ecdsa-p256".to_string()),
+			labels: BTreeMap::from([("team".to_string(), "payments".to_string())]),
+			details,
+		}
+	}
+
+	fn webhook(events: &[&str]) -> Webhook {
+		Webhook {
+			name: "chat".to_string(),
+			url: "https://chat.example.org/{{ certificate }}".to_string(),
+			events: events.iter().map(|e| e.to_string()).collect(),
+			headers: BTreeMap::from([(
+				"Authorization".to_string(),
+				"Bearer {{ env.TOKEN }} {{ labels.team }}".to_string(),
+			)]),
+			body: None,
+			signature: None,
+			env: HashMap::from([("TOKEN".to_string(), "secret".to_string())]),
+		}
+	}
+
+	#[test]
+	fn test_matches() {
+		let failed = event("renewal_finished", json!({"success": false}));
+		let succeeded = event("renewal_finished", json!({"success": true}));
+		let started = event("renewal_started", json!(null));
+		let wh = webhook(&["renewal_failed", "renewal_started"]);
+		assert!(wh.matches(&failed));
+		assert!(!wh.matches(&succeeded));
+		assert!(wh.matches(&started));
+		let wh = webhook(&["renewal_succeeded"]);
+		assert!(!wh.matches(&failed));
+		assert!(wh.matches(&succeeded));
+		let wh = webhook(&["renewal_finished"]);
+		assert!(wh.matches(&failed));
+		assert!(wh.matches(&succeeded));
+		assert!(!wh.matches(&started));
+	}
+
+	#[test]
+	fn test_render() {
+		let ev = event("certificate_expiring", json!({"expires_in_days": 3}));
+		let req = webhook(&[]).render(&ev).unwrap();
+		assert_eq!(req.url, "https://chat.example.org/example.org_ecdsa-p256");
+		assert_eq!(
+			req.headers,
+			vec![(
+				"Authorization".to_string(),
+				"Bearer secret payments".to_string()
+			)]
+		);
+		let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+		assert_eq!(body["kind"], "certificate_expiring");
+		assert_eq!(body["labels"]["team"], "payments");
+		assert_eq!(body["details"]["expires_in_days"], 3);
+
+		let wh = Webhook {
+			body: Some(
+				r#"{"text": {{ (certificate ~ " expires in " ~ details.expires_in_days ~ " days (" ~ date ~ ")") | tojson }}}"#
+					.to_string(),
+			),
+			..webhook(&[])
+		};
+		let req = wh.render(&ev).unwrap();
+		let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+		assert_eq!(
+			body["text"],
+			"example.org_ecdsa-p256 expires in 3 days (2020-02-28T18:41:56Z)"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_sign() {
+		let dir = TestDir::new("webhooks-sig");
+		let key_file = dir.path().join("key");
+		std::fs::write(&key_file, "a2V5\n").unwrap();
+		let sig = HookSignature {
+			algorithm: JwsSignatureAlgorithm::Hs256,
+			key_file: key_file.display().to_string(),
+		};
+		let ev = event("renewal_started", json!(null));
+		let mut req = webhook(&[]).render(&ev).unwrap();
+		let unsigned = req.headers.clone();
+		webhook(&[]).sign(&mut req).await.unwrap();
+		assert_eq!(req.headers, unsigned);
+		let wh = Webhook {
+			signature: Some(sig.clone()),
+			..webhook(&[])
+		};
+		wh.sign(&mut req).await.unwrap();
+		let expected = sig.sign(req.body.as_bytes()).await.unwrap();
+		assert_eq!(
+			req.headers[unsigned.len()..],
+			[
+				("x-acmed-signature".to_string(), expected),
+				(
+					"x-acmed-signature-algorithm".to_string(),
+					"HS256".to_string()
+				),
+			]
+		);
+	}
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use acmed::config::{self, Config};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(contents) = std::str::from_utf8(data) {
+		let _ = toml::from_str::<Config>(contents);
+		// Referencing an environment variable switches to the interpolation path.
+		let _ = config::parse_cnf(contents);
+	}
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use acmed::config;
+use libfuzzer_sys::fuzz_target;
+use std::fs;
+use std::path::PathBuf;
+
+/// Configuration file which may be included by the fuzzed one, using `include = ["included.toml"]`.
+const INCLUDED: &str = r#"
+[[hook]]
+name = "included-hook"
+type = ["file-post-create"]
+cmd = "true"
+
+[[group]]
+name = "included-group"
+hooks = ["included-hook"]
+"#;
+
+fn get_dir() -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("acmed-fuzz-{}", std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("included.toml"), INCLUDED).unwrap();
+	dir
+}
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(contents) = std::str::from_utf8(data) {
+		let path = get_dir().join("acmed.toml");
+		fs::write(&path, contents).unwrap();
+		let _ = config::read_files(&path.display().to_string());
+	}
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use acmed::{duration, renewal_window};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(value) = std::str::from_utf8(data) {
+		let _ = duration::parse_duration(value);
+		if let Ok(window) = value.parse::<renewal_window::RenewalWindow>() {
+			let formatted = window.to_string();
+			assert_eq!(
+				formatted.parse::<renewal_window::RenewalWindow>().ok(),
+				Some(window)
+			);
+		}
+	}
+});
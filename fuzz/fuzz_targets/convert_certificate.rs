@@ -0,0 +1,11 @@
+#![no_main]
+
+use acme_common::crypto::{convert_certificate, KeyEncoding};
+use libfuzzer_sys::fuzz_target;
+use std::io;
+
+fuzz_target!(|data: &[u8]| {
+	for encoding in [KeyEncoding::Pem, KeyEncoding::Der] {
+		let _ = convert_certificate(data, encoding, &mut io::sink());
+	}
+});
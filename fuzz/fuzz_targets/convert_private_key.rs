@@ -0,0 +1,16 @@
+#![no_main]
+
+use acme_common::crypto::{convert_private_key, KeyEncoding, PrivateKeyFormat};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	for format in [
+		PrivateKeyFormat::Pkcs1,
+		PrivateKeyFormat::Pkcs8,
+		PrivateKeyFormat::Sec1,
+	] {
+		for encoding in [KeyEncoding::Pem, KeyEncoding::Der] {
+			let _ = convert_private_key(data, format, encoding);
+		}
+	}
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use acmed::template;
+use libfuzzer_sys::fuzz_target;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+struct HookData {
+	identifier: String,
+	identifier_tls_alpn: String,
+	file_name: String,
+	proof: String,
+	is_clean_hook: bool,
+	env: HashMap<String, String>,
+}
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(tpl) = std::str::from_utf8(data) {
+		let hook_data = HookData {
+			identifier: "www.example.org".to_string(),
+			identifier_tls_alpn: "www.example.org".to_string(),
+			file_name: "pXl5Yy5z4e8Zra8Cyt1OcVW3hfWTqcoVx5wQ1bw1Ho0".to_string(),
+			proof: "pXl5Yy5z4e8Zra8Cyt1OcVW3hfWTqcoVx5wQ1bw1Ho0".to_string(),
+			is_clean_hook: false,
+			env: HashMap::from([("HOME".to_string(), "/root".to_string())]),
+		};
+		// A fresh environment is used for each template, hence the shared registry does not grow.
+		let _ = template::check_template(tpl, &hook_data);
+	}
+});
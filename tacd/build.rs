@@ -32,6 +32,7 @@ macro_rules! set_runstate_path_if_absent {
 }
 
 fn main() {
+	println!("cargo:rustc-check-cfg=cfg(ossl110)");
 	if let Ok(target) = env::var("TARGET") {
 		println!("cargo:rustc-env=TACD_TARGET={target}");
 	};
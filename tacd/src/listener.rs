@@ -0,0 +1,87 @@
+use acme_common::error::Error;
+use log::debug;
+use std::net::TcpListener;
+
+#[cfg(unix)]
+use log::warn;
+#[cfg(unix)]
+use nix::sys::socket::{getsockname, AddressFamily, SockaddrLike, SockaddrStorage};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+/// First file descriptor passed by systemd, see sd_listen_fds(3).
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+pub enum Listener {
+	Tcp(TcpListener),
+	#[cfg(unix)]
+	Unix(UnixListener),
+}
+
+impl Listener {
+	/// Listen on the given host and port or, using the `unix:` prefix, on a Unix socket. A
+	/// stale Unix socket left by a previous instance is removed.
+	pub fn bind(listen_addr: &str) -> Result<Self, Error> {
+		#[cfg(unix)]
+		if let Some(path) = listen_addr.strip_prefix("unix:") {
+			if let Ok(m) = std::fs::symlink_metadata(path) {
+				if m.file_type().is_socket() {
+					debug!("removing the stale unix socket {path}");
+					std::fs::remove_file(path).map_err(|e| Error::from(e).prefix(path))?;
+				}
+			}
+			debug!("listening on unix socket {path}");
+			let listener = UnixListener::bind(path).map_err(|e| Error::from(e).prefix(path))?;
+			return Ok(Listener::Unix(listener));
+		}
+		debug!("listening on {listen_addr}");
+		let listener =
+			TcpListener::bind(listen_addr).map_err(|e| Error::from(e).prefix(listen_addr))?;
+		Ok(Listener::Tcp(listener))
+	}
+
+	/// Return the socket passed by systemd using socket activation, if any. The environment
+	/// variables are removed so they are not inherited.
+	#[cfg(unix)]
+	pub fn from_systemd() -> Result<Option<Self>, Error> {
+		let pid = std::env::var("LISTEN_PID").ok();
+		let fds = std::env::var("LISTEN_FDS").ok();
+		std::env::remove_var("LISTEN_PID");
+		std::env::remove_var("LISTEN_FDS");
+		std::env::remove_var("LISTEN_FDNAMES");
+		let is_for_us = pid.and_then(|p| p.parse::<u32>().ok()) == Some(std::process::id());
+		let nb_fds = match fds.and_then(|n| n.parse::<usize>().ok()) {
+			Some(n) if is_for_us && n > 0 => n,
+			_ => return Ok(None),
+		};
+		if nb_fds > 1 {
+			warn!("{nb_fds} sockets passed by systemd, only the first one is used");
+		}
+		let fd = SD_LISTEN_FDS_START;
+		let addr: SockaddrStorage = getsockname(fd)
+			.map_err(|e| Error::from(e).prefix("invalid socket passed by systemd"))?;
+		// The file descriptor is owned by this process from now on.
+		let listener = match addr.family() {
+			Some(AddressFamily::Unix) => {
+				debug!("listening on the unix socket passed by systemd");
+				Listener::Unix(unsafe { UnixListener::from_raw_fd(fd) })
+			}
+			Some(AddressFamily::Inet) | Some(AddressFamily::Inet6) => {
+				debug!("listening on the socket passed by systemd: {addr}");
+				Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) })
+			}
+			_ => return Err("unsupported socket passed by systemd".into()),
+		};
+		Ok(Some(listener))
+	}
+
+	#[cfg(not(unix))]
+	pub fn from_systemd() -> Result<Option<Self>, Error> {
+		Ok(None)
+	}
+}
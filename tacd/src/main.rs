@@ -1,17 +1,23 @@
+mod listener;
 #[cfg(feature = "crypto_openssl")]
 mod openssl_server;
+mod proxy_protocol;
 
+use crate::listener::Listener;
 #[cfg(feature = "crypto_openssl")]
 use crate::openssl_server::start as server_start;
-use acme_common::crypto::{get_lib_name, get_lib_version, HashFunction, KeyType, X509Certificate};
+use acme_common::crypto::{
+	get_lib_name, get_lib_version, HashFunction, KeyPair, KeyType, X509Certificate,
+};
 use acme_common::error::Error;
 use acme_common::logs::{set_log_system, DEFAULT_LOG_LEVEL};
 use acme_common::{clean_pid_file, to_idna};
 use clap::builder::PossibleValuesParser;
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use log::{debug, error, info};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read};
+use std::path::PathBuf;
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -21,19 +27,81 @@ const DEFAULT_CRT_KEY_TYPE: KeyType = KeyType::EcdsaP256;
 const DEFAULT_CRT_DIGEST: HashFunction = HashFunction::Sha256;
 const ALPN_ACME_PROTO_NAME: &[u8] = b"\x0aacme-tls/1";
 
-fn read_line(path: Option<&String>) -> Result<String, Error> {
+/// Self-signed certificate presented to the validation connections of a domain.
+pub struct Challenge {
+	pub domain: String,
+	pub certificate: X509Certificate,
+	pub key_pair: KeyPair,
+}
+
+impl Challenge {
+	fn new(
+		domain: &str,
+		ext: &str,
+		key_type: KeyType,
+		digest: HashFunction,
+	) -> Result<Self, Error> {
+		let (key_pair, certificate) = X509Certificate::from_acme_ext(domain, ext, key_type, digest)
+			.map_err(|e| e.prefix(domain))?;
+		Ok(Challenge {
+			domain: domain.to_string(),
+			certificate,
+			key_pair,
+		})
+	}
+}
+
+/// Directory in which each file is named after a domain and contains its acmeIdentifier
+/// extension. The challenges are read when a connection requests them, so they can be added
+/// to and removed from a running instance.
+pub struct ChallengeDir {
+	path: PathBuf,
+	key_type: KeyType,
+	digest: HashFunction,
+}
+
+impl ChallengeDir {
+	/// Return the acmeIdentifier extension of the given domain, if any.
+	pub fn get_acme_ext(&self, domain: &str) -> Result<Option<String>, Error> {
+		// The domain comes from the client, hence it must not be able to reach another file.
+		let is_valid = !domain.is_empty()
+			&& !domain.starts_with('.')
+			&& domain
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+		if !is_valid {
+			return Ok(None);
+		}
+		match fs::read_to_string(self.path.join(domain)) {
+			Ok(ext) => Ok(Some(ext.trim().to_string())),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(Error::from(e).prefix(domain)),
+		}
+	}
+
+	pub fn get_challenge(&self, domain: &str, ext: &str) -> Result<Challenge, Error> {
+		Challenge::new(domain, ext, self.key_type, self.digest)
+	}
+}
+
+/// Read the values from a file, one per line, or a single value from the standard input.
+fn read_lines(path: Option<&String>) -> Result<Vec<String>, Error> {
 	let mut input = String::new();
 	match path {
 		Some(p) => File::open(p)?.read_to_string(&mut input)?,
 		None => io::stdin().read_line(&mut input)?,
 	};
-	let line = input.trim().to_string();
-	Ok(line)
+	let lines = input
+		.lines()
+		.map(|l| l.trim().to_string())
+		.filter(|l| !l.is_empty())
+		.collect();
+	Ok(lines)
 }
 
-fn get_acme_value(cnf: &ArgMatches, opt: &str, opt_file: &str) -> Result<String, Error> {
-	match cnf.get_one::<String>(opt) {
-		Some(v) => Ok(v.to_string()),
+fn get_acme_values(cnf: &ArgMatches, opt: &str, opt_file: &str) -> Result<Vec<String>, Error> {
+	match cnf.get_many::<String>(opt) {
+		Some(v) => Ok(v.map(|e| e.to_string()).collect()),
 		None => {
 			debug!(
 				"reading {opt} from {}",
@@ -41,34 +109,92 @@ fn get_acme_value(cnf: &ArgMatches, opt: &str, opt_file: &str) -> Result<String,
 					.map(|e| e.as_str())
 					.unwrap_or("stdin")
 			);
-			read_line(cnf.get_one::<String>(opt_file))
+			read_lines(cnf.get_one::<String>(opt_file))
 		}
 	}
 }
 
 fn init(cnf: &ArgMatches) -> Result<(), Error> {
+	// The socket passed by systemd is checked before the process forks, since it is only
+	// passed to the current process.
+	let systemd_listener = Listener::from_systemd()?;
 	acme_common::init_server(
 		cnf.get_flag("foreground"),
 		cnf.get_one::<String>("pid-file").map(|e| e.as_str()),
 	);
-	let domain = get_acme_value(cnf, "domain", "domain-file")?;
-	let domain = to_idna(&domain)?;
-	let ext = get_acme_value(cnf, "acme-ext", "acme-ext-file")?;
+	let challenge_dir = cnf.get_one::<String>("challenge-dir").map(PathBuf::from);
+	// Using a challenge directory, the domains may all be added afterwards.
+	let has_domains = ["domain", "domain-file", "acme-ext", "acme-ext-file"]
+		.iter()
+		.any(|opt| cnf.contains_id(opt));
+	let (domains, exts) = if challenge_dir.is_some() && !has_domains {
+		(vec![], vec![])
+	} else {
+		(
+			get_acme_values(cnf, "domain", "domain-file")?,
+			get_acme_values(cnf, "acme-ext", "acme-ext-file")?,
+		)
+	};
+	if domains.is_empty() && challenge_dir.is_none() {
+		return Err("no domain specified".into());
+	}
+	if domains.len() != exts.len() {
+		let msg = format!(
+			"{} domains but {} acmeIdentifier extensions specified",
+			domains.len(),
+			exts.len()
+		);
+		return Err(msg.into());
+	}
 	let listen_addr = cnf
 		.get_one::<String>("listen")
 		.map(|e| e.as_str())
 		.unwrap_or(DEFAULT_LISTEN_ADDR);
-	let crt_signature_alg = match cnf.get_one::<&str>("crt-signature-alg") {
+	let crt_signature_alg = match cnf.get_one::<String>("crt-signature-alg") {
 		Some(alg) => alg.parse()?,
 		None => DEFAULT_CRT_KEY_TYPE,
 	};
-	let crt_digest = match cnf.get_one::<&str>("crt-digest") {
+	let crt_digest = match cnf.get_one::<String>("crt-digest") {
 		Some(alg) => alg.parse()?,
 		None => DEFAULT_CRT_DIGEST,
 	};
-	let (pk, cert) = X509Certificate::from_acme_ext(&domain, &ext, crt_signature_alg, crt_digest)?;
-	info!("starting {APP_NAME} on {listen_addr} for {domain}");
-	server_start(listen_addr, &cert, &pk)?;
+	let mut challenges: Vec<Challenge> = Vec::with_capacity(domains.len());
+	for (domain, ext) in domains.iter().zip(exts.iter()) {
+		let domain = to_idna(domain)?.to_lowercase();
+		if challenges.iter().any(|c| c.domain == domain) {
+			return Err(format!("{domain}: duplicated domain").into());
+		}
+		challenges.push(Challenge::new(&domain, ext, crt_signature_alg, crt_digest)?);
+	}
+	let mut domain_list = challenges
+		.iter()
+		.map(|c| c.domain.to_owned())
+		.collect::<Vec<String>>();
+	if let Some(path) = &challenge_dir {
+		domain_list.push(format!("the domains in {}", path.display()));
+	}
+	let domain_list = domain_list.join(", ");
+	let challenge_dir = challenge_dir.map(|path| ChallengeDir {
+		path,
+		key_type: crt_signature_alg,
+		digest: crt_digest,
+	});
+	let listener = match systemd_listener {
+		Some(l) => {
+			info!("starting {APP_NAME} on the socket passed by systemd for {domain_list}");
+			l
+		}
+		None => {
+			info!("starting {APP_NAME} on {listen_addr} for {domain_list}");
+			Listener::bind(listen_addr)?
+		}
+	};
+	server_start(
+		listener,
+		&challenges,
+		challenge_dir,
+		cnf.get_flag("proxy-protocol"),
+	)?;
 	Ok(())
 }
 
@@ -94,19 +220,26 @@ fn main() {
 				.value_name("host:port|unix:path")
 				.default_value(DEFAULT_LISTEN_ADDR),
 		)
+		.arg(
+			Arg::new("proxy-protocol")
+				.long("proxy-protocol")
+				.help("Expect a PROXY protocol header at the beginning of each connection")
+				.action(ArgAction::SetTrue),
+		)
 		.arg(
 			Arg::new("domain")
 				.long("domain")
 				.short('d')
-				.help("The domain that is being validated")
+				.help("The domain that is being validated, may be repeated along with --acme-ext")
 				.num_args(1)
+				.action(ArgAction::Append)
 				.value_name("STRING")
 				.conflicts_with("domain-file"),
 		)
 		.arg(
 			Arg::new("domain-file")
 				.long("domain-file")
-				.help("File from which is read the domain that is being validated")
+				.help("File from which are read the domains that are being validated, one per line")
 				.num_args(1)
 				.value_name("FILE")
 				.conflicts_with("domain"),
@@ -115,19 +248,27 @@ fn main() {
 			Arg::new("acme-ext")
 				.long("acme-ext")
 				.short('e')
-				.help("The acmeIdentifier extension to set in the self-signed certificate")
+				.help("The acmeIdentifier extension to set in the self-signed certificate of the corresponding domain")
 				.num_args(1)
+				.action(ArgAction::Append)
 				.value_name("STRING")
 				.conflicts_with("acme-ext-file"),
 		)
 		.arg(
 			Arg::new("acme-ext-file")
 				.long("acme-ext-file")
-				.help("File from which is read the acmeIdentifier extension to set in the self-signed certificate")
+				.help("File from which are read the acmeIdentifier extensions to set in the self-signed certificates, one per line")
 				.num_args(1)
 				.value_name("FILE")
 				.conflicts_with("acme-ext"),
 		)
+		.arg(
+			Arg::new("challenge-dir")
+				.long("challenge-dir")
+				.help("Directory from which are read the acmeIdentifier extensions of the domains requested by the connections, each file being named after its domain")
+				.num_args(1)
+				.value_name("DIR"),
+		)
 		.arg(
 			Arg::new("crt-signature-alg")
 				.long("crt-signature-alg")
@@ -217,3 +358,31 @@ fn main() {
 		}
 	};
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{ChallengeDir, DEFAULT_CRT_DIGEST, DEFAULT_CRT_KEY_TYPE};
+	use std::fs;
+
+	#[test]
+	fn test_challenge_dir() {
+		let path = std::env::temp_dir().join(format!("tacd-test-{}", std::process::id()));
+		fs::create_dir_all(path.join("sub")).unwrap();
+		fs::write(path.join("a.example.org"), "ext\n").unwrap();
+		fs::write(path.join("sub").join("b.example.org"), "ext\n").unwrap();
+		let dir = ChallengeDir {
+			path: path.clone(),
+			key_type: DEFAULT_CRT_KEY_TYPE,
+			digest: DEFAULT_CRT_DIGEST,
+		};
+		assert_eq!(
+			dir.get_acme_ext("a.example.org").unwrap(),
+			Some("ext".to_string())
+		);
+		assert_eq!(dir.get_acme_ext("c.example.org").unwrap(), None);
+		assert_eq!(dir.get_acme_ext("sub/b.example.org").unwrap(), None);
+		assert_eq!(dir.get_acme_ext("..").unwrap(), None);
+		assert_eq!(dir.get_acme_ext("").unwrap(), None);
+		fs::remove_dir_all(&path).unwrap();
+	}
+}
@@ -1,55 +1,145 @@
-use acme_common::crypto::{KeyPair, X509Certificate};
+use crate::listener::Listener;
+use crate::proxy_protocol;
+use crate::{Challenge, ChallengeDir};
 use acme_common::error::Error;
 use log::debug;
-use openssl::ssl::{self, AlpnError, SslAcceptor, SslMethod};
-use std::net::TcpListener;
-use std::sync::Arc;
+use openssl::ssl::{
+	self, AlpnError, NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslAlert, SslContext,
+	SslMethod,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
-#[cfg(target_family = "unix")]
-use std::os::unix::net::UnixListener;
-
 #[cfg(ossl110)]
 const ALPN_ERROR: AlpnError = AlpnError::ALERT_FATAL;
 #[cfg(not(ossl110))]
 const ALPN_ERROR: AlpnError = AlpnError::NOACK;
 
-macro_rules! listen_and_accept {
-	($lt: ident, $addr: ident, $acceptor: ident) => {
-		let listener = $lt::bind($addr)?;
-		for stream in listener.incoming() {
-			if let Ok(stream) = stream {
+macro_rules! accept {
+	($listener: ident, $acceptor: ident, $proxy_protocol: ident) => {
+		for stream in $listener.incoming() {
+			if let Ok(mut stream) = stream {
 				let acceptor = $acceptor.clone();
 				thread::spawn(move || {
-					debug!("new client");
-					let _ = acceptor.accept(stream).unwrap();
+					if $proxy_protocol {
+						match proxy_protocol::read_header(&mut stream) {
+							Ok(Some(addr)) => debug!("new client: {addr}"),
+							Ok(None) => debug!("new client"),
+							Err(e) => {
+								debug!("{e}");
+								return;
+							}
+						}
+					} else {
+						debug!("new client");
+					}
+					if let Err(e) = acceptor.accept(stream) {
+						debug!("handshake failed: {e}");
+					}
 				});
 			};
 		}
 	};
 }
 
-pub fn start(
-	listen_addr: &str,
-	certificate: &X509Certificate,
-	key_pair: &KeyPair,
-) -> Result<(), Error> {
+/// Acceptor without any certificate, whose connections are only served if the SNI matches a
+/// challenge.
+fn build_empty_acceptor() -> Result<SslAcceptorBuilder, Error> {
 	let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
 	acceptor.set_alpn_select_callback(|_, client| {
 		debug!("ALPN negociation");
 		ssl::select_next_proto(crate::ALPN_ACME_PROTO_NAME, client).ok_or(ALPN_ERROR)
 	});
-	acceptor.set_private_key(&key_pair.inner_key)?;
-	acceptor.set_certificate(&certificate.inner_cert)?;
+	Ok(acceptor)
+}
+
+fn build_acceptor(challenge: &Challenge) -> Result<SslAcceptorBuilder, Error> {
+	let mut acceptor = build_empty_acceptor()?;
+	acceptor.set_private_key(&challenge.key_pair.inner_key)?;
+	acceptor.set_certificate(&challenge.certificate.inner_cert)?;
 	acceptor.check_private_key()?;
+	Ok(acceptor)
+}
+
+/// Return the context of a domain from the challenge directory. Since a new certificate is
+/// generated for each acmeIdentifier extension, the contexts are cached along with the extension
+/// they have been generated for.
+fn get_dir_context(
+	dir: &ChallengeDir,
+	cache: &Mutex<HashMap<String, (String, SslContext)>>,
+	domain: &str,
+) -> Result<Option<SslContext>, Error> {
+	let ext = match dir.get_acme_ext(domain)? {
+		Some(ext) => ext,
+		None => return Ok(None),
+	};
+	let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+	if let Some((cached_ext, ctx)) = cache.get(domain) {
+		if *cached_ext == ext {
+			return Ok(Some(ctx.clone()));
+		}
+	}
+	let challenge = dir.get_challenge(domain, &ext)?;
+	let ctx = build_acceptor(&challenge)?.build().into_context();
+	cache.insert(domain.to_string(), (ext, ctx.clone()));
+	Ok(Some(ctx))
+}
+
+/// Serve the challenges, the certificate presented being selected using the SNI. Without SNI,
+/// the first challenge's certificate is presented.
+pub fn start(
+	listener: Listener,
+	challenges: &[Challenge],
+	challenge_dir: Option<ChallengeDir>,
+	proxy_protocol: bool,
+) -> Result<(), Error> {
+	let mut contexts = HashMap::with_capacity(challenges.len());
+	for challenge in challenges.iter().skip(1) {
+		let ctx = build_acceptor(challenge)?.build().into_context();
+		contexts.insert(challenge.domain.to_owned(), ctx);
+	}
+	let (first_domain, mut acceptor) = match challenges.first() {
+		Some(first) => (Some(first.domain.to_owned()), build_acceptor(first)?),
+		None if challenge_dir.is_some() => (None, build_empty_acceptor()?),
+		None => return Err("no challenge to serve".into()),
+	};
+	let cache = Mutex::new(HashMap::new());
+	acceptor.set_servername_callback(move |ssl, alert| {
+		let name = match ssl.servername(NameType::HOST_NAME) {
+			Some(n) => n.to_lowercase(),
+			None => return Ok(()),
+		};
+		debug!("SNI: {name}");
+		if first_domain.as_ref() == Some(&name) {
+			return Ok(());
+		}
+		let ctx = match (contexts.get(&name), &challenge_dir) {
+			(Some(ctx), _) => Some(ctx.clone()),
+			(None, Some(dir)) => get_dir_context(dir, &cache, &name).unwrap_or_else(|e| {
+				debug!("{e}");
+				None
+			}),
+			(None, None) => None,
+		};
+		match ctx {
+			Some(ctx) => ssl.set_ssl_context(&ctx).map_err(|_| SniError::ALERT_FATAL),
+			None => {
+				debug!("{name}: unknown domain");
+				*alert = SslAlert::UNRECOGNIZED_NAME;
+				Err(SniError::ALERT_FATAL)
+			}
+		}
+	});
 	let acceptor = Arc::new(acceptor.build());
-	if cfg!(unix) && listen_addr.starts_with("unix:") {
-		let listen_addr = &listen_addr[5..];
-		debug!("listening on unix socket {listen_addr}");
-		listen_and_accept!(UnixListener, listen_addr, acceptor);
-	} else {
-		debug!("listening on {listen_addr}");
-		listen_and_accept!(TcpListener, listen_addr, acceptor);
+	match listener {
+		Listener::Tcp(l) => {
+			accept!(l, acceptor, proxy_protocol);
+		}
+		#[cfg(unix)]
+		Listener::Unix(l) => {
+			accept!(l, acceptor, proxy_protocol);
+		}
 	}
 	Err("main thread loop unexpectedly exited".into())
 }
@@ -0,0 +1,156 @@
+use acme_common::error::Error;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_CMD_LOCAL: u8 = 0x20;
+const V2_CMD_PROXY: u8 = 0x21;
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_TCP6: u8 = 0x21;
+
+/// Read the PROXY protocol header, either version 1 or 2, sent by a proxy at the beginning of
+/// the connection and return the address of the original client, if known. Nothing past the
+/// header is read, so the TLS handshake can start right after.
+pub fn read_header<R: Read>(stream: &mut R) -> Result<Option<SocketAddr>, Error> {
+	let mut start = [0; 8];
+	stream.read_exact(&mut start)?;
+	if start.starts_with(V1_PREFIX) {
+		let mut line = start.to_vec();
+		while !line.ends_with(b"\r\n") {
+			if line.len() >= V1_MAX_LEN {
+				return Err("PROXY protocol: header too long".into());
+			}
+			let mut b = [0; 1];
+			stream.read_exact(&mut b)?;
+			line.push(b[0]);
+		}
+		return parse_v1(&line);
+	}
+	if V2_SIGNATURE.starts_with(&start) {
+		let mut header = [0; 8];
+		stream.read_exact(&mut header)?;
+		if header[..4] != V2_SIGNATURE[8..] {
+			return Err("PROXY protocol: invalid signature".into());
+		}
+		let len = u16::from_be_bytes([header[6], header[7]]) as usize;
+		let mut addresses = vec![0; len];
+		stream.read_exact(&mut addresses)?;
+		return parse_v2(header[4], header[5], &addresses);
+	}
+	Err("PROXY protocol: header not found".into())
+}
+
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>, Error> {
+	let err = || Error::from("PROXY protocol: invalid header");
+	let line = std::str::from_utf8(line).map_err(|_| err())?.trim_end();
+	let parts: Vec<&str> = line.split(' ').collect();
+	match parts.as_slice() {
+		["PROXY", "UNKNOWN", ..] => Ok(None),
+		["PROXY", proto, src, _dst, src_port, _dst_port] => {
+			let ip: IpAddr = src.parse().map_err(|_| err())?;
+			let is_valid = matches!(
+				(*proto, ip),
+				("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_))
+			);
+			if !is_valid {
+				return Err(err());
+			}
+			let port = src_port.parse().map_err(|_| err())?;
+			Ok(Some(SocketAddr::new(ip, port)))
+		}
+		_ => Err(err()),
+	}
+}
+
+fn parse_v2(ver_cmd: u8, family: u8, addresses: &[u8]) -> Result<Option<SocketAddr>, Error> {
+	match ver_cmd {
+		V2_CMD_LOCAL => return Ok(None),
+		V2_CMD_PROXY => {}
+		_ => return Err("PROXY protocol: unsupported version or command".into()),
+	}
+	let too_short = || Error::from("PROXY protocol: truncated addresses");
+	// The source address comes first, followed by the destination one, then the ports.
+	let addr = match family {
+		V2_FAMILY_TCP4 => {
+			let a = addresses.get(..12).ok_or_else(too_short)?;
+			let ip = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+			SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([a[8], a[9]]))
+		}
+		V2_FAMILY_TCP6 => {
+			let a = addresses.get(..36).ok_or_else(too_short)?;
+			let mut ip = [0; 16];
+			ip.copy_from_slice(&a[..16]);
+			let ip = Ipv6Addr::from(ip);
+			SocketAddr::new(IpAddr::V6(ip), u16::from_be_bytes([a[32], a[33]]))
+		}
+		// Unix sockets and unspecified families do not carry any usable address.
+		_ => return Ok(None),
+	};
+	Ok(Some(addr))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::read_header;
+	use std::io::Read;
+
+	fn read(data: &[u8]) -> (Result<Option<String>, String>, Vec<u8>) {
+		let mut stream = data;
+		let res = read_header(&mut stream)
+			.map(|a| a.map(|a| a.to_string()))
+			.map_err(|e| e.message);
+		let mut rest = vec![];
+		stream.read_to_end(&mut rest).unwrap();
+		(res, rest)
+	}
+
+	#[test]
+	fn test_v1() {
+		let (res, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n\x16\x03\x01");
+		assert_eq!(res, Ok(Some("192.0.2.1:56324".to_string())));
+		assert_eq!(rest, b"\x16\x03\x01");
+		let (res, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n");
+		assert_eq!(res, Ok(Some("[2001:db8::1]:56324".to_string())));
+		let (res, rest) = read(b"PROXY UNKNOWN\r\n\x16");
+		assert_eq!(res, Ok(None));
+		assert_eq!(rest, b"\x16");
+		assert!(read(b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n")
+			.0
+			.is_err());
+		assert!(read(b"PROXY TCP4 192.0.2.1\r\n").0.is_err());
+		assert!(read(&[b"PROXY ".as_slice(), &[b'a'; 120]].concat())
+			.0
+			.is_err());
+		assert!(read(b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc").0.is_err());
+	}
+
+	#[test]
+	fn test_v2() {
+		let mut data = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+		data.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb]);
+		data.extend_from_slice(b"\x16\x03\x01");
+		let (res, rest) = read(&data);
+		assert_eq!(res, Ok(Some("192.0.2.1:56324".to_string())));
+		assert_eq!(rest, b"\x16\x03\x01");
+
+		let mut data = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24".to_vec();
+		data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+		data.extend_from_slice(&[0; 11]);
+		data.push(1);
+		data.extend_from_slice(&[0; 16]);
+		data.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+		assert_eq!(read(&data).0, Ok(Some("[2001:db8::1]:56324".to_string())));
+
+		let (res, rest) = read(b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00\x16");
+		assert_eq!(res, Ok(None));
+		assert_eq!(rest, b"\x16");
+		assert!(
+			read(b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x04\x00\x00\x00\x00")
+				.0
+				.is_err()
+		);
+		assert!(read(b"\r\n\r\n\0\r\nQUIT\n\x11\x11\x00\x00").0.is_err());
+	}
+}